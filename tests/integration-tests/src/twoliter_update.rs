@@ -72,3 +72,31 @@ async fn test_twoliter_update_crane() {
 
     tokio::fs::remove_file(&lockfile).await.ok();
 }
+
+#[tokio::test]
+#[ignore]
+/// Generates a Twoliter.lock file for the `external-kit` project using the built-in registry client
+async fn test_twoliter_update_native() {
+    let external_kit = test_projects_dir().join("external-kit");
+
+    let lockfile = external_kit.join("Twoliter.lock");
+    tokio::fs::remove_file(&lockfile).await.ok();
+
+    let output = run_command(
+        TWOLITER_PATH,
+        [
+            "update",
+            "--project-path",
+            external_kit.join("Twoliter.toml").to_str().unwrap(),
+        ],
+        [("TWOLITER_KIT_IMAGE_TOOL", "native")],
+    )
+    .await;
+
+    assert!(output.status.success());
+
+    let lock_contents = tokio::fs::read_to_string(&lockfile).await.unwrap();
+    assert_eq!(lock_contents, EXPECTED_LOCKFILE);
+
+    tokio::fs::remove_file(&lockfile).await.ok();
+}
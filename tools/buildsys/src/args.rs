@@ -5,9 +5,11 @@ of its input arguments from environment variables.
 
 !*/
 
-use buildsys::manifest::SupportedArch;
+use buildsys::manifest::{BuildMode, OutputFormat, PackageNetwork, SupportedArch};
+use buildsys::runtime::ContainerRuntime;
 use buildsys::BuildType;
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::num::{NonZeroU16, NonZeroUsize};
 use std::path::PathBuf;
 use url::Url;
 
@@ -15,28 +17,46 @@ use url::Url;
 /// variable changes. The build type is represented with bit flags so that we can easily list
 /// multiple build types for a single variable. See `[BuildType]` and `[rerun_for_envs]` below to
 /// see how this list is used.
-const REBUILD_VARS: [(&str, u8); 16] = [
+const REBUILD_VARS: [(&str, u8); 30] = [
     ("BUILDSYS_ARCH", PACKAGE | KIT | VARIANT),
     ("BUILDSYS_CACERTS_BUNDLE_OVERRIDE", VARIANT),
+    ("BUILDSYS_CHECKPOINTS", PACKAGE | KIT),
+    ("BUILDSYS_DOCKERFILE_FRAGMENTS", PACKAGE | KIT | VARIANT),
+    ("BUILDSYS_DOCKERFILE_LABELS", PACKAGE | KIT | VARIANT),
+    ("BUILDSYS_DOCKERFILE_TARGETS", PACKAGE | KIT | VARIANT),
     ("BUILDSYS_KITS_DIR", KIT),
     ("BUILDSYS_EXTERNAL_KITS_DIR", PACKAGE | KIT | VARIANT),
+    ("BUILDSYS_GO_BUILD_FLAGS", PACKAGE),
+    ("BUILDSYS_IMAGE_COMPRESSION_LEVEL", VARIANT),
+    ("BUILDSYS_KIT_FEATURES", PACKAGE),
     ("BUILDSYS_NAME", VARIANT),
     ("BUILDSYS_OUTPUT_DIR", VARIANT),
+    ("BUILDSYS_IMAGE_FORMATS", VARIANT),
     ("BUILDSYS_OUTPUT_GENERATION_ID", PACKAGE | KIT | VARIANT),
     ("BUILDSYS_PACKAGES_DIR", PACKAGE),
+    ("BUILDSYS_PACKAGE_BUILD_MODE", PACKAGE),
+    ("BUILDSYS_PACKAGE_NETWORK", PACKAGE),
     ("BUILDSYS_PRETTY_NAME", VARIANT),
+    ("BUILDSYS_REPRODUCIBLE", PACKAGE),
+    ("BUILDSYS_RERUN_HINTS", PACKAGE),
     ("BUILDSYS_ROOT_DIR", PACKAGE | KIT | VARIANT),
+    ("BUILDSYS_RPM_DEBUGINFO", PACKAGE),
     ("BUILDSYS_STATE_DIR", PACKAGE | KIT | VARIANT),
     ("BUILDSYS_TIMESTAMP", VARIANT),
     ("BUILDSYS_VARIANT", VARIANT),
     ("BUILDSYS_VERSION_BUILD", KIT | VARIANT),
     ("BUILDSYS_VERSION_IMAGE", KIT | VARIANT),
+    ("TLPRIVATE_SDK_DIGEST", PACKAGE | KIT | VARIANT),
     ("TLPRIVATE_SDK_IMAGE", PACKAGE | KIT | VARIANT),
 ];
 
 /// A tool for building Bottlerocket images and artifacts.
 #[derive(Debug, Parser)]
 pub(crate) struct Buildsys {
+    /// Print the configuration the command takes from the environment before it starts.
+    #[arg(short, long, global = true, env = "BUILDSYS_VERBOSE")]
+    pub(crate) verbose: bool,
+
     #[command(subcommand)]
     pub(crate) command: Command,
 }
@@ -47,15 +67,24 @@ pub(crate) enum Command {
     BuildKit(Box<BuildKitArgs>),
     BuildVariant(Box<BuildVariantArgs>),
     RepackVariant(Box<RepackVariantArgs>),
+    LintSpec(LintSpecArgs),
+    PopulateCache(PopulateCacheArgs),
+    Graph(GraphArgs),
+    BuildSdk(BuildSdkArgs),
 }
 
 impl Command {
-    pub(crate) fn build_type(&self) -> BuildType {
+    /// The type of build the command carries out, if it builds anything.
+    pub(crate) fn build_type(&self) -> Option<BuildType> {
         match self {
-            Command::BuildPackage(_) => BuildType::Package,
-            Command::BuildKit(_) => BuildType::Kit,
-            Command::BuildVariant(_) => BuildType::Variant,
-            Command::RepackVariant(_) => BuildType::Repack,
+            Command::BuildPackage(_) => Some(BuildType::Package),
+            Command::BuildKit(_) => Some(BuildType::Kit),
+            Command::BuildVariant(_) => Some(BuildType::Variant),
+            Command::RepackVariant(_) => Some(BuildType::Repack),
+            Command::LintSpec(_)
+            | Command::PopulateCache(_)
+            | Command::Graph(_)
+            | Command::BuildSdk(_) => None,
         }
     }
 }
@@ -81,6 +110,41 @@ pub(crate) struct Common {
     #[arg(long, env = "BUILDSYS_TIMESTAMP")]
     pub(crate) timestamp: String,
 
+    /// A directory in which to record how long the build took. Not a reason to rebuild, so builds
+    /// which Cargo skips are not recorded.
+    #[arg(long, env = "BUILDSYS_TIMINGS_DIR")]
+    pub(crate) timings_dir: Option<PathBuf>,
+
+    /// A directory in which to record why the build failed, along with the output of the failed
+    /// Docker build. Not a reason to rebuild.
+    #[arg(long, env = "BUILDSYS_FAILURES_DIR")]
+    pub(crate) failures_dir: Option<PathBuf>,
+
+    /// A directory in which to write the output of each Docker build, in
+    /// `<arch>/<kind>-<name>.log`. Not a reason to rebuild.
+    #[arg(long, env = "BUILDSYS_LOGS_DIR")]
+    pub(crate) logs_dir: Option<PathBuf>,
+
+    /// A file to which a line of JSON is appended for each event of the build, such as when it
+    /// starts, is skipped or produces an artifact. Not a reason to rebuild.
+    #[arg(long, env = "BUILDSYS_EVENTS_PATH")]
+    pub(crate) events_path: Option<PathBuf>,
+
+    /// A directory in which to write an in-toto statement of the SLSA provenance of each build's
+    /// artifacts, in `<arch>/<kind>-<name>.intoto.json`. Not a reason to rebuild.
+    #[arg(long, env = "BUILDSYS_PROVENANCE_DIR")]
+    pub(crate) provenance_dir: Option<PathBuf>,
+
+    /// Whether the provenance of builds is recorded in the provenance directory. Not a reason to
+    /// rebuild.
+    #[arg(long, env = "BUILDSYS_PROVENANCE", default_value_t = true, action = ArgAction::Set)]
+    pub(crate) provenance: bool,
+
+    /// Whether package and kit builds are skipped when the contents of their inputs are unchanged
+    /// since their last successful build.
+    #[arg(long, env = "BUILDSYS_CHECKPOINTS", default_value_t = true, action = ArgAction::Set)]
+    pub(crate) checkpoints: bool,
+
     #[arg(long, env = "BUILDSYS_VERSION_FULL")]
     pub(crate) version_full: String,
 
@@ -90,8 +154,155 @@ pub(crate) struct Common {
     #[arg(long, env = "TLPRIVATE_SDK_IMAGE")]
     pub(crate) sdk_image: String,
 
+    /// The digest of the SDK image, or the ID of a local SDK image. Checkpoints and build
+    /// directories are keyed on it rather than the SDK's tag, which may be moved to a different
+    /// image. Defaults to the SDK's tag.
+    #[arg(long, env = "TLPRIVATE_SDK_DIGEST")]
+    pub(crate) sdk_digest: Option<String>,
+
     #[arg(long, env = "TWOLITER_TOOLS_DIR")]
     pub(crate) tools_dir: PathBuf,
+
+    #[command(flatten)]
+    pub(crate) profile: ProfileArgs,
+
+    #[command(flatten)]
+    pub(crate) backend: BackendArgs,
+
+    #[command(flatten)]
+    pub(crate) dockerfile: DockerfileArgs,
+}
+
+impl Common {
+    /// What identifies the SDK's contents: its digest, or else its tag.
+    pub(crate) fn sdk_digest(&self) -> String {
+        self.sdk_digest
+            .clone()
+            .filter(|digest| !digest.is_empty())
+            .unwrap_or_else(|| self.sdk_image.clone())
+    }
+}
+
+/// How image builds are run. Not a reason to rebuild, since every backend builds the same
+/// artifacts.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct BackendArgs {
+    /// The tool which runs image builds: `runtime` runs the container runtime's `build` command,
+    /// and `buildkit` runs `buildctl` against a buildkitd instance.
+    #[arg(long, env = "BUILDSYS_BUILD_BACKEND", value_enum, default_value_t = BuildBackend::Runtime)]
+    pub(crate) build_backend: BuildBackend,
+
+    /// The container runtime which runs containers, and image builds unless they run with
+    /// buildkit.
+    #[arg(
+        long,
+        env = "BUILDSYS_CONTAINER_RUNTIME",
+        value_enum,
+        default_value_t = ContainerRuntime::Docker
+    )]
+    pub(crate) container_runtime: ContainerRuntime,
+
+    /// The address of the buildkitd instance, such as `tcp://buildkitd:1234`. Defaults to the
+    /// address `buildctl` uses by default.
+    #[arg(long, env = "BUILDSYS_BUILDKIT_ADDR")]
+    pub(crate) buildkit_addr: Option<String>,
+
+    /// A registry repository under which package builds look for a cache of their build stages,
+    /// such as one exported by CI.
+    #[arg(long, env = "BUILDSYS_CACHE_FROM")]
+    pub(crate) cache_from: Option<String>,
+
+    /// A registry repository under which package builds export a cache of their build stages.
+    #[arg(long, env = "BUILDSYS_CACHE_TO")]
+    pub(crate) cache_to: Option<String>,
+
+    /// An OCI image layout holding the SDK image, which builds with buildkit use in place of the
+    /// SDK image, since buildkitd can't see images loaded into the container runtime. The SDK is
+    /// written to it when it's built with buildkit.
+    #[arg(long, env = "BUILDSYS_SDK_LAYOUT")]
+    pub(crate) sdk_layout: Option<PathBuf>,
+}
+
+/// Changes a project makes to the embedded Dockerfile, declared under `[dockerfile]` in
+/// Twoliter.toml. Each is a list separated by commas.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct DockerfileArgs {
+    /// Files of extra build stages to append to the Dockerfile, relative to the project's root.
+    #[arg(long, env = "BUILDSYS_DOCKERFILE_FRAGMENTS", value_delimiter = ',')]
+    pub(crate) dockerfile_fragments: Vec<PathBuf>,
+
+    /// Labels to give the images built, as `<key>=<value>`.
+    #[arg(long, env = "BUILDSYS_DOCKERFILE_LABELS", value_delimiter = ',')]
+    pub(crate) dockerfile_labels: Vec<String>,
+
+    /// Stages from the fragments to build in place of the usual ones, as `<kind>=<stage>` where
+    /// the kind is `package`, `kit`, `variant` or `repack`.
+    #[arg(long, env = "BUILDSYS_DOCKERFILE_TARGETS", value_delimiter = ',')]
+    pub(crate) dockerfile_targets: Vec<String>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub(crate) enum BuildBackend {
+    #[value(alias = "docker")]
+    Runtime,
+    Buildkit,
+}
+
+/// How a package build tells Cargo which of its source files to watch.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub(crate) enum RerunHints {
+    /// Every file in the source groups, so that Cargo reruns the build only when one changes.
+    Files,
+    /// Only the source group directories, which is much faster for Cargo to check on large trees.
+    /// The build then checks a digest of the files which aren't ignored before doing any work,
+    /// through its checkpoint, which is used even if checkpoints are otherwise turned off.
+    Directories,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub(crate) enum CompilerCache {
+    None,
+    Ccache,
+    Sccache,
+}
+
+/// Build settings which Twoliter sets from the profile selected with `--profile`.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct ProfileArgs {
+    /// The name of the profile the settings came from, which is recorded in the provenance of
+    /// builds.
+    #[arg(long, env = "BUILDSYS_PROFILE")]
+    pub(crate) profile: Option<String>,
+
+    /// Whether RPM builds generate debuginfo packages.
+    #[arg(long, env = "BUILDSYS_RPM_DEBUGINFO", default_value_t = true, action = ArgAction::Set)]
+    pub(crate) rpm_debuginfo: bool,
+
+    /// Whether package builds pin their timestamps and build host so that RPMs built from the
+    /// same inputs are identical, and record the digests of the RPMs they produce.
+    #[arg(long, env = "BUILDSYS_REPRODUCIBLE", default_value_t = false, action = ArgAction::Set)]
+    pub(crate) reproducible: bool,
+
+    /// The compiler cache package builds keep: `ccache` for C and C++, or `sccache` for Rust.
+    #[arg(long, env = "BUILDSYS_COMPILER_CACHE", value_enum, default_value_t = CompilerCache::None)]
+    pub(crate) compiler_cache: CompilerCache,
+
+    /// The largest the compiler cache may grow, e.g. `20G`.
+    #[arg(long, env = "BUILDSYS_COMPILER_CACHE_SIZE", default_value = "20G")]
+    pub(crate) compiler_cache_size: String,
+
+    /// Flags given to the Go toolchain when building packages, after any `GOFLAGS` the SDK sets.
+    #[arg(long, env = "BUILDSYS_GO_BUILD_FLAGS", default_value = "")]
+    pub(crate) go_build_flags: String,
+
+    /// The lz4 compression level used for disk images.
+    #[arg(
+        long,
+        env = "BUILDSYS_IMAGE_COMPRESSION_LEVEL",
+        default_value_t = 9,
+        value_parser = clap::value_parser!(u8).range(1..=12)
+    )]
+    pub(crate) image_compression_level: u8,
 }
 
 /// Build RPMs from a spec file and sources.
@@ -115,12 +326,71 @@ pub(crate) struct BuildPackageArgs {
     #[arg(long, env = "BUILDSYS_SOURCES_DIR")]
     pub(crate) sources_dir: PathBuf,
 
+    /// The lookaside cache to fetch external files from: an `https://`, `file://` or `s3://` URL.
     #[arg(long, env = "BUILDSYS_LOOKASIDE_CACHE")]
     pub(crate) lookaside_cache: Url,
 
     #[arg(long, env = "BUILDSYS_UPSTREAM_SOURCE_FALLBACK")]
     pub(crate) upstream_source_fallback: String,
 
+    /// The number of external files which may be downloaded at once. Not a reason to rebuild.
+    #[arg(long, env = "BUILDSYS_FETCH_JOBS", default_value = "4")]
+    pub(crate) fetch_jobs: NonZeroUsize,
+
+    /// The seconds to wait on an upstream URL or mirror, for a response or for more of the file,
+    /// before trying the next. Not a reason to rebuild.
+    #[arg(long, env = "BUILDSYS_UPSTREAM_TIMEOUT", default_value = "60")]
+    pub(crate) upstream_timeout: u64,
+
+    /// A directory through which packages share the external files they download, named by their
+    /// hash. Not a reason to rebuild.
+    #[arg(long, env = "BUILDSYS_EXTERNAL_FILES_DIR")]
+    pub(crate) external_files_dir: Option<PathBuf>,
+
+    /// The features enabled for the project's kits, as space-separated `<kit>:<feature>` pairs.
+    /// Each is offered to spec files as an rpmbuild conditional, e.g. `%{with kit_core_kit_fips}`.
+    #[arg(long, env = "BUILDSYS_KIT_FEATURES", default_value = "")]
+    pub(crate) kit_features: String,
+
+    /// The number of package builds which may run at once, across every buildsys process using
+    /// the same state directory. Waiting builds start in order of the critical path through the
+    /// packages which depend on them. When absent, every build Cargo starts runs right away.
+    #[arg(long, env = "BUILDSYS_PACKAGE_JOBS")]
+    pub(crate) package_jobs: Option<NonZeroU16>,
+
+    /// The number of CPUs a package build may use, unless its `Cargo.toml` says otherwise.
+    #[arg(long, env = "BUILDSYS_PACKAGE_CPUS")]
+    pub(crate) package_cpus: Option<u32>,
+
+    /// The memory a package build may use, e.g. `8g`, unless its `Cargo.toml` says otherwise.
+    #[arg(long, env = "BUILDSYS_PACKAGE_MEMORY")]
+    pub(crate) package_memory: Option<String>,
+
+    /// The network access of a package build, unless its `Cargo.toml` says otherwise: `none`,
+    /// `sdk-proxy` or `full`.
+    #[arg(long, env = "BUILDSYS_PACKAGE_NETWORK")]
+    pub(crate) package_network: Option<PackageNetwork>,
+
+    /// How packages are built, unless their `Cargo.toml` says otherwise: `release`, or
+    /// instrumented with `debug`, `asan` or `ubsan`.
+    #[arg(long, env = "BUILDSYS_PACKAGE_BUILD_MODE")]
+    pub(crate) package_build_mode: Option<BuildMode>,
+
+    /// The proxy through which builds of packages with `sdk-proxy` network access download,
+    /// e.g. `http://proxy.example.com:3128`.
+    #[arg(long, env = "BUILDSYS_SDK_PROXY")]
+    pub(crate) sdk_proxy: Option<String>,
+
+    /// Where the project's secrets are read from, one `<name>=env:<variable>` or
+    /// `<name>=file:<path>` per line. Packages are given the secrets they declare.
+    #[arg(long, env = "BUILDSYS_SECRETS")]
+    pub(crate) secrets: Option<String>,
+
+    /// How to tell Cargo which files of the package's source groups to watch: `files` or
+    /// `directories`.
+    #[arg(long, env = "BUILDSYS_RERUN_HINTS", value_enum, default_value_t = RerunHints::Files)]
+    pub(crate) rerun_hints: RerunHints,
+
     #[command(flatten)]
     pub(crate) common: Common,
 }
@@ -182,6 +452,15 @@ pub(crate) struct BuildVariantArgs {
     #[arg(long, env = "BUILDSYS_VERSION_IMAGE")]
     pub(crate) version_image: String,
 
+    /// Additional formats to convert the variant's disk images to.
+    #[arg(
+        long = "output-format",
+        env = "BUILDSYS_IMAGE_FORMATS",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub(crate) output_formats: Vec<OutputFormat>,
+
     #[command(flatten)]
     pub(crate) common: Common,
 }
@@ -201,10 +480,289 @@ pub(crate) struct RepackVariantArgs {
     #[arg(long, env = "BUILDSYS_VERSION_IMAGE")]
     pub(crate) version_image: String,
 
+    /// The directory where built kits go, e.g. build/kits
+    #[arg(long, env = "BUILDSYS_KITS_DIR")]
+    pub(crate) kits_dir: PathBuf,
+
+    /// Locally built kits whose packages replace those the image has from the locked kits, so that
+    /// a change to a kit can be tried without rebuilding the variant.
+    #[arg(long = "kit", env = "BUILDSYS_REPACK_KITS", value_delimiter = ',')]
+    pub(crate) kits: Vec<String>,
+
+    /// A user data TOML file to write to the image's private partition, so that the image boots
+    /// with those settings, e.g. for a preconfigured test image. Its settings are checked against
+    /// the settings schema, if one is given.
+    #[arg(long, env = "BUILDSYS_REPACK_USER_DATA")]
+    pub(crate) user_data: Option<PathBuf>,
+
+    /// A JSON Schema of the variant's settings, which the settings in the user data must follow.
+    #[arg(long, env = "BUILDSYS_REPACK_SETTINGS_SCHEMA", requires = "user_data")]
+    pub(crate) settings_schema: Option<PathBuf>,
+
+    /// A bootconfig file to render into the image's private partition, in place of the bootconfig
+    /// the image was built with. The variant must have the `grub-set-private-var` image feature.
+    #[arg(long, env = "BUILDSYS_REPACK_BOOTCONFIG")]
+    pub(crate) bootconfig: Option<PathBuf>,
+
+    /// Additional formats to convert the variant's disk images to.
+    #[arg(
+        long = "output-format",
+        env = "BUILDSYS_IMAGE_FORMATS",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub(crate) output_formats: Vec<OutputFormat>,
+
     #[command(flatten)]
     pub(crate) common: Common,
 }
 
+/// Checks a spec file for problems which would otherwise fail its build late: macros which are used
+/// but never defined, and patches which are never applied.
+#[derive(Debug, Parser)]
+pub(crate) struct LintSpecArgs {
+    /// The spec file to check. The patches it may use are found next to it.
+    pub(crate) spec: PathBuf,
+
+    /// rpm macro files whose definitions the spec file may use. The macros which rpm, the SDK and
+    /// buildsys define for every build are known without them.
+    #[arg(long = "macros")]
+    pub(crate) macros: Vec<PathBuf>,
+}
+
+/// Uploads the external files of packages to the lookaside cache, after downloading them from
+/// upstream and verifying them. Files which are already in the cache are skipped.
+#[derive(Debug, Parser)]
+pub(crate) struct PopulateCacheArgs {
+    /// The manifests (`Cargo.toml`) of the packages whose external files are uploaded.
+    #[arg(required = true)]
+    pub(crate) manifests: Vec<PathBuf>,
+
+    /// The lookaside cache to upload to: a `file://` or `s3://` URL.
+    #[arg(long, env = "BUILDSYS_LOOKASIDE_CACHE")]
+    pub(crate) lookaside_cache: Url,
+
+    /// The number of external files which may be transferred at once.
+    #[arg(long, env = "BUILDSYS_FETCH_JOBS", default_value = "4")]
+    pub(crate) fetch_jobs: NonZeroUsize,
+
+    /// The seconds to wait on an upstream URL or mirror before trying the next.
+    #[arg(long, env = "BUILDSYS_UPSTREAM_TIMEOUT", default_value = "60")]
+    pub(crate) upstream_timeout: u64,
+}
+
+/// Prints the graph of dependencies between packages, as declared by their manifests and spec
+/// files.
+#[derive(Debug, Parser)]
+pub(crate) struct GraphArgs {
+    /// The manifests (`Cargo.toml`) of the packages. Manifests of kits and variants are skipped.
+    #[arg(required = true)]
+    pub(crate) manifests: Vec<PathBuf>,
+
+    /// The format to print the graph in.
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub(crate) format: GraphFormat,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub(crate) enum GraphFormat {
+    Dot,
+    Json,
+}
+
+/// Builds the Bottlerocket SDK container image from a checkout of the SDK's repository.
+#[derive(Debug, Parser)]
+pub(crate) struct BuildSdkArgs {
+    /// The checkout of the SDK's repository, with its `Dockerfile` at the top.
+    #[arg(long, env = "BUILDSYS_SDK_DIR")]
+    pub(crate) sdk_dir: PathBuf,
+
+    #[arg(long, env = "BUILDSYS_ARCH")]
+    pub(crate) arch: SupportedArch,
+
+    /// The tag to give the SDK image, e.g. `bottlerocket-sdk-local:x86_64`.
+    #[arg(long, env = "BUILDSYS_SDK_TAG")]
+    pub(crate) tag: String,
+
+    /// The stage of the SDK's Dockerfile to build, if not its last.
+    #[arg(long, env = "BUILDSYS_SDK_TARGET")]
+    pub(crate) target: Option<String>,
+
+    /// A file to which the ID of the SDK image is written once it's built.
+    #[arg(long, env = "BUILDSYS_SDK_ID_FILE")]
+    pub(crate) id_file: Option<PathBuf>,
+
+    /// A file to which the output of the build is appended.
+    #[arg(long, env = "BUILDSYS_SDK_LOG")]
+    pub(crate) log: Option<PathBuf>,
+
+    /// How the image is built.
+    #[command(flatten)]
+    pub(crate) backend: BackendArgs,
+}
+
+/// An environment variable which a subcommand takes its configuration from.
+#[derive(Debug)]
+pub(crate) struct EnvVar {
+    pub(crate) name: String,
+    /// Whether the subcommand fails without it, since it has no default.
+    pub(crate) required: bool,
+    pub(crate) default: Option<String>,
+    pub(crate) help: Option<String>,
+    /// The option that may be given on the command line instead.
+    long: Option<String>,
+}
+
+impl EnvVar {
+    /// Whether the variable is set to something other than an empty string, or its option is
+    /// given on the command line.
+    pub(crate) fn is_set(&self, cli: &[String]) -> bool {
+        let given = self.long.as_ref().is_some_and(|long| {
+            let option = format!("--{long}");
+            cli.iter()
+                .any(|arg| *arg == option || arg.starts_with(&format!("{option}=")))
+        });
+        given || std::env::var_os(&self.name).is_some_and(|value| !value.is_empty())
+    }
+}
+
+/// The environment variables read by the subcommand with the given name, such as `build-package`,
+/// as declared by the `env` attributes of its arguments.
+pub(crate) fn env_vars(subcommand: &str) -> Vec<EnvVar> {
+    let command = Buildsys::command();
+    let Some(subcommand) = command.find_subcommand(subcommand) else {
+        return Vec::new();
+    };
+    subcommand
+        .get_arguments()
+        .filter_map(|arg| {
+            let default = arg
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(",");
+            Some(EnvVar {
+                name: arg.get_env()?.to_string_lossy().to_string(),
+                required: arg.is_required_set(),
+                default: (!arg.get_default_values().is_empty()).then_some(default),
+                help: arg.get_help().map(|help| help.to_string()),
+                long: arg.get_long().map(str::to_string),
+            })
+        })
+        .collect()
+}
+
+/// Variables which buildsys reads without an argument, or which Twoliter's Makefile sets for its
+/// own tasks, so they aren't taken for misspellings.
+const OTHER_VARS: &[&str] = &[
+    "BUILDSYS_ALLOW_FAILED_LICENSE_CHECK",
+    "BUILDSYS_BUILD_DIR",
+    "BUILDSYS_CACERTS_BUNDLE_OVERRIDE",
+    "BUILDSYS_COMMAND",
+    "BUILDSYS_CONTAINER_CLI",
+    "BUILDSYS_DIAGNOSTICS_PATH",
+    "BUILDSYS_FULL_VERSION",
+    "BUILDSYS_IMAGES",
+    "BUILDSYS_IMAGES_DIR",
+    "BUILDSYS_JOBS",
+    "BUILDSYS_KEEP_GOING",
+    "BUILDSYS_KIT",
+    "BUILDSYS_KMOD_KIT",
+    "BUILDSYS_KMOD_KIT_PATH",
+    "BUILDSYS_METADATA_DIR",
+    "BUILDSYS_NAME_FRIENDLY",
+    "BUILDSYS_NAME_FULL",
+    "BUILDSYS_NAME_VARIANT",
+    "BUILDSYS_NAME_VERSION",
+    "BUILDSYS_OUTPUT_FORMAT",
+    "BUILDSYS_OUTPUT_GENERATION_ID",
+    "BUILDSYS_OVA",
+    "BUILDSYS_OVA_PATH",
+    "BUILDSYS_OVF_TEMPLATE",
+    "BUILDSYS_POPULATE_MANIFESTS",
+    "BUILDSYS_REGISTRY",
+    "BUILDSYS_RELEASE_CONFIG_PATH",
+    "BUILDSYS_SBKEYS_DIR",
+    "BUILDSYS_SBKEYS_PROFILE",
+    "BUILDSYS_SBKEYS_PROFILE_DIR",
+    "BUILDSYS_SDK_NAME",
+    "BUILDSYS_SDK_VERSION",
+    "BUILDSYS_TOOLS_DIR",
+    "BUILDSYS_UPSTREAM_LICENSE_FETCH",
+    "BUILDSYS_VARIANT_CRATE_DIR",
+    "BUILDSYS_VARIANT_DIR",
+];
+
+/// Prefixes of variables which are set once per item, such as an image feature of the variant.
+const OTHER_PREFIXES: &[&str] = &["BUILDSYS_VARIANT_IMAGE_FEATURE_"];
+
+/// A `BUILDSYS_*` variable which nothing reads, with the known variable it is most likely a
+/// misspelling of.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct UnknownVar {
+    pub(crate) name: String,
+    pub(crate) suggestion: Option<String>,
+}
+
+/// Finds the `BUILDSYS_*` variables among `names` which no subcommand, nor Twoliter's Makefile,
+/// knows about.
+pub(crate) fn unknown_env_vars<I, S>(names: I) -> Vec<UnknownVar>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let command = Buildsys::command();
+    let mut known: Vec<String> = command
+        .get_arguments()
+        .chain(
+            command
+                .get_subcommands()
+                .flat_map(|sub| sub.get_arguments()),
+        )
+        .filter_map(|arg| Some(arg.get_env()?.to_string_lossy().to_string()))
+        .chain(OTHER_VARS.iter().map(|var| var.to_string()))
+        .collect();
+    known.sort();
+    known.dedup();
+
+    names
+        .into_iter()
+        .filter(|name| name.as_ref().starts_with("BUILDSYS_"))
+        .filter(|name| {
+            let name = name.as_ref();
+            !known.iter().any(|var| var == name)
+                && !OTHER_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+        })
+        .map(|name| {
+            let name = name.as_ref().to_string();
+            let suggestion = known
+                .iter()
+                .map(|var| (edit_distance(&name, var), var))
+                .filter(|(distance, _)| *distance <= 2)
+                .min()
+                .map(|(_, var)| var.clone());
+            UnknownVar { name, suggestion }
+        })
+        .collect()
+}
+
+/// The number of single-character insertions, deletions and substitutions which turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
 /// Returns the environment variables that need to be watched for a given `[BuildType]`.
 fn sensitive_env_vars(build_type: BuildFlags) -> impl Iterator<Item = &'static str> {
     REBUILD_VARS
@@ -290,3 +848,56 @@ fn test_sensitive_env_vars_package() {
     assert!(list.contains(&"BUILDSYS_PACKAGES_DIR"));
     assert!(!list.contains(&"BUILDSYS_VARIANT"));
 }
+
+#[test]
+fn declares_env_vars() {
+    let vars = env_vars("build-package");
+    let var = |name: &str| vars.iter().find(|var| var.name == name).unwrap();
+    assert!(var("BUILDSYS_ARCH").required);
+    assert!(var("BUILDSYS_SOURCES_DIR").required);
+    assert!(!var("BUILDSYS_FETCH_JOBS").required);
+    assert_eq!(var("BUILDSYS_FETCH_JOBS").default.as_deref(), Some("4"));
+    assert!(!var("BUILDSYS_PACKAGE_JOBS").required);
+    assert!(var("BUILDSYS_PACKAGE_JOBS").default.is_none());
+    assert!(var("BUILDSYS_ARCH").is_set(&["buildsys".to_string(), "--arch=x86_64".to_string()]));
+    assert!(env_vars("no-such-command").is_empty());
+}
+
+#[test]
+fn finds_unknown_env_vars() {
+    let unknown = unknown_env_vars([
+        "PATH",
+        "BUILDSYS_ARCH",
+        "BUILDSYS_VERBOSE",
+        "BUILDSYS_DIAGNOSTICS_PATH",
+        "BUILDSYS_VARIANT_IMAGE_FEATURE_GRUB_SET_PRIVATE_VAR",
+        "BUILDSYS_ARHC",
+        "BUILDSYS_SOMETHING_ELSE",
+    ]);
+    assert_eq!(
+        unknown,
+        vec![
+            UnknownVar {
+                name: "BUILDSYS_ARHC".to_string(),
+                suggestion: Some("BUILDSYS_ARCH".to_string()),
+            },
+            UnknownVar {
+                name: "BUILDSYS_SOMETHING_ELSE".to_string(),
+                suggestion: None,
+            },
+        ]
+    );
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+}
+
+#[test]
+fn knows_makefile_env_vars() {
+    // Every variable Twoliter's Makefile sets must be known, so it isn't reported.
+    let makefile = include_str!("../../../twoliter/embedded/Makefile.toml");
+    let vars: Vec<&str> = makefile
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|word| word.starts_with("BUILDSYS_"))
+        .collect();
+    assert_eq!(unknown_env_vars(vars), Vec::new());
+}
@@ -4,31 +4,67 @@ builds. The actual build steps and the expected parameters are defined in
 the repository's top-level Dockerfile.
 
 */
+mod backend;
+mod checkpoint;
+mod compiler_cache;
+mod container;
+mod digests;
+mod dockerfile;
+mod emulation;
 pub(crate) mod error;
-
-use crate::args::{BuildKitArgs, BuildPackageArgs, BuildVariantArgs, RepackVariantArgs};
+mod events;
+mod failure;
+mod invocation;
+mod provenance;
+mod schedule;
+mod sdk;
+mod secrets;
+mod timing;
+mod user_data;
+
+use crate::args::{
+    BackendArgs, BuildBackend, BuildKitArgs, BuildPackageArgs, BuildVariantArgs, CompilerCache,
+    DockerfileArgs, ProfileArgs, RepackVariantArgs, RerunHints,
+};
+use backend::{ImageBuild, ResourceLimits, SdkLayout};
+use buildsys::events::Event;
 use buildsys::manifest::{
-    ExternalKitMetadataView, ImageFeature, ImageFormat, ImageLayout, Manifest, PartitionPlan,
-    SupportedArch,
+    BuildMode, ExternalKitMetadataView, ImageFeature, ImageFormat, ImageLayout, Manifest,
+    OutputFormat, PackageNetwork, PartitionPlan, SupportedArch,
 };
+use buildsys::project::ProjectInfo;
+use buildsys::spec::SpecInfo;
+use buildsys::timing::{unix_ms, Cache, Timing};
 use buildsys::BuildType;
 use buildsys_config::EXTERNAL_KIT_METADATA;
+use checkpoint::{checkpoint_path, source_digests_path, Checkpoint};
+use digests::digests_path;
+use dockerfile::dockerfile_path;
 use duct::cmd;
 use error::Result;
+use events::Events;
+use invocation::{invocation_path, Invocation};
 use lazy_static::lazy_static;
 use nonzero_ext::nonzero;
 use pipesys::server::Server as PipesysServer;
+use provenance::{digest_cache_path, provenance_path, BuildRecord};
 use rand::Rng;
 use regex::Regex;
+use schedule::{duration_path, record_duration, Schedule};
+pub(crate) use sdk::build_sdk;
+use secrets::{PackageSecrets, SdkProxy};
 use sha2::{Digest, Sha512};
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
-use std::fs::{self, read_dir, File};
+use std::fs::{self, read_dir, File, OpenOptions};
+use std::io::{Read, Write};
 use std::num::NonZeroU16;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::process::Output;
+use std::time::{Duration, Instant, SystemTime};
+use user_data::check_user_data;
 use walkdir::{DirEntry, WalkDir};
 
 /*
@@ -106,18 +142,23 @@ enum OutputCleanup {
 struct CommonBuildArgs {
     arch: SupportedArch,
     sdk: String,
+    /// What identifies the SDK's contents, which checkpoints are keyed on
+    sdk_digest: String,
     nocache: String,
     token: String,
     cleanup: OutputCleanup,
     output_socket: String,
+    profile: ProfileArgs,
 }
 
 impl CommonBuildArgs {
     fn new(
         root: impl AsRef<Path>,
+        sdk_digest: String,
         sdk: String,
         arch: SupportedArch,
         cleanup: OutputCleanup,
+        profile: ProfileArgs,
     ) -> Self {
         let token = token(&root);
 
@@ -131,10 +172,12 @@ impl CommonBuildArgs {
         Self {
             arch,
             sdk,
+            sdk_digest,
             nocache,
             token,
             cleanup,
             output_socket,
+            profile,
         }
     }
 }
@@ -146,6 +189,20 @@ struct PackageBuildArgs {
     external_kit_dependencies: Vec<String>,
     version_build: String,
     version_build_timestamp: String,
+    kit_features: Vec<String>,
+    network: PackageNetwork,
+    /// The proxy the package downloads through, which is passed as a secret since it may hold
+    /// credentials
+    sdk_proxy: Option<String>,
+    /// The key of the inputs of the package's `%prep` and `%build` stages, which its build
+    /// directory is kept for
+    build_stage_key: String,
+    persistent_build_dir: bool,
+    build_mode: BuildMode,
+    /// The secrets the package's build reads, which are only gathered when it runs
+    secrets: Vec<String>,
+    /// Where the project's secrets are read from
+    secret_sources: String,
 }
 
 impl KitBuildArgs {
@@ -158,6 +215,9 @@ impl KitBuildArgs {
         args.build_arg("EXTERNAL_KIT_METADATA", &self.external_kit_metadata);
         args.build_arg("VENDOR", &self.vendor);
         args.build_arg("LOCAL_KIT_DEPENDENCIES", self.local_kits.join(" "));
+        args.build_arg("KIT_DEPRECATED", &self.deprecated);
+        args.build_arg("KIT_END_OF_SUPPORT", &self.end_of_support);
+        args.build_arg("KIT_LICENSE", &self.license);
         args
     }
 }
@@ -165,11 +225,16 @@ impl KitBuildArgs {
 struct KitBuildArgs {
     kit: String,
     package_dependencies: Vec<String>,
+    /// The directories holding the RPMs of the kit's packages, which aren't a build argument.
+    package_dirs: Vec<PathBuf>,
     external_kit_metadata: String,
     local_kits: Vec<String>,
     vendor: String,
     version_build: String,
     version_id: String,
+    deprecated: String,
+    end_of_support: String,
+    license: String,
 }
 
 impl crate::builder::PackageBuildArgs {
@@ -184,10 +249,52 @@ impl crate::builder::PackageBuildArgs {
         args.build_arg("PACKAGE_DEPENDENCIES", self.package_dependencies.join(" "));
         args.build_arg("BUILD_ID", &self.version_build);
         args.build_arg("BUILD_ID_TIMESTAMP", &self.version_build_timestamp);
+        args.build_arg("KIT_FEATURES", self.kit_features.join(" "));
+        args.build_arg("NETWORK", self.network.to_string());
+        args.build_arg("BUILD_STAGE_KEY", &self.build_stage_key);
+        args.build_arg(
+            "PERSISTENT_BUILD_DIR",
+            if self.persistent_build_dir {
+                "true"
+            } else {
+                ""
+            },
+        );
+        let build_mode = match self.build_mode {
+            BuildMode::Release => String::new(),
+            mode => mode.to_string(),
+        };
+        args.build_arg("BUILD_MODE", build_mode);
+        args.build_arg("BUILD_MODE_CFLAGS", self.build_mode.compiler_flags());
+        args.build_arg("BUILD_MODE_LDFLAGS", self.build_mode.linker_flags());
         args
     }
 }
 
+/// Converts `<kit>:<feature>` pairs into the names of rpmbuild conditionals, which may only contain
+/// letters, digits and underscores.
+fn kit_feature_conditionals(kit_features: &str) -> Vec<String> {
+    kit_features
+        .split_whitespace()
+        .map(|kit_feature| {
+            let name: String = kit_feature
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("kit_{name}")
+        })
+        .collect()
+}
+
+/// The names of the additional image formats, as the image scripts take them.
+fn output_formats(formats: &[OutputFormat]) -> String {
+    formats
+        .iter()
+        .map(OutputFormat::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 struct VariantBuildArgs {
     package_dependencies: Vec<String>,
     kit_dependencies: Vec<String>,
@@ -200,6 +307,7 @@ struct VariantBuildArgs {
     name: String,
     os_image_publish_size_gib: String,
     os_image_size_gib: String,
+    output_formats: String,
     packages: String,
     partition_plan: String,
     pretty_name: String,
@@ -231,6 +339,7 @@ impl VariantBuildArgs {
         );
         args.build_arg("OS_IMAGE_PUBLISH_SIZE_GIB", &self.os_image_publish_size_gib);
         args.build_arg("OS_IMAGE_SIZE_GIB", &self.os_image_size_gib);
+        args.build_arg("OUTPUT_FORMATS", &self.output_formats);
         args.build_arg("PACKAGES", &self.packages);
         args.build_arg("PACKAGE_DEPENDENCIES", self.package_dependencies.join(" "));
         args.build_arg("PARTITION_PLAN", &self.partition_plan);
@@ -258,10 +367,14 @@ struct RepackVariantBuildArgs {
     name: String,
     os_image_publish_size_gib: String,
     os_image_size_gib: String,
+    output_formats: String,
     partition_plan: String,
     variant: String,
     version_build: String,
     version_image: String,
+    kits: Vec<String>,
+    user_data: bool,
+    bootconfig: bool,
 }
 
 impl RepackVariantBuildArgs {
@@ -278,10 +391,14 @@ impl RepackVariantBuildArgs {
         args.build_arg("IMAGE_NAME", &self.name);
         args.build_arg("OS_IMAGE_PUBLISH_SIZE_GIB", &self.os_image_publish_size_gib);
         args.build_arg("OS_IMAGE_SIZE_GIB", &self.os_image_size_gib);
+        args.build_arg("OUTPUT_FORMATS", &self.output_formats);
         args.build_arg("PARTITION_PLAN", &self.partition_plan);
         args.build_arg("VARIANT", &self.variant);
         args.build_arg("BUILD_ID", &self.version_build);
         args.build_arg("VERSION_ID", &self.version_image);
+        args.build_arg("REPACK_KITS", self.kits.join(" "));
+        args.build_arg("REPACK_USER_DATA", if self.user_data { "1" } else { "" });
+        args.build_arg("REPACK_BOOTCONFIG", if self.bootconfig { "1" } else { "" });
 
         for image_feature in self.image_features.iter() {
             args.build_arg(format!("{}", image_feature), "1");
@@ -322,14 +439,112 @@ pub(crate) struct DockerBuild {
     common_build_args: CommonBuildArgs,
     target_build_args: TargetBuildArgs,
     secrets_args: Vec<String>,
+    /// The files and directories read by the build, which are recorded in its provenance.
+    inputs: Vec<PathBuf>,
+    /// The package's source groups, which are among its inputs but are checkpointed by the digest
+    /// of the files they hold that aren't ignored.
+    source_groups: Vec<PathBuf>,
+    /// Whether the build is skipped when the contents of its inputs are unchanged since it last
+    /// succeeded, which is only the case for package and kit builds.
+    checkpoints: bool,
+    /// Where to record the provenance of the build's artifacts, if anywhere.
+    provenance_dir: Option<PathBuf>,
+    /// Where to record how long the build took, if anywhere.
+    timings_dir: Option<PathBuf>,
+    /// Where to record why the build failed, if anywhere.
+    failures_dir: Option<PathBuf>,
+    /// Where to write the output of the Docker build, if anywhere.
+    log_path: Option<PathBuf>,
+    /// Where to report the events of the build, if anywhere.
+    events_path: Option<PathBuf>,
+    backend: BackendArgs,
+    /// The project's changes to the embedded Dockerfile.
+    dockerfile_args: DockerfileArgs,
+    /// The CPUs and memory the build may use, which are only limited for packages.
+    limits: ResourceLimits,
+    /// The slots which limit how many package builds run at once, if any.
+    schedule: Option<Schedule>,
 }
 
 impl DockerBuild {
     /// Create a new `DockerBuild` that can build a package.
     pub(crate) fn new_package(args: BuildPackageArgs, manifest: &Manifest) -> Result<Self> {
+        let sdk_digest = args.common.sdk_digest();
         let package = manifest.info().package_name();
         let per_package_dir = format!("{}/{}", args.packages_dir.display(), package).into();
         let old_package_dir = format!("{}", args.packages_dir.display()).into();
+        let package_dependencies = manifest.package_dependencies().context(error::GraphSnafu)?;
+        let arch = args.common.arch.to_string();
+        let schedule = match args.package_jobs {
+            Some(jobs) => Some(Schedule {
+                slots_dir: args.common.state_dir.join("slots"),
+                jobs,
+                priority: manifest
+                    .critical_path(|package| {
+                        schedule::weight(&duration_path(&args.common.state_dir, &arch, package))
+                    })
+                    .context(error::GraphSnafu)?,
+            }),
+            None => None,
+        };
+        let network = manifest
+            .info()
+            .network()
+            .or(args.package_network)
+            .unwrap_or_default();
+        let sdk_proxy = match network {
+            PackageNetwork::SdkProxy => Some(
+                args.sdk_proxy
+                    .clone()
+                    .context(error::SdkProxyMissingSnafu { package })?,
+            ),
+            PackageNetwork::None | PackageNetwork::Full => None,
+        };
+        let build_mode = manifest
+            .info()
+            .build_mode()
+            .or(args.package_build_mode)
+            .unwrap_or_default();
+        let source_groups: Vec<PathBuf> = manifest
+            .info()
+            .source_groups()
+            .into_iter()
+            .flatten()
+            .map(|group| args.sources_dir.join(group))
+            .collect();
+        // The package's build directory is kept until the SDK's digest, its source groups, its
+        // build mode, or the inputs of its `%prep` and `%build` stages change.
+        let spec = args
+            .common
+            .cargo_manifest_dir
+            .join(format!("{package}.spec"));
+        let build_stage_key = SpecInfo::new(&spec)
+            .and_then(|info| info.build_stage_key(&args.common.cargo_manifest_dir))
+            .context(error::SpecSnafu)?;
+        let build_stage_key = checkpoint::digest(
+            &[
+                sdk_digest.clone(),
+                build_stage_key,
+                source_groups_digest(
+                    &source_groups,
+                    &source_digests_path(&args.common.state_dir, &arch, package),
+                )?,
+                build_mode.to_string(),
+            ],
+            &[],
+        )?;
+
+        let mut inputs = vec![
+            args.common.tools_dir.join("build.Dockerfile"),
+            args.common.root_dir.join(EXTERNAL_KIT_METADATA),
+            args.common.cargo_manifest_dir.clone(),
+        ];
+        inputs.extend(source_groups.iter().cloned());
+        inputs.extend(
+            package_dependencies
+                .iter()
+                .map(|dependency| args.packages_dir.join(dependency)),
+        );
 
         Ok(Self {
             dockerfile: args.common.tools_dir.join("build.Dockerfile"),
@@ -346,30 +561,85 @@ impl DockerBuild {
             root_dir: args.common.root_dir.clone(),
             artifacts_dirs: vec![per_package_dir, old_package_dir],
             state_dir: args.common.state_dir,
+            timings_dir: args.common.timings_dir,
+            events_path: args.common.events_path,
+            failures_dir: args.common.failures_dir,
+            backend: args.common.backend,
+            dockerfile_args: args.common.dockerfile,
+            log_path: log_path(
+                args.common.logs_dir.as_deref(),
+                &args.common.arch.to_string(),
+                "package",
+                package,
+            ),
             artifact_name: package.to_string(),
+            limits: ResourceLimits {
+                cpus: manifest.info().cpus().or(args.package_cpus),
+                memory: manifest
+                    .info()
+                    .memory()
+                    .map(str::to_string)
+                    .or(args.package_memory),
+            },
+            schedule,
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
+                sdk_digest,
                 args.common.sdk_image,
                 args.common.arch,
                 OutputCleanup::BeforeBuild,
+                args.common.profile,
             ),
             target_build_args: TargetBuildArgs::Package(PackageBuildArgs {
                 package: package.to_string(),
-                package_dependencies: manifest.package_dependencies().context(error::GraphSnafu)?,
+                package_dependencies,
                 kit_dependencies: manifest.kit_dependencies().context(error::GraphSnafu)?,
                 external_kit_dependencies: ExternalKitMetadataView::load(args.common.root_dir)
                     .context(error::GraphSnafu)?
                     .list(),
                 version_build: args.version_build,
                 version_build_timestamp: args.version_build_timestamp,
+                kit_features: kit_feature_conditionals(&args.kit_features),
+                network,
+                sdk_proxy,
+                build_stage_key,
+                persistent_build_dir: manifest.info().persistent_build_dir(),
+                build_mode,
+                secrets: manifest.info().secrets().to_vec(),
+                secret_sources: args.secrets.unwrap_or_default(),
             }),
             secrets_args: Vec::new(),
+            inputs,
+            source_groups,
+            // Directory rerun hints rely on the checkpoint to skip builds when only ignored files
+            // changed.
+            checkpoints: args.common.checkpoints || args.rerun_hints == RerunHints::Directories,
+            provenance_dir: args
+                .common
+                .provenance_dir
+                .filter(|_| args.common.provenance),
         })
     }
 
     pub(crate) fn new_kit(args: BuildKitArgs, manifest: &Manifest) -> Result<Self> {
+        let sdk_digest = args.common.sdk_digest();
         let kit = manifest.info().kit_name();
         let per_kit_dir = args.kits_dir.join(kit);
+        let local_kits = manifest.kit_dependencies().context(error::GraphSnafu)?;
+        let package_dependencies = manifest.package_dependencies().context(error::GraphSnafu)?;
+
+        let package_dirs: Vec<PathBuf> = package_dependencies
+            .iter()
+            .map(|package| args.packages_dir.join(package))
+            .collect();
+
+        let mut inputs = vec![
+            args.common.tools_dir.join("build.Dockerfile"),
+            args.common.root_dir.join(EXTERNAL_KIT_METADATA),
+            args.common.cargo_manifest_dir.clone(),
+        ];
+        inputs.extend(package_dirs.iter().cloned());
+        inputs.extend(local_kits.iter().map(|kit| args.kits_dir.join(kit)));
 
         Ok(Self {
             dockerfile: args.common.tools_dir.join("build.Dockerfile"),
@@ -386,28 +656,59 @@ impl DockerBuild {
             root_dir: args.common.root_dir.clone(),
             artifacts_dirs: vec![per_kit_dir],
             state_dir: args.common.state_dir,
+            timings_dir: args.common.timings_dir,
+            events_path: args.common.events_path,
+            failures_dir: args.common.failures_dir,
+            backend: args.common.backend,
+            dockerfile_args: args.common.dockerfile,
+            limits: ResourceLimits::default(),
+            schedule: None,
+            log_path: log_path(
+                args.common.logs_dir.as_deref(),
+                &args.common.arch.to_string(),
+                "kit",
+                kit,
+            ),
             artifact_name: kit.to_string(),
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
+                sdk_digest,
                 args.common.sdk_image,
                 args.common.arch,
                 OutputCleanup::BeforeBuild,
+                args.common.profile,
             ),
             target_build_args: TargetBuildArgs::Kit(KitBuildArgs {
                 kit: kit.to_string(),
                 vendor: manifest.info().kit_vendor().context(error::GraphSnafu)?,
-                local_kits: manifest.kit_dependencies().context(error::GraphSnafu)?,
+                local_kits,
                 external_kit_metadata: EXTERNAL_KIT_METADATA.into(),
-                package_dependencies: manifest.package_dependencies().context(error::GraphSnafu)?,
+                package_dependencies,
+                package_dirs,
                 version_build: args.version_build,
                 version_id: args.version_image,
+                deprecated: manifest.info().kit_deprecated().unwrap_or_default().into(),
+                end_of_support: manifest
+                    .info()
+                    .kit_end_of_support()
+                    .unwrap_or_default()
+                    .into(),
+                license: manifest.info().license().unwrap_or_default().into(),
             }),
             secrets_args: Vec::new(),
+            inputs,
+            source_groups: Vec::new(),
+            checkpoints: args.common.checkpoints,
+            provenance_dir: args
+                .common
+                .provenance_dir
+                .filter(|_| args.common.provenance),
         })
     }
 
     /// Create a new `DockerBuild` that can build a variant image.
     pub(crate) fn new_variant(args: BuildVariantArgs, manifest: &Manifest) -> Result<Self> {
+        let sdk_digest = args.common.sdk_digest();
         let image_layout = manifest.info().image_layout().cloned().unwrap_or_default();
         let ImageLayout {
             os_image_size_gib,
@@ -419,6 +720,12 @@ impl DockerBuild {
         let (os_image_publish_size_gib, data_image_publish_size_gib) =
             image_layout.publish_image_sizes_gib();
 
+        let inputs = vec![
+            args.common.tools_dir.join("build.Dockerfile"),
+            args.common.root_dir.join(EXTERNAL_KIT_METADATA),
+            args.common.cargo_manifest_dir.clone(),
+        ];
+
         Ok(Self {
             dockerfile: args.common.tools_dir.join("build.Dockerfile"),
             context: args.common.root_dir.clone(),
@@ -434,12 +741,27 @@ impl DockerBuild {
             root_dir: args.common.root_dir.clone(),
             artifacts_dirs: vec![args.common.image_arch_variant_dir],
             state_dir: args.common.state_dir,
+            timings_dir: args.common.timings_dir,
+            events_path: args.common.events_path,
+            failures_dir: args.common.failures_dir,
+            backend: args.common.backend,
+            dockerfile_args: args.common.dockerfile,
+            limits: ResourceLimits::default(),
+            schedule: None,
+            log_path: log_path(
+                args.common.logs_dir.as_deref(),
+                &args.common.arch.to_string(),
+                "variant",
+                &args.variant,
+            ),
             artifact_name: args.variant.clone(),
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
+                sdk_digest,
                 args.common.sdk_image,
                 args.common.arch,
                 OutputCleanup::BeforeBuild,
+                args.common.profile,
             ),
             target_build_args: TargetBuildArgs::Variant(VariantBuildArgs {
                 package_dependencies: manifest.package_dependencies().context(error::GraphSnafu)?,
@@ -465,6 +787,7 @@ impl DockerBuild {
                 name: args.name,
                 os_image_publish_size_gib: os_image_publish_size_gib.to_string(),
                 os_image_size_gib: os_image_size_gib.to_string(),
+                output_formats: output_formats(&args.output_formats),
                 packages: manifest
                     .info()
                     .included_packages()
@@ -486,11 +809,19 @@ impl DockerBuild {
                 version_image: args.version_image,
             }),
             secrets_args: secrets_args()?,
+            inputs,
+            source_groups: Vec::new(),
+            checkpoints: false,
+            provenance_dir: args
+                .common
+                .provenance_dir
+                .filter(|_| args.common.provenance),
         })
     }
 
     /// Create a new `DockerBuild` that can repackage a variant image.
     pub(crate) fn repack_variant(args: RepackVariantArgs, manifest: &Manifest) -> Result<Self> {
+        let sdk_digest = args.common.sdk_digest();
         let image_layout = manifest.info().image_layout().cloned().unwrap_or_default();
         let ImageLayout {
             os_image_size_gib,
@@ -502,6 +833,33 @@ impl DockerBuild {
         let (os_image_publish_size_gib, data_image_publish_size_gib) =
             image_layout.publish_image_sizes_gib();
 
+        let mut inputs = vec![
+            args.common.tools_dir.join("build.Dockerfile"),
+            args.common.cargo_manifest_dir.clone(),
+        ];
+        for kit in &args.kits {
+            let path = args.kits_dir.join(kit).join(args.common.arch.to_string());
+            ensure!(path.is_dir(), error::RepackKitMissingSnafu { kit, path });
+            inputs.push(path);
+        }
+
+        // The files to add to the private partition may be anywhere, so they're passed to the
+        // build as secrets rather than through the project directory.
+        let mut secrets_args = secrets_args()?;
+        if let Some(user_data) = &args.user_data {
+            check_user_data(user_data, args.settings_schema.as_deref())?;
+            if let Some(settings_schema) = &args.settings_schema {
+                inputs.push(settings_schema.clone());
+            }
+            secrets_args.build_secret("file", "user-data.toml", &user_data.to_string_lossy());
+            inputs.push(user_data.clone());
+        }
+        if let Some(bootconfig) = &args.bootconfig {
+            fs::metadata(bootconfig).context(error::FileReadSnafu { path: bootconfig })?;
+            secrets_args.build_secret("file", "bootconfig.in", &bootconfig.to_string_lossy());
+            inputs.push(bootconfig.clone());
+        }
+
         Ok(Self {
             dockerfile: args.common.tools_dir.join("build.Dockerfile"),
             context: args.common.root_dir.clone(),
@@ -517,12 +875,27 @@ impl DockerBuild {
             root_dir: args.common.root_dir.clone(),
             artifacts_dirs: vec![args.common.image_arch_variant_dir],
             state_dir: args.common.state_dir,
+            timings_dir: args.common.timings_dir,
+            events_path: args.common.events_path,
+            failures_dir: args.common.failures_dir,
+            backend: args.common.backend,
+            dockerfile_args: args.common.dockerfile,
+            limits: ResourceLimits::default(),
+            schedule: None,
+            log_path: log_path(
+                args.common.logs_dir.as_deref(),
+                &args.common.arch.to_string(),
+                "repack",
+                &args.variant,
+            ),
             artifact_name: args.variant.clone(),
             common_build_args: CommonBuildArgs::new(
                 &args.common.root_dir,
+                sdk_digest,
                 args.common.sdk_image,
                 args.common.arch,
                 OutputCleanup::None,
+                args.common.profile,
             ),
             target_build_args: TargetBuildArgs::Repack(RepackVariantBuildArgs {
                 data_image_publish_size_gib,
@@ -537,6 +910,7 @@ impl DockerBuild {
                 name: args.name,
                 os_image_publish_size_gib: os_image_publish_size_gib.to_string(),
                 os_image_size_gib: os_image_size_gib.to_string(),
+                output_formats: output_formats(&args.output_formats),
                 partition_plan: match partition_plan {
                     PartitionPlan::Split => "split",
                     PartitionPlan::Unified => "unified",
@@ -545,12 +919,76 @@ impl DockerBuild {
                 variant: args.variant,
                 version_build: args.version_build,
                 version_image: args.version_image,
+                kits: args.kits,
+                user_data: args.user_data.is_some(),
+                bootconfig: args.bootconfig.is_some(),
             }),
-            secrets_args: secrets_args()?,
+            secrets_args,
+            inputs,
+            source_groups: Vec::new(),
+            checkpoints: false,
+            provenance_dir: args
+                .common
+                .provenance_dir
+                .filter(|_| args.common.provenance),
         })
     }
 
     pub(crate) fn build(&self) -> Result<()> {
+        Invocation::current()?.write(&invocation_path(
+            &self.state_dir,
+            &self.common_build_args.arch.to_string(),
+            &self.target,
+            &self.artifact_name,
+        ))?;
+
+        let events = Events {
+            path: self.events_path.clone(),
+            name: self.artifact_name.clone(),
+            kind: self.target.clone(),
+            arch: self.common_build_args.arch.to_string(),
+        };
+        events.emit(Event::StageStarted);
+        let start = SystemTime::now();
+        let mut cache = None;
+        let mut queued = Duration::ZERO;
+        let result = self.build_artifacts(&events, &mut cache, &mut queued);
+        events.emit(Event::StageFinished {
+            succeeded: result.is_ok(),
+        });
+        if let Some(timings_dir) = &self.timings_dir {
+            let timing = Timing {
+                name: self.artifact_name.clone(),
+                kind: self.target.clone(),
+                arch: self.common_build_args.arch.to_string(),
+                start_ms: unix_ms(start),
+                queue_ms: queued.as_millis(),
+                end_ms: unix_ms(SystemTime::now()),
+                cache,
+                succeeded: result.is_ok(),
+            };
+            timing::write(&timing, timings_dir)?;
+        }
+        if let (Err(e), Some(failures_dir)) = (&result, &self.failures_dir) {
+            failure::record(
+                failures_dir,
+                &self.common_build_args.arch.to_string(),
+                &self.target,
+                &self.artifact_name,
+                e,
+            )?;
+        }
+        result
+    }
+
+    /// Builds the artifacts, noting in `cache` whether the build was skipped by its checkpoint,
+    /// and in `queued` how long it waited for a slot to run in.
+    fn build_artifacts(
+        &self,
+        events: &Events,
+        cache: &mut Option<Cache>,
+        queued: &mut Duration,
+    ) -> Result<()> {
         env::set_current_dir(&self.root_dir).context(error::DirectoryChangeSnafu {
             path: &self.root_dir,
         })?;
@@ -563,6 +1001,49 @@ impl DockerBuild {
             &self.state_dir,
         )?;
 
+        // Skip the build if its inputs are unchanged since it last succeeded, and its outputs are
+        // still in place.
+        let kit_rpms = self.kit_rpms()?;
+        let checkpoint = self.checkpoint(kit_rpms.as_ref())?;
+        if let Some(checkpoint) = &checkpoint {
+            if checkpoint.is_current() && has_build_files(&marker_dir, &self.artifacts_dirs[0]) {
+                *cache = Some(Cache::Hit);
+                events.emit(Event::CacheHit);
+                return Ok(());
+            }
+            checkpoint.remove()?;
+        }
+        *cache = Some(Cache::Miss);
+        if let Some(rpms) = &kit_rpms {
+            self.explain_kit_rebuild(events, rpms);
+        }
+
+        // Explain how to run an SDK built for another architecture, rather than failing with an
+        // exec format error part way through the build.
+        emulation::check(
+            container::backend(self.backend.container_runtime).program(),
+            &self.common_build_args.sdk,
+        )?;
+
+        // Wait for a slot when the number of package builds is limited.
+        let waiting = Instant::now();
+        let _slot = match &self.schedule {
+            Some(schedule) => Some(schedule.acquire(&self.artifact_name, events)?),
+            None => None,
+        };
+        *queued = waiting.elapsed();
+        let start = Instant::now();
+        let started_on = SystemTime::now();
+
+        // Start a fresh log for this build.
+        if let Some(log_path) = &self.log_path {
+            let logs_dir = log_path
+                .parent()
+                .context(error::BadDirectorySnafu { path: log_path })?;
+            fs::create_dir_all(logs_dir).context(error::DirectoryCreateSnafu { path: logs_dir })?;
+            File::create(log_path).context(error::FileCreateSnafu { path: log_path })?;
+        }
+
         // Clean up any previous outputs we have tracked.
         match self.common_build_args.cleanup {
             OutputCleanup::BeforeBuild => {
@@ -571,25 +1052,99 @@ impl DockerBuild {
             OutputCleanup::None => (),
         }
 
-        let mut build = format!(
-            "build {context} \
-            --target {target} \
-            --tag {tag} \
-            --network host \
-            --file {dockerfile} \
-            --no-cache-filter rpmbuild,kitbuild,repobuild,imgbuild,migrationbuild,kmodkitbuild,imgrepack \
-            --build-arg BYPASS_SOCKET={tag}-bypass \
-            --build-arg BUILDER_UID={uid}",
-            context = self.context.display(),
-            dockerfile = self.dockerfile.display(),
-            target = self.target,
-            tag = self.tag,
-            uid = *BUILDER_UID,
-        )
-        .split_string();
-
-        build.extend(self.build_args());
-        build.extend(self.secrets_args.clone());
+        let mut args = Vec::new();
+        args.build_arg("BYPASS_SOCKET", format!("{}-bypass", self.tag));
+        args.build_arg("BUILDER_UID", BUILDER_UID.to_string());
+        args.extend(self.build_args());
+        args.extend(self.secrets_args.clone());
+        // Like other secrets, the package's secrets are not tracked for changes. The archive is
+        // kept until the build has finished.
+        let package_secrets = match &self.target_build_args {
+            TargetBuildArgs::Package(package) => {
+                PackageSecrets::gather(&package.package, &package.secrets, &package.secret_sources)?
+            }
+            _ => None,
+        };
+        if let Some(package_secrets) = &package_secrets {
+            args.extend(package_secrets.args());
+        }
+        let sdk_proxy = match &self.target_build_args {
+            TargetBuildArgs::Package(PackageBuildArgs {
+                sdk_proxy: Some(proxy),
+                ..
+            }) => Some(SdkProxy::write(proxy)?),
+            _ => None,
+        };
+        if let Some(sdk_proxy) = &sdk_proxy {
+            args.extend(sdk_proxy.args());
+        }
+        // Only package builds share their cache through a registry, since the stages of kit and
+        // variant builds are rerun every time.
+        let cache_ref = |repository: &String| {
+            format!(
+                "{repository}/{}-{}-{}",
+                self.common_build_args.arch, self.target, self.artifact_name
+            )
+        };
+        let is_package = matches!(self.target_build_args, TargetBuildArgs::Package(_));
+        let dockerfile = dockerfile::compose(
+            &self.dockerfile,
+            &self.root_dir,
+            &self.dockerfile_args,
+            &self.target,
+            &dockerfile_path(
+                &self.state_dir,
+                &self.common_build_args.arch.to_string(),
+                &self.target,
+                &self.artifact_name,
+            ),
+        )?;
+        let image_build = ImageBuild {
+            context: &self.context,
+            dockerfile: &dockerfile.path,
+            target: &dockerfile.target,
+            tag: &self.tag,
+            args,
+            cache_from: self
+                .backend
+                .cache_from
+                .as_ref()
+                .filter(|_| is_package)
+                .map(cache_ref),
+            cache_to: self
+                .backend
+                .cache_to
+                .as_ref()
+                .filter(|_| is_package)
+                .map(cache_ref),
+            limits: &self.limits,
+            sdk_layout: match (&self.backend.build_backend, &self.backend.sdk_layout) {
+                (BuildBackend::Buildkit, Some(layout)) => {
+                    Some(SdkLayout::read(&self.common_build_args.sdk, layout)?)
+                }
+                _ => None,
+            },
+        };
+        let containers = container::backend(self.backend.container_runtime);
+        // Only the container runtime's build leaves an image behind to clean up.
+        let leaves_image = self.backend.build_backend == BuildBackend::Runtime;
+        if leaves_image {
+            containers.prepare(&image_build)?;
+        }
+        if self.limits.is_set() && !(leaves_image && containers.enforces_limits()) {
+            events.emit(Event::Warning {
+                message: format!(
+                    "The CPU and memory limits of '{}' are not enforced when building with {}",
+                    self.artifact_name,
+                    if leaves_image {
+                        containers.program()
+                    } else {
+                        "buildctl"
+                    }
+                ),
+            });
+        }
+        let (program, build) = image_build.command(&self.backend);
 
         // Run a container with the project's root as a read-only volume mount, so that pipesys can
         // serve a read-only file descriptor that's safe to pass into builds.
@@ -616,10 +1171,14 @@ impl DockerBuild {
         let rm_bypass = format!("rm --force {}-bypass", self.tag).split_string();
 
         // Clean up the previous image if it exists.
-        let _ = docker(&rm_image, Retry::No);
+        if leaves_image {
+            let _ = run(containers.program(), &rm_image, Retry::No, None);
+        }
 
         // Clean up the stopped bypass container if it exists.
-        let _ = docker(&rm_bypass, Retry::No);
+        if leaves_image {
+            let _ = run(containers.program(), &rm_bypass, Retry::No, None);
+        }
 
         let runtime = tokio::runtime::Runtime::new().context(error::AsyncRuntimeSnafu)?;
 
@@ -633,14 +1192,26 @@ impl DockerBuild {
         });
 
         // Spawn a background task for the bypass container that will serve the project root file
-        // descriptor.
-        runtime.spawn(async move {
-            let _ = docker(&run_bypass, Retry::No);
-        });
+        // descriptor. Without a container runtime, the project root is served from here instead,
+        // which can't give the build a read-only mount of it.
+        if leaves_image {
+            runtime.spawn(async move {
+                let _ = run(containers.program(), &run_bypass, Retry::No, None);
+            });
+        } else {
+            let bypass_socket = format!("{}-bypass", self.tag);
+            let root_dir = self.root_dir.clone();
+            runtime.spawn(async move {
+                PipesysServer::for_path(bypass_socket, ROOT_UID, &root_dir)
+                    .serve()
+                    .await
+            });
+        }
 
         // Build the image, which builds the artifacts we want.
         // Work around transient, known failure cases with Docker.
-        let build_result = docker(
+        let build_result = run(
+            program,
             &build,
             Retry::Yes {
                 attempts: DOCKER_BUILD_MAX_ATTEMPTS,
@@ -650,27 +1221,254 @@ impl DockerBuild {
                     &*UNEXPECTED_EOF_ERROR,
                     &*CREATEREPO_C_READ_HEADER_ERROR,
                 ],
+                on_retry: &|attempt| {
+                    events.emit(Event::Warning {
+                        message: format!(
+                            "Attempt {attempt} of {DOCKER_BUILD_MAX_ATTEMPTS} hit a known \
+                            transient failure, retrying the build"
+                        ),
+                    });
+                },
             },
+            self.log_path.as_deref(),
         );
 
         // Clean up our bypass container.
-        let _ = docker(&rm_bypass, Retry::No);
+        if leaves_image {
+            let _ = run(containers.program(), &rm_bypass, Retry::No, None);
+        }
 
         // Stop the runtime and the background threads.
         runtime.shutdown_background();
 
         // Check whether the build succeeded before continuing.
-        build_result?;
+        let output = build_result?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if let Some(cache) = compiler_cache::unavailable(&stdout) {
+            events.emit(Event::Warning {
+                message: format!(
+                    "The SDK does not provide {cache}, so the package was built without a \
+                    compiler cache"
+                ),
+            });
+        }
+        if let Some(stats) = compiler_cache::stats(&stdout) {
+            compiler_cache::record(
+                &compiler_cache::stats_path(
+                    &self.state_dir,
+                    &self.common_build_args.arch.to_string(),
+                    &self.artifact_name,
+                ),
+                &stats,
+            )?;
+        }
 
         // Clean up our image now that we're done.
-        docker(&rm_image, Retry::No)?;
+        if leaves_image {
+            run(containers.program(), &rm_image, Retry::No, None)?;
+        }
 
         // Copy artifacts to the expected directory and write markers to track them.
-        copy_build_files(&marker_dir, &self.artifacts_dirs[0])?;
+        let artifacts = copy_build_files(&marker_dir, &self.artifacts_dirs[0])?;
+        for artifact in &artifacts {
+            events.emit(Event::ArtifactProduced {
+                path: artifact.clone(),
+            });
+        }
+
+        // Reproducible package builds record their RPMs' digests for comparison with other builds.
+        if is_package && self.common_build_args.profile.reproducible {
+            digests::record(
+                &digests_path(
+                    &self.state_dir,
+                    &self.common_build_args.arch.to_string(),
+                    &self.artifact_name,
+                ),
+                &artifacts,
+            )?;
+        }
+
+        if let Some(provenance_dir) = &self.provenance_dir {
+            let arch = self.common_build_args.arch.to_string();
+            BuildRecord {
+                kind: &self.target,
+                name: &self.artifact_name,
+                arch: &arch,
+                profile: self.common_build_args.profile.profile.as_deref(),
+                tag: &self.tag,
+                target: &dockerfile.target,
+                // The cache-busting arguments differ for every build, so they are left out.
+                build_args: self
+                    .build_args()
+                    .into_iter()
+                    .filter(|arg| {
+                        !arg.starts_with("--")
+                            && !arg.starts_with("NOCACHE=")
+                            && !arg.starts_with("OUTPUT_SOCKET=")
+                    })
+                    .collect(),
+                sdk: &self.common_build_args.sdk,
+                runtime: containers.program(),
+                inputs: &self.all_inputs(),
+                root_dir: &self.root_dir,
+                digest_cache: &digest_cache_path(
+                    &self.state_dir,
+                    &arch,
+                    &self.target,
+                    &self.artifact_name,
+                ),
+                start: started_on,
+            }
+            .write(
+                &provenance_path(provenance_dir, &arch, &self.target, &self.artifact_name),
+                &artifacts,
+            )?;
+        }
+
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.record()?;
+        }
+        if let Some(rpms) = &kit_rpms {
+            digests::write(&self.kit_digests_path(), rpms)?;
+        }
+
+        if is_package {
+            record_duration(
+                &duration_path(
+                    &self.state_dir,
+                    &self.common_build_args.arch.to_string(),
+                    &self.artifact_name,
+                ),
+                start.elapsed(),
+            )?;
+        }
 
         Ok(())
     }
 
+    /// The checkpoint of a package or kit build, computed from its build arguments and inputs. A
+    /// kit's is computed from the digests of the RPMs it's assembled from, rather than everything
+    /// in its packages' directories.
+    fn checkpoint(
+        &self,
+        kit_rpms: Option<&BTreeMap<String, String>>,
+    ) -> Result<Option<Checkpoint>> {
+        if !self.checkpoints {
+            return Ok(None);
+        }
+        let path = checkpoint_path(
+            &self.state_dir,
+            &self.common_build_args.arch.to_string(),
+            &self.target,
+            &self.artifact_name,
+        );
+        let package_dirs = match &self.target_build_args {
+            TargetBuildArgs::Kit(kit) => kit.package_dirs.as_slice(),
+            _ => &[],
+        };
+        // The cache-busting arguments differ for every build, so they are left out. So is a
+        // package's build ID, which changes with every commit: it only marks the releases of the
+        // package's RPMs, and Cargo doesn't rebuild packages when it changes either. A kit keeps
+        // its build ID, since its archives are named for it and published by that name. The SDK's
+        // digest is kept alongside its tag, which may be moved to a different image.
+        let is_package = matches!(self.target_build_args, TargetBuildArgs::Package(_));
+        let mut settings: Vec<String> = [
+            self.target.clone(),
+            self.tag.clone(),
+            self.common_build_args.sdk_digest.clone(),
+        ]
+        .into_iter()
+        .chain(self.build_args().into_iter().filter(|arg| {
+            let build_id = is_package && arg.starts_with("BUILD_ID");
+            !arg.starts_with("NOCACHE=") && !arg.starts_with("OUTPUT_SOCKET=") && !build_id
+        }))
+        .chain(self.dockerfile_args.dockerfile_labels.iter().cloned())
+        .chain(self.dockerfile_args.dockerfile_targets.iter().cloned())
+        .collect();
+        settings.push(source_groups_digest(
+            &self.source_groups,
+            &source_digests_path(
+                &self.state_dir,
+                &self.common_build_args.arch.to_string(),
+                &self.artifact_name,
+            ),
+        )?);
+        settings.extend(
+            kit_rpms
+                .into_iter()
+                .flatten()
+                .map(|(name, digest)| format!("{name}={digest}")),
+        );
+        let inputs: Vec<PathBuf> = self
+            .all_inputs()
+            .into_iter()
+            .filter(|input| !self.source_groups.contains(input) && !package_dirs.contains(input))
+            .collect();
+        Checkpoint::new(path, &settings, &inputs).map(Some)
+    }
+
+    /// The digests of the RPMs for the build's architecture in a kit's packages, when kits are
+    /// checkpointed.
+    fn kit_rpms(&self) -> Result<Option<BTreeMap<String, String>>> {
+        match &self.target_build_args {
+            TargetBuildArgs::Kit(kit) if self.checkpoints => {
+                digests::rpm_digests(&kit.package_dirs, &self.common_build_args.arch.to_string())
+                    .map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Where the digests of the RPMs the kit was last assembled from are recorded.
+    fn kit_digests_path(&self) -> PathBuf {
+        digests_path(
+            &self.state_dir,
+            &self.common_build_args.arch.to_string(),
+            &format!("kit-{}", self.artifact_name),
+        )
+    }
+
+    /// Reports which of the kit's RPMs changed since it was last assembled, if it has been.
+    fn explain_kit_rebuild(&self, events: &Events, rpms: &BTreeMap<String, String>) {
+        let Some(previous) = digests::load(&self.kit_digests_path()) else {
+            return;
+        };
+        let changed: Vec<&str> = rpms
+            .iter()
+            .filter(|(name, digest)| previous.get(*name) != Some(*digest))
+            .map(|(name, _)| name.as_str())
+            .chain(
+                previous
+                    .keys()
+                    .filter(|name| !rpms.contains_key(*name))
+                    .map(String::as_str),
+            )
+            .collect();
+        let message = if changed.is_empty() {
+            "Rebuilding, its RPMs are unchanged but its other inputs changed".to_string()
+        } else {
+            format!(
+                "Rebuilding, {} of its RPMs changed: {}",
+                changed.len(),
+                changed.join(", ")
+            )
+        };
+        events.emit(Event::Message { message });
+    }
+
+    /// The build's inputs, along with the project's fragments of the Dockerfile.
+    fn all_inputs(&self) -> Vec<PathBuf> {
+        let mut inputs = self.inputs.clone();
+        inputs.extend(
+            self.dockerfile_args
+                .dockerfile_fragments
+                .iter()
+                .map(|fragment| self.root_dir.join(fragment)),
+        );
+        inputs
+    }
+
     fn build_args(&self) -> Vec<String> {
         let mut args = match &self.target_build_args {
             TargetBuildArgs::Package(p) => p.build_args(),
@@ -684,29 +1482,70 @@ impl DockerBuild {
         args.build_arg("NOCACHE", &self.common_build_args.nocache);
         args.build_arg("TOKEN", &self.common_build_args.token);
         args.build_arg("OUTPUT_SOCKET", &self.common_build_args.output_socket);
+        let profile = &self.common_build_args.profile;
+        // Instrumented builds are for debugging, so they keep their debuginfo packages.
+        let instrumented = matches!(
+            &self.target_build_args,
+            TargetBuildArgs::Package(p) if p.build_mode != BuildMode::Release
+        );
+        let debuginfo = profile.rpm_debuginfo || instrumented;
+        args.build_arg("NO_DEBUGINFO", if debuginfo { "" } else { "1" });
+        args.build_arg("GO_BUILD_FLAGS", &profile.go_build_flags);
+        args.build_arg("REPRODUCIBLE", if profile.reproducible { "1" } else { "" });
+        args.build_arg(
+            "COMPILER_CACHE",
+            match profile.compiler_cache {
+                CompilerCache::None => "",
+                CompilerCache::Ccache => "ccache",
+                CompilerCache::Sccache => "sccache",
+            },
+        );
+        args.build_arg("COMPILER_CACHE_SIZE", &profile.compiler_cache_size);
+        args.build_arg(
+            "MAX_CPUS",
+            self.limits
+                .cpus
+                .map(|cpus| cpus.to_string())
+                .unwrap_or_default(),
+        );
+        args.build_arg(
+            "IMAGE_COMPRESSION_LEVEL",
+            profile.image_compression_level.to_string(),
+        );
         args
     }
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
-/// Run `docker` with the specified arguments.
-fn docker(args: &[String], retry: Retry) -> Result<Output> {
+/// Run `program` with the specified arguments. If `log` is given, the output is also appended to
+/// it as the command runs.
+fn run(program: &str, args: &[String], retry: Retry, log: Option<&Path>) -> Result<Output> {
     let mut max_attempts: u16 = 1;
     let mut retry_messages: &[&Regex] = &[];
-    if let Retry::Yes { attempts, messages } = retry {
+    let mut on_retry: &dyn Fn(u16) = &|_| ();
+    if let Retry::Yes {
+        attempts,
+        messages,
+        on_retry: retry_fn,
+    } = retry
+    {
         max_attempts = attempts.into();
         retry_messages = messages;
+        on_retry = retry_fn;
     }
 
     let mut attempt = 1;
     loop {
-        let output = cmd("docker", args)
-            .stderr_to_stdout()
-            .stdout_capture()
-            .unchecked()
-            .run()
-            .context(error::CommandStartSnafu)?;
+        let output = match log {
+            Some(log) => run_logged(program, args, log)?,
+            None => cmd(program, args)
+                .stderr_to_stdout()
+                .stdout_capture()
+                .unchecked()
+                .run()
+                .context(error::CommandStartSnafu)?,
+        };
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         println!("{}", &stdout);
@@ -716,15 +1555,56 @@ fn docker(args: &[String], retry: Retry) -> Result<Output> {
 
         ensure!(
             retry_messages.iter().any(|m| m.is_match(&stdout)) && attempt < max_attempts,
-            error::DockerExecutionSnafu {
-                args: &args.join(" ")
+            error::CommandExecutionSnafu {
+                command: format!("{program} {}", args.join(" ")),
+                output: stdout.to_string(),
             }
         );
 
+        on_retry(attempt);
         attempt += 1;
     }
 }
 
+/// Run `program` with the specified arguments, copying its output to the end of `log_path` as it
+/// is written so that the log can be followed while the build runs.
+fn run_logged(program: &str, args: &[String], log_path: &Path) -> Result<Output> {
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .context(error::FileCreateSnafu { path: log_path })?;
+    let mut reader = cmd(program, args)
+        .stderr_to_stdout()
+        .unchecked()
+        .reader()
+        .context(error::CommandStartSnafu)?;
+
+    let mut stdout = Vec::new();
+    let mut buf = [0; 8192];
+    loop {
+        let len = reader.read(&mut buf).context(error::CommandOutputSnafu)?;
+        if len == 0 {
+            break;
+        }
+        log.write_all(&buf[..len])
+            .context(error::FileWriteSnafu { path: log_path })?;
+        stdout.extend_from_slice(&buf[..len]);
+    }
+
+    // The reader waits for the command to exit once its output ends.
+    let status = reader
+        .try_wait()
+        .context(error::CommandOutputSnafu)?
+        .context(error::CommandRunningSnafu)?
+        .status;
+    Ok(Output {
+        status,
+        stdout,
+        stderr: Vec::new(),
+    })
+}
+
 /// Allow the caller to configure retry behavior, since the command may fail
 /// for spurious reasons that should not be treated as an error.
 enum Retry<'a> {
@@ -732,11 +1612,32 @@ enum Retry<'a> {
     Yes {
         attempts: NonZeroU16,
         messages: &'a [&'static Regex],
+        /// Called with the number of the failed attempt before the command is retried.
+        on_retry: &'a dyn Fn(u16),
     },
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+/// The log of a build, in `<logs_dir>/<arch>/<kind>-<name>.log`.
+fn log_path(logs_dir: Option<&Path>, arch: &str, kind: &str, name: &str) -> Option<PathBuf> {
+    logs_dir.map(|logs_dir| logs_dir.join(arch).join(format!("{kind}-{name}.log")))
+}
+
+/// The digest of the files in the source groups which aren't ignored, so that changes to ignored
+/// files neither invalidate checkpoints nor the package's build directory. The digests of the
+/// individual files are cached in `cache`, so that only files which changed are read again.
+fn source_groups_digest(source_groups: &[PathBuf], cache: &Path) -> Result<String> {
+    if source_groups.is_empty() {
+        return Ok(String::new());
+    }
+    ProjectInfo::crawl(source_groups)
+        .and_then(|info| info.digest(Some(cache)))
+        .context(error::ProjectCrawlSnafu)
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
 /// Add secrets that might be needed for builds. Since most builds won't use
 /// them, they are not automatically tracked for changes. If necessary, builds
 /// can emit the relevant cargo directives for tracking in their build script.
@@ -820,7 +1721,8 @@ const MARKER_EXTENSION: &str = ".buildsys_marker";
 
 /// Copy build artifacts to the output directory.
 /// Before we copy each file, we create a corresponding marker file to record its existence.
-fn copy_build_files<P>(build_dir: P, output_dir: P) -> Result<()>
+/// Returns the paths of the copied artifacts.
+fn copy_build_files<P>(build_dir: P, output_dir: P) -> Result<Vec<PathBuf>>
 where
     P: AsRef<Path>,
 {
@@ -837,6 +1739,7 @@ where
         is_dir || is_not_marker || is_symlink
     }
 
+    let mut output_files = Vec::new();
     for artifact_file in find_files(&build_dir, has_artifacts) {
         let mut marker_file = artifact_file.clone().into_os_string();
         marker_file.push(MARKER_EXTENSION);
@@ -860,9 +1763,33 @@ where
             old_path: &artifact_file,
             new_path: &output_file,
         })?;
+        output_files.push(output_file);
     }
 
-    Ok(())
+    Ok(output_files)
+}
+
+/// Whether every artifact recorded by a marker file is still in the output directory. False when
+/// there are no marker files, since every build produces at least one artifact.
+fn has_build_files(build_dir: &Path, output_dir: &Path) -> bool {
+    let markers: Vec<PathBuf> = WalkDir::new(build_dir)
+        .follow_links(false)
+        .into_iter()
+        .flatten()
+        .map(|entry| entry.into_path())
+        .filter(|path| path.to_string_lossy().ends_with(MARKER_EXTENSION))
+        .collect();
+    !markers.is_empty()
+        && markers.iter().all(|marker| {
+            marker
+                .strip_prefix(build_dir)
+                .map(|relative| {
+                    let mut output_file = output_dir.join(relative);
+                    output_file.set_extension("");
+                    output_file.exists() || output_file.is_symlink()
+                })
+                .unwrap_or(false)
+        })
 }
 
 /// Remove build artifacts from any of the known output directories.
@@ -0,0 +1,446 @@
+/*!
+Image builds run either with the container runtime, or with `buildctl` against a buildkitd instance.
+Talking to buildkitd directly shows the progress of every build step, and doesn't need a container
+runtime's build support. `buildctl` builds don't produce an image, since only the artifacts the
+build sends back through pipesys are needed.
+
+buildkitd can't see the images loaded into or built by the container runtime, so an SDK which isn't
+pulled from a registry is given to `buildctl` builds as an OCI image layout instead. Referring to a
+layout from the Dockerfile needs a newer Dockerfile frontend than the one the Dockerfile asks for.
+
+Either way, package builds can import the cache of their build stages from a registry, and export
+it there, so that hosts such as CI runners share it rather than rebuilding identical stages.
+
+Package builds may be limited to a number of CPUs and an amount of memory, so that heavyweight
+packages don't starve the builds running alongside them. rpmbuild is told how many CPUs it may use
+with every backend. Podman's `build` enforces the limits itself. Docker builds with BuildKit, which
+ignores the limits of the `build` command, so a limited package is built on a buildx builder of its
+own whose container is limited instead. nerdctl and buildkitd have no way to limit a single build.
+
+*/
+use super::error::{self, Result};
+use super::{container, SplitString};
+use crate::args::{BackendArgs, BuildBackend};
+use serde::Deserialize;
+use snafu::{OptionExt, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The build stages which are always rerun, since they produce the artifacts.
+const NO_CACHE_STAGES: &str =
+    "rpmbuild,kitbuild,repobuild,imgbuild,migrationbuild,kmodkitbuild,imgrepack";
+
+/// The Dockerfile frontend which builds from an SDK layout, since the Dockerfile's own frontend
+/// predates `oci-layout://` contexts.
+const LAYOUT_FRONTEND: &str = "docker/dockerfile:1.5";
+
+/// The scheduling period, in microseconds, over which the CPU quota of limited builds is measured.
+const CPU_PERIOD: u64 = 100_000;
+
+pub(super) struct ImageBuild<'a> {
+    pub(super) context: &'a Path,
+    pub(super) dockerfile: &'a Path,
+    pub(super) target: &'a str,
+    pub(super) tag: &'a str,
+    /// `--build-arg` and `--secret` arguments, in the form `docker build` takes them
+    pub(super) args: Vec<String>,
+    /// The registry reference to import the build cache from, if any
+    pub(super) cache_from: Option<String>,
+    /// The registry reference to export the build cache to, if any
+    pub(super) cache_to: Option<String>,
+    pub(super) limits: &'a ResourceLimits,
+    /// The SDK image and the layout holding it, for builds which can't pull it
+    pub(super) sdk_layout: Option<SdkLayout>,
+}
+
+/// An SDK image in an OCI image layout.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct SdkLayout {
+    /// The SDK image, as the Dockerfile refers to it
+    image: String,
+    path: PathBuf,
+    /// The digest of the image's manifest in the layout
+    digest: String,
+}
+
+#[derive(Deserialize)]
+struct LayoutIndex {
+    manifests: Vec<LayoutManifest>,
+}
+
+#[derive(Deserialize)]
+struct LayoutManifest {
+    digest: String,
+}
+
+impl SdkLayout {
+    /// Finds the SDK `image` in the layout at `path`, which holds only the SDK.
+    pub(super) fn read(image: &str, path: &Path) -> Result<Self> {
+        let index_path = path.join("index.json");
+        let index =
+            fs::read(&index_path).context(error::SdkLayoutReadSnafu { path: &index_path })?;
+        let index: LayoutIndex = serde_json::from_slice(&index)
+            .context(error::SdkLayoutParseSnafu { path: &index_path })?;
+        let manifest = index
+            .manifests
+            .into_iter()
+            .next()
+            .context(error::SdkLayoutEmptySnafu { path })?;
+        Ok(Self {
+            image: image.to_string(),
+            path: path.to_path_buf(),
+            digest: manifest.digest,
+        })
+    }
+
+    /// Arguments which have `buildctl` build from the layout wherever the Dockerfile uses the
+    /// image.
+    fn buildctl_args(&self) -> Vec<String> {
+        vec![
+            "--oci-layout".to_string(),
+            format!("sdk={}", self.path.display()),
+            "--opt".to_string(),
+            format!("context:{}=oci-layout://sdk@{}", self.image, self.digest),
+            "--opt".to_string(),
+            format!("build-arg:BUILDKIT_SYNTAX={LAYOUT_FRONTEND}"),
+        ]
+    }
+}
+
+/// Arguments which point `buildctl` at the buildkitd instance, if one is given.
+pub(super) fn buildkit_addr_args(backend: &BackendArgs) -> Vec<String> {
+    match &backend.buildkit_addr {
+        Some(addr) => vec!["--addr".to_string(), addr.clone()],
+        None => Vec::new(),
+    }
+}
+
+/// The CPUs and memory a build may use.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ResourceLimits {
+    pub(super) cpus: Option<u32>,
+    /// An amount of memory as container runtimes take it, e.g. `8g`
+    pub(super) memory: Option<String>,
+}
+
+impl ResourceLimits {
+    pub(super) fn is_set(&self) -> bool {
+        self.cpus.is_some() || self.memory.is_some()
+    }
+
+    /// The CPUs and memory as `name=value` container options, with the CPUs given as a quota of
+    /// CPU time per scheduling period.
+    fn options(&self) -> Vec<(&'static str, String)> {
+        let mut options = Vec::new();
+        if let Some(cpus) = self.cpus {
+            options.push(("cpu-period", CPU_PERIOD.to_string()));
+            options.push(("cpu-quota", (u64::from(cpus) * CPU_PERIOD).to_string()));
+        }
+        if let Some(memory) = &self.memory {
+            options.push(("memory", memory.clone()));
+        }
+        options
+    }
+}
+
+impl ImageBuild<'_> {
+    /// Returns the program and arguments which run the build with the chosen backend.
+    pub(super) fn command(&self, backend: &BackendArgs) -> (&'static str, Vec<String>) {
+        match backend.build_backend {
+            BuildBackend::Runtime => {
+                let runtime = container::backend(backend.container_runtime);
+                (runtime.program(), runtime.build_args(self))
+            }
+            BuildBackend::Buildkit => ("buildctl", self.buildctl_args(backend)),
+        }
+    }
+
+    /// Arguments for the `build` command of a container runtime. Runtimes which cannot leave
+    /// specific stages out of the cache still rerun them, since the `NOCACHE` build argument
+    /// changes for every build.
+    pub(super) fn runtime_args(&self, no_cache_filter: bool) -> Vec<String> {
+        let mut args = format!(
+            "build {context} \
+            --target {target} \
+            --tag {tag} \
+            --network host \
+            --file {dockerfile}",
+            context = self.context.display(),
+            dockerfile = self.dockerfile.display(),
+            target = self.target,
+            tag = self.tag,
+        )
+        .split_string();
+        if no_cache_filter {
+            args.push("--no-cache-filter".to_string());
+            args.push(NO_CACHE_STAGES.to_string());
+        }
+        args.extend(self.args.iter().cloned());
+        args
+    }
+
+    /// The host network must be allowed by buildkitd, with
+    /// `--allow-insecure-entitlement network.host`, for pipesys to reach the build.
+    fn buildctl_args(&self, backend: &BackendArgs) -> Vec<String> {
+        let mut args = buildkit_addr_args(backend);
+        args.extend(
+            format!(
+                "build \
+                --frontend dockerfile.v0 \
+                --local context={context} \
+                --local dockerfile={dockerfile_dir} \
+                --opt filename={dockerfile_name} \
+                --opt target={target} \
+                --opt force-network-mode=host \
+                --allow network.host \
+                --opt no-cache={NO_CACHE_STAGES} \
+                --progress plain",
+                context = self.context.display(),
+                dockerfile_dir = self.dockerfile.parent().unwrap_or(Path::new(".")).display(),
+                dockerfile_name = self
+                    .dockerfile
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                target = self.target,
+            )
+            .split_string(),
+        );
+        // Secrets are given the same way to both, but build arguments are frontend options.
+        for pair in self.args.chunks(2) {
+            if let [flag, value] = pair {
+                if flag == "--build-arg" {
+                    args.push("--opt".to_string());
+                    args.push(format!("build-arg:{value}"));
+                } else {
+                    args.push(flag.clone());
+                    args.push(value.clone());
+                }
+            }
+        }
+        args.extend(self.cache_args("--import-cache", "--export-cache"));
+        if let Some(sdk_layout) = &self.sdk_layout {
+            args.extend(sdk_layout.buildctl_args());
+        }
+        args
+    }
+
+    /// Arguments which limit the resources of the build's containers, for `build` commands which
+    /// enforce them. They have no `--cpus`.
+    pub(super) fn limit_args(&self) -> Vec<String> {
+        self.limits
+            .options()
+            .into_iter()
+            .flat_map(|(name, value)| [format!("--{name}"), value])
+            .collect()
+    }
+
+    /// The name of the buildx builder which builds within the build's limits. Builders are kept
+    /// between builds so that they keep their cache, and are named after the limits alone, so
+    /// that every build with the same limits shares one builder and a change to them makes a new
+    /// one. `twoliter build clean` removes them.
+    pub(super) fn limited_builder(&self) -> String {
+        let cpus = self.limits.cpus.map(|cpus| cpus.to_string());
+        format!(
+            "buildsys-limits-{}-{}",
+            cpus.as_deref().unwrap_or("x"),
+            self.limits.memory.as_deref().unwrap_or("x")
+        )
+    }
+
+    /// Arguments for `docker buildx create` which make the build's limited builder. The builder's
+    /// container shares the host's network so that builds can reach pipesys, as they do with the
+    /// default builder.
+    pub(super) fn limited_builder_args(&self) -> Vec<String> {
+        let mut args = format!(
+            "buildx create \
+            --name {name} \
+            --driver docker-container \
+            --driver-opt network=host \
+            --buildkitd-flags",
+            name = self.limited_builder(),
+        )
+        .split_string();
+        args.push("--allow-insecure-entitlement network.host".to_string());
+        for (name, value) in self.limits.options() {
+            args.push("--driver-opt".to_string());
+            args.push(format!("{name}={value}"));
+        }
+        args
+    }
+
+    /// Arguments which import and export the build cache, for tools which take BuildKit's cache
+    /// options with the given flags.
+    pub(super) fn cache_args(&self, import_flag: &str, export_flag: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cache_from) = &self.cache_from {
+            args.push(import_flag.to_string());
+            args.push(format!("type=registry,ref={cache_from}"));
+        }
+        if let Some(cache_to) = &self.cache_to {
+            args.push(export_flag.to_string());
+            args.push(format!("type=registry,ref={cache_to},mode=max"));
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use buildsys::runtime::ContainerRuntime;
+
+    #[test]
+    fn test_buildctl_args() {
+        let build = ImageBuild {
+            context: Path::new("/project"),
+            dockerfile: Path::new("/project/build/tools/build.Dockerfile"),
+            target: "package",
+            tag: "buildsys-pkg-glibc-x86_64",
+            args: vec![
+                "--build-arg".to_string(),
+                "ARCH=x86_64".to_string(),
+                "--secret".to_string(),
+                "type=file,id=root.json,src=/project/root.json".to_string(),
+            ],
+            cache_from: Some("registry.example.com/cache/x86_64-package-glibc".to_string()),
+            cache_to: None,
+            limits: &ResourceLimits::default(),
+            sdk_layout: None,
+        };
+        let backend = BackendArgs {
+            build_backend: BuildBackend::Buildkit,
+            container_runtime: ContainerRuntime::Docker,
+            buildkit_addr: Some("tcp://buildkitd:1234".to_string()),
+            cache_from: None,
+            cache_to: None,
+            sdk_layout: None,
+        };
+        let (program, args) = build.command(&backend);
+        assert_eq!(program, "buildctl");
+        assert_eq!(
+            args.join(" "),
+            "--addr tcp://buildkitd:1234 build --frontend dockerfile.v0 --local context=/project \
+            --local dockerfile=/project/build/tools --opt filename=build.Dockerfile \
+            --opt target=package --opt force-network-mode=host --allow network.host \
+            --opt no-cache=rpmbuild,kitbuild,repobuild,imgbuild,migrationbuild,kmodkitbuild,imgrepack \
+            --progress plain --opt build-arg:ARCH=x86_64 \
+            --secret type=file,id=root.json,src=/project/root.json \
+            --import-cache type=registry,ref=registry.example.com/cache/x86_64-package-glibc"
+        );
+    }
+
+    #[test]
+    fn test_limit_args() {
+        let limits = ResourceLimits {
+            cpus: Some(4),
+            memory: Some("8g".to_string()),
+        };
+        let build = ImageBuild {
+            context: Path::new("/project"),
+            dockerfile: Path::new("/project/build/tools/build.Dockerfile"),
+            target: "package",
+            tag: "buildsys-pkg-kernel-x86_64",
+            args: Vec::new(),
+            cache_from: None,
+            cache_to: None,
+            limits: &limits,
+            sdk_layout: None,
+        };
+        let command = |container_runtime| {
+            build.command(&BackendArgs {
+                build_backend: BuildBackend::Runtime,
+                container_runtime,
+                buildkit_addr: None,
+                cache_from: None,
+                cache_to: None,
+                sdk_layout: None,
+            })
+        };
+
+        // Podman's build enforces the limits itself.
+        let (program, args) = command(ContainerRuntime::Podman);
+        assert_eq!(program, "podman");
+        assert!(args
+            .join(" ")
+            .ends_with("--cpu-period 100000 --cpu-quota 400000 --memory 8g"));
+
+        // Docker builds on a builder whose container is limited.
+        let (program, args) = command(ContainerRuntime::Docker);
+        assert_eq!(program, "docker");
+        let args = args.join(" ");
+        assert!(args.starts_with(
+            "buildx build /project --builder buildsys-limits-4-8g --load \
+            --allow network.host "
+        ));
+        assert!(!args.contains("--memory"));
+        assert_eq!(
+            build.limited_builder_args(),
+            vec![
+                "buildx",
+                "create",
+                "--name",
+                "buildsys-limits-4-8g",
+                "--driver",
+                "docker-container",
+                "--driver-opt",
+                "network=host",
+                "--buildkitd-flags",
+                "--allow-insecure-entitlement network.host",
+                "--driver-opt",
+                "cpu-period=100000",
+                "--driver-opt",
+                "cpu-quota=400000",
+                "--driver-opt",
+                "memory=8g",
+            ]
+        );
+
+        // nerdctl can't limit a build.
+        let (_, args) = command(ContainerRuntime::Nerdctl);
+        assert!(!args.contains(&"--memory".to_string()));
+
+        // Unlimited Docker builds use the default builder.
+        let unlimited = ImageBuild {
+            limits: &ResourceLimits::default(),
+            ..build
+        };
+        assert!(unlimited
+            .command(&BackendArgs {
+                build_backend: BuildBackend::Runtime,
+                container_runtime: ContainerRuntime::Docker,
+                buildkit_addr: None,
+                cache_from: None,
+                cache_to: None,
+                sdk_layout: None,
+            })
+            .1
+            .join(" ")
+            .starts_with("build /project "));
+    }
+
+    #[test]
+    fn test_sdk_layout_args() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(SdkLayout::read("sdk:local", dir.path()).is_err());
+        fs::write(
+            dir.path().join("index.json"),
+            r#"{"schemaVersion": 2, "manifests": [{"digest": "sha256:abc", "size": 1}]}"#,
+        )
+        .unwrap();
+        let layout = SdkLayout::read("sdk:local", dir.path()).unwrap();
+        assert_eq!(
+            layout.buildctl_args(),
+            vec![
+                "--oci-layout".to_string(),
+                format!("sdk={}", dir.path().display()),
+                "--opt".to_string(),
+                "context:sdk:local=oci-layout://sdk@sha256:abc".to_string(),
+                "--opt".to_string(),
+                "build-arg:BUILDKIT_SYNTAX=docker/dockerfile:1.5".to_string(),
+            ]
+        );
+        fs::write(dir.path().join("index.json"), r#"{"manifests": []}"#).unwrap();
+        assert!(SdkLayout::read("sdk:local", dir.path()).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+/*!
+Checkpoints record the digest of everything a package or kit build reads, once the build succeeds.
+When Cargo reruns a build script whose inputs have the same contents as at the last successful
+build, and its artifacts are still in place, the build is skipped. This lets a variant build which
+failed late resume where it left off, even if file modification times have changed.
+
+A package's checkpoint leaves out the build ID, which changes with every commit but only marks the
+releases of its RPMs. A kit is only assembled from the RPMs of its packages for its architecture, so
+its checkpoint takes the digests of those RPMs in place of its packages' directories. A kit whose
+packages were rebuilt into identical RPMs isn't reassembled, but a kit is rebuilt for a new build ID,
+which names its archives.
+
+*/
+use super::error::{self, Result};
+use sha2::{Digest, Sha512};
+use snafu::ResultExt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub(super) struct Checkpoint {
+    path: PathBuf,
+    digest: String,
+}
+
+impl Checkpoint {
+    /// Computes the digest of a build from its `settings`, such as its build arguments, and the
+    /// contents of its `inputs`.
+    pub(super) fn new(path: PathBuf, settings: &[String], inputs: &[PathBuf]) -> Result<Self> {
+        Ok(Self {
+            path,
+            digest: digest(settings, inputs)?,
+        })
+    }
+
+    /// Whether the last successful build had the same digest.
+    pub(super) fn is_current(&self) -> bool {
+        fs::read_to_string(&self.path).is_ok_and(|digest| digest.trim() == self.digest)
+    }
+
+    /// Records the digest after a successful build.
+    pub(super) fn record(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context(error::DirectoryCreateSnafu { path: parent })?;
+        }
+        fs::write(&self.path, &self.digest).context(error::FileCreateSnafu { path: &self.path })
+    }
+
+    /// Forgets the last successful build, before its artifacts are cleaned up.
+    pub(super) fn remove(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context(error::FileRemoveSnafu { path: &self.path })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The digest of `settings` and the contents of `inputs`, which may be files or directories.
+/// Inputs which do not exist are recorded as missing.
+pub(super) fn digest(settings: &[String], inputs: &[PathBuf]) -> Result<String> {
+    let mut d = Sha512::new();
+    for setting in settings {
+        d.update(setting);
+        d.update([0]);
+    }
+    for input in inputs {
+        d.update(input.display().to_string());
+        d.update([0]);
+        if !input.exists() {
+            d.update("missing");
+            continue;
+        }
+        for entry in WalkDir::new(input).follow_links(false).sort_by_file_name() {
+            let entry = entry.context(error::DirectoryWalkSnafu)?;
+            let path = entry.path();
+            d.update(
+                path.strip_prefix(input)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .as_bytes(),
+            );
+            d.update([0]);
+            if entry.file_type().is_symlink() {
+                let target = fs::read_link(path).context(error::FileReadSnafu { path })?;
+                d.update(target.to_string_lossy().as_bytes());
+            } else if entry.file_type().is_file() {
+                let mut file = File::open(path).context(error::FileReadSnafu { path })?;
+                io::copy(&mut file, &mut d).context(error::FileReadSnafu { path })?;
+            }
+            d.update([0]);
+        }
+    }
+    Ok(hex::encode(d.finalize()))
+}
+
+/// The path of the checkpoint for a build, kept beside its marker directories under the state
+/// directory.
+pub(super) fn checkpoint_path(state_dir: &Path, arch: &str, prefix: &str, name: &str) -> PathBuf {
+    state_dir
+        .join(arch)
+        .join("checkpoints")
+        .join(format!("{prefix}-{name}"))
+}
+
+/// The path of the cache of the digests of a package's source files, which saves reading files
+/// that haven't changed to compute the digest of its source groups.
+pub(super) fn source_digests_path(state_dir: &Path, arch: &str, package: &str) -> PathBuf {
+    state_dir
+        .join(arch)
+        .join("source-digests")
+        .join(format!("{package}.json"))
+}
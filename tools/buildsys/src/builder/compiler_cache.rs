@@ -0,0 +1,106 @@
+/*!
+Package builds can keep a compiler cache, ccache for C and C++ or sccache for Rust, in a BuildKit
+cache mount shared by every package build, so that rebuilding packages such as the kernel or glibc
+only recompiles what changed. After the package is built, the cache's statistics for the build are
+printed between markers, which buildsys finds in the build's output and records so that Twoliter
+can report them. An SDK which doesn't provide the compiler cache builds the package without one,
+and prints a marker naming it so that buildsys can warn.
+
+*/
+use super::error::{self, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use snafu::ResultExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Printed by the build before the compiler cache statistics.
+const STATS_BEGIN: &str = "=== compiler cache statistics ===";
+
+/// Printed by the build after the compiler cache statistics.
+const STATS_END: &str = "=== end of compiler cache statistics ===";
+
+/// Printed by the build, with the name of the compiler cache, when the SDK doesn't provide it.
+const UNAVAILABLE: &str = "=== compiler cache unavailable: ";
+
+lazy_static! {
+    /// BuildKit prefixes each line of a step's output with the step number and elapsed time.
+    static ref BUILDKIT_PREFIX: Regex = Regex::new(r"^#\d+ \d+(\.\d+)? ").unwrap();
+}
+
+/// The compiler cache statistics in the output of a package build, if it printed any.
+pub(super) fn stats(output: &str) -> Option<String> {
+    let mut lines = output
+        .lines()
+        .map(|line| BUILDKIT_PREFIX.replace(line, ""))
+        .skip_while(|line| line.trim() != STATS_BEGIN)
+        .skip(1);
+    let mut stats = String::new();
+    for line in lines.by_ref() {
+        if line.trim() == STATS_END {
+            return Some(stats);
+        }
+        stats.push_str(&line);
+        stats.push('\n');
+    }
+    None
+}
+
+/// The compiler cache the package was built without, if the SDK didn't provide the one asked for.
+pub(super) fn unavailable(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        BUILDKIT_PREFIX
+            .replace(line, "")
+            .trim()
+            .strip_prefix(UNAVAILABLE)
+            .and_then(|rest| rest.strip_suffix(" ==="))
+            .map(str::to_string)
+    })
+}
+
+/// Writes the statistics to `path`.
+pub(super) fn record(path: &Path, stats: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::DirectoryCreateSnafu { path: parent })?;
+    }
+    fs::write(path, stats).context(error::FileCreateSnafu { path })
+}
+
+/// The compiler cache statistics of a package build are recorded in
+/// `<state_dir>/<arch>/compiler-cache/<name>.txt`.
+pub(super) fn stats_path(state_dir: &Path, arch: &str, name: &str) -> PathBuf {
+    state_dir
+        .join(arch)
+        .join("compiler-cache")
+        .join(format!("{name}.txt"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let output = "#14 301.2 Wrote: /home/builder/rpmbuild/RPMS/x86_64/kernel.rpm\n\
+            #14 301.5 === compiler cache statistics ===\n\
+            #14 301.5 Cacheable calls:   900 / 1000 (90.00%)\n\
+            #14 301.5   Hits:            450 /  900 (50.00%)\n\
+            #14 301.6 === end of compiler cache statistics ===\n\
+            #14 DONE 301.7s\n";
+        assert_eq!(
+            stats(output).as_deref(),
+            Some(
+                "Cacheable calls:   900 / 1000 (90.00%)\n  Hits:            450 /  900 (50.00%)\n"
+            )
+        );
+        assert_eq!(stats("#14 DONE 301.7s\n"), None);
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let output = "#14 0.2 === compiler cache unavailable: sccache ===\n\
+            #14 301.2 Wrote: /home/builder/rpmbuild/RPMS/x86_64/kernel.rpm\n";
+        assert_eq!(unavailable(output).as_deref(), Some("sccache"));
+        assert_eq!(unavailable("#14 DONE 301.7s\n"), None);
+    }
+}
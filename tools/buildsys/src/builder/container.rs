@@ -0,0 +1,187 @@
+/*!
+The container runtime runs the bypass container which serves the project to builds, and runs the
+image builds themselves unless they run with buildkit. Docker, Podman and nerdctl take the same
+arguments for everything buildsys asks of them, apart from a few build options. nerdctl's `build`
+doesn't take resource limits.
+
+Docker's default builder uses the `docker` driver, which can import a build cache but not export
+one, so buildsys checks the builder before a build that exports its cache rather than letting the
+build fail. Builds with resource limits run on a builder shared by the builds with the same
+limits, which is made if it doesn't exist yet.
+
+*/
+use super::backend::ImageBuild;
+use super::error::{self, Result};
+use buildsys::runtime::ContainerRuntime;
+use duct::cmd;
+use snafu::ResultExt;
+
+pub(super) trait ContainerBackend: Sync {
+    /// The CLI which talks to the container runtime.
+    fn program(&self) -> &'static str;
+
+    /// Arguments which build an image.
+    fn build_args(&self, build: &ImageBuild) -> Vec<String>;
+
+    /// Whether the runtime's builds keep to their resource limits.
+    fn enforces_limits(&self) -> bool;
+
+    /// Gets the runtime ready to run `build`, or fails if it can't.
+    fn prepare(&self, _build: &ImageBuild) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The arguments of a runtime which passes BuildKit's cache options through.
+fn buildkit_build_args(build: &ImageBuild, no_cache_filter: bool) -> Vec<String> {
+    let mut args = build.runtime_args(no_cache_filter);
+    args.extend(build.cache_args("--cache-from", "--cache-to"));
+    args
+}
+
+struct Docker;
+
+impl ContainerBackend for Docker {
+    fn program(&self) -> &'static str {
+        "docker"
+    }
+
+    /// Limited builds run on their builder with `docker buildx build`, and load the image into
+    /// Docker as the default builder does.
+    fn build_args(&self, build: &ImageBuild) -> Vec<String> {
+        let mut args = buildkit_build_args(build, true);
+        if build.limits.is_set() {
+            let builder = [
+                "--builder".to_string(),
+                build.limited_builder(),
+                "--load".to_string(),
+                "--allow".to_string(),
+                "network.host".to_string(),
+            ];
+            args.splice(2..2, builder);
+            args.insert(0, "buildx".to_string());
+        }
+        args
+    }
+
+    fn enforces_limits(&self) -> bool {
+        true
+    }
+
+    /// Makes the builder of a limited build, and otherwise checks that the default builder can
+    /// export the build's cache. The check is skipped when the builder can't be inspected, for
+    /// example because buildx isn't installed, in which case the build reports the problem itself.
+    fn prepare(&self, build: &ImageBuild) -> Result<()> {
+        if build.limits.is_set() {
+            let name = build.limited_builder();
+            let exists = || {
+                cmd("docker", ["buildx", "inspect", &name])
+                    .stdout_null()
+                    .stderr_null()
+                    .run()
+                    .is_ok()
+            };
+            if exists() {
+                return Ok(());
+            }
+            // A build running at the same time may make the builder between the check and the
+            // creation, in which case `buildx create` fails because the builder already exists.
+            if let Err(source) = cmd("docker", build.limited_builder_args())
+                .stdout_null()
+                .run()
+            {
+                if !exists() {
+                    return Err(source).context(error::LimitedBuilderCreateSnafu { name });
+                }
+            }
+            return Ok(());
+        }
+        if build.cache_to.is_none() {
+            return Ok(());
+        }
+        let Ok(output) = cmd("docker", ["buildx", "inspect"]).stderr_null().read() else {
+            return Ok(());
+        };
+        match builder_driver(&output) {
+            Some(driver) if driver == "docker" => {
+                error::CacheExportUnsupportedSnafu { driver }.fail()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The driver of the builder described by `docker buildx inspect`.
+fn builder_driver(inspect_output: &str) -> Option<&str> {
+    inspect_output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Driver:"))
+        .map(str::trim)
+}
+
+struct Podman;
+
+impl ContainerBackend for Podman {
+    fn program(&self) -> &'static str {
+        "podman"
+    }
+
+    /// Podman's cache options take a bare repository.
+    fn build_args(&self, build: &ImageBuild) -> Vec<String> {
+        let mut args = build.runtime_args(false);
+        if let Some(cache_from) = &build.cache_from {
+            args.push("--cache-from".to_string());
+            args.push(cache_from.clone());
+        }
+        if let Some(cache_to) = &build.cache_to {
+            args.push("--cache-to".to_string());
+            args.push(cache_to.clone());
+        }
+        args.extend(build.limit_args());
+        args
+    }
+
+    fn enforces_limits(&self) -> bool {
+        true
+    }
+}
+
+struct Nerdctl;
+
+impl ContainerBackend for Nerdctl {
+    fn program(&self) -> &'static str {
+        "nerdctl"
+    }
+
+    fn build_args(&self, build: &ImageBuild) -> Vec<String> {
+        buildkit_build_args(build, false)
+    }
+
+    fn enforces_limits(&self) -> bool {
+        false
+    }
+}
+
+/// The backend which drives `runtime`.
+pub(super) fn backend(runtime: ContainerRuntime) -> &'static dyn ContainerBackend {
+    match runtime {
+        ContainerRuntime::Docker => &Docker,
+        ContainerRuntime::Podman => &Podman,
+        ContainerRuntime::Nerdctl => &Nerdctl,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_builder_driver() {
+        let output =
+            "Name:          default\nDriver:        docker\n\nNodes:\nName:      default\n";
+        assert_eq!(builder_driver(output), Some("docker"));
+        let output = "Name:          ci\nDriver:        docker-container\n";
+        assert_eq!(builder_driver(output), Some("docker-container"));
+        assert_eq!(builder_driver(""), None);
+    }
+}
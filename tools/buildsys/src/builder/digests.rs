@@ -0,0 +1,110 @@
+/*!
+Records the digests of the RPMs a reproducible package build produced, so that two builds from the
+same inputs can be compared without keeping both sets of RPMs around.
+
+Kits are checkpointed by the digests of the RPMs they're assembled from, and the digests of the
+last successful build of a kit are recorded to explain why the next one isn't skipped.
+
+*/
+use super::error::{self, Result};
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Writes the sha256 digest of each RPM in `files` to `path`, as a JSON object keyed by file name.
+pub(super) fn record(path: &Path, files: &[PathBuf]) -> Result<()> {
+    let mut digests = BTreeMap::new();
+    for file in files {
+        if file.extension() == Some(OsStr::new("rpm")) {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            digests.insert(name.to_string(), sha256(file)?);
+        }
+    }
+    write(path, &digests)
+}
+
+/// The sha256 digest of each RPM for `arch` in the directories, keyed by file name. These are all
+/// a kit is assembled from, unlike the RPMs for other architectures kept beside them.
+pub(super) fn rpm_digests(dirs: &[PathBuf], arch: &str) -> Result<BTreeMap<String, String>> {
+    let suffixes = [format!(".{arch}.rpm"), ".noarch.rpm".to_string()];
+    let mut digests = BTreeMap::new();
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        for entry in fs::read_dir(dir).context(error::DirectoryReadSnafu { path: dir })? {
+            let path = entry
+                .context(error::DirectoryReadSnafu { path: dir })?
+                .path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if suffixes
+                .iter()
+                .any(|suffix| name.ends_with(suffix.as_str()))
+            {
+                digests.insert(name.to_string(), sha256(&path)?);
+            }
+        }
+    }
+    Ok(digests)
+}
+
+/// Reads the digests recorded at `path`, if there are any.
+pub(super) fn load(path: &Path) -> Option<BTreeMap<String, String>> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Writes `digests` to `path` as a JSON object.
+pub(super) fn write(path: &Path, digests: &BTreeMap<String, String>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::DirectoryCreateSnafu { path: parent })?;
+    }
+    let contents = serde_json::to_string_pretty(digests).context(error::DigestsSerializeSnafu)?;
+    fs::write(path, contents).context(error::FileCreateSnafu { path })
+}
+
+/// The digests of a package build are recorded in `<state_dir>/<arch>/digests/<name>.json`, and
+/// those of the RPMs a kit was last assembled from in `kit-<name>.json` beside them.
+pub(super) fn digests_path(state_dir: &Path, arch: &str, name: &str) -> PathBuf {
+    state_dir
+        .join(arch)
+        .join("digests")
+        .join(format!("{name}.json"))
+}
+
+/// The hex-encoded sha256 digest of the file at `path`.
+pub(super) fn sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context(error::FileReadSnafu { path })?;
+    let mut digest = Sha256::new();
+    io::copy(&mut file, &mut digest).context(error::FileReadSnafu { path })?;
+    Ok(hex::encode(digest.finalize()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn digests_rpms_for_arch() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path().join("pkg");
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "pkg-1.x86_64.rpm",
+            "pkg-1.aarch64.rpm",
+            "pkg-doc-1.noarch.rpm",
+        ] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let missing = temp_dir.path().join("missing");
+        let digests = rpm_digests(&[dir, missing], "x86_64").unwrap();
+        assert_eq!(
+            digests.keys().collect::<Vec<_>>(),
+            ["pkg-1.x86_64.rpm", "pkg-doc-1.noarch.rpm"]
+        );
+
+        let path = temp_dir.path().join("kit-core.json");
+        write(&path, &digests).unwrap();
+        assert_eq!(load(&path).unwrap(), digests);
+    }
+}
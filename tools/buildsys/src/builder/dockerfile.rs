@@ -0,0 +1,287 @@
+/*!
+A project can change the embedded Dockerfile without forking it, by declaring under `[dockerfile]`
+in Twoliter.toml files of extra build stages, called fragments, to append to it, stages from them
+to build in place of the usual ones, and labels to give the images built. Fragments may only add
+stages, so that the stages buildsys relies on stay as they are.
+
+The Dockerfile is composed separately for each build, since Cargo runs builds in parallel.
+
+*/
+use super::error::{self, Result};
+use crate::args::DockerfileArgs;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The stage added after the target stage to label the image a build produces.
+const LABELED_STAGE: &str = "buildsys-labeled";
+
+/// The Dockerfile a build uses, and the stage it builds.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) struct Dockerfile {
+    pub(super) path: PathBuf,
+    pub(super) target: String,
+}
+
+/// Composes the Dockerfile for a build of `kind` at `path`, from the embedded Dockerfile at `base`
+/// and the project's changes to it. The embedded Dockerfile is used as it is when the project has
+/// no changes.
+pub(super) fn compose(
+    base: &Path,
+    root_dir: &Path,
+    args: &DockerfileArgs,
+    kind: &str,
+    path: &Path,
+) -> Result<Dockerfile> {
+    if args.dockerfile_fragments.is_empty()
+        && args.dockerfile_labels.is_empty()
+        && args.dockerfile_targets.is_empty()
+    {
+        return Ok(Dockerfile {
+            path: base.to_path_buf(),
+            target: kind.to_string(),
+        });
+    }
+    let mut contents = fs::read_to_string(base).context(error::FileReadSnafu { path: base })?;
+    let mut stages = stage_names(&contents);
+    let mut fragment_stages = HashSet::new();
+    for fragment in &args.dockerfile_fragments {
+        let fragment_path = root_dir.join(fragment);
+        ensure!(
+            fragment.is_relative()
+                && fragment
+                    .components()
+                    .all(|component| component != Component::ParentDir),
+            error::DockerfileFragmentSnafu {
+                path: &fragment_path,
+                reason: "it must be a path within the project directory",
+            }
+        );
+        println!("cargo:rerun-if-changed={}", fragment_path.display());
+        let fragment_contents =
+            fs::read_to_string(&fragment_path).context(error::FileReadSnafu {
+                path: &fragment_path,
+            })?;
+        let new_stages = check_fragment(&fragment_contents).map_err(|reason| {
+            error::DockerfileFragmentSnafu {
+                path: &fragment_path,
+                reason,
+            }
+            .build()
+        })?;
+        for stage in new_stages {
+            ensure!(
+                stage != LABELED_STAGE && stages.insert(stage.clone()),
+                error::DockerfileFragmentSnafu {
+                    path: &fragment_path,
+                    reason: format!("stage '{stage}' is already defined"),
+                }
+            );
+            fragment_stages.insert(stage);
+        }
+        contents.push_str(&format!("\n# From {}\n", fragment.display()));
+        contents.push_str(&fragment_contents);
+        contents.push('\n');
+    }
+
+    let mut target = kind.to_string();
+    for setting in &args.dockerfile_targets {
+        let (target_kind, stage) = parse_setting(setting)?;
+        if target_kind == kind {
+            ensure!(
+                fragment_stages.contains(&stage.to_lowercase()),
+                error::DockerfileTargetSnafu { kind, stage }
+            );
+            target = stage.to_string();
+        }
+    }
+
+    if !args.dockerfile_labels.is_empty() {
+        contents.push_str(&format!("\nFROM {target} AS {LABELED_STAGE}\n"));
+        for setting in &args.dockerfile_labels {
+            let (key, value) = parse_setting(setting)?;
+            contents.push_str(&format!("LABEL \"{}\"=\"{}\"\n", quote(key), quote(value)));
+        }
+        target = LABELED_STAGE.to_string();
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::DirectoryCreateSnafu { path: parent })?;
+    }
+    fs::write(path, contents).context(error::FileCreateSnafu { path })?;
+    Ok(Dockerfile {
+        path: path.to_path_buf(),
+        target,
+    })
+}
+
+/// The Dockerfile of a build is composed in `<state_dir>/<arch>/dockerfiles/<kind>-<name>`, a
+/// directory of its own since `buildctl` takes the directory holding the Dockerfile.
+pub(super) fn dockerfile_path(state_dir: &Path, arch: &str, kind: &str, name: &str) -> PathBuf {
+    state_dir
+        .join(arch)
+        .join("dockerfiles")
+        .join(format!("{kind}-{name}"))
+        .join("build.Dockerfile")
+}
+
+/// Returns the stages a fragment defines. A fragment must start with a `FROM` instruction, since
+/// any instructions before it would change the last stage of the Dockerfile.
+fn check_fragment(fragment: &str) -> std::result::Result<Vec<String>, String> {
+    match instructions(fragment).next() {
+        Some(line) if is_instruction(line, "FROM") => {
+            Ok(stage_names(fragment).into_iter().collect())
+        }
+        Some(line) => Err(format!(
+            "it must start with a FROM instruction, not '{line}'"
+        )),
+        None => Err("it defines no stages".to_string()),
+    }
+}
+
+/// The names of the stages defined in a Dockerfile, in lowercase since they are case-insensitive.
+fn stage_names(dockerfile: &str) -> HashSet<String> {
+    instructions(dockerfile)
+        .filter(|line| is_instruction(line, "FROM"))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            words.find(|word| word.eq_ignore_ascii_case("AS"))?;
+            words.next().map(|name| name.to_lowercase())
+        })
+        .collect()
+}
+
+/// The lines of a Dockerfile which aren't blank or comments.
+fn instructions(dockerfile: &str) -> impl Iterator<Item = &str> {
+    dockerfile
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn is_instruction(line: &str, instruction: &str) -> bool {
+    line.split_whitespace()
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case(instruction))
+}
+
+/// Parses a setting given as `<name>=<value>`.
+fn parse_setting(setting: &str) -> Result<(&str, &str)> {
+    setting
+        .split_once('=')
+        .filter(|(name, _)| !name.is_empty())
+        .context(error::DockerfileSettingSnafu { setting })
+}
+
+/// Escapes a label's key or value for use between double quotes, where the Dockerfile would
+/// otherwise expand variables.
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BASE: &str = "# syntax=docker/dockerfile:1.4.3\nARG SDK\nFROM ${SDK} as sdk\n\
+        FROM sdk AS rpmbuild\nRUN rpmbuild\nFROM scratch AS package\n";
+
+    fn args(fragments: &[&str], labels: &[&str], targets: &[&str]) -> DockerfileArgs {
+        DockerfileArgs {
+            dockerfile_fragments: fragments.iter().map(PathBuf::from).collect(),
+            dockerfile_labels: labels.iter().map(|label| label.to_string()).collect(),
+            dockerfile_targets: targets.iter().map(|target| target.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_compose() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root_dir = temp_dir.path();
+        let base = root_dir.join("build.Dockerfile");
+        fs::write(&base, BASE).unwrap();
+        let path = dockerfile_path(&root_dir.join("state"), "x86_64", "package", "kernel");
+
+        let unchanged = compose(&base, root_dir, &args(&[], &[], &[]), "package", &path).unwrap();
+        assert_eq!(unchanged.path, base);
+        assert_eq!(unchanged.target, "package");
+
+        fs::write(
+            root_dir.join("scan.Dockerfile"),
+            "# Scans the RPMs\nFROM rpmbuild AS scan\nRUN scan\nFROM scratch AS scanned-package\n",
+        )
+        .unwrap();
+        let composed = compose(
+            &base,
+            root_dir,
+            &args(
+                &["scan.Dockerfile"],
+                &["team=os", "cost=$HOME"],
+                &["package=scanned-package", "kit=scan"],
+            ),
+            "package",
+            &path,
+        )
+        .unwrap();
+        assert_eq!(composed.path, path);
+        assert_eq!(composed.target, LABELED_STAGE);
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with(BASE));
+        assert!(contents.contains("RUN scan\n"));
+        assert!(contents.ends_with(
+            "FROM scanned-package AS buildsys-labeled\nLABEL \"team\"=\"os\"\n\
+                LABEL \"cost\"=\"\\$HOME\"\n"
+        ));
+
+        let targeted = compose(
+            &base,
+            root_dir,
+            &args(&["scan.Dockerfile"], &[], &["package=scanned-package"]),
+            "package",
+            &path,
+        )
+        .unwrap();
+        assert_eq!(targeted.target, "scanned-package");
+    }
+
+    #[test]
+    fn test_compose_rejects_invalid_changes() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root_dir = temp_dir.path();
+        let base = root_dir.join("build.Dockerfile");
+        fs::write(&base, BASE).unwrap();
+        let path = dockerfile_path(&root_dir.join("state"), "x86_64", "package", "kernel");
+        let compose_with = |fragment: &str, targets: &[&str]| {
+            fs::write(root_dir.join("extra.Dockerfile"), fragment).unwrap();
+            compose(
+                &base,
+                root_dir,
+                &args(&["extra.Dockerfile"], &[], targets),
+                "package",
+                &path,
+            )
+        };
+
+        assert!(compose_with("FROM sdk AS extra\n", &[]).is_ok());
+        // Redefines a stage of the embedded Dockerfile.
+        assert!(compose_with("FROM sdk AS RPMBUILD\n", &[]).is_err());
+        // Changes the last stage of the embedded Dockerfile.
+        assert!(compose_with("RUN rm -rf /\nFROM sdk AS extra\n", &[]).is_err());
+        assert!(compose_with("# Nothing\n", &[]).is_err());
+        // Lies outside the project directory.
+        assert!(compose(
+            &base,
+            root_dir,
+            &args(&["docker/../../extra.Dockerfile"], &[], &[]),
+            "package",
+            &path,
+        )
+        .is_err());
+        // Targets a stage which isn't from a fragment.
+        assert!(compose_with("FROM sdk AS extra\n", &["package=rpmbuild"]).is_err());
+        assert!(compose_with("FROM sdk AS extra\n", &["package"]).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+/*!
+A project may use an SDK image built for another architecture than the host's, such as one declared
+with `[sdk-arch.aarch64]` in Twoliter.toml to build natively for that architecture. Running it needs
+QEMU emulation registered with binfmt_misc. Without it, builds fail deep inside the image build with
+"exec format error", so buildsys checks first and explains how to set emulation up.
+
+Builds can't be sent to a remote builder of the SDK's architecture instead, since they exchange
+files with buildsys through sockets on the host.
+
+*/
+use super::error::{self, Result};
+use buildsys::manifest::SupportedArch;
+use duct::cmd;
+use snafu::ensure;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Where binfmt_misc lists the interpreters registered for foreign executables.
+const BINFMT_MISC_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// Fails unless this host can run containers from the `sdk` image with the container runtime's
+/// CLI, `program`. The check is skipped when the image's architecture can't be found, for example
+/// because it hasn't been pulled yet.
+pub(super) fn check(program: &str, sdk: &str) -> Result<()> {
+    let Ok(image_arch) = cmd(
+        program,
+        ["image", "inspect", "--format", "{{.Architecture}}", sdk],
+    )
+    .stderr_null()
+    .read() else {
+        return Ok(());
+    };
+    let (Some(sdk_arch), Ok(host_arch)) = (
+        from_goarch(image_arch.trim()),
+        SupportedArch::from_str(std::env::consts::ARCH),
+    ) else {
+        return Ok(());
+    };
+    ensure!(
+        sdk_arch == host_arch || is_emulated(Path::new(BINFMT_MISC_DIR), sdk_arch),
+        error::EmulationUnavailableSnafu {
+            sdk,
+            sdk_arch: sdk_arch.to_string(),
+            host_arch: host_arch.to_string(),
+            goarch: sdk_arch.goarch(),
+        }
+    );
+    Ok(())
+}
+
+/// Whether an enabled QEMU interpreter for `arch` is registered in `binfmt_dir`.
+fn is_emulated(binfmt_dir: &Path, arch: SupportedArch) -> bool {
+    let Ok(entries) = fs::read_dir(binfmt_dir) else {
+        return false;
+    };
+    let prefix = format!("qemu-{arch}");
+    entries.flatten().any(|entry| {
+        entry.file_name().to_string_lossy().starts_with(&prefix)
+            && fs::read_to_string(entry.path())
+                .is_ok_and(|contents| contents.lines().next() == Some("enabled"))
+    })
+}
+
+/// The architecture Docker calls `goarch`.
+fn from_goarch(goarch: &str) -> Option<SupportedArch> {
+    [SupportedArch::X86_64, SupportedArch::Aarch64]
+        .into_iter()
+        .find(|arch| arch.goarch() == goarch)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_emulated() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let binfmt_dir = temp_dir.path();
+        assert!(!is_emulated(binfmt_dir, SupportedArch::Aarch64));
+
+        fs::write(
+            binfmt_dir.join("qemu-aarch64"),
+            "disabled\ninterpreter /usr/bin/qemu\n",
+        )
+        .unwrap();
+        assert!(!is_emulated(binfmt_dir, SupportedArch::Aarch64));
+
+        fs::write(
+            binfmt_dir.join("qemu-aarch64"),
+            "enabled\ninterpreter /usr/bin/qemu\n",
+        )
+        .unwrap();
+        assert!(is_emulated(binfmt_dir, SupportedArch::Aarch64));
+        assert!(!is_emulated(binfmt_dir, SupportedArch::X86_64));
+        assert_eq!(from_goarch("arm64"), Some(SupportedArch::Aarch64));
+    }
+}
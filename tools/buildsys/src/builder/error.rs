@@ -16,8 +16,17 @@ pub(crate) enum Error {
     #[snafu(display("Failed to start command: {}", source))]
     CommandStart { source: std::io::Error },
 
-    #[snafu(display("Failed to execute command: 'docker {}'", args))]
-    DockerExecution { args: String },
+    #[snafu(display("Failed to describe the current process: {}", source))]
+    CurrentProcess { source: std::io::Error },
+
+    #[snafu(display("Failed to read command output: {}", source))]
+    CommandOutput { source: std::io::Error },
+
+    #[snafu(display("Command was still running after its output ended"))]
+    CommandRunning,
+
+    #[snafu(display("Failed to execute command: '{}'", command))]
+    CommandExecution { command: String, output: String },
 
     #[snafu(display("Failed to change directory to '{}': {}", path.display(), source))]
     DirectoryChange {
@@ -28,6 +37,30 @@ pub(crate) enum Error {
     #[snafu(display("Failed to get parent directory for '{}'", path.display()))]
     BadDirectory { path: PathBuf },
 
+    #[snafu(display("The SDK checkout has no Dockerfile at '{}'", path.display()))]
+    SdkDockerfileMissing { path: PathBuf },
+
+    #[snafu(display("Failed to read SDK layout index '{}': {}", path.display(), source))]
+    SdkLayoutRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse SDK layout index '{}': {}", path.display(), source))]
+    SdkLayoutParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("The SDK layout '{}' holds no image", path.display()))]
+    SdkLayoutEmpty { path: PathBuf },
+
+    #[snafu(display("Building the SDK with buildkit needs BUILDSYS_SDK_LAYOUT to write it to"))]
+    SdkLayoutMissing,
+
+    #[snafu(display("buildctl wrote no image digest to '{}'", path.display()))]
+    SdkBuildDigest { path: PathBuf },
+
     #[snafu(display("Failed to create directory '{}': {}", path.display(), source))]
     DirectoryCreate {
         path: PathBuf,
@@ -55,12 +88,30 @@ pub(crate) enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Failed to read file '{}': {}", path.display(), source))]
+    FileRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to write file '{}': {}", path.display(), source))]
+    FileWrite {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to remove file '{}': {}", path.display(), source))]
     FileRemove {
         path: PathBuf,
         source: std::io::Error,
     },
 
+    #[snafu(display("Failed to lock file '{}': {}", path.display(), source))]
+    FileLock {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to rename file '{}' to '{}': {}", old_path.display(), new_path.display(), source))]
     FileRename {
         old_path: PathBuf,
@@ -71,12 +122,165 @@ pub(crate) enum Error {
     #[snafu(display("Failed to create build arguments due to a dependency error: {source}"))]
     Graph { source: buildsys::manifest::Error },
 
+    #[snafu(display(
+        "The SDK image '{sdk}' is built for {sdk_arch}, but this {host_arch} host has no emulator \
+        registered to run it. Register QEMU emulation for {sdk_arch}, for example with \
+        `docker run --privileged --rm tonistiigi/binfmt --install {goarch}`, or build on an \
+        {sdk_arch} host"
+    ))]
+    EmulationUnavailable {
+        sdk: String,
+        sdk_arch: String,
+        host_arch: String,
+        goarch: String,
+    },
+
+    #[snafu(display(
+        "Exporting the build cache needs a Docker builder which uses the docker-container driver, \
+        but the current builder uses the '{driver}' driver. Create one with \
+        `docker buildx create --driver docker-container --use`, or stop exporting the cache"
+    ))]
+    CacheExportUnsupported { driver: String },
+
+    #[snafu(display("Failed to create the buildx builder '{}': {}", name, source))]
+    LimitedBuilderCreate {
+        name: String,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Missing environment variable '{}'", var))]
     Environment {
         var: String,
         source: std::env::VarError,
     },
 
+    #[snafu(display("Failed to serialize RPM digests: {}", source))]
+    DigestsSerialize { source: serde_json::Error },
+
+    #[snafu(display("Invalid Dockerfile fragment '{}': {}", path.display(), reason))]
+    DockerfileFragment { path: PathBuf, reason: String },
+
+    #[snafu(display("Invalid Dockerfile setting '{}', expected '<name>=<value>'", setting))]
+    DockerfileSetting { setting: String },
+
+    #[snafu(display(
+        "The Dockerfile target '{}' for {} builds is not a stage of any Dockerfile fragment",
+        stage,
+        kind
+    ))]
+    DockerfileTarget { kind: String, stage: String },
+
+    #[snafu(display("Failed to serialize build event: {}", source))]
+    EventSerialize { source: serde_json::Error },
+
+    #[snafu(display("Failed to serialize build provenance: {}", source))]
+    ProvenanceSerialize { source: serde_json::Error },
+
+    #[snafu(display("Failed to digest the inputs of the build's provenance: {}", source))]
+    ProvenanceDigest { source: buildsys::project::Error },
+
+    #[snafu(display(
+        "Kit '{}' can't replace the image's kit, since it hasn't been built for this architecture \
+        in '{}'",
+        kit,
+        path.display()
+    ))]
+    RepackKitMissing { kit: String, path: PathBuf },
+
+    #[snafu(display("Failed to parse user data '{}': {}", path.display(), source))]
+    UserDataParse {
+        path: PathBuf,
+        source: Box<toml::de::Error>,
+    },
+
+    #[snafu(display(
+        "User data '{}' may only contain `settings`, but has '{}'",
+        path.display(),
+        key
+    ))]
+    UserDataKey { path: PathBuf, key: String },
+
+    #[snafu(display("User data '{}' must set `settings` as a table", path.display()))]
+    UserDataSettings { path: PathBuf },
+
+    #[snafu(display("Failed to convert the settings in user data '{}': {}", path.display(), source))]
+    UserDataConvert {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "User data '{}' doesn't follow the settings schema '{}': {}",
+        path.display(),
+        schema.display(),
+        problem
+    ))]
+    UserDataSchema {
+        path: PathBuf,
+        schema: PathBuf,
+        problem: String,
+    },
+
+    #[snafu(display("Failed to parse settings schema '{}': {}", path.display(), source))]
+    SettingsSchemaParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Package '{}' downloads through the SDK proxy, but the project has no `sdk-proxy` in its \
+        `[package-limits]`",
+        package
+    ))]
+    SdkProxyMissing { package: String },
+
+    #[snafu(display("Failed to write the SDK proxy to '{}': {}", path.display(), source))]
+    SdkProxyWrite {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Package '{package}' reads the secret '{name}', which is not declared under `[secrets]` in \
+        Twoliter.toml"
+    ))]
+    SecretUndeclared { package: String, name: String },
+
+    #[snafu(display(
+        "Secret '{name}' is read from the environment variable '{var}', which is not set"
+    ))]
+    SecretEnv { name: String, var: String },
+
+    #[snafu(display("Failed to read secret '{}' from '{}': {}", name, path.display(), source))]
+    SecretRead {
+        name: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to archive package secrets in '{}': {}", path.display(), source))]
+    SecretArchive {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Invalid secret source '{line}', expected '<name>=env:<var>' or '<name>=file:<path>'"
+    ))]
+    SecretSource { line: String },
+
+    #[snafu(display("Failed to serialize buildsys invocation: {}", source))]
+    InvocationSerialize { source: serde_json::Error },
+
+    #[snafu(display("Failed to serialize build timing: {}", source))]
+    TimingSerialize { source: serde_json::Error },
+
+    #[snafu(display("Failed to crawl source groups: {}", source))]
+    ProjectCrawl { source: buildsys::project::Error },
+
+    #[snafu(display("Failed to parse spec file: {}", source))]
+    Spec { source: buildsys::spec::Error },
+
     #[snafu(display("Failed to strip prefix '{}' from path '{}': {}", prefix.display(), path.display(), source))]
     StripPathPrefix {
         path: PathBuf,
@@ -85,4 +289,14 @@ pub(crate) enum Error {
     },
 }
 
+impl Error {
+    /// The output of the failed command, if the error came from one.
+    pub(super) fn command_output(&self) -> Option<&str> {
+        match self {
+            Error::CommandExecution { output, .. } => Some(output),
+            _ => None,
+        }
+    }
+}
+
 pub(super) type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,119 @@
+/*!
+Reports the progress of a build as it happens, by appending a line of JSON for each event to a file
+that Twoliter follows. Cargo runs buildsys as a build script and only shows its output once it
+fails, so the file is how Twoliter learns which builds have started, been skipped, warned or
+produced artifacts. Cargo runs builds in parallel, so each event is appended with a single write.
+The file only reports progress, so an event that can't be written is warned about rather than
+failing the build.
+
+*/
+use super::error::{self, Result};
+use buildsys::diagnostics::Diagnostic;
+use buildsys::events::{BuildEvent, Event};
+use buildsys::timing::unix_ms;
+use snafu::ResultExt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Where the events of one build are reported, if anywhere.
+#[derive(Debug, Clone)]
+pub(super) struct Events {
+    pub(super) path: Option<PathBuf>,
+    pub(super) name: String,
+    pub(super) kind: String,
+    pub(super) arch: String,
+}
+
+impl Events {
+    /// Appends `event` to the file, warning if it can't.
+    pub(super) fn emit(&self, event: Event) {
+        if let Err(e) = self.append(event) {
+            Diagnostic::warning(
+                "build-events",
+                format!("Unable to report a build event: {e}"),
+            )
+            .emit();
+        }
+    }
+
+    fn append(&self, event: Event) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let record = BuildEvent {
+            name: self.name.clone(),
+            kind: self.kind.clone(),
+            arch: self.arch.clone(),
+            time_ms: unix_ms(SystemTime::now()),
+            event,
+        };
+        let mut line = serde_json::to_string(&record).context(error::EventSerializeSnafu)?;
+        line.push('\n');
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(error::DirectoryCreateSnafu { path: parent })?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(error::FileCreateSnafu { path })?
+            .write_all(line.as_bytes())
+            .context(error::FileWriteSnafu { path })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emit() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("events/x86_64-aws-dev.jsonl");
+        let events = Events {
+            path: Some(path.clone()),
+            name: "kernel".to_string(),
+            kind: "package".to_string(),
+            arch: "x86_64".to_string(),
+        };
+        events.emit(Event::StageStarted);
+        events.emit(Event::Message {
+            message: "Waiting for one of 4 build slots to free up".to_string(),
+        });
+        events.emit(Event::ArtifactProduced {
+            path: PathBuf::from("build/rpms/kernel/kernel.rpm"),
+        });
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["event"], "stage-started");
+        assert_eq!(lines[0]["name"], "kernel");
+        assert_eq!(lines[1]["event"], "message");
+        assert_eq!(
+            lines[1]["message"],
+            "Waiting for one of 4 build slots to free up"
+        );
+        assert_eq!(lines[2]["event"], "artifact-produced");
+        assert_eq!(lines[2]["path"], "build/rpms/kernel/kernel.rpm");
+
+        let disabled = Events {
+            path: None,
+            ..events.clone()
+        };
+        disabled.emit(Event::CacheHit);
+
+        // Failing to write an event doesn't fail the build.
+        let unwritable = Events {
+            path: Some(temp_dir.path().join("events/x86_64-aws-dev.jsonl/nested")),
+            ..events
+        };
+        assert!(unwritable.append(Event::CacheHit).is_err());
+        unwritable.emit(Event::CacheHit);
+    }
+}
@@ -0,0 +1,29 @@
+/*!
+Records why a build failed, along with the output of the failed Docker build, so that
+`twoliter build variant --keep-going` can list every broken package once Cargo has built
+everything it can.
+
+*/
+use super::error::{self, Error, Result};
+use snafu::ResultExt;
+use std::fs;
+use std::path::Path;
+
+/// Writes the failure to `<failures_dir>/<arch>-<kind>-<name>.log`. The first line describes the
+/// failure, and the rest is the output of the failed Docker build.
+pub(super) fn record(
+    failures_dir: &Path,
+    arch: &str,
+    kind: &str,
+    name: &str,
+    failure: &Error,
+) -> Result<()> {
+    fs::create_dir_all(failures_dir).context(error::DirectoryCreateSnafu { path: failures_dir })?;
+    let path = failures_dir.join(format!("{arch}-{kind}-{name}.log"));
+    let mut contents = format!("Failed to build {kind} '{name}': {failure}\n");
+    if let Some(output) = failure.command_output() {
+        contents.push('\n');
+        contents.push_str(output);
+    }
+    fs::write(&path, contents).context(error::FileCreateSnafu { path: &path })
+}
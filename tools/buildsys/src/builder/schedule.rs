@@ -0,0 +1,238 @@
+/*!
+Limits how many package builds run at once when `twoliter build variant --jobs` asks for it, rather
+than running every build Cargo starts. Cargo is allowed to start more builds than may run, and the
+waiting builds take the free slots in order of their critical path through the packages which
+depend on them, so that the longest chains of builds start first. Packages are weighed by how long
+they last took to build.
+
+Builds run in separate buildsys processes, so the slots and the queue of waiting builds are files
+which the builds holding them keep locked. The kernel releases the locks of a build which exits, so
+a slot or a place in the queue is never held by a build which was killed, even once its process ID
+has been reused.
+
+*/
+use super::error::{self, Result};
+use super::events::Events;
+use buildsys::events::Event;
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use snafu::ResultExt;
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::num::NonZeroU16;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// How often a waiting build checks for a free slot.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The weight, in seconds, of a package which has not been built before.
+const DEFAULT_WEIGHT: u64 = 60;
+
+/// The slots which limit how many package builds run at once.
+#[derive(Debug, Clone)]
+pub(super) struct Schedule {
+    pub(super) slots_dir: PathBuf,
+    pub(super) jobs: NonZeroU16,
+    /// The critical path through the packages which depend on this one
+    pub(super) priority: u64,
+}
+
+/// A slot, or a place in the queue, held by this build through the lock on its file. It is freed
+/// when dropped, or when the build exits.
+pub(super) struct Slot {
+    _lock: Flock<File>,
+    /// The file of a place in the queue, which is removed when the build leaves the queue
+    queue_entry: Option<PathBuf>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(path) = &self.queue_entry {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Schedule {
+    /// Waits until one of the slots is free and no waiting build with a higher priority needs it.
+    pub(super) fn acquire(&self, name: &str, events: &Events) -> Result<Slot> {
+        let queue_dir = self.slots_dir.join("queue");
+        fs::create_dir_all(&queue_dir).context(error::DirectoryCreateSnafu { path: &queue_dir })?;
+        // The build leaves the queue once it has a slot, or gives up.
+        let (entry, _waiting) = join_queue(&queue_dir, self.priority, name)?;
+
+        let rank = (self.priority, Reverse(name.to_string()));
+        let mut announced = false;
+        loop {
+            let mut ahead = waiting_ahead(&queue_dir, &entry, &rank)?;
+            for slot in 0..self.jobs.get() {
+                let path = self.slots_dir.join(format!("slot-{slot}"));
+                let file = OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(&path)
+                    .context(error::FileCreateSnafu { path: &path })?;
+                match try_lock(file, FlockArg::LockExclusiveNonblock, &path)? {
+                    // Free slots are left to the builds which outrank this one.
+                    Ok(_) if ahead > 0 => ahead -= 1,
+                    Ok(lock) => {
+                        return Ok(Slot {
+                            _lock: lock,
+                            queue_entry: None,
+                        })
+                    }
+                    Err(_) => continue,
+                }
+            }
+            if !announced {
+                events.emit(Event::Message {
+                    message: format!("Waiting for one of {} build slots to free up", self.jobs),
+                });
+                announced = true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Adds a locked entry recording the build's `priority` and `name` to the queue, returning the
+/// entry's file name. The entry is locked before it is moved into the queue, so that other builds
+/// never see it unlocked while this build waits.
+fn join_queue(queue_dir: &Path, priority: u64, name: &str) -> Result<(String, Slot)> {
+    let entry = format!("{}-{:016x}", process::id(), rand::random::<u64>());
+    let staged = queue_dir.join(format!(".{entry}"));
+    let path = queue_dir.join(&entry);
+    let mut file = File::create(&staged).context(error::FileCreateSnafu { path: &staged })?;
+    file.write_all(format!("{priority} {name}").as_bytes())
+        .context(error::FileWriteSnafu { path: &staged })?;
+    let lock = match try_lock(file, FlockArg::LockExclusiveNonblock, &staged)? {
+        Ok(lock) => lock,
+        Err(_) => {
+            return Err(io::Error::from(Errno::EWOULDBLOCK))
+                .context(error::FileLockSnafu { path: &staged })
+        }
+    };
+    fs::rename(&staged, &path).context(error::FileRenameSnafu {
+        old_path: &staged,
+        new_path: &path,
+    })?;
+    Ok((
+        entry,
+        Slot {
+            _lock: lock,
+            queue_entry: Some(path),
+        },
+    ))
+}
+
+/// Tries to lock `file` without waiting, returning the file back if another build holds a lock
+/// on it.
+fn try_lock(
+    file: File,
+    arg: FlockArg,
+    path: &Path,
+) -> Result<std::result::Result<Flock<File>, File>> {
+    match Flock::lock(file, arg) {
+        Ok(lock) => Ok(Ok(lock)),
+        Err((file, Errno::EWOULDBLOCK)) => Ok(Err(file)),
+        Err((_, errno)) => Err(io::Error::from(errno)).context(error::FileLockSnafu { path }),
+    }
+}
+
+/// How many builds waiting in the queue outrank the build whose entry is `own_entry`. Entries left
+/// behind by builds which exited are unlocked, and are removed.
+fn waiting_ahead(
+    queue_dir: &Path,
+    own_entry: &str,
+    rank: &(u64, Reverse<String>),
+) -> Result<usize> {
+    let mut ahead = 0;
+    for entry in fs::read_dir(queue_dir).context(error::DirectoryReadSnafu { path: queue_dir })? {
+        let path = entry
+            .context(error::DirectoryReadSnafu { path: queue_dir })?
+            .path();
+        let entry_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        // Entries which are still being added are locked once they are moved into place.
+        if entry_name == own_entry || entry_name.starts_with('.') {
+            continue;
+        }
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        let mut file = match try_lock(file, FlockArg::LockSharedNonblock, &path)? {
+            Ok(_) => {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            Err(file) => file,
+        };
+        let mut contents = String::new();
+        if file.read_to_string(&mut contents).is_err() {
+            continue;
+        }
+        let Some((priority, name)) = contents.split_once(' ') else {
+            continue;
+        };
+        let Ok(priority) = priority.parse::<u64>() else {
+            continue;
+        };
+        if (priority, Reverse(name.to_string())) > *rank {
+            ahead += 1;
+        }
+    }
+    Ok(ahead)
+}
+
+/// Package build durations are recorded in `<state_dir>/<arch>/durations/<name>`, in seconds.
+pub(super) fn duration_path(state_dir: &Path, arch: &str, name: &str) -> PathBuf {
+    state_dir.join(arch).join("durations").join(name)
+}
+
+/// Records how long a package took to build, to weigh it when scheduling later builds.
+pub(super) fn record_duration(path: &Path, duration: Duration) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::DirectoryCreateSnafu { path: parent })?;
+    }
+    fs::write(path, duration.as_secs().max(1).to_string()).context(error::FileCreateSnafu { path })
+}
+
+/// The weight of a package in the critical path: how long it last took to build.
+pub(super) fn weight(path: &Path) -> u64 {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| contents.trim().parse().ok())
+        .unwrap_or(DEFAULT_WEIGHT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queue_entries_are_held_by_locks() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let queue_dir = temp_dir.path();
+        let rank = (10, Reverse("pkg-b".to_string()));
+
+        let (entry, waiting) = join_queue(queue_dir, 20, "pkg-a").unwrap();
+        assert_eq!(waiting_ahead(queue_dir, "own", &rank).unwrap(), 1);
+        assert_eq!(waiting_ahead(queue_dir, &entry, &rank).unwrap(), 0);
+
+        // An entry left behind by a build which exited isn't locked, and is removed.
+        fs::write(queue_dir.join("1-0"), "30 pkg-c").unwrap();
+        assert_eq!(waiting_ahead(queue_dir, "own", &rank).unwrap(), 1);
+        assert!(!queue_dir.join("1-0").exists());
+
+        drop(waiting);
+        assert!(!queue_dir.join(&entry).exists());
+        assert_eq!(waiting_ahead(queue_dir, "own", &rank).unwrap(), 0);
+    }
+}
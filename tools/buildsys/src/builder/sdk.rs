@@ -0,0 +1,185 @@
+/*!
+The Bottlerocket SDK is usually pulled from a registry, at the version in `Twoliter.lock`. To try
+out changes to the SDK itself, a project can build it from a checkout of the SDK's repository
+instead. The image is built with the checkout's own `Dockerfile` and tagged, rather than sending
+artifacts back like other builds, so that Twoliter can build the project's packages with it. The
+image's ID is written out as well, since builds are keyed on it rather than the tag, which is the
+same for every build of the SDK.
+
+buildkitd can neither tag images in the container runtime nor use them, so with the buildkit backend
+the SDK is written to an OCI image layout instead, which package builds then build from. The digest
+of its manifest stands in for the image's ID.
+
+*/
+use super::backend::buildkit_addr_args;
+use super::error::{self, Result};
+use super::{container, run, BuildArg, Retry};
+use crate::args::{BuildBackend, BuildSdkArgs};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs;
+use std::path::Path;
+
+/// The proxy settings which are passed on to the SDK's build.
+const PROXY_VARS: [&str; 6] = [
+    "HTTP_PROXY",
+    "http_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "NO_PROXY",
+    "no_proxy",
+];
+
+/// Builds the SDK image for the architecture and tags it.
+pub(crate) fn build_sdk(args: &BuildSdkArgs) -> Result<()> {
+    let dockerfile = args.sdk_dir.join("Dockerfile");
+    ensure!(
+        dockerfile.is_file(),
+        error::SdkDockerfileMissingSnafu { path: &dockerfile }
+    );
+
+    println!(
+        "Building the SDK for {} from '{}'",
+        args.arch,
+        args.sdk_dir.display()
+    );
+    match args.backend.build_backend {
+        BuildBackend::Runtime => build_with_runtime(args, &dockerfile)?,
+        BuildBackend::Buildkit => build_with_buildkit(args)?,
+    }
+    println!("Built the SDK as {}", args.tag);
+    Ok(())
+}
+
+/// The build arguments of the SDK's build.
+fn sdk_build_args(args: &BuildSdkArgs) -> Vec<String> {
+    let mut build_args = Vec::new();
+    build_args.build_arg("ARCH", args.arch.to_string());
+    // The SDK's build downloads what it builds from, so it goes through the same proxy as Twoliter.
+    // Docker and BuildKit predefine these build arguments and leave them out of the image.
+    for name in PROXY_VARS {
+        if let Ok(value) = std::env::var(name) {
+            build_args.build_arg(name, value);
+        }
+    }
+    build_args
+}
+
+fn build_with_runtime(args: &BuildSdkArgs, dockerfile: &Path) -> Result<()> {
+    // A layout from an earlier build with buildkit would stand in for the image built here.
+    if let Some(layout) = &args.backend.sdk_layout {
+        if layout.exists() {
+            fs::remove_dir_all(layout).context(error::DirectoryRemoveSnafu { path: layout })?;
+        }
+    }
+
+    let mut build_args = vec![
+        "build".to_string(),
+        "--file".to_string(),
+        dockerfile.display().to_string(),
+        "--tag".to_string(),
+        args.tag.clone(),
+    ];
+    build_args.extend(sdk_build_args(args));
+    if let Some(id_file) = &args.id_file {
+        build_args.push("--iidfile".to_string());
+        build_args.push(id_file.display().to_string());
+    }
+    if let Some(target) = &args.target {
+        build_args.push("--target".to_string());
+        build_args.push(target.clone());
+    }
+    build_args.push(args.sdk_dir.display().to_string());
+
+    run(
+        container::backend(args.backend.container_runtime).program(),
+        &build_args,
+        Retry::No,
+        args.log.as_deref(),
+    )?;
+    Ok(())
+}
+
+fn build_with_buildkit(args: &BuildSdkArgs) -> Result<()> {
+    let layout = args
+        .backend
+        .sdk_layout
+        .as_deref()
+        .context(error::SdkLayoutMissingSnafu)?;
+    if layout.exists() {
+        fs::remove_dir_all(layout).context(error::DirectoryRemoveSnafu { path: layout })?;
+    }
+    let metadata = tempfile::NamedTempFile::new().context(error::FileCreateSnafu {
+        path: std::env::temp_dir(),
+    })?;
+
+    let sdk_dir = args.sdk_dir.display();
+    let mut build_args = buildkit_addr_args(&args.backend);
+    build_args.extend([
+        "build".to_string(),
+        "--frontend".to_string(),
+        "dockerfile.v0".to_string(),
+        "--local".to_string(),
+        format!("context={sdk_dir}"),
+        "--local".to_string(),
+        format!("dockerfile={sdk_dir}"),
+        "--opt".to_string(),
+        "filename=Dockerfile".to_string(),
+    ]);
+    for arg in sdk_build_args(args).chunks(2) {
+        build_args.push("--opt".to_string());
+        build_args.push(format!("build-arg:{}", arg[1]));
+    }
+    if let Some(target) = &args.target {
+        build_args.push("--opt".to_string());
+        build_args.push(format!("target={target}"));
+    }
+    build_args.extend([
+        "--output".to_string(),
+        format!(
+            "type=oci,dest={},tar=false,name={}",
+            layout.display(),
+            args.tag
+        ),
+        "--metadata-file".to_string(),
+        metadata.path().display().to_string(),
+    ]);
+    run("buildctl", &build_args, Retry::No, args.log.as_deref())?;
+
+    if let Some(id_file) = &args.id_file {
+        let digest = image_digest(metadata.path())?;
+        fs::write(id_file, digest).context(error::FileWriteSnafu { path: id_file })?;
+    }
+    Ok(())
+}
+
+/// Reads the digest of the image `buildctl` built from the metadata it wrote to `path`.
+fn image_digest(path: &Path) -> Result<String> {
+    let metadata = fs::read(path).context(error::FileReadSnafu { path })?;
+    let metadata: serde_json::Value = serde_json::from_slice(&metadata)
+        .ok()
+        .context(error::SdkBuildDigestSnafu { path })?;
+    metadata
+        .get("containerimage.digest")
+        .and_then(|digest| digest.as_str())
+        .map(str::to_string)
+        .context(error::SdkBuildDigestSnafu { path })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_image_digest() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("metadata.json");
+        fs::write(
+            &path,
+            r#"{"containerimage.digest": "sha256:abc", "image.name": "sdk"}"#,
+        )
+        .unwrap();
+        assert_eq!(image_digest(&path).unwrap(), "sha256:abc");
+        fs::write(&path, "{}").unwrap();
+        assert!(image_digest(&path).is_err());
+    }
+}
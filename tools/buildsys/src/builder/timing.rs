@@ -0,0 +1,17 @@
+/*!
+Records how long a build took, and whether it was skipped thanks to its checkpoint.
+
+*/
+use super::error::{self, Result};
+use buildsys::timing::Timing;
+use snafu::ResultExt;
+use std::fs;
+use std::path::Path;
+
+/// Writes `timing` to its file in `timings_dir`.
+pub(super) fn write(timing: &Timing, timings_dir: &Path) -> Result<()> {
+    fs::create_dir_all(timings_dir).context(error::DirectoryCreateSnafu { path: timings_dir })?;
+    let path = timings_dir.join(timing.file_name());
+    let contents = serde_json::to_string(timing).context(error::TimingSerializeSnafu)?;
+    fs::write(&path, contents).context(error::FileCreateSnafu { path: &path })
+}
@@ -0,0 +1,243 @@
+/*!
+User data which `repack-variant` writes to an image's private partition must be TOML which only sets
+a `settings` table, since that's all the image reads from it. The settings model is compiled into
+the image's API server, so the settings themselves can only be checked when a JSON Schema of the
+variant's settings is given. Without one, settings the variant doesn't have are only rejected when
+the image boots, and reported in its early boot logs.
+
+Only the keywords of JSON Schema which describe the shape of settings are understood: `type`,
+`properties`, `additionalProperties`, `required`, `items` and `enum`. Others are ignored.
+
+*/
+use super::error::{self, Result};
+use serde_json::Value;
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::Path;
+
+/// Checks the user data at `path`, and its settings against the schema at `schema`, if given.
+pub(super) fn check_user_data(path: &Path, schema: Option<&Path>) -> Result<()> {
+    let contents = fs::read_to_string(path).context(error::FileReadSnafu { path })?;
+    let user_data: toml::Table = toml::from_str(&contents)
+        .map_err(Box::new)
+        .context(error::UserDataParseSnafu { path })?;
+    if let Some(key) = user_data.keys().find(|key| *key != "settings") {
+        return error::UserDataKeySnafu { path, key }.fail();
+    }
+    let settings = match user_data.get("settings") {
+        Some(settings) => {
+            ensure!(settings.is_table(), error::UserDataSettingsSnafu { path });
+            settings.clone()
+        }
+        None => toml::Value::Table(Default::default()),
+    };
+
+    let Some(schema_path) = schema else {
+        return Ok(());
+    };
+    let schema = fs::read(schema_path).context(error::FileReadSnafu { path: schema_path })?;
+    let schema: Value = serde_json::from_slice(&schema)
+        .context(error::SettingsSchemaParseSnafu { path: schema_path })?;
+    let settings = serde_json::to_value(settings).context(error::UserDataConvertSnafu { path })?;
+    if let Some(problem) = check(&schema, &settings, "settings") {
+        return error::UserDataSchemaSnafu {
+            path,
+            schema: schema_path,
+            problem,
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+/// Checks `value`, found at `at`, against `schema`, returning what's wrong with it if anything.
+fn check(schema: &Value, value: &Value, at: &str) -> Option<String> {
+    let schema = match schema {
+        Value::Bool(true) => return None,
+        Value::Bool(false) => return Some(format!("'{at}' is not allowed")),
+        Value::Object(schema) => schema,
+        _ => return None,
+    };
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            return Some(format!("'{at}' must be of type {}", types.join(" or ")));
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return Some(format!(
+                "'{at}' must be one of {}",
+                Value::from(allowed.clone())
+            ));
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                if let Some(missing) = required
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .find(|name| !fields.contains_key(*name))
+                {
+                    return Some(format!("'{at}' must set '{missing}'"));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, field) in fields {
+                let field_at = format!("{at}.{name}");
+                let field_schema = properties
+                    .and_then(|properties| properties.get(name))
+                    .or_else(|| schema.get("additionalProperties"));
+                if let Some(problem) =
+                    field_schema.and_then(|field_schema| check(field_schema, field, &field_at))
+                {
+                    return Some(problem);
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    if let Some(problem) = check(item_schema, item, &format!("{at}[{index}]")) {
+                        return Some(problem);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    None
+}
+
+/// Whether `value` has the JSON Schema type `name`.
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn user_data_only_sets_settings() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("user-data.toml");
+        let check = |contents: &str| {
+            fs::write(&path, contents).unwrap();
+            check_user_data(&path, None)
+        };
+        check("[settings.kubernetes]\ncluster-name = \"test\"\n").unwrap();
+        check("").unwrap();
+        assert!(matches!(
+            check("[setting.motd]\n"),
+            Err(error::Error::UserDataKey { .. })
+        ));
+        assert!(matches!(
+            check("settings = \"motd\"\n"),
+            Err(error::Error::UserDataSettings { .. })
+        ));
+        assert!(matches!(
+            check("[settings\n"),
+            Err(error::Error::UserDataParse { .. })
+        ));
+    }
+
+    #[test]
+    fn settings_follow_schema() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("user-data.toml");
+        let schema_path = dir.path().join("settings.json");
+        fs::write(
+            &schema_path,
+            r#"{
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "motd": {"type": "string"},
+                    "kubernetes": {
+                        "type": "object",
+                        "required": ["cluster-name"],
+                        "properties": {
+                            "cluster-name": {"type": "string"},
+                            "max-pods": {"type": "integer"},
+                            "node-labels": {"type": "object", "additionalProperties": {"type": "string"}}
+                        }
+                    },
+                    "ntp": {
+                        "type": "object",
+                        "properties": {
+                            "time-servers": {"type": "array", "items": {"type": "string"}},
+                            "options": {"enum": ["iburst", "minpoll"]}
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let check = |contents: &str| {
+            fs::write(&path, contents).unwrap();
+            match check_user_data(&path, Some(&schema_path)) {
+                Err(error::Error::UserDataSchema { problem, .. }) => Err(problem),
+                result => {
+                    result.unwrap();
+                    Ok(())
+                }
+            }
+        };
+
+        check(
+            "[settings]\nmotd = \"hi\"\n[settings.kubernetes]\ncluster-name = \"test\"\n\
+            max-pods = 10\nnode-labels = { team = \"a\" }\n[settings.ntp]\n\
+            time-servers = [\"a.example.com\"]\noptions = \"iburst\"\n",
+        )
+        .unwrap();
+        check("").unwrap();
+        assert_eq!(
+            check("[settings]\nmtod = \"hi\"\n"),
+            Err("'settings.mtod' is not allowed".to_string())
+        );
+        assert_eq!(
+            check("[settings.kubernetes]\ncluster-name = \"test\"\nmax-pods = \"10\"\n"),
+            Err("'settings.kubernetes.max-pods' must be of type integer".to_string())
+        );
+        assert_eq!(
+            check("[settings.kubernetes]\nmax-pods = 10\n"),
+            Err("'settings.kubernetes' must set 'cluster-name'".to_string())
+        );
+        assert_eq!(
+            check("[settings.kubernetes]\ncluster-name = \"a\"\nnode-labels = { team = 1 }\n"),
+            Err("'settings.kubernetes.node-labels.team' must be of type string".to_string())
+        );
+        assert_eq!(
+            check("[settings.ntp]\ntime-servers = [\"a\", 1]\n"),
+            Err("'settings.ntp.time-servers[1]' must be of type string".to_string())
+        );
+        assert_eq!(
+            check("[settings.ntp]\noptions = \"maxpoll\"\n"),
+            Err("'settings.ntp.options' must be one of [\"iburst\",\"minpoll\"]".to_string())
+        );
+
+        fs::write(&schema_path, "not json").unwrap();
+        fs::write(&path, "").unwrap();
+        assert!(matches!(
+            check_user_data(&path, Some(&schema_path)),
+            Err(error::Error::SettingsSchemaParse { .. })
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+/*!
+Packages may have upstream tar archives that include only the source code of the
+project, but not the source code of its dependencies. Besides Go modules, which
+are handled by the `gomod` module, this Rust module extends the functionality of
+`packages.metadata.build-package.external-files` with the ability to vendor the
+dependencies locked by a few other package managers, so that the package can be
+built without network access:
+
+* Cargo vendors the crates locked by `Cargo.lock` with `cargo vendor --locked`,
+  which verifies each crate against its checksum in the lock file.
+* pip downloads the distributions pinned by `requirements.txt`, which must give
+  the hash of every distribution, since pip is run in hash-checking mode. Wheels
+  are chosen for the architecture being built rather than that of the build host.
+* npm installs the packages locked by `package-lock.json` with `npm ci`, which
+  verifies each package against its integrity hash in the lock file. Every
+  package must have one, and install scripts are not run.
+
+The dependencies are fetched in the SDK container by the docker-bundle script.
+Downloads are cached in the project's Cargo home for Cargo, and in the build
+state directory for pip and npm. The registry and proxy are controlled by the
+standard configuration and environment variables of each package manager.
+
+ */
+
+pub(crate) mod error;
+
+use buildsys::manifest::{self, SupportedArch};
+use duct::cmd;
+use error::Result;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// A package manager whose dependencies are vendored in the SDK.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PackageManager {
+    Cargo,
+    Pip,
+    Npm,
+}
+
+pub(crate) struct Bundle;
+
+const BUNDLE_DOCKER_SCRIPT_NAME: &str = "docker-bundle-script.sh";
+
+// The following bash template script is intended to be run within a container
+// using the docker-bundle tool that twoliter installs alongside buildsys.
+//
+// Like the Go module script, it uses the top level directory found in the
+// package upstream archive as the project path if no explicit path was provided.
+// It untars the archive and vendors the dependencies with the package manager's
+// commands, which are substituted for __VENDOR__. The vendored paths are written
+// to the output archive, with fixed owners, timestamps and order, so that the
+// archive only changes when the locked dependencies do. Finally, it cleans up by
+// removing the untar'd source code.
+const BUNDLE_SCRIPT_TMPL: &str = r#"#!/bin/bash
+
+set -e -o pipefail
+
+toplevel=$(tar tf __LOCAL_FILE_NAME__ | head -1)
+if [ -z __PROJECT_DIR__ ] ; then
+    targetdir="${toplevel}"
+else
+    targetdir="__PROJECT_DIR__"
+fi
+
+tar xf __LOCAL_FILE_NAME__
+
+pushd "${targetdir}"
+__VENDOR__
+popd
+
+tar --sort=name --mtime=@0 --owner=0 --group=0 --numeric-owner -cf - \
+    __PATHS__ | gzip -n > __OUTPUT__
+rm -rf "${targetdir}"
+touch -r __LOCAL_FILE_NAME__ __OUTPUT__
+"#;
+
+// Appends the source replacement printed by `cargo vendor` to the project's
+// Cargo configuration, so that Cargo uses the vendored crates.
+const CARGO_VENDOR: &str = r#"    mkdir -p .cargo
+    cargo vendor --locked --versioned-dirs --quiet vendor > .cargo/vendor.toml
+    cat .cargo/vendor.toml >> .cargo/config.toml
+    rm .cargo/vendor.toml
+    # A crate without dependencies has nothing to vendor.
+    mkdir -p vendor"#;
+
+// Dependencies are not resolved, since hash-checking mode already requires every
+// one of them to be pinned. The package is then installed with
+// `pip install --no-index --find-links vendor --require-hashes -r requirements.txt`.
+//
+// pip runs on the build host, so the platforms of the architecture being built,
+// which replaces __ARCH__, are given for it to pick wheels for instead. Pure-Python
+// wheels match any platform, and a distribution without a matching wheel is
+// downloaded as an sdist, which is built for the right architecture when the
+// package is. The Python version is that of the SDK, which builds the package.
+const PIP_VENDOR: &str = r#"    arch="__ARCH__"
+    python3 -m pip download --disable-pip-version-check --no-input --quiet \
+        --platform "manylinux_2_28_${arch}" --platform "manylinux2014_${arch}" \
+        --require-hashes --no-deps --requirement requirements.txt --dest vendor"#;
+
+// `npm ci` skips the integrity check of packages without an integrity hash, such
+// as those from git repositories, so they are rejected first.
+const NPM_VENDOR: &str = r#"    node -e '
+        const lock = require("./package-lock.json");
+        const unverified = Object.entries(lock.packages || {})
+            .filter(([path, pkg]) => path && !pkg.link && !pkg.inBundle && !pkg.integrity)
+            .map(([path]) => path);
+        if (unverified.length) {
+            console.error("Packages without an integrity hash: " + unverified.join(" "));
+            process.exit(1);
+        }'
+    npm ci --ignore-scripts --no-audit --no-fund --loglevel=error
+    # A project without dependencies has nothing to install.
+    mkdir -p node_modules"#;
+
+impl PackageManager {
+    /// The commands which vendor the dependencies, run in the project directory.
+    fn vendor_commands(&self) -> &'static str {
+        match self {
+            Self::Cargo => CARGO_VENDOR,
+            Self::Pip => PIP_VENDOR,
+            Self::Npm => NPM_VENDOR,
+        }
+    }
+
+    /// The paths written to the output archive, relative to the project directory.
+    fn vendored_paths(&self) -> &'static [&'static str] {
+        match self {
+            Self::Cargo => &["vendor", ".cargo/config.toml"],
+            Self::Pip => &["vendor"],
+            Self::Npm => &["node_modules"],
+        }
+    }
+
+    /// The script which vendors the dependencies of the project at `project_dir` in the archive
+    /// `local_file_name` for `arch`, and writes them to the archive `output`.
+    fn script(
+        &self,
+        arch: SupportedArch,
+        local_file_name: &str,
+        project_dir: &str,
+        output: &str,
+    ) -> String {
+        let paths = self
+            .vendored_paths()
+            .iter()
+            .map(|path| format!("\"${{targetdir}}\"/{path}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        BUNDLE_SCRIPT_TMPL
+            .replace("__VENDOR__", self.vendor_commands())
+            .replace("__ARCH__", &arch.to_string())
+            .replace("__PATHS__", &paths)
+            .replace("__LOCAL_FILE_NAME__", local_file_name)
+            .replace("__PROJECT_DIR__", project_dir)
+            .replace("__OUTPUT__", output)
+    }
+
+    /// The directory where the package manager caches downloads between builds.
+    fn cache_dir(&self, root_dir: &Path, state_dir: &Path) -> PathBuf {
+        match self {
+            Self::Cargo => root_dir.join(".cargo"),
+            Self::Pip => state_dir.join("pip-cache"),
+            Self::Npm => state_dir.join("npm-cache"),
+        }
+    }
+}
+
+impl Bundle {
+    pub(crate) fn vendor(
+        package_manager: PackageManager,
+        root_dir: &Path,
+        state_dir: &Path,
+        package_dir: &Path,
+        external_file: &manifest::ExternalFile,
+        sdk: &str,
+        arch: SupportedArch,
+    ) -> Result<()> {
+        let local_file_name = &external_file
+            .file_name()
+            .context(error::ExternalFileSnafu)?;
+        ensure!(
+            local_file_name.components().count() == 1,
+            error::InputFileSnafu
+        );
+
+        let full_path = package_dir.join(local_file_name);
+        ensure!(
+            full_path.is_file(),
+            error::InputFileBadSnafu { path: full_path }
+        );
+
+        // Without a provided project directory, the first directory found in the
+        // archive is used.
+        let default_empty_path = PathBuf::from("");
+        let project_dir = external_file
+            .bundle_root_path
+            .as_ref()
+            .unwrap_or(&default_empty_path);
+
+        // Use a default "bundle-{name-of-file}" if no output path was provided
+        let default_output_path =
+            PathBuf::from(format!("bundled-{}", local_file_name.to_string_lossy()));
+        let output_path_arg = external_file
+            .bundle_output_path
+            .as_ref()
+            .unwrap_or(&default_output_path);
+        println!(
+            "cargo:rerun-if-changed={}",
+            output_path_arg.to_string_lossy()
+        );
+
+        let cache_dir = package_manager.cache_dir(root_dir, state_dir);
+        let args = DockerBundleArgs {
+            package_path: package_dir,
+            sdk_image: sdk.to_string(),
+            cache_dir: &cache_dir,
+            command: format!("./{}", BUNDLE_DOCKER_SCRIPT_NAME),
+        };
+
+        let script_contents = package_manager.script(
+            arch,
+            &local_file_name.to_string_lossy(),
+            &project_dir.to_string_lossy(),
+            &output_path_arg.to_string_lossy(),
+        );
+        let script_path = package_dir.join(BUNDLE_DOCKER_SCRIPT_NAME);
+
+        // Drop the reference after writing the file to avoid a "text busy" error
+        // when attempting to execute it.
+        {
+            let mut script_file = fs::File::create(&script_path)
+                .context(error::CreateFileSnafu { path: &script_path })?;
+            fs::set_permissions(&script_path, fs::Permissions::from_mode(0o777))
+                .context(error::SetFilePermissionsSnafu { path: &script_path })?;
+            script_file
+                .write_all(script_contents.as_bytes())
+                .context(error::WriteFileSnafu { path: &script_path })?;
+        }
+
+        let res = docker_bundle(&args);
+        fs::remove_file(&script_path).context(error::RemoveFileSnafu { path: &script_path })?;
+        res
+    }
+}
+
+struct DockerBundleArgs<'a> {
+    package_path: &'a Path,
+    sdk_image: String,
+    cache_dir: &'a Path,
+    command: String,
+}
+
+/// Run `docker-bundle` with the specified arguments.
+fn docker_bundle(db_args: &DockerBundleArgs) -> Result<()> {
+    let args = vec![
+        "--package-path",
+        db_args
+            .package_path
+            .to_str()
+            .context(error::InputFileSnafu)?,
+        "--sdk-image",
+        &db_args.sdk_image,
+        "--cache-dir",
+        db_args.cache_dir.to_str().context(error::InputFileSnafu)?,
+        "--command",
+        &db_args.command,
+    ];
+    let arg_string = args.join(" ");
+    let twoliter_tools_dir = env::var("TWOLITER_TOOLS_DIR").context(error::EnvironmentSnafu {
+        var: "TWOLITER_TOOLS_DIR",
+    })?;
+    let program = PathBuf::from(twoliter_tools_dir).join("docker-bundle");
+    println!("program: {}", program.to_string_lossy());
+    let output = cmd(program, args)
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .context(error::CommandStartSnafu)?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("{}", &stdout);
+    ensure!(
+        output.status.success(),
+        error::DockerExecutionSnafu { args: arg_string }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MANAGERS: [PackageManager; 3] = [
+        PackageManager::Cargo,
+        PackageManager::Pip,
+        PackageManager::Npm,
+    ];
+
+    fn script(package_manager: PackageManager, arch: SupportedArch) -> String {
+        package_manager.script(arch, "project-1.0.tar.gz", "", "bundled-project-1.0.tar.gz")
+    }
+
+    #[test]
+    fn fills_in_every_placeholder() {
+        for package_manager in MANAGERS {
+            let script = script(package_manager, SupportedArch::X86_64);
+            for placeholder in [
+                "__VENDOR__",
+                "__ARCH__",
+                "__PATHS__",
+                "__LOCAL_FILE_NAME__",
+                "__PROJECT_DIR__",
+                "__OUTPUT__",
+            ] {
+                assert!(
+                    !script.contains(placeholder),
+                    "{package_manager:?} script contains {placeholder}"
+                );
+            }
+            assert!(script.contains("tar xf project-1.0.tar.gz"));
+            assert!(script.contains("gzip -n > bundled-project-1.0.tar.gz"));
+        }
+    }
+
+    #[test]
+    fn uses_project_dir_when_given() {
+        let script = PackageManager::Cargo.script(
+            SupportedArch::X86_64,
+            "project-1.0.tar.gz",
+            "project-1.0/rust",
+            "bundled-project-1.0.tar.gz",
+        );
+        assert!(script.contains(r#"targetdir="project-1.0/rust""#));
+    }
+
+    #[test]
+    fn archives_vendored_paths() {
+        let cargo = script(PackageManager::Cargo, SupportedArch::X86_64);
+        assert!(cargo.contains("cargo vendor --locked"));
+        assert!(cargo.contains(r#""${targetdir}"/vendor "${targetdir}"/.cargo/config.toml"#));
+
+        let pip = script(PackageManager::Pip, SupportedArch::X86_64);
+        assert!(pip.contains("--require-hashes --no-deps"));
+        assert!(pip.contains(r#"    "${targetdir}"/vendor | gzip"#));
+
+        let npm = script(PackageManager::Npm, SupportedArch::X86_64);
+        assert!(npm.contains("npm ci --ignore-scripts"));
+        assert!(npm.contains(r#""${targetdir}"/node_modules"#));
+    }
+
+    #[test]
+    fn downloads_wheels_for_target_arch() {
+        let script = script(PackageManager::Pip, SupportedArch::Aarch64);
+        assert!(script.contains(r#"arch="aarch64""#));
+        assert!(script.contains(r#"--platform "manylinux2014_${arch}""#));
+        assert!(!script.contains("x86_64"));
+    }
+
+    #[test]
+    fn caches_downloads() {
+        let root = Path::new("/project");
+        let state = Path::new("/project/build/state");
+        assert_eq!(
+            PackageManager::Cargo.cache_dir(root, state),
+            Path::new("/project/.cargo")
+        );
+        assert_eq!(
+            PackageManager::Pip.cache_dir(root, state),
+            Path::new("/project/build/state/pip-cache")
+        );
+        assert_eq!(
+            PackageManager::Npm.cache_dir(root, state),
+            Path::new("/project/build/state/npm-cache")
+        );
+    }
+}
@@ -8,30 +8,78 @@ given the (name, url, hash) data that uniquely identifies each file.
 
 It implements a two-tier approach to retrieval: files are first pulled from the
 "lookaside" cache and only fetched from the upstream site if that access fails.
+An upstream site may list mirrors, which are tried in order after it, and files
+may also be archived from a commit of a git repository; see `git`.
+The cache may be a web server, a local directory or an S3 bucket; see `backend`.
+The latter two can also be populated from upstream, for every external file of
+a project's packages.
+
+Files are fetched by a limited number of threads at once, which share one HTTP
+client so that connections to the same host are reused. Interrupted downloads are
+resumed with HTTP range requests rather than started over.
+
+Packages which share an upstream file, such as a kernel or toolchain tarball, may
+share its download through a store of files named by their hash. A file missing
+from a package directory is hard linked from the store if it's there, and added
+to the store once it's downloaded.
 
 */
+mod backend;
 pub(crate) mod error;
+mod git;
 use error::Result;
 
+use backend::Backend;
 use buildsys::manifest;
-use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use buildsys::proxy::redact_credentials;
+use nix::errno::Errno;
+use reqwest::blocking::{Body, Client, Response};
+use reqwest::header::{
+    HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_RANGE, ETAG, RANGE, USER_AGENT,
+};
+use reqwest::{Method, StatusCode};
 use sha2::{Digest, Sha512};
 use snafu::{ensure, OptionExt, ResultExt};
-use std::fs::{self, File};
-use std::io::{self, BufWriter};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use url::Url;
+use uuid::Uuid;
+
+/// The number of times a download is attempted before giving up on a source.
+const FETCH_ATTEMPTS: usize = 3;
+
+/// Files larger than this are uploaded to S3 in parts of this size.
+const PART_SIZE: u64 = 64 * 1024 * 1024;
 
 pub(crate) struct LookasideCache {
-    /// The version string to include in HTTP headers.
-    version: String,
+    /// The client shared by every download, which keeps connections open between them.
+    client: Client,
+
+    /// The client for upstream URLs and mirrors, which gives up on a connection, or on a response
+    /// which stops sending more of the file, after the upstream timeout, and moves on to the next.
+    /// The timeout of a blocking client applies to each read rather than the whole request, so
+    /// large files can take as long as they need.
+    upstream_client: Client,
+
+    /// The number of files which may be downloaded at once.
+    jobs: NonZeroUsize,
 
-    /// The lookaside cache base URL for source tarballs.
-    lookaside_cache: Url,
+    /// Where the lookaside cache of source tarballs is stored.
+    backend: Backend,
 
     /// Whether we are allowed to pull sources from upstream URLs. When this is false, it can be
     /// overridden by `upstream-fallback` in the manifest.
     upstream_fallback: bool,
+
+    /// A directory of verified files named by their SHA-512 hash, shared by every package, so that
+    /// a file used by several packages is only downloaded once.
+    store: Option<PathBuf>,
 }
 
 impl LookasideCache {
@@ -39,127 +87,601 @@ impl LookasideCache {
         version: impl AsRef<str>,
         lookaside_cache: Url,
         upstream_fallback: bool,
-    ) -> Self {
-        Self {
-            version: version.as_ref().to_string(),
-            lookaside_cache,
+        upstream_timeout: Duration,
+        jobs: NonZeroUsize,
+    ) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_str(&format!(
+                "Bottlerocket buildsys {} (https://github.com/bottlerocket-os/bottlerocket)",
+                version.as_ref()
+            ))
+            .unwrap_or(HeaderValue::from_static(
+                "Bottlerocket buildsys (https://github.com/bottlerocket-os/bottlerocket)",
+            )),
+        );
+        let client = Client::builder()
+            .default_headers(headers.clone())
+            .pool_max_idle_per_host(jobs.get())
+            .build()
+            .context(error::ClientBuildSnafu)?;
+        let upstream_client = Client::builder()
+            .default_headers(headers)
+            .pool_max_idle_per_host(jobs.get())
+            .connect_timeout(upstream_timeout)
+            .timeout(upstream_timeout)
+            .build()
+            .context(error::ClientBuildSnafu)?;
+        log_proxy();
+        Ok(Self {
+            client,
+            upstream_client,
+            jobs,
+            backend: Backend::new(lookaside_cache)?,
             upstream_fallback,
-        }
+            store: None,
+        })
     }
 
-    /// Fetch files stored out-of-tree and ensure they match the stored hash.
+    /// Shares downloaded files between packages through the directory `store`. Files are hard
+    /// linked between it and the package directories where possible, so it should be on the same
+    /// filesystem as the packages.
+    pub(crate) fn with_store(mut self, store: PathBuf) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Fetch files stored out-of-tree and ensure they match the stored hash. Up to `jobs` files
+    /// are downloaded at once, and the first error stops the downloads which haven't started.
     pub(crate) fn fetch(&self, files: &[manifest::ExternalFile]) -> Result<()> {
-        for f in files {
-            let url_file_name = Self::extract_file_name(&f.url)?;
-            let path = &f.path.as_ref().unwrap_or(&url_file_name);
-            ensure!(
-                path.components().count() == 1,
-                error::ExternalFileNameSnafu { path }
-            );
+        let start = Instant::now();
+        let downloaded = self.for_each(files, |f| self.fetch_one(f))?;
+        downloaded.report("Fetched", start);
+        Ok(())
+    }
+
+    /// Uploads each external file to the cache, unless it's already there. The files are
+    /// downloaded from upstream into the directory given with them, where a build would fetch
+    /// them, and verified before they're uploaded. The cache must be a directory or S3 bucket.
+    pub(crate) fn populate(&self, files: &[(&Path, &manifest::ExternalFile)]) -> Result<()> {
+        ensure!(
+            self.backend.is_writable(),
+            error::UnwritableCacheSnafu {
+                url: self.backend.url().as_str()
+            }
+        );
+        let start = Instant::now();
+        let uploaded = self.for_each(files, |(dir, f)| self.populate_one(dir, f))?;
+        uploaded.report("Uploaded", start);
+        println!(
+            "{} of {} external files were already cached",
+            files.len() - uploaded.files,
+            files.len()
+        );
+        Ok(())
+    }
 
-            let hash = &f.sha512;
-            if path.is_file() {
-                match Self::verify_file(path, hash) {
-                    Ok(_) => continue,
-                    Err(e) => {
-                        println!("{}", e);
-                        fs::remove_file(path).context(error::ExternalFileDeleteSnafu { path })?;
+    /// Calls `f` on each item from up to `jobs` threads at once, stopping at the first error.
+    /// Tallies the sizes `f` returns for the items it transferred.
+    fn for_each<T: Sync>(
+        &self,
+        items: &[T],
+        f: impl Fn(&T) -> Result<Option<u64>> + Sync,
+    ) -> Result<Transferred> {
+        let queue = Mutex::new(items.iter().collect::<VecDeque<_>>());
+        let transferred = Mutex::new(Transferred::default());
+        let failure = Mutex::new(None);
+        let workers = self.jobs.get().min(items.len());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    if lock(&failure).is_some() {
+                        break;
+                    }
+                    let Some(item) = lock(&queue).pop_front() else {
+                        break;
+                    };
+                    match f(item) {
+                        Ok(Some(bytes)) => {
+                            let mut transferred = lock(&transferred);
+                            transferred.files += 1;
+                            transferred.bytes += bytes;
+                        }
+                        Ok(None) => (),
+                        Err(e) => {
+                            lock(&failure).get_or_insert(e);
+                        }
                     }
+                });
+            }
+        });
+        if let Some(e) = lock(&failure).take() {
+            return Err(e);
+        }
+        Ok(transferred.into_inner().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Downloads a file from upstream and uploads it to the cache, unless the cache already has
+    /// it, returning the number of bytes uploaded.
+    fn populate_one(&self, dir: &Path, f: &manifest::ExternalFile) -> Result<Option<u64>> {
+        let name = &f.file_name().context(error::ExternalFileManifestSnafu)?;
+        ensure!(
+            name.components().count() == 1,
+            error::ExternalFileNameSnafu { path: name }
+        );
+        let hash = &f.sha512;
+        let cache_url = self.backend.file_url(&name.display().to_string(), hash)?;
+        if self.is_cached(&cache_url)? {
+            return Ok(None);
+        }
+
+        let path = dir.join(name);
+        if Self::verify_file(&path, hash).is_err() {
+            let tmp = dir.join(format!(".{}", name.display()));
+            self.fetch_upstream(f, &tmp)?;
+            fs::rename(&tmp, &path).context(error::ExternalFileRenameSnafu { path: &tmp })?;
+        }
+        println!("Uploading {:?} to {}", name, cache_url);
+        self.upload(&path, &cache_url).map(Some)
+    }
+
+    /// Whether the cache already holds the file at the URL.
+    fn is_cached(&self, url: &Url) -> Result<bool> {
+        if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|_| error::FileUrlSnafu { url: url.clone() }.build())?;
+            return Ok(path.is_file());
+        }
+        let mut request = self.client.head(url.as_str());
+        for (name, value) in self.backend.authorize("HEAD", url, &[])? {
+            request = request.header(name, value);
+        }
+        let resp = request
+            .send()
+            .context(error::ExternalFileRequestSnafu { url: url.as_str() })?;
+        match resp.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            // S3 answers with 403 rather than 404 unless the caller may list the bucket. If the
+            // caller can't write to it either, the upload fails with S3's reason.
+            StatusCode::FORBIDDEN if matches!(self.backend, Backend::S3(_)) => Ok(false),
+            status => error::ExternalFileFetchSnafu {
+                url: url.as_str(),
+                status,
+            }
+            .fail(),
+        }
+    }
+
+    /// Uploads the file at the path to the URL in the cache, returning its size.
+    fn upload(&self, path: &Path, url: &Url) -> Result<u64> {
+        if url.scheme() == "file" {
+            let dest = url
+                .to_file_path()
+                .map_err(|_| error::FileUrlSnafu { url: url.clone() }.build())?;
+            let dir = dest.parent().unwrap_or(Path::new("/"));
+            fs::create_dir_all(dir).context(error::CacheWriteSnafu { path: dir })?;
+            // Copy next to the destination first, so that readers never see part of the file.
+            let tmp = dir.join(format!(
+                ".{}",
+                dest.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            let bytes = fs::copy(path, &tmp).context(error::CacheWriteSnafu { path: &tmp })?;
+            fs::rename(&tmp, &dest).context(error::ExternalFileRenameSnafu { path: &tmp })?;
+            return Ok(bytes);
+        }
+
+        let bytes = fs::metadata(path)
+            .context(error::ExternalFileLoadSnafu { path })?
+            .len();
+        if bytes > PART_SIZE && matches!(self.backend, Backend::S3(_)) {
+            self.upload_parts(path, url, bytes)?;
+        } else {
+            self.send_upload(Method::PUT, url, Some(Self::part_body(path, 0, bytes)?))?;
+        }
+        Ok(bytes)
+    }
+
+    /// Uploads the file at the path to the URL in an S3 bucket in parts, since a single upload is
+    /// limited to 5 GiB. The upload is aborted if any part fails, so that the bucket doesn't keep
+    /// the parts which were uploaded.
+    fn upload_parts(&self, path: &Path, url: &Url, bytes: u64) -> Result<()> {
+        let mut create = url.clone();
+        create.set_query(Some("uploads"));
+        let resp = self.send_upload(Method::POST, &create, None)?;
+        let upload_id = xml_value(&resp.text().unwrap_or_default(), "UploadId")
+            .context(error::CacheUploadResponseSnafu { url: url.as_str() })?;
+        let with_upload_id = |part: Option<u64>| {
+            let mut part_url = url.clone();
+            if let Some(part) = part {
+                part_url
+                    .query_pairs_mut()
+                    .append_pair("partNumber", &part.to_string());
+            }
+            part_url
+                .query_pairs_mut()
+                .append_pair("uploadId", &upload_id);
+            part_url
+        };
+
+        let upload = || -> Result<()> {
+            let mut parts = String::new();
+            for (index, offset) in (0..bytes).step_by(PART_SIZE as usize).enumerate() {
+                let number = index as u64 + 1;
+                let len = PART_SIZE.min(bytes - offset);
+                let resp = self.send_upload(
+                    Method::PUT,
+                    &with_upload_id(Some(number)),
+                    Some(Self::part_body(path, offset, len)?),
+                )?;
+                let etag = resp
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|value| value.to_str().ok())
+                    .context(error::CacheUploadResponseSnafu { url: url.as_str() })?;
+                parts.push_str(&format!(
+                    "<Part><PartNumber>{number}</PartNumber><ETag>{etag}</ETag></Part>"
+                ));
+            }
+            let complete = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>");
+            let len = complete.len() as u64;
+            let resp = self.send_upload(
+                Method::POST,
+                &with_upload_id(None),
+                Some((Body::sized(io::Cursor::new(complete), len), len)),
+            )?;
+            // S3 may report that completing the upload failed after it has answered with 200.
+            let text = resp.text().unwrap_or_default();
+            match xml_value(&text, "Message") {
+                Some(message) if text.contains("<Error>") => error::CacheUploadStatusSnafu {
+                    url: url.as_str(),
+                    status: StatusCode::OK,
+                    message,
                 }
+                .fail(),
+                _ => Ok(()),
             }
+        };
+        let result = upload();
+        if result.is_err() {
+            if let Err(e) = self.send_upload(Method::DELETE, &with_upload_id(None), None) {
+                println!("Failed to abort the upload to {}: {}", url, e);
+            }
+        }
+        result
+    }
 
-            let name = &path.display().to_string();
-            let tmp = PathBuf::from(format!(".{}", name));
+    /// The body of a request which uploads `len` bytes of the file at the path from `offset`.
+    fn part_body(path: &Path, offset: u64, len: u64) -> Result<(Body, u64)> {
+        let mut file = File::open(path).context(error::ExternalFileOpenSnafu { path })?;
+        file.seek(SeekFrom::Start(offset))
+            .context(error::ExternalFileLoadSnafu { path })?;
+        Ok((Body::sized(file.take(len), len), len))
+    }
+
+    /// Sends an authorized request which uploads to the cache, failing with what the cache said
+    /// about it unless it succeeds.
+    fn send_upload(
+        &self,
+        method: Method,
+        url: &Url,
+        body: Option<(Body, u64)>,
+    ) -> Result<Response> {
+        let mut request = self.client.request(method.clone(), url.as_str());
+        for (name, value) in self.backend.authorize(method.as_str(), url, &[])? {
+            request = request.header(name, value);
+        }
+        let (body, len) = body.unwrap_or_else(|| (Body::from(Vec::new()), 0));
+        let resp = request
+            .header(CONTENT_LENGTH, len)
+            .body(body)
+            .send()
+            .context(error::CacheUploadSnafu { url: url.as_str() })?;
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(resp);
+        }
+        // S3 explains why, such as that access was denied, in the body of the response.
+        let text = resp.text().unwrap_or_default();
+        let message = xml_value(&text, "Message").unwrap_or(text);
+        error::CacheUploadStatusSnafu {
+            url: url.as_str(),
+            status,
+            message,
+        }
+        .fail()
+    }
 
-            // first check the lookaside cache
-            let mut url = self.lookaside_cache.clone();
-            url.path_segments_mut()
-                .map_err(|_| {
-                    error::UrlPathSegmentsSnafu {
-                        url: self.lookaside_cache.clone(),
+    /// Fetches a file unless it is already present and verified, returning the number of bytes
+    /// downloaded if it was fetched.
+    fn fetch_one(&self, f: &manifest::ExternalFile) -> Result<Option<u64>> {
+        let path = &f.file_name().context(error::ExternalFileManifestSnafu)?;
+        ensure!(
+            path.components().count() == 1,
+            error::ExternalFileNameSnafu { path }
+        );
+
+        let hash = &f.sha512;
+        if path.is_file() {
+            match Self::verify_file(path, hash) {
+                Ok(_) => return Ok(None),
+                Err(e) => {
+                    println!("{}", e);
+                    fs::remove_file(path).context(error::ExternalFileDeleteSnafu { path })?;
+                }
+            }
+        }
+
+        if self.link_from_store(path, hash)? {
+            return Ok(None);
+        }
+
+        let name = &path.display().to_string();
+        let tmp = PathBuf::from(format!(".{}", name));
+
+        // first check the lookaside cache
+        let url = self.backend.file_url(name, hash)?.to_string();
+        let bytes = match self.fetch_file(&url, &tmp, hash, Source::Cache(&self.backend)) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                // next check with upstream and its mirrors, if permitted
+                if f.force_upstream.unwrap_or(false) || self.upstream_fallback {
+                    println!("Error fetching from lookaside cache: {}", e);
+                    self.fetch_upstream(f, &tmp)?
+                } else {
+                    // we failed to fetch from the lookaside cache, and we cannot fall back to
+                    // upstream sources, so we should not continue, we need to return the error
+                    return Err(e);
+                }
+            }
+        };
+        fs::rename(&tmp, path).context(error::ExternalFileRenameSnafu { path: &tmp })?;
+        self.add_to_store(path, hash)?;
+        Ok(Some(bytes))
+    }
+
+    /// Links or copies the file with the hash from the shared store to the path, returning whether
+    /// the store had it. The file is verified first, since a package may have changed its link.
+    fn link_from_store(&self, path: &Path, hash: &str) -> Result<bool> {
+        let Some(store) = &self.store else {
+            return Ok(false);
+        };
+        let stored = store.join(hash);
+        if !stored.is_file() {
+            return Ok(false);
+        }
+        if let Err(e) = Self::verify_file(&stored, hash) {
+            println!("{}", e);
+            fs::remove_file(&stored).context(error::ExternalFileDeleteSnafu { path: &stored })?;
+            return Ok(false);
+        }
+        println!("Using '{}' from the shared store", path.display());
+        link_or_copy(&stored, path)?;
+        Ok(true)
+    }
+
+    /// Adds the verified file at the path to the shared store, unless it's already there.
+    fn add_to_store(&self, path: &Path, hash: &str) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let stored = store.join(hash);
+        if stored.is_file() {
+            return Ok(());
+        }
+        fs::create_dir_all(store).context(error::StoreWriteSnafu { path: store })?;
+        link_or_copy(path, &stored)
+    }
+
+    /// Fetches a file from its upstream URL, or failing that from each of its mirrors in turn,
+    /// returning the number of bytes downloaded. A partial download left by one is resumed from
+    /// the next. Files from git repositories are archived from the commit instead.
+    fn fetch_upstream(&self, f: &manifest::ExternalFile, path: &Path) -> Result<u64> {
+        if let Some(git) = &f.git {
+            println!(
+                "Archiving '{}' at {} from upstream source",
+                git.url, git.rev
+            );
+            let bytes = git::archive(git, path)?;
+            if let Err(e) = Self::verify_file(path, &f.sha512) {
+                fs::remove_file(path).context(error::ExternalFileDeleteSnafu { path })?;
+                return Err(e);
+            }
+            return Ok(bytes);
+        }
+
+        let mut mirrors = f.mirrors.iter().flatten();
+        let mut url = f.source_url();
+        loop {
+            println!("Fetching '{}' from upstream source", url);
+            match self.fetch_file(url, path, &f.sha512, Source::Upstream) {
+                Ok(bytes) => return Ok(bytes),
+                Err(e) => match mirrors.next() {
+                    Some(mirror) => {
+                        println!("Error fetching from {}: {}", url, e);
+                        url = mirror.as_str();
                     }
-                    .build()
-                })?
-                .extend([name, hash, name]);
-            let url = url.to_string();
-            match self.fetch_file(&url, &tmp, hash) {
-                Ok(_) => {
-                    fs::rename(&tmp, path)
-                        .context(error::ExternalFileRenameSnafu { path: &tmp })?;
+                    None => return Err(e),
+                },
+            }
+        }
+    }
+
+    /// Retrieves a file from the specified URL and write it to the given path,
+    /// then verifies the contents against the SHA-512 hash provided. Returns the
+    /// number of bytes downloaded.
+    ///
+    /// If the path already holds part of the file, from an earlier attempt, only the rest is
+    /// requested. A download which fails partway is resumed a few times before giving up, and a
+    /// resumed file which fails verification is downloaded again from the start.
+    fn fetch_file<P: AsRef<Path>>(
+        &self,
+        url: &str,
+        path: P,
+        hash: &str,
+        source: Source<'_>,
+    ) -> Result<u64> {
+        let path = path.as_ref();
+        let mut bytes = 0;
+        let mut attempt = 1;
+        loop {
+            let resumed = Self::partial_len(path) > 0;
+            match self.download(url, path, source) {
+                Ok(downloaded) => bytes += downloaded,
+                Err(e) if attempt < FETCH_ATTEMPTS && e.is_transient() => {
+                    println!("Error fetching '{}', resuming: {}", url, e);
+                    attempt += 1;
                     continue;
                 }
+                Err(e) => return Err(e),
+            }
+
+            match Self::verify_file(path, hash) {
+                Ok(_) => return Ok(bytes),
                 Err(e) => {
-                    // next check with upstream, if permitted
-                    if f.force_upstream.unwrap_or(false) || self.upstream_fallback {
-                        println!("Error fetching from lookaside cache: {}", e);
-                        println!("Fetching {:?} from upstream source", url_file_name);
-                        self.fetch_file(&f.url, &tmp, hash)?;
-                        fs::rename(&tmp, path)
-                            .context(error::ExternalFileRenameSnafu { path: &tmp })?;
-                    } else {
-                        // we failed to fetch from the lookaside cache, and we cannot fall back to
-                        // upstream sources, so we should not continue, we need to return the error
+                    fs::remove_file(path).context(error::ExternalFileDeleteSnafu { path })?;
+                    if !resumed || attempt >= FETCH_ATTEMPTS {
                         return Err(e);
                     }
+                    println!("Resumed download of '{}' is corrupt, restarting it", url);
+                    attempt += 1;
                 }
             }
         }
-
-        Ok(())
     }
 
-    /// Retrieves a file from the specified URL and write it to the given path,
-    /// then verifies the contents against the SHA-512 hash provided.
-    fn fetch_file<P: AsRef<Path>>(&self, url: &str, path: P, hash: &str) -> Result<()> {
-        let path = path.as_ref();
-
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            USER_AGENT,
-            HeaderValue::from_str(&format!(
-                "Bottlerocket buildsys {} (https://github.com/bottlerocket-os/bottlerocket)",
-                self.version
-            ))
-            .unwrap_or(HeaderValue::from_static(
-                "Bottlerocket buildsys (https://github.com/bottlerocket-os/bottlerocket)",
-            )),
-        );
+    /// Downloads the part of the file at the URL which is missing from the path, returning the
+    /// number of bytes written. Whatever was written is kept if the transfer fails, so that it can
+    /// be resumed.
+    fn download(&self, url: &str, path: &Path, source: Source<'_>) -> Result<u64> {
+        let offset = Self::partial_len(path);
+        let parsed = Url::parse(url).context(error::ExternalFileUrlSnafu { url })?;
+        if parsed.scheme() == "file" {
+            return Self::copy_local(&parsed, path, offset);
+        }
 
-        let client = reqwest::blocking::Client::new();
-        let mut resp = client
-            .get(url)
-            .headers(headers)
+        let range = format!("bytes={}-", offset);
+        let mut headers = Vec::new();
+        if offset > 0 {
+            headers.push((RANGE.as_str(), range.as_str()));
+        }
+        let (mut request, authorization) = match source {
+            Source::Cache(backend) => (
+                self.client.get(url),
+                backend.authorize("GET", &parsed, &headers)?,
+            ),
+            Source::Upstream => (self.upstream_client.get(url), Vec::new()),
+        };
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        for (name, value) in authorization {
+            request = request.header(name, value);
+        }
+        let mut resp = request
             .send()
             .context(error::ExternalFileRequestSnafu { url })?;
         let status = resp.status();
-        ensure!(
-            status.is_success(),
-            error::ExternalFileFetchSnafu { url, status }
-        );
-
-        let f = File::create(path).context(error::ExternalFileOpenSnafu { path })?;
-        let mut f = BufWriter::new(f);
-        resp.copy_to(&mut f)
-            .context(error::ExternalFileSaveSnafu { path })?;
-        drop(f);
+        let content_range = resp
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(ContentRange::parse);
 
-        match Self::verify_file(path, hash) {
-            Ok(_) => Ok(()),
-            Err(e) => {
+        let (file, expected_len) = match status {
+            StatusCode::PARTIAL_CONTENT => match content_range {
+                Some(ContentRange {
+                    start: Some(start),
+                    total,
+                }) if start == offset => {
+                    let file = OpenOptions::new()
+                        .append(true)
+                        .open(path)
+                        .context(error::ExternalFileOpenSnafu { path })?;
+                    (file, total)
+                }
+                _ if offset > 0 => {
+                    // The server sent some other part of the file than the one asked for, so the
+                    // partial file can't be trusted; start over.
+                    fs::remove_file(path).context(error::ExternalFileDeleteSnafu { path })?;
+                    return self.download(url, path, source);
+                }
+                _ => return error::ExternalFileFetchSnafu { url, status }.fail(),
+            },
+            StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+                if matches!(
+                    content_range,
+                    Some(ContentRange { total: Some(total), .. }) if total == offset
+                ) {
+                    // The earlier attempt got the whole file, only missing the rename.
+                    return Ok(0);
+                }
                 fs::remove_file(path).context(error::ExternalFileDeleteSnafu { path })?;
-                Err(e)
+                return self.download(url, path, source);
+            }
+            status if status.is_success() => {
+                // The server ignored the range, if there was one, and sent the whole file.
+                let file = File::create(path).context(error::ExternalFileOpenSnafu { path })?;
+                (file, resp.content_length())
             }
+            status => return error::ExternalFileFetchSnafu { url, status }.fail(),
+        };
+
+        let mut f = BufWriter::new(file);
+        let bytes = resp
+            .copy_to(&mut f)
+            .context(error::ExternalFileSaveSnafu { path })?;
+        f.into_inner()
+            .map_err(|e| e.into_error())
+            .context(error::ExternalFileWriteSnafu { path })?;
+
+        if let Some(expected) = expected_len {
+            let actual = Self::partial_len(path);
+            ensure!(
+                actual == expected,
+                error::ExternalFileSizeSnafu {
+                    path,
+                    expected,
+                    actual
+                }
+            );
         }
+        Ok(bytes)
     }
 
-    fn extract_file_name(url: &str) -> Result<PathBuf> {
-        let parsed = reqwest::Url::parse(url).context(error::ExternalFileUrlSnafu { url })?;
-        let name = parsed
-            .path_segments()
-            .context(error::ExternalFileNameSnafu { path: url })?
-            .last()
-            .context(error::ExternalFileNameSnafu { path: url })?;
-        Ok(name.into())
+    /// Copies the part of a local file which is missing from the path, returning the number of
+    /// bytes written.
+    fn copy_local(url: &Url, path: &Path, offset: u64) -> Result<u64> {
+        let source = url
+            .to_file_path()
+            .map_err(|_| error::FileUrlSnafu { url: url.clone() }.build())?;
+        let mut from =
+            File::open(&source).context(error::ExternalFileOpenSnafu { path: &source })?;
+        let len = from
+            .metadata()
+            .context(error::ExternalFileLoadSnafu { path: &source })?
+            .len();
+        let offset = if offset > len {
+            fs::remove_file(path).context(error::ExternalFileDeleteSnafu { path })?;
+            0
+        } else {
+            offset
+        };
+        from.seek(SeekFrom::Start(offset))
+            .context(error::ExternalFileLoadSnafu { path: &source })?;
+        let mut to = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(error::ExternalFileOpenSnafu { path })?;
+        io::copy(&mut from, &mut to).context(error::ExternalFileWriteSnafu { path })
+    }
+
+    /// The size of what has been downloaded to the path so far.
+    fn partial_len(path: &Path) -> u64 {
+        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
     }
 
     /// Reads a file from disk and compares it to the expected SHA-512 hash.
@@ -178,3 +700,213 @@ impl LookasideCache {
         Ok(())
     }
 }
+
+/// Where a download comes from, which decides how its requests are made.
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    /// The lookaside cache, whose backend authorizes requests
+    Cache(&'a Backend),
+    /// An upstream URL or mirror, whose requests time out when they stall for the upstream timeout
+    Upstream,
+}
+
+/// The files which were transferred rather than found in place, and their total size.
+#[derive(Default)]
+struct Transferred {
+    files: usize,
+    bytes: u64,
+}
+
+impl Transferred {
+    /// Prints the number of files transferred and the throughput, unless there were none.
+    fn report(&self, verb: &str, start: Instant) {
+        if self.files == 0 {
+            return;
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        let mebibytes = self.bytes as f64 / (1024.0 * 1024.0);
+        println!(
+            "{} {} external files ({:.1} MiB) in {:.1}s, at {:.1} MiB/s",
+            verb,
+            self.files,
+            mebibytes,
+            seconds,
+            mebibytes / seconds.max(0.001)
+        );
+    }
+}
+
+/// Prints the proxy settings which the client picks up from the environment, if there are any, so
+/// that connectivity problems can be traced to them.
+fn log_proxy() {
+    let settings = ["HTTP_PROXY", "HTTPS_PROXY", "NO_PROXY"].map(|name| {
+        let value = [name.to_string(), name.to_lowercase()]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()));
+        (name, value)
+    });
+    if settings.iter().all(|(_, value)| value.is_none()) {
+        return;
+    }
+    let settings: Vec<String> = settings
+        .iter()
+        .map(|(name, value)| match value {
+            Some(value) => format!("{}={}", name, redact_credentials(value)),
+            None => format!("{}=none", name),
+        })
+        .collect();
+    println!("Using proxy settings {}", settings.join(" "));
+}
+
+/// Hard links `from` to `to`, or copies it if the filesystem doesn't allow a link between them.
+/// The link or copy is made next to `to` and renamed into place, so that builds running alongside
+/// never see part of the file.
+///
+/// The file is made read-only first, since every package linked to it shares its contents with the
+/// store; a build which wants to change it must replace it instead.
+fn link_or_copy(from: &Path, to: &Path) -> Result<()> {
+    let tmp = to.with_file_name(format!(
+        ".{}.{}",
+        to.file_name().unwrap_or_default().to_string_lossy(),
+        Uuid::new_v4()
+    ));
+    let mut permissions = fs::metadata(from)
+        .context(error::ExternalFileLoadSnafu { path: from })?
+        .permissions();
+    if !permissions.readonly() {
+        permissions.set_readonly(true);
+        fs::set_permissions(from, permissions).context(error::StoreWriteSnafu { path: from })?;
+    }
+    match fs::hard_link(from, &tmp) {
+        Ok(()) => {}
+        // Links can't cross filesystems, and some filesystems don't allow them at all.
+        Err(e)
+            if [Errno::EXDEV, Errno::EPERM]
+                .contains(&Errno::from_raw(e.raw_os_error().unwrap_or(0))) =>
+        {
+            fs::copy(from, &tmp).context(error::StoreWriteSnafu { path: &tmp })?;
+        }
+        Err(source) => return Err(source).context(error::StoreWriteSnafu { path: &tmp }),
+    }
+    fs::rename(&tmp, to).context(error::ExternalFileRenameSnafu { path: &tmp })
+}
+
+/// The text of the first `<name>` element of an S3 XML response. S3's responses are simple enough
+/// that they're searched rather than parsed.
+fn xml_value(xml: &str, name: &str) -> Option<String> {
+    let (_, rest) = xml.split_once(&format!("<{name}>"))?;
+    let (value, _) = rest.split_once(&format!("</{name}>"))?;
+    Some(value.to_string())
+}
+
+/// Locks a mutex, even if a thread panicked while holding it, since the values guarded here are
+/// left consistent.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The parts of a `Content-Range` header: the first byte of the range sent, if any, and the size
+/// of the whole file, if the server knows it.
+#[derive(Debug, PartialEq)]
+struct ContentRange {
+    start: Option<u64>,
+    total: Option<u64>,
+}
+
+impl ContentRange {
+    /// Parses `bytes 100-199/200`, `bytes 100-199/*` or `bytes */200`.
+    fn parse(value: &str) -> Option<Self> {
+        let (range, total) = value.strip_prefix("bytes ")?.trim().split_once('/')?;
+        let total = match total {
+            "*" => None,
+            total => Some(total.parse().ok()?),
+        };
+        let start = match range {
+            "*" => None,
+            range => Some(range.split_once('-')?.0.parse().ok()?),
+        };
+        Some(Self { start, total })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_s3_xml_values() {
+        let created = "<?xml version=\"1.0\"?><InitiateMultipartUploadResult><Bucket>b</Bucket>\
+            <Key>k</Key><UploadId>abc.def</UploadId></InitiateMultipartUploadResult>";
+        assert_eq!(xml_value(created, "UploadId").as_deref(), Some("abc.def"));
+        let denied = "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>";
+        assert_eq!(
+            xml_value(denied, "Message").as_deref(),
+            Some("Access Denied")
+        );
+        assert_eq!(xml_value(denied, "UploadId"), None);
+    }
+
+    #[test]
+    fn parses_content_range() {
+        assert_eq!(
+            ContentRange::parse("bytes 100-199/200"),
+            Some(ContentRange {
+                start: Some(100),
+                total: Some(200)
+            })
+        );
+        assert_eq!(
+            ContentRange::parse("bytes 100-199/*"),
+            Some(ContentRange {
+                start: Some(100),
+                total: None
+            })
+        );
+        assert_eq!(
+            ContentRange::parse("bytes */200"),
+            Some(ContentRange {
+                start: None,
+                total: Some(200)
+            })
+        );
+        assert_eq!(ContentRange::parse("items 0-1/2"), None);
+        assert_eq!(ContentRange::parse("bytes x-1/2"), None);
+    }
+
+    #[test]
+    fn shares_files_through_store() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = LookasideCache::new(
+            "0.0.0",
+            Url::parse("file:///nonexistent").unwrap(),
+            false,
+            Duration::from_secs(1),
+            NonZeroUsize::MIN,
+        )
+        .unwrap()
+        .with_store(dir.path().join("store"));
+        let hash = hex::encode(Sha512::digest(b"contents"));
+        let first = dir.path().join("first.tar");
+        let second = dir.path().join("second.tar");
+        fs::write(&first, "contents").unwrap();
+
+        assert!(!cache.link_from_store(&second, &hash).unwrap());
+        cache.add_to_store(&first, &hash).unwrap();
+        assert!(cache.link_from_store(&second, &hash).unwrap());
+        assert_eq!(fs::read_to_string(&second).unwrap(), "contents");
+        // The links share the stored file, which mustn't be changed through them.
+        assert!(fs::metadata(&second).unwrap().permissions().readonly());
+
+        // A stored file which no longer matches its hash is dropped.
+        let stored = dir.path().join("store").join(&hash);
+        let mut permissions = fs::metadata(&stored).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&stored, permissions).unwrap();
+        fs::write(&stored, "changed").unwrap();
+        assert!(!cache.link_from_store(&second, &hash).unwrap());
+        assert!(!dir.path().join("store").join(&hash).exists());
+    }
+}
@@ -0,0 +1,205 @@
+/*!
+The lookaside cache may be any of a few kinds of storage, chosen by the scheme of its URL:
+
+* `https://` (or `http://`) is fetched with plain, anonymous requests.
+* `file://` is a directory, such as a network share mounted on the build host.
+* `s3://<bucket>/<prefix>` is an S3 bucket. Requests are signed with SigV4 using the credentials,
+  region and endpoint found by the AWS SDK in the environment, so private buckets work too.
+
+Whatever the backend, a file is stored at `<base>/<name>/<sha512>/<name>`.
+
+*/
+use super::error::{self, Result};
+use aws_config::BehaviorVersion;
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_sigv4::http_request::{
+    sign, PayloadChecksumKind, SignableBody, SignableRequest, SigningSettings,
+};
+use aws_sigv4::sign::v4;
+use aws_smithy_runtime_api::client::identity::Identity;
+use snafu::{OptionExt, ResultExt};
+use std::time::SystemTime;
+use tokio::runtime::Runtime;
+use url::Url;
+
+pub(super) enum Backend {
+    /// A web server, or a directory if the URL is a `file://` URL
+    Plain(Url),
+    S3(Box<S3>),
+}
+
+/// An S3 bucket, with what's needed to sign requests for it.
+pub(super) struct S3 {
+    /// The `s3://` URL of the cache
+    url: Url,
+    /// The HTTPS URL of the prefix within the bucket
+    base: Url,
+    region: String,
+    credentials: SharedCredentialsProvider,
+    /// Runs the SDK's credential providers, which are async
+    runtime: Runtime,
+}
+
+impl Backend {
+    pub(super) fn new(url: Url) -> Result<Self> {
+        match url.scheme() {
+            "http" | "https" | "file" => Ok(Self::Plain(url)),
+            "s3" => Ok(Self::S3(Box::new(S3::new(&url)?))),
+            _ => error::UnsupportedSchemeSnafu { url }.fail(),
+        }
+    }
+
+    /// The URL the cache was configured with.
+    pub(super) fn url(&self) -> &Url {
+        match self {
+            Self::Plain(url) => url,
+            Self::S3(s3) => &s3.url,
+        }
+    }
+
+    /// Whether files can be uploaded to the cache.
+    pub(super) fn is_writable(&self) -> bool {
+        match self {
+            Self::Plain(url) => url.scheme() == "file",
+            Self::S3(_) => true,
+        }
+    }
+
+    /// The URL of a file in the cache.
+    pub(super) fn file_url(&self, name: &str, hash: &str) -> Result<Url> {
+        let base = match self {
+            Self::Plain(url) => url,
+            Self::S3(s3) => &s3.base,
+        };
+        let mut url = base.clone();
+        url.path_segments_mut()
+            .map_err(|_| error::UrlPathSegmentsSnafu { url: base.as_str() }.build())?
+            .pop_if_empty()
+            .extend([name, hash, name]);
+        Ok(url)
+    }
+
+    /// Returns the headers which authorize a request to the cache, if it needs any. `headers` are
+    /// the other headers of the request, which are signed along with it.
+    pub(super) fn authorize(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(&str, &str)],
+    ) -> Result<Vec<(String, String)>> {
+        match self {
+            Self::Plain(_) => Ok(Vec::new()),
+            Self::S3(s3) => s3.sign(method, url, headers),
+        }
+    }
+}
+
+impl S3 {
+    fn new(url: &Url) -> Result<Self> {
+        let bucket = url
+            .host_str()
+            .context(error::S3BucketSnafu { url: url.clone() })?;
+        let runtime = Runtime::new().context(error::AsyncRuntimeSnafu)?;
+        let config = runtime.block_on(aws_config::load_defaults(BehaviorVersion::latest()));
+        let region = config
+            .region()
+            .context(error::S3RegionSnafu { url: url.clone() })?
+            .to_string();
+        let credentials = config
+            .credentials_provider()
+            .context(error::S3CredentialsMissingSnafu { url: url.clone() })?;
+
+        // Address the bucket by path on a custom endpoint, since S3-compatible stores rarely
+        // support virtual hosts, and by virtual host on AWS.
+        let endpoint = match config.endpoint_url() {
+            Some(endpoint) => format!("{}/{}", endpoint.trim_end_matches('/'), bucket),
+            None => format!("https://{bucket}.s3.{region}.amazonaws.com"),
+        };
+        let mut base = Url::parse(&endpoint).context(error::S3EndpointSnafu {
+            endpoint: &endpoint,
+        })?;
+        base.path_segments_mut()
+            .map_err(|_| error::UrlPathSegmentsSnafu { url: &endpoint }.build())?
+            .pop_if_empty()
+            .extend(
+                url.path_segments()
+                    .into_iter()
+                    .flatten()
+                    .filter(|s| !s.is_empty()),
+            );
+
+        Ok(Self {
+            url: url.clone(),
+            base,
+            region,
+            credentials,
+            runtime,
+        })
+    }
+
+    fn sign(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(&str, &str)],
+    ) -> Result<Vec<(String, String)>> {
+        let credentials = self
+            .runtime
+            .block_on(self.credentials.provide_credentials())
+            .context(error::S3CredentialsSnafu)?;
+        let identity: Identity = credentials.into();
+
+        let mut settings = SigningSettings::default();
+        settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name("s3")
+            .time(SystemTime::now())
+            .settings(settings)
+            .build()
+            .map_err(signing_error)?
+            .into();
+        let request = SignableRequest::new(
+            method,
+            url.as_str(),
+            headers.iter().copied(),
+            SignableBody::UnsignedPayload,
+        )
+        .map_err(signing_error)?;
+        let (instructions, _) = sign(request, &params).map_err(signing_error)?.into_parts();
+        Ok(instructions
+            .headers()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect())
+    }
+}
+
+fn signing_error(e: impl std::fmt::Display) -> error::Error {
+    error::S3SignSnafu {
+        message: e.to_string(),
+    }
+    .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_url() {
+        for base in ["https://cache.example.com", "https://cache.example.com/"] {
+            let backend = Backend::new(Url::parse(base).unwrap()).unwrap();
+            assert_eq!(
+                backend.file_url("a.tar.gz", "abc").unwrap().as_str(),
+                "https://cache.example.com/a.tar.gz/abc/a.tar.gz"
+            );
+        }
+        let backend = Backend::new(Url::parse("file:///mnt/cache/").unwrap()).unwrap();
+        assert_eq!(
+            backend.file_url("a.tar.gz", "abc").unwrap().as_str(),
+            "file:///mnt/cache/a.tar.gz/abc/a.tar.gz"
+        );
+        assert!(Backend::new(Url::parse("ftp://cache.example.com").unwrap()).is_err());
+    }
+}
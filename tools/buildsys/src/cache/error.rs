@@ -6,6 +6,9 @@ use std::path::PathBuf;
 #[snafu(visibility(pub(super)))]
 #[allow(clippy::enum_variant_names)]
 pub(crate) enum Error {
+    #[snafu(display("{}", source))]
+    ExternalFileManifest { source: buildsys::manifest::Error },
+
     #[snafu(display("Bad file name '{}'", path.display()))]
     ExternalFileName { path: PathBuf },
 
@@ -33,6 +36,21 @@ pub(crate) enum Error {
         source: reqwest::Error,
     },
 
+    #[snafu(display("Failed to write file '{}': {}", path.display(), source))]
+    ExternalFileWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display(
+        "Downloaded file '{}' has {} bytes, but the server reported {}",
+        path.display(),
+        actual,
+        expected
+    ))]
+    ExternalFileSize {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+
     #[snafu(display("Failed to load file '{}': {}", path.display(), source))]
     ExternalFileLoad { path: PathBuf, source: io::Error },
 
@@ -45,8 +63,100 @@ pub(crate) enum Error {
     #[snafu(display("Failed to delete file '{}': {}", path.display(), source))]
     ExternalFileDelete { path: PathBuf, source: io::Error },
 
+    #[snafu(display("Failed to write '{}' to the shared store: {}", path.display(), source))]
+    StoreWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display(
+        "Lookaside cache '{}' can't be written to; use a file:// or s3:// URL",
+        url
+    ))]
+    UnwritableCache { url: String },
+
+    #[snafu(display("Failed to write '{}' to the lookaside cache: {}", path.display(), source))]
+    CacheWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to upload to '{}': {}", url, source))]
+    CacheUpload { url: String, source: reqwest::Error },
+
+    #[snafu(display("Failed to upload to '{}': {} {}", url, status, message))]
+    CacheUploadStatus {
+        url: String,
+        status: reqwest::StatusCode,
+        message: String,
+    },
+
+    #[snafu(display(
+        "Failed to upload to '{}': S3 answered without an upload ID or ETag",
+        url
+    ))]
+    CacheUploadResponse { url: String },
+
+    #[snafu(display("Git source rev '{}' must be the full hash of a commit", rev))]
+    GitRev { rev: String },
+
+    #[snafu(display("Failed to run 'git {}': {}", args, source))]
+    GitCommand { args: String, source: io::Error },
+
+    #[snafu(display("Repository '{}' has no commit '{}'", url, rev))]
+    GitCommit { url: String, rev: String },
+
+    #[snafu(display("Failed to prepare git repository '{}': {}", path.display(), source))]
+    GitRepo { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to create HTTP client: {}", source))]
+    ClientBuild { source: reqwest::Error },
+
     #[snafu(display("Failed to get path segments from URL '{}'", url))]
     UrlPathSegments { url: String },
+
+    #[snafu(display(
+        "Unsupported lookaside cache URL '{}', expected https, file or s3",
+        url
+    ))]
+    UnsupportedScheme { url: url::Url },
+
+    #[snafu(display("Bad file URL '{}'", url))]
+    FileUrl { url: url::Url },
+
+    #[snafu(display("Failed to create async runtime: {}", source))]
+    AsyncRuntime { source: io::Error },
+
+    #[snafu(display("No bucket in S3 URL '{}'", url))]
+    S3Bucket { url: url::Url },
+
+    #[snafu(display("No AWS region is configured for '{}'", url))]
+    S3Region { url: url::Url },
+
+    #[snafu(display("No AWS credentials are configured for '{}'", url))]
+    S3CredentialsMissing { url: url::Url },
+
+    #[snafu(display("Failed to load AWS credentials: {}", source))]
+    S3Credentials {
+        source: aws_credential_types::provider::error::CredentialsError,
+    },
+
+    #[snafu(display("Bad S3 endpoint '{}': {}", endpoint, source))]
+    S3Endpoint {
+        endpoint: String,
+        source: url::ParseError,
+    },
+
+    #[snafu(display("Failed to sign S3 request: {}", message))]
+    S3Sign { message: String },
+}
+
+impl Error {
+    /// Whether the error may have been caused by a network blip, so that the download is worth
+    /// resuming.
+    pub(super) fn is_transient(&self) -> bool {
+        match self {
+            Error::ExternalFileRequest { .. }
+            | Error::ExternalFileSave { .. }
+            | Error::ExternalFileSize { .. } => true,
+            Error::ExternalFileFetch { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
 }
 
 pub(super) type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,114 @@
+/*!
+External files may be archived from a commit of a git repository, rather than downloaded. The
+commit is fetched into a scratch repository next to the file, and written out with `git archive`,
+which gives every entry the commit's timestamp and orders entries as the commit's tree does. The
+tarball is left uncompressed, so that its hash doesn't depend on the compressor's version.
+
+*/
+use super::error::{self, Result};
+use buildsys::manifest::GitSource;
+use duct::cmd;
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::Path;
+
+/// Attributes which would make the archive depend on more than the commit, or leave files out.
+const ARCHIVE_ATTRIBUTES: &str = "* -export-subst -export-ignore\n";
+
+/// Writes a tarball of the commit to `path`, returning its size. The tarball's entries are in a
+/// directory named like the file, without its `.tar` suffix.
+pub(super) fn archive(git: &GitSource, path: &Path) -> Result<u64> {
+    ensure!(
+        matches!(git.rev.len(), 40 | 64) && git.rev.chars().all(|c| c.is_ascii_hexdigit()),
+        error::GitRevSnafu { rev: &git.rev }
+    );
+    let file_name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let prefix = file_name.trim_start_matches('.');
+    let prefix = prefix.strip_suffix(".tar").unwrap_or(prefix);
+    let repo = path.with_file_name(format!("{}.git", file_name));
+    if repo.exists() {
+        fs::remove_dir_all(&repo).context(error::GitRepoSnafu { path: &repo })?;
+    }
+
+    let result = fetch_and_archive(git, &repo, prefix, path);
+    fs::remove_dir_all(&repo).context(error::GitRepoSnafu { path: &repo })?;
+    result?;
+    Ok(fs::metadata(path)
+        .context(error::ExternalFileLoadSnafu { path })?
+        .len())
+}
+
+fn fetch_and_archive(git: &GitSource, repo: &Path, prefix: &str, path: &Path) -> Result<()> {
+    let repo_arg = repo.display().to_string();
+    run_git(&["init", "--quiet", "--bare", &repo_arg])?;
+    let info = repo.join("info");
+    fs::create_dir_all(&info).context(error::GitRepoSnafu { path: &info })?;
+    fs::write(info.join("attributes"), ARCHIVE_ATTRIBUTES)
+        .context(error::GitRepoSnafu { path: &info })?;
+
+    // Most hosts allow fetching a commit by its hash, which saves fetching the history. Otherwise
+    // fetch every branch and tag, and hope the commit is reachable from one of them.
+    let git_dir = format!("--git-dir={}", repo_arg);
+    let shallow = cmd!(
+        "git",
+        &git_dir,
+        "fetch",
+        "--quiet",
+        "--depth=1",
+        &git.url,
+        &git.rev
+    )
+    .stderr_null()
+    .run();
+    if shallow.is_err() {
+        run_git(&[
+            &git_dir,
+            "fetch",
+            "--quiet",
+            "--tags",
+            &git.url,
+            "+refs/heads/*:refs/heads/*",
+        ])?;
+    }
+
+    let commit = format!("{}^{{commit}}", git.rev);
+    let resolved = cmd("git", [&git_dir, "rev-parse", "--verify", &commit])
+        .stderr_null()
+        .read()
+        .context(error::GitCommandSnafu {
+            args: format!("rev-parse {}", commit),
+        })?;
+    ensure!(
+        resolved.trim().eq_ignore_ascii_case(&git.rev),
+        error::GitCommitSnafu {
+            url: &git.url,
+            rev: &git.rev,
+        }
+    );
+
+    let output = path.display().to_string();
+    run_git(&[
+        &git_dir,
+        "-c",
+        "tar.umask=0022",
+        "archive",
+        "--format=tar",
+        &format!("--prefix={}/", prefix),
+        &format!("--output={}", output),
+        &git.rev,
+    ])
+}
+
+fn run_git(args: &[&str]) -> Result<()> {
+    cmd("git", args)
+        .stdout_null()
+        .run()
+        .context(error::GitCommandSnafu {
+            args: args.join(" "),
+        })?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+/*!
+The `SHA256SUMS` manifest which lists the artifacts of a variant build, in the format of
+`sha256sum`. Twoliter writes and verifies the manifest, and pubsys uploads the artifacts it lists.
+
+*/
+use snafu::{ensure, OptionExt, Snafu};
+use std::fmt::Write;
+use std::path::{Component, PathBuf};
+
+/// The name of the manifest, which sits in the directory of the artifacts it lists.
+pub const CHECKSUMS_FILE: &str = "SHA256SUMS";
+
+/// The name of the signature of the manifest, written next to it when the build signs it.
+pub const SIGNATURE_FILE: &str = "SHA256SUMS.sig";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid line in {CHECKSUMS_FILE}: '{line}'"))]
+    InvalidLine { line: String },
+
+    #[snafu(display("Invalid checksum in {CHECKSUMS_FILE}: '{line}'"))]
+    InvalidChecksum { line: String },
+
+    #[snafu(display("{CHECKSUMS_FILE} lists a path outside its directory: '{line}'"))]
+    PathOutsideDirectory { line: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The manifest listing `entries` of checksum and path.
+pub fn render(entries: &[(String, PathBuf)]) -> String {
+    entries
+        .iter()
+        .fold(String::new(), |mut manifest, (sha256, path)| {
+            let _ = writeln!(manifest, "{sha256}  {}", path.display());
+            manifest
+        })
+}
+
+/// Parses a manifest into its entries of lowercase checksum and path, rejecting paths which leave
+/// its directory.
+pub fn parse(manifest: &str) -> Result<Vec<(String, PathBuf)>> {
+    manifest
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (sha256, path) = line.split_once("  ").context(InvalidLineSnafu { line })?;
+            ensure!(
+                sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()),
+                InvalidChecksumSnafu { line }
+            );
+            let path = PathBuf::from(path);
+            ensure!(
+                path.is_relative() && path.components().all(|c| matches!(c, Component::Normal(_))),
+                PathOutsideDirectorySnafu { line }
+            );
+            Ok((sha256.to_ascii_lowercase(), path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_and_parses_manifest() {
+        let entries = vec![
+            ("a".repeat(64), PathBuf::from("image.img.lz4")),
+            ("b".repeat(64), PathBuf::from("extra/inventory.json")),
+        ];
+        let manifest = render(&entries);
+        assert_eq!(
+            manifest,
+            format!(
+                "{}  image.img.lz4\n{}  extra/inventory.json\n",
+                "a".repeat(64),
+                "b".repeat(64)
+            )
+        );
+        assert_eq!(parse(&manifest).unwrap(), entries);
+        assert_eq!(
+            parse(&format!("{}  image.img\n\n", "A".repeat(64))).unwrap(),
+            vec![("a".repeat(64), PathBuf::from("image.img"))]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(parse(&format!("{}  ../secret", "a".repeat(64))).is_err());
+        assert!(parse(&format!("{}  extra/../../secret", "a".repeat(64))).is_err());
+        assert!(parse(&format!("{}  /etc/passwd", "a".repeat(64))).is_err());
+        assert!(parse("not-a-checksum  image.img").is_err());
+        assert!(parse(&"a".repeat(64)).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+/*!
+Buildsys reports errors and warnings as text by default: errors on stderr, and warnings as Cargo
+`warning` directives. With `BUILDSYS_OUTPUT_FORMAT=json`, each is instead written as a line of JSON
+with a stable `code`, the `message`, and the `file` and `hint` when there are any, so that Twoliter
+and CI systems can gather the problems of many builds without scraping Cargo's output.
+
+The records are appended to the file named by `BUILDSYS_DIAGNOSTICS_PATH`, or written to stderr
+without one. Each record is written at once, so that builds running in parallel can share the file.
+Twoliter reads the file back with [`Diagnostic::load`] once a build has finished.
+
+*/
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The environment variable which selects the output format, `text` or `json`.
+pub const OUTPUT_FORMAT_VAR: &str = "BUILDSYS_OUTPUT_FORMAT";
+
+/// The environment variable which names the file JSON records are appended to.
+pub const DIAGNOSTICS_PATH_VAR: &str = "BUILDSYS_DIAGNOSTICS_PATH";
+
+/// How buildsys reports diagnostics, as text for people or as JSON records for tools.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MessageFormat {
+    Text,
+    Json,
+}
+
+impl MessageFormat {
+    /// The format selected by `BUILDSYS_OUTPUT_FORMAT`, which is text unless it says `json`.
+    pub fn from_env() -> Self {
+        match env::var(OUTPUT_FORMAT_VAR) {
+            Ok(format) if format.eq_ignore_ascii_case("json") => Self::Json,
+            _ => Self::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+/// An error or warning, as reported to whatever is running buildsys.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Diagnostic {
+    pub level: Level,
+    /// A stable, kebab-case name for the kind of problem
+    pub code: String,
+    pub message: String,
+    /// The file the problem is in, if it's in one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// What may fix the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Level::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Level::Warning, code, message)
+    }
+
+    fn new(level: Level, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            level,
+            code: code.to_string(),
+            message: message.into(),
+            file: None,
+            hint: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Reports the diagnostic in the format selected by the environment.
+    pub fn emit(&self) {
+        match MessageFormat::from_env() {
+            MessageFormat::Text => match self.level {
+                Level::Error => eprintln!("{}", self.message),
+                Level::Warning => println!("cargo::warning={}", self.message),
+            },
+            MessageFormat::Json => self.write_json(),
+        }
+    }
+
+    /// Appends the diagnostic as a line of JSON to the file named by `BUILDSYS_DIAGNOSTICS_PATH`,
+    /// falling back to stderr if there isn't one or it can't be written to.
+    fn write_json(&self) {
+        let Ok(mut line) = serde_json::to_string(self) else {
+            eprintln!("{}", self.message);
+            return;
+        };
+        line.push('\n');
+        let written = env::var_os(DIAGNOSTICS_PATH_VAR)
+            .and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok())
+            .is_some_and(|mut file| file.write_all(line.as_bytes()).is_ok());
+        if !written {
+            eprint!("{line}");
+        }
+    }
+
+    /// Reads the diagnostics appended to `path`, skipping lines which aren't records, such as a
+    /// line cut short by a build that was killed. A missing file has no diagnostics.
+    pub fn load(path: &Path) -> io::Result<Vec<Self>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serializes_records() {
+        let diagnostic = Diagnostic::warning("unused-patch", "patch 'a.patch' is never applied")
+            .with_file("pkg/pkg.spec");
+        assert_eq!(
+            serde_json::to_string(&diagnostic).unwrap(),
+            r#"{"level":"warning","code":"unused-patch","message":"patch 'a.patch' is never applied","file":"pkg/pkg.spec"}"#
+        );
+        let diagnostic = Diagnostic::error("missing-env", "Missing BUILDSYS_ARCH")
+            .with_hint("Run the build through Twoliter");
+        assert_eq!(
+            serde_json::to_string(&diagnostic).unwrap(),
+            r#"{"level":"error","code":"missing-env","message":"Missing BUILDSYS_ARCH","hint":"Run the build through Twoliter"}"#
+        );
+    }
+
+    #[test]
+    fn appends_and_loads_records() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("diagnostics.jsonl");
+        assert!(Diagnostic::load(&path).unwrap().is_empty());
+
+        env::set_var(DIAGNOSTICS_PATH_VAR, &path);
+        let warning = Diagnostic::warning("unused-patch", "patch 'a.patch' is never applied");
+        let error = Diagnostic::error("missing-env", "Missing BUILDSYS_ARCH").with_hint("hint");
+        warning.write_json();
+        error.write_json();
+        env::remove_var(DIAGNOSTICS_PATH_VAR);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"level\":")
+            .unwrap();
+
+        assert_eq!(Diagnostic::load(&path).unwrap(), vec![warning, error]);
+    }
+}
@@ -0,0 +1,76 @@
+/*!
+The events buildsys reports while it builds a package, kit or variant. Each event is appended as a
+line of JSON to the file named by `BUILDSYS_EVENTS_PATH`, which Twoliter follows to log the builds
+as they start, are skipped, warn or finish.
+
+*/
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// An event of one build, as a line of the events file.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildEvent {
+    pub name: String,
+    pub kind: String,
+    pub arch: String,
+    /// When the event happened, in milliseconds since the Unix epoch
+    pub time_ms: u128,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Something that happened during a build.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    StageStarted,
+    /// The build was skipped because its inputs were unchanged since it last succeeded.
+    CacheHit,
+    Warning {
+        message: String,
+    },
+    /// Progress worth showing while the build runs, such as why it is being rebuilt.
+    Message {
+        message: String,
+    },
+    ArtifactProduced {
+        path: PathBuf,
+    },
+    StageFinished {
+        succeeded: bool,
+    },
+    /// An event added to a later buildsys than the one reading it.
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_events() {
+        let event = BuildEvent {
+            name: "kernel".to_string(),
+            kind: "package".to_string(),
+            arch: "x86_64".to_string(),
+            time_ms: 1_700_000_000_000,
+            event: Event::Warning {
+                message: "retrying".to_string(),
+            },
+        };
+        let line = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            line,
+            r#"{"name":"kernel","kind":"package","arch":"x86_64","time-ms":1700000000000,"event":"warning","message":"retrying"}"#
+        );
+        assert_eq!(serde_json::from_str::<BuildEvent>(&line).unwrap(), event);
+
+        let unknown: BuildEvent = serde_json::from_str(
+            r#"{"name":"kernel","kind":"package","arch":"x86_64","time-ms":5,"event":"something-new"}"#,
+        )
+        .unwrap();
+        assert_eq!(unknown.event, Event::Unknown);
+    }
+}
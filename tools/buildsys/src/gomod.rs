@@ -13,6 +13,12 @@ standard environment variables employed by the Go tool: `GOPROXY`, `GOSUMDB`, an
 `GOPRIVATE`. These variables are automatically retrieved from the host environment
 when the docker-go script is invoked.
 
+Vendoring takes minutes for large projects, so the vendor directory is cached in the
+Go module cache, keyed by the SDK's Go version, `go.mod`, `go.sum` and the contents of any
+directories which `replace` directives point modules at. A new upstream
+archive whose dependencies are unchanged reuses it, unless its packages import one
+that isn't vendored, which `go list -mod=vendor` finds before anything is built.
+
  */
 
 pub(crate) mod error;
@@ -36,8 +42,9 @@ const GO_MOD_DOCKER_SCRIPT_NAME: &str = "docker-go-script.sh";
 // This script inspects the top level directory found in the package upstream
 // archive and uses that as the default Go module path if no explicit module
 // path was provided. It will then untar the archive, vendor the Go
-// dependencies, create a new archive using the {module-path}/vendor directory
-// and name it the output path provided. If no output path was given, it
+// dependencies (or reuse those cached for the same go.mod and go.sum), create a
+// new archive using the {module-path}/vendor directory and name it the output
+// path provided. If no output path was given, it
 // defaults to "bundled-{package-file-name}". Finally, it cleans up by removing
 // the untar'd source code. The upstream archive remains intact and both tar
 // files can then be used during packaging.
@@ -62,7 +69,28 @@ fi
 tar xf __LOCAL_FILE_NAME__
 
 pushd "${targetdir}"
-    go list -mod=readonly ./... >/dev/null && go mod vendor
+    cache_dir="${GOPATH}/vendor-cache"
+    # Modules replaced by local directories are vendored from those directories, so their
+    # contents are part of the key as well as the versions go.mod and go.sum pin.
+    replaced=$(awk '/=>/ { sub(/.*=>[ \t]*/, ""); split($0, target, /[ \t]+/);
+        if (target[1] ~ /^(\.|\/)/) print target[1] }' go.mod)
+    cache_key=$( { go env GOVERSION; cat go.mod; cat go.sum 2>/dev/null || true;
+        for dir in ${replaced}; do
+            echo "${dir}"
+            find "${dir}" -type f ! -path '*/vendor/*' -print0 | sort -z | xargs -0r sha256sum
+        done; } | sha256sum | cut -d ' ' -f 1 )
+    cached="${cache_dir}/${cache_key}.tar.gz"
+    rm -rf vendor
+    if [ -f "${cached}" ] && tar xzf "${cached}" && go list -mod=vendor ./... >/dev/null 2>&1 ; then
+        echo "Using the vendor directory cached for go.sum"
+    else
+        rm -rf vendor
+        go list -mod=readonly ./... >/dev/null && go mod vendor
+        mkdir -p "${cache_dir}"
+        tmp=$(mktemp "${cache_dir}/.XXXXXX")
+        tar czf "${tmp}" vendor
+        mv "${tmp}" "${cached}"
+    fi
 popd
 
 tar czf __OUTPUT__ "${targetdir}"/vendor
@@ -77,8 +105,9 @@ impl GoMod {
         external_file: &manifest::ExternalFile,
         sdk: &str,
     ) -> Result<()> {
-        let url_file_name = extract_file_name(&external_file.url)?;
-        let local_file_name = &external_file.path.as_ref().unwrap_or(&url_file_name);
+        let local_file_name = &external_file
+            .file_name()
+            .context(error::ExternalFileSnafu)?;
         ensure!(
             local_file_name.components().count() == 1,
             error::InputFileSnafu
@@ -148,16 +177,6 @@ impl GoMod {
     }
 }
 
-fn extract_file_name(url: &str) -> Result<PathBuf> {
-    let parsed = reqwest::Url::parse(url).context(error::InputUrlSnafu { url })?;
-    let name = parsed
-        .path_segments()
-        .context(error::InputFileBadSnafu { path: url })?
-        .last()
-        .context(error::InputFileBadSnafu { path: url })?;
-    Ok(name.into())
-}
-
 struct DockerGoArgs<'a> {
     module_path: &'a Path,
     sdk_image: String,
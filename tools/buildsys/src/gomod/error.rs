@@ -11,18 +11,15 @@ pub(crate) enum Error {
     #[snafu(display("Failed to execute docker-go script. 'args: {}'", args))]
     DockerExecution { args: String },
 
+    #[snafu(display("{}", source))]
+    ExternalFile { source: buildsys::manifest::Error },
+
     #[snafu(display("Input url is required"))]
     InputFile,
 
     #[snafu(display("Input file {} must be a file", path.display()))]
     InputFileBad { path: PathBuf },
 
-    #[snafu(display("Bad file url '{}': {}", url, source))]
-    InputUrl {
-        url: String,
-        source: url::ParseError,
-    },
-
     #[snafu(display("Missing environment variable '{}'", var))]
     Environment {
         var: String,
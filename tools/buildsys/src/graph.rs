@@ -0,0 +1,322 @@
+/*!
+This module builds the graph of dependencies between a project's packages, so that maintainers can
+see how far a change to one package reaches.
+
+A package depends on another when its `Cargo.toml` lists the other's crate, or when its spec file
+requires something the other's spec file provides, either by the name of one of its packages or
+with a `Provides` tag. Build dependencies come from `[build-dependencies]` and `BuildRequires`,
+and runtime dependencies from `[dependencies]` and `Requires`. Each dependency records which of
+the two declared it, since they're meant to agree: only those in `Cargo.toml` order the builds.
+
+*/
+mod error;
+
+use crate::manifest::ManifestInfo;
+use crate::spec::SpecInfo;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+#[derive(Debug, Snafu)]
+pub struct Error(error::Error);
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyKind {
+    /// Needed to build the package
+    BuildRequires,
+    /// Needed to run the package
+    Requires,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeclaredIn {
+    Manifest,
+    Spec,
+}
+
+/// A package's dependency on another package.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Dependency {
+    pub from: String,
+    pub to: String,
+    pub kind: DependencyKind,
+    pub declared_in: BTreeSet<DeclaredIn>,
+}
+
+/// The packages of a project and the dependencies between them, sorted by name.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct PackageGraph {
+    pub packages: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+}
+
+/// What a package's manifest and spec file declare.
+struct PackageDeclarations {
+    name: String,
+    crate_name: String,
+    /// The crates of the `[build-dependencies]` and `[dependencies]` tables.
+    manifest: Vec<(DependencyKind, String)>,
+    spec: Option<SpecInfo>,
+}
+
+impl PackageGraph {
+    /// Reads the package manifests, along with the spec file beside each. Manifests of anything
+    /// other than packages, such as kits or the workspace, are skipped.
+    pub fn new<P: AsRef<Path>>(manifests: &[P]) -> Result<Self> {
+        let mut declarations = Vec::new();
+        for manifest in manifests {
+            if let Some(package) = read_package(manifest.as_ref())? {
+                declarations.push(package);
+            }
+        }
+
+        // Packages are named by their crates in manifests, and by what they provide in specs.
+        let crates: HashMap<&str, &str> = declarations
+            .iter()
+            .map(|package| (package.crate_name.as_str(), package.name.as_str()))
+            .collect();
+        let mut providers: HashMap<&str, &str> = HashMap::new();
+        for package in &declarations {
+            for built in package.spec.iter().flat_map(|spec| &spec.packages) {
+                providers.insert(&built.name, &package.name);
+                for provided in built.provides.iter().filter_map(|p| capability(p)) {
+                    providers.insert(provided, &package.name);
+                }
+            }
+        }
+
+        let mut edges: BTreeMap<(String, String, DependencyKind), BTreeSet<DeclaredIn>> =
+            BTreeMap::new();
+        for package in &declarations {
+            let from = package.name.as_str();
+            let mut add = |to: &str, kind, declared_in| {
+                if to != from {
+                    edges
+                        .entry((from.to_string(), to.to_string(), kind))
+                        .or_default()
+                        .insert(declared_in);
+                }
+            };
+            for (kind, crate_name) in &package.manifest {
+                if let Some(to) = crates.get(crate_name.as_str()) {
+                    add(to, *kind, DeclaredIn::Manifest);
+                }
+            }
+            let Some(spec) = &package.spec else {
+                continue;
+            };
+            let requires = spec
+                .build_requires
+                .iter()
+                .map(|r| (DependencyKind::BuildRequires, r))
+                .chain(spec.packages.iter().flat_map(|built| {
+                    built.requires.iter().map(|r| (DependencyKind::Requires, r))
+                }));
+            for (kind, required) in requires {
+                if let Some(to) = capability(required).and_then(|c| providers.get(c)) {
+                    add(to, kind, DeclaredIn::Spec);
+                }
+            }
+        }
+
+        let mut packages: Vec<String> = declarations.into_iter().map(|p| p.name).collect();
+        packages.sort();
+        let dependencies = edges
+            .into_iter()
+            .map(|((from, to, kind), declared_in)| Dependency {
+                from,
+                to,
+                kind,
+                declared_in,
+            })
+            .collect();
+        Ok(Self {
+            packages,
+            dependencies,
+        })
+    }
+
+    /// Renders the graph in Graphviz's DOT language, with an edge from each package to those it
+    /// depends on. Runtime dependencies are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph packages {\n");
+        for package in &self.packages {
+            dot.push_str(&format!("  {};\n", quote(package)));
+        }
+        for dependency in &self.dependencies {
+            let mut attributes = Vec::new();
+            if dependency.kind == DependencyKind::Requires {
+                attributes.push("style=dashed".to_string());
+            }
+            // Dependencies missing from either the manifest or the spec file are called out.
+            if let [declared_in] = Vec::from_iter(&dependency.declared_in).as_slice() {
+                let only = match declared_in {
+                    DeclaredIn::Manifest => "manifest only",
+                    DeclaredIn::Spec => "spec only",
+                };
+                attributes.push(format!("label={}", quote(only)));
+            }
+            let attributes = if attributes.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", attributes.join(", "))
+            };
+            dot.push_str(&format!(
+                "  {} -> {}{};\n",
+                quote(&dependency.from),
+                quote(&dependency.to),
+                attributes
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the graph as JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self).context(error::SerializeSnafu)?)
+    }
+}
+
+/// Reads the declarations of the package whose manifest is at `path`, if it is one.
+fn read_package(path: &Path) -> Result<Option<PackageDeclarations>> {
+    let contents = fs::read_to_string(path).context(error::ManifestReadSnafu { path })?;
+    let manifest: Value = toml::from_str(&contents)
+        .map_err(Box::new)
+        .context(error::ManifestParseSnafu { path })?;
+    let is_package = manifest
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("build-package"))
+        .is_some();
+    if !is_package {
+        return Ok(None);
+    }
+
+    let info = ManifestInfo::new(path).context(error::ManifestInfoSnafu)?;
+    let name = info.package_name().to_string();
+    let tables = [
+        ("build-dependencies", DependencyKind::BuildRequires),
+        ("dependencies", DependencyKind::Requires),
+    ];
+    let crates = tables
+        .into_iter()
+        .flat_map(|(table, kind)| {
+            manifest
+                .get(table)
+                .and_then(Value::as_table)
+                .into_iter()
+                .flat_map(|deps| deps.keys())
+                .map(move |crate_name| (kind, crate_name.clone()))
+        })
+        .collect();
+
+    let spec_path = path.with_file_name(format!("{name}.spec"));
+    let spec = if spec_path.is_file() {
+        Some(SpecInfo::new(&spec_path).context(error::SpecSnafu)?)
+    } else {
+        None
+    };
+    Ok(Some(PackageDeclarations {
+        crate_name: info.manifest_name().to_string(),
+        name,
+        manifest: crates,
+        spec,
+    }))
+}
+
+/// The name of what a dependency such as `glibc >= 2.38` requires or provides. File and rich
+/// dependencies aren't provided by a package's name, so they're left out.
+fn capability(dependency: &str) -> Option<&str> {
+    dependency
+        .split_whitespace()
+        .next()
+        .filter(|name| !name.starts_with('/') && !name.starts_with('('))
+}
+
+fn quote(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write_package(dir: &Path, name: &str, manifest: &str, spec: &str) -> PathBuf {
+        let package_dir = dir.join(name);
+        fs::create_dir_all(&package_dir).unwrap();
+        let manifest_path = package_dir.join("Cargo.toml");
+        fs::write(
+            &manifest_path,
+            format!(
+                "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n\n\
+                [package.metadata.build-package]\n\n{manifest}"
+            ),
+        )
+        .unwrap();
+        fs::write(package_dir.join(format!("{name}.spec")), spec).unwrap();
+        manifest_path
+    }
+
+    #[test]
+    fn graphs_packages() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let manifests = [
+            write_package(
+                dir,
+                "libfoo",
+                "",
+                "Name: %{_cross_os}libfoo\n\n%package devel\nProvides: foo-headers\n",
+            ),
+            write_package(
+                dir,
+                "app",
+                "[build-dependencies]\nlibfoo = { path = \"../libfoo\" }\n",
+                "Name: %{_cross_os}app\nBuildRequires: foo-headers >= 1\n\
+                Requires: %{_cross_os}libfoo\nRequires: /bin/sh\n",
+            ),
+        ];
+        let kit = dir.join("kit/Cargo.toml");
+        fs::create_dir_all(kit.parent().unwrap()).unwrap();
+        fs::write(
+            &kit,
+            "[package]\nname = \"kit\"\nversion = \"0.1.0\"\n\n[package.metadata.build-kit]\n",
+        )
+        .unwrap();
+
+        let graph = PackageGraph::new(&[&manifests[0], &manifests[1], &kit]).unwrap();
+        assert_eq!(graph.packages, ["app", "libfoo"]);
+        assert_eq!(
+            graph.dependencies,
+            [
+                Dependency {
+                    from: "app".to_string(),
+                    to: "libfoo".to_string(),
+                    kind: DependencyKind::BuildRequires,
+                    declared_in: BTreeSet::from([DeclaredIn::Manifest, DeclaredIn::Spec]),
+                },
+                Dependency {
+                    from: "app".to_string(),
+                    to: "libfoo".to_string(),
+                    kind: DependencyKind::Requires,
+                    declared_in: BTreeSet::from([DeclaredIn::Spec]),
+                },
+            ]
+        );
+        assert_eq!(
+            graph.to_dot(),
+            "digraph packages {\n  \"app\";\n  \"libfoo\";\n  \"app\" -> \"libfoo\";\n  \
+            \"app\" -> \"libfoo\" [style=dashed, label=\"spec only\"];\n}\n"
+        );
+        assert!(graph.to_json().unwrap().contains("\"declared-in\": ["));
+    }
+}
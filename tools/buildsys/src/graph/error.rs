@@ -0,0 +1,27 @@
+use snafu::Snafu;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(super)))]
+pub(super) enum Error {
+    #[snafu(display("Failed to read manifest file '{}': {}", path.display(), source))]
+    ManifestRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse manifest file '{}': {}", path.display(), source))]
+    ManifestParse {
+        path: PathBuf,
+        source: Box<toml::de::Error>,
+    },
+
+    #[snafu(display("{}", source))]
+    ManifestInfo { source: crate::manifest::Error },
+
+    #[snafu(display("{}", source))]
+    Spec { source: crate::spec::Error },
+
+    #[snafu(display("Failed to serialize package graph: {}", source))]
+    Serialize { source: serde_json::Error },
+}
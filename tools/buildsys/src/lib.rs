@@ -1,4 +1,13 @@
+pub mod checksums;
+pub mod diagnostics;
+pub mod events;
+pub mod graph;
 pub mod manifest;
+pub mod project;
+pub mod proxy;
+pub mod runtime;
+pub mod spec;
+pub mod timing;
 
 /// The thing that buildsys is being asked to build.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -10,25 +10,31 @@ The implementation is closely tied to the top-level Dockerfile.
 */
 mod args;
 mod builder;
+mod bundle;
 mod cache;
 mod gomod;
-mod project;
-mod spec;
 
 use crate::args::{
-    BuildKitArgs, BuildPackageArgs, BuildVariantArgs, Buildsys, Command, RepackVariantArgs,
+    BuildKitArgs, BuildPackageArgs, BuildSdkArgs, BuildVariantArgs, Buildsys, Command, GraphArgs,
+    GraphFormat, LintSpecArgs, PopulateCacheArgs, RepackVariantArgs, RerunHints,
 };
 use crate::builder::DockerBuild;
+use buildsys::diagnostics::{Diagnostic, MessageFormat};
+use buildsys::graph::PackageGraph;
 use buildsys::manifest::{BundleModule, Manifest, ManifestInfo, SupportedArch};
+use buildsys::project::ProjectInfo;
+use buildsys::proxy;
+use buildsys::spec::{Macros, SpecInfo, SpecLint};
 use buildsys_config::EXTERNAL_KIT_METADATA;
+use bundle::{Bundle, PackageManager};
 use cache::LookasideCache;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use gomod::GoMod;
-use project::ProjectInfo;
 use snafu::{ensure, ResultExt};
-use spec::SpecInfo;
+use std::env;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
 
 mod error {
     use buildsys::manifest::SupportedArch;
@@ -42,18 +48,22 @@ mod error {
         ManifestParse { source: buildsys::manifest::Error },
 
         #[snafu(display("{source}"))]
-        SpecParse { source: super::spec::error::Error },
+        SpecParse { source: buildsys::spec::Error },
 
         #[snafu(display("{source}"))]
         ExternalFileFetch { source: super::cache::error::Error },
 
+        #[snafu(display("{source}"))]
+        Bundle { source: super::bundle::error::Error },
+
         #[snafu(display("{source}"))]
         GoMod { source: super::gomod::error::Error },
 
         #[snafu(display("{source}"))]
-        ProjectCrawl {
-            source: super::project::error::Error,
-        },
+        ProjectCrawl { source: buildsys::project::Error },
+
+        #[snafu(display("{source}"))]
+        Graph { source: buildsys::graph::Error },
 
         #[snafu(display("{source}"))]
         BuildAttempt {
@@ -94,30 +104,254 @@ mod error {
         path.display(),
         ))]
         VariantSensitive { name: String, path: PathBuf },
+
+        #[snafu(display("Missing environment variables for `buildsys {subcommand}`:\n{vars}"))]
+        MissingEnv { subcommand: String, vars: String },
+
+        #[snafu(display("Found {count} problems in spec file '{}'", path.display()))]
+        SpecLint { path: PathBuf, count: usize },
     }
 }
 
 type Result<T> = std::result::Result<T, error::Error>;
 
+impl error::Error {
+    /// The error as reported to whatever is running buildsys, with a stable code for each kind.
+    fn diagnostic(&self) -> Diagnostic {
+        use error::Error;
+        let (code, file, hint) = match self {
+            Error::ManifestParse { .. } => ("manifest-parse", None, None),
+            Error::SpecParse { .. } => ("spec-parse", None, None),
+            Error::ExternalFileFetch { .. } => (
+                "external-file-fetch",
+                None,
+                Some("Check the url and sha512 of the external file in the package's Cargo.toml"),
+            ),
+            Error::Bundle { .. } => ("bundle", None, None),
+            Error::GoMod { .. } => ("go-mod", None, None),
+            Error::ProjectCrawl { .. } => ("project-crawl", None, None),
+            Error::Graph { .. } => ("graph", None, None),
+            Error::BuildAttempt { .. } => ("build-attempt", None, None),
+            Error::BuilderInstantiation { .. } => ("builder-instantiation", None, None),
+            Error::UnsupportedArch { .. } => (
+                "unsupported-arch",
+                None,
+                Some("Build for one of the architectures the variant supports"),
+            ),
+            Error::PackageFeatures { path, .. } => ("package-features", Some(path), None),
+            Error::VariantSensitive { path, .. } => ("variant-sensitive", Some(path), None),
+            Error::MissingEnv { .. } => (
+                "missing-env",
+                None,
+                Some("Run buildsys through Twoliter, which sets these variables"),
+            ),
+            Error::SpecLint { path, .. } => ("spec-lint", Some(path), None),
+        };
+        let mut diagnostic = Diagnostic::error(code, self.to_string());
+        if let Some(file) = file {
+            diagnostic = diagnostic.with_file(file);
+        }
+        if let Some(hint) = hint {
+            diagnostic = diagnostic.with_hint(hint);
+        }
+        diagnostic
+    }
+}
+
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
 // we have nice Display representations of the error, so we wrap "main" (run) and print any error.
 // https://github.com/shepmaster/snafu/issues/110
 fn main() {
-    let args = Buildsys::parse();
-    if let Err(e) = run(args) {
-        eprintln!("{}", e);
+    let cli: Vec<String> = env::args().collect();
+    let result = check_env(&cli).and_then(|()| {
+        let matches = Buildsys::command().get_matches_from(&cli);
+        let args = Buildsys::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        run(args, matches.subcommand_name().unwrap_or_default())
+    });
+    if let Err(e) = result {
+        e.diagnostic().emit();
         process::exit(1);
     }
 }
 
-fn run(args: Buildsys) -> Result<()> {
-    args::rerun_for_envs(args.command.build_type());
+/// Checks that the environment variables the subcommand requires are set before the arguments are
+/// parsed, so that all the missing ones are listed together by name.
+fn check_env(cli: &[String]) -> Result<()> {
+    let Some(subcommand) = cli.iter().skip(1).find(|arg| !arg.starts_with('-')) else {
+        return Ok(());
+    };
+    let missing: Vec<String> = args::env_vars(subcommand)
+        .into_iter()
+        .filter(|var| var.required && !var.is_set(cli))
+        .map(|var| match var.help {
+            Some(help) => format!("  {}: {}", var.name, help),
+            None => format!("  {}", var.name),
+        })
+        .collect();
+    ensure!(
+        missing.is_empty(),
+        error::MissingEnvSnafu {
+            subcommand,
+            vars: missing.join("\n"),
+        }
+    );
+    Ok(())
+}
+
+/// Warns about `BUILDSYS_*` variables which nothing reads, since they are most likely misspelt.
+fn warn_unknown_env() {
+    for var in args::unknown_env_vars(env::vars().map(|(name, _)| name)) {
+        let mut warning = Diagnostic::warning(
+            "unknown-env",
+            format!("{} is set but is not read by buildsys", var.name),
+        );
+        if let Some(suggestion) = var.suggestion {
+            warning = warning.with_hint(format!("Did you mean {suggestion}?"));
+        }
+        warning.emit();
+    }
+}
+
+/// Prints the environment variables the subcommand reads, along with the values in effect, to
+/// stderr so that the output of commands such as `graph` is left alone.
+fn print_config(subcommand: &str) {
+    let vars = args::env_vars(subcommand);
+    let width = vars
+        .iter()
+        .map(|var| var.name.len())
+        .max()
+        .unwrap_or_default();
+    eprintln!("Configuration of `buildsys {subcommand}`:");
+    for var in vars {
+        let value = match env::var(&var.name) {
+            Ok(value) if !value.is_empty() && var.name.ends_with("_PROXY") => {
+                proxy::redact_credentials(&value)
+            }
+            Ok(value) if !value.is_empty() => value,
+            _ => match var.default {
+                Some(default) => format!("{default} (default)"),
+                None => "(unset)".to_string(),
+            },
+        };
+        eprintln!("  {:width$}  {}", var.name, value);
+    }
+}
+
+fn run(args: Buildsys, subcommand: &str) -> Result<()> {
+    if args.verbose {
+        print_config(subcommand);
+    }
+    if let Some(build_type) = args.command.build_type() {
+        args::rerun_for_envs(build_type);
+        // Warnings go to stdout as Cargo directives, so only builds run by Cargo check.
+        warn_unknown_env();
+    }
     match args.command {
         Command::BuildPackage(args) => build_package(*args),
         Command::BuildKit(args) => build_kit(*args),
         Command::BuildVariant(args) => build_variant(*args),
         Command::RepackVariant(args) => repack_variant(*args),
+        Command::LintSpec(args) => lint_spec(args),
+        Command::PopulateCache(args) => populate_cache(args),
+        Command::Graph(args) => graph(args),
+        Command::BuildSdk(args) => build_sdk(args),
+    }
+}
+
+fn lint_spec(args: LintSpecArgs) -> Result<()> {
+    let macros = Macros::read(&args.macros).context(error::SpecParseSnafu)?;
+    let info = SpecLint::new(&args.spec, &macros).context(error::SpecParseSnafu)?;
+    let package_dir = match args.spec.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let unused_patches = info
+        .unused_patches(package_dir)
+        .context(error::SpecParseSnafu)?;
+
+    for name in &info.undefined_macros {
+        report_lint(
+            Diagnostic::warning(
+                "undefined-macro",
+                format!("macro '%{{{name}}}' is never defined"),
+            )
+            .with_hint("Define the macro, or remove the line that uses it"),
+            &args.spec,
+        );
+    }
+    for patch in &unused_patches {
+        report_lint(
+            Diagnostic::warning(
+                "unused-patch",
+                format!("patch '{}' is never applied", patch.display()),
+            )
+            .with_hint("Apply the patch in %prep, or remove it"),
+            &args.spec,
+        );
+    }
+    let count = info.undefined_macros.len() + unused_patches.len();
+    ensure!(
+        count == 0,
+        error::SpecLintSnafu {
+            path: args.spec,
+            count
+        }
+    );
+    Ok(())
+}
+
+/// Prints a problem found in a spec file prefixed with the file's path, or reports it as a record.
+fn report_lint(diagnostic: Diagnostic, spec: &Path) {
+    match MessageFormat::from_env() {
+        MessageFormat::Text => println!("{}: {}", spec.display(), diagnostic.message),
+        MessageFormat::Json => diagnostic.with_file(spec).emit(),
+    }
+}
+
+fn populate_cache(args: PopulateCacheArgs) -> Result<()> {
+    let mut packages = Vec::new();
+    for manifest_path in &args.manifests {
+        let info = ManifestInfo::new(manifest_path).context(error::ManifestParseSnafu)?;
+        let package_dir = match manifest_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        packages.push((package_dir, info));
     }
+    let files: Vec<_> = packages
+        .iter()
+        .flat_map(|(dir, info)| {
+            info.external_files()
+                .into_iter()
+                .flatten()
+                .map(move |f| (*dir, f))
+        })
+        .collect();
+
+    let lookaside_cache = LookasideCache::new(
+        env!("CARGO_PKG_VERSION"),
+        args.lookaside_cache,
+        true,
+        Duration::from_secs(args.upstream_timeout),
+        args.fetch_jobs,
+    )
+    .context(error::ExternalFileFetchSnafu)?;
+    lookaside_cache
+        .populate(&files)
+        .context(error::ExternalFileFetchSnafu)
+}
+
+fn graph(args: GraphArgs) -> Result<()> {
+    let graph = PackageGraph::new(&args.manifests).context(error::GraphSnafu)?;
+    match args.format {
+        GraphFormat::Dot => print!("{}", graph.to_dot()),
+        GraphFormat::Json => println!("{}", graph.to_json().context(error::GraphSnafu)?),
+    }
+    Ok(())
+}
+
+fn build_sdk(args: BuildSdkArgs) -> Result<()> {
+    builder::build_sdk(&args).context(error::BuildAttemptSnafu)
 }
 
 fn build_package(args: BuildPackageArgs) -> Result<()> {
@@ -136,11 +370,17 @@ fn build_package(args: BuildPackageArgs) -> Result<()> {
     ensure_package_is_not_variant_sensitive(&manifest, &manifest_path)?;
 
     if let Some(files) = manifest.info().external_files() {
-        let lookaside_cache = LookasideCache::new(
+        let mut lookaside_cache = LookasideCache::new(
             &args.common.version_full,
             args.lookaside_cache.clone(),
             args.upstream_source_fallback == "true",
-        );
+            Duration::from_secs(args.upstream_timeout),
+            args.fetch_jobs,
+        )
+        .context(error::ExternalFileFetchSnafu)?;
+        if let Some(store) = &args.external_files_dir {
+            lookaside_cache = lookaside_cache.with_store(store.clone());
+        }
         lookaside_cache
             .fetch(files)
             .context(error::ExternalFileFetchSnafu)?;
@@ -150,15 +390,31 @@ fn build_package(args: BuildPackageArgs) -> Result<()> {
             }
 
             for b in f.bundle_modules.as_ref().unwrap() {
-                match b {
-                    BundleModule::Go => GoMod::vendor(
-                        &args.common.root_dir,
-                        &args.common.cargo_manifest_dir,
-                        f,
-                        &args.common.sdk_image,
-                    )
-                    .context(error::GoModSnafu)?,
-                }
+                let package_manager = match b {
+                    BundleModule::Go => {
+                        GoMod::vendor(
+                            &args.common.root_dir,
+                            &args.common.cargo_manifest_dir,
+                            f,
+                            &args.common.sdk_image,
+                        )
+                        .context(error::GoModSnafu)?;
+                        continue;
+                    }
+                    BundleModule::Cargo => PackageManager::Cargo,
+                    BundleModule::Pip => PackageManager::Pip,
+                    BundleModule::Npm => PackageManager::Npm,
+                };
+                Bundle::vendor(
+                    package_manager,
+                    &args.common.root_dir,
+                    &args.common.state_dir,
+                    &args.common.cargo_manifest_dir,
+                    f,
+                    &args.common.sdk_image,
+                    args.common.arch,
+                )
+                .context(error::BundleSnafu)?;
             }
         }
     }
@@ -168,9 +424,20 @@ fn build_package(args: BuildPackageArgs) -> Result<()> {
             .iter()
             .map(|d| args.sources_dir.join(d))
             .collect::<Vec<_>>();
-        let info = ProjectInfo::crawl(&dirs).context(error::ProjectCrawlSnafu)?;
-        for f in info.files {
-            println!("cargo:rerun-if-changed={}", f.display());
+        match args.rerun_hints {
+            RerunHints::Files => {
+                let info = ProjectInfo::crawl(&dirs).context(error::ProjectCrawlSnafu)?;
+                for f in info.files {
+                    println!("cargo:rerun-if-changed={}", f.display());
+                }
+            }
+            // Cargo reruns the build when anything in the directories changes, including ignored
+            // files, but the checkpoint only covers the files which aren't.
+            RerunHints::Directories => {
+                for d in dirs {
+                    println!("cargo:rerun-if-changed={}", d.display());
+                }
+            }
         }
     }
 
@@ -33,6 +33,41 @@ url = "https://bar"
 sha512 = "123456"
 ```
 
+An external file may instead be archived from a git repository, for upstreams
+which don't publish tarballs. `git` gives the repository's URL and the full
+hash of the commit to archive; buildsys clones it, checks that it has that
+commit, and writes it to an uncompressed tarball with stable ordering and
+timestamps, whose contents are in a directory named like the file without its
+`.tar` suffix. The file is named `<repository>-<first 12 characters of rev>.tar`
+unless `path` is given. `sha512` is the hash of the tarball, which is stored in
+the lookaside cache like any other external file.
+```ignore
+[[package.metadata.build-package.external-files]]
+git = { url = "https://github.com/foo/libfoo.git", rev = "0123456789abcdef0123456789abcdef01234567" }
+sha512 = "abcdef"
+```
+
+`mirrors` optionally lists other URLs the file may be downloaded from. When
+the file must be fetched from upstream, because the lookaside cache doesn't
+have it, `url` is tried first and then each mirror in order. A download which
+stalls for longer than `BUILDSYS_UPSTREAM_TIMEOUT` moves on to the next.
+```ignore
+[[package.metadata.build-package.external-files]]
+url = "https://foo/libfoo-1.0.tar.gz"
+mirrors = ["https://mirror.example.com/foo/libfoo-1.0.tar.gz"]
+sha512 = "abcdef"
+```
+
+`license` optionally declares the license of an external file as an SPDX
+expression, for files whose license differs from the package's own, such as
+a vendored library. It is reported by `twoliter licenses`.
+```ignore
+[[package.metadata.build-package.external-files]]
+url = "https://foo/libfoo-1.0.tar.gz"
+sha512 = "abcdef"
+license = "BSD-3-Clause"
+```
+
 The `bundle-*` keys on `external-files` are a group of optional modifiers
 and are used to untar an upstream external file archive, vendor any dependent
 code, and produce an additional archive with those dependencies.
@@ -41,7 +76,17 @@ Only `bundle-modules` is required when bundling an archive's dependences.
 `bundle-modules` is a list of module "paradigms" the external-file should
 be vendored through. For example, if a project contains a `go.mod` and `go.sum`
 file, adding "go" to the list will vendor the dependencies through go modules.
-Currently, only "go" is supported.
+If a project contains a `Cargo.toml` and `Cargo.lock` file, adding "cargo" to the
+list will vendor the locked crates with `cargo vendor`, and add the source
+replacement that Cargo needs to use them to `.cargo/config.toml`. Adding "pip"
+will download the distributions listed in the project's `requirements.txt` to
+`vendor`, and adding "npm" will install the packages locked by its
+`package-lock.json` to `node_modules`. Every requirement must be pinned with a
+hash, and every locked npm package must have an integrity hash, which are
+checked as the dependencies are downloaded. npm install scripts are not run.
+The output archive of these modules is reproducible for given lock files.
+Currently, "go", "cargo", "pip" and "npm" are supported. Each module writes the
+output archive, so an external file should list only one of them.
 
 `bundle-root-path` is an optional argument that provides the filepath
 within the archive that contains the module. By default, the first top level
@@ -80,6 +125,66 @@ naming.
 package-name = "better.name"
 ```
 
+`cpus` and `memory` limit the resources of the package's build, so that
+heavyweight packages don't starve the builds running alongside them. They
+override the project's defaults. `memory` is given as container runtimes take
+it.
+```ignore
+[package.metadata.build-package]
+cpus = 8
+memory = "16g"
+```
+
+`network` sets the network access of the package's build once its sources are
+fetched. With `none`, the default, the build can't reach the network, so it
+can't silently download dependencies it doesn't declare. With `sdk-proxy`, the
+build can reach only the proxy the project configures for package builds, which
+decides what it may download. With `full`, the build may reach anything.
+It overrides the project's default.
+```ignore
+[package.metadata.build-package]
+network = "sdk-proxy"
+```
+
+Every package keeps its build directory between builds, one for each
+architecture, which is emptied whenever the SDK, the package's sources, patches
+or source groups, or the parts of its spec file that `%prep` and `%build`
+depend on, change. The sources are extracted into it, so a change to
+`%install`, `%files` or other later sections of the spec file skips `%prep` and
+builds on the last build's objects. If such a build fails, the next one starts
+again from `%prep`.
+
+`persistent-build-dir` keeps the build directory even when a build fails, so
+that packages such as the kernel can reuse the objects of their last build
+rather than compiling everything again, and lets the spec file find it at
+`%{_persistent_build_dir}`.
+```ignore
+[package.metadata.build-package]
+persistent-build-dir = true
+```
+
+`secrets` lists the secrets declared under `[secrets]` in Twoliter.toml which
+the package's build reads, from `%{_package_secrets_dir}/<name>`. Only these
+secrets are given to the build, and they're only read when the package is
+built.
+```ignore
+[package.metadata.build-package]
+secrets = ["git-token"]
+```
+
+`build-mode` builds the package instrumented for debugging, without editing its
+spec file. With `debug`, it's compiled with full debug information and without
+optimizations; with `asan` or `ubsan`, with AddressSanitizer or
+UndefinedBehaviorSanitizer. The flags are added to the C and C++ compiler and
+linker flags of the build, and the release of the RPMs ends with the mode, e.g.
+`.br1.asan`, so they can't be mistaken for the usual build. With `release`,
+the default, the package is built as usual. It overrides the project's build
+profile.
+```ignore
+[package.metadata.build-package]
+build-mode = "asan"
+```
+
 `releases-url` is ignored by buildsys, but can be used by packager maintainers
 to indicate a good URL for checking whether the software has had a new release.
 ```ignore
@@ -106,6 +211,16 @@ another-kit = { path = "../../kits/another-kit" }
 some-package = { path = "../../packages/some-package" }
 ```
 
+`deprecated` and `end-of-support` are recorded in the kit's image metadata so that projects which
+depend on the kit are warned when they update their lock file. So is the kit's `package.license`,
+which `twoliter licenses` reports for projects that use the kit. `deprecated` explains what to use
+instead, and `end-of-support` is the last date, as `YYYY-MM-DD`, on which the kit is supported.
+```ignore
+[package.metadata.build-kit]
+deprecated = "Use my-other-kit instead"
+end-of-support = "2025-06-30"
+```
+
 ## Metadata for variants
 
 `included-packages` is a list of packages that should be included in a variant.
@@ -235,8 +350,10 @@ fips = true
 
 mod error;
 
+use crate::diagnostics::Diagnostic;
 use crate::BuildType;
 use buildsys_config::EXTERNAL_KIT_METADATA;
+use clap::ValueEnum;
 use guppy::graph::{DependencyDirection, PackageGraph, PackageLink, PackageMetadata};
 use guppy::{CargoMetadata, PackageId};
 use serde::{Deserialize, Serialize};
@@ -331,11 +448,58 @@ impl Manifest {
         Ok(kits)
     }
 
+    /// The length of the critical path from this manifest through everything which depends on it:
+    /// the total weight of the heaviest chain of dependents, including this manifest. Packages are
+    /// weighted by `weight`, given their package names, while kits and variants weigh nothing.
+    /// Building packages with longer critical paths first lets the build finish sooner.
+    pub fn critical_path(&self, weight: impl Fn(&str) -> u64) -> Result<u64> {
+        let name = self.info().manifest_name();
+        let manifest_type = self.info().build_type()?;
+        let id = find_id(name, &self.graph, manifest_type)
+            .context(error::RootDependencyMissingSnafu { name })?;
+        Ok(critical_path(
+            &self.graph,
+            &id,
+            &weight,
+            &mut HashMap::new(),
+        ))
+    }
+
     pub fn info(&self) -> &ManifestInfo {
         &self.manifest_info
     }
 }
 
+/// The critical path from `id` through its dependents, remembering the length found for each
+/// package in `lengths` since the chains of dependents overlap.
+fn critical_path(
+    graph: &PackageGraph,
+    id: &PackageId,
+    weight: &impl Fn(&str) -> u64,
+    lengths: &mut HashMap<PackageId, u64>,
+) -> u64 {
+    if let Some(length) = lengths.get(id) {
+        return *length;
+    }
+    let Ok(pkg_metadata) = graph.metadata(id) else {
+        return 0;
+    };
+    let own_weight = if is_manifest_type(&pkg_metadata, BuildType::Package) {
+        weight(&get_buildsys_package_name(&pkg_metadata))
+    } else {
+        0
+    };
+    let dependents = pkg_metadata
+        .reverse_direct_links()
+        .filter(|link| !link.dev_only())
+        .map(|link| critical_path(graph, link.from().id(), weight, lengths))
+        .max()
+        .unwrap_or_default();
+    let length = own_weight + dependents;
+    lengths.insert(id.clone(), length);
+    length
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ExternalKitMetadataView {
     #[serde(rename = "kit")]
@@ -359,7 +523,7 @@ impl ExternalKitMetadataView {
             })
             .map_err(Error)
     }
-    /// List all external kits needed for the build in the format of "<vendor>-<kit_name>"
+    /// List all external kits needed for the build in the format of "<vendor>/<kit_name>"
     pub fn list(&self) -> Vec<String> {
         self.kits
             .iter()
@@ -436,6 +600,21 @@ impl ManifestInfo {
             .clone())
     }
 
+    /// The license declared by `package.license`, as an SPDX expression.
+    pub fn license(&self) -> Option<&str> {
+        self.package.license.as_deref()
+    }
+
+    /// Convenience method to return why the kit is deprecated, if it is.
+    pub fn kit_deprecated(&self) -> Option<&str> {
+        self.build_kit().and_then(|b| b.deprecated.as_deref())
+    }
+
+    /// Convenience method to return the last date on which the kit is supported, if it has one.
+    pub fn kit_end_of_support(&self) -> Option<&str> {
+        self.build_kit().and_then(|b| b.end_of_support.as_deref())
+    }
+
     /// Convenience method to find whether the package is sensitive to variant changes.
     pub fn variant_sensitive(&self) -> Option<&VariantSensitivity> {
         self.build_package()
@@ -448,6 +627,40 @@ impl ManifestInfo {
             .and_then(|b| b.package_features.as_ref().map(|m| m.iter().collect()))
     }
 
+    /// Convenience method to return the number of CPUs the package's build may use, if limited.
+    pub fn cpus(&self) -> Option<u32> {
+        self.build_package().and_then(|b| b.cpus)
+    }
+
+    /// Convenience method to return the memory the package's build may use, if limited.
+    pub fn memory(&self) -> Option<&str> {
+        self.build_package().and_then(|b| b.memory.as_deref())
+    }
+
+    /// Convenience method to find whether the package keeps its build directory when a build fails.
+    pub fn persistent_build_dir(&self) -> bool {
+        self.build_package()
+            .and_then(|b| b.persistent_build_dir)
+            .unwrap_or(false)
+    }
+
+    /// Convenience method to return the network access of the package's build, if set.
+    pub fn network(&self) -> Option<PackageNetwork> {
+        self.build_package().and_then(|b| b.network)
+    }
+
+    /// Convenience method to return the secrets the package's build reads.
+    pub fn secrets(&self) -> &[String] {
+        self.build_package()
+            .and_then(|b| b.secrets.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Convenience method to return the build mode of the package, if set.
+    pub fn build_mode(&self) -> Option<BuildMode> {
+        self.build_package().and_then(|b| b.build_mode)
+    }
+
     /// Convenience method to return the list of included packages.
     pub fn included_packages(&self) -> Option<&Vec<String>> {
         self.build_variant()
@@ -503,10 +716,13 @@ impl ManifestInfo {
         } else if self.build_variant().is_some() {
             Ok(BuildType::Variant)
         } else {
-            println!(
-                "cargo::warning=Expected to find one of 'build-package', 'build-kit', or \
-                'build-variant' in package.metadata. Assuming 'build-package'."
-            );
+            Diagnostic::warning(
+                "unknown-build-type",
+                "Expected to find one of 'build-package', 'build-kit', or 'build-variant' in \
+                package.metadata. Assuming 'build-package'.",
+            )
+            .with_hint("Add a [package.metadata.build-package] section to Cargo.toml")
+            .emit();
             Ok(BuildType::Package)
         }
     }
@@ -598,6 +814,7 @@ fn get_buildsys_package_name(pkg_metadata: &PackageMetadata) -> String {
 #[serde(rename_all = "kebab-case")]
 struct Package {
     name: String,
+    license: Option<String>,
     metadata: Option<Metadata>,
 }
 
@@ -619,6 +836,12 @@ pub struct BuildPackage {
     pub source_groups: Option<Vec<PathBuf>>,
     pub variant_sensitive: Option<VariantSensitivity>,
     pub package_features: Option<Vec<ImageFeature>>,
+    pub cpus: Option<u32>,
+    pub memory: Option<String>,
+    pub network: Option<PackageNetwork>,
+    pub persistent_build_dir: Option<bool>,
+    pub build_mode: Option<BuildMode>,
+    pub secrets: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -627,6 +850,8 @@ pub struct BuildPackage {
 pub struct BuildKit {
     pub kit_name: Option<String>,
     pub vendor: String,
+    pub deprecated: Option<String>,
+    pub end_of_support: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -666,6 +891,24 @@ pub enum ImageFormat {
     Vmdk,
 }
 
+/// Image formats which variant builds can emit besides the one the variant declares. The images
+/// are converted in the SDK container once the primary image is built.
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    Qcow2,
+    /// A fixed size VHD, as most clouds require
+    Vhd,
+    Vhdx,
+    /// A raw image compressed with gzip
+    RawGz,
+    /// A stream optimized VMDK
+    Vmdk,
+}
+
+serde_plain::derive_fromstr_from_deserialize!(OutputFormat);
+serde_plain::derive_display_from_serialize!(OutputFormat);
+
 #[derive(Deserialize, Debug, Copy, Clone)]
 /// Constrain specified image sizes to a plausible range, from 0 - 65535 GiB.
 pub struct ImageSize(u16);
@@ -749,6 +992,63 @@ impl Default for ImageLayout {
     }
 }
 
+/// The network access of a package's build once its sources are fetched.
+#[derive(Deserialize, Serialize, Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageNetwork {
+    /// No network access
+    #[default]
+    None,
+    /// Network access through the proxy given as `sdk-proxy`
+    SdkProxy,
+    /// Unrestricted network access
+    Full,
+}
+
+serde_plain::derive_fromstr_from_deserialize!(PackageNetwork);
+serde_plain::derive_display_from_serialize!(PackageNetwork);
+
+/// Whether a package is built as usual, or instrumented for debugging.
+#[derive(Deserialize, Serialize, Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildMode {
+    /// The usual build
+    #[default]
+    Release,
+    /// Full debug information and no optimizations
+    Debug,
+    /// Instrumented with AddressSanitizer
+    Asan,
+    /// Instrumented with UndefinedBehaviorSanitizer
+    Ubsan,
+}
+
+serde_plain::derive_fromstr_from_deserialize!(BuildMode);
+serde_plain::derive_display_from_serialize!(BuildMode);
+
+impl BuildMode {
+    /// The flags added to those of the C and C++ compilers.
+    pub fn compiler_flags(&self) -> &'static str {
+        match self {
+            BuildMode::Release => "",
+            BuildMode::Debug => "-Og -g3 -fno-omit-frame-pointer",
+            BuildMode::Asan => "-fsanitize=address -fno-omit-frame-pointer -g",
+            BuildMode::Ubsan => "-fsanitize=undefined -fno-omit-frame-pointer -g",
+        }
+    }
+
+    /// The flags added to those of the linker.
+    pub fn linker_flags(&self) -> &'static str {
+        match self {
+            BuildMode::Release | BuildMode::Debug => "",
+            BuildMode::Asan => "-fsanitize=address",
+            BuildMode::Ubsan => "-fsanitize=undefined",
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum PartitionPlan {
@@ -822,6 +1122,9 @@ impl fmt::Display for ImageFeature {
 #[serde(rename_all = "lowercase")]
 pub enum BundleModule {
     Go,
+    Cargo,
+    Pip,
+    Npm,
 }
 
 #[derive(Deserialize, Debug)]
@@ -829,11 +1132,68 @@ pub enum BundleModule {
 pub struct ExternalFile {
     pub path: Option<PathBuf>,
     pub sha512: String,
-    pub url: String,
+    pub url: Option<String>,
+    pub git: Option<GitSource>,
+    pub mirrors: Option<Vec<String>>,
     pub force_upstream: Option<bool>,
     pub bundle_modules: Option<Vec<BundleModule>>,
     pub bundle_root_path: Option<PathBuf>,
     pub bundle_output_path: Option<PathBuf>,
+    pub license: Option<String>,
+}
+
+impl ExternalFile {
+    /// The URL the file is downloaded from, or the URL of the repository it's archived from.
+    pub fn source_url(&self) -> &str {
+        match (&self.url, &self.git) {
+            (Some(url), _) => url,
+            (None, Some(git)) => &git.url,
+            (None, None) => "",
+        }
+    }
+
+    /// The name of the file in the package directory. Fails unless the file has exactly one of a
+    /// URL and a git source.
+    pub fn file_name(&self) -> Result<PathBuf> {
+        let name = match (&self.url, &self.git) {
+            (Some(url), None) => last_path_segment(url),
+            (None, Some(git)) => last_path_segment(&git.url).map(|repo| {
+                let repo = repo.strip_suffix(".git").unwrap_or(&repo);
+                let rev = git.rev.get(..12).unwrap_or(&git.rev);
+                format!("{repo}-{rev}.tar")
+            }),
+            _ => error::ExternalFileSourceSnafu {
+                sha512: &self.sha512,
+            }
+            .fail()?,
+        };
+        match &self.path {
+            Some(path) => Ok(path.clone()),
+            None => Ok(name
+                .context(error::ExternalFileNameSnafu {
+                    url: self.source_url(),
+                })?
+                .into()),
+        }
+    }
+}
+
+/// A git commit that an external file is archived from.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct GitSource {
+    pub url: String,
+    /// The full hash of the commit
+    pub rev: String,
+}
+
+/// The last non-empty component of a URL's path.
+fn last_path_segment(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()?
+        .path_segments()?
+        .rfind(|segment| !segment.is_empty())
+        .map(str::to_string)
 }
 
 // =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^= =^..^=
@@ -927,6 +1287,18 @@ mod test {
         assert_eq!(package_list, expected);
     }
 
+    #[test]
+    fn test_critical_path_pkg_a() {
+        let manifest_path = cargo_manifest("pkg-a-1.27");
+        let temp_dir = TempDir::new().unwrap();
+        let cargo_metadata_path = cargo_metadata_path(&temp_dir);
+        let manifest = Manifest::new(manifest_path, cargo_metadata_path).unwrap();
+        // The longest chains are pkg-a, pkg-f, pkg-g and pkg-a, pkg-b, pkg-e.
+        assert_eq!(manifest.critical_path(|_| 1).unwrap(), 3);
+        let weight = |package: &str| if package == "pkg-g" { 10 } else { 1 };
+        assert_eq!(manifest.critical_path(weight).unwrap(), 12);
+    }
+
     #[test]
     fn test_kit_dependencies_pkg_e() {
         let manifest_path = cargo_manifest("pkg-e");
@@ -957,4 +1329,33 @@ mod test {
         ];
         assert_eq!(kit_list, expected);
     }
+
+    #[test]
+    fn test_external_file_name() {
+        let file = |toml: &str| toml::from_str::<ExternalFile>(toml).unwrap();
+        let name = |toml: &str| file(toml).file_name().unwrap();
+        assert_eq!(
+            name(
+                r#"url = "https://example.com/foo-1.0.tar.gz"
+                sha512 = "abc""#
+            ),
+            PathBuf::from("foo-1.0.tar.gz")
+        );
+        assert_eq!(
+            name(
+                r#"url = "https://example.com/foo-1.0.tar.gz"
+                path = "foo.tar.gz"
+                sha512 = "abc""#
+            ),
+            PathBuf::from("foo.tar.gz")
+        );
+        assert_eq!(
+            name(
+                r#"git = { url = "https://example.com/foo.git", rev = "0123456789abcdef0123" }
+                sha512 = "abc""#
+            ),
+            PathBuf::from("foo-0123456789ab.tar")
+        );
+        assert!(file(r#"sha512 = "abc""#).file_name().is_err());
+    }
 }
@@ -39,6 +39,15 @@ pub(super) enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display(
+        "External file with sha512 '{}' must have exactly one of `url` and `git`",
+        sha512
+    ))]
+    ExternalFileSource { sha512: String },
+
+    #[snafu(display("Unable to name external file from '{}'; give it a `path`", url))]
+    ExternalFileName { url: String },
+
     #[snafu(display("Failed to parse image feature '{}'", what))]
     ParseImageFeature { what: String },
 
@@ -3,43 +3,147 @@ This module handles iterating through project directories to discover source
 files that should be passed to Cargo to watch for changes.
 
 For now, it's a thin wrapper around `walkdir` with a filter applied to ignore
-files that shouldn't trigger rebuilds.
+files that shouldn't trigger rebuilds. Twoliter also uses it, along with the
+`spec` module, to find the files that `twoliter watch` monitors.
+
+Symlinks are followed, except those which point back to a directory that is
+already being walked.
+
+Hidden files, `target` and `vendor` directories and `README.md` files are always
+ignored. A directory may ignore more with a `.buildsysignore` file, which lists
+one glob pattern per line, such as `*.log` or `docs/`, along with comments that
+start with `#`. A pattern is matched against paths relative to the directory the
+file is in; one without a `/` matches names at any depth below it. A pattern
+starting with `!` includes what an earlier pattern ignored, and the last pattern
+to match a path decides, with the patterns of deeper `.buildsysignore` files
+coming later. As with git, an ignored directory is not walked at all, so nothing
+below it can be included again.
+
+The digest of the files can be given a cache of the digests of individual files,
+keyed by their size and change times, so that only files which changed since the
+last digest are read again.
+
+Git submodules declared in a `.gitmodules` file, either in the repository that
+holds a directory or within it, must be initialized. Otherwise the crawl fails
+with the list of missing submodules, rather than letting the build go ahead with
+their sources silently empty.
 
 */
-pub(crate) mod error;
-use error::Result;
+mod error;
 
-use snafu::ResultExt;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use snafu::{ensure, ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
-pub(crate) struct ProjectInfo {
-    pub(crate) files: Vec<PathBuf>,
+#[derive(Debug, Snafu)]
+pub struct Error(error::Error);
+type Result<T> = std::result::Result<T, Error>;
+
+/// The name of the files which list further paths to ignore.
+pub const IGNORE_FILE_NAME: &str = ".buildsysignore";
+
+#[derive(Debug)]
+pub struct ProjectInfo {
+    /// The files to track, along with the `.buildsysignore` files which decided which they are.
+    pub files: Vec<PathBuf>,
 }
 
 impl ProjectInfo {
     /// Traverse the list of directories and produce a list of files to track.
-    pub(crate) fn crawl<P: AsRef<Path>>(dirs: &[P]) -> Result<Self> {
+    pub fn crawl<P: AsRef<Path>>(dirs: &[P]) -> Result<Self> {
         let mut files = Vec::new();
+        let mut missing = Vec::new();
 
         for dir in dirs {
+            let dir = dir.as_ref();
             let walker = WalkDir::new(dir)
-                .follow_links(false)
+                .follow_links(true)
                 .same_file_system(true)
                 .into_iter();
 
-            files.extend(
-                walker
-                    .filter_entry(|e| !Self::ignored(e))
-                    .flat_map(|e| e.context(error::DirectoryWalkSnafu))
-                    .map(|e| e.into_path())
-                    .filter(|e| e.is_file()),
-            );
+            // The patterns of each `.buildsysignore` found so far, with the directory it's in.
+            let mut rules: Vec<(PathBuf, Vec<Pattern>)> = Vec::new();
+            // The submodules declared by the repository holding the directory, or found within it.
+            let mut submodules = Self::enclosing_submodules(dir)?;
+            let mut failure = None;
+            let entries = walker.filter_entry(|e| {
+                if Self::ignored(e) || Self::ignored_by(&rules, e.path()) {
+                    return false;
+                }
+                if e.file_type().is_dir() {
+                    if submodules.iter().any(|s| s == e.path()) && is_empty_dir(e.path()) {
+                        missing.push(e.path().to_path_buf());
+                    }
+                    let loaded = Self::load_rules(e.path()).and_then(|set| {
+                        if let Some(patterns) = set {
+                            files.push(e.path().join(IGNORE_FILE_NAME));
+                            rules.push((e.path().to_path_buf(), patterns));
+                        }
+                        submodules.extend(Self::submodule_paths(e.path())?);
+                        Ok(())
+                    });
+                    if let Err(err) = loaded {
+                        failure.get_or_insert(err);
+                    }
+                }
+                true
+            });
+            let mut found = Vec::new();
+            for entry in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    // A symlink to a directory being walked is skipped, since its files are
+                    // already found through the directory, as is a dangling symlink.
+                    Err(e) if e.loop_ancestor().is_some() || is_dangling_link(&e) => continue,
+                    Err(e) => Err(e).context(error::DirectoryWalkSnafu)?,
+                };
+                if entry.file_type().is_file() {
+                    found.push(entry.into_path());
+                }
+            }
+            if let Some(err) = failure {
+                return Err(err);
+            }
+            // Submodules that were never checked out may not even have an empty directory.
+            missing.extend(submodules.into_iter().filter(|s| !s.exists()));
+            files.extend(found);
         }
 
+        ensure!(
+            missing.is_empty(),
+            error::MissingSubmodulesSnafu {
+                paths: missing
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            }
+        );
         Ok(ProjectInfo { files })
     }
 
+    /// The digest of the paths and contents of the files, which changes only when a tracked file
+    /// does, unlike the modification times of the directories holding them. With a `cache`, the
+    /// digests of files whose size and change times are the same as when they were cached are
+    /// reused, and the cache is updated with the rest.
+    pub fn digest(&self, cache: Option<&Path>) -> Result<String> {
+        let mut d = Sha512::new();
+        for (path, file_digest) in file_digests(&self.files, cache)? {
+            d.update(path.to_string_lossy().as_bytes());
+            d.update([0]);
+            d.update(file_digest.as_bytes());
+            d.update([0]);
+        }
+        Ok(hex::encode(d.finalize()))
+    }
+
     /// Exclude hidden files and build artifacts from the list.
     fn ignored(entry: &DirEntry) -> bool {
         entry
@@ -48,4 +152,340 @@ impl ProjectInfo {
             .map(|s| s.starts_with('.') || s == "target" || s == "vendor" || s == "README.md")
             .unwrap_or(false)
     }
+
+    /// Whether the `.buildsysignore` files in the directories above the path ignore it, which is
+    /// decided by the last pattern that matches.
+    fn ignored_by(rules: &[(PathBuf, Vec<Pattern>)], path: &Path) -> bool {
+        let mut ignored = false;
+        for (dir, patterns) in rules {
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+            for pattern in patterns {
+                if pattern.glob.is_match(relative) {
+                    ignored = !pattern.negated;
+                }
+            }
+        }
+        ignored
+    }
+
+    /// The paths of the submodules declared by the `.gitmodules` in the directory, if it has one.
+    fn submodule_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+        let path = dir.join(".gitmodules");
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&path).context(error::FileReadSnafu { path: &path })?;
+        Ok(contents
+            .lines()
+            .filter_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                let value = value.trim().trim_matches('"');
+                (key.trim() == "path").then(|| dir.join(value))
+            })
+            .collect())
+    }
+
+    /// The submodules within the directory which are declared by the git repository holding it.
+    fn enclosing_submodules(dir: &Path) -> Result<Vec<PathBuf>> {
+        let Ok(canonical) = dir.canonicalize() else {
+            return Ok(Vec::new());
+        };
+        let Some(repo) = canonical
+            .ancestors()
+            .skip(1)
+            .find(|ancestor| ancestor.join(".git").exists())
+        else {
+            return Ok(Vec::new());
+        };
+        Ok(Self::submodule_paths(repo)?
+            .into_iter()
+            .filter_map(|path| {
+                path.strip_prefix(&canonical)
+                    .ok()
+                    .map(|relative| dir.join(relative))
+            })
+            .collect())
+    }
+
+    /// Reads the patterns of the `.buildsysignore` in the directory, if it has one.
+    fn load_rules(dir: &Path) -> Result<Option<Vec<Pattern>>> {
+        let path = dir.join(IGNORE_FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path).context(error::FileReadSnafu { path: &path })?;
+        let mut patterns = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, rule) = match line.strip_prefix('!') {
+                Some(rule) => (true, rule),
+                None => (false, line),
+            };
+            let pattern = rule.trim_start_matches('/').trim_end_matches('/');
+            let pattern = if rule.trim_end_matches('/').contains('/') {
+                pattern.to_string()
+            } else {
+                format!("**/{pattern}")
+            };
+            let glob = GlobBuilder::new(&pattern)
+                .literal_separator(true)
+                .build()
+                .context(error::IgnorePatternSnafu {
+                    path: &path,
+                    pattern: line,
+                })?;
+            patterns.push(Pattern {
+                glob: glob.compile_matcher(),
+                negated,
+            });
+        }
+        Ok(Some(patterns))
+    }
+}
+
+/// A pattern from a `.buildsysignore`, which includes what it matches again if it's negated.
+#[derive(Debug)]
+struct Pattern {
+    glob: GlobMatcher,
+    negated: bool,
+}
+
+/// The size and change times of a file, which change whenever its contents do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    len: u64,
+    modified: (i64, i64),
+    changed: (i64, i64),
+    inode: u64,
+}
+
+impl FileStamp {
+    fn new(metadata: &fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: (metadata.mtime(), metadata.mtime_nsec()),
+            changed: (metadata.ctime(), metadata.ctime_nsec()),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+/// The digest of a file, as cached along with its stamp.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedDigest {
+    stamp: FileStamp,
+    digest: String,
+}
+
+/// The hex-encoded sha512 digests of the contents of `files`, keyed by path. With a `cache`, the
+/// digests of files whose size and change times are the same as when they were cached are reused,
+/// and the cache is replaced with the digests of `files`.
+pub fn file_digests(files: &[PathBuf], cache: Option<&Path>) -> Result<BTreeMap<PathBuf, String>> {
+    let mut cached: BTreeMap<PathBuf, CachedDigest> = cache
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default();
+    let mut updated = BTreeMap::new();
+    for path in files {
+        let metadata = fs::metadata(path).context(error::FileReadSnafu { path })?;
+        let stamp = FileStamp::new(&metadata);
+        let digest = match cached.remove(path) {
+            Some(entry) if entry.stamp == stamp => entry.digest,
+            _ => file_digest(path)?,
+        };
+        updated.insert(path.clone(), CachedDigest { stamp, digest });
+    }
+    // The cache only saves work, so failing to update it doesn't fail the digest.
+    if let Some(path) = cache {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(contents) = serde_json::to_vec(&updated) {
+            let _ = fs::write(path, contents);
+        }
+    }
+    Ok(updated
+        .into_iter()
+        .map(|(path, entry)| (path, entry.digest))
+        .collect())
+}
+
+/// The digest of the contents of a file.
+fn file_digest(path: &Path) -> Result<String> {
+    let mut d = Sha512::new();
+    let mut file = File::open(path).context(error::FileReadSnafu { path })?;
+    io::copy(&mut file, &mut d).context(error::FileReadSnafu { path })?;
+    Ok(hex::encode(d.finalize()))
+}
+
+/// Whether the path is a directory with nothing in it, as a submodule is until it's initialized.
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// Whether the walk failed because a symlink points to nothing.
+fn is_dangling_link(err: &walkdir::Error) -> bool {
+    err.io_error()
+        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+        && err
+            .path()
+            .is_some_and(|path| path.symlink_metadata().is_ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crawl_honors_ignore_files() {
+        // The temporary directory's name starts with a `.`, which would hide everything in it.
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = &dir.path().join("sources");
+        for file in [
+            "src/main.rs",
+            "src/debug.log",
+            "docs/guide.md",
+            "nested/docs/kept.md",
+            "nested/build.log",
+            "target/out",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+        fs::write(root.join(IGNORE_FILE_NAME), "# comment\n*.log\n/docs/\n").unwrap();
+
+        let info = ProjectInfo::crawl(&[root]).unwrap();
+        let mut files: Vec<_> = info
+            .files
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [IGNORE_FILE_NAME, "nested/docs/kept.md", "src/main.rs"].map(PathBuf::from)
+        );
+
+        let digest = info.digest(None).unwrap();
+        fs::write(root.join("src/debug.log"), "changed").unwrap();
+        assert_eq!(
+            ProjectInfo::crawl(&[root]).unwrap().digest(None).unwrap(),
+            digest
+        );
+        fs::write(root.join("src/main.rs"), "changed").unwrap();
+        assert_ne!(
+            ProjectInfo::crawl(&[root]).unwrap().digest(None).unwrap(),
+            digest
+        );
+    }
+
+    #[test]
+    fn crawl_includes_negated_patterns() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = &dir.path().join("sources");
+        for file in ["a.log", "keep.log", "nested/b.log", "nested/c.log", "out/x"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+        fs::write(
+            root.join(IGNORE_FILE_NAME),
+            "*.log\n!keep.log\nout/\n!out/x\n",
+        )
+        .unwrap();
+        fs::write(root.join("nested").join(IGNORE_FILE_NAME), "!c.log\n").unwrap();
+
+        let info = ProjectInfo::crawl(&[root]).unwrap();
+        let mut files: Vec<_> = info
+            .files
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        // Nothing in an ignored directory is included again.
+        assert_eq!(
+            files,
+            [
+                IGNORE_FILE_NAME,
+                "keep.log",
+                &format!("nested/{IGNORE_FILE_NAME}"),
+                "nested/c.log"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn digest_reuses_cached_file_digests() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = &dir.path().join("sources");
+        let cache = dir.path().join("cache.json");
+        fs::create_dir_all(root).unwrap();
+        fs::write(root.join("main.rs"), "main").unwrap();
+
+        let info = ProjectInfo::crawl(&[root]).unwrap();
+        let digest = info.digest(Some(&cache)).unwrap();
+        assert_eq!(info.digest(None).unwrap(), digest);
+        assert!(cache.is_file());
+        assert_eq!(info.digest(Some(&cache)).unwrap(), digest);
+
+        // A cached digest whose file has changed since is not reused.
+        fs::write(root.join("main.rs"), "changed").unwrap();
+        assert_ne!(info.digest(Some(&cache)).unwrap(), digest);
+        assert_eq!(
+            info.digest(Some(&cache)).unwrap(),
+            info.digest(None).unwrap()
+        );
+    }
+
+    #[test]
+    fn crawl_follows_symlinks_and_checks_submodules() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = &dir.path().join("sources");
+        let shared = &dir.path().join("shared");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(shared).unwrap();
+        fs::write(root.join("src/main.rs"), "main").unwrap();
+        fs::write(shared.join("lib.rs"), "lib").unwrap();
+        std::os::unix::fs::symlink(shared, root.join("shared")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("src/loop")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("dangling")).unwrap();
+
+        let info = ProjectInfo::crawl(&[root]).unwrap();
+        let mut files: Vec<_> = info
+            .files
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["shared/lib.rs", "src/main.rs"].map(PathBuf::from));
+
+        fs::write(
+            root.join(".gitmodules"),
+            "[submodule \"empty\"]\n\tpath = deps/empty\n\turl = https://example.com/empty.git\n\
+             [submodule \"absent\"]\n\tpath = deps/absent\n\turl = https://example.com/absent.git\n\
+             [submodule \"ready\"]\n\tpath = deps/ready\n\turl = https://example.com/ready.git\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("deps/empty")).unwrap();
+        fs::create_dir_all(root.join("deps/ready")).unwrap();
+        fs::write(
+            root.join("deps/ready/.git"),
+            "gitdir: ../../.git/modules/ready",
+        )
+        .unwrap();
+
+        let err = ProjectInfo::crawl(&[root]).unwrap_err().to_string();
+        assert!(err.contains(&root.join("deps/empty").display().to_string()));
+        assert!(err.contains(&root.join("deps/absent").display().to_string()));
+        assert!(!err.contains("ready"));
+    }
 }
@@ -1,10 +1,29 @@
 use snafu::Snafu;
+use std::path::PathBuf;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(super)))]
-pub(crate) enum Error {
+pub(super) enum Error {
     #[snafu(display("Failed to walk directory to find project files: {}", source))]
     DirectoryWalk { source: walkdir::Error },
-}
 
-pub(super) type Result<T> = std::result::Result<T, Error>;
+    #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+    FileRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid pattern '{}' in '{}': {}", pattern, path.display(), source))]
+    IgnorePattern {
+        path: PathBuf,
+        pattern: String,
+        source: globset::Error,
+    },
+
+    #[snafu(display(
+        "Source groups contain uninitialized git submodules: {}. Run \
+        `git submodule update --init --recursive` to check them out",
+        paths
+    ))]
+    MissingSubmodules { paths: String },
+}
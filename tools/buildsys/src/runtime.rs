@@ -0,0 +1,46 @@
+/*!
+The container runtimes buildsys can build with. Twoliter reads the runtime from `[tools]` in
+`Twoliter.toml` and passes it on as `BUILDSYS_CONTAINER_RUNTIME`.
+
+*/
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// A container runtime which buildsys can use.
+#[derive(
+    Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, ValueEnum,
+)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerRuntime {
+    /// Docker, through BuildKit in the docker daemon
+    #[default]
+    Docker,
+    /// Podman, through its built-in buildah
+    Podman,
+    /// nerdctl, through a buildkitd it talks to directly
+    Nerdctl,
+}
+
+serde_plain::derive_fromstr_from_deserialize!(ContainerRuntime);
+serde_plain::derive_display_from_serialize!(ContainerRuntime);
+
+impl ContainerRuntime {
+    /// The name of the runtime's CLI, which is found through `PATH` unless a path is pinned.
+    pub fn program(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+            ContainerRuntime::Nerdctl => "nerdctl",
+        }
+    }
+
+    /// The template given to `<runtime> info --format` to print the directory in which the runtime
+    /// keeps images and build caches. nerdctl's `info` has the same fields as docker's.
+    pub fn root_dir_format(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker | ContainerRuntime::Nerdctl => "{{.DockerRootDir}}",
+            ContainerRuntime::Podman => "{{.Store.GraphRoot}}",
+        }
+    }
+}
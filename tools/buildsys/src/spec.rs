@@ -1,61 +1,317 @@
 /*!
-This module provides a very simple parser for RPM spec files.
+This module provides a simple parser for RPM spec files.
 
-It does not attempt to expand macros or perform any meaningful validation. Its
-only purpose is to extract Source and Patch declarations so they can be passed
-to Cargo as files to watch for changes.
+It does not attempt to perform any meaningful validation. Its main purpose is to extract Source and
+Patch declarations so they can be passed to Cargo as files to watch for changes. It also extracts
+the tags of the main package and its sub-packages, such as the Version, License and URL which
+Twoliter reports in SBOMs, along with the order in which `%prep` applies the patches.
+
+Macros are expanded with the definitions in the spec file itself, and with those read from rpm macro
+files such as the SDK's. Other macros are left as they are.
+
+`buildsys lint-spec` reports the macros which are used but never defined, and the patches which are
+never applied, before a slow build fails on them. It neither expands macros nor reads sources, so it
+stays cheap. Macros used in `%{lua:...}` blocks are not macros of the spec file, and those used in a
+`%if` block which tests whether they are defined may be undefined.
+
+The lines which `%prep` and `%build` depend on, such as the preamble and those sections themselves,
+are kept apart from the rest. Together with the sources and patches they make up the key of the
+build stage, which stays the same when only `%install`, `%files` or other later sections change.
 
 */
-pub(crate) mod error;
-use error::Result;
+mod error;
 
-use snafu::ResultExt;
-use std::collections::VecDeque;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use sha2::{Digest, Sha256};
+use snafu::{ResultExt, Snafu};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
 use std::path::{Path, PathBuf};
 
-pub(crate) struct SpecInfo {
-    pub(crate) sources: Vec<PathBuf>,
-    pub(crate) patches: Vec<PathBuf>,
+#[derive(Debug, Snafu)]
+pub struct Error(error::Error);
+type Result<T> = std::result::Result<T, Error>;
+
+pub struct SpecInfo {
+    pub sources: Vec<PathBuf>,
+    pub patches: Vec<PathBuf>,
+    /// The tags of the main package, which are `None` when absent or when they use macros that
+    /// are not defined.
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub license: Option<String>,
+    pub url: Option<String>,
+    pub build_requires: Vec<String>,
+    /// The main package, followed by its sub-packages.
+    pub packages: Vec<Package>,
+    /// The patches which `%prep` applies, in the order it applies them.
+    pub applied_patches: Vec<PathBuf>,
+    /// The lines of the spec file which `%prep` and `%build` depend on.
+    build_stage: Vec<String>,
+}
+
+/// The problems in a spec file which would otherwise fail its build late.
+pub struct SpecLint {
+    /// The macros used as `%{name}` which are neither defined by the spec file, by the macro files
+    /// it was checked with, by rpm itself nor by the SDK.
+    pub undefined_macros: Vec<String>,
+    /// Each declared patch as written, along with its number
+    declared_patches: Vec<(u32, String)>,
+    /// The numbers of the patches which `%prep` applies
+    applied_patches: Vec<u32>,
+}
+
+/// A package built from a spec file, with its dependencies as written, e.g. `glibc >= 2.38`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Package {
+    pub name: String,
+    pub requires: Vec<String>,
+    pub provides: Vec<String>,
+}
+
+/// Macro definitions read from rpm macro files, such as `/usr/lib/rpm/macros`.
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    definitions: HashMap<String, String>,
+}
+
+impl Macros {
+    /// Reads the definitions from each file, with later files taking precedence.
+    pub fn read<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let mut macros = Self::default();
+        for path in paths {
+            let path = path.as_ref();
+            let contents = fs::read_to_string(path).context(error::MacroFileReadSnafu { path })?;
+            macros.definitions.extend(parse_macro_file(&contents));
+        }
+        Ok(macros)
+    }
+}
+
+/// The values of potential interest found in a spec file, before expansion and filtering.
+#[derive(Default)]
+struct Parsed {
+    sources: Vec<String>,
+    /// Each patch along with its number
+    patches: Vec<(u32, String)>,
+    macros: HashMap<String, String>,
+    tags: HashMap<String, String>,
+    build_requires: Vec<String>,
+    packages: Vec<Package>,
+    prep: Vec<String>,
+    /// The macros used outside of `%{lua:...}` blocks and of `%if` blocks which test for them
+    references: BTreeSet<String>,
+    build_stage: Vec<String>,
 }
 
 impl SpecInfo {
-    /// Returns a list of 'Source' and 'Patch' lines found in a spec file.
-    pub(crate) fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let (sources, patches) = Self::parse(path)?;
-        let sources = Self::filter(&sources);
-        let patches = Self::filter(&patches);
-        Ok(Self { sources, patches })
+    /// Returns a list of 'Source' and 'Patch' lines found in a spec file, along with the tags of
+    /// its packages.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_macros(path, &Macros::default())
+    }
+
+    /// Parses a spec file, expanding the macros it uses with the definitions in `macros` along
+    /// with its own.
+    pub fn with_macros<P: AsRef<Path>>(path: P, macros: &Macros) -> Result<Self> {
+        let parsed = Self::parse(path)?;
+        let mut definitions = macros.definitions.clone();
+        definitions.extend(parsed.macros.clone());
+        for tag in [
+            "name", "version", "release", "epoch", "summary", "license", "url",
+        ] {
+            if let Some(value) = parsed.tags.get(tag) {
+                definitions.entry(tag.to_string()).or_insert(value.clone());
+            }
+        }
+        let expand = |value: &str| expand(value, &definitions);
+        let tag = |name: &str| {
+            parsed
+                .tags
+                .get(name)
+                .map(|value| expand(value))
+                .filter(|value| !value.contains('%'))
+        };
+
+        let sources: Vec<String> = parsed.sources.iter().map(|s| expand(s)).collect();
+        let patches: Vec<String> = parsed.patches.iter().map(|(_, p)| expand(p)).collect();
+        let numbers: HashMap<u32, &String> = parsed
+            .patches
+            .iter()
+            .map(|(number, _)| *number)
+            .zip(patches.iter())
+            .collect();
+        let mut declared: Vec<u32> = numbers.keys().copied().collect();
+        declared.sort();
+        let applied_patches = applied_patches(&parsed.prep, &declared)
+            .into_iter()
+            .filter_map(|number| numbers.get(&number))
+            .map(PathBuf::from)
+            .collect();
+
+        let packages = parsed
+            .packages
+            .iter()
+            .map(|package| Package {
+                name: expand(&package.name),
+                requires: package.requires.iter().map(|r| expand(r)).collect(),
+                provides: package.provides.iter().map(|p| expand(p)).collect(),
+            })
+            .collect();
+        Ok(Self {
+            name: tag("name"),
+            version: tag("version"),
+            license: tag("license"),
+            url: tag("url"),
+            build_requires: parsed.build_requires.iter().map(|r| expand(r)).collect(),
+            sources: Self::filter(&sources),
+            patches: Self::filter(&patches),
+            packages,
+            applied_patches,
+            build_stage: parsed.build_stage,
+        })
+    }
+
+    /// Returns a digest of everything the `%prep` and `%build` stages of the package depend on: the
+    /// lines of the spec file outside of later sections, along with the contents of the sources and
+    /// patches in `package_dir`.
+    pub fn build_stage_key<P: AsRef<Path>>(&self, package_dir: P) -> Result<String> {
+        let package_dir = package_dir.as_ref();
+        let mut d = Sha256::new();
+        for line in &self.build_stage {
+            d.update(line);
+            d.update([b'\n']);
+        }
+        for file in self.sources.iter().chain(self.patches.iter()) {
+            let path = package_dir.join(file);
+            d.update(file.to_string_lossy().as_bytes());
+            d.update([0]);
+            match File::open(&path) {
+                Ok(mut f) => {
+                    io::copy(&mut f, &mut d).context(error::SourceFileReadSnafu { path })?;
+                }
+                // External files may not have been fetched yet.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => d.update("missing"),
+                Err(e) => Err(e).context(error::SourceFileReadSnafu { path })?,
+            }
+            d.update([0]);
+        }
+        Ok(hex::encode(d.finalize()))
     }
 
     /// "Parse" a spec file, extracting values of potential interest.
-    fn parse<P: AsRef<Path>>(path: P) -> Result<(Vec<String>, Vec<String>)> {
+    fn parse<P: AsRef<Path>>(path: P) -> Result<Parsed> {
         let path = path.as_ref();
         let f = File::open(path).context(error::SpecFileReadSnafu { path })?;
         let f = BufReader::new(f);
 
-        let mut sources = Vec::new();
-        let mut patches = Vec::new();
+        let mut parsed = Parsed {
+            packages: vec![Package {
+                name: "%{name}".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        // Tags after the first section, such as `%description` or `%package`, belong to
+        // subpackages.
+        let mut in_preamble = true;
+        let mut in_build_stage = true;
+        // The package whose tags are being read, if any.
+        let mut package = Some(0);
+        let mut in_prep = false;
+        // How deeply nested the braces of the `%{lua:...}` block being read are, if in one.
+        let mut lua_depth = 0;
+        // The macros tested by each enclosing `%if`.
+        let mut guards: Vec<BTreeSet<String>> = Vec::new();
 
         for line in f.lines() {
             let line = line.context(error::SpecFileReadSnafu { path })?;
+            let macro_line = strip_lua(&line, &mut lua_depth);
+            parsed.references.extend(
+                references(&macro_line)
+                    .into_iter()
+                    .filter(|name| !guards.iter().any(|guard| guard.contains(name))),
+            );
+            match macro_line.split_whitespace().next() {
+                Some(t) if t.starts_with("%if") => guards.push(tested_macros(&macro_line)),
+                Some("%endif") => {
+                    guards.pop();
+                }
+                _ => (),
+            }
 
             let mut tokens = line.split_whitespace().collect::<VecDeque<&str>>();
+            if let Some(t) = tokens.front() {
+                if LATER_SECTIONS.contains(t) {
+                    in_build_stage = false;
+                } else if BUILD_STAGE_SECTIONS.contains(t) {
+                    in_build_stage = true;
+                }
+                if LATER_SECTIONS.contains(t) || BUILD_STAGE_SECTIONS.contains(t) {
+                    in_prep = *t == "%prep";
+                    package = None;
+                }
+            }
+            if in_build_stage {
+                parsed.build_stage.push(line.clone());
+            }
+            if in_prep {
+                parsed.prep.push(line.clone());
+            }
             if let Some(t) = tokens.pop_front() {
                 if t.starts_with("Source") {
                     if let Some(s) = tokens.pop_front() {
-                        sources.push(s.into());
+                        parsed.sources.push(s.into());
                     }
-                } else if t.starts_with("Patch") {
+                } else if let Some(number) = t.strip_prefix("Patch") {
                     if let Some(p) = tokens.pop_front() {
-                        patches.push(p.into());
+                        // Patches without a number follow the one before them.
+                        let number = number.trim_end_matches(':').parse().unwrap_or_else(|_| {
+                            parsed.patches.last().map_or(0, |(last, _)| last + 1)
+                        });
+                        parsed.patches.push((number, p.into()));
+                    }
+                } else if t == "%global" || t == "%define" {
+                    if let Some(name) = tokens.pop_front() {
+                        let value = Vec::from(tokens).join(" ");
+                        parsed.macros.entry(name.into()).or_insert(value);
+                    }
+                } else if t == "%package" {
+                    in_preamble = false;
+                    let name = match Vec::from(tokens).as_slice() {
+                        ["-n", name, ..] => name.to_string(),
+                        [name, ..] => format!("%{{name}}-{name}"),
+                        [] => continue,
+                    };
+                    parsed.packages.push(Package {
+                        name,
+                        ..Default::default()
+                    });
+                    package = Some(parsed.packages.len() - 1);
+                } else if t.starts_with('%') && !t.starts_with("%{") {
+                    in_preamble = in_preamble && !SECTIONS.contains(&t);
+                } else if let Some(name) = t.strip_suffix(':').filter(|_| package.is_some()) {
+                    // Qualifiers such as `Requires(post):` don't change what is required.
+                    let name = name.split('(').next().unwrap_or_default().to_lowercase();
+                    let value = Vec::from(tokens).join(" ");
+                    match (name.as_str(), package) {
+                        ("buildrequires", _) => parsed.build_requires.extend(dependencies(&value)),
+                        ("requires", Some(i)) => {
+                            parsed.packages[i].requires.extend(dependencies(&value))
+                        }
+                        ("provides", Some(i)) => {
+                            parsed.packages[i].provides.extend(dependencies(&value))
+                        }
+                        _ if in_preamble => {
+                            parsed.tags.entry(name).or_insert(value);
+                        }
+                        _ => (),
                     }
                 }
             }
         }
 
-        Ok((sources, patches))
+        Ok(parsed)
     }
 
     /// Emitting a non-existent file for `rerun-if-changed` will cause Cargo
@@ -72,3 +328,725 @@ impl SpecInfo {
             .collect()
     }
 }
+
+impl SpecLint {
+    /// Checks a spec file, which may use the macros defined in `macros` along with its own.
+    pub fn new<P: AsRef<Path>>(path: P, macros: &Macros) -> Result<Self> {
+        let parsed = SpecInfo::parse(path)?;
+        let undefined_macros = parsed
+            .references
+            .iter()
+            .filter(|name| !parsed.macros.contains_key(*name))
+            .filter(|name| !macros.definitions.contains_key(*name))
+            .filter(|name| !BUILTIN_MACROS.contains(&name.as_str()))
+            .filter(|name| !is_sdk_macro(name))
+            .filter(|name| !is_numbered(name, "SOURCE") && !is_numbered(name, "PATCH"))
+            .cloned()
+            .collect();
+        let mut declared: Vec<u32> = parsed.patches.iter().map(|(number, _)| *number).collect();
+        declared.sort();
+        Ok(Self {
+            undefined_macros,
+            applied_patches: applied_patches(&parsed.prep, &declared),
+            declared_patches: parsed.patches,
+        })
+    }
+
+    /// Returns the patches which are declared but never applied by `%prep`, followed by the
+    /// `.patch` files in `package_dir` which are never declared. A macro in the name of a declared
+    /// patch matches any part of a file's name.
+    pub fn unused_patches<P: AsRef<Path>>(&self, package_dir: P) -> Result<Vec<PathBuf>> {
+        let package_dir = package_dir.as_ref();
+        let mut unused: Vec<PathBuf> = self
+            .declared_patches
+            .iter()
+            .filter(|(number, _)| !self.applied_patches.contains(number))
+            .map(|(_, patch)| PathBuf::from(patch))
+            .collect();
+        let mut undeclared = Vec::new();
+        let entries =
+            fs::read_dir(package_dir).context(error::DirectoryListSnafu { path: package_dir })?;
+        for entry in entries {
+            let entry = entry.context(error::DirectoryListSnafu { path: package_dir })?;
+            let file = entry.file_name().to_string_lossy().to_string();
+            if file.ends_with(".patch")
+                && !self
+                    .declared_patches
+                    .iter()
+                    .any(|(_, patch)| matches_declared(patch, &file))
+            {
+                undeclared.push(PathBuf::from(file));
+            }
+        }
+        undeclared.sort();
+        unused.extend(undeclared);
+        Ok(unused)
+    }
+}
+
+/// The sections which end the preamble of the main package.
+const SECTIONS: &[&str] = &[
+    "%package",
+    "%description",
+    "%prep",
+    "%build",
+    "%install",
+    "%check",
+    "%files",
+    "%changelog",
+];
+
+/// The sections which the `%prep` and `%build` stages depend on. Sub-packages are included because
+/// their preambles may declare build dependencies.
+const BUILD_STAGE_SECTIONS: &[&str] = &[
+    "%package",
+    "%prep",
+    "%generate_buildrequires",
+    "%conf",
+    "%build",
+];
+
+/// The sections which only matter after the `%build` stage, when the package is installed and
+/// packaged, along with the descriptions of packages.
+const LATER_SECTIONS: &[&str] = &[
+    "%description",
+    "%install",
+    "%check",
+    "%clean",
+    "%files",
+    "%changelog",
+    "%pre",
+    "%post",
+    "%preun",
+    "%postun",
+    "%pretrans",
+    "%posttrans",
+    "%preuntrans",
+    "%postuntrans",
+    "%verifyscript",
+    "%triggerprein",
+    "%triggerin",
+    "%triggerun",
+    "%triggerpostun",
+    "%filetriggerin",
+    "%filetriggerun",
+    "%filetriggerpostun",
+    "%transfiletriggerin",
+    "%transfiletriggerun",
+    "%transfiletriggerpostun",
+];
+
+/// Macros which rpm defines for every spec file, or which are built into it.
+const BUILTIN_MACROS: &[&str] = &[
+    "S",
+    "P",
+    "arch",
+    "autopatch",
+    "autosetup",
+    "basename",
+    "bcond",
+    "bcond_with",
+    "bcond_without",
+    "build_cflags",
+    "build_cxxflags",
+    "build_ldflags",
+    "buildroot",
+    "buildsubdir",
+    "defined",
+    "dirname",
+    "dist",
+    "dnl",
+    "dump",
+    "echo",
+    "epoch",
+    "error",
+    "exists",
+    "expand",
+    "getconfdir",
+    "getenv",
+    "gsub",
+    "len",
+    "license",
+    "lower",
+    "lua",
+    "name",
+    "nil",
+    "optflags",
+    "patch",
+    "quote",
+    "release",
+    "rep",
+    "reverse",
+    "setup",
+    "shrink",
+    "sub",
+    "suffix",
+    "summary",
+    "trace",
+    "u2p",
+    "uncompress",
+    "undefined",
+    "upper",
+    "url",
+    "url2path",
+    "verbose",
+    "version",
+    "warn",
+    "with",
+    "without",
+];
+
+/// Macros which rpm's macro files, the Bottlerocket SDK or buildsys define for every build, so that
+/// spec files may use them even when `buildsys lint-spec` isn't given the macro files.
+const SDK_MACROS: &[&str] = &[
+    "__cc",
+    "__cp",
+    "__cxx",
+    "__install",
+    "__make",
+    "__mkdir_p",
+    "__rm",
+    "__sed",
+    "__tar",
+    "_bindir",
+    "_builddir",
+    "_datadir",
+    "_docdir",
+    "_includedir",
+    "_infodir",
+    "_lib",
+    "_libdir",
+    "_libexecdir",
+    "_licensedir",
+    "_localstatedir",
+    "_mandir",
+    "_package_secrets_dir",
+    "_persistent_build_dir",
+    "_prefix",
+    "_rpmdir",
+    "_sbindir",
+    "_sharedstatedir",
+    "_smp_build_ncpus",
+    "_smp_mflags",
+    "_sourcedir",
+    "_specdir",
+    "_srcrpmdir",
+    "_sysconfdir",
+    "_target_cpu",
+    "_target_os",
+    "_target_platform",
+    "_tmppath",
+    "_topdir",
+    "_unitdir",
+    "_usr",
+    "_var",
+    "build_ldflags",
+    "configure",
+    "debug_package",
+    "make_build",
+    "make_install",
+    "optflags",
+];
+
+/// The prefixes of the macros which the SDK defines for cross-compiling, such as `_cross_libdir`
+/// and `cross_configure`.
+const SDK_MACRO_PREFIXES: &[&str] = &["_cross_", "cross_", "set_cross_"];
+
+fn is_sdk_macro(name: &str) -> bool {
+    SDK_MACROS.contains(&name)
+        || SDK_MACRO_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// Whether `file` is the patch declared as `patch`, in which a macro such as `%{version}` matches
+/// any text.
+fn matches_declared(patch: &str, file: &str) -> bool {
+    let mut parts = Vec::new();
+    let mut rest = patch;
+    while let Some(start) = rest.find("%{") {
+        parts.push(&rest[..start]);
+        rest = rest[start..].split_once('}').map_or("", |(_, after)| after);
+    }
+    parts.push(rest);
+    let Some((first, others)) = parts.split_first() else {
+        return false;
+    };
+    let Some(mut remaining) = file.strip_prefix(first) else {
+        return false;
+    };
+    let Some((last, middle)) = others.split_last() else {
+        return remaining.is_empty();
+    };
+    for part in middle {
+        match remaining.find(part) {
+            Some(i) => remaining = &remaining[i + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.ends_with(last)
+}
+
+/// Removes the `%{lua:...}` blocks from `line`, which may start on an earlier line or end on a
+/// later one. `depth` tracks how deeply nested the braces of an unfinished block are.
+fn strip_lua(line: &str, depth: &mut usize) -> String {
+    let mut kept = String::new();
+    let mut rest = line;
+    loop {
+        if *depth > 0 {
+            let end = rest.char_indices().find_map(|(i, c)| {
+                match c {
+                    '{' => *depth += 1,
+                    '}' => *depth -= 1,
+                    _ => (),
+                }
+                (*depth == 0).then_some(i)
+            });
+            match end {
+                Some(i) => rest = &rest[i + 1..],
+                None => return kept,
+            }
+        }
+        match rest.find("%{lua:") {
+            Some(start) => {
+                kept.push_str(&rest[..start]);
+                rest = &rest[start + "%{lua:".len()..];
+                *depth = 1;
+            }
+            None => {
+                kept.push_str(rest);
+                return kept;
+            }
+        }
+    }
+}
+
+/// The macros whose definition a `%if` line tests, as `%{?name}`, `%{!?name}`, `%{defined name}`
+/// or `%{undefined name}`.
+fn tested_macros(line: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for prefix in ["%{?", "%{!?", "%{defined ", "%{undefined "] {
+        let mut rest = line;
+        while let Some(start) = rest.find(prefix) {
+            rest = &rest[start + prefix.len()..];
+            let name: String = rest
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect();
+            if !name.is_empty() {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+/// Whether `name` is `prefix` followed by a number, like the `SOURCE1` macro.
+fn is_numbered(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix)
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The names of the macros used as `%{name}` or `%{name:arg}` in `line`. Conditional uses such as
+/// `%{?name}` are not included, since they allow the macro to be undefined.
+fn references(line: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('%') {
+        rest = &rest[start + 1..];
+        if let Some(escaped) = rest.strip_prefix('%') {
+            rest = escaped;
+            continue;
+        }
+        let Some(body) = rest.strip_prefix('{') else {
+            continue;
+        };
+        let name: String = body
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+        if !name.is_empty() && body[name.len()..].starts_with(['}', ':', ' ']) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Splits the value of a `Requires:` or similar tag into its dependencies, keeping versions with
+/// their names.
+fn dependencies(value: &str) -> Vec<String> {
+    const OPERATORS: &[&str] = &["<", "<=", "=", ">=", ">"];
+    let mut dependencies = Vec::new();
+    for part in value.split(',') {
+        let tokens: Vec<&str> = part.split_whitespace().collect();
+        let mut i = 0;
+        while i < tokens.len() {
+            if i + 2 < tokens.len() && OPERATORS.contains(&tokens[i + 1]) {
+                dependencies.push(tokens[i..i + 3].join(" "));
+                i += 3;
+            } else {
+                dependencies.push(tokens[i].to_string());
+                i += 1;
+            }
+        }
+    }
+    dependencies
+}
+
+/// The numbers of the patches which the lines of `%prep` apply, in the order they apply them.
+/// `declared` holds the numbers of every declared patch, in ascending order.
+fn applied_patches(prep: &[String], declared: &[u32]) -> Vec<u32> {
+    let mut applied = Vec::new();
+    for line in prep {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = tokens.split_first() else {
+            continue;
+        };
+        let mut numbers = Vec::new();
+        match *command {
+            "%autosetup" if !args.contains(&"-N") => numbers.extend(declared),
+            "%autopatch" => {
+                let option = |flag| {
+                    args.iter()
+                        .position(|arg| *arg == flag)
+                        .and_then(|i| args.get(i + 1))
+                        .and_then(|n| n.parse::<u32>().ok())
+                };
+                let listed: Vec<u32> = args
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i == 0 || !["-m", "-M", "-p"].contains(&args[i - 1]))
+                    .filter_map(|(_, arg)| arg.parse().ok())
+                    .collect();
+                let (min, max) = (option("-m"), option("-M"));
+                numbers.extend(declared.iter().copied().filter(|n| {
+                    (listed.is_empty() || listed.contains(n))
+                        && !matches!(min, Some(min) if *n < min)
+                        && !matches!(max, Some(max) if *n > max)
+                }));
+            }
+            command if command.starts_with("%patch") => {
+                if let Ok(number) = command["%patch".len()..].parse() {
+                    numbers.push(number);
+                } else {
+                    let mut args = args.iter();
+                    while let Some(arg) = args.next() {
+                        let number = match arg.strip_prefix("-P") {
+                            Some("") => args.next().and_then(|n| n.parse::<u32>().ok()),
+                            Some(number) => number.parse().ok(),
+                            None => arg.parse().ok(),
+                        };
+                        numbers.extend(number);
+                    }
+                    if numbers.is_empty() && command == "%patch" {
+                        numbers.push(0);
+                    }
+                }
+            }
+            _ => (),
+        }
+        for number in numbers {
+            if !applied.contains(&number) {
+                applied.push(number);
+            }
+        }
+    }
+    applied
+}
+
+/// Reads the definitions in an rpm macro file, where each line like `%name body` defines a macro,
+/// and lines ending with `\` continue on the next.
+fn parse_macro_file(contents: &str) -> HashMap<String, String> {
+    let mut definitions = HashMap::new();
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        let mut line = line.to_string();
+        while line.ends_with('\\') {
+            line.pop();
+            match lines.next() {
+                Some(next) => {
+                    line.push('\n');
+                    line.push_str(next);
+                }
+                None => break,
+            }
+        }
+        let Some(definition) = line.strip_prefix('%') else {
+            continue;
+        };
+        let name: String = definition
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+        if name.is_empty() {
+            continue;
+        }
+        let body = definition[name.len()..].trim_start();
+        // Parametric macros declare their options in parentheses.
+        let body = match body.strip_prefix('(') {
+            Some(options) => options.split_once(')').map_or("", |(_, body)| body),
+            None => body,
+        };
+        definitions.insert(name, body.trim().to_string());
+    }
+    definitions
+}
+
+/// Expands the `%{name}`, `%{?name}`, `%{?name:value}` and `%{!?name:value}` macros in `value`
+/// which are defined in `macros`, leaving others as they are.
+fn expand(value: &str, macros: &HashMap<String, String>) -> String {
+    let mut value = value.to_string();
+    // Macros may refer to other macros, but not endlessly.
+    for _ in 0..8 {
+        let expanded = expand_once(&value, macros);
+        if expanded == value {
+            break;
+        }
+        value = expanded;
+    }
+    value
+}
+
+fn expand_once(value: &str, macros: &HashMap<String, String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("%{") {
+        expanded.push_str(&rest[..start]);
+        // Find the matching brace, since conditional values may use macros themselves.
+        let mut depth = 0;
+        let end = rest[start..].char_indices().find_map(|(i, c)| {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => (),
+            }
+            (depth == 0 && c == '}').then_some(start + i)
+        });
+        let Some(end) = end else {
+            break;
+        };
+        let body = &rest[start + 2..end];
+        let (negated, conditional, body) = match body.strip_prefix("!?") {
+            Some(body) => (true, true, body),
+            None => match body.strip_prefix('?') {
+                Some(body) => (false, true, body),
+                None => (false, false, body),
+            },
+        };
+        let (name, alternative) = match body.split_once(':') {
+            Some((name, alternative)) => (name, Some(alternative)),
+            None => (body, None),
+        };
+        let definition = macros.get(name);
+        match (conditional, negated, definition, alternative) {
+            (true, false, Some(_), Some(alternative)) => expanded.push_str(alternative),
+            (true, false, Some(definition), None) => expanded.push_str(definition),
+            (true, true, None, Some(alternative)) => expanded.push_str(alternative),
+            (true, _, _, _) => (),
+            (false, _, Some(definition), None) => expanded.push_str(definition),
+            (false, _, _, _) => expanded.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tags() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("pkg.spec");
+        std::fs::write(
+            &path,
+            "%global gover 1.22.1\n%global rpmver %{gover}\n\nName: %{_cross_os}pkg\n\
+            Version: %{rpmver}\nRelease: 1%{?dist}\nLicense: Apache-2.0 OR MIT\n\
+            URL: %{_cross_url}\nSource0: pkg-%{gover}.tar.gz\nSource1: pkg.service\n\n\
+            %package devel\nLicense: GPL-2.0-only\n",
+        )
+        .unwrap();
+        let info = SpecInfo::new(&path).unwrap();
+        assert_eq!(info.version.as_deref(), Some("1.22.1"));
+        assert_eq!(info.license.as_deref(), Some("Apache-2.0 OR MIT"));
+        assert_eq!(info.url, None);
+        assert_eq!(
+            info.sources,
+            vec![
+                PathBuf::from("pkg-1.22.1.tar.gz"),
+                PathBuf::from("pkg.service")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_packages_and_patches() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("pkg.spec");
+        std::fs::write(
+            &path,
+            "Name: %{_cross_os}pkg\nVersion: 1.0\nBuildRequires: %{_cross_os}glibc-devel, make\n\
+            Requires: %{_cross_os}glibc >= 2.38 %{name}-libs\nSource0: %{name}-%{version}.tar.gz\n\
+            Patch0002: 0002-second.patch\nPatch0001: 0001-first.patch\nPatch0003: 0003-unused.patch\n\
+            \n%description\n%{summary}\n\n%package libs\nProvides: libpkg.so.1\n\n\
+            %package -n %{_cross_os}pkg-tools\nRequires(post): %{_cross_os}systemd\n\n\
+            %prep\n%setup -q\n%patch -P 1 -p1\n%patch2 -p1\n\n%build\n%{cross_configure}\n\
+            %make_build %{?_smp_mflags} %{!?_with_foo:--without-foo}\n",
+        )
+        .unwrap();
+        std::fs::write(temp_dir.path().join("0004-undeclared.patch"), "").unwrap();
+
+        let macros_path = temp_dir.path().join("macros");
+        std::fs::write(
+            &macros_path,
+            "# comment\n%_cross_os bottlerocket-\n%_smp_mflags -j4\n%cross_wrap(n:) \\\n  echo\n",
+        )
+        .unwrap();
+        let macros = Macros::read(&[&macros_path]).unwrap();
+        let info = SpecInfo::with_macros(&path, &macros).unwrap();
+        assert_eq!(info.name.as_deref(), Some("bottlerocket-pkg"));
+        assert_eq!(info.build_requires, ["bottlerocket-glibc-devel", "make"]);
+        assert_eq!(
+            info.packages,
+            vec![
+                Package {
+                    name: "bottlerocket-pkg".to_string(),
+                    requires: vec![
+                        "bottlerocket-glibc >= 2.38".to_string(),
+                        "bottlerocket-pkg-libs".to_string()
+                    ],
+                    provides: Vec::new(),
+                },
+                Package {
+                    name: "bottlerocket-pkg-libs".to_string(),
+                    requires: Vec::new(),
+                    provides: vec!["libpkg.so.1".to_string()],
+                },
+                Package {
+                    name: "bottlerocket-pkg-tools".to_string(),
+                    requires: vec!["bottlerocket-systemd".to_string()],
+                    provides: Vec::new(),
+                },
+            ]
+        );
+        assert_eq!(
+            info.sources,
+            vec![PathBuf::from("bottlerocket-pkg-1.0.tar.gz")]
+        );
+        assert_eq!(
+            info.applied_patches,
+            vec![
+                PathBuf::from("0001-first.patch"),
+                PathBuf::from("0002-second.patch")
+            ]
+        );
+
+        let lint = SpecLint::new(&path, &macros).unwrap();
+        assert_eq!(
+            lint.unused_patches(temp_dir.path()).unwrap(),
+            vec![
+                PathBuf::from("0003-unused.patch"),
+                PathBuf::from("0004-undeclared.patch")
+            ]
+        );
+        assert!(lint.undefined_macros.is_empty());
+    }
+
+    #[test]
+    fn test_lint() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("pkg.spec");
+        std::fs::write(
+            &path,
+            "%global upstream 2.0\nName: pkg\nVersion: %{upstream}\n\
+            Patch1: %{name}-%{version}-fix.patch\n\
+            %{lua:\n  local v = rpm.expand(\"%{unknown_in_lua}\")\n  if v then\n    print(v)\n  end\n}\n\
+            %if %{defined with_extra}\nBuildRequires: %{with_extra}\n%else\n%endif\n\
+            %if 0%{?extra_flags:1}\n%global flags %{extra_flags}\n%endif\n\
+            %prep\n%autosetup -p1\n\n%build\n%{set_cross_build_flags}\n\
+            make %{_smp_mflags} %{_cross_libdir} %{missing} %{?maybe}\n",
+        )
+        .unwrap();
+        std::fs::write(temp_dir.path().join("pkg-2.0-fix.patch"), "").unwrap();
+        std::fs::write(temp_dir.path().join("other.patch"), "").unwrap();
+
+        let lint = SpecLint::new(&path, &Macros::default()).unwrap();
+        assert_eq!(lint.undefined_macros, ["missing"]);
+        assert_eq!(
+            lint.unused_patches(temp_dir.path()).unwrap(),
+            vec![PathBuf::from("other.patch")]
+        );
+    }
+
+    #[test]
+    fn test_lua_and_patterns() {
+        let mut depth = 0;
+        assert_eq!(strip_lua("a %{lua: print(\"{}\") } b", &mut depth), "a  b");
+        assert_eq!(strip_lua("c %{lua:", &mut depth), "c ");
+        assert_eq!(depth, 1);
+        assert_eq!(strip_lua("  if x then { } end", &mut depth), "");
+        assert_eq!(strip_lua("} d", &mut depth), " d");
+        assert_eq!(depth, 0);
+
+        assert!(matches_declared("a.patch", "a.patch"));
+        assert!(!matches_declared("a.patch", "b.patch"));
+        assert!(matches_declared(
+            "%{name}-%{version}.patch",
+            "pkg-1.0.patch"
+        ));
+        assert!(!matches_declared("%{name}-fix.patch", "pkg-other.patch"));
+    }
+
+    #[test]
+    fn test_autopatch() {
+        let prep = |line: &str| vec![line.to_string()];
+        assert_eq!(
+            applied_patches(&prep("%autosetup -p1"), &[1, 2, 3]),
+            [1, 2, 3]
+        );
+        assert!(applied_patches(&prep("%autosetup -N"), &[1, 2]).is_empty());
+        assert_eq!(
+            applied_patches(&prep("%autopatch -p1 -m 2"), &[1, 2, 3]),
+            [2, 3]
+        );
+        assert_eq!(
+            applied_patches(&prep("%autopatch -p1 3 1"), &[1, 2, 3]),
+            [1, 3]
+        );
+    }
+
+    #[test]
+    fn test_build_stage_key() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("pkg.spec");
+        let key = |spec: &str| {
+            std::fs::write(&path, spec).unwrap();
+            SpecInfo::new(&path)
+                .unwrap()
+                .build_stage_key(temp_dir.path())
+                .unwrap()
+        };
+        let spec = "Name: pkg\nPatch0001: 0001-fix.patch\n\n%description\nA package.\n\n\
+            %prep\n%autosetup -p1\n\n%build\nmake\n\n%install\nmake install\n\n\
+            %files\n/usr/bin/pkg\n";
+        let original = key(spec);
+        assert_eq!(
+            original,
+            key(&spec.replace("make install", "make install-strip"))
+        );
+        assert_eq!(
+            original,
+            key(&spec.replace("A package.", "A useful package."))
+        );
+        assert_ne!(original, key(&spec.replace("make\n", "make V=1\n")));
+        assert_ne!(
+            original,
+            key(&spec.replace("Name: pkg", "Name: pkg\nBuildRequires: gcc"))
+        );
+
+        std::fs::write(temp_dir.path().join("0001-fix.patch"), "--- a\n+++ b\n").unwrap();
+        assert_ne!(original, key(spec));
+    }
+}
@@ -4,9 +4,16 @@ use std::path::PathBuf;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(super)))]
-pub(crate) enum Error {
+pub(super) enum Error {
     #[snafu(display("Failed to read spec file '{}': {}", path.display(), source))]
     SpecFileRead { path: PathBuf, source: io::Error },
-}
 
-pub(super) type Result<T> = std::result::Result<T, Error>;
+    #[snafu(display("Failed to read source file '{}': {}", path.display(), source))]
+    SourceFileRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to read macro file '{}': {}", path.display(), source))]
+    MacroFileRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to list directory '{}': {}", path.display(), source))]
+    DirectoryList { path: PathBuf, source: io::Error },
+}
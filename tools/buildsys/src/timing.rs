@@ -0,0 +1,48 @@
+/*!
+The timing buildsys records for each build it runs when `BUILDSYS_TIMINGS_DIR` is set, so that
+`twoliter build variant --timings` can report which packages dominate a variant build. Each build
+writes its own `<arch>-<kind>-<name>.json` file, since Cargo runs builds in parallel.
+
+*/
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Whether a build ran, or was skipped because its inputs were unchanged.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cache {
+    Hit,
+    Miss,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Timing {
+    pub name: String,
+    pub kind: String,
+    pub arch: String,
+    /// When the build started, in milliseconds since the Unix epoch
+    pub start_ms: u128,
+    /// How long the build waited for a slot to run in, when the number of builds is limited
+    #[serde(default)]
+    pub queue_ms: u128,
+    /// When the build finished, in milliseconds since the Unix epoch
+    pub end_ms: u128,
+    /// `None` if the build failed before its checkpoint was checked
+    pub cache: Option<Cache>,
+    pub succeeded: bool,
+}
+
+impl Timing {
+    /// The name of the file the timing is recorded in.
+    pub fn file_name(&self) -> String {
+        format!("{}-{}-{}.json", self.arch, self.kind, self.name)
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn unix_ms(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default()
+}
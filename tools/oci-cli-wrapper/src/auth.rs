@@ -0,0 +1,175 @@
+//! Registry authentication through docker credential helpers.
+//!
+//! Both docker and crane read the docker client configuration, so we authenticate by writing a
+//! configuration directory which layers `credHelpers` entries over the user's own configuration and
+//! pointing the image tool at it with `DOCKER_CONFIG`.
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt};
+use tempfile::TempDir;
+
+use crate::{error, Result};
+
+const CONFIG_FILE: &str = "config.json";
+
+/// The keys of the docker configuration which supply registry credentials.
+const CREDENTIAL_KEYS: &[&str] = &["auths", "credHelpers", "credsStore"];
+
+/// The prefix docker uses to find a credential helper program on the search path.
+const HELPER_PREFIX: &str = "docker-credential-";
+
+/// How to obtain credentials for a registry.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(untagged)]
+pub enum CredentialHelper {
+    /// The name of a docker credential helper, such as `ecr-login` for the
+    /// `docker-credential-ecr-login` program.
+    Name(String),
+    /// A command which implements the docker credential helper protocol. It is run with the
+    /// helper action (e.g. `get`) appended to its arguments and the registry on stdin.
+    Command { command: Vec<String> },
+}
+
+/// A docker client configuration directory which directs the image tool to use credential helpers
+/// for particular registries. The directory is removed when this is dropped.
+#[derive(Debug)]
+pub struct RegistryAuth {
+    dir: TempDir,
+}
+
+impl RegistryAuth {
+    /// Creates a configuration which uses the given credential helper for each registry host,
+    /// keeping everything else from the user's existing docker configuration.
+    pub fn new<I>(helpers: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (String, CredentialHelper)>,
+    {
+        Self::layered(|config, dir| {
+            let mut cred_helpers = BTreeMap::new();
+            for (i, (registry, helper)) in helpers.into_iter().enumerate() {
+                let name = match helper {
+                    CredentialHelper::Name(name) => name,
+                    CredentialHelper::Command { command } => {
+                        let name = format!("twoliter-{i}");
+                        write_shim(&dir.join(format!("{HELPER_PREFIX}{name}")), &command)?;
+                        name
+                    }
+                };
+                log::debug!("Using credential helper '{name}' for registry '{registry}'");
+                cred_helpers.insert(registry, serde_json::Value::String(name));
+            }
+            let helpers_entry = config
+                .entry("credHelpers")
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+            if let serde_json::Value::Object(existing) = helpers_entry {
+                existing.extend(cred_helpers);
+            }
+            Ok(())
+        })
+    }
+
+    /// Creates a configuration without any registry credentials, so that requests are made
+    /// anonymously.
+    pub(crate) fn anonymous() -> Result<Self> {
+        Self::layered(|config, _| {
+            for key in CREDENTIAL_KEYS {
+                config.remove(*key);
+            }
+            Ok(())
+        })
+    }
+
+    /// Writes the user's docker configuration, as changed by `modify`, to a new directory.
+    fn layered<F>(modify: F) -> Result<Self>
+    where
+        F: FnOnce(&mut serde_json::Map<String, serde_json::Value>, &Path) -> Result<()>,
+    {
+        let dir = TempDir::new().context(error::AuthTempSnafu)?;
+        let user_dir = user_config_dir();
+        let mut config = match user_dir.as_ref().map(|dir| dir.join(CONFIG_FILE)) {
+            Some(path) if path.exists() => read_config(&path)?,
+            _ => serde_json::Map::new(),
+        };
+        if let Some(user_dir) = user_dir.as_ref().filter(|dir| dir.is_dir()) {
+            link_user_config(user_dir, dir.path())?;
+        }
+        modify(&mut config, dir.path())?;
+
+        let path = dir.path().join(CONFIG_FILE);
+        let bytes = serde_json::to_vec_pretty(&serde_json::Value::Object(config))
+            .context(error::DockerConfigSerializeSnafu)?;
+        fs::write(&path, bytes).context(error::DockerConfigWriteSnafu { path })?;
+        Ok(Self { dir })
+    }
+
+    /// The environment the image tool needs in order to use this configuration.
+    pub(crate) fn env(&self) -> Vec<(&'static str, OsString)> {
+        let mut path = OsString::from(self.dir.path());
+        if let Some(existing) = env::var_os("PATH") {
+            path.push(":");
+            path.push(existing);
+        }
+        vec![
+            ("DOCKER_CONFIG", self.dir.path().as_os_str().to_owned()),
+            ("PATH", path),
+        ]
+    }
+}
+
+/// The directory holding the user's docker configuration, if it can be determined.
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".docker")))
+}
+
+fn read_config(path: &Path) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let bytes = fs::read(path).context(error::DockerConfigReadSnafu { path })?;
+    serde_json::from_slice(&bytes).context(error::DockerConfigParseSnafu { path })
+}
+
+/// Links everything other than the config file from the user's configuration directory, such as
+/// docker contexts and cli plugins, so that the image tool behaves as it otherwise would.
+fn link_user_config(user_dir: &Path, dir: &Path) -> Result<()> {
+    let entries =
+        fs::read_dir(user_dir).context(error::DockerConfigReadSnafu { path: user_dir })?;
+    for entry in entries {
+        let entry = entry.context(error::DockerConfigReadSnafu { path: user_dir })?;
+        if entry.file_name() == CONFIG_FILE {
+            continue;
+        }
+        let link = dir.join(entry.file_name());
+        std::os::unix::fs::symlink(entry.path(), &link)
+            .context(error::DockerConfigWriteSnafu { path: link })?;
+    }
+    Ok(())
+}
+
+/// Writes an executable script which forwards its arguments and stdin to `command`, so that docker
+/// can find it on the search path as a credential helper.
+fn write_shim(path: &Path, command: &[String]) -> Result<()> {
+    let program = command
+        .first()
+        .context(error::EmptyCredentialCommandSnafu)?;
+    let args = command
+        .iter()
+        .skip(1)
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let script = format!("#!/bin/sh\nexec {} {args} \"$@\"\n", shell_quote(program));
+    fs::write(path, script).context(error::DockerConfigWriteSnafu { path })?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o700))
+        .context(error::DockerConfigWriteSnafu { path })?;
+    Ok(())
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
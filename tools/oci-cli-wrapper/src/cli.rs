@@ -1,15 +1,42 @@
 use snafu::{ensure, ResultExt};
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
 
 use crate::{error, Result};
 
-#[derive(Debug)]
+/// The OCI distribution spec error codes which are used to decide how to handle a failed request.
+/// crane prints them as registries return them, e.g. `MANIFEST_UNKNOWN: manifest unknown`, and
+/// docker lowercased and with spaces, e.g. `manifest unknown: manifest unknown`.
+const REGISTRY_ERROR_CODES: &[&str] = &[
+    "BLOB_UNKNOWN",
+    "DENIED",
+    "MANIFEST_UNKNOWN",
+    "NAME_UNKNOWN",
+    "TOOMANYREQUESTS",
+    "UNAUTHORIZED",
+];
+
+/// How crane (`unexpected status code 404 Not Found`) and docker (`unexpected HTTP status: 404`)
+/// report the HTTP status of a registry response which had no error body.
+const STATUS_MARKERS: &[&str] = &["status code ", "HTTP status: "];
+
+#[derive(Debug, Clone)]
 pub(crate) struct CommandLine {
     pub(crate) path: PathBuf,
+    pub(crate) env: Vec<(String, OsString)>,
 }
 
 impl CommandLine {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            env: Vec::new(),
+        }
+    }
+
     pub(crate) async fn output(&self, args: &[&str], error_msg: String) -> Result<Vec<u8>> {
         log::debug!(
             "Executing '{}' with args [{}]",
@@ -21,15 +48,19 @@ impl CommandLine {
         );
         let output = Command::new(&self.path)
             .args(args)
+            .envs(self.env.iter().cloned())
             .output()
             .await
             .context(error::CommandFailedSnafu { message: error_msg })?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
         ensure!(
             output.status.success(),
             error::OperationFailedSnafu {
-                message: String::from_utf8_lossy(&output.stderr),
+                message: stderr.as_ref(),
                 program: self.path.clone(),
-                args: args.iter().map(|x| x.to_string()).collect::<Vec<_>>()
+                args: args.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+                status: parse_status(&stderr),
+                code: parse_code(&stderr),
             }
         );
         log::debug!(
@@ -48,25 +79,125 @@ impl CommandLine {
                 .collect::<Vec<_>>()
                 .join(", ")
         );
-        let status = Command::new(&self.path)
+        // stderr is logged as the tool writes it, and also kept so that the reason for a failure
+        // can be reported, and inspected to decide whether the operation can be retried.
+        let mut child = Command::new(&self.path)
             .args(args)
+            .envs(self.env.iter().cloned())
+            .stderr(Stdio::piped())
             .spawn()
-            .context(error::CommandFailedSnafu {
-                message: error_msg.clone(),
-            })?
-            .wait()
-            .await
             .context(error::CommandFailedSnafu {
                 message: error_msg.clone(),
             })?;
+        let mut stderr = String::new();
+        if let Some(pipe) = child.stderr.take() {
+            let mut lines = BufReader::new(pipe).lines();
+            while let Some(line) = lines.next_line().await.context(error::CommandFailedSnafu {
+                message: error_msg.clone(),
+            })? {
+                log::info!("{}: {line}", self.path.display());
+                stderr.push_str(&line);
+                stderr.push('\n');
+            }
+        }
+        let status = child.wait().await.context(error::CommandFailedSnafu {
+            message: error_msg.clone(),
+        })?;
         ensure!(
             status.success(),
             error::OperationFailedSnafu {
-                message: error_msg.clone(),
+                message: format!("{}: {}", error_msg, stderr.trim()),
                 program: self.path.clone(),
-                args: args.iter().map(|x| x.to_string()).collect::<Vec<_>>()
+                args: args.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+                status: parse_status(&stderr),
+                code: parse_code(&stderr),
             }
         );
         Ok(())
     }
 }
+
+/// The HTTP status of the registry response reported in the stderr of a failed command.
+fn parse_status(stderr: &str) -> Option<u16> {
+    STATUS_MARKERS.iter().find_map(|marker| {
+        stderr.match_indices(marker).find_map(|(index, _)| {
+            let rest = &stderr[index + marker.len()..];
+            let digits = rest.get(..3)?;
+            let ends = !rest[3..].starts_with(|c: char| c.is_ascii_digit());
+            if digits.bytes().all(|b| b.is_ascii_digit()) && ends {
+                digits.parse().ok()
+            } else {
+                None
+            }
+        })
+    })
+}
+
+/// The registry error code reported in the stderr of a failed command. A code is only recognized
+/// where the tools print one: at the start of an error, and followed by its message.
+fn parse_code(stderr: &str) -> Option<String> {
+    stderr
+        .lines()
+        .flat_map(|line| line.split("; "))
+        .find_map(|error| {
+            let parts: Vec<&str> = error.split(": ").collect();
+            parts[..parts.len() - 1].iter().find_map(|part| {
+                let part = part.trim();
+                REGISTRY_ERROR_CODES
+                    .iter()
+                    .find(|code| part == **code || part == code.to_lowercase().replace('_', " "))
+                    .map(|code| code.to_string())
+            })
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIGEST: &str = "sha256:4040429401403404aaaa4290000000000000000000000000000000000000429";
+
+    #[test]
+    fn parses_status_from_tool_output() {
+        assert_eq!(
+            parse_status(
+                "GET https://a.com/v2/b/manifests/c: unexpected status code 404 Not Found"
+            ),
+            Some(404)
+        );
+        assert_eq!(
+            parse_status("received unexpected HTTP status: 429 Too Many Requests"),
+            Some(429)
+        );
+        assert_eq!(
+            parse_status(&format!("failed to pull a.com/b@{DIGEST}")),
+            None
+        );
+        assert_eq!(parse_status("status code 4041"), None);
+    }
+
+    #[test]
+    fn parses_code_from_tool_output() {
+        assert_eq!(
+            parse_code("Error: fetching manifest a.com/b:c: GET https://a.com/v2/b/manifests/c: MANIFEST_UNKNOWN: manifest unknown; map[Tag:c]"),
+            Some("MANIFEST_UNKNOWN".to_string())
+        );
+        assert_eq!(
+            parse_code("Error response from daemon: toomanyrequests: You have reached your pull rate limit."),
+            Some("TOOMANYREQUESTS".to_string())
+        );
+        assert_eq!(
+            parse_code("Error response from daemon: unauthorized: authentication required"),
+            Some("UNAUTHORIZED".to_string())
+        );
+        // Neither digests, repository names nor other errors are mistaken for codes.
+        assert_eq!(
+            parse_code(&format!("Error: pulling a.com/not-found@{DIGEST}: denied")),
+            None
+        );
+        assert_eq!(
+            parse_code("Error: open /tmp/x: permission denied: no such file"),
+            None
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::ffi::OsStr;
 use std::fs::File;
 use std::path::Path;
 
@@ -10,7 +11,7 @@ use crate::{
     cli::CommandLine, error, ConfigView, DockerArchitecture, ImageToolImpl, ImageView, Result,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CraneCLI {
     pub(crate) cli: CommandLine,
 }
@@ -37,6 +38,33 @@ impl ImageToolImpl for CraneCLI {
             .await
     }
 
+    async fn get_digest(&self, uri: &str) -> Result<String> {
+        let bytes = self
+            .cli
+            .output(
+                &["digest", uri],
+                format!("failed to fetch digest of resource at {}", uri),
+            )
+            .await?;
+        Ok(String::from_utf8_lossy(&bytes).trim().to_string())
+    }
+
+    async fn list_tags(&self, repository: &str) -> Result<Vec<String>> {
+        let bytes = self
+            .cli
+            .output(
+                &["ls", repository],
+                format!("failed to list tags of {}", repository),
+            )
+            .await?;
+        Ok(String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(ToString::to_string)
+            .collect())
+    }
+
     async fn get_config(&self, uri: &str) -> Result<ConfigView> {
         let bytes = self
             .cli
@@ -47,7 +75,7 @@ impl ImageToolImpl for CraneCLI {
             .await?;
         let image_view: ImageView =
             serde_json::from_slice(bytes.as_slice()).context(error::ConfigDeserializeSnafu)?;
-        Ok(image_view.config)
+        Ok(image_view.into())
     }
 
     async fn push_oci_archive(&self, path: &Path, uri: &str) -> Result<()> {
@@ -67,6 +95,15 @@ impl ImageToolImpl for CraneCLI {
             .await
     }
 
+    async fn copy_image(&self, from: &str, to: &str) -> Result<()> {
+        self.cli
+            .spawn(
+                &["copy", from, to],
+                format!("failed to copy image {} to {}", from, to),
+            )
+            .await
+    }
+
     async fn push_multi_platform_manifest(
         &self,
         platform_images: Vec<(DockerArchitecture, String)>,
@@ -91,4 +128,47 @@ impl ImageToolImpl for CraneCLI {
 
         Ok(())
     }
+
+    async fn push_oci_layout(&self, dir: &Path, uri: &str) -> Result<()> {
+        self.cli
+            .spawn(
+                &["push", &dir.to_string_lossy(), uri],
+                format!("failed to push image {}", uri),
+            )
+            .await
+    }
+
+    async fn append_to_index(
+        &self,
+        index_uri: &str,
+        exists: bool,
+        manifest_uri: &str,
+    ) -> Result<()> {
+        let mut args = vec!["index", "append"];
+        if exists {
+            args.push(index_uri);
+        }
+        args.extend_from_slice(&["-m", manifest_uri, "-t", index_uri]);
+        self.cli
+            .output(
+                &args,
+                format!("could not add {} to index {}", manifest_uri, index_uri),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_blob(&self, uri: &str) -> Result<Vec<u8>> {
+        self.cli
+            .output(&["blob", uri], format!("failed to fetch blob {}", uri))
+            .await
+    }
+
+    fn set_env(&mut self, key: &str, value: &OsStr) {
+        self.cli.env.push((key.to_string(), value.to_owned()));
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ImageToolImpl>> {
+        Some(Box::new(self.clone()))
+    }
 }
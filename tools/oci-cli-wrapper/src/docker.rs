@@ -1,3 +1,4 @@
+use std::ffi::OsStr;
 use std::path::Path;
 
 use async_trait::async_trait;
@@ -8,13 +9,26 @@ use tar::Archive;
 use tempfile::NamedTempFile;
 
 use crate::cli::CommandLine;
+use crate::registry::RegistryClient;
 use crate::{error, ConfigView, DockerArchitecture, ImageToolImpl, Result};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DockerCLI {
     pub(crate) cli: CommandLine,
 }
 
+impl DockerCLI {
+    /// A registry client with the same environment as the docker CLI, and so the same docker
+    /// configuration, for requests the docker CLI can't make.
+    fn registry_client(&self) -> Result<RegistryClient> {
+        let mut client = RegistryClient::new()?;
+        for (key, value) in self.cli.env.iter() {
+            client.set_env(key, value);
+        }
+        Ok(client)
+    }
+}
+
 #[async_trait]
 impl ImageToolImpl for DockerCLI {
     async fn pull_oci_image(&self, path: &Path, uri: &str) -> Result<()> {
@@ -51,18 +65,22 @@ impl ImageToolImpl for DockerCLI {
             .await
     }
 
+    async fn list_tags(&self, repository: &str) -> Result<Vec<String>> {
+        // The docker CLI has no way to query a registry for the tags of a repository, so ask the
+        // registry directly.
+        self.registry_client()?.list_tags(repository).await
+    }
+
+    async fn get_digest(&self, uri: &str) -> Result<String> {
+        // `docker manifest inspect` prints the manifest again rather than as the registry serves
+        // it, so its digest can't be computed from that.
+        self.registry_client()?.get_digest(uri).await
+    }
+
     async fn get_config(&self, uri: &str) -> Result<ConfigView> {
-        self.cli
-            .spawn(&["pull", uri], format!("failed to pull image from {}", uri))
-            .await?;
-        let bytes = self
-            .cli
-            .output(
-                &["image", "inspect", uri, "--format", "{{ json .Config }}"],
-                format!("failed to fetch image config from {}", uri),
-            )
-            .await?;
-        serde_json::from_slice(bytes.as_slice()).context(error::ConfigDeserializeSnafu)
+        // `docker image inspect` only sees images in the daemon, and pulling a whole image to
+        // read its config is slow for large ones, so fetch just the config from the registry.
+        self.registry_client()?.get_config(uri).await
     }
 
     async fn push_oci_archive(&self, path: &Path, uri: &str) -> Result<()> {
@@ -95,6 +113,35 @@ impl ImageToolImpl for DockerCLI {
         Ok(())
     }
 
+    async fn copy_image(&self, from: &str, to: &str) -> Result<()> {
+        // Unlike `docker pull` and `docker push`, which only copy the platform of the host,
+        // `imagetools create` copies the manifest list and every image in it between registries.
+        self.cli
+            .spawn(
+                &["buildx", "imagetools", "create", "--tag", to, from],
+                format!("failed to copy image '{from}' to '{to}'"),
+            )
+            .await
+    }
+
+    async fn push_oci_layout(&self, _dir: &Path, _uri: &str) -> Result<()> {
+        // `docker push` only pushes images from the daemon, which can't hold artifacts.
+        error::ReferrersUnsupportedSnafu { tool: "docker" }.fail()
+    }
+
+    async fn append_to_index(
+        &self,
+        _index_uri: &str,
+        _exists: bool,
+        _manifest_uri: &str,
+    ) -> Result<()> {
+        error::ReferrersUnsupportedSnafu { tool: "docker" }.fail()
+    }
+
+    async fn get_blob(&self, _uri: &str) -> Result<Vec<u8>> {
+        error::ReferrersUnsupportedSnafu { tool: "docker" }.fail()
+    }
+
     async fn push_multi_platform_manifest(
         &self,
         platform_images: Vec<(DockerArchitecture, String)>,
@@ -145,4 +192,12 @@ impl ImageToolImpl for DockerCLI {
 
         Ok(())
     }
+
+    fn set_env(&mut self, key: &str, value: &OsStr) {
+        self.cli.env.push((key.to_string(), value.to_owned()));
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ImageToolImpl>> {
+        Some(Box::new(self.clone()))
+    }
 }
@@ -0,0 +1,368 @@
+//! Reads images from local OCI image layout directories rather than a registry.
+//!
+//! Each repository is a layout of its own, as `skopeo copy` and `docker buildx build` write them:
+//! `oci-layout:<dir>/<name>:<tag>` is looked up in the layout at `<dir>/<name>` by the
+//! `org.opencontainers.image.ref.name` annotation in its `index.json`, which holds only the tag,
+//! and `oci-layout:<dir>/<name>@<digest>` is read straight from its blobs. Every blob read is
+//! checked against its digest. A relative `<dir>` is resolved from the root given to the image
+//! tool, so that uris recorded in lock files do not depend on where the project is checked out.
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt, ResultExt};
+
+use crate::{error, referrers, ConfigView, ImageView, Result};
+
+/// The prefix which marks an image uri as referring to a local OCI image layout.
+pub const OCI_LAYOUT_SCHEME: &str = "oci-layout:";
+
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Reference {
+    Tag(String),
+    Digest(String),
+}
+
+/// An image in a local OCI image layout.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct LayoutReference {
+    uri: String,
+    /// The layout of the image's repository, `<dir>/<name>`
+    dir: PathBuf,
+    reference: Reference,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    digest: String,
+    size: u64,
+    #[serde(default, skip_serializing)]
+    annotations: std::collections::HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Index {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Manifest {
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+impl LayoutReference {
+    /// Parses `uri` if it refers to an OCI image layout, returning `None` for registry uris.
+    /// Relative layout directories are resolved from `root`.
+    pub(crate) fn parse(uri: &str, root: &Path) -> Option<Result<Self>> {
+        let rest = uri.strip_prefix(OCI_LAYOUT_SCHEME)?;
+        Some(Self::parse_path(uri, rest, root))
+    }
+
+    fn parse_path(uri: &str, rest: &str, root: &Path) -> Result<Self> {
+        let invalid = || error::InvalidLayoutReferenceSnafu { uri };
+        let (dir, image) = rest.rsplit_once('/').context(invalid())?;
+        let (name, reference) = match image.split_once('@') {
+            Some((name, digest)) => (name, Reference::Digest(digest.to_string())),
+            None => {
+                let (name, tag) = image.split_once(':').context(invalid())?;
+                (name, Reference::Tag(tag.to_string()))
+            }
+        };
+        ensure!(!name.is_empty(), invalid());
+        Ok(Self {
+            uri: uri.to_string(),
+            dir: root.join(dir).join(name),
+            reference,
+        })
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.dir.join("blobs").join(digest.replace(':', "/"))
+    }
+
+    /// Finds the descriptor of the referenced manifest or manifest list in the layout's index.
+    fn descriptor(&self) -> Result<Descriptor> {
+        let path = self.dir.join(INDEX_FILE);
+        let index: Index = read_json(&path)?;
+        let tag = match &self.reference {
+            Reference::Tag(tag) => tag,
+            Reference::Digest(digest) => {
+                let size = fs::metadata(self.blob_path(digest))
+                    .context(error::LayoutReadSnafu {
+                        path: self.blob_path(digest),
+                    })?
+                    .len();
+                return Ok(Descriptor {
+                    media_type: None,
+                    digest: digest.clone(),
+                    size,
+                    annotations: Default::default(),
+                });
+            }
+        };
+        index
+            .manifests
+            .into_iter()
+            .find(|descriptor| descriptor.annotations.get(REF_NAME_ANNOTATION) == Some(tag))
+            .context(error::LayoutImageNotFoundSnafu {
+                uri: self.uri.clone(),
+            })
+    }
+
+    /// Reads the blob with the given digest, checking it against the digest.
+    fn read_blob(&self, digest: &str) -> Result<Vec<u8>> {
+        let path = self.blob_path(digest);
+        let bytes = fs::read(&path).context(error::LayoutReadSnafu { path: &path })?;
+        let actual = referrers::digest(&bytes);
+        ensure!(
+            actual == digest,
+            error::DigestMismatchSnafu {
+                uri: path.display().to_string(),
+                expected: digest,
+                actual
+            }
+        );
+        Ok(bytes)
+    }
+
+    /// Reads the blob with the given digest as JSON, checking it against the digest.
+    fn read_blob_json<T>(&self, digest: &str) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let bytes = self.read_blob(digest)?;
+        serde_json::from_slice(&bytes).context(error::LayoutParseSnafu {
+            path: self.blob_path(digest),
+        })
+    }
+
+    /// Copies the blob with the given digest to `to`, checking it against the digest as it is
+    /// copied, so that layers need not be held in memory.
+    fn copy_blob(&self, digest: &str, to: &Path) -> Result<()> {
+        let from = self.blob_path(digest);
+        let mut reader = File::open(&from).context(error::LayoutReadSnafu { path: &from })?;
+        let mut writer = File::create(to).context(error::LayoutWriteSnafu { path: to })?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = reader
+                .read(&mut buf)
+                .context(error::LayoutReadSnafu { path: &from })?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            writer
+                .write_all(&buf[..read])
+                .context(error::LayoutWriteSnafu { path: to })?;
+        }
+        let actual = referrers::finish_digest(hasher);
+        if actual != digest {
+            // The copy is removed so that it is not mistaken for the blob later.
+            let _ = fs::remove_file(to);
+            return error::DigestMismatchSnafu {
+                uri: from.display().to_string(),
+                expected: digest,
+                actual,
+            }
+            .fail();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_manifest(&self) -> Result<Vec<u8>> {
+        self.read_blob(&self.descriptor()?.digest)
+    }
+
+    pub(crate) fn get_config(&self) -> Result<ConfigView> {
+        let manifest: Manifest = self.read_blob_json(&self.descriptor()?.digest)?;
+        let image: ImageView = self.read_blob_json(&manifest.config.digest)?;
+        Ok(image.into())
+    }
+
+    /// Copies the referenced single-architecture image into a new OCI layout at `path`, in the
+    /// same form as a pull from a registry.
+    pub(crate) fn pull_oci_image(&self, path: &Path) -> Result<()> {
+        let descriptor = self.descriptor()?;
+        let manifest: Manifest = self.read_blob_json(&descriptor.digest)?;
+        let blobs = std::iter::once(&descriptor)
+            .chain(std::iter::once(&manifest.config))
+            .chain(manifest.layers.iter());
+        for blob in blobs {
+            let to = path.join("blobs").join(blob.digest.replace(':', "/"));
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent).context(error::LayoutWriteSnafu { path: parent })?;
+            }
+            self.copy_blob(&blob.digest, &to)?;
+        }
+
+        let layout = serde_json::json!({ "imageLayoutVersion": "1.0.0" });
+        let index = serde_json::json!({ "schemaVersion": 2, "manifests": [descriptor] });
+        for (file, value) in [(OCI_LAYOUT_FILE, layout), (INDEX_FILE, index)] {
+            let to = path.join(file);
+            let bytes = serde_json::to_vec(&value).context(error::LayoutSerializeSnafu)?;
+            fs::write(&to, bytes).context(error::LayoutWriteSnafu { path: to })?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the tags of the repository `oci-layout:<dir>/<name>`, resolving a relative `<dir>` from
+/// `root`, and returns `None` for registry repositories.
+pub(crate) fn list_tags(repository: &str, root: &Path) -> Option<Result<Vec<String>>> {
+    let dir = repository.strip_prefix(OCI_LAYOUT_SCHEME)?;
+    Some(layout_tags(&root.join(dir)))
+}
+
+fn layout_tags(dir: &Path) -> Result<Vec<String>> {
+    let index: Index = read_json(&dir.join(INDEX_FILE))?;
+    Ok(index
+        .manifests
+        .iter()
+        .filter_map(|descriptor| descriptor.annotations.get(REF_NAME_ANNOTATION))
+        .map(ToString::to_string)
+        .collect())
+}
+
+fn read_json<T>(path: &Path) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let bytes = fs::read(path).context(error::LayoutReadSnafu { path })?;
+    serde_json::from_slice(&bytes).context(error::LayoutParseSnafu { path })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Writes `bytes` as a blob of the layout at `dir`, returning its descriptor.
+    fn write_blob(dir: &Path, bytes: &[u8]) -> serde_json::Value {
+        let digest = referrers::digest(bytes);
+        let path = dir.join("blobs").join(digest.replace(':', "/"));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+        serde_json::json!({ "digest": digest, "size": bytes.len() })
+    }
+
+    /// Writes a layout holding an image tagged `v1` to `<dir>/core-kit`, returning the digest of its
+    /// manifest.
+    fn write_layout(dir: &Path) -> String {
+        let layout = dir.join("core-kit");
+        let config = write_blob(
+            &layout,
+            br#"{"config":{"Labels":{"org.opencontainers.image.title":"core-kit"}}}"#,
+        );
+        let layer = write_blob(&layout, b"layer");
+        let manifest =
+            serde_json::json!({ "schemaVersion": 2, "config": config, "layers": [layer] });
+        let mut descriptor = write_blob(&layout, manifest.to_string().as_bytes());
+        descriptor["annotations"] = serde_json::json!({ REF_NAME_ANNOTATION: "v1" });
+        let index = serde_json::json!({ "schemaVersion": 2, "manifests": [descriptor.clone()] });
+        fs::write(layout.join(INDEX_FILE), index.to_string()).unwrap();
+        descriptor["digest"].as_str().unwrap().to_string()
+    }
+
+    fn reference(uri: &str) -> LayoutReference {
+        LayoutReference::parse(uri, Path::new("/"))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_images_by_tag_and_digest() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().display();
+        let digest = write_layout(temp_dir.path());
+
+        let tagged = reference(&format!("oci-layout:{dir}/core-kit:v1"));
+        let manifest = tagged.get_manifest().unwrap();
+        assert_eq!(referrers::digest(&manifest), digest);
+        assert_eq!(
+            tagged.get_config().unwrap().labels["org.opencontainers.image.title"],
+            "core-kit"
+        );
+        let by_digest = reference(&format!("oci-layout:{dir}/core-kit@{digest}"));
+        assert_eq!(by_digest.get_manifest().unwrap(), manifest);
+
+        assert_eq!(
+            list_tags(&format!("oci-layout:{dir}/core-kit"), Path::new("/"))
+                .unwrap()
+                .unwrap(),
+            vec!["v1"]
+        );
+        assert!(reference(&format!("oci-layout:{dir}/core-kit:v2"))
+            .get_manifest()
+            .is_err());
+        assert!(
+            LayoutReference::parse(&format!("oci-layout:{dir}/core-kit"), Path::new("/"))
+                .unwrap()
+                .is_err()
+        );
+
+        // Relative layouts are found from the root, wherever the process runs from.
+        let relative = LayoutReference::parse("oci-layout:./core-kit:v1", temp_dir.path());
+        assert_eq!(relative.unwrap().unwrap().get_manifest().unwrap(), manifest);
+    }
+
+    #[test]
+    fn pulled_image_round_trips() {
+        let temp_dir = TempDir::new().unwrap();
+        let digest = write_layout(temp_dir.path());
+        let pulled = temp_dir.path().join("pulled");
+        reference(&format!(
+            "oci-layout:{}/core-kit:v1",
+            temp_dir.path().display()
+        ))
+        .pull_oci_image(&pulled)
+        .unwrap();
+
+        // The pulled layout is itself a layout the image can be read from by digest.
+        let pulled_image = LayoutReference {
+            uri: "pulled".to_string(),
+            dir: pulled,
+            reference: Reference::Digest(digest.clone()),
+        };
+        let manifest = pulled_image.get_manifest().unwrap();
+        assert_eq!(referrers::digest(&manifest), digest);
+        assert_eq!(
+            pulled_image.get_config().unwrap().labels["org.opencontainers.image.title"],
+            "core-kit"
+        );
+        let index: Index = read_json(&pulled_image.dir.join(INDEX_FILE)).unwrap();
+        assert_eq!(index.manifests[0].digest, digest);
+    }
+
+    #[test]
+    fn rejects_corrupt_blobs() {
+        let temp_dir = TempDir::new().unwrap();
+        write_layout(temp_dir.path());
+        let layer = referrers::digest(b"layer");
+        let layer_path = temp_dir
+            .path()
+            .join("core-kit/blobs")
+            .join(layer.replace(':', "/"));
+        fs::write(&layer_path, "corrupt").unwrap();
+
+        let pulled = temp_dir.path().join("pulled");
+        let err = reference(&format!(
+            "oci-layout:{}/core-kit:v1",
+            temp_dir.path().display()
+        ))
+        .pull_oci_image(&pulled)
+        .unwrap_err();
+        assert!(matches!(err, error::Error::DigestMismatch { .. }), "{err}");
+        assert!(!pulled.join("blobs").join(layer.replace(':', "/")).exists());
+    }
+}
@@ -1,77 +1,180 @@
 //! ImageTool enablement library implements a standardized way of calling commandline container image
 //! tools for interacting primarily with kit images in a container registry.
 //!
-//! Current two tools are supported:
+//! Three tools are supported:
 //! * crane, gcrane, krane
 //!     Crane provides a more direct interaction with the container registry,
 //!     allowing us to query image information in the registry without having to pull the full image to
 //!     disk. It also does not require a daemon to operate and has optimizations for pulling large images to disk
 //! * docker
 //!     Docker can perform all interactions we need with several caveats that make it less efficient than
-//!     crane. The image needs to be pulled locally in order for docker to inspect the manifest and extract
-//!     metadata. In addition, in order to operate with OCI image format, the containerd-snapshotter
+//!     crane. Images need to be pulled into the daemon to be saved to disk, and image configs, tags and
+//!     digests, which the docker CLI can't query, are fetched with the native client using docker's
+//!     credentials. In addition, in order to operate with OCI image format, the containerd-snapshotter
 //!     feature has to be enabled in the docker daemon
+//! * native
+//!     A registry client built into this library, which talks to registries over HTTP without any
+//!     external program. It is used when neither crane nor docker is installed
+//!
+//! Image uris beginning with `oci-layout:` are read directly from a local OCI image layout directory
+//! without using either tool.
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fmt::{Display, Formatter};
-use std::{collections::HashMap, env, path::Path};
+use std::{collections::HashMap, env, path::Path, path::PathBuf};
 
 use async_trait::async_trait;
 use cli::CommandLine;
 use crane::CraneCLI;
 use docker::DockerCLI;
 use olpc_cjson::CanonicalFormatter;
+use registry::RegistryClient;
 use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
+use tempfile::TempDir;
 use which::which;
 
+pub use auth::{CredentialHelper, RegistryAuth};
+pub use layout::OCI_LAYOUT_SCHEME;
+pub use mirror::Mirror;
+pub use referrers::{Referrer, ReferrerKind};
+pub use throttle::RegistryLimits;
+
+use layout::LayoutReference;
+use throttle::Throttle;
+
+mod auth;
 mod cli;
 mod crane;
 mod docker;
+mod layout;
+mod mirror;
+mod referrers;
+mod registry;
+mod throttle;
+
+/// The registry which image names without a registry host, such as `alpine`, are on.
+const DOCKER_HUB: &str = "docker.io";
+
+/// The label of a kit's image config which holds its base64 encoded metadata.
+pub const KIT_METADATA_LABEL: &str = "dev.bottlerocket.kit.v1";
 
 #[derive(Debug)]
 pub struct ImageTool {
     image_tool_impl: Box<dyn ImageToolImpl>,
+    registry_auth: Option<RegistryAuth>,
+    repository_auth: Vec<RepositoryAuth>,
+    anonymous: Option<AnonymousFallback>,
+    throttle: Throttle,
+    mirrors: Vec<Mirror>,
+    retries: std::sync::Mutex<Vec<Retry>>,
+    layout_root: PathBuf,
+    /// Held while a referrers index is updated, so that artifacts attached at once through this
+    /// image tool don't replace each other in the index
+    referrers_index_lock: tokio::sync::Mutex<()>,
+}
+
+/// How many times an artifact is added to a referrers index before giving up, when other
+/// publishers keep replacing the index without it.
+const REFERRERS_INDEX_ATTEMPTS: u32 = 5;
+
+/// A request which failed and was made again, as listed by [`ImageTool::retries`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Retry {
+    /// The uri the failed request was made for
+    pub uri: String,
+    /// Why the request was made again
+    #[serde(flatten)]
+    pub reason: RetryReason,
+    /// The error the failed request returned
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum RetryReason {
+    /// The registry refused the request because too many had been made, so it was made again after
+    /// waiting
+    #[serde(rename_all = "kebab-case")]
+    RateLimited { wait_secs: u64 },
+    /// The request was not authorized, so it was made again anonymously
+    Anonymous,
+    /// The request failed through a mirror, so it was made again to the registry
+    Mirror { mirror: String },
+}
+
+/// A copy of the image tool without registry credentials, used to retry requests to public
+/// registries which were rejected as unauthorized.
+#[derive(Debug)]
+struct AnonymousFallback {
+    image_tool_impl: Box<dyn ImageToolImpl>,
+    registries: Vec<String>,
+    _auth: RegistryAuth,
+}
+
+/// A copy of the image tool which authenticates with its own credential helper, used for the
+/// images in one repository. Docker configurations key credential helpers by registry host, so
+/// repositories on the same host which need different credentials each get their own.
+#[derive(Debug)]
+struct RepositoryAuth {
+    repository: String,
+    image_tool_impl: Box<dyn ImageToolImpl>,
+    _auth: RegistryAuth,
 }
 
 impl ImageTool {
     /// Uses the container tool specified by the given tool name.
     ///
-    /// The specified tool must be present in the unix search path.
+    /// The specified tool must be present in the unix search path, unless it is the built-in
+    /// `native` registry client.
     fn from_tool_name(tool_name: &str) -> Result<Self> {
+        if tool_name == "native" {
+            return Ok(Self::new(Box::new(RegistryClient::new()?)));
+        }
+        Self::from_tool_path(tool_name, Path::new(tool_name))
+    }
+
+    /// Uses the container tool at `path`, which is driven as the tool named `tool_name`:
+    /// `docker`, `crane`, `gcrane` or `krane`. This allows a specific copy of a tool to be used
+    /// regardless of what is first in the unix search path.
+    pub fn from_tool_path(tool_name: &str, path: &Path) -> Result<Self> {
+        let name = path.display().to_string();
         let image_tool_impl: Box<dyn ImageToolImpl> = match tool_name {
             "docker" => Box::new(DockerCLI {
-                cli: CommandLine {
-                    path: which("docker").context(error::NotFoundSnafu { name: "docker" })?,
-                },
+                cli: CommandLine::new(which(path).context(error::NotFoundSnafu { name })?),
             }),
-            tool @ ("crane" | "gcrane" | "krane") => Box::new(CraneCLI {
-                cli: CommandLine {
-                    path: which(tool).context(error::NotFoundSnafu { name: tool })?,
-                },
+            "crane" | "gcrane" | "krane" => Box::new(CraneCLI {
+                cli: CommandLine::new(which(path).context(error::NotFoundSnafu { name })?),
             }),
             _ => return error::UnsupportedSnafu { name: tool_name }.fail(),
         };
 
-        Ok(Self { image_tool_impl })
+        Ok(Self::new(image_tool_impl))
     }
 
     /// Auto-selects the container tool based on unix search path.
     ///
-    /// Uses `crane` if available, falling back to `docker` otherwise.
+    /// Uses `crane` if available, falling back to `docker`, and then to the built-in registry
+    /// client when neither is installed.
     fn from_unix_search_path() -> Result<Self> {
         let crane = which("krane").or(which("gcrane")).or(which("crane"));
         let image_tool_impl: Box<dyn ImageToolImpl> = if let Ok(path) = crane {
             Box::new(CraneCLI {
-                cli: CommandLine { path },
+                cli: CommandLine::new(path),
             })
-        } else {
+        } else if let Ok(path) = which("docker") {
             Box::new(DockerCLI {
-                cli: CommandLine {
-                    path: which("docker").context(error::NoneFoundSnafu)?,
-                },
+                cli: CommandLine::new(path),
             })
+        } else {
+            log::debug!(
+                "Neither crane nor docker is installed, using the built-in registry client"
+            );
+            Box::new(RegistryClient::new()?)
         };
 
-        Ok(Self { image_tool_impl })
+        Ok(Self::new(image_tool_impl))
     }
 
     /// Auto-select the container tool to use by environment variable
@@ -81,33 +184,337 @@ impl ImageTool {
     /// Valid values are:
     /// * docker
     /// * crane | gcrane | krane
+    /// * native
+    ///
+    /// If TWOLITER_KIT_IMAGE_TOOL_PATH is also set, the tool at that path is used, which is how
+    /// Twoliter passes on the tool pinned in `Twoliter.toml`.
     ///
-    /// Otherwise, searches $PATH, using `crane` if available and falling back to docker otherwise.
+    /// Otherwise, searches $PATH, using `crane` if available, then docker, and otherwise the
+    /// built-in registry client.
     pub fn from_environment() -> Result<Self> {
         if let Ok(name) = env::var("TWOLITER_KIT_IMAGE_TOOL") {
-            Self::from_tool_name(&name)
+            match env::var_os("TWOLITER_KIT_IMAGE_TOOL_PATH") {
+                Some(path) => Self::from_tool_path(&name, Path::new(&path)),
+                None => Self::from_tool_name(&name),
+            }
         } else {
             Self::from_unix_search_path()
         }
     }
 
     pub fn new(image_tool_impl: Box<dyn ImageToolImpl>) -> Self {
-        Self { image_tool_impl }
+        Self {
+            image_tool_impl,
+            registry_auth: None,
+            repository_auth: Vec::new(),
+            anonymous: None,
+            throttle: Throttle::default(),
+            mirrors: Vec::new(),
+            retries: Default::default(),
+            layout_root: PathBuf::new(),
+            referrers_index_lock: Default::default(),
+        }
+    }
+
+    /// Authenticate to registries with the credential helpers in `registry_auth` rather than
+    /// relying only on the user's docker configuration.
+    pub fn with_registry_auth(mut self, registry_auth: RegistryAuth) -> Self {
+        for (key, value) in registry_auth.env() {
+            self.image_tool_impl.set_env(key, &value);
+        }
+        self.registry_auth = Some(registry_auth);
+        self
+    }
+
+    /// Authenticate to each repository, such as `public.ecr.aws/bottlerocket`, with its own
+    /// credential helper, so that repositories on the same registry host may use different
+    /// credentials. Images in other repositories use the user's docker configuration. When the
+    /// tool cannot be copied, the helpers are keyed by registry host instead, and the last helper
+    /// given for a host is used for all of its repositories.
+    pub fn with_repository_auth(
+        mut self,
+        helpers: Vec<(String, CredentialHelper)>,
+    ) -> Result<Self> {
+        let host = |repository: &str| repository.split('/').next().unwrap_or_default().to_string();
+        if self.image_tool_impl.try_clone().is_none() {
+            log::debug!("The image tool can't be copied, keying credential helpers by host");
+            let helpers = helpers
+                .into_iter()
+                .map(|(repository, helper)| (host(&repository), helper));
+            return Ok(self.with_registry_auth(RegistryAuth::new(helpers)?));
+        }
+        for (repository, helper) in helpers {
+            let Some(mut image_tool_impl) = self.image_tool_impl.try_clone() else {
+                continue;
+            };
+            let auth = RegistryAuth::new([(host(&repository), helper)])?;
+            for (key, value) in auth.env() {
+                image_tool_impl.set_env(key, &value);
+            }
+            self.repository_auth.push(RepositoryAuth {
+                repository,
+                image_tool_impl,
+                _auth: auth,
+            });
+        }
+        Ok(self)
+    }
+
+    /// The tool to make requests for `uri` with: the copy holding the credentials of the most
+    /// specific repository which contains it, if any.
+    fn tool(&self, uri: &str) -> &dyn ImageToolImpl {
+        let image_repository = repository(uri);
+        self.repository_auth
+            .iter()
+            .filter(|auth| {
+                image_repository == auth.repository
+                    || image_repository
+                        .strip_prefix(auth.repository.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(|auth| auth.repository.len())
+            .map(|auth| auth.image_tool_impl.as_ref())
+            .unwrap_or(self.image_tool_impl.as_ref())
+    }
+
+    /// Set environment variables, such as proxy settings, for every invocation of the tool. Call
+    /// this before `with_anonymous_fallback` and `with_repository_auth` so that retries and
+    /// repositories with their own credentials use them too.
+    pub fn with_env<'a>(mut self, env: impl IntoIterator<Item = (&'a str, String)>) -> Self {
+        for (key, value) in env {
+            self.image_tool_impl.set_env(key, OsStr::new(&value));
+        }
+        self
+    }
+
+    /// Retry pulls from the given public registry hosts anonymously when they are rejected as
+    /// unauthorized, such as when the credentials for the registry have expired. This has no effect
+    /// if the image tool cannot be copied.
+    pub fn with_anonymous_fallback(mut self, registries: Vec<String>) -> Result<Self> {
+        if let Some(mut image_tool_impl) = self.image_tool_impl.try_clone() {
+            let auth = RegistryAuth::anonymous()?;
+            for (key, value) in auth.env() {
+                image_tool_impl.set_env(key, &value);
+            }
+            self.anonymous = Some(AnonymousFallback {
+                image_tool_impl,
+                registries,
+                _auth: auth,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Resolve the relative directories of `oci-layout:` uris from `root` rather than the current
+    /// directory.
+    pub fn with_layout_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.layout_root = root.into();
+        self
+    }
+
+    /// Keep the requests made to each registry host within its `limits`. Requests which a registry
+    /// refuses because too many have been made are retried once it allows, whether or not it has
+    /// limits.
+    pub fn with_registry_limits(mut self, limits: HashMap<String, RegistryLimits>) -> Self {
+        self.throttle = Throttle::new(limits);
+        self
+    }
+
+    /// Pull images referenced by digest through the given mirrors of their registries, such as
+    /// pull-through caches, rather than from the registries themselves. Tags are still resolved by
+    /// the registries, and what is pulled through a mirror is checked against its digest.
+    pub fn with_mirrors(mut self, mirrors: Vec<Mirror>) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Runs `operation` with the uri to make a request for `uri` to: a mirror of its registry, if
+    /// it has one, and otherwise, or if that fails, `uri` itself.
+    async fn mirrored<T, F, Fut>(&self, uri: &str, operation: F) -> Result<T>
+    where
+        F: Fn(String) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        if let Some(target) = self.mirrors.iter().find_map(|mirror| mirror.redirect(uri)) {
+            match operation(target.clone()).await {
+                Ok(value) => return Ok(value),
+                // A mirror serving other content than the registry did for the same digest has
+                // been tampered with or is broken, which has to be looked into rather than
+                // worked around.
+                Err(e) if e.is_digest_mismatch() => {
+                    return Err(error::Error::MirrorMismatch {
+                        uri: uri.to_string(),
+                        mirror: target,
+                        source: Box::new(e),
+                    });
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Unable to pull '{uri}' through mirror '{target}', pulling it from its \
+                        registry instead: {e}"
+                    );
+                    self.record_retry(uri, RetryReason::Mirror { mirror: target }, &e);
+                }
+            }
+        }
+        operation(uri.to_string()).await
+    }
+
+    /// Runs `operation`, a request for `uri`, within the limits of the registry it is made to.
+    async fn throttled<T, F, Fut>(&self, uri: &str, operation: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let registry = registry_host(uri);
+        let on_retry = |e: &error::Error, wait: std::time::Duration| {
+            let wait_secs = wait.as_secs();
+            self.record_retry(uri, RetryReason::RateLimited { wait_secs }, e)
+        };
+        self.throttle.run(registry, operation, on_retry).await
+    }
+
+    /// Runs `operation`, a copy from `from` to `to`, within the limits of both registries. The
+    /// registries are always entered in the same order, so that copies in opposite directions
+    /// can't each hold what the other is waiting for.
+    async fn throttled_copy<T, F, Fut>(&self, from: &str, to: &str, operation: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        if registry_host(from) == registry_host(to) {
+            return self.throttled(to, operation).await;
+        }
+        let (first, second) = if registry_host(from) < registry_host(to) {
+            (from, to)
+        } else {
+            (to, from)
+        };
+        self.throttled(first, || self.throttled(second, &operation))
+            .await
+    }
+
+    fn record_retry(&self, uri: &str, reason: RetryReason, error: &error::Error) {
+        self.retries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(Retry {
+                uri: uri.to_string(),
+                reason,
+                error: error.to_string(),
+            });
+    }
+
+    /// The requests which have failed and been made again so far, in the order they failed.
+    pub fn retries(&self) -> Vec<Retry> {
+        self.retries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Returns the anonymous image tool to retry a failed request for `uri` with, if any.
+    fn anonymous_retry(&self, uri: &str, error: &error::Error) -> Option<&dyn ImageToolImpl> {
+        let anonymous = self.anonymous.as_ref()?;
+        let registry = registry_host(uri);
+        if !error.is_unauthorized() || !anonymous.registries.iter().any(|r| r == registry) {
+            return None;
+        }
+        log::warn!(
+            "Request for '{uri}' was not authorized, retrying anonymously because '{registry}' is \
+            a public registry"
+        );
+        self.record_retry(uri, RetryReason::Anonymous, error);
+        Some(anonymous.image_tool_impl.as_ref())
     }
 
     /// Pull an image archive to disk
     pub async fn pull_oci_image(&self, path: &Path, uri: &str) -> Result<()> {
-        self.image_tool_impl.pull_oci_image(path, uri).await
+        if let Some(reference) = LayoutReference::parse(uri, &self.layout_root) {
+            return reference?.pull_oci_image(path);
+        }
+        self.mirrored(uri, |target| async move {
+            let result = self
+                .throttled(&target, || self.tool(&target).pull_oci_image(path, &target))
+                .await;
+            if let Err(e) = result {
+                match self.anonymous_retry(&target, &e) {
+                    Some(anonymous) => {
+                        self.throttled(&target, || anonymous.pull_oci_image(path, &target))
+                            .await?
+                    }
+                    None => return Err(e),
+                }
+            }
+            if target != uri && !mirror::check_layout(&target, path)? {
+                // Without the manifest in the layout, the config it names is checked instead,
+                // which lists the digests of the layers. The manifest is fetched from the registry
+                // rather than the mirror, since that is what's being checked.
+                let manifest = self
+                    .throttled(uri, || self.tool(uri).get_manifest(uri))
+                    .await?;
+                mirror::check_config(&target, path, &manifest)?;
+            }
+            Ok(())
+        })
+        .await
     }
 
     /// Fetch the image config
     pub async fn get_config(&self, uri: &str) -> Result<ConfigView> {
-        self.image_tool_impl.get_config(uri).await
+        if let Some(reference) = LayoutReference::parse(uri, &self.layout_root) {
+            return reference?.get_config();
+        }
+        self.mirrored(uri, |target| async move {
+            let result = self
+                .throttled(&target, || self.tool(&target).get_config(&target))
+                .await;
+            match result {
+                Err(e) => match self.anonymous_retry(&target, &e) {
+                    Some(anonymous) => {
+                        self.throttled(&target, || anonymous.get_config(&target))
+                            .await
+                    }
+                    None => Err(e),
+                },
+                result => result,
+            }
+        })
+        .await
+    }
+
+    /// Fetch the manifest as the registry serves it, so that its digest can be computed
+    async fn get_raw_manifest(&self, uri: &str) -> Result<Vec<u8>> {
+        match LayoutReference::parse(uri, &self.layout_root) {
+            Some(reference) => reference?.get_manifest(),
+            None => {
+                self.mirrored(uri, |target| async move {
+                    let bytes = match self
+                        .throttled(&target, || self.tool(&target).get_manifest(&target))
+                        .await
+                    {
+                        Err(e) => match self.anonymous_retry(&target, &e) {
+                            Some(anonymous) => {
+                                self.throttled(&target, || anonymous.get_manifest(&target))
+                                    .await?
+                            }
+                            None => return Err(e),
+                        },
+                        Ok(bytes) => bytes,
+                    };
+                    if target != uri {
+                        mirror::check_content(&target, &bytes)?;
+                    }
+                    Ok(bytes)
+                })
+                .await
+            }
+        }
     }
 
     /// Fetch the manifest
     pub async fn get_manifest(&self, uri: &str) -> Result<Vec<u8>> {
-        let manifest_bytes = self.image_tool_impl.get_manifest(uri).await?;
+        let manifest_bytes = self.get_raw_manifest(uri).await?;
         let manifest_object: serde_json::Value =
             serde_json::from_slice(&manifest_bytes).context(error::ManifestDeserializeSnafu)?;
 
@@ -124,9 +531,53 @@ impl ImageTool {
         Ok(canonicalized_manifest)
     }
 
+    /// Fetch the digest of the manifest at `uri`, so that it can be referred to as
+    /// `<repository>@<digest>` even if its tag moves
+    pub async fn digest(&self, uri: &str) -> Result<String> {
+        if let Some(reference) = LayoutReference::parse(uri, &self.layout_root) {
+            return Ok(referrers::digest(&reference?.get_manifest()?));
+        }
+        let result = self.throttled(uri, || self.tool(uri).get_digest(uri)).await;
+        match result {
+            Err(e) => match self.anonymous_retry(uri, &e) {
+                Some(anonymous) => self.throttled(uri, || anonymous.get_digest(uri)).await,
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    /// List the tags of a repository, such as `public.ecr.aws/bottlerocket/bottlerocket-sdk`
+    pub async fn list_tags(&self, repository: &str) -> Result<Vec<String>> {
+        if let Some(tags) = layout::list_tags(repository, &self.layout_root) {
+            return tags;
+        }
+        let result = self
+            .throttled(repository, || self.tool(repository).list_tags(repository))
+            .await;
+        match result {
+            Err(e) => match self.anonymous_retry(repository, &e) {
+                Some(anonymous) => {
+                    self.throttled(repository, || anonymous.list_tags(repository))
+                        .await
+                }
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
     /// Push a single-arch image in oci archive format
     pub async fn push_oci_archive(&self, path: &Path, uri: &str) -> Result<()> {
-        self.image_tool_impl.push_oci_archive(path, uri).await
+        self.throttled(uri, || self.tool(uri).push_oci_archive(path, uri))
+            .await
+    }
+
+    /// Copy an image, and every platform of it, from one uri to another without changing its
+    /// digest
+    pub async fn copy_image(&self, from: &str, to: &str) -> Result<()> {
+        self.throttled_copy(from, to, || self.tool(to).copy_image(from, to))
+            .await
     }
 
     /// Push the multi-arch kit manifest list
@@ -135,9 +586,156 @@ impl ImageTool {
         platform_images: Vec<(DockerArchitecture, String)>,
         uri: &str,
     ) -> Result<()> {
-        self.image_tool_impl
-            .push_multi_platform_manifest(platform_images, uri)
-            .await
+        self.throttled(uri, || {
+            self.tool(uri)
+                .push_multi_platform_manifest(platform_images.clone(), uri)
+        })
+        .await
+    }
+
+    /// Attach the file at `path` to the image at `subject` as a referrer of the given kind, and
+    /// list it in the image's referrers index. Returns the digest of the artifact's manifest
+    pub async fn push_referrer(
+        &self,
+        subject: &str,
+        kind: ReferrerKind,
+        path: &Path,
+        annotations: BTreeMap<String, String>,
+    ) -> Result<String> {
+        let repository = repository(subject);
+        let subject = referrers::subject(&self.get_raw_manifest(subject).await?)?;
+        let index_uri = format!("{repository}:{}", referrers::fallback_tag(&subject.digest));
+
+        let temp_dir = TempDir::new().context(error::ReferrerTempSnafu)?;
+        let digest = referrers::write_layout(temp_dir.path(), subject, kind, path, annotations)?;
+        let artifact_uri = format!("{repository}@{digest}");
+        self.throttled(&artifact_uri, || {
+            self.tool(&artifact_uri)
+                .push_oci_layout(temp_dir.path(), &artifact_uri)
+        })
+        .await?;
+
+        // The index is read, appended to and written back, which registries can't make
+        // conditional on the index being unchanged. If another publisher writes the index in
+        // between, one of the artifacts is lost, so the index is read again after it's written
+        // and the artifact added again until it is listed. The image tool may already have listed
+        // it, if the registry lacks the referrers API.
+        let _guard = self.referrers_index_lock.lock().await;
+        for attempt in 1..=REFERRERS_INDEX_ATTEMPTS {
+            let listed = self.referrers_index(&index_uri).await?;
+            let exists = listed.is_some();
+            if listed
+                .unwrap_or_default()
+                .iter()
+                .any(|d| d.digest == digest)
+            {
+                return Ok(digest);
+            }
+            if attempt == REFERRERS_INDEX_ATTEMPTS {
+                break;
+            }
+            if attempt > 1 {
+                log::warn!(
+                    "'{index_uri}' was written by another publisher without {digest}, adding \
+                    it again"
+                );
+            }
+            self.throttled(&index_uri, || {
+                self.tool(&index_uri)
+                    .append_to_index(&index_uri, exists, &artifact_uri)
+            })
+            .await?;
+            // Give a publisher which read the index before this write time to write it back, so
+            // that the lost artifact is noticed.
+            tokio::time::sleep(std::time::Duration::from_millis(500 * u64::from(attempt))).await;
+        }
+        error::ReferrersIndexContendedSnafu {
+            uri: index_uri,
+            attempts: REFERRERS_INDEX_ATTEMPTS,
+        }
+        .fail()
+    }
+
+    /// List the artifacts attached to the image at `subject`
+    pub async fn list_referrers(&self, subject: &str) -> Result<Vec<Referrer>> {
+        if subject.starts_with(OCI_LAYOUT_SCHEME) {
+            return Ok(Vec::new());
+        }
+        let repository = repository(subject);
+        let digest = referrers::digest(&self.get_raw_manifest(subject).await?);
+        let index_uri = format!("{repository}:{}", referrers::fallback_tag(&digest));
+        let mut referrers = Vec::new();
+        for descriptor in self.referrers_index(&index_uri).await?.unwrap_or_default() {
+            let manifest = self
+                .get_raw_manifest(&format!("{repository}@{}", descriptor.digest))
+                .await?;
+            referrers.push(referrers::referrer(&descriptor.digest, &manifest)?);
+        }
+        Ok(referrers)
+    }
+
+    /// Fetch the content of an artifact attached to the image at `subject`
+    pub async fn get_referrer_content(
+        &self,
+        subject: &str,
+        referrer: &Referrer,
+    ) -> Result<Vec<u8>> {
+        let uri = format!("{}@{}", repository(subject), referrer.content_digest);
+        let uri = uri.as_str();
+        self.mirrored(uri, |target| async move {
+            let bytes = self
+                .throttled(&target, || self.tool(&target).get_blob(&target))
+                .await?;
+            if target != uri {
+                mirror::check_content(&target, &bytes)?;
+            }
+            Ok(bytes)
+        })
+        .await
+    }
+
+    /// The descriptors in the referrers index at `uri`, or `None` if the registry reports that
+    /// there is no index. Any other failure is returned, since starting a new index would replace
+    /// the artifacts already listed in it.
+    async fn referrers_index(&self, uri: &str) -> Result<Option<Vec<referrers::Descriptor>>> {
+        let bytes = match self.get_raw_manifest(uri).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.is_not_found() => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let index: referrers::ReferrersIndex =
+            serde_json::from_slice(&bytes).context(error::ManifestDeserializeSnafu)?;
+        Ok(Some(index.manifests))
+    }
+}
+
+/// The registry host of an image uri or repository, by which requests are limited and retried
+/// anonymously. Names without a host, such as Docker Hub's `bottlerocket/sdk`, are on Docker Hub,
+/// which is `docker.io` however it's written.
+pub fn registry_host(uri: &str) -> &str {
+    match uri.split_once('/') {
+        Some((host, _)) if is_host(host) => match host {
+            "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB,
+            host => host,
+        },
+        _ => DOCKER_HUB,
+    }
+}
+
+/// Whether the first component of an image name is a registry host, rather than the first part of
+/// a Docker Hub repository.
+pub(crate) fn is_host(component: &str) -> bool {
+    component.contains('.') || component.contains(':') || component == "localhost"
+}
+
+/// The repository of an image uri, without its tag or digest.
+pub fn repository(uri: &str) -> &str {
+    if let Some((repository, _)) = uri.split_once('@') {
+        return repository;
+    }
+    match uri.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => uri,
     }
 }
 
@@ -149,14 +747,40 @@ pub trait ImageToolImpl: std::fmt::Debug {
     async fn get_config(&self, uri: &str) -> Result<ConfigView>;
     /// Fetch the manifest
     async fn get_manifest(&self, uri: &str) -> Result<Vec<u8>>;
+    /// Fetch the digest of the manifest as the registry serves it
+    async fn get_digest(&self, uri: &str) -> Result<String>;
+    /// List the tags of a repository
+    async fn list_tags(&self, repository: &str) -> Result<Vec<String>>;
     /// Push a single-arch image in oci archive format
     async fn push_oci_archive(&self, path: &Path, uri: &str) -> Result<()>;
+    /// Copy an image, and every platform of it, from one uri to another without changing its
+    /// digest
+    async fn copy_image(&self, from: &str, to: &str) -> Result<()>;
     /// Push the multi-arch kit manifest list
     async fn push_multi_platform_manifest(
         &self,
         platform_images: Vec<(DockerArchitecture, String)>,
         uri: &str,
     ) -> Result<()>;
+    /// Push the single image in the OCI image layout at `dir` to `uri`, which may be a digest
+    async fn push_oci_layout(&self, dir: &Path, uri: &str) -> Result<()>;
+    /// Add the manifest at `manifest_uri` to the index at `index_uri`, creating the index unless it
+    /// `exists`
+    async fn append_to_index(
+        &self,
+        index_uri: &str,
+        exists: bool,
+        manifest_uri: &str,
+    ) -> Result<()>;
+    /// Fetch a blob
+    async fn get_blob(&self, uri: &str) -> Result<Vec<u8>>;
+    /// Set an environment variable for every invocation of the tool
+    fn set_env(&mut self, _key: &str, _value: &OsStr) {}
+    /// Returns a copy of the tool, if it can be copied, so that requests can be retried with a
+    /// different environment
+    fn try_clone(&self) -> Option<Box<dyn ImageToolImpl>> {
+        None
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
@@ -192,6 +816,8 @@ impl Display for DockerArchitecture {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
 struct ImageView {
+    #[serde(default)]
+    architecture: Option<String>,
     config: ConfigView,
 }
 
@@ -199,12 +825,25 @@ struct ImageView {
 #[serde(rename_all = "PascalCase")]
 pub struct ConfigView {
     pub labels: HashMap<String, String>,
+    /// The architecture of the image, which the image config records beside this view.
+    #[serde(skip)]
+    pub architecture: Option<String>,
+}
+
+impl From<ImageView> for ConfigView {
+    fn from(image: ImageView) -> Self {
+        Self {
+            architecture: image.architecture,
+            ..image.config
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, error::Error>;
 
 pub mod error {
     use std::path::PathBuf;
+    use std::time::Duration;
 
     use snafu::Snafu;
 
@@ -226,29 +865,123 @@ pub mod error {
         #[snafu(display("Failed to deserialize image config: {source}"))]
         ConfigDeserialize { source: serde_json::Error },
 
+        #[snafu(display("Failed to create temporary directory for registry auth: {source}"))]
+        AuthTemp { source: std::io::Error },
+
         #[snafu(display("Failed to create temporary directory for crane push: {source}"))]
         CraneTemp { source: std::io::Error },
 
+        #[snafu(display("Failed to run credential helper '{program}': {source}"))]
+        CredentialHelper {
+            program: String,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Content of '{uri}' has digest '{actual}', expected '{expected}'"))]
+        DigestMismatch {
+            uri: String,
+            expected: String,
+            actual: String,
+        },
+
         #[snafu(display("Failed to create temporary directory for docker save: {source}"))]
         DockerTemp { source: std::io::Error },
 
+        #[snafu(display("Failed to parse docker config '{}': {source}", path.display()))]
+        DockerConfigParse {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to read docker config '{}': {source}", path.display()))]
+        DockerConfigRead {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to serialize docker config: {source}"))]
+        DockerConfigSerialize { source: serde_json::Error },
+
+        #[snafu(display("Failed to write docker config '{}': {source}", path.display()))]
+        DockerConfigWrite {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("A credential helper command must not be empty"))]
+        EmptyCredentialCommand,
+
+        #[snafu(display("Invalid image uri '{uri}'"))]
+        InvalidImageUri { uri: String },
+
         #[snafu(display("invalid architecture '{value}'"))]
         InvalidArchitecture { value: String },
 
+        #[snafu(display(
+            "invalid artifact kind '{value}', expected 'sbom', 'provenance' or 'test-results'"
+        ))]
+        InvalidReferrerKind { value: String },
+
+        #[snafu(display(
+            "Invalid OCI layout reference '{uri}', expected 'oci-layout:<dir>/<name>:<tag>' or \
+            'oci-layout:<dir>/<name>@<digest>'"
+        ))]
+        InvalidLayoutReference { uri: String },
+
+        #[snafu(display("Unable to find image '{uri}' in its OCI layout"))]
+        LayoutImageNotFound { uri: String },
+
+        #[snafu(display("Failed to parse '{}' in OCI layout: {source}", path.display()))]
+        LayoutParse {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to read '{}' from OCI layout: {source}", path.display()))]
+        LayoutRead {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to serialize OCI layout index: {source}"))]
+        LayoutSerialize { source: serde_json::Error },
+
+        #[snafu(display("Failed to write '{}': {source}", path.display()))]
+        LayoutWrite {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
         #[snafu(display("Failed to deserialize image manifest: {source}"))]
         ManifestDeserialize { source: serde_json::Error },
 
         #[snafu(display("Failed to canonicalize image manifest: {source}"))]
         ManifestCanonicalize { source: serde_json::Error },
 
-        #[snafu(display("No digest returned by `docker load`"))]
-        NoDigest,
-
         #[snafu(display(
-            "Unable to find any supported container image tool, please install docker or crane: {}",
-            source
+            "Mirror '{mirror}' served content for '{uri}' which does not match its digest, \
+            refusing to pull it from the mirror or the registry: {source}"
         ))]
-        NoneFound { source: which::Error },
+        MirrorMismatch {
+            uri: String,
+            mirror: String,
+            source: Box<Error>,
+        },
+
+        #[snafu(display("The manifest of '{uri}' has no image config"))]
+        MissingConfig { uri: String },
+
+        #[snafu(display("No credentials for registry '{registry}' in the docker configuration"))]
+        MissingCredentials { registry: String },
+
+        #[snafu(display("'{uri}' has no image for platform '{platform}'"))]
+        MissingPlatform { uri: String, platform: String },
+
+        #[snafu(display("No token in the response from '{uri}'"))]
+        MissingToken { uri: String },
+
+        #[snafu(display("No digest returned by `docker load`"))]
+        NoDigest,
 
         #[snafu(display(
             "Unable to find a container image tool by name '{}' in current environment",
@@ -261,12 +994,209 @@ pub mod error {
             message: String,
             program: PathBuf,
             args: Vec<String>,
+            /// The HTTP status of the registry response, if the tool reported it
+            status: Option<u16>,
+            /// The registry error code, such as `MANIFEST_UNKNOWN`, if the tool reported it
+            code: Option<String>,
+        },
+
+        #[snafu(display("Failed to read '{}' to attach it to an image: {source}", path.display()))]
+        ReferrerRead {
+            path: PathBuf,
+            source: std::io::Error,
         },
 
+        #[snafu(display("Failed to create temporary directory for referrer: {source}"))]
+        ReferrerTemp { source: std::io::Error },
+
+        #[snafu(display(
+            "Other publishers kept replacing referrers index '{uri}' after {attempts} attempts to \
+            add to it"
+        ))]
+        ReferrersIndexContended { uri: String, attempts: u32 },
+
+        #[snafu(display(
+            "Attaching artifacts to images is not supported by {tool}, please install crane"
+        ))]
+        ReferrersUnsupported { tool: String },
+
         #[snafu(display("Failed to parse kit filename: {}", source))]
         Regex { source: regex::Error },
 
+        #[snafu(display("Failed to create registry client: {source}"))]
+        RegistryClient { source: reqwest::Error },
+
+        #[snafu(display("Request to '{uri}' failed: {source}"))]
+        RegistryRequest { uri: String, source: reqwest::Error },
+
+        #[snafu(display("Failed to parse response from '{uri}': {source}"))]
+        RegistryResponse {
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Request to '{uri}' failed with status {status}: {message}"))]
+        RegistryStatus {
+            uri: String,
+            status: u16,
+            message: String,
+            /// How long the registry asked for requests to stop, in seconds
+            retry_after: Option<u64>,
+        },
+
+        #[snafu(display("Failed to create temporary directory for registry push: {source}"))]
+        RegistryTemp { source: std::io::Error },
+
         #[snafu(display("Unsupported container image tool '{}'", name))]
         Unsupported { name: String },
+
+        #[snafu(display("Registry '{registry}' asked for unsupported authentication: {scheme}"))]
+        UnsupportedChallenge { registry: String, scheme: String },
+
+        #[snafu(display(
+            "Unable to check the image pulled from '{uri}', it has neither its manifest nor its \
+            config"
+        ))]
+        UnverifiedLayout { uri: String },
+
+        #[snafu(display("No upload location in the response from '{uri}'"))]
+        UploadLocation { uri: String },
+    }
+
+    impl Error {
+        /// Whether the error looks like a registry rejecting the request's credentials.
+        pub fn is_unauthorized(&self) -> bool {
+            match self {
+                Self::RegistryStatus { status, .. } => *status == 401 || *status == 403,
+                Self::MissingCredentials { .. } => true,
+                Self::OperationFailed { status, code, .. } => {
+                    matches!(status, Some(401 | 403))
+                        || matches!(code.as_deref(), Some("UNAUTHORIZED" | "DENIED"))
+                }
+                _ => false,
+            }
+        }
+
+        /// Whether the error looks like a registry refusing a request because too many have been
+        /// made.
+        pub fn is_rate_limited(&self) -> bool {
+            match self {
+                Self::RegistryStatus { status, .. } => *status == 429,
+                Self::OperationFailed { status, code, .. } => {
+                    *status == Some(429) || code.as_deref() == Some("TOOMANYREQUESTS")
+                }
+                _ => false,
+            }
+        }
+
+        /// How long the registry asked for requests to stop, if it said.
+        pub fn retry_after(&self) -> Option<Duration> {
+            match self {
+                Self::RegistryStatus { retry_after, .. } => retry_after.map(Duration::from_secs),
+                _ => None,
+            }
+        }
+
+        /// Whether the error is content which does not match the digest it was fetched by.
+        pub fn is_digest_mismatch(&self) -> bool {
+            matches!(self, Self::DigestMismatch { .. })
+        }
+
+        /// Whether the error looks like a registry reporting that an image does not exist.
+        pub fn is_not_found(&self) -> bool {
+            match self {
+                Self::RegistryStatus { status, .. } => *status == 404,
+                Self::OperationFailed { status, code, .. } => {
+                    *status == Some(404)
+                        || matches!(
+                            code.as_deref(),
+                            Some("MANIFEST_UNKNOWN" | "NAME_UNKNOWN" | "BLOB_UNKNOWN")
+                        )
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn failed(message: &str, status: Option<u16>, code: Option<&str>) -> error::Error {
+        error::Error::OperationFailed {
+            message: message.to_string(),
+            program: "crane".into(),
+            args: Vec::new(),
+            status,
+            code: code.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn credentials_are_chosen_by_repository() {
+        let image_tool = ImageTool::new(Box::new(DockerCLI {
+            cli: CommandLine::new("/usr/bin/docker".into()),
+        }))
+        .with_repository_auth(vec![
+            (
+                "a.com/team".to_string(),
+                CredentialHelper::Name("one".into()),
+            ),
+            (
+                "a.com/team/sub".to_string(),
+                CredentialHelper::Name("two".into()),
+            ),
+        ])
+        .unwrap();
+        let tool = |uri| format!("{:?}", image_tool.tool(uri));
+        let auth = |i: usize| format!("{:?}", image_tool.repository_auth[i].image_tool_impl);
+        assert_eq!(tool("a.com/team/kit:v1"), auth(0));
+        assert_eq!(tool("a.com/team/sub/kit@sha256:abc"), auth(1));
+        assert_eq!(tool("a.com/teammate/kit:v1"), tool("b.com/kit:v1"));
+        assert_ne!(tool("a.com/teammate/kit:v1"), auth(0));
+    }
+
+    #[test]
+    fn registry_hosts_are_normalised() {
+        assert_eq!(
+            registry_host("public.ecr.aws/bottlerocket/sdk:v1"),
+            "public.ecr.aws"
+        );
+        assert_eq!(
+            registry_host("localhost:5000/kit@sha256:abc"),
+            "localhost:5000"
+        );
+        assert_eq!(registry_host("localhost/kit:v1"), "localhost");
+        assert_eq!(registry_host("bottlerocket/sdk:v1"), "docker.io");
+        assert_eq!(registry_host("alpine:3"), "docker.io");
+        assert_eq!(registry_host("docker.io/library/alpine"), "docker.io");
+        assert_eq!(
+            registry_host("index.docker.io/bottlerocket/sdk"),
+            "docker.io"
+        );
+        assert_eq!(
+            registry_host("registry-1.docker.io/bottlerocket/sdk"),
+            "docker.io"
+        );
+    }
+
+    #[test]
+    fn not_found_is_classified_by_status_or_code() {
+        assert!(failed("", Some(404), None).is_not_found());
+        assert!(failed("", None, Some("MANIFEST_UNKNOWN")).is_not_found());
+        // A digest containing "404", or a message mentioning "not found", is not enough.
+        let message = "failed to fetch a.com/b:sha256-4040: blob not found in cache";
+        assert!(!failed(message, None, None).is_not_found());
+        assert!(!failed("", Some(500), None).is_not_found());
+    }
+
+    #[test]
+    fn rate_limiting_is_classified_by_status_or_code() {
+        assert!(failed("", Some(429), None).is_rate_limited());
+        assert!(failed("", None, Some("TOOMANYREQUESTS")).is_rate_limited());
+        let message = "failed to pull a.com/b@sha256:4290429: too many requests to the daemon";
+        assert!(!failed(message, None, None).is_rate_limited());
+        assert!(!failed("", None, Some("DENIED")).is_rate_limited());
     }
 }
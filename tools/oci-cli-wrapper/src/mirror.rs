@@ -0,0 +1,203 @@
+//! Redirects pulls of images from a registry to a mirror of it, such as an ECR pull-through cache
+//! or a Harbor proxy project, while images keep being referred to by their canonical uris.
+//!
+//! Only images referenced by digest are pulled through a mirror. A tag is resolved by the canonical
+//! registry, so that the digest it resolves to is the one the canonical registry serves, and what
+//! the mirror returns for a digest is checked against it. A mirror which can't be reached is passed
+//! over for the canonical registry, but one which returns other content is an error.
+use std::path::Path;
+
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt};
+
+use crate::{error, referrers, Result};
+
+/// A mirror of the images under a canonical registry and repository prefix.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mirror {
+    /// The prefix of the uris of the images which are mirrored, e.g. `public.ecr.aws/bottlerocket`
+    pub canonical: String,
+    /// The prefix the images are mirrored at, e.g.
+    /// `111122223333.dkr.ecr.us-west-2.amazonaws.com/ecr-public/bottlerocket`
+    pub mirror: String,
+}
+
+impl Mirror {
+    pub fn new(canonical: impl Into<String>, mirror: impl Into<String>) -> Self {
+        Self {
+            canonical: canonical.into().trim_end_matches('/').to_string(),
+            mirror: mirror.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The uri to pull `uri` from through this mirror, if it is mirrored and referenced by digest.
+    pub(crate) fn redirect(&self, uri: &str) -> Option<String> {
+        let path = uri.strip_prefix(&self.canonical)?.strip_prefix('/')?;
+        if !path.contains("@sha256:") {
+            return None;
+        }
+        Some(format!("{}/{}", self.mirror, path))
+    }
+}
+
+/// The digest `uri` references its content by.
+fn expected_digest(uri: &str) -> Option<&str> {
+    uri.rsplit_once('@').map(|(_, digest)| digest)
+}
+
+/// Checks that `bytes`, fetched from `uri`, are the content of the digest `uri` references.
+pub(crate) fn check_content(uri: &str, bytes: &[u8]) -> Result<()> {
+    if let Some(expected) = expected_digest(uri) {
+        let actual = referrers::digest(bytes);
+        ensure!(
+            actual == expected,
+            error::DigestMismatchSnafu {
+                uri,
+                expected,
+                actual
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the image pulled from `uri` into the layout at `path` has the manifest `uri`
+/// references. The image tools check that the layers match the manifest as they pull them, but
+/// the manifest is only written to the layout as a blob named by its digest, so this reads it back.
+/// Returns whether the manifest was there to check, since `docker save` does not always keep it.
+pub(crate) fn check_layout(uri: &str, path: &Path) -> Result<bool> {
+    let Some(expected) = expected_digest(uri) else {
+        return Ok(true);
+    };
+    let Some(hex) = expected.strip_prefix("sha256:") else {
+        return Ok(true);
+    };
+    match std::fs::read(path.join("blobs").join("sha256").join(hex)) {
+        Ok(bytes) => check_content(uri, &bytes).map(|()| true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Checks that the layout at `path`, pulled from `uri` without its manifest, has the config named
+/// by `manifest`, the manifest `uri` references as fetched from elsewhere. The config lists the
+/// digests of the image's layers, which docker checks as it loads them.
+pub(crate) fn check_config(uri: &str, path: &Path, manifest: &[u8]) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Manifest {
+        config: Option<Descriptor>,
+    }
+    #[derive(Deserialize)]
+    struct Descriptor {
+        digest: String,
+    }
+    let manifest: Manifest =
+        serde_json::from_slice(manifest).context(error::ManifestDeserializeSnafu)?;
+    let digest = manifest
+        .config
+        .context(error::MissingConfigSnafu { uri })?
+        .digest;
+    let hex = digest.strip_prefix("sha256:").unwrap_or(&digest);
+    // `docker save` writes the config as a blob, or as `<hex>.json` in older versions.
+    let bytes = [
+        path.join("blobs").join("sha256").join(hex),
+        path.join(format!("{hex}.json")),
+    ]
+    .iter()
+    .find_map(|path| std::fs::read(path).ok())
+    .context(error::UnverifiedLayoutSnafu { uri })?;
+    let repository = uri
+        .rsplit_once('@')
+        .map_or(uri, |(repository, _)| repository);
+    check_content(&format!("{repository}@{digest}"), &bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIGEST: &str = "sha256:4f53cda18c2baa0c0354bb5f9a3ecbe5ed12ab4d8e11ba873c2f11161202b945";
+
+    #[test]
+    fn redirects_images_referenced_by_digest() {
+        let mirror = Mirror::new(
+            "public.ecr.aws/bottlerocket/",
+            "111122223333.dkr.ecr.us-west-2.amazonaws.com/ecr-public/bottlerocket",
+        );
+        assert_eq!(
+            mirror.redirect(&format!("public.ecr.aws/bottlerocket/core-kit@{DIGEST}")),
+            Some(format!(
+                "111122223333.dkr.ecr.us-west-2.amazonaws.com/ecr-public/bottlerocket/core-kit@{DIGEST}"
+            ))
+        );
+        // Tags are resolved by the canonical registry.
+        assert_eq!(
+            mirror.redirect("public.ecr.aws/bottlerocket/core-kit:v1.0.0"),
+            None
+        );
+        // Only whole path components of the prefix match.
+        assert_eq!(
+            mirror.redirect(&format!(
+                "public.ecr.aws/bottlerocket-extra/core-kit@{DIGEST}"
+            )),
+            None
+        );
+        assert_eq!(
+            mirror.redirect(&format!("docker.io/bottlerocket/core-kit@{DIGEST}")),
+            None
+        );
+    }
+
+    #[test]
+    fn redirects_registries_with_ports() {
+        let mirror = Mirror::new("registry.example.com:5000", "localhost:5001/cache");
+        assert_eq!(
+            mirror.redirect(&format!("registry.example.com:5000/kits/core-kit@{DIGEST}")),
+            Some(format!("localhost:5001/cache/kits/core-kit@{DIGEST}"))
+        );
+        assert_eq!(
+            mirror.redirect("registry.example.com:5000/kits/core-kit:v1.0.0"),
+            None
+        );
+        assert_eq!(
+            mirror.redirect(&format!(
+                "registry.example.com:50001/kits/core-kit@{DIGEST}"
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn checks_content_against_digest() {
+        let digest = referrers::digest(b"manifest");
+        check_content(&format!("a.com/b@{digest}"), b"manifest").unwrap();
+        assert!(check_content(&format!("a.com/b@{digest}"), b"other").is_err());
+        check_content("a.com/b:v1", b"other").unwrap();
+    }
+
+    #[test]
+    fn checks_layout_without_manifest_by_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = b"config";
+        let config_digest = referrers::digest(config);
+        let manifest = serde_json::json!({"config": {"digest": config_digest}}).to_string();
+        let uri = format!("mirror.com/b@{}", referrers::digest(manifest.as_bytes()));
+
+        // Without the manifest or the config, the image can't be checked.
+        assert!(!check_layout(&uri, dir.path()).unwrap());
+        assert!(matches!(
+            check_config(&uri, dir.path(), manifest.as_bytes()),
+            Err(error::Error::UnverifiedLayout { .. })
+        ));
+
+        let blobs = dir.path().join("blobs").join("sha256");
+        std::fs::create_dir_all(&blobs).unwrap();
+        let config_path = blobs.join(config_digest.strip_prefix("sha256:").unwrap());
+        std::fs::write(&config_path, config).unwrap();
+        check_config(&uri, dir.path(), manifest.as_bytes()).unwrap();
+
+        std::fs::write(&config_path, b"other").unwrap();
+        assert!(check_config(&uri, dir.path(), manifest.as_bytes())
+            .unwrap_err()
+            .is_digest_mismatch());
+    }
+}
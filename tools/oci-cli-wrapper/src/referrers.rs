@@ -0,0 +1,251 @@
+//! Attaches artifacts such as SBOMs, provenance and test results to published images as OCI
+//! referrers: manifests whose `subject` is the image they describe.
+//!
+//! An artifact is written to a temporary OCI image layout and pushed by digest. Since not every
+//! registry serves the referrers API, and the image tools cannot query it, the referrers of an
+//! image are also listed in an index tagged with the image's digest, as the OCI distribution spec
+//! describes for registries without the API: `sha256:<hex>` is tagged `sha256-<hex>`.
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+
+use crate::{error, Result};
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+const EMPTY_CONFIG: &[u8] = b"{}";
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// The kinds of artifact which are attached to published kits.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ReferrerKind {
+    /// An SPDX SBOM
+    Sbom,
+    /// An in-toto statement of build provenance
+    Provenance,
+    /// The results of testing the image
+    TestResults,
+}
+
+impl ReferrerKind {
+    /// The `artifactType` of referrers of this kind, which is also the media type of their content.
+    pub fn artifact_type(&self) -> &'static str {
+        match self {
+            Self::Sbom => "application/spdx+json",
+            Self::Provenance => "application/vnd.in-toto+json",
+            Self::TestResults => "application/vnd.bottlerocket.test-results.v1+json",
+        }
+    }
+
+    /// The kind of referrers with `artifact_type`, if it is one of ours.
+    pub fn from_artifact_type(artifact_type: &str) -> Option<Self> {
+        [Self::Sbom, Self::Provenance, Self::TestResults]
+            .into_iter()
+            .find(|kind| kind.artifact_type() == artifact_type)
+    }
+}
+
+impl Display for ReferrerKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Sbom => "sbom",
+            Self::Provenance => "provenance",
+            Self::TestResults => "test-results",
+        })
+    }
+}
+
+impl FromStr for ReferrerKind {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sbom" => Ok(Self::Sbom),
+            "provenance" => Ok(Self::Provenance),
+            "test-results" => Ok(Self::TestResults),
+            _ => error::InvalidReferrerKindSnafu { value: s }.fail(),
+        }
+    }
+}
+
+/// An artifact attached to an image.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Referrer {
+    /// The digest of the artifact's manifest
+    pub digest: String,
+    pub artifact_type: String,
+    /// The file name the artifact was attached with
+    pub title: Option<String>,
+    /// The digest of the artifact's content
+    pub content_digest: String,
+    /// The size of the artifact's content
+    pub size: u64,
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl Referrer {
+    /// The kind of the artifact, if it is one of ours.
+    pub fn kind(&self) -> Option<ReferrerKind> {
+        ReferrerKind::from_artifact_type(&self.artifact_type)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Descriptor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) media_type: Option<String>,
+    pub(crate) digest: String,
+    pub(crate) size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) artifact_type: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) annotations: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ArtifactManifest {
+    schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subject: Option<Descriptor>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ReferrersIndex {
+    #[serde(default)]
+    pub(crate) manifests: Vec<Descriptor>,
+}
+
+/// The OCI digest of `bytes`.
+pub(crate) fn digest(bytes: &[u8]) -> String {
+    finish_digest(Sha256::new_with_prefix(bytes))
+}
+
+/// The OCI digest of everything fed to `hasher`, for content hashed as it streams.
+pub(crate) fn finish_digest(hasher: Sha256) -> String {
+    format!("sha256:{}", hex::encode(hasher.finalize()))
+}
+
+/// The descriptor of the manifest `bytes`, as the subject of a referrer.
+pub(crate) fn subject(bytes: &[u8]) -> Result<Descriptor> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct MediaType {
+        media_type: Option<String>,
+    }
+    let manifest: MediaType =
+        serde_json::from_slice(bytes).context(error::ManifestDeserializeSnafu)?;
+    Ok(Descriptor {
+        media_type: manifest.media_type,
+        digest: digest(bytes),
+        size: bytes.len() as u64,
+        ..Default::default()
+    })
+}
+
+/// The tag of the index listing the referrers of the manifest with `digest`.
+pub(crate) fn fallback_tag(digest: &str) -> String {
+    digest.replace(':', "-")
+}
+
+/// Writes an OCI image layout to `dir` holding an artifact of `kind` with the contents of `path`,
+/// which refers to `subject`. Returns the digest of the artifact's manifest.
+pub(crate) fn write_layout(
+    dir: &Path,
+    subject: Descriptor,
+    kind: ReferrerKind,
+    path: &Path,
+    annotations: BTreeMap<String, String>,
+) -> Result<String> {
+    let content = fs::read(path).context(error::ReferrerReadSnafu { path })?;
+    let title = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string());
+    let manifest = ArtifactManifest {
+        schema_version: 2,
+        media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+        artifact_type: Some(kind.artifact_type().to_string()),
+        config: Descriptor {
+            media_type: Some(EMPTY_MEDIA_TYPE.to_string()),
+            digest: digest(EMPTY_CONFIG),
+            size: EMPTY_CONFIG.len() as u64,
+            ..Default::default()
+        },
+        layers: vec![Descriptor {
+            media_type: Some(kind.artifact_type().to_string()),
+            digest: digest(&content),
+            size: content.len() as u64,
+            annotations: title
+                .map(|title| BTreeMap::from([(TITLE_ANNOTATION.to_string(), title)]))
+                .unwrap_or_default(),
+            ..Default::default()
+        }],
+        subject: Some(subject),
+        annotations,
+    };
+    let manifest = serde_json::to_vec(&manifest).context(error::LayoutSerializeSnafu)?;
+    let manifest_digest = digest(&manifest);
+
+    for blob in [EMPTY_CONFIG, content.as_slice(), manifest.as_slice()] {
+        write_file(
+            &dir.join("blobs").join(digest(blob).replace(':', "/")),
+            blob,
+        )?;
+    }
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "manifests": [{
+            "mediaType": MANIFEST_MEDIA_TYPE,
+            "digest": manifest_digest,
+            "size": manifest.len(),
+            "artifactType": kind.artifact_type(),
+        }],
+    });
+    let layout = serde_json::json!({ "imageLayoutVersion": "1.0.0" });
+    for (file, value) in [("index.json", index), ("oci-layout", layout)] {
+        let bytes = serde_json::to_vec(&value).context(error::LayoutSerializeSnafu)?;
+        write_file(&dir.join(file), &bytes)?;
+    }
+    Ok(manifest_digest)
+}
+
+/// Describes the artifact whose manifest is `bytes`, with `digest`.
+pub(crate) fn referrer(digest: &str, bytes: &[u8]) -> Result<Referrer> {
+    let manifest: ArtifactManifest =
+        serde_json::from_slice(bytes).context(error::ManifestDeserializeSnafu)?;
+    let layer = manifest.layers.first().cloned().unwrap_or_default();
+    Ok(Referrer {
+        digest: digest.to_string(),
+        artifact_type: manifest
+            .artifact_type
+            .or(manifest.config.media_type)
+            .unwrap_or_default(),
+        title: layer.annotations.get(TITLE_ANNOTATION).cloned(),
+        content_digest: layer.digest,
+        size: layer.size,
+        annotations: manifest.annotations,
+    })
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(error::LayoutWriteSnafu { path: parent })?;
+    }
+    fs::write(path, bytes).context(error::LayoutWriteSnafu { path })
+}
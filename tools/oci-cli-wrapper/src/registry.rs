@@ -0,0 +1,1261 @@
+//! An OCI distribution client which talks to registries directly over HTTP, so that images can be
+//! resolved, pulled and pushed without crane or docker installed.
+//!
+//! Credentials come from the docker client configuration, as they do for the command line tools:
+//! `auths` entries are used as they are, and `credHelpers` and `credsStore` are run as docker
+//! credential helpers. Registries which issue bearer tokens are asked for one scoped to each
+//! repository, which is reused until the registry rejects it. Blobs are streamed to disk and
+//! checked against their digests as they are pulled, and streamed from disk as they are pushed, so
+//! that large layers are never held in memory.
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::Engine;
+use reqwest::header::{
+    ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, LINK, LOCATION, RETRY_AFTER,
+    WWW_AUTHENTICATE,
+};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt, ResultExt};
+use tar::Archive as TarArchive;
+use tempfile::TempDir;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::{
+    error, is_host, referrers, ConfigView, DockerArchitecture, ImageToolImpl, ImageView, Result,
+    DOCKER_HUB,
+};
+
+const DOCKER_HUB_API: &str = "registry-1.docker.io";
+/// The key of Docker Hub in the `auths` of the docker configuration.
+const DOCKER_HUB_AUTH_KEY: &str = "https://index.docker.io/v1/";
+
+const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// The actions a token is requested for, by operation.
+const PULL: &str = "pull";
+const PUSH: &str = "pull,push";
+
+#[derive(Debug, Clone)]
+pub struct RegistryClient {
+    client: Client,
+    env: Vec<(String, OsString)>,
+    /// The `Authorization` header for each repository and set of actions, once a registry has
+    /// asked for one.
+    authorizations: Arc<Mutex<HashMap<String, String>>>,
+}
+
+/// An image uri split into the parts which the distribution API addresses.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct ImageReference {
+    registry: String,
+    repository: String,
+    /// A tag or a digest
+    reference: String,
+}
+
+impl ImageReference {
+    fn parse(uri: &str) -> Result<Self> {
+        let (name, reference) = match uri.split_once('@') {
+            Some((name, digest)) => (without_tag(name), digest),
+            None => match uri.rsplit_once(':') {
+                Some((name, tag)) if !tag.contains('/') => (name, tag),
+                _ => (uri, "latest"),
+            },
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, rest)) if is_host(host) => (host.to_string(), rest.to_string()),
+            Some(_) => (DOCKER_HUB.to_string(), name.to_string()),
+            None => (DOCKER_HUB.to_string(), format!("library/{name}")),
+        };
+        ensure!(
+            !repository.is_empty() && !reference.is_empty(),
+            error::InvalidImageUriSnafu { uri }
+        );
+        Ok(Self {
+            registry,
+            repository,
+            reference: reference.to_string(),
+        })
+    }
+
+    fn with_reference(&self, reference: &str) -> Self {
+        Self {
+            reference: reference.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// The url of the repository in the registry's API. Registries on the local host are reached
+    /// over plain HTTP, as they usually are for testing.
+    fn url(&self) -> String {
+        let host = match self.registry.as_str() {
+            DOCKER_HUB => DOCKER_HUB_API,
+            host => host,
+        };
+        let scheme = if host.starts_with("localhost") || host.starts_with("127.0.0.1") {
+            "http"
+        } else {
+            "https"
+        };
+        format!("{scheme}://{host}/v2/{}", self.repository)
+    }
+
+    fn manifest_url(&self) -> String {
+        format!("{}/manifests/{}", self.url(), self.reference)
+    }
+
+    fn blob_url(&self, digest: &str) -> String {
+        format!("{}/blobs/{digest}", self.url())
+    }
+
+    /// Whether the reference pins the content by digest, so that it can be checked.
+    fn digest(&self) -> Option<&str> {
+        self.reference
+            .starts_with("sha256:")
+            .then_some(self.reference.as_str())
+    }
+}
+
+/// Removes the tag from an image name which also has a digest, such as `name:tag@sha256:...`.
+fn without_tag(name: &str) -> &str {
+    match name.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => name,
+        _ => name,
+    }
+}
+
+/// A manifest or manifest list as the registry served it.
+#[derive(Debug)]
+struct Manifest {
+    bytes: Vec<u8>,
+    media_type: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ManifestView {
+    media_type: Option<String>,
+    artifact_type: Option<String>,
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+impl ManifestView {
+    fn blobs(&self) -> impl Iterator<Item = &Descriptor> {
+        self.config.iter().chain(self.layers.iter())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    digest: String,
+    size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagList {
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+    creds_store: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthEntry {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredential {
+    username: String,
+    secret: String,
+}
+
+/// A username and password for a registry.
+#[derive(Clone)]
+struct Credential {
+    username: String,
+    password: String,
+}
+
+impl RegistryClient {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            client: build_client(&[])?,
+            env: Vec::new(),
+            authorizations: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// The value of `key` in the environment given to the client, or else the process's.
+    fn var(&self, key: &str) -> Option<OsString> {
+        self.env
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var_os(key))
+    }
+
+    /// Sends the request made by `request` to the repository of `image`. When the registry asks
+    /// for credentials, they are obtained for `actions` and the request is made once more.
+    async fn send<F>(&self, image: &ImageReference, actions: &str, request: F) -> Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        self.try_send(image, actions, |client| Ok(request(client)))
+            .await
+    }
+
+    /// Like [`RegistryClient::send`], for requests which may fail to be made, such as those whose
+    /// body is read from a file each time they're made.
+    async fn try_send<F>(
+        &self,
+        image: &ImageReference,
+        actions: &str,
+        request: F,
+    ) -> Result<Response>
+    where
+        F: Fn(&Client) -> Result<RequestBuilder>,
+    {
+        let key = format!("{}/{}:{actions}", image.registry, image.repository);
+        let cached = self.authorizations.lock().await.get(&key).cloned();
+        let response = execute(request(&self.client)?, cached.as_deref()).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        let Some(challenge) = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Challenge::parse)
+        else {
+            return Ok(response);
+        };
+        let authorization = self.authorize(image, actions, &challenge).await?;
+        self.authorizations
+            .lock()
+            .await
+            .insert(key, authorization.clone());
+        execute(request(&self.client)?, Some(&authorization)).await
+    }
+
+    /// Answers the registry's challenge with an `Authorization` header.
+    async fn authorize(
+        &self,
+        image: &ImageReference,
+        actions: &str,
+        challenge: &Challenge,
+    ) -> Result<String> {
+        let credential = self.credential(&image.registry).await?;
+        match challenge.scheme.as_str() {
+            "basic" => {
+                let credential = credential.context(error::MissingCredentialsSnafu {
+                    registry: &image.registry,
+                })?;
+                let encoded = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", credential.username, credential.password));
+                Ok(format!("Basic {encoded}"))
+            }
+            "bearer" => {
+                let realm =
+                    challenge
+                        .params
+                        .get("realm")
+                        .context(error::UnsupportedChallengeSnafu {
+                            registry: &image.registry,
+                            scheme: "bearer without a realm",
+                        })?;
+                let mut request = self.client.get(realm).query(&[(
+                    "scope",
+                    format!("repository:{}:{actions}", image.repository),
+                )]);
+                if let Some(service) = challenge.params.get("service") {
+                    request = request.query(&[("service", service)]);
+                }
+                if let Some(credential) = credential {
+                    request = request.basic_auth(credential.username, Some(credential.password));
+                }
+                let response = request
+                    .send()
+                    .await
+                    .context(error::RegistryRequestSnafu { uri: realm })?;
+                let bytes = check(response, realm).await?;
+                let token: TokenResponse = serde_json::from_slice(&bytes)
+                    .context(error::RegistryResponseSnafu { uri: realm })?;
+                let token = token
+                    .token
+                    .or(token.access_token)
+                    .context(error::MissingTokenSnafu { uri: realm })?;
+                Ok(format!("Bearer {token}"))
+            }
+            scheme => error::UnsupportedChallengeSnafu {
+                registry: &image.registry,
+                scheme,
+            }
+            .fail(),
+        }
+    }
+
+    /// The credentials for `registry` from the docker configuration, if it has any.
+    async fn credential(&self, registry: &str) -> Result<Option<Credential>> {
+        let dir = self.var("DOCKER_CONFIG").map(PathBuf::from).or_else(|| {
+            self.var("HOME")
+                .map(|home| Path::new(&home).join(".docker"))
+        });
+        let Some(path) = dir.map(|dir| dir.join("config.json")) else {
+            return Ok(None);
+        };
+        let config: DockerConfig = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .context(error::DockerConfigParseSnafu { path: &path })?,
+            Err(_) => return Ok(None),
+        };
+        let key = match registry {
+            DOCKER_HUB => DOCKER_HUB_AUTH_KEY,
+            registry => registry,
+        };
+
+        if let Some(helper) = config.cred_helpers.get(key) {
+            return self.run_helper(helper, key).await;
+        }
+        let entry = config.auths.iter().find(|(server, _)| {
+            server.as_str() == key
+                || server
+                    .trim_start_matches("https://")
+                    .trim_start_matches("http://")
+                    .split('/')
+                    .next()
+                    == Some(key)
+        });
+        if let Some((_, entry)) = entry {
+            if let (Some(username), Some(password)) = (&entry.username, &entry.password) {
+                return Ok(Some(Credential {
+                    username: username.clone(),
+                    password: password.clone(),
+                }));
+            }
+            let decoded = entry
+                .auth
+                .as_ref()
+                .and_then(|auth| base64::engine::general_purpose::STANDARD.decode(auth).ok())
+                .map(|bytes| String::from_utf8_lossy(&bytes).to_string());
+            if let Some((username, password)) = decoded.as_ref().and_then(|d| d.split_once(':')) {
+                return Ok(Some(Credential {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                }));
+            }
+        }
+        match &config.creds_store {
+            Some(store) => self.run_helper(store, key).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the credentials for `server` from the docker credential helper `helper`. A helper which
+    /// has no credentials for the server fails, which is treated as having none.
+    async fn run_helper(&self, helper: &str, server: &str) -> Result<Option<Credential>> {
+        let program = format!("docker-credential-{helper}");
+        let mut child = Command::new(&program)
+            .arg("get")
+            .envs(self.env.iter().cloned())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(error::CredentialHelperSnafu { program: &program })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(server.as_bytes())
+                .await
+                .context(error::CredentialHelperSnafu { program: &program })?;
+        }
+        let output = child
+            .wait_with_output()
+            .await
+            .context(error::CredentialHelperSnafu { program: &program })?;
+        if !output.status.success() {
+            log::debug!(
+                "'{program}' has no credentials for '{server}': {}",
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+            return Ok(None);
+        }
+        let credential: HelperCredential = serde_json::from_slice(&output.stdout)
+            .context(error::RegistryResponseSnafu { uri: &program })?;
+        Ok(Some(Credential {
+            username: credential.username,
+            password: credential.secret,
+        }))
+    }
+
+    /// Fetches the manifest or manifest list of `image`, checking it against the digest it is
+    /// referenced by.
+    async fn fetch_manifest(&self, image: &ImageReference) -> Result<Manifest> {
+        let url = image.manifest_url();
+        let accept = [
+            OCI_INDEX,
+            OCI_MANIFEST,
+            DOCKER_MANIFEST_LIST,
+            DOCKER_MANIFEST,
+        ]
+        .join(", ");
+        let response = self
+            .send(image, PULL, |client| {
+                client.get(&url).header(ACCEPT, &accept)
+            })
+            .await?;
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+        let bytes = check(response, &url).await?;
+        if let Some(expected) = image.digest() {
+            let actual = referrers::digest(&bytes);
+            ensure!(
+                actual == expected,
+                error::DigestMismatchSnafu {
+                    uri: url,
+                    expected,
+                    actual
+                }
+            );
+        }
+        let view: ManifestView =
+            serde_json::from_slice(&bytes).context(error::ManifestDeserializeSnafu)?;
+        let media_type = view
+            .media_type
+            .or(content_type)
+            .unwrap_or_else(|| OCI_MANIFEST.to_string());
+        Ok(Manifest { bytes, media_type })
+    }
+
+    /// Fetches the manifest of `image`, choosing the image for this host's platform when it is a
+    /// manifest list. Returns the manifest along with the reference it was found at.
+    async fn fetch_image_manifest(
+        &self,
+        image: &ImageReference,
+    ) -> Result<(Manifest, ImageReference)> {
+        let manifest = self.fetch_manifest(image).await?;
+        let view: ManifestView =
+            serde_json::from_slice(&manifest.bytes).context(error::ManifestDeserializeSnafu)?;
+        if view.manifests.is_empty() {
+            return Ok((manifest, image.clone()));
+        }
+        let arch = DockerArchitecture::try_from(std::env::consts::ARCH)?.to_string();
+        let descriptor =
+            view.manifests
+                .iter()
+                .find(|descriptor| {
+                    descriptor.platform.as_ref().is_some_and(|platform| {
+                        platform.os == "linux" && platform.architecture == arch
+                    })
+                })
+                .context(error::MissingPlatformSnafu {
+                    uri: image.manifest_url(),
+                    platform: format!("linux/{arch}"),
+                })?;
+        let image = image.with_reference(&descriptor.digest);
+        Ok((self.fetch_manifest(&image).await?, image))
+    }
+
+    /// Fetches a blob of the repository of `image` into memory, checking it against its digest.
+    /// Only used for blobs which are small, such as image configs; layers are streamed to disk
+    /// with [`RegistryClient::pull_blob`].
+    async fn fetch_blob(&self, image: &ImageReference, digest: &str) -> Result<Vec<u8>> {
+        let url = image.blob_url(digest);
+        let response = self.send(image, PULL, |client| client.get(&url)).await?;
+        let bytes = check(response, &url).await?;
+        let actual = referrers::digest(&bytes);
+        ensure!(
+            actual == digest,
+            error::DigestMismatchSnafu {
+                uri: url,
+                expected: digest,
+                actual
+            }
+        );
+        Ok(bytes)
+    }
+
+    /// Streams a blob of the repository of `image` to `path`, checking it against its digest. The
+    /// blob is written beside `path` and only moved into place once it has been checked.
+    async fn pull_blob(&self, image: &ImageReference, digest: &str, path: &Path) -> Result<()> {
+        if path.exists() {
+            return Ok(());
+        }
+        let url = image.blob_url(digest);
+        let response = self.send(image, PULL, |client| client.get(&url)).await?;
+        let mut response = ensure_success(response, &url).await?;
+
+        let partial = path.with_extension("partial");
+        if let Some(parent) = partial.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context(error::LayoutWriteSnafu { path: parent })?;
+        }
+        let mut file = tokio::fs::File::create(&partial)
+            .await
+            .context(error::LayoutWriteSnafu { path: &partial })?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .context(error::RegistryRequestSnafu { uri: &url })?
+        {
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .await
+                .context(error::LayoutWriteSnafu { path: &partial })?;
+        }
+        file.flush()
+            .await
+            .context(error::LayoutWriteSnafu { path: &partial })?;
+        let actual = referrers::finish_digest(hasher);
+        if actual != digest {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return error::DigestMismatchSnafu {
+                uri: url,
+                expected: digest,
+                actual,
+            }
+            .fail();
+        }
+        tokio::fs::rename(&partial, path)
+            .await
+            .context(error::LayoutWriteSnafu { path })
+    }
+
+    /// Whether the repository of `image` already has the blob `digest`.
+    async fn has_blob(&self, image: &ImageReference, digest: &str) -> Result<bool> {
+        let url = image.blob_url(digest);
+        let existing = self.send(image, PUSH, |client| client.head(&url)).await?;
+        Ok(existing.status().is_success())
+    }
+
+    /// Uploads the blob at `path`, whose digest is `digest`, to the repository of `image` unless
+    /// the registry already has it.
+    async fn push_blob(&self, image: &ImageReference, path: &Path, digest: &str) -> Result<()> {
+        if self.has_blob(image, digest).await? {
+            log::debug!("Blob '{digest}' already exists in '{}'", image.repository);
+            return Ok(());
+        }
+        self.upload_blob(image, path, digest).await
+    }
+
+    /// Uploads the blob at `path` to the repository of `image`. The blob is streamed from the file,
+    /// which is opened again if the request has to be made again, rather than read into memory.
+    async fn upload_blob(&self, image: &ImageReference, path: &Path, digest: &str) -> Result<()> {
+        let size = tokio::fs::metadata(path)
+            .await
+            .context(error::LayoutReadSnafu { path })?
+            .len();
+        let start_url = format!("{}/blobs/uploads/", image.url());
+        let response = self
+            .send(image, PUSH, |client| client.post(&start_url))
+            .await?;
+        let response = ensure_success(response, &start_url).await?;
+        let mut upload_url = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|location| response.url().join(location).ok())
+            .context(error::UploadLocationSnafu { uri: &start_url })?;
+        upload_url.query_pairs_mut().append_pair("digest", digest);
+        let response = self
+            .try_send(image, PUSH, |client| {
+                let file = std::fs::File::open(path).context(error::LayoutReadSnafu { path })?;
+                Ok(client
+                    .put(upload_url.clone())
+                    .header(CONTENT_TYPE, "application/octet-stream")
+                    .header(CONTENT_LENGTH, size)
+                    .body(tokio::fs::File::from_std(file)))
+            })
+            .await?;
+        check(response, upload_url.as_str()).await?;
+        Ok(())
+    }
+
+    /// Copies the blob `digest` between repositories unless the target already has it. The blob is
+    /// streamed through a file in `dir`, where it is checked against its digest, rather than held
+    /// in memory.
+    async fn copy_blob(
+        &self,
+        from: &ImageReference,
+        to: &ImageReference,
+        digest: &str,
+        dir: &Path,
+    ) -> Result<()> {
+        if self.has_blob(to, digest).await? {
+            log::debug!("Blob '{digest}' already exists in '{}'", to.repository);
+            return Ok(());
+        }
+        let path = blob_path(dir, digest);
+        self.pull_blob(from, digest, &path).await?;
+        let uploaded = self.upload_blob(to, &path, digest).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        uploaded
+    }
+
+    async fn push_manifest(&self, image: &ImageReference, manifest: &Manifest) -> Result<()> {
+        let url = image.manifest_url();
+        let response = self
+            .send(image, PUSH, |client| {
+                client
+                    .put(&url)
+                    .header(CONTENT_TYPE, &manifest.media_type)
+                    .body(manifest.bytes.clone())
+            })
+            .await?;
+        check(response, &url).await?;
+        Ok(())
+    }
+
+    /// Copies a single-architecture image, its blobs and then its manifest, between repositories.
+    /// Blobs are passed through `dir`.
+    async fn copy_manifest(
+        &self,
+        from: &ImageReference,
+        to: &ImageReference,
+        dir: &Path,
+    ) -> Result<()> {
+        let manifest = self.fetch_manifest(from).await?;
+        let view: ManifestView =
+            serde_json::from_slice(&manifest.bytes).context(error::ManifestDeserializeSnafu)?;
+        for blob in view.blobs() {
+            self.copy_blob(from, to, &blob.digest, dir).await?;
+        }
+        self.push_manifest(to, &manifest).await
+    }
+}
+
+#[async_trait]
+impl ImageToolImpl for RegistryClient {
+    async fn pull_oci_image(&self, path: &Path, uri: &str) -> Result<()> {
+        let (manifest, image) = self
+            .fetch_image_manifest(&ImageReference::parse(uri)?)
+            .await?;
+        let view: ManifestView =
+            serde_json::from_slice(&manifest.bytes).context(error::ManifestDeserializeSnafu)?;
+        for blob in view.blobs() {
+            self.pull_blob(&image, &blob.digest, &blob_path(path, &blob.digest))
+                .await?;
+        }
+
+        let manifest_digest = referrers::digest(&manifest.bytes);
+        let descriptor = Descriptor {
+            media_type: Some(manifest.media_type.clone()),
+            digest: manifest_digest.clone(),
+            size: manifest.bytes.len() as u64,
+            platform: None,
+        };
+        let layout = serde_json::json!({ "imageLayoutVersion": "1.0.0" });
+        let index = serde_json::json!({ "schemaVersion": 2, "manifests": [descriptor] });
+        let files = [
+            (blob_path(path, &manifest_digest), manifest.bytes),
+            (
+                path.join("oci-layout"),
+                serde_json::to_vec(&layout).context(error::LayoutSerializeSnafu)?,
+            ),
+            (
+                path.join("index.json"),
+                serde_json::to_vec(&index).context(error::LayoutSerializeSnafu)?,
+            ),
+        ];
+        for (file, bytes) in files {
+            if let Some(parent) = file.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .context(error::LayoutWriteSnafu { path: parent })?;
+            }
+            tokio::fs::write(&file, bytes)
+                .await
+                .context(error::LayoutWriteSnafu { path: &file })?;
+        }
+        Ok(())
+    }
+
+    async fn get_config(&self, uri: &str) -> Result<ConfigView> {
+        let (manifest, image) = self
+            .fetch_image_manifest(&ImageReference::parse(uri)?)
+            .await?;
+        let view: ManifestView =
+            serde_json::from_slice(&manifest.bytes).context(error::ManifestDeserializeSnafu)?;
+        let config = view.config.context(error::MissingConfigSnafu { uri })?;
+        let bytes = self.fetch_blob(&image, &config.digest).await?;
+        let image_view: ImageView =
+            serde_json::from_slice(&bytes).context(error::ConfigDeserializeSnafu)?;
+        Ok(image_view.into())
+    }
+
+    async fn get_manifest(&self, uri: &str) -> Result<Vec<u8>> {
+        Ok(self
+            .fetch_manifest(&ImageReference::parse(uri)?)
+            .await?
+            .bytes)
+    }
+
+    async fn get_digest(&self, uri: &str) -> Result<String> {
+        Ok(referrers::digest(&self.get_manifest(uri).await?))
+    }
+
+    async fn list_tags(&self, repository: &str) -> Result<Vec<String>> {
+        let image = ImageReference::parse(repository)?;
+        let mut url = format!("{}/tags/list", image.url());
+        let mut tags = Vec::new();
+        loop {
+            let response = self.send(&image, PULL, |client| client.get(&url)).await?;
+            // Registries which paginate give the next page as a relative link.
+            let next = response
+                .headers()
+                .get(LINK)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|link| link.split(';').next())
+                .map(|target| target.trim().trim_start_matches('<').trim_end_matches('>'))
+                .and_then(|target| response.url().join(target).ok());
+            let bytes = check(response, &url).await?;
+            let page: TagList = serde_json::from_slice(&bytes)
+                .context(error::RegistryResponseSnafu { uri: &url })?;
+            tags.extend(page.tags.unwrap_or_default());
+            match next {
+                Some(next) => url = next.to_string(),
+                None => return Ok(tags),
+            }
+        }
+    }
+
+    async fn push_oci_archive(&self, path: &Path, uri: &str) -> Result<()> {
+        let temp_dir = TempDir::new().context(error::RegistryTempSnafu)?;
+        let archive = File::open(path).context(error::ArchiveReadSnafu)?;
+        TarArchive::new(archive)
+            .unpack(temp_dir.path())
+            .context(error::ArchiveExtractSnafu)?;
+        self.push_oci_layout(temp_dir.path(), uri).await
+    }
+
+    async fn copy_image(&self, from: &str, to: &str) -> Result<()> {
+        let source = ImageReference::parse(from)?;
+        let target = ImageReference::parse(to)?;
+        let temp_dir = TempDir::new().context(error::RegistryTempSnafu)?;
+        let manifest = self.fetch_manifest(&source).await?;
+        let view: ManifestView =
+            serde_json::from_slice(&manifest.bytes).context(error::ManifestDeserializeSnafu)?;
+        for descriptor in &view.manifests {
+            self.copy_manifest(
+                &source.with_reference(&descriptor.digest),
+                &target.with_reference(&descriptor.digest),
+                temp_dir.path(),
+            )
+            .await?;
+        }
+        for blob in view.blobs() {
+            self.copy_blob(&source, &target, &blob.digest, temp_dir.path())
+                .await?;
+        }
+        self.push_manifest(&target, &manifest).await
+    }
+
+    async fn push_multi_platform_manifest(
+        &self,
+        platform_images: Vec<(DockerArchitecture, String)>,
+        uri: &str,
+    ) -> Result<()> {
+        let mut manifests = Vec::new();
+        for (arch, image_uri) in platform_images {
+            let manifest = self
+                .fetch_manifest(&ImageReference::parse(&image_uri)?)
+                .await?;
+            manifests.push(Descriptor {
+                media_type: Some(manifest.media_type),
+                digest: referrers::digest(&manifest.bytes),
+                size: manifest.bytes.len() as u64,
+                platform: Some(Platform {
+                    architecture: arch.to_string(),
+                    os: "linux".to_string(),
+                }),
+            });
+        }
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_INDEX,
+            "manifests": manifests,
+        });
+        let manifest = Manifest {
+            bytes: serde_json::to_vec(&index).context(error::LayoutSerializeSnafu)?,
+            media_type: OCI_INDEX.to_string(),
+        };
+        self.push_manifest(&ImageReference::parse(uri)?, &manifest)
+            .await
+    }
+
+    async fn push_oci_layout(&self, dir: &Path, uri: &str) -> Result<()> {
+        let image = ImageReference::parse(uri)?;
+        let index_path = dir.join("index.json");
+        let index: ManifestView = serde_json::from_slice(
+            &std::fs::read(&index_path).context(error::LayoutReadSnafu { path: &index_path })?,
+        )
+        .context(error::LayoutParseSnafu { path: &index_path })?;
+        let descriptor = index
+            .manifests
+            .first()
+            .context(error::LayoutImageNotFoundSnafu { uri })?;
+        let manifest_path = blob_path(dir, &descriptor.digest);
+        let bytes = std::fs::read(&manifest_path).context(error::LayoutReadSnafu {
+            path: &manifest_path,
+        })?;
+        let view: ManifestView =
+            serde_json::from_slice(&bytes).context(error::ManifestDeserializeSnafu)?;
+        for blob in view.blobs() {
+            self.push_blob(&image, &blob_path(dir, &blob.digest), &blob.digest)
+                .await?;
+        }
+        let media_type = view
+            .media_type
+            .or(descriptor.media_type.clone())
+            .unwrap_or_else(|| OCI_MANIFEST.to_string());
+        self.push_manifest(&image, &Manifest { bytes, media_type })
+            .await
+    }
+
+    async fn append_to_index(
+        &self,
+        index_uri: &str,
+        exists: bool,
+        manifest_uri: &str,
+    ) -> Result<()> {
+        let index_image = ImageReference::parse(index_uri)?;
+        let mut index: serde_json::Value = if exists {
+            serde_json::from_slice(&self.fetch_manifest(&index_image).await?.bytes)
+                .context(error::ManifestDeserializeSnafu)?
+        } else {
+            serde_json::json!({ "schemaVersion": 2, "mediaType": OCI_INDEX, "manifests": [] })
+        };
+        let manifest = self
+            .fetch_manifest(&ImageReference::parse(manifest_uri)?)
+            .await?;
+        let view: ManifestView =
+            serde_json::from_slice(&manifest.bytes).context(error::ManifestDeserializeSnafu)?;
+        let mut descriptor = serde_json::json!({
+            "mediaType": manifest.media_type,
+            "digest": referrers::digest(&manifest.bytes),
+            "size": manifest.bytes.len(),
+        });
+        if let Some(artifact_type) = view.artifact_type {
+            descriptor["artifactType"] = serde_json::Value::String(artifact_type);
+        }
+        match index.get_mut("manifests").and_then(|m| m.as_array_mut()) {
+            Some(manifests) => manifests.push(descriptor),
+            None => index["manifests"] = serde_json::Value::Array(vec![descriptor]),
+        }
+        let manifest = Manifest {
+            bytes: serde_json::to_vec(&index).context(error::LayoutSerializeSnafu)?,
+            media_type: OCI_INDEX.to_string(),
+        };
+        self.push_manifest(&index_image, &manifest).await
+    }
+
+    async fn get_blob(&self, uri: &str) -> Result<Vec<u8>> {
+        let image = ImageReference::parse(uri)?;
+        let digest = image.reference.clone();
+        self.fetch_blob(&image, &digest).await
+    }
+
+    fn set_env(&mut self, key: &str, value: &std::ffi::OsStr) {
+        self.env.push((key.to_string(), value.to_owned()));
+        if key.to_ascii_uppercase().ends_with("_PROXY") {
+            match build_client(&self.env) {
+                Ok(client) => self.client = client,
+                Err(e) => log::warn!("Unable to apply {key} to the registry client: {e}"),
+            }
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ImageToolImpl>> {
+        // The copy is given its own credentials, since it may be given a different environment.
+        Some(Box::new(Self {
+            authorizations: Arc::new(Mutex::new(HashMap::new())),
+            ..self.clone()
+        }))
+    }
+}
+
+/// A `WWW-Authenticate` challenge, such as
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+#[derive(Debug)]
+struct Challenge {
+    /// The lowercase authentication scheme
+    scheme: String,
+    params: HashMap<String, String>,
+}
+
+impl Challenge {
+    fn parse(header: &str) -> Option<Self> {
+        let header = header.trim();
+        let (scheme, mut rest) = header.split_once(' ').unwrap_or((header, ""));
+        let mut params = HashMap::new();
+        rest = rest.trim();
+        while !rest.is_empty() {
+            let (key, after) = rest.split_once('=')?;
+            let after = after.trim_start();
+            // Quoted values, such as a scope of `repository:name:pull,push`, may contain commas.
+            let (value, remaining) = match after.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"')?,
+                None => after.split_once(',').unwrap_or((after, "")),
+            };
+            params.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+            rest = remaining.trim_start().trim_start_matches(',').trim_start();
+        }
+        Some(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            params,
+        })
+    }
+}
+
+/// Builds the HTTP client, with the proxies set in `env` if there are any. Otherwise the proxies
+/// are taken from the process environment.
+fn build_client(env: &[(String, OsString)]) -> Result<Client> {
+    let var = |key: &str| {
+        env.iter()
+            .rev()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.to_string_lossy().to_string())
+    };
+    let no_proxy = var("NO_PROXY").and_then(|value| reqwest::NoProxy::from_string(&value));
+    let mut builder = Client::builder().user_agent(concat!(
+        env!("CARGO_PKG_NAME"),
+        "/",
+        env!("CARGO_PKG_VERSION")
+    ));
+    if let Some(url) = var("HTTP_PROXY").filter(|url| !url.is_empty()) {
+        let proxy = reqwest::Proxy::http(url.as_str()).context(error::RegistryClientSnafu)?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+    }
+    if let Some(url) = var("HTTPS_PROXY").filter(|url| !url.is_empty()) {
+        let proxy = reqwest::Proxy::https(url.as_str()).context(error::RegistryClientSnafu)?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy));
+    }
+    builder.build().context(error::RegistryClientSnafu)
+}
+
+async fn execute(request: RequestBuilder, authorization: Option<&str>) -> Result<Response> {
+    let request = match authorization {
+        Some(authorization) => request.header(AUTHORIZATION, authorization),
+        None => request,
+    };
+    let (client, request) = request.build_split();
+    let request = request.context(error::RegistryClientSnafu)?;
+    let uri = request.url().to_string();
+    client
+        .execute(request)
+        .await
+        .context(error::RegistryRequestSnafu { uri })
+}
+
+/// Fails with the registry's error message unless `response` was successful.
+async fn ensure_success(response: Response, uri: &str) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    // Only the delay in seconds form of `Retry-After` is used, not the HTTP date form.
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    let message = response.text().await.unwrap_or_default();
+    error::RegistryStatusSnafu {
+        uri,
+        status: status.as_u16(),
+        message: message.trim(),
+        retry_after,
+    }
+    .fail()
+}
+
+/// Reads the body of `response`, failing with the registry's error message unless it was
+/// successful.
+async fn check(response: Response, uri: &str) -> Result<Vec<u8>> {
+    let response = ensure_success(response, uri).await?;
+    Ok(response
+        .bytes()
+        .await
+        .context(error::RegistryRequestSnafu { uri })?
+        .to_vec())
+}
+
+fn blob_path(dir: &Path, digest: &str) -> PathBuf {
+    dir.join("blobs").join(digest.replace(':', "/"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn reference(registry: &str, repository: &str, reference: &str) -> ImageReference {
+        ImageReference {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_image_references() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        assert_eq!(
+            ImageReference::parse("alpine").unwrap(),
+            reference(DOCKER_HUB, "library/alpine", "latest")
+        );
+        assert_eq!(
+            ImageReference::parse("bottlerocket/sdk:v1").unwrap(),
+            reference(DOCKER_HUB, "bottlerocket/sdk", "v1")
+        );
+        assert_eq!(
+            ImageReference::parse("public.ecr.aws/bottlerocket/sdk:v1").unwrap(),
+            reference("public.ecr.aws", "bottlerocket/sdk", "v1")
+        );
+        assert_eq!(
+            ImageReference::parse("localhost:5000/kits/core").unwrap(),
+            reference("localhost:5000", "kits/core", "latest")
+        );
+        assert_eq!(
+            ImageReference::parse(&format!("registry:5000/kits/core:v1@{digest}")).unwrap(),
+            reference("registry:5000", "kits/core", &digest)
+        );
+        assert!(ImageReference::parse("example.com/").is_err());
+    }
+
+    #[test]
+    fn parses_challenges() {
+        let challenge = Challenge::parse(
+            r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:kits/core:pull,push""#,
+        )
+        .unwrap();
+        assert_eq!(challenge.scheme, "bearer");
+        assert_eq!(challenge.params.len(), 3);
+        assert_eq!(challenge.params["realm"], "https://auth.example.com/token");
+        assert_eq!(challenge.params["service"], "registry.example.com");
+        assert_eq!(challenge.params["scope"], "repository:kits/core:pull,push");
+
+        let challenge = Challenge::parse(r#"Basic realm=registry, charset="UTF-8""#).unwrap();
+        assert_eq!(challenge.scheme, "basic");
+        assert_eq!(challenge.params["realm"], "registry");
+        assert_eq!(challenge.params["charset"], "UTF-8");
+    }
+
+    /// Serves `responses` of `(path, headers, body)` from a registry on the local host, and returns
+    /// the registry's host. Paths without a response are not found.
+    async fn serve(responses: Vec<(String, String, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut buf = [0; 1024];
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let path = request.split(' ').nth(1).unwrap();
+                let response = match responses.iter().find(|(p, _, _)| p == path) {
+                    Some((_, headers, body)) => format!(
+                        "HTTP/1.1 200 OK\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    ),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string(),
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        host
+    }
+
+    fn client() -> RegistryClient {
+        RegistryClient {
+            client: Client::builder().no_proxy().build().unwrap(),
+            env: Vec::new(),
+            authorizations: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    #[tokio::test]
+    async fn list_tags_follows_pages() {
+        let host = serve(vec![
+            (
+                "/v2/kits/core/tags/list".to_string(),
+                "Link: </v2/kits/core/tags/list?last=b&n=2>; rel=\"next\"\r\n".to_string(),
+                r#"{"name":"kits/core","tags":["a","b"]}"#.to_string(),
+            ),
+            (
+                "/v2/kits/core/tags/list?last=b&n=2".to_string(),
+                String::new(),
+                r#"{"name":"kits/core","tags":["c"]}"#.to_string(),
+            ),
+        ])
+        .await;
+        let tags = client()
+            .list_tags(&format!("{host}/kits/core"))
+            .await
+            .unwrap();
+        assert_eq!(tags, vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn docker_lists_tags_from_the_registry() {
+        let host = serve(vec![(
+            "/v2/kits/core/tags/list".to_string(),
+            String::new(),
+            r#"{"name":"kits/core","tags":["v1.0.0"]}"#.to_string(),
+        )])
+        .await;
+        let mut cli = crate::cli::CommandLine::new("docker".into());
+        cli.env.push(("NO_PROXY".to_string(), "127.0.0.1".into()));
+        let docker = crate::docker::DockerCLI { cli };
+        let tags = docker
+            .list_tags(&format!("{host}/kits/core"))
+            .await
+            .unwrap();
+        assert_eq!(tags, vec!["v1.0.0"]);
+    }
+
+    #[tokio::test]
+    async fn image_manifest_must_match_host_platform() {
+        let list = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_INDEX,
+            "manifests": [{
+                "mediaType": OCI_MANIFEST,
+                "digest": format!("sha256:{}", "a".repeat(64)),
+                "size": 100,
+                "platform": { "architecture": "s390x", "os": "linux" }
+            }]
+        });
+        let host = serve(vec![(
+            "/v2/kits/core/manifests/v1".to_string(),
+            format!("Content-Type: {OCI_INDEX}\r\n"),
+            list.to_string(),
+        )])
+        .await;
+        let image = ImageReference::parse(&format!("{host}/kits/core:v1")).unwrap();
+        let err = client().fetch_image_manifest(&image).await.unwrap_err();
+        let arch = DockerArchitecture::try_from(std::env::consts::ARCH).unwrap();
+        assert!(
+            err.to_string().contains(&format!("'linux/{arch}'")),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn pushes_blobs_from_disk() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let (uploads, mut uploaded) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let mut buf = [0; 1024];
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let split = request.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+                let head = String::from_utf8(request[..split].to_vec()).unwrap();
+                let mut body = request[split..].to_vec();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_ascii_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(|n| n.parse().unwrap())
+                    })
+                    .unwrap_or(0);
+                while body.len() < length {
+                    let mut buf = [0; 1024];
+                    let read = stream.read(&mut buf).await.unwrap();
+                    body.extend_from_slice(&buf[..read]);
+                }
+                let response = match head.split(' ').next().unwrap() {
+                    "POST" => {
+                        "HTTP/1.1 202 Accepted\r\nLocation: /v2/kits/core/blobs/uploads/1\r\n"
+                    }
+                    "PUT" => {
+                        uploads.send(body).unwrap();
+                        "HTTP/1.1 201 Created\r\n"
+                    }
+                    _ => "HTTP/1.1 404 Not Found\r\n",
+                };
+                let response = format!("{response}Content-Length: 0\r\nConnection: close\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let dir = TempDir::new().unwrap();
+        let blob = vec![7u8; 100_000];
+        let digest = referrers::digest(&blob);
+        let path = blob_path(dir.path(), &digest);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &blob).unwrap();
+        let image = ImageReference::parse(&format!("{host}/kits/core:v1")).unwrap();
+        client().push_blob(&image, &path, &digest).await.unwrap();
+        assert_eq!(uploaded.recv().await.unwrap(), blob);
+
+        // A blob which can't be read fails before anything is uploaded.
+        let missing = dir.path().join("missing");
+        assert!(client().push_blob(&image, &missing, &digest).await.is_err());
+        assert!(uploaded.try_recv().is_err());
+    }
+}
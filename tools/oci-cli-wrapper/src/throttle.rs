@@ -0,0 +1,158 @@
+//! Keeps the operations made against each registry, such as resolving a tag or pulling an image,
+//! within the limits configured for it, and waits out a registry's rate limiting when it reports
+//! that too many requests have been made.
+//!
+//! Registries such as public ECR and Docker Hub answer `429 Too Many Requests` once a client
+//! exceeds its quota, usually with a `Retry-After` header saying how long to stop for. Every
+//! request to that registry waits until then, rather than only the one which was refused, since
+//! the others would be refused too.
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+use tokio::time::Instant;
+
+use crate::{error::Error, Result};
+
+/// How many times a rate limited request is retried before its error is returned.
+const MAX_RETRIES: u32 = 5;
+
+/// How long to wait before retrying a rate limited request when the registry does not say. This is
+/// doubled for each retry, up to `MAX_BACKOFF`.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Limits on the operations made against a registry. An operation is one call of the image tool,
+/// such as fetching a manifest or pulling or pushing a whole image, which may be made up of many
+/// HTTP requests; crane and docker don't expose their requests to be limited one by one.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct RegistryLimits {
+    /// The most operations to have in flight at once
+    pub max_concurrent: Option<usize>,
+    /// The most operations to start each minute
+    pub requests_per_minute: Option<u32>,
+}
+
+impl RegistryLimits {
+    /// The limits which satisfy both `self` and `other`.
+    pub fn min(self, other: Self) -> Self {
+        fn min<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        Self {
+            max_concurrent: min(self.max_concurrent, other.max_concurrent),
+            requests_per_minute: min(self.requests_per_minute, other.requests_per_minute),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    limits: HashMap<String, RegistryLimits>,
+    registries: Mutex<HashMap<String, Arc<RegistryThrottle>>>,
+}
+
+#[derive(Debug)]
+struct RegistryThrottle {
+    permits: Option<Semaphore>,
+    /// The time between the starts of requests
+    interval: Duration,
+    /// When the next request may start, by the rate limit or the registry's `Retry-After`
+    next_start: AsyncMutex<Instant>,
+}
+
+impl Throttle {
+    pub(crate) fn new(limits: HashMap<String, RegistryLimits>) -> Self {
+        Self {
+            limits,
+            registries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `operation` against `registry` within its limits, retrying it while the registry
+    /// reports that too many requests have been made. `on_retry` is told of each refused request
+    /// and how long the retry waits.
+    pub(crate) async fn run<T, F, Fut, R>(
+        &self,
+        registry: &str,
+        operation: F,
+        on_retry: R,
+    ) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        R: Fn(&Error, Duration),
+    {
+        let throttle = self.registry(registry);
+        let mut retries = 0;
+        loop {
+            let _permit = match &throttle.permits {
+                Some(permits) => permits.acquire().await.ok(),
+                None => None,
+            };
+            throttle.wait_turn().await;
+            match operation().await {
+                Err(e) if e.is_rate_limited() && retries < MAX_RETRIES => {
+                    let wait = e
+                        .retry_after()
+                        .unwrap_or_else(|| (INITIAL_BACKOFF * 2u32.pow(retries)).min(MAX_BACKOFF));
+                    log::warn!(
+                        "Registry '{registry}' is rate limiting requests, retrying in {}s",
+                        wait.as_secs()
+                    );
+                    on_retry(&e, wait);
+                    throttle.pause(wait).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn registry(&self, registry: &str) -> Arc<RegistryThrottle> {
+        let mut registries = self
+            .registries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        registries
+            .entry(registry.to_string())
+            .or_insert_with(|| {
+                let limits = self.limits.get(registry).copied().unwrap_or_default();
+                Arc::new(RegistryThrottle {
+                    permits: limits
+                        .max_concurrent
+                        .map(|permits| Semaphore::new(permits.max(1))),
+                    interval: limits
+                        .requests_per_minute
+                        .map(|rate| Duration::from_secs(60) / rate.max(1))
+                        .unwrap_or_default(),
+                    next_start: AsyncMutex::new(Instant::now()),
+                })
+            })
+            .clone()
+    }
+}
+
+impl RegistryThrottle {
+    /// Waits until a request may start, and reserves the next start for the request after.
+    async fn wait_turn(&self) {
+        let start = {
+            let mut next_start = self.next_start.lock().await;
+            let start = (*next_start).max(Instant::now());
+            *next_start = start + self.interval;
+            start
+        };
+        tokio::time::sleep_until(start).await;
+    }
+
+    /// Holds back every request for `wait`.
+    async fn pause(&self, wait: Duration) {
+        let mut next_start = self.next_start.lock().await;
+        *next_start = (*next_start).max(Instant::now() + wait);
+    }
+}
@@ -111,6 +111,41 @@ impl InfraConfig {
 #[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct Vendor {
     pub registry: String,
+    /// Sign images published to the registry with cosign. Images are left unsigned without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<ImageSigningConfig>,
+}
+
+/// How cosign signs, and attaches attestations to, the images published to a vendor's registry
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImageSigningConfig {
+    pub key: CosignKeyConfig,
+    /// Also record signatures in the public Rekor transparency log
+    #[serde(default)]
+    pub tlog_upload: bool,
+}
+
+/// Location of the key cosign signs images with
+// These variant names are lowercase to match `SigningKeyConfig`.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum CosignKeyConfig {
+    /// A cosign private key, whose password is read from `COSIGN_PASSWORD`
+    file { path: PathBuf },
+    /// An AWS KMS key
+    kms { key_arn: String },
+}
+
+impl CosignKeyConfig {
+    /// The key reference which cosign is given with `--key`
+    pub fn key_ref(&self) -> String {
+        match self {
+            CosignKeyConfig::file { path } => path.display().to_string(),
+            CosignKeyConfig::kms { key_arn } => format!("awskms:///{key_arn}"),
+        }
+    }
 }
 
 /// S3-specific TUF infrastructure configuration
@@ -10,12 +10,12 @@ pub(crate) mod wait;
 use crate::aws::ami::launch_permissions::get_launch_permissions;
 use crate::aws::ami::public::ami_is_public;
 use crate::aws::publish_ami::{get_snapshots, modify_image, modify_snapshots, ModifyOptions};
-use crate::aws::{client::build_client_config, parse_arch, region_from_string};
+use crate::aws::{client::build_client_config, parse_arch, parse_tag, region_from_string};
 use crate::Args;
 use aws_sdk_ebs::Client as EbsClient;
 use aws_sdk_ec2::error::{ProvideErrorMetadata, SdkError};
 use aws_sdk_ec2::operation::copy_image::{CopyImageError, CopyImageOutput};
-use aws_sdk_ec2::types::{ArchitectureValues, OperationType};
+use aws_sdk_ec2::types::{ArchitectureValues, OperationType, Tag};
 use aws_sdk_ec2::{config::Region, Client as Ec2Client};
 use aws_sdk_sts::operation::get_caller_identity::{
     GetCallerIdentityError, GetCallerIdentityOutput,
@@ -77,12 +77,17 @@ pub(crate) struct AmiArgs {
     /// If specified, save created regional AMI IDs in JSON at this path.
     #[arg(long)]
     ami_output: Option<PathBuf>,
+
+    /// Tags, as KEY=VALUE, to apply to the AMI in every region
+    #[arg(long = "tag", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
 }
 
 /// Common entrypoint from main()
 pub(crate) async fn run(args: &Args, ami_args: &AmiArgs) -> Result<()> {
     match _run(args, ami_args).await {
         Ok(amis) => {
+            tag_amis(args, ami_args, &amis).await?;
             // Write the AMI IDs to file if requested
             if let Some(ref path) = ami_args.ami_output {
                 write_amis(path, &amis)
@@ -421,6 +426,48 @@ async fn _run(args: &Args, ami_args: &AmiArgs) -> Result<HashMap<String, Image>>
     Ok(amis)
 }
 
+/// Applies the requested tags to the AMI in each region, whether it was just registered, copied,
+/// or found already registered.
+async fn tag_amis(args: &Args, ami_args: &AmiArgs, amis: &HashMap<String, Image>) -> Result<()> {
+    if ami_args.tags.is_empty() {
+        return Ok(());
+    }
+    let infra_config = InfraConfig::from_path_or_lock(&args.infra_config_path, true)
+        .context(error::ConfigSnafu)?;
+    let aws = infra_config.aws.unwrap_or_default();
+    let base_region = ami_args
+        .regions
+        .first()
+        .or(aws.regions.front())
+        .map(|name| region_from_string(name))
+        .context(error::MissingConfigSnafu {
+            missing: "aws.regions",
+        })?;
+
+    let tags: Vec<Tag> = ami_args
+        .tags
+        .iter()
+        .map(|(key, value)| Tag::builder().key(key).value(value).build())
+        .collect();
+    for (region_name, image) in amis {
+        let region = region_from_string(region_name);
+        let client_config = build_client_config(&region, &base_region, &aws).await;
+        let ec2_client = Ec2Client::new(&client_config);
+        info!("Tagging AMI {} in {}", image.id, region_name);
+        ec2_client
+            .create_tags()
+            .resources(&image.id)
+            .set_tags(Some(tags.clone()))
+            .send()
+            .await
+            .context(error::TagAmiSnafu {
+                image_id: &image.id,
+                region: region_name,
+            })?;
+    }
+    Ok(())
+}
+
 /// If JSON output was requested, we serialize out a mapping of region to AMI information; this
 /// struct holds the information we save about each AMI.  The `ssm` subcommand uses this
 /// information to populate templates representing SSM parameter names and values.
@@ -500,6 +547,7 @@ async fn get_account_ids(
 mod error {
     use crate::aws::{ami, publish_ami};
     use aws_sdk_ec2::error::SdkError;
+    use aws_sdk_ec2::operation::create_tags::CreateTagsError;
     use aws_sdk_ec2::operation::modify_image_attribute::ModifyImageAttributeError;
     use aws_sdk_sts::operation::get_caller_identity::GetCallerIdentityError;
     use snafu::Snafu;
@@ -597,6 +645,13 @@ mod error {
             source: ami::register::Error,
         },
 
+        #[snafu(display("Failed to tag AMI {} in {}: {}", image_id, region, source))]
+        TagAmi {
+            image_id: String,
+            region: String,
+            source: SdkError<CreateTagsError>,
+        },
+
         #[snafu(display("AMI '{}' in {} did not become available: {}", id, region, source))]
         WaitAmi {
             id: String,
@@ -8,6 +8,7 @@ pub(crate) mod ami;
 pub(crate) mod promote_ssm;
 pub(crate) mod publish_ami;
 pub(crate) mod ssm;
+pub(crate) mod upload_images;
 pub(crate) mod validate_ami;
 pub(crate) mod validate_ssm;
 
@@ -29,6 +30,14 @@ pub(crate) fn parse_arch(input: &str) -> Result<ArchitectureValues> {
     }
 }
 
+/// Parses a tag given as KEY=VALUE, for subcommands which tag what they create.
+pub(crate) fn parse_tag(input: &str) -> std::result::Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected a tag as KEY=VALUE, found '{input}'")),
+    }
+}
+
 mod error {
     use snafu::Snafu;
 
@@ -0,0 +1,447 @@
+//! The upload_images module owns the 'upload-images' subcommand, which uploads the artifacts of a
+//! built variant to S3. The artifacts are the files listed in the `SHA256SUMS` manifest which the
+//! variant build writes, and each is checked against the manifest before it is uploaded. Each
+//! object carries its SHA-256 checksum, which S3 verifies on upload, and the requested tags, which
+//! bucket lifecycle rules can match. The manifest and its signature, `SHA256SUMS.sig`, are uploaded
+//! with the artifacts so that downloads can be checked with `twoliter verify-artifacts`.
+//!
+//! S3 only accepts objects of up to 5 GiB in one request, so files larger than a part are uploaded
+//! in parts, each of which S3 verifies against its own checksum.
+
+use crate::aws::client::build_client_config;
+use crate::aws::{parse_tag, region_from_string};
+use crate::Args;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client as S3Client;
+use aws_smithy_types::byte_stream::Length;
+use base64::Engine;
+use buildsys::checksums::{self as checksum_manifest, SIGNATURE_FILE};
+use clap::Parser;
+use log::{info, trace, warn};
+use pubsys_config::InfraConfig;
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// The size of each part of a multipart upload. Files no larger than this are uploaded in one
+/// request. S3 allows at most 10,000 parts, so this bounds files at 640 GiB.
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Uploads the artifacts of a built variant to S3
+#[derive(Debug, Parser)]
+pub(crate) struct UploadImagesArgs {
+    /// The `SHA256SUMS` manifest written by the variant build. Every file it lists is uploaded,
+    /// along with the manifest and its signature if the build signed it
+    #[arg(long)]
+    checksums: PathBuf,
+
+    /// The bucket to upload to
+    #[arg(long)]
+    bucket: String,
+
+    /// The prefix of the uploaded objects' keys, such as `aws-k8s-1.30/x86_64/1.20.0`
+    #[arg(long, default_value = "")]
+    prefix: String,
+
+    /// The region of the bucket. Defaults to the first region in Infra.toml
+    #[arg(long)]
+    region: Option<String>,
+
+    /// Tags, as KEY=VALUE, to apply to each uploaded object
+    #[arg(long = "tag", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+}
+
+/// Common entrypoint from main()
+pub(crate) async fn run(args: &Args, upload_args: &UploadImagesArgs) -> Result<()> {
+    // If a lock file exists, use that, otherwise use Infra.toml or default
+    let infra_config = InfraConfig::from_path_or_lock(&args.infra_config_path, true)
+        .context(error::ConfigSnafu)?;
+    trace!("Using infra config: {:?}", infra_config);
+    let aws = infra_config.aws.unwrap_or_default();
+
+    let region = upload_args
+        .region
+        .as_ref()
+        .or(aws.regions.front())
+        .map(|name| region_from_string(name))
+        .context(error::MissingConfigSnafu {
+            missing: "aws.regions",
+        })?;
+    let client_config = build_client_config(&region, &region, &aws).await;
+    let s3_client = S3Client::new(&client_config);
+    let tagging = tagging(&upload_args.tags);
+
+    let checksums = &upload_args.checksums;
+    ensure!(
+        checksums.is_file(),
+        error::MissingManifestSnafu { path: checksums }
+    );
+    let dir = checksums.parent().unwrap_or(Path::new(""));
+    let manifest =
+        std::fs::read_to_string(checksums).context(error::ReadManifestSnafu { path: checksums })?;
+    let entries = checksum_manifest::parse(&manifest)
+        .context(error::InvalidManifestSnafu { path: checksums })?;
+
+    for (expected, relative) in &entries {
+        let path = dir.join(relative);
+        ensure!(path.is_file(), error::MissingArtifactSnafu { path });
+        let digest = sha256(&path)?;
+        ensure!(
+            &hex::encode(&digest) == expected,
+            error::ChecksumMismatchSnafu { path }
+        );
+        let key = object_key(&upload_args.prefix, &relative.to_string_lossy());
+        upload_file(
+            &s3_client,
+            &upload_args.bucket,
+            &key,
+            &path,
+            &digest,
+            &tagging,
+        )
+        .await?;
+    }
+
+    // The manifest and its signature go last, so that they are only uploaded once every file they
+    // cover is.
+    let mut manifests = vec![checksums.clone()];
+    let signature = checksums.with_file_name(SIGNATURE_FILE);
+    if signature.is_file() {
+        manifests.push(signature);
+    } else {
+        warn!(
+            "'{}' is not signed, so downloads can't be verified",
+            checksums.display()
+        );
+    }
+    for path in &manifests {
+        let file_name = path
+            .file_name()
+            .context(error::InvalidPathSnafu { path })?
+            .to_string_lossy();
+        let key = object_key(&upload_args.prefix, &file_name);
+        let digest = sha256(path)?;
+        upload_file(
+            &s3_client,
+            &upload_args.bucket,
+            &key,
+            path,
+            &digest,
+            &tagging,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Uploads the file at `path`, whose SHA-256 digest is `digest`, to `key`, in parts if it is larger
+/// than a part.
+async fn upload_file(
+    s3_client: &S3Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    digest: &[u8],
+    tagging: &Option<String>,
+) -> Result<()> {
+    info!("Uploading {} to s3://{}/{}", path.display(), bucket, key);
+    let size = path
+        .metadata()
+        .context(error::ChecksumSnafu { path })?
+        .len();
+    if size > PART_SIZE {
+        return upload_parts(s3_client, bucket, key, path, size, tagging.clone()).await;
+    }
+    let body = ByteStream::from_path(path)
+        .await
+        .context(error::ReadArtifactSnafu { path })?;
+    s3_client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .body(body)
+        .checksum_sha256(base64::engine::general_purpose::STANDARD.encode(digest))
+        .set_tagging(tagging.clone())
+        .send()
+        .await
+        .context(error::PutObjectSnafu { key })?;
+    Ok(())
+}
+
+/// Uploads the file at `path`, of `size` bytes, to `key` in parts of `PART_SIZE`. The upload is
+/// aborted if any part fails, so that S3 doesn't keep the parts already uploaded.
+async fn upload_parts(
+    s3_client: &S3Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    size: u64,
+    tagging: Option<String>,
+) -> Result<()> {
+    let upload_id = s3_client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .checksum_algorithm(ChecksumAlgorithm::Sha256)
+        .set_tagging(tagging)
+        .send()
+        .await
+        .context(error::CreateMultipartUploadSnafu { key })?
+        .upload_id
+        .context(error::MissingUploadIdSnafu { key })?;
+
+    let result = async {
+        let mut parts = Vec::new();
+        for (index, (offset, length)) in part_ranges(size).into_iter().enumerate() {
+            let part_number = index as i32 + 1;
+            let checksum = base64::engine::general_purpose::STANDARD
+                .encode(sha256_range(path, offset, length)?);
+            let body = ByteStream::read_from()
+                .path(path)
+                .offset(offset)
+                .length(Length::Exact(length))
+                .build()
+                .await
+                .context(error::ReadArtifactSnafu { path })?;
+            trace!("Uploading part {} of '{}'", part_number, key);
+            let output = s3_client
+                .upload_part()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .part_number(part_number)
+                .body(body)
+                .checksum_sha256(&checksum)
+                .send()
+                .await
+                .context(error::UploadPartSnafu { key, part_number })?;
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(output.e_tag)
+                    .checksum_sha256(checksum)
+                    .build(),
+            );
+        }
+        s3_client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .context(error::CompleteMultipartUploadSnafu { key })?;
+        Ok(())
+    }
+    .await;
+
+    if result.is_err() {
+        if let Err(e) = s3_client
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .send()
+            .await
+        {
+            warn!("Failed to abort the upload of '{}': {}", key, e);
+        }
+    }
+    result
+}
+
+/// The offset and length of each part of a file of `size` bytes.
+fn part_ranges(size: u64) -> Vec<(u64, u64)> {
+    (0..size)
+        .step_by(PART_SIZE as usize)
+        .map(|offset| (offset, PART_SIZE.min(size - offset)))
+        .collect()
+}
+
+/// The key of the object holding `file_name` under `prefix`.
+fn object_key(prefix: &str, file_name: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        file_name.to_string()
+    } else {
+        format!("{prefix}/{file_name}")
+    }
+}
+
+/// The tags in the URL-encoded form S3 expects, if there are any.
+fn tagging(tags: &[(String, String)]) -> Option<String> {
+    if tags.is_empty() {
+        return None;
+    }
+    Some(
+        url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(tags)
+            .finish(),
+    )
+}
+
+/// The SHA-256 digest of the file at `path`.
+fn sha256(path: &Path) -> Result<Vec<u8>> {
+    let mut file = File::open(path).context(error::ChecksumSnafu { path })?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).context(error::ChecksumSnafu { path })?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// The SHA-256 digest of the `length` bytes at `offset` in the file at `path`.
+fn sha256_range(path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+    let mut file = File::open(path).context(error::ChecksumSnafu { path })?;
+    file.seek(SeekFrom::Start(offset))
+        .context(error::ChecksumSnafu { path })?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file.take(length), &mut hasher).context(error::ChecksumSnafu { path })?;
+    Ok(hasher.finalize().to_vec())
+}
+
+mod error {
+    use aws_sdk_s3::error::SdkError;
+    use aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadError;
+    use aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadError;
+    use aws_sdk_s3::operation::put_object::PutObjectError;
+    use aws_sdk_s3::operation::upload_part::UploadPartError;
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub(crate) enum Error {
+        #[snafu(display("'{}' does not match its checksum in SHA256SUMS", path.display()))]
+        ChecksumMismatch { path: PathBuf },
+
+        #[snafu(display("Failed to checksum '{}': {}", path.display(), source))]
+        Checksum {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to complete the upload of '{}': {}", key, source))]
+        CompleteMultipartUpload {
+            key: String,
+            source: SdkError<CompleteMultipartUploadError>,
+        },
+
+        #[snafu(display("Error reading config: {}", source))]
+        Config { source: pubsys_config::Error },
+
+        #[snafu(display("Failed to start the upload of '{}': {}", key, source))]
+        CreateMultipartUpload {
+            key: String,
+            source: SdkError<CreateMultipartUploadError>,
+        },
+
+        #[snafu(display("Invalid checksum manifest '{}': {}", path.display(), source))]
+        InvalidManifest {
+            path: PathBuf,
+            source: buildsys::checksums::Error,
+        },
+
+        #[snafu(display("Failed to get file name from path '{}'", path.display()))]
+        InvalidPath { path: PathBuf },
+
+        #[snafu(display("'{}' is listed in SHA256SUMS but does not exist", path.display()))]
+        MissingArtifact { path: PathBuf },
+
+        #[snafu(display("Infra.toml is missing {}", missing))]
+        MissingConfig { missing: String },
+
+        #[snafu(display(
+            "Checksum manifest '{}' does not exist, has the variant been built?",
+            path.display()
+        ))]
+        MissingManifest { path: PathBuf },
+
+        #[snafu(display("S3 did not return an upload ID for '{}'", key))]
+        MissingUploadId { key: String },
+
+        #[snafu(display("Failed to upload '{}': {}", key, source))]
+        PutObject {
+            key: String,
+            source: SdkError<PutObjectError>,
+        },
+
+        #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+        ReadArtifact {
+            path: PathBuf,
+            source: aws_sdk_s3::primitives::ByteStreamError,
+        },
+
+        #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+        ReadManifest {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to upload part {} of '{}': {}", part_number, key, source))]
+        UploadPart {
+            key: String,
+            part_number: i32,
+            source: SdkError<UploadPartError>,
+        },
+    }
+}
+pub(crate) use error::Error;
+type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_object_key() {
+        assert_eq!(object_key("", "a.img.lz4"), "a.img.lz4");
+        assert_eq!(
+            object_key("/variant/x86_64/", "a.img.lz4"),
+            "variant/x86_64/a.img.lz4"
+        );
+    }
+
+    #[test]
+    fn test_tagging() {
+        assert_eq!(tagging(&[]), None);
+        assert_eq!(
+            tagging(&[
+                ("lifecycle".to_string(), "30 days".to_string()),
+                ("team".to_string(), "os&kits".to_string()),
+            ]),
+            Some("lifecycle=30+days&team=os%26kits".to_string())
+        );
+        assert!(parse_tag("lifecycle=30").is_ok());
+        assert!(parse_tag("=30").is_err());
+    }
+
+    #[test]
+    fn test_part_ranges() {
+        assert_eq!(part_ranges(0), vec![]);
+        assert_eq!(part_ranges(PART_SIZE), vec![(0, PART_SIZE)]);
+        assert_eq!(
+            part_ranges(2 * PART_SIZE + 1),
+            vec![(0, PART_SIZE), (PART_SIZE, PART_SIZE), (2 * PART_SIZE, 1)]
+        );
+    }
+
+    #[test]
+    fn test_sha256_range() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("image");
+        std::fs::write(&path, b"abcdef").unwrap();
+        assert_eq!(
+            hex::encode(sha256_range(&path, 2, 3).unwrap()),
+            hex::encode(Sha256::digest(b"cde"))
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Signs published kit images with cosign, and attaches the kit's SBOM and build provenance to them
+//! as signed attestations, so that consumers can check who published a kit and how it was built.
+use log::{debug, info};
+use pubsys_config::ImageSigningConfig;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use tokio::process::Command;
+
+/// The environment variable through which Twoliter passes on the cosign binary pinned in
+/// `Twoliter.toml`.
+const COSIGN_ENV: &str = "TWOLITER_COSIGN";
+
+/// The cosign predicate type of SPDX SBOMs.
+const SBOM_PREDICATE_TYPE: &str = "spdxjson";
+
+/// The cosign predicate type of the SLSA v1 provenance buildsys records.
+const PROVENANCE_PREDICATE_TYPE: &str = "slsaprovenance1";
+
+/// Signs images with the key in a vendor's signing config.
+pub(super) struct Cosign<'a> {
+    config: &'a ImageSigningConfig,
+    program: OsString,
+}
+
+impl<'a> Cosign<'a> {
+    /// Signs with the pinned cosign binary, if Twoliter passed one on, otherwise with the first
+    /// `cosign` in `PATH`.
+    pub(super) fn new(config: &'a ImageSigningConfig) -> Self {
+        Self {
+            config,
+            program: std::env::var_os(COSIGN_ENV).unwrap_or_else(|| "cosign".into()),
+        }
+    }
+
+    /// Signs the image at `uri`, which should name the image by digest so that the tag can't be
+    /// moved to another image before it is signed.
+    pub(super) async fn sign(&self, uri: &str) -> Result<()> {
+        info!("Signing {}", uri);
+        self.run(&["sign"], uri).await
+    }
+
+    /// Attaches the SPDX SBOM at `path` to the image at `uri` as a signed attestation.
+    pub(super) async fn attest_sbom(&self, uri: &str, path: &Path) -> Result<()> {
+        info!("Attaching SBOM {} to {}", path.display(), uri);
+        self.attest(uri, SBOM_PREDICATE_TYPE, path).await
+    }
+
+    /// Attaches the in-toto provenance statement at `path` to the image at `uri` as a signed
+    /// attestation. cosign wraps the predicate in a statement of its own, so only the predicate of
+    /// the statement buildsys recorded is attached.
+    pub(super) async fn attest_provenance(&self, uri: &str, path: &Path) -> Result<()> {
+        info!("Attaching provenance {} to {}", path.display(), uri);
+        let predicate = read_predicate(path)?;
+
+        let temp_dir = tempfile::TempDir::new().context(error::TempDirSnafu)?;
+        let predicate_path = temp_dir.path().join("predicate.json");
+        fs::write(&predicate_path, predicate.to_string()).context(error::WriteSnafu {
+            path: &predicate_path,
+        })?;
+        self.attest(uri, PROVENANCE_PREDICATE_TYPE, &predicate_path)
+            .await
+    }
+
+    async fn attest(&self, uri: &str, predicate_type: &str, predicate: &Path) -> Result<()> {
+        let predicate = predicate.display().to_string();
+        self.run(
+            &[
+                "attest",
+                "--type",
+                predicate_type,
+                "--predicate",
+                &predicate,
+            ],
+            uri,
+        )
+        .await
+    }
+
+    /// The cosign command which runs `args` with the vendor's key for the image at `uri`.
+    fn command(&self, args: &[&str], uri: &str) -> Command {
+        let key = self.config.key.key_ref();
+        let tlog_upload = format!("--tlog-upload={}", self.config.tlog_upload);
+        let mut command = Command::new(&self.program);
+        command
+            .args(args)
+            .args(["--yes", "--key", &key, &tlog_upload])
+            .arg(uri);
+        command
+    }
+
+    async fn run(&self, args: &[&str], uri: &str) -> Result<()> {
+        let mut command = self.command(args, uri);
+        debug!("Running {:?}", command);
+        let status = command.status().await.context(error::CommandStartSnafu)?;
+        ensure!(
+            status.success(),
+            error::CommandFailedSnafu {
+                command: args[0],
+                uri,
+            }
+        );
+        Ok(())
+    }
+}
+
+/// The predicate of the in-toto statement at `path`.
+fn read_predicate(path: &Path) -> Result<serde_json::Value> {
+    let mut statement: serde_json::Value =
+        serde_json::from_slice(&fs::read(path).context(error::ReadSnafu { path })?)
+            .context(error::ParseProvenanceSnafu { path })?;
+    statement
+        .get_mut("predicate")
+        .map(serde_json::Value::take)
+        .context(error::NoPredicateSnafu { path })
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub(crate) enum Error {
+        #[snafu(display("'cosign {}' failed for {}", command, uri))]
+        CommandFailed { command: String, uri: String },
+
+        #[snafu(display("Failed to run cosign, is it installed? {}", source))]
+        CommandStart { source: std::io::Error },
+
+        #[snafu(display("Provenance {} has no predicate", path.display()))]
+        NoPredicate { path: PathBuf },
+
+        #[snafu(display("Failed to parse provenance {}: {}", path.display(), source))]
+        ParseProvenance {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to read {}: {}", path.display(), source))]
+        Read {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Could not create temporary directory: {}", source))]
+        TempDir { source: std::io::Error },
+
+        #[snafu(display("Failed to write {}: {}", path.display(), source))]
+        Write {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+
+pub(crate) use error::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pubsys_config::CosignKeyConfig;
+
+    const DIGEST_URI: &str = "example.com/kits/core@sha256:0123";
+
+    fn config(key: CosignKeyConfig, tlog_upload: bool) -> ImageSigningConfig {
+        ImageSigningConfig { key, tlog_upload }
+    }
+
+    fn cosign<'a>(config: &'a ImageSigningConfig, program: &str) -> Cosign<'a> {
+        Cosign {
+            config,
+            program: program.into(),
+        }
+    }
+
+    #[test]
+    fn command_uses_key_and_tlog_setting() {
+        let kms = config(
+            CosignKeyConfig::kms {
+                key_arn: "arn:aws:kms:us-west-2:111122223333:key/abc".to_string(),
+            },
+            false,
+        );
+        let command = cosign(&kms, "/pinned/cosign").command(&["sign"], DIGEST_URI);
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "/pinned/cosign");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "sign",
+                "--yes",
+                "--key",
+                "awskms:///arn:aws:kms:us-west-2:111122223333:key/abc",
+                "--tlog-upload=false",
+                DIGEST_URI,
+            ]
+        );
+
+        let file = config(
+            CosignKeyConfig::file {
+                path: "/keys/cosign.key".into(),
+            },
+            true,
+        );
+        let command =
+            cosign(&file, "cosign").command(&["attest", "--type", "spdxjson"], DIGEST_URI);
+        assert_eq!(
+            command.as_std().get_args().collect::<Vec<_>>(),
+            [
+                "attest",
+                "--type",
+                "spdxjson",
+                "--yes",
+                "--key",
+                "/keys/cosign.key",
+                "--tlog-upload=true",
+                DIGEST_URI,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_command_is_an_error() {
+        let file = config(
+            CosignKeyConfig::file {
+                path: "/keys/cosign.key".into(),
+            },
+            false,
+        );
+        cosign(&file, "true").sign(DIGEST_URI).await.unwrap();
+        assert!(matches!(
+            cosign(&file, "false").sign(DIGEST_URI).await,
+            Err(Error::CommandFailed { .. })
+        ));
+        assert!(matches!(
+            cosign(&file, "/nonexistent/cosign").sign(DIGEST_URI).await,
+            Err(Error::CommandStart { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_provenance_predicate() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("provenance.json");
+        fs::write(
+            &path,
+            r#"{"_type": "https://in-toto.io/Statement/v1", "predicate": {"buildDefinition": {}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_predicate(&path).unwrap(),
+            serde_json::json!({"buildDefinition": {}})
+        );
+
+        fs::write(&path, r#"{"_type": "https://in-toto.io/Statement/v1"}"#).unwrap();
+        assert!(matches!(
+            read_predicate(&path),
+            Err(Error::NoPredicate { .. })
+        ));
+        fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            read_predicate(&path),
+            Err(Error::ParseProvenance { .. })
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+//! Reads the kit metadata which rpm2kit attaches to each platform's kit image as the
+//! `dev.bottlerocket.kit.v1` label, so that an image can be checked before it's pushed. Twoliter
+//! resolves a kit's dependencies from this label when locking a project that consumes it, so an
+//! image without it, or whose label names another kit or version, can't be consumed.
+use base64::Engine;
+use oci_cli_wrapper::KIT_METADATA_LABEL;
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tar::Archive;
+
+/// The parts of the kit metadata which identify the kit.
+#[derive(Debug, Deserialize)]
+pub(super) struct KitMetadata {
+    pub(super) name: String,
+    pub(super) version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Index {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    config: Descriptor,
+}
+
+#[derive(Debug, Deserialize)]
+struct Descriptor {
+    digest: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageConfig {
+    architecture: String,
+    #[serde(default)]
+    config: ContainerConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ContainerConfig {
+    #[serde(rename = "Labels", default)]
+    labels: Option<HashMap<String, String>>,
+}
+
+/// Checks that the OCI image archive at `path` is a kit image for `docker_arch` whose metadata
+/// names the kit `name` at `version`, and returns its metadata.
+pub(super) fn check_archive(
+    path: &Path,
+    docker_arch: &str,
+    name: &str,
+    version: &str,
+) -> Result<KitMetadata> {
+    let blobs = read_archive(path)?;
+    let index: Index = parse(&blobs, path, "index.json")?;
+    let manifest_digest = &index
+        .manifests
+        .first()
+        .context(error::NoManifestSnafu { path })?
+        .digest;
+    let manifest: Manifest = parse(&blobs, path, &blob_name(manifest_digest))?;
+    let config: ImageConfig = parse(&blobs, path, &blob_name(&manifest.config.digest))?;
+    ensure!(
+        config.architecture == docker_arch,
+        error::ArchitectureSnafu {
+            path,
+            expected: docker_arch,
+            actual: config.architecture,
+        }
+    );
+
+    let encoded = config
+        .config
+        .labels
+        .unwrap_or_default()
+        .remove(KIT_METADATA_LABEL)
+        .context(error::NoLabelSnafu { path })?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .context(error::DecodeSnafu { path })?;
+    let metadata: KitMetadata =
+        serde_json::from_slice(&bytes).context(error::ParseMetadataSnafu { path })?;
+    // The image is tagged with a `v` before its version, which the metadata leaves out.
+    let version = version.strip_prefix('v').unwrap_or(version);
+    ensure!(
+        metadata.name == name && metadata.version == version,
+        error::MismatchSnafu {
+            path,
+            expected: format!("{name}@{version}"),
+            actual: format!("{}@{}", metadata.name, metadata.version),
+        }
+    );
+    Ok(metadata)
+}
+
+/// The largest file read from the archive; image indexes, manifests and configs are far smaller.
+const MAX_DOCUMENT_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Reads the files in the archive which describe the image, leaving out its layers.
+fn read_archive(path: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let file = File::open(path).context(error::ReadSnafu { path })?;
+    let mut archive = Archive::new(file);
+    let mut files = HashMap::new();
+    for entry in archive.entries().context(error::ReadSnafu { path })? {
+        let mut entry = entry.context(error::ReadSnafu { path })?;
+        let name = entry
+            .path()
+            .context(error::ReadSnafu { path })?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .to_string();
+        // Layers can be large and aren't needed, so only the JSON documents are kept.
+        if entry.header().size().unwrap_or_default() > MAX_DOCUMENT_SIZE {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .context(error::ReadSnafu { path })?;
+        files.insert(name, data);
+    }
+    Ok(files)
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(
+    files: &HashMap<String, Vec<u8>>,
+    path: &Path,
+    name: &str,
+) -> Result<T> {
+    let data = files
+        .get(name)
+        .context(error::MissingFileSnafu { path, name })?;
+    serde_json::from_slice(data).context(error::ParseSnafu { path, name })
+}
+
+fn blob_name(digest: &str) -> String {
+    format!("blobs/{}", digest.replacen(':', "/", 1))
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub(crate) enum Error {
+        #[snafu(display(
+            "Kit image {} is for architecture '{}', expected '{}'",
+            path.display(),
+            actual,
+            expected
+        ))]
+        Architecture {
+            path: PathBuf,
+            expected: String,
+            actual: String,
+        },
+
+        #[snafu(display(
+            "Failed to decode the '{}' label of kit image {}: {}",
+            super::KIT_METADATA_LABEL,
+            path.display(),
+            source
+        ))]
+        Decode {
+            path: PathBuf,
+            source: base64::DecodeError,
+        },
+
+        #[snafu(display(
+            "The '{}' label of kit image {} is for {}, expected {}",
+            super::KIT_METADATA_LABEL,
+            path.display(),
+            actual,
+            expected
+        ))]
+        Mismatch {
+            path: PathBuf,
+            expected: String,
+            actual: String,
+        },
+
+        #[snafu(display("Kit image {} has no {}", path.display(), name))]
+        MissingFile { path: PathBuf, name: String },
+
+        #[snafu(display(
+            "Kit image {} has no '{}' label, it appears to not be a kit",
+            path.display(),
+            super::KIT_METADATA_LABEL
+        ))]
+        NoLabel { path: PathBuf },
+
+        #[snafu(display("Kit image {} has no manifest", path.display()))]
+        NoManifest { path: PathBuf },
+
+        #[snafu(display("Failed to parse {} in kit image {}: {}", name, path.display(), source))]
+        Parse {
+            path: PathBuf,
+            name: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display(
+            "Failed to parse the '{}' label of kit image {}: {}",
+            super::KIT_METADATA_LABEL,
+            path.display(),
+            source
+        ))]
+        ParseMetadata {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to read kit image {}: {}", path.display(), source))]
+        Read {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+
+pub(crate) use error::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use tar::{Builder, Header};
+
+    fn append(builder: &mut Builder<File>, name: &str, data: &[u8]) {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data).unwrap();
+    }
+
+    fn write_kit_archive(path: &Path, metadata: Option<&str>) {
+        let labels = metadata
+            .map(|m| json!({ KIT_METADATA_LABEL: base64::engine::general_purpose::STANDARD.encode(m) }))
+            .unwrap_or_else(|| json!({}));
+        let config = json!({"architecture": "amd64", "config": {"Labels": labels}}).to_string();
+        let manifest = json!({"config": {"digest": "sha256:c0"}}).to_string();
+        let index = json!({"manifests": [{"digest": "sha256:aa"}]}).to_string();
+        let mut builder = Builder::new(File::create(path).unwrap());
+        append(&mut builder, "index.json", index.as_bytes());
+        append(&mut builder, "blobs/sha256/aa", manifest.as_bytes());
+        append(&mut builder, "blobs/sha256/c0", config.as_bytes());
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_check_archive() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("core-kit.tar");
+        write_kit_archive(
+            &path,
+            Some(r#"{"name":"core-kit","version":"1.2.0","sdk":{},"kit":[]}"#),
+        );
+
+        let metadata = check_archive(&path, "amd64", "core-kit", "v1.2.0").unwrap();
+        assert_eq!(metadata.name, "core-kit");
+        assert!(matches!(
+            check_archive(&path, "amd64", "core-kit", "v1.3.0"),
+            Err(Error::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check_archive(&path, "arm64", "core-kit", "v1.2.0"),
+            Err(Error::Architecture { .. })
+        ));
+
+        write_kit_archive(&path, None);
+        assert!(matches!(
+            check_archive(&path, "amd64", "core-kit", "v1.2.0"),
+            Err(Error::NoLabel { .. })
+        ));
+    }
+}
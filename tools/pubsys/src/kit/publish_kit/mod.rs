@@ -1,9 +1,14 @@
+mod cosign;
+mod metadata;
+mod provenance;
+
 use crate::Args;
 use clap::Parser;
 use log::{debug, info, trace};
-use oci_cli_wrapper::{DockerArchitecture, ImageTool};
+use oci_cli_wrapper::{DockerArchitecture, ImageTool, ReferrerKind};
 use pubsys_config::InfraConfig;
 use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 /// Takes a local kit built using buildsys and publishes it to a vendor specified in Infra.toml
@@ -24,6 +29,30 @@ pub(crate) struct PublishKitArgs {
     /// The build id of the kit that should be published
     #[arg(long)]
     build_id: String,
+
+    /// The directory of build provenance recorded by buildsys. When given, the provenance of each
+    /// platform's kit is attached to the kit's manifest list as an OCI referrer
+    #[arg(long)]
+    provenance_dir: Option<PathBuf>,
+
+    /// An SPDX SBOM of the kit, to attach to the kit's manifest list as a signed attestation. The
+    /// vendor must sign its images
+    #[arg(long)]
+    sbom: Option<PathBuf>,
+
+    /// Artifacts, as KIND=PATH, to attach to the kit's manifest list as OCI referrers. KIND is
+    /// one of `sbom`, `provenance` or `test-results`
+    #[arg(long = "referrer", value_parser = parse_referrer)]
+    referrers: Vec<(ReferrerKind, PathBuf)>,
+}
+
+/// Parses an artifact to attach given as KIND=PATH.
+fn parse_referrer(input: &str) -> std::result::Result<(ReferrerKind, PathBuf), String> {
+    let (kind, path) = input
+        .split_once('=')
+        .ok_or_else(|| format!("expected an artifact as KIND=PATH, found '{input}'"))?;
+    let kind = kind.parse().map_err(|e| format!("{e}"))?;
+    Ok((kind, PathBuf::from(path)))
 }
 
 pub(crate) async fn run(args: &Args, publish_kit_args: &PublishKitArgs) -> Result<()> {
@@ -52,6 +81,13 @@ async fn publish_kit(
             name: publish_kit_args.vendor.clone(),
         })?;
     let vendor_registry_uri = vendor.registry.clone();
+    let cosign = vendor.signing.as_ref().map(cosign::Cosign::new);
+    ensure!(
+        publish_kit_args.sbom.is_none() || cosign.is_some(),
+        error::UnsignedSbomSnafu {
+            vendor: &publish_kit_args.vendor,
+        }
+    );
     debug!(
         "Found vendor container registry at uri: {}",
         vendor_registry_uri
@@ -66,7 +102,9 @@ async fn publish_kit(
     let kit_version = publish_kit_args.version.clone();
     let build_id = publish_kit_args.build_id.clone();
 
-    let mut platform_images = Vec::new();
+    // Find each platform's kit image and check its metadata label, which consumers of the kit
+    // resolve it with, before pushing any of them.
+    let mut archives = Vec::new();
     for arch in ["aarch64", "x86_64"] {
         let docker_arch =
             DockerArchitecture::try_from(arch).context(error::InvalidArchitectureSnafu { arch })?;
@@ -79,6 +117,24 @@ async fn publish_kit(
             continue;
         }
 
+        let metadata =
+            metadata::check_archive(&path, &docker_arch.to_string(), &kit_name, &kit_version)
+                .context(error::KitMetadataSnafu)?;
+        trace!(
+            "Kit image for platform {} has metadata: {:?}",
+            arch,
+            metadata
+        );
+        archives.push((arch, docker_arch, path));
+    }
+    ensure!(
+        !archives.is_empty(),
+        error::NoArchiveSnafu { path: kit_path }
+    );
+
+    let mut platform_images = Vec::new();
+    let mut provenance = Vec::new();
+    for (arch, docker_arch, path) in archives {
         let arch_specific_target_uri = format!(
             "{}/{}:{}-{}-{}",
             vendor_registry_uri, kit_name, &kit_version, &build_id, arch
@@ -93,13 +149,39 @@ async fn publish_kit(
             .push_oci_archive(&path, &arch_specific_target_uri)
             .await
             .context(error::PublishKitSnafu)?;
+        // The image is signed, and listed in the manifest list, by digest, so that what is signed
+        // can't change if the tag is moved in the meantime.
+        let arch_specific_digest_uri = digest_uri(
+            image_tool,
+            &vendor_registry_uri,
+            &kit_name,
+            &arch_specific_target_uri,
+        )
+        .await?;
+
+        let provenance_path = publish_kit_args
+            .provenance_dir
+            .as_deref()
+            .and_then(|dir| provenance::provenance_path(dir, arch, &kit_name));
+
+        if let Some(cosign) = &cosign {
+            cosign
+                .sign(&arch_specific_digest_uri)
+                .await
+                .context(error::SignSnafu)?;
+            if let Some(provenance_path) = &provenance_path {
+                cosign
+                    .attest_provenance(&arch_specific_digest_uri, provenance_path)
+                    .await
+                    .context(error::SignSnafu)?;
+            }
+        }
 
-        platform_images.push((docker_arch, arch_specific_target_uri.clone()));
+        if let Some(provenance_path) = provenance_path {
+            provenance.push((docker_arch.to_string(), provenance_path));
+        }
+        platform_images.push((docker_arch, arch_specific_digest_uri));
     }
-    ensure!(
-        !platform_images.is_empty(),
-        error::NoArchiveSnafu { path: kit_path }
-    );
 
     let target_uri = format!("{}/{}:{}", vendor_registry_uri, kit_name, kit_version);
 
@@ -109,12 +191,77 @@ async fn publish_kit(
         .push_multi_platform_manifest(platform_images, &target_uri)
         .await
         .context(error::PublishKitSnafu)?;
+    let target_digest_uri =
+        digest_uri(image_tool, &vendor_registry_uri, &kit_name, &target_uri).await?;
+
+    if let Some(cosign) = &cosign {
+        cosign
+            .sign(&target_digest_uri)
+            .await
+            .context(error::SignSnafu)?;
+        if let Some(sbom) = &publish_kit_args.sbom {
+            cosign
+                .attest_sbom(&target_digest_uri, sbom)
+                .await
+                .context(error::SignSnafu)?;
+        }
+    }
+
+    // Each platform's provenance is attached along with the artifacts given to attach, and names
+    // the platform it describes.
+    let referrers = provenance
+        .iter()
+        .map(|(platform, path)| (ReferrerKind::Provenance, path, Some(platform)))
+        .chain(
+            publish_kit_args
+                .referrers
+                .iter()
+                .map(|(kind, path)| (*kind, path, None)),
+        );
+    for (kind, path, platform) in referrers {
+        info!("Attaching {} {} to {}", kind, path.display(), &target_uri);
+        let mut annotations = BTreeMap::from([
+            (
+                "dev.bottlerocket.kit.name".to_string(),
+                kit_name.to_string(),
+            ),
+            (
+                "dev.bottlerocket.kit.version".to_string(),
+                kit_version.clone(),
+            ),
+        ]);
+        if let Some(platform) = platform {
+            annotations.insert(
+                "dev.bottlerocket.kit.architecture".to_string(),
+                platform.clone(),
+            );
+        }
+        let digest = image_tool
+            .push_referrer(&target_digest_uri, kind, path, annotations)
+            .await
+            .context(error::AttachReferrerSnafu { path })?;
+        debug!("Attached {} as {}", path.display(), digest);
+    }
 
     info!("Successfully published kit to {}", target_uri);
 
     Ok(())
 }
 
+/// The uri of the image just pushed to `uri`, by its digest rather than its tag.
+async fn digest_uri(
+    image_tool: &ImageTool,
+    registry: &str,
+    kit_name: &str,
+    uri: &str,
+) -> Result<String> {
+    let digest = image_tool
+        .digest(uri)
+        .await
+        .context(error::DigestSnafu { uri })?;
+    Ok(format!("{registry}/{kit_name}@{digest}"))
+}
+
 mod error {
     use snafu::Snafu;
     use std::path::PathBuf;
@@ -122,9 +269,21 @@ mod error {
     #[derive(Debug, Snafu)]
     #[snafu(visibility(pub(super)))]
     pub(crate) enum Error {
+        #[snafu(display("Could not attach {} to kit: {}", path.display(), source))]
+        AttachReferrer {
+            path: PathBuf,
+            source: oci_cli_wrapper::error::Error,
+        },
+
         #[snafu(display("Error reading config: {}", source))]
         Config { source: pubsys_config::Error },
 
+        #[snafu(display("Could not find the digest of {}: {}", uri, source))]
+        Digest {
+            uri: String,
+            source: oci_cli_wrapper::error::Error,
+        },
+
         #[snafu(display("Could not find image tool: {}", source))]
         ImageTool {
             source: oci_cli_wrapper::error::Error,
@@ -139,6 +298,9 @@ mod error {
         #[snafu(display("Failed not get kit name from path {}", path.display()))]
         InvalidPath { path: PathBuf },
 
+        #[snafu(display("Invalid kit image: {}", source))]
+        KitMetadata { source: super::metadata::Error },
+
         #[snafu(display("No kit archive(s) exist at path {}", path.display()))]
         NoArchive { path: PathBuf },
 
@@ -150,6 +312,16 @@ mod error {
             source: oci_cli_wrapper::error::Error,
         },
 
+        #[snafu(display("Could not sign kit: {}", source))]
+        Sign { source: super::cosign::Error },
+
+        #[snafu(display(
+            "Cannot attach an SBOM because vendor '{}' does not sign its images, add signing to \
+            it in Infra.toml",
+            vendor
+        ))]
+        UnsignedSbom { vendor: String },
+
         #[snafu(display("Vendor '{}' not specified in Infra.toml", name))]
         VendorNotFound { name: String },
     }
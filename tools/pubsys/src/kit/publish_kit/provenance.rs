@@ -0,0 +1,31 @@
+//! Finds the provenance buildsys records for a kit, so that it can be attached to the published kit.
+use std::path::{Path, PathBuf};
+
+/// The provenance of a kit built for `arch`, if buildsys recorded it in `provenance_dir`.
+pub(super) fn provenance_path(provenance_dir: &Path, arch: &str, kit: &str) -> Option<PathBuf> {
+    let path = provenance_dir
+        .join(arch)
+        .join(format!("kit-{kit}.intoto.json"));
+    path.is_file().then_some(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_provenance_path() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let provenance_dir = temp_dir.path().join("provenance");
+        assert!(provenance_path(&provenance_dir, "x86_64", "core-kit").is_none());
+        fs::create_dir_all(provenance_dir.join("x86_64")).unwrap();
+        let path = provenance_dir.join("x86_64/kit-core-kit.intoto.json");
+        fs::write(&path, r#"{"_type":"https://in-toto.io/Statement/v1"}"#).unwrap();
+        assert_eq!(
+            provenance_path(&provenance_dir, "x86_64", "core-kit"),
+            Some(path)
+        );
+        assert!(provenance_path(&provenance_dir, "aarch64", "core-kit").is_none());
+    }
+}
@@ -7,6 +7,7 @@ Currently implemented:
 * checking for repository metadata expirations within specified number of days
 * refreshing and re-signing repos' non-root metadata files
 * registering and copying EC2 AMIs
+* uploading variant images to S3
 * Marking EC2 AMIs public (or private again)
 * setting SSM parameters based on built AMIs
 * promoting SSM parameters from versioned entries to named (e.g. 'latest')
@@ -106,6 +107,9 @@ async fn run() -> Result<()> {
         SubCommands::PublishAmi(ref publish_args) => aws::publish_ami::run(&args, publish_args)
             .await
             .context(error::PublishAmiSnafu),
+        SubCommands::UploadImages(ref upload_args) => aws::upload_images::run(&args, upload_args)
+            .await
+            .context(error::UploadImagesSnafu),
         SubCommands::Ssm(ref ssm_args) => aws::ssm::run(&args, ssm_args)
             .await
             .context(error::SsmSnafu),
@@ -168,6 +172,7 @@ enum SubCommands {
     Ami(aws::ami::AmiArgs),
     PublishAmi(aws::publish_ami::Who),
     ValidateAmi(aws::validate_ami::ValidateAmiArgs),
+    UploadImages(aws::upload_images::UploadImagesArgs),
 
     Ssm(aws::ssm::SsmArgs),
     PromoteSsm(aws::promote_ssm::PromoteArgs),
@@ -254,6 +259,11 @@ mod error {
         #[snafu(display("Failed to update SSM: {}", source))]
         Ssm { source: crate::aws::ssm::Error },
 
+        #[snafu(display("Failed to upload images: {}", source))]
+        UploadImages {
+            source: crate::aws::upload_images::Error,
+        },
+
         #[snafu(display("Failed to upload OVA: {}", source))]
         UploadOva {
             source: crate::vmware::upload_ova::Error,
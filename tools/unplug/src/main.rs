@@ -1,5 +1,12 @@
 //! Unplug is a command-line tool to run another program without network access.
 //! It applies a seccomp filter that restricts most socket-related syscalls.
+//!
+//! Given `--proxy <url>` first, it runs the program with access only to the proxy; see `proxy`.
+//! `--proxy-file <path>` reads the URL from a file instead, so that credentials it may hold stay
+//! off the command line.
+
+#[cfg(target_os = "linux")]
+mod proxy;
 
 use std::env;
 #[cfg(target_os = "linux")]
@@ -46,6 +53,24 @@ fn create_network_filter() -> Result<SeccompFilter> {
 
 #[cfg(target_os = "linux")]
 fn run(args: env::Args) -> Result<ExitCode> {
+    let mut args = args.skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("--proxy") {
+        args.next();
+        let proxy = args.next().context("--proxy requires a URL")?;
+        let program = args.next().context("--proxy requires a program to run")?;
+        return proxy::run(&proxy, program, args.collect());
+    }
+    if args.peek().map(String::as_str) == Some("--proxy-file") {
+        args.next();
+        let path = args.next().context("--proxy-file requires a path")?;
+        let proxy = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read proxy from {path}"))?;
+        let program = args
+            .next()
+            .context("--proxy-file requires a program to run")?;
+        return proxy::run(proxy.trim(), program, args.collect());
+    }
+
     let network_filter = create_network_filter().context("failed to create network filter")?;
     let bpf_program: BpfProgram = network_filter
         .try_into()
@@ -53,7 +78,6 @@ fn run(args: env::Args) -> Result<ExitCode> {
 
     apply_filter(&bpf_program).context("failed to apply network filter")?;
 
-    let mut args = args.skip(1);
     if let Some(program) = args.next() {
         let ret = Command::new(&program)
             .args(args)
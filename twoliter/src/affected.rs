@@ -0,0 +1,283 @@
+//! Works out which of a project's packages, kits and variants are affected by a change to some of
+//! its files, so that CI can rebuild and test only those.
+//!
+//! A package is changed by the files in its directory, the sources and patches its spec file names,
+//! and the files buildsys finds in its `source-groups`. A kit or variant is changed by the files in
+//! its directory. Whatever depends on something affected, through `Cargo.toml` or the package
+//! dependency graph, is affected too. A change to `Twoliter.toml` or `Twoliter.lock` may change the
+//! SDK or any kit, so it affects everything.
+use crate::lint::find_manifests;
+use anyhow::{Context, Result};
+use buildsys::graph::PackageGraph;
+use buildsys::manifest::ManifestInfo;
+use buildsys::project::ProjectInfo;
+use buildsys::spec::SpecInfo;
+use buildsys::BuildType;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+use toml::Value;
+
+/// The files whose changes affect everything in the project.
+const PROJECT_FILES: [&str; 2] = ["Twoliter.toml", "Twoliter.lock"];
+
+/// The packages, kits and variants affected by a change, sorted by name.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub(crate) struct Affected {
+    pub(crate) packages: BTreeSet<String>,
+    pub(crate) kits: BTreeSet<String>,
+    pub(crate) variants: BTreeSet<String>,
+}
+
+/// A package, kit or variant, along with the files which change it.
+#[derive(Debug)]
+struct Target {
+    build_type: BuildType,
+    name: String,
+    /// The name other manifests list the target by.
+    crate_name: String,
+    dir: PathBuf,
+    /// Files outside `dir` which change the target, such as those in a package's source groups.
+    files: BTreeSet<PathBuf>,
+    /// Directories whose files change the target, even once they're deleted.
+    source_dirs: Vec<PathBuf>,
+    /// The crates listed by `[dependencies]` and `[build-dependencies]`.
+    crates: Vec<String>,
+}
+
+/// The packages, kits and variants of a project, along with what depends on each.
+#[derive(Debug)]
+pub(crate) struct ProjectTargets {
+    project_dir: PathBuf,
+    targets: Vec<Target>,
+    /// The indexes of the targets which depend on each target.
+    dependents: Vec<BTreeSet<usize>>,
+}
+
+impl ProjectTargets {
+    /// Reads the manifests of the project, along with the spec files and source groups of its
+    /// packages.
+    pub(crate) async fn load(project_dir: &Path) -> Result<Self> {
+        let project_dir = normalize(project_dir);
+        let mut manifests = find_manifests(&project_dir).await?;
+        manifests.sort();
+
+        let mut targets = Vec::new();
+        let mut package_manifests = Vec::new();
+        for manifest_path in manifests {
+            if let Some(target) = read_target(&project_dir, &manifest_path)? {
+                if target.build_type == BuildType::Package {
+                    package_manifests.push(manifest_path);
+                }
+                targets.push(target);
+            }
+        }
+
+        let mut dependents = vec![BTreeSet::new(); targets.len()];
+        let by_crate: HashMap<&str, usize> = targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| (target.crate_name.as_str(), i))
+            .collect();
+        for (i, target) in targets.iter().enumerate() {
+            for crate_name in &target.crates {
+                if let Some(&dependency) = by_crate.get(crate_name.as_str()) {
+                    dependents[dependency].insert(i);
+                }
+            }
+        }
+
+        // Specs may require packages that their manifests don't list.
+        let graph = PackageGraph::new(&package_manifests)
+            .context("Unable to read the package dependency graph")?;
+        let packages: HashMap<&str, usize> = targets
+            .iter()
+            .enumerate()
+            .filter(|(_, target)| target.build_type == BuildType::Package)
+            .map(|(i, target)| (target.name.as_str(), i))
+            .collect();
+        for dependency in &graph.dependencies {
+            if let (Some(&from), Some(&to)) = (
+                packages.get(dependency.from.as_str()),
+                packages.get(dependency.to.as_str()),
+            ) {
+                dependents[to].insert(from);
+            }
+        }
+
+        Ok(Self {
+            project_dir,
+            targets,
+            dependents,
+        })
+    }
+
+    /// The targets affected by changes to `changed`, which are paths relative to the project
+    /// directory unless they are absolute.
+    pub(crate) fn affected<P: AsRef<Path>>(&self, changed: &[P]) -> Affected {
+        let changed: Vec<PathBuf> = changed
+            .iter()
+            .map(|path| normalize(&self.project_dir.join(path)))
+            .collect();
+        let everything = changed.iter().any(|path| {
+            PROJECT_FILES
+                .iter()
+                .any(|file| *path == self.project_dir.join(file))
+        });
+
+        let mut queue: Vec<usize> = (0..self.targets.len())
+            .filter(|&i| everything || changed.iter().any(|path| self.targets[i].changed_by(path)))
+            .collect();
+        let mut seen: BTreeSet<usize> = queue.iter().copied().collect();
+        while let Some(i) = queue.pop() {
+            for &dependent in &self.dependents[i] {
+                if seen.insert(dependent) {
+                    queue.push(dependent);
+                }
+            }
+        }
+
+        let mut affected = Affected::default();
+        for target in seen.into_iter().map(|i| &self.targets[i]) {
+            let names = match target.build_type {
+                BuildType::Package => &mut affected.packages,
+                BuildType::Kit => &mut affected.kits,
+                BuildType::Variant | BuildType::Repack => &mut affected.variants,
+            };
+            names.insert(target.name.clone());
+        }
+        affected
+    }
+}
+
+impl Target {
+    fn changed_by(&self, path: &Path) -> bool {
+        path.starts_with(&self.dir)
+            || self.files.contains(path)
+            || (!path.exists() && self.source_dirs.iter().any(|dir| path.starts_with(dir)))
+    }
+}
+
+/// Reads the target whose manifest is at `manifest_path`, unless it's a manifest of something else,
+/// such as the workspace or a crate in `sources`.
+fn read_target(project_dir: &Path, manifest_path: &Path) -> Result<Option<Target>> {
+    let contents = std::fs::read_to_string(manifest_path)
+        .context(format!("Unable to read '{}'", manifest_path.display()))?;
+    let manifest: Value = toml::from_str(&contents)
+        .context(format!("Unable to parse '{}'", manifest_path.display()))?;
+    let metadata = manifest
+        .get("package")
+        .and_then(|package| package.get("metadata"));
+    let has = |key: &str| metadata.and_then(|metadata| metadata.get(key)).is_some();
+    let build_type = if has("build-package") {
+        BuildType::Package
+    } else if has("build-kit") {
+        BuildType::Kit
+    } else if has("build-variant") {
+        BuildType::Variant
+    } else {
+        return Ok(None);
+    };
+
+    let info = ManifestInfo::new(manifest_path)
+        .context(format!("Unable to parse '{}'", manifest_path.display()))?;
+    let dir = normalize(
+        manifest_path
+            .parent()
+            .context("manifest has no parent directory")?,
+    );
+    let crates = ["dependencies", "build-dependencies"]
+        .into_iter()
+        .filter_map(|table| manifest.get(table).and_then(Value::as_table))
+        .flat_map(|table| table.keys().cloned())
+        .collect();
+    let mut target = Target {
+        build_type,
+        name: match build_type {
+            BuildType::Package => info.package_name(),
+            BuildType::Kit => info.kit_name(),
+            BuildType::Variant | BuildType::Repack => info.manifest_name(),
+        }
+        .to_string(),
+        crate_name: info.manifest_name().to_string(),
+        dir,
+        files: BTreeSet::new(),
+        source_dirs: Vec::new(),
+        crates,
+    };
+    if build_type != BuildType::Package {
+        return Ok(Some(target));
+    }
+
+    let spec = target.dir.join(format!("{}.spec", target.name));
+    if spec.is_file() {
+        let info = SpecInfo::new(&spec).context(format!("Unable to parse '{}'", spec.display()))?;
+        target.files.extend(
+            info.sources
+                .iter()
+                .chain(info.patches.iter())
+                .map(|file| normalize(&target.dir.join(file))),
+        );
+    }
+    if let Some(groups) = info.source_groups() {
+        let sources_dir = project_dir.join("sources");
+        target.source_dirs = groups.iter().map(|group| sources_dir.join(group)).collect();
+        let crawled = ProjectInfo::crawl(&target.source_dirs).context(format!(
+            "Unable to find the sources of package '{}'",
+            target.name
+        ))?;
+        target
+            .files
+            .extend(crawled.files.iter().map(|file| normalize(file)));
+    }
+    Ok(Some(target))
+}
+
+/// Makes the path absolute and resolves `.` and `..` without touching the filesystem, since the
+/// changed files may have been deleted.
+fn normalize(path: &Path) -> PathBuf {
+    let path = std::env::current_dir()
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_affected_targets() {
+        let temp_dir = crate::test::copy_project_to_temp_dir("local-kit");
+        let targets = ProjectTargets::load(temp_dir.path()).await.unwrap();
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        // pkg-f is required by pkg-g, which is in extra-3-kit, which the variant builds with.
+        let affected = targets.affected(&["packages/pkg-f/pkg-f.spec"]);
+        assert_eq!(
+            affected,
+            Affected {
+                packages: names(&["pkg-f", "pkg-g"]),
+                kits: names(&["extra-3-kit"]),
+                variants: names(&["hello-ootb"]),
+            }
+        );
+
+        let affected = targets.affected(&[temp_dir.path().join("kits/extra-2-kit/Cargo.toml")]);
+        assert_eq!(affected.packages, names(&["pkg-e"]));
+        assert_eq!(affected.kits, names(&["extra-2-kit", "extra-3-kit"]));
+
+        assert_eq!(targets.affected(&["README.md"]), Affected::default());
+        assert_eq!(targets.affected(&["Twoliter.toml"]).packages.len(), 7);
+    }
+}
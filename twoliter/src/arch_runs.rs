@@ -0,0 +1,200 @@
+//! Runs a build or fetch once per architecture, either one after another or all at once, and
+//! summarizes how each run went once they are done.
+use crate::project::SUPPORTED_ARCHES;
+use anyhow::{ensure, Result};
+use futures::future::{join_all, LocalBoxFuture};
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// The `--arch` value which stands for every supported architecture.
+const ALL_ARCHES: &str = "all";
+
+/// Expands the values given with `--arch` into the architectures to run for, in the order given
+/// and without duplicates.
+pub(crate) fn expand_arches(requested: &[String]) -> Result<Vec<String>> {
+    let mut arches: Vec<String> = Vec::new();
+    for arch in requested {
+        let expanded = if arch == ALL_ARCHES {
+            SUPPORTED_ARCHES.iter().map(ToString::to_string).collect()
+        } else {
+            ensure!(
+                SUPPORTED_ARCHES.contains(&arch.as_str()),
+                "unsupported arch '{arch}', expected one of: {}, {ALL_ARCHES}",
+                SUPPORTED_ARCHES.join(", ")
+            );
+            vec![arch.clone()]
+        };
+        for arch in expanded {
+            if !arches.contains(&arch) {
+                arches.push(arch);
+            }
+        }
+    }
+    Ok(arches)
+}
+
+/// The runs to make, each building or fetching something for one architecture.
+pub(crate) struct ArchRuns<'a> {
+    parallel: bool,
+    runs: Vec<(String, String, LocalBoxFuture<'a, Result<()>>)>,
+}
+
+impl<'a> ArchRuns<'a> {
+    /// When `parallel` is false, the runs are made in the order they were added and stop at the
+    /// first failure.
+    pub(crate) fn new(parallel: bool) -> Self {
+        Self {
+            parallel,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Adds a run of `target`, e.g. `variant 'aws-dev'`, for `arch`.
+    pub(crate) fn push(
+        &mut self,
+        target: impl Into<String>,
+        arch: impl Into<String>,
+        run: LocalBoxFuture<'a, Result<()>>,
+    ) {
+        self.runs.push((target.into(), arch.into(), run));
+    }
+
+    /// Makes the runs, then logs a summary of them when there is more than one. Errors if any run
+    /// failed.
+    pub(crate) async fn run(self) -> Result<()> {
+        let results = if self.parallel {
+            join_all(
+                self.runs
+                    .into_iter()
+                    .map(|(target, arch, run)| timed(target, arch, run)),
+            )
+            .await
+        } else {
+            let mut results = Vec::new();
+            let mut failed = false;
+            for (target, arch, run) in self.runs {
+                let result = if failed {
+                    ArchRun {
+                        target,
+                        arch,
+                        outcome: Outcome::Skipped,
+                    }
+                } else {
+                    timed(target, arch, run).await
+                };
+                failed |= matches!(result.outcome, Outcome::Failed(..));
+                results.push(result);
+            }
+            results
+        };
+        summarize(results)
+    }
+}
+
+/// How one run went.
+struct ArchRun {
+    target: String,
+    arch: String,
+    outcome: Outcome,
+}
+
+enum Outcome {
+    Succeeded(Duration),
+    Failed(Duration, anyhow::Error),
+    /// Not run, because an earlier run failed.
+    Skipped,
+}
+
+impl Display for ArchRun {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} for {}: ", self.target, self.arch)?;
+        match &self.outcome {
+            Outcome::Succeeded(duration) => write!(f, "succeeded in {}s", duration.as_secs()),
+            Outcome::Failed(duration, _) => write!(f, "failed after {}s", duration.as_secs()),
+            Outcome::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+async fn timed(target: String, arch: String, run: LocalBoxFuture<'_, Result<()>>) -> ArchRun {
+    let start = Instant::now();
+    let outcome = match run.await {
+        Ok(()) => Outcome::Succeeded(start.elapsed()),
+        Err(e) => Outcome::Failed(start.elapsed(), e),
+    };
+    ArchRun {
+        target,
+        arch,
+        outcome,
+    }
+}
+
+/// Logs a summary of the runs when there is more than one. A single failed run returns its own
+/// error, while several are each logged and reported together.
+fn summarize(mut results: Vec<ArchRun>) -> Result<()> {
+    if results.len() <= 1 {
+        return match results.pop().map(|result| result.outcome) {
+            Some(Outcome::Failed(_, e)) => Err(e),
+            _ => Ok(()),
+        };
+    }
+    info!("Summary:");
+    for result in &results {
+        info!("  {result}");
+    }
+    let mut failures = Vec::new();
+    for result in results {
+        if let Outcome::Failed(_, e) = result.outcome {
+            error!("{} for {} failed: {e:?}", result.target, result.arch);
+            failures.push(format!("{} for {}", result.target, result.arch));
+        }
+    }
+    ensure!(failures.is_empty(), "failed: {}", failures.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_expand_arches() {
+        let arches = |requested: &[&str]| {
+            expand_arches(
+                &requested
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(arches(&[]).unwrap(), Vec::<String>::new());
+        assert_eq!(arches(&["aarch64"]).unwrap(), vec!["aarch64"]);
+        assert_eq!(
+            arches(&["aarch64", "all", "x86_64"]).unwrap(),
+            vec!["aarch64", "x86_64"]
+        );
+        assert!(arches(&["riscv64"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_runs_are_reported() {
+        for (parallel, expected_runs) in [(false, 1), (true, 2)] {
+            let count = AtomicUsize::new(0);
+            let mut runs = ArchRuns::new(parallel);
+            for arch in ["x86_64", "aarch64"] {
+                let count = &count;
+                let run = async move {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    anyhow::bail!("{arch} broke")
+                };
+                runs.push("kit 'core-kit'", arch, run.boxed_local());
+            }
+            let error = runs.run().await.unwrap_err().to_string();
+            assert_eq!(count.load(Ordering::SeqCst), expected_runs);
+            assert!(error.contains("kit 'core-kit' for x86_64"), "{error}");
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Writes a manifest of the checksums of a variant build's artifacts, signs it, and verifies
+//! downloaded artifacts against it. The manifest is written as `SHA256SUMS` next to the artifacts,
+//! in the format of `sha256sum`, and signed with ssh-keygen, cosign or minisign into
+//! `SHA256SUMS.sig` when `[artifact-signing]` is configured. `pubsys upload-images` uploads the
+//! files the manifest lists along with the manifest and its signature.
+use crate::build_output::{BuildOutput, BUILD_OUTPUT_FILE};
+use crate::common::{exec, fs};
+use crate::kit_contents::sha256_file;
+use crate::project::{ArtifactSigning, SigningMethod};
+use anyhow::{bail, ensure, Context, Result};
+use buildsys::checksums::{self, CHECKSUMS_FILE, SIGNATURE_FILE};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use tokio::process::Command;
+use tracing::info;
+
+/// The namespace of signatures made with ssh-keygen, so that they can't be mistaken for
+/// signatures of anything else made with the same key.
+const SSH_NAMESPACE: &str = "twoliter-artifacts";
+
+/// The identity given to the public key when verifying signatures made with ssh-keygen.
+const SSH_IDENTITY: &str = "twoliter";
+
+/// Writes the checksum manifest of `build_output`, including its `build-output.json`, removing the
+/// signature of an earlier manifest. Returns the path to the manifest, which is what `pubsys
+/// upload-images` uploads.
+pub(crate) async fn write_manifest(build_output: &BuildOutput) -> Result<PathBuf> {
+    let output_dir = &build_output.output_dir;
+    let mut entries: Vec<(String, PathBuf)> = build_output
+        .artifacts
+        .iter()
+        .map(|artifact| (artifact.sha256.clone(), artifact.path.clone()))
+        .collect();
+    let build_output_file = output_dir.join(BUILD_OUTPUT_FILE);
+    if build_output_file.is_file() {
+        entries.push((
+            sha256_file(&build_output_file)?,
+            PathBuf::from(BUILD_OUTPUT_FILE),
+        ));
+    }
+    entries.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let manifest = output_dir.join(CHECKSUMS_FILE);
+    fs::write(&manifest, checksums::render(&entries)).await?;
+    let signature = output_dir.join(SIGNATURE_FILE);
+    if signature.exists() {
+        fs::remove_file(&signature).await?;
+    }
+    info!(
+        "Wrote the checksums of {} artifacts to '{}'",
+        entries.len(),
+        manifest.display()
+    );
+    Ok(manifest)
+}
+
+/// Signs the checksum manifest written by [`write_manifest`] as configured, into `SHA256SUMS.sig`
+/// next to it, running `cosign` when signing with cosign.
+pub(crate) async fn sign(manifest: &Path, signing: &ArtifactSigning, cosign: &Path) -> Result<()> {
+    let signature = manifest.with_file_name(SIGNATURE_FILE);
+    sign_file(signing, cosign, manifest, &signature).await?;
+    info!("Signed '{}'", manifest.display());
+    Ok(())
+}
+
+/// Checks the signature of the checksum manifest in `dir` with `public_key`, then checks every
+/// file the manifest lists against its checksum. Returns the number of files checked. With
+/// `tlog`, a cosign signature must also be recorded in the Rekor transparency log. Cosign
+/// signatures are checked by running `cosign`.
+pub(crate) async fn verify(
+    dir: &Path,
+    method: SigningMethod,
+    public_key: &Path,
+    tlog: bool,
+    cosign: &Path,
+) -> Result<usize> {
+    let manifest = dir.join(CHECKSUMS_FILE);
+    let signature = dir.join(SIGNATURE_FILE);
+    ensure!(
+        manifest.is_file() && signature.is_file(),
+        "'{}' does not contain '{CHECKSUMS_FILE}' and '{SIGNATURE_FILE}'",
+        dir.display()
+    );
+    verify_file(method, public_key, tlog, cosign, &manifest, &signature)
+        .await
+        .context(format!(
+            "The signature of '{}' is not valid for '{}'",
+            manifest.display(),
+            public_key.display()
+        ))?;
+
+    let entries = checksums::parse(&fs::read_to_string(&manifest).await?)?;
+    let mut problems = Vec::new();
+    for (sha256, path) in &entries {
+        let file = dir.join(path);
+        if !file.is_file() {
+            problems.push(format!("'{}' is missing", path.display()));
+        } else if &sha256_file(&file)? != sha256 {
+            problems.push(format!("'{}' does not match its checksum", path.display()));
+        }
+    }
+    if !problems.is_empty() {
+        bail!(
+            "{} of {} artifacts failed verification:\n  {}",
+            problems.len(),
+            entries.len(),
+            problems.join("\n  ")
+        );
+    }
+    Ok(entries.len())
+}
+
+async fn sign_file(
+    signing: &ArtifactSigning,
+    cosign: &Path,
+    file: &Path,
+    signature: &Path,
+) -> Result<()> {
+    let key = &signing.key;
+    let mut cmd = match signing.method {
+        SigningMethod::Ssh => {
+            // ssh-keygen writes the signature next to the file, with `.sig` appended.
+            let mut cmd = Command::new("ssh-keygen");
+            cmd.args(["-Y", "sign", "-n", SSH_NAMESPACE, "-f"])
+                .arg(key)
+                .arg(file);
+            cmd
+        }
+        SigningMethod::Cosign => {
+            // Signatures are kept out of the public transparency log unless it is asked for, since
+            // the log is public and permanent.
+            let mut cmd = Command::new(cosign);
+            cmd.args(["sign-blob", "--yes", "--key"])
+                .arg(key)
+                .arg(format!("--tlog-upload={}", signing.tlog_upload))
+                .arg("--output-signature")
+                .arg(signature)
+                .arg(file);
+            cmd
+        }
+        SigningMethod::Minisign => {
+            let mut cmd = Command::new("minisign");
+            cmd.args(["-S", "-s"])
+                .arg(key)
+                .arg("-m")
+                .arg(file)
+                .arg("-x")
+                .arg(signature);
+            cmd
+        }
+    };
+    exec(&mut cmd, true).await.context(format!(
+        "Unable to sign '{}' with {}",
+        file.display(),
+        signing.method.tool()
+    ))?;
+    Ok(())
+}
+
+async fn verify_file(
+    method: SigningMethod,
+    public_key: &Path,
+    tlog: bool,
+    cosign: &Path,
+    file: &Path,
+    signature: &Path,
+) -> Result<()> {
+    // Kept until ssh-keygen has run, since it reads the allowed signers from the directory.
+    let mut _allowed_signers_dir = None;
+    let mut cmd = match method {
+        SigningMethod::Ssh => {
+            let dir = TempDir::new().context("Unable to create a tempdir for allowed signers")?;
+            let allowed_signers = dir.path().join("allowed_signers");
+            let key = fs::read_to_string(public_key).await?;
+            fs::write(
+                &allowed_signers,
+                format!(
+                    "{SSH_IDENTITY} namespaces=\"{SSH_NAMESPACE}\" {}\n",
+                    key.trim()
+                ),
+            )
+            .await?;
+            let mut cmd = Command::new("ssh-keygen");
+            cmd.args([
+                "-Y",
+                "verify",
+                "-n",
+                SSH_NAMESPACE,
+                "-I",
+                SSH_IDENTITY,
+                "-f",
+            ])
+            .arg(&allowed_signers)
+            .arg("-s")
+            .arg(signature)
+            .stdin(
+                std::fs::File::open(file)
+                    .context(format!("Unable to open '{}'", file.display()))?,
+            );
+            _allowed_signers_dir = Some(dir);
+            cmd
+        }
+        SigningMethod::Cosign => {
+            let mut cmd = Command::new(cosign);
+            cmd.args(["verify-blob", "--key"])
+                .arg(public_key)
+                .arg("--signature")
+                .arg(signature);
+            if !tlog {
+                cmd.arg("--insecure-ignore-tlog");
+            }
+            cmd.arg(file);
+            cmd
+        }
+        SigningMethod::Minisign => {
+            let mut cmd = Command::new("minisign");
+            cmd.args(["-V", "-p"])
+                .arg(public_key)
+                .arg("-m")
+                .arg(file)
+                .arg("-x")
+                .arg(signature);
+            cmd
+        }
+    };
+    exec(&mut cmd, true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn signs_and_verifies_with_ssh_keygen() {
+        let temp_dir = TempDir::new().unwrap();
+        let key = temp_dir.path().join("key");
+        let generated = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .status();
+        if !generated.is_ok_and(|status| status.success()) {
+            // ssh-keygen is not installed.
+            return;
+        }
+        let output_dir = temp_dir.path().join("output");
+        std::fs::create_dir(&output_dir).unwrap();
+        std::fs::write(output_dir.join("image.img.lz4"), "image").unwrap();
+        let build_output = BuildOutput::scan("aws-dev", "x86_64", "1.0.0", &output_dir)
+            .await
+            .unwrap();
+        build_output.write().await.unwrap();
+        let signing = ArtifactSigning {
+            method: SigningMethod::Ssh,
+            key: key.clone(),
+            tlog_upload: false,
+        };
+        let manifest = write_manifest(&build_output).await.unwrap();
+        sign(&manifest, &signing, Path::new("cosign"))
+            .await
+            .unwrap();
+
+        let public_key = temp_dir.path().join("key.pub");
+        let checked = verify(
+            &output_dir,
+            SigningMethod::Ssh,
+            &public_key,
+            false,
+            Path::new("cosign"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(checked, 2);
+
+        std::fs::write(output_dir.join("image.img.lz4"), "tampered").unwrap();
+        assert!(verify(
+            &output_dir,
+            SigningMethod::Ssh,
+            &public_key,
+            false,
+            Path::new("cosign")
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Reports the errors and warnings of the package, kit and variant builds of `twoliter build
+//! variant` together once the build has finished, rather than leaving them scattered through
+//! Cargo's output. Buildsys appends them as lines of JSON to
+//! `build/diagnostics/<arch>-<variant>.jsonl`, which is kept for CI systems to read.
+use crate::common::fs;
+use anyhow::{Context, Result};
+use buildsys::diagnostics::{Diagnostic, Level, DIAGNOSTICS_PATH_VAR, OUTPUT_FORMAT_VAR};
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+/// The diagnostics file of one variant build.
+#[derive(Debug)]
+pub(crate) struct Diagnostics {
+    path: PathBuf,
+}
+
+impl Diagnostics {
+    /// Prepares `path` for a build, removing the diagnostics of the last one.
+    pub(crate) async fn create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            fs::remove_file(path).await?;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The environment variables which have buildsys record its diagnostics in the file.
+    pub(crate) fn env(&self) -> [(&'static str, String); 2] {
+        [
+            (OUTPUT_FORMAT_VAR, "json".to_string()),
+            (DIAGNOSTICS_PATH_VAR, self.path.display().to_string()),
+        ]
+    }
+
+    /// The diagnostics buildsys recorded.
+    fn load(&self) -> Result<Vec<Diagnostic>> {
+        Diagnostic::load(&self.path).context(format!("Unable to read '{}'", self.path.display()))
+    }
+
+    /// Logs the diagnostics buildsys recorded.
+    pub(crate) fn report(&self) -> Result<()> {
+        for diagnostic in self.load()? {
+            match diagnostic.level {
+                Level::Error => error!("{}", describe(&diagnostic)),
+                Level::Warning => warn!("{}", describe(&diagnostic)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes a diagnostic on one line, followed by its hint.
+fn describe(diagnostic: &Diagnostic) -> String {
+    let mut description = match &diagnostic.file {
+        Some(file) => format!("{}: {}", file.display(), diagnostic.message),
+        None => diagnostic.message.clone(),
+    };
+    description.push_str(&format!(" [{}]", diagnostic.code));
+    if let Some(hint) = &diagnostic.hint {
+        description.push_str(&format!("\n  {hint}"));
+    }
+    description
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_recorded_diagnostics() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("diagnostics/x86_64-aws-dev.jsonl");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "stale").unwrap();
+        let diagnostics = Diagnostics::create(&path).await.unwrap();
+        assert!(!path.exists());
+        assert!(diagnostics.load().unwrap().is_empty());
+
+        let warning = Diagnostic::warning("unused-patch", "patch 'a.patch' is never applied")
+            .with_file("packages/pkg/pkg.spec");
+        let error = Diagnostic::error("missing-env", "Missing BUILDSYS_ARCH")
+            .with_hint("Run the build through Twoliter");
+        let lines: Vec<_> = [&warning, &error]
+            .iter()
+            .map(|d| serde_json::to_string(d).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        assert_eq!(
+            diagnostics.load().unwrap(),
+            vec![warning.clone(), error.clone()]
+        );
+        diagnostics.report().unwrap();
+
+        assert_eq!(
+            describe(&warning),
+            "packages/pkg/pkg.spec: patch 'a.patch' is never applied [unused-patch]"
+        );
+        assert_eq!(
+            describe(&error),
+            "Missing BUILDSYS_ARCH [missing-env]\n  Run the build through Twoliter"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Follows the events buildsys reports while a build runs, to log each package, kit and variant
+//! build as it starts, is skipped, warns or finishes, and to summarize the build once it is done.
+//! Cargo only shows the output of buildsys when a build fails, so buildsys appends its events as
+//! lines of JSON to a file under `build/events`, such as `<arch>-<variant>.jsonl`, which is kept as
+//! a machine-readable record of the build.
+use crate::common::fs;
+use anyhow::{Context, Result};
+use buildsys::events::{BuildEvent, Event};
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{debug, error, info, warn};
+
+/// How often the events are checked while the build runs.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How many builds ran, were skipped or failed, as counted from their events.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Summary {
+    pub(crate) built: usize,
+    pub(crate) reused: usize,
+    pub(crate) failed: usize,
+    pub(crate) warnings: usize,
+    pub(crate) artifacts: usize,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} built, {} reused from checkpoints, {} failed, {} warnings, {} artifacts produced",
+            self.built, self.reused, self.failed, self.warnings, self.artifacts
+        )
+    }
+}
+
+/// The events of one package, kit or variant build, read as buildsys appends them.
+#[derive(Debug)]
+pub(crate) struct EventLog {
+    path: PathBuf,
+    /// How much of the file has been read
+    offset: u64,
+    /// The builds which were skipped, by kind and name
+    reused: HashSet<(String, String)>,
+    summary: Summary,
+}
+
+impl EventLog {
+    /// Starts a log at `path`, removing the events of an earlier build.
+    pub(crate) async fn create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            fs::remove_file(path).await?;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            offset: 0,
+            reused: HashSet::new(),
+            summary: Summary::default(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn summary(&self) -> Summary {
+        self.summary
+    }
+
+    /// Logs the events buildsys reports until `build` finishes, returning its output.
+    pub(crate) async fn follow<T>(&mut self, build: impl Future<Output = T>) -> T {
+        tokio::pin!(build);
+        loop {
+            tokio::select! {
+                output = &mut build => {
+                    self.report_new().await;
+                    return output;
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => self.report_new().await,
+            }
+        }
+    }
+
+    /// Logs the events written since the last read. The build is more important than its
+    /// progress, so events that can't be read are skipped.
+    async fn report_new(&mut self) {
+        match self.read_new().await {
+            Ok(events) => events.iter().for_each(|event| self.report(event)),
+            Err(e) => debug!("Unable to read the build's events: {e:?}"),
+        }
+    }
+
+    /// Reads the events written since the last read, from where that read stopped. A line that is
+    /// still being written is left for the next read.
+    async fn read_new(&mut self) -> Result<Vec<BuildEvent>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut file = tokio::fs::File::open(&self.path)
+            .await
+            .context(format!("Unable to open '{}'", self.path.display()))?;
+        file.seek(SeekFrom::Start(self.offset))
+            .await
+            .context(format!("Unable to seek in '{}'", self.path.display()))?;
+        let mut new = Vec::new();
+        file.read_to_end(&mut new)
+            .await
+            .context(format!("Unable to read '{}'", self.path.display()))?;
+        let Some(end) = new.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(Vec::new());
+        };
+        let new = std::str::from_utf8(&new[..end])
+            .context(format!("'{}' is not valid UTF-8", self.path.display()))?;
+        self.offset += end as u64 + 1;
+        Ok(new
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    debug!("Skipping the build event '{line}': {e}");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Logs `event` and counts it in the summary.
+    fn report(&mut self, event: &BuildEvent) {
+        let BuildEvent {
+            name, kind, arch, ..
+        } = event;
+        match &event.event {
+            Event::StageStarted => info!("Building {kind} '{name}' ({arch})"),
+            Event::CacheHit => {
+                info!("Reusing {kind} '{name}' ({arch}), its inputs are unchanged");
+                self.reused.insert((kind.clone(), name.clone()));
+                self.summary.reused += 1;
+            }
+            Event::Warning { message } => {
+                warn!("{kind} '{name}' ({arch}): {message}");
+                self.summary.warnings += 1;
+            }
+            Event::Message { message } => info!("{kind} '{name}' ({arch}): {message}"),
+            Event::ArtifactProduced { path } => {
+                debug!("{kind} '{name}' ({arch}) produced '{}'", path.display());
+                self.summary.artifacts += 1;
+            }
+            Event::StageFinished { succeeded: true } => {
+                if !self.reused.contains(&(kind.clone(), name.clone())) {
+                    info!("Built {kind} '{name}' ({arch})");
+                    self.summary.built += 1;
+                }
+            }
+            Event::StageFinished { succeeded: false } => {
+                error!("Failed to build {kind} '{name}' ({arch})");
+                self.summary.failed += 1;
+            }
+            Event::Unknown => debug!("Skipping an unknown event of {kind} '{name}' ({arch})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn reads_events_as_they_are_written() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("events/x86_64-aws-dev.jsonl");
+        let mut log = EventLog::create(&path).await.unwrap();
+        assert!(log.read_new().await.unwrap().is_empty());
+
+        let mut file = std::fs::File::create(&path).unwrap();
+        for line in [
+            r#"{"name":"glibc","kind":"package","arch":"x86_64","time-ms":1,"event":"stage-started"}"#,
+            r#"{"name":"glibc","kind":"package","arch":"x86_64","time-ms":2,"event":"cache-hit"}"#,
+            r#"{"name":"glibc","kind":"package","arch":"x86_64","time-ms":3,"event":"stage-finished","succeeded":true}"#,
+            r#"{"name":"kernel","kind":"package","arch":"x86_64","time-ms":4,"event":"warning","message":"retrying"}"#,
+            r#"{"name":"kernel","kind":"package","arch":"x86_64","time-ms":4,"event":"message","message":"waiting"}"#,
+            r#"{"name":"kernel","kind":"package","arch":"x86_64","time-ms":5,"event":"something-new"}"#,
+        ] {
+            writeln!(file, "{line}").unwrap();
+        }
+        file.write_all(
+            r#"{"name":"kernel","kind":"package","arch":"x86_64","time-ms":6,"event":"stage-fin"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let events = log.read_new().await.unwrap();
+        assert_eq!(events.len(), 6);
+        assert_eq!(
+            events[3].event,
+            Event::Warning {
+                message: "retrying".to_string()
+            }
+        );
+        assert_eq!(
+            events[4].event,
+            Event::Message {
+                message: "waiting".to_string()
+            }
+        );
+        assert_eq!(events[5].event, Event::Unknown);
+        events.iter().for_each(|event| log.report(event));
+
+        writeln!(file, r#"ished","succeeded":true}}"#).unwrap();
+        let events = log.read_new().await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(log.offset, std::fs::metadata(&path).unwrap().len());
+        assert!(log.read_new().await.unwrap().is_empty());
+        log.report(&events[0]);
+        assert_eq!(
+            log.summary(),
+            Summary {
+                built: 1,
+                reused: 1,
+                failed: 0,
+                warnings: 1,
+                artifacts: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Lists the package and kit builds which failed during `twoliter build variant --keep-going`,
+//! from the failures buildsys records for every build it runs.
+use crate::common::fs;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// A package or kit build which failed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct BuildFailure {
+    /// Which build failed, and why
+    pub(crate) description: String,
+    /// The log of the failed build, which starts with the description
+    pub(crate) log: PathBuf,
+}
+
+impl BuildFailure {
+    /// Loads the failures buildsys recorded in `failures_dir`, ordered by the name of their log.
+    pub(crate) async fn load_all(failures_dir: &Path) -> Result<Vec<Self>> {
+        let mut failures = Vec::new();
+        if !failures_dir.is_dir() {
+            return Ok(failures);
+        }
+        let mut entries = tokio::fs::read_dir(failures_dir)
+            .await
+            .context(format!("Unable to read '{}'", failures_dir.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("Unable to read '{}'", failures_dir.display()))?
+        {
+            let log = entry.path();
+            let contents = fs::read_to_string(&log).await?;
+            failures.push(Self {
+                description: contents.lines().next().unwrap_or_default().to_string(),
+                log,
+            });
+        }
+        failures.sort_by(|a, b| a.log.cmp(&b.log));
+        Ok(failures)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn lists_failed_builds() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let failures_dir = temp_dir.path().join("failures");
+        assert!(BuildFailure::load_all(&failures_dir)
+            .await
+            .unwrap()
+            .is_empty());
+
+        std::fs::create_dir(&failures_dir).unwrap();
+        std::fs::write(
+            failures_dir.join("x86_64-package-pkg-b.log"),
+            "Failed to build package 'pkg-b': Failed to execute command\n\n#1 rpmbuild failed\n",
+        )
+        .unwrap();
+        std::fs::write(
+            failures_dir.join("x86_64-package-pkg-a.log"),
+            "Failed to build package 'pkg-a': Failed to execute command\n",
+        )
+        .unwrap();
+
+        let failures = BuildFailure::load_all(&failures_dir).await.unwrap();
+        assert_eq!(
+            failures,
+            vec![
+                BuildFailure {
+                    description: "Failed to build package 'pkg-a': Failed to execute command"
+                        .to_string(),
+                    log: failures_dir.join("x86_64-package-pkg-a.log"),
+                },
+                BuildFailure {
+                    description: "Failed to build package 'pkg-b': Failed to execute command"
+                        .to_string(),
+                    log: failures_dir.join("x86_64-package-pkg-b.log"),
+                },
+            ]
+        );
+    }
+}
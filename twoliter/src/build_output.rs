@@ -0,0 +1,361 @@
+//! Describes the artifacts of a variant build in `build-output.json`, written next to them, so that
+//! publishing automation does not need to scrape the output directory and guess what each file is.
+use crate::common::fs;
+use crate::kit_contents::sha256_file;
+use anyhow::{Context, Result};
+use buildsys::checksums::{CHECKSUMS_FILE, SIGNATURE_FILE};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// The name of the manifest written to the output directory of a variant build.
+pub(crate) const BUILD_OUTPUT_FILE: &str = "build-output.json";
+
+/// The suffixes of disk images, and the format each is in.
+const DISK_IMAGE_FORMATS: &[(&str, &str)] = &[
+    (".img", "raw"),
+    (".img.gz", "raw-gz"),
+    (".img.lz4", "raw-lz4"),
+    (".qcow2", "qcow2"),
+    (".vhd", "vhd"),
+    (".vhdx", "vhdx"),
+    (".vmdk", "vmdk"),
+];
+
+/// The artifacts produced by building a variant for one architecture.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BuildOutput {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    /// The release version of the project, from `Twoliter.toml`
+    pub(crate) version: String,
+    /// The directory holding the artifacts, with symlinks resolved
+    pub(crate) output_dir: PathBuf,
+    pub(crate) artifacts: Vec<Artifact>,
+}
+
+/// A file produced by a variant build.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Artifact {
+    pub(crate) kind: ArtifactKind,
+    /// The format of a disk image, e.g. `qcow2` or `raw-lz4`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) format: Option<String>,
+    /// The path of the file, relative to the output directory
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) sha256: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ArtifactKind {
+    /// A disk or partition image, e.g. `<name>.img.lz4` or `<name>-root.verity.lz4`
+    Image,
+    /// The dm-verity parameters, partition UUIDs and measurements of an OS image
+    ImageReport,
+    /// The kit for building out-of-tree kernel modules
+    KmodKit,
+    /// The archive of data store migrations
+    Migrations,
+    /// A VMware OVA
+    Ova,
+    /// Any other file, e.g. a package inventory
+    Other,
+}
+
+impl ArtifactKind {
+    fn from_file_name(name: &str) -> Self {
+        const PARTITION_IMAGE_SUFFIXES: &[&str] = &[".ext4.lz4", ".verity.lz4"];
+        if name.ends_with(".ova") {
+            Self::Ova
+        } else if name.ends_with(".report.json") {
+            Self::ImageReport
+        } else if name.contains("-kmod-kit-") {
+            Self::KmodKit
+        } else if name.ends_with("-migrations.tar") {
+            Self::Migrations
+        } else if disk_image_format(name).is_some()
+            || PARTITION_IMAGE_SUFFIXES
+                .iter()
+                .any(|suffix| name.ends_with(suffix))
+        {
+            Self::Image
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// The report written beside each OS image of the dm-verity parameters, partition UUIDs and
+/// measurements which attestation and Secure Boot tooling need.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ImageReport {
+    /// The name of the uncompressed OS image
+    pub(crate) image: String,
+    /// The digest of the uncompressed OS image
+    pub(crate) sha256: String,
+    pub(crate) disk_guid: String,
+    pub(crate) partitions: Vec<PartitionReport>,
+    pub(crate) verity: VerityReport,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PartitionReport {
+    pub(crate) number: u32,
+    pub(crate) name: String,
+    pub(crate) type_guid: String,
+    pub(crate) unique_guid: String,
+    pub(crate) first_sector: u64,
+    pub(crate) last_sector: u64,
+    /// The digest of the partition, for the partitions the image boots from. The others change on
+    /// a running host, so they aren't measured.
+    pub(crate) sha256: Option<String>,
+}
+
+/// The dm-verity parameters of the root partition.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct VerityReport {
+    pub(crate) version: u32,
+    pub(crate) hash_algorithm: String,
+    pub(crate) data_block_size: u32,
+    pub(crate) hash_block_size: u32,
+    pub(crate) data_blocks: u64,
+    pub(crate) root_hash: String,
+    pub(crate) salt: String,
+}
+
+/// The format of the disk image named `name`, if it is one.
+fn disk_image_format(name: &str) -> Option<&'static str> {
+    DISK_IMAGE_FORMATS
+        .iter()
+        .find(|(suffix, _)| name.ends_with(suffix))
+        .map(|(_, format)| *format)
+}
+
+impl BuildOutput {
+    /// Describes every file in `output_dir`, skipping symlinks since they point at files which are
+    /// listed under their own names.
+    pub(crate) async fn scan(
+        variant: &str,
+        arch: &str,
+        version: &str,
+        output_dir: &Path,
+    ) -> Result<Self> {
+        let output_dir = fs::canonicalize(output_dir).await?;
+        let dir = output_dir.clone();
+        let artifacts = tokio::task::spawn_blocking(move || {
+            let mut artifacts = Vec::new();
+            scan_dir(&dir, Path::new(""), &mut artifacts)?;
+            artifacts.sort_by(|a: &Artifact, b| a.path.cmp(&b.path));
+            Ok::<_, anyhow::Error>(artifacts)
+        })
+        .await
+        .context("build output scan task panicked")??;
+        Ok(Self {
+            variant: variant.to_string(),
+            arch: arch.to_string(),
+            version: version.to_string(),
+            output_dir,
+            artifacts,
+        })
+    }
+
+    /// Reads the reports of the OS images among the artifacts.
+    pub(crate) async fn image_reports(&self) -> Result<Vec<ImageReport>> {
+        let mut reports = Vec::new();
+        for artifact in &self.artifacts {
+            if artifact.kind == ArtifactKind::ImageReport {
+                let path = self.output_dir.join(&artifact.path);
+                let report = serde_json::from_str(&fs::read_to_string(&path).await?)
+                    .context(format!("Unable to parse '{}'", path.display()))?;
+                reports.push(report);
+            }
+        }
+        Ok(reports)
+    }
+
+    /// Writes the manifest to `build-output.json` in the output directory.
+    pub(crate) async fn write(&self) -> Result<()> {
+        let path = self.output_dir.join(BUILD_OUTPUT_FILE);
+        let contents =
+            serde_json::to_string_pretty(self).context("Unable to serialize the build output")?;
+        fs::write(&path, contents).await?;
+        info!("Wrote the build output manifest to '{}'", path.display());
+        Ok(())
+    }
+}
+
+fn scan_dir(root: &Path, relative: &Path, artifacts: &mut Vec<Artifact>) -> Result<()> {
+    let dir = root.join(relative);
+    for entry in std::fs::read_dir(&dir).context(format!("failed to read '{}'", dir.display()))? {
+        let entry = entry.context(format!("failed to read entry in '{}'", dir.display()))?;
+        let relative = relative.join(entry.file_name());
+        // The manifests describing the artifacts aren't artifacts themselves.
+        if [BUILD_OUTPUT_FILE, CHECKSUMS_FILE, SIGNATURE_FILE]
+            .iter()
+            .any(|manifest| relative == Path::new(manifest))
+        {
+            continue;
+        }
+        let path = entry.path();
+        let metadata = std::fs::symlink_metadata(&path)
+            .context(format!("failed to stat '{}'", path.display()))?;
+        if metadata.is_dir() {
+            scan_dir(root, &relative, artifacts)?;
+        } else if metadata.is_file() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            artifacts.push(Artifact {
+                kind: ArtifactKind::from_file_name(&name),
+                format: disk_image_format(&name).map(str::to_string),
+                path: relative,
+                size: metadata.len(),
+                sha256: sha256_file(&path)?,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn describes_build_artifacts() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let output_dir = temp_dir.path();
+        let name = "bottlerocket-aws-dev-x86_64-1.0.0-abcdef";
+        std::fs::write(output_dir.join(format!("{name}.img.lz4")), "image").unwrap();
+        std::fs::write(output_dir.join(format!("{name}.img.gz")), "gz").unwrap();
+        std::fs::write(output_dir.join(format!("{name}.vhd")), "vhd").unwrap();
+        std::fs::write(output_dir.join(format!("{name}-migrations.tar")), "").unwrap();
+        std::fs::write(
+            output_dir.join("aws-dev-x86_64-kmod-kit-v1.0.0.tar.xz"),
+            "kmod",
+        )
+        .unwrap();
+        std::fs::create_dir(output_dir.join("extra")).unwrap();
+        std::fs::write(output_dir.join("extra/application-inventory.json"), "{}").unwrap();
+        std::os::unix::fs::symlink(
+            format!("{name}.img.lz4"),
+            output_dir.join("bottlerocket-aws-dev-x86_64.img.lz4"),
+        )
+        .unwrap();
+
+        let output = BuildOutput::scan("aws-dev", "x86_64", "1.0.0", output_dir)
+            .await
+            .unwrap();
+        output.write().await.unwrap();
+        let kinds: Vec<(String, ArtifactKind)> = output
+            .artifacts
+            .iter()
+            .map(|artifact| (artifact.path.display().to_string(), artifact.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    "aws-dev-x86_64-kmod-kit-v1.0.0.tar.xz".to_string(),
+                    ArtifactKind::KmodKit
+                ),
+                (format!("{name}-migrations.tar"), ArtifactKind::Migrations),
+                (format!("{name}.img.gz"), ArtifactKind::Image),
+                (format!("{name}.img.lz4"), ArtifactKind::Image),
+                (format!("{name}.vhd"), ArtifactKind::Image),
+                (
+                    "extra/application-inventory.json".to_string(),
+                    ArtifactKind::Other
+                ),
+            ]
+        );
+        let formats: Vec<Option<&str>> = output
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.format.as_deref())
+            .collect();
+        assert_eq!(
+            formats,
+            vec![
+                None,
+                None,
+                Some("raw-gz"),
+                Some("raw-lz4"),
+                Some("vhd"),
+                None
+            ]
+        );
+        assert_eq!(output.artifacts[3].size, 5);
+        assert_eq!(
+            output.artifacts[3].sha256,
+            "6105d6cc76af400325e94d588ce511be5bfdbb73b437dc51eca43917d7a43e3d"
+        );
+
+        // The manifest is not listed when scanning again.
+        let rescanned = BuildOutput::scan("aws-dev", "x86_64", "1.0.0", output_dir)
+            .await
+            .unwrap();
+        assert_eq!(rescanned, output);
+    }
+
+    #[tokio::test]
+    async fn reads_image_reports() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let output_dir = temp_dir.path();
+        let name = "bottlerocket-aws-dev-x86_64-1.0.0-abcdef";
+        std::fs::write(output_dir.join(format!("{name}.img.lz4")), "image").unwrap();
+        std::fs::write(
+            output_dir.join(format!("{name}.report.json")),
+            r#"{
+  "image": "bottlerocket-aws-dev-x86_64-1.0.0-abcdef.img",
+  "sha256": "c161e96545902807b8004e24208e79d1c915ca052c830edc38e51fd0fc78dbc0",
+  "disk-guid": "6B1E0B31-AAAA-BBBB-CCCC-000000000001",
+  "partitions": [
+    {
+      "number": 1,
+      "name": "BOTTLEROCKET-ROOT-A",
+      "type-guid": "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+      "unique-guid": "5B94E8DF-28B8-485C-9D19-362263B5944C",
+      "first-sector": 2048,
+      "last-sector": 4095,
+      "sha256": "ba5e10f4e513740adbc1c0a848a7c92b6aa5f9d369e97a89b374997c440eb51c"
+    },
+    {
+      "number": 2,
+      "name": "BOTTLEROCKET-PRIVATE",
+      "type-guid": "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+      "unique-guid": "626F7474-6C65-6474-6861-726d61726b73",
+      "first-sector": 4096,
+      "last-sector": 8158,
+      "sha256": null
+    }
+  ],
+  "verity": {
+    "version": 1,
+    "hash-algorithm": "sha256",
+    "data-block-size": 4096,
+    "hash-block-size": 4096,
+    "data-blocks": 2,
+    "root-hash": "deadbeef",
+    "salt": "cafe"
+  }
+}"#,
+        )
+        .unwrap();
+
+        let output = BuildOutput::scan("aws-dev", "x86_64", "1.0.0", output_dir)
+            .await
+            .unwrap();
+        assert_eq!(output.artifacts[1].kind, ArtifactKind::ImageReport);
+        let reports = output.image_reports().await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].image, format!("{name}.img"));
+        assert_eq!(reports[0].verity.root_hash, "deadbeef");
+        assert_eq!(reports[0].partitions[1].sha256, None);
+    }
+}
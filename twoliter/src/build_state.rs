@@ -0,0 +1,77 @@
+//! Remembers the settings of the last `twoliter build variant` in a project, so that running it
+//! again without a variant repeats the previous build.
+use crate::common::fs;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Where the state is kept, relative to the project directory.
+const STATE_PATH: &str = ".twoliter/state.toml";
+
+/// The settings of the last variant build.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BuildState {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) arch: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) variant: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) profile: Option<String>,
+}
+
+impl BuildState {
+    /// Loads the state of the project, which is empty if nothing has been built yet. State which
+    /// cannot be read is ignored with a warning, since it is only a convenience.
+    pub(crate) async fn load(project_dir: &Path) -> Self {
+        let path = state_path(project_dir);
+        if !path.is_file() {
+            return Self::default();
+        }
+        let state = fs::read_to_string(&path).await.and_then(|contents| {
+            toml::from_str(&contents).context(format!("Unable to parse '{}'", path.display()))
+        });
+        state.unwrap_or_else(|e| {
+            warn!("Ignoring the saved build settings: {e:?}");
+            Self::default()
+        })
+    }
+
+    /// Saves the state of the project, replacing what was there.
+    pub(crate) async fn save(&self, project_dir: &Path) -> Result<()> {
+        let path = state_path(project_dir);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let contents = toml::to_string(self).context("Unable to serialize the build settings")?;
+        fs::write(&path, contents).await
+    }
+}
+
+fn state_path(project_dir: &Path) -> PathBuf {
+    project_dir.join(STATE_PATH)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn state_round_trips() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_dir = temp_dir.path();
+        assert_eq!(BuildState::load(project_dir).await, BuildState::default());
+
+        let state = BuildState {
+            arch: vec!["aarch64".into(), "x86_64".into()],
+            variant: Some("my-variant".into()),
+            profile: None,
+        };
+        state.save(project_dir).await.unwrap();
+        assert_eq!(BuildState::load(project_dir).await, state);
+
+        std::fs::write(state_path(project_dir), "arch = [").unwrap();
+        assert_eq!(BuildState::load(project_dir).await, BuildState::default());
+    }
+}
@@ -1,11 +1,12 @@
 use crate::common::{exec_log, BUILDSYS_OUTPUT_GENERATION_ID};
+use crate::local_sdk::Sdk;
 use anyhow::{bail, Result};
 use std::path::PathBuf;
 use tokio::process::Command;
 use tracing::trace;
 
 /// A struct used to invoke `cargo make` tasks with `twoliter`'s `Makefile.toml`.
-/// ```rust
+/// ```ignore
 /// # use crate::project::Project;
 /// # use crate::test::data_dir;
 /// # use self::CargoMake;
@@ -47,13 +48,21 @@ pub struct CargoMake {
 }
 
 impl CargoMake {
-    /// Create a new `cargo make` command. The sdk environment variable will be set based on the
+    /// Create a new `cargo make` command. The sdk environment variables will be set based on the
     /// definition in `Twoliter.toml`.
-    pub(crate) fn new(sdk: &str) -> Result<Self> {
-        Ok(Self::default().env("TLPRIVATE_SDK_IMAGE", sdk).env(
-            "BUILDSYS_OUTPUT_GENERATION_ID",
-            BUILDSYS_OUTPUT_GENERATION_ID.to_string(),
-        ))
+    pub(crate) fn new(sdk: &Sdk) -> Result<Self> {
+        let cargo_make = Self::default()
+            .env("TLPRIVATE_SDK_IMAGE", &sdk.image)
+            .env("TLPRIVATE_SDK_DIGEST", &sdk.digest)
+            .env(
+                "BUILDSYS_OUTPUT_GENERATION_ID",
+                BUILDSYS_OUTPUT_GENERATION_ID.to_string(),
+            );
+        // Builds with buildkit take the SDK from its layout, where there is one.
+        Ok(match &sdk.layout {
+            Some(layout) => cargo_make.env("BUILDSYS_SDK_LAYOUT", layout.display().to_string()),
+            None => cargo_make,
+        })
     }
 
     /// Specify the path to the `Makefile.toml` for the `cargo make` command
@@ -188,7 +197,7 @@ const DISALLOWED_ENV_VARS: [&str; 4] = [
 ];
 
 /// Returns `true` if `key` is an environment variable that needs to be passed to `cargo make`.
-fn is_build_system_env(key: impl AsRef<str>) -> bool {
+pub(crate) fn is_build_system_env(key: impl AsRef<str>) -> bool {
     let key = key.as_ref();
     key.starts_with("BUILDSYS_")
         || key.starts_with("PUBLISH_")
@@ -0,0 +1,94 @@
+use crate::affected::ProjectTargets;
+use crate::common::exec;
+use crate::project;
+use anyhow::{ensure, Context, Result};
+use clap::{Parser, ValueEnum};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Format {
+    Text,
+    Json,
+}
+
+/// Print the packages, kits and variants affected by a change, so that CI can rebuild and test only
+/// those. The change is either the files that differ from a git revision, or a list of paths.
+#[derive(Debug, Parser)]
+pub(crate) struct Affected {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The git revision to compare the working tree with, such as `origin/develop`
+    #[clap(long = "since", conflicts_with = "paths")]
+    pub(crate) since: Option<String>,
+
+    /// Output format. Text prints a line for each, such as `package glibc`
+    #[clap(long = "format", value_enum, default_value = "text")]
+    pub(crate) format: Format,
+
+    /// The changed paths, relative to the current directory
+    pub(crate) paths: Vec<PathBuf>,
+}
+
+impl Affected {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let project_dir = project.project_dir();
+        let changed = match &self.since {
+            Some(revision) => changed_since(&project_dir, revision).await?,
+            None => {
+                ensure!(
+                    !self.paths.is_empty(),
+                    "either a list of changed paths or --since must be given"
+                );
+                let current_dir = std::env::current_dir().context("Unable to get current dir")?;
+                self.paths
+                    .iter()
+                    .map(|path| current_dir.join(path))
+                    .collect()
+            }
+        };
+
+        let targets = ProjectTargets::load(&project_dir).await?;
+        let affected = targets.affected(&changed);
+        match self.format {
+            Format::Text => {
+                let lines = [
+                    ("package", &affected.packages),
+                    ("kit", &affected.kits),
+                    ("variant", &affected.variants),
+                ];
+                for (kind, names) in lines {
+                    for name in names {
+                        println!("{kind} {name}");
+                    }
+                }
+            }
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&affected)
+                    .context("Unable to serialize the affected targets")?
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// The files in the project directory which differ between `revision` and the working tree,
+/// relative to the project directory.
+async fn changed_since(project_dir: &Path, revision: &str) -> Result<Vec<PathBuf>> {
+    let output = exec(
+        Command::new("git")
+            .args(["diff", "--name-only", "--relative", revision, "--"])
+            .current_dir(project_dir),
+        true,
+    )
+    .await
+    .context(format!(
+        "Unable to list the files changed since '{revision}'"
+    ))?
+    .unwrap_or_default();
+    Ok(output.lines().map(PathBuf::from).collect())
+}
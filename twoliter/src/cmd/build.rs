@@ -1,27 +1,54 @@
 use super::build_clean::BuildClean;
+use crate::arch_runs::{expand_arches, ArchRuns};
+use crate::artifact_signing;
+use crate::build_diagnostics::Diagnostics;
+use crate::build_events::EventLog;
+use crate::build_failures::BuildFailure;
+use crate::build_output::BuildOutput;
+use crate::build_state::BuildState;
 use crate::cargo_make::CargoMake;
+use crate::common::exec_log;
 use crate::common::fs;
+use crate::compiler_cache::CacheStats;
+use crate::disk_space::{Estimate, DEFAULT_MIN_FREE_GIB};
+use crate::local_sdk;
 use crate::lock::Lock;
-use crate::project;
+use crate::project::{self, Hook, Project, VariantConfig};
+use crate::sbom::{self, SbomFormat};
+use crate::scaffold;
+use crate::timings::TimingReport;
 use crate::tools::install_tools;
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use buildsys::manifest::OutputFormat;
 use clap::Parser;
-use std::path::PathBuf;
+use futures::FutureExt;
+use std::num::NonZeroU16;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use tempfile::TempDir;
+use tokio::process::Command;
+use tracing::{error, info};
+
+/// The architecture built when none is given or declared for a variant.
+const DEFAULT_ARCH: &str = "x86_64";
 
 #[derive(Debug, Parser)]
 pub(crate) enum BuildCommand {
     Clean(BuildClean),
     Kit(BuildKit),
+    Package(BuildPackage),
+    Sdk(BuildSdk),
     Variant(BuildVariant),
 }
 
 impl BuildCommand {
-    pub(crate) async fn run(self) -> Result<()> {
+    pub(crate) async fn run(self, strict: bool) -> Result<()> {
         match self {
-            BuildCommand::Clean(command) => command.run().await,
-            BuildCommand::Kit(command) => command.run().await,
-            BuildCommand::Variant(command) => command.run().await,
+            BuildCommand::Clean(command) => command.run(strict).await,
+            BuildCommand::Kit(command) => command.run(strict).await,
+            BuildCommand::Package(command) => command.run(strict).await,
+            BuildCommand::Sdk(command) => command.run(strict).await,
+            BuildCommand::Variant(command) => command.run(strict).await,
         }
     }
 }
@@ -33,9 +60,14 @@ pub(crate) struct BuildKit {
     #[clap(long = "project-path")]
     pub(crate) project_path: Option<PathBuf>,
 
-    /// The architecture to build for.
+    /// The architecture to build for. May be given multiple times, or as `all` for every
+    /// supported architecture.
     #[clap(long = "arch", default_value = "x86_64")]
-    pub(crate) arch: String,
+    pub(crate) arch: Vec<String>,
+
+    /// Build for all of the architectures at once, rather than one after another.
+    #[clap(long = "parallel")]
+    pub(crate) parallel: bool,
 
     /// The name of the kit to build.
     pub(crate) kit: String,
@@ -48,53 +80,333 @@ pub(crate) struct BuildKit {
     /// from the upstream URL found in a package's `Cargo.toml`.
     #[clap(long = "upstream-source-fallback")]
     pub(crate) upstream_source_fallback: bool,
+
+    /// The build profile to use, as declared by `[profile.<name>]` in Twoliter.toml.
+    #[clap(long = "profile")]
+    pub(crate) profile: Option<String>,
+
+    /// Rebuild packages even if their inputs are unchanged since they last built successfully.
+    #[clap(long = "no-checkpoints")]
+    pub(crate) no_checkpoints: bool,
+
+    /// Don't record the SLSA provenance of the builds' artifacts in `build/provenance`.
+    #[clap(long = "no-provenance")]
+    pub(crate) no_provenance: bool,
+
+    /// Export the cache of package builds to the `[build-cache]` registry, even if Twoliter.toml
+    /// doesn't ask for it, e.g. only in CI.
+    #[clap(long = "export-build-cache", env = "TWOLITER_EXPORT_BUILD_CACHE")]
+    pub(crate) export_build_cache: bool,
 }
 
 impl BuildKit {
-    pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
         let lock = Lock::load(&project).await?;
         let toolsdir = project.project_dir().join("build/tools");
         install_tools(&toolsdir).await?;
+
+        let mut runs = ArchRuns::new(self.parallel);
+        for arch in expand_arches(&self.arch)? {
+            let run = self
+                .build(&project, &lock, &toolsdir, arch.clone())
+                .boxed_local();
+            runs.push(format!("kit '{}'", self.kit), arch, run);
+        }
+        runs.run().await
+    }
+
+    /// Builds the kit for one architecture.
+    async fn build(
+        &self,
+        project: &Project,
+        lock: &Lock,
+        toolsdir: &Path,
+        arch: String,
+    ) -> Result<()> {
         let makefile_path = toolsdir.join("Makefile.toml");
 
         let mut optional_envs = Vec::new();
 
         if let Some(lookaside_cache) = &self.lookaside_cache {
-            optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache))
+            optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache.clone()))
         }
 
-        CargoMake::new(&lock.sdk.source)?
+        if let Some(profile) = &self.profile {
+            optional_envs.extend(project.profile(profile)?.env(profile));
+        }
+
+        let hook_context = [
+            ("TWOLITER_ARCH", arch.clone()),
+            ("TWOLITER_KIT", self.kit.clone()),
+            (
+                "TWOLITER_OUTPUT_DIR",
+                project
+                    .project_dir()
+                    .join("build/kits")
+                    .join(&self.kit)
+                    .display()
+                    .to_string(),
+            ),
+        ];
+        project.run_hook(Hook::PreKitBuild, &hook_context).await?;
+
+        let events_path = project
+            .project_dir()
+            .join("build/events")
+            .join(format!("{arch}-kit-{}.jsonl", self.kit));
+        let mut events = EventLog::create(&events_path).await?;
+        optional_envs.push(("BUILDSYS_EVENTS_PATH", events_path.display().to_string()));
+
+        let cargo_make = CargoMake::new(&local_sdk::sdk_for(project, lock, &arch).await?)?
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
-            .env("BUILDSYS_ARCH", &self.arch)
+            .env("BUILDSYS_ARCH", &arch)
             .env("BUILDSYS_KIT", &self.kit)
+            .env("BUILDSYS_KIT_FEATURES", lock.kit_features())
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
             .env("GO_MODULES", project.find_go_modules().await?.join(" "))
             .env(
                 "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
                 self.upstream_source_fallback.to_string(),
             )
+            .env("BUILDSYS_CHECKPOINTS", (!self.no_checkpoints).to_string())
+            .env("BUILDSYS_PROVENANCE", (!self.no_provenance).to_string())
             .envs(optional_envs.into_iter())
             .makefile(makefile_path)
             .project_dir(project.project_dir())
-            .exec("build-kit")
-            .await
+            .envs(project.cargo_make_env(self.export_build_cache)?.into_iter());
+        let result = events.follow(cargo_make.exec("build-kit")).await;
+        info!("Kit '{}' ({arch}): {}", self.kit, events.summary());
+        result?;
+        project.run_hook(Hook::PostKitBuild, &hook_context).await
     }
 }
 
-/// Build a Bottlerocket variant image.
+/// Build the Bottlerocket SDK from a local checkout of its repository, and build the project with
+/// it in place of the SDK in Twoliter.lock until `--clear` is given.
+#[derive(Debug, Parser)]
+pub(crate) struct BuildSdk {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The architecture to build for. May be given multiple times, or as `all` for every
+    /// supported architecture.
+    #[clap(long = "arch", default_value = "x86_64")]
+    pub(crate) arch: Vec<String>,
+
+    /// Build for all of the architectures at once, rather than one after another.
+    #[clap(long = "parallel")]
+    pub(crate) parallel: bool,
+
+    /// The checkout of the SDK's repository, with its Dockerfile at the top.
+    #[clap(long = "sdk-dir", required_unless_present = "clear")]
+    pub(crate) sdk_dir: Option<PathBuf>,
+
+    /// The stage of the SDK's Dockerfile to build, if not its last.
+    #[clap(long = "target")]
+    pub(crate) target: Option<String>,
+
+    /// Stop building with the local SDK, and go back to the SDK in Twoliter.lock.
+    #[clap(long = "clear", conflicts_with = "sdk_dir")]
+    pub(crate) clear: bool,
+}
+
+impl BuildSdk {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        if self.clear {
+            local_sdk::clear(&project).await?;
+            info!("Builds will use the SDK in Twoliter.lock");
+            return Ok(());
+        }
+        let sdk_dir = self
+            .sdk_dir
+            .as_deref()
+            .context("the SDK checkout must be given with --sdk-dir")?;
+        let sdk_dir = fs::canonicalize(sdk_dir).await?;
+        let toolsdir = project.project_dir().join("build/tools");
+        install_tools(&toolsdir).await?;
+
+        let mut runs = ArchRuns::new(self.parallel);
+        for arch in expand_arches(&self.arch)? {
+            let run = self
+                .build(&project, &toolsdir, &sdk_dir, arch.clone())
+                .boxed_local();
+            runs.push("the SDK", arch, run);
+        }
+        runs.run().await
+    }
+
+    /// Builds the SDK for one architecture, and records it as the SDK to build with.
+    async fn build(
+        &self,
+        project: &Project,
+        toolsdir: &Path,
+        sdk_dir: &Path,
+        arch: String,
+    ) -> Result<()> {
+        let tag = local_sdk::tag(project, &arch);
+        let id_path = local_sdk::id_path(project, &arch).await?;
+        let layout_path = local_sdk::layout_path(project, &arch).await?;
+        let mut command = Command::new(toolsdir.join("buildsys"));
+        command
+            .arg("build-sdk")
+            .env("BUILDSYS_SDK_DIR", sdk_dir)
+            .env("BUILDSYS_ARCH", &arch)
+            .env("BUILDSYS_SDK_TAG", &tag)
+            .env("BUILDSYS_SDK_ID_FILE", &id_path)
+            .env("BUILDSYS_SDK_LAYOUT", &layout_path)
+            .envs(project.tools().env()?)
+            .envs(project.proxy_env());
+        if let Some(target) = &self.target {
+            command.env("BUILDSYS_SDK_TARGET", target);
+        }
+        exec_log(&mut command).await?;
+        local_sdk::record(project, &arch, &tag).await?;
+        info!(
+            "Builds for {arch} will use the local SDK '{tag}', until `twoliter build sdk --clear`"
+        );
+        Ok(())
+    }
+}
+
+/// Build a package and the packages it depends on.
 #[derive(Debug, Parser)]
+pub(crate) struct BuildPackage {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The architecture to build for. May be given multiple times, or as `all` for every
+    /// supported architecture.
+    #[clap(long = "arch", default_value = "x86_64")]
+    pub(crate) arch: Vec<String>,
+
+    /// Build for all of the architectures at once, rather than one after another.
+    #[clap(long = "parallel")]
+    pub(crate) parallel: bool,
+
+    /// The name of the package to build, as given in its `Cargo.toml`.
+    pub(crate) package: String,
+
+    /// The URL to the lookaside cache where sources are stored to avoid pulling them from upstream.
+    /// Defaults to https://cache.bottlerocket.aws
+    #[clap(long = "lookaside-cache")]
+    pub(crate) lookaside_cache: Option<String>,
+
+    /// If sources are not found in the lookaside cache, this flag will cause buildsys to pull them
+    /// from the upstream URL found in a package's `Cargo.toml`.
+    #[clap(long = "upstream-source-fallback")]
+    pub(crate) upstream_source_fallback: bool,
+
+    /// The build profile to use, as declared by `[profile.<name>]` in Twoliter.toml.
+    #[clap(long = "profile")]
+    pub(crate) profile: Option<String>,
+
+    /// Rebuild packages even if their inputs are unchanged since they last built successfully.
+    #[clap(long = "no-checkpoints")]
+    pub(crate) no_checkpoints: bool,
+
+    /// Don't record the SLSA provenance of the builds' artifacts in `build/provenance`.
+    #[clap(long = "no-provenance")]
+    pub(crate) no_provenance: bool,
+
+    /// Export the cache of package builds to the `[build-cache]` registry, even if Twoliter.toml
+    /// doesn't ask for it, e.g. only in CI.
+    #[clap(long = "export-build-cache", env = "TWOLITER_EXPORT_BUILD_CACHE")]
+    pub(crate) export_build_cache: bool,
+}
+
+impl BuildPackage {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let lock = Lock::load(&project).await?;
+        let toolsdir = project.project_dir().join("build/tools");
+        install_tools(&toolsdir).await?;
+
+        let mut runs = ArchRuns::new(self.parallel);
+        for arch in expand_arches(&self.arch)? {
+            let run = self
+                .build(&project, &lock, &toolsdir, arch.clone())
+                .boxed_local();
+            runs.push(format!("package '{}'", self.package), arch, run);
+        }
+        runs.run().await
+    }
+
+    /// Builds the package for one architecture.
+    pub(super) async fn build(
+        &self,
+        project: &Project,
+        lock: &Lock,
+        toolsdir: &Path,
+        arch: String,
+    ) -> Result<()> {
+        let mut optional_envs = Vec::new();
+
+        if let Some(lookaside_cache) = &self.lookaside_cache {
+            optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache.clone()))
+        }
+
+        if let Some(profile) = &self.profile {
+            optional_envs.extend(project.profile(profile)?.env(profile));
+        }
+
+        let events_path = project
+            .project_dir()
+            .join("build/events")
+            .join(format!("{arch}-package-{}.jsonl", self.package));
+        let mut events = EventLog::create(&events_path).await?;
+        optional_envs.push(("BUILDSYS_EVENTS_PATH", events_path.display().to_string()));
+
+        let cargo_make = CargoMake::new(&local_sdk::sdk_for(project, lock, &arch).await?)?
+            .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
+            .env("BUILDSYS_ARCH", &arch)
+            .env("PACKAGE", &self.package)
+            .env("BUILDSYS_KIT_FEATURES", lock.kit_features())
+            .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+            .env("GO_MODULES", project.find_go_modules().await?.join(" "))
+            .env(
+                "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
+                self.upstream_source_fallback.to_string(),
+            )
+            .env("BUILDSYS_CHECKPOINTS", (!self.no_checkpoints).to_string())
+            .env("BUILDSYS_PROVENANCE", (!self.no_provenance).to_string())
+            .envs(optional_envs.into_iter())
+            .makefile(toolsdir.join("Makefile.toml"))
+            .project_dir(project.project_dir())
+            .envs(project.cargo_make_env(self.export_build_cache)?.into_iter());
+        let result = events.follow(cargo_make.exec("build-package")).await;
+        info!("Package '{}' ({arch}): {}", self.package, events.summary());
+        result
+    }
+}
+
+/// Build a Bottlerocket variant image.
+#[derive(Debug, Clone, Parser)]
 pub(crate) struct BuildVariant {
     /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
     #[clap(long = "project-path")]
     project_path: Option<PathBuf>,
 
-    /// The architecture to build for.
-    #[clap(long = "arch", default_value = "x86_64")]
-    arch: String,
+    /// The architecture to build for. May be given multiple times, or as `all` for every
+    /// supported architecture. Defaults to the architectures declared for the variant in
+    /// Twoliter.toml, or x86_64 when the variant is not declared there.
+    #[clap(long = "arch")]
+    arch: Vec<String>,
+
+    /// Build for all of the architectures at once, rather than one after another.
+    #[clap(long = "parallel")]
+    parallel: bool,
+
+    /// The variant to build. When absent, the variant, architecture and profile of the last build
+    /// in the project are used, unless given on the command line.
+    variant: Option<String>,
 
-    /// The variant to build.
-    variant: String,
+    /// Build every variant declared in Twoliter.toml.
+    #[clap(long = "all", conflicts_with = "variant")]
+    all: bool,
 
     /// The URL to the lookaside cache where sources are stored to avoid pulling them from upstream.
     /// Defaults to https://cache.bottlerocket.aws
@@ -108,14 +420,173 @@ pub(crate) struct BuildVariant {
     /// Path to the Infra.toml file
     #[clap(long)]
     infra_toml: Option<PathBuf>,
+
+    /// The build profile to use, as declared by `[profile.<name>]` in Twoliter.toml.
+    #[clap(long = "profile")]
+    profile: Option<String>,
+
+    /// Rebuild packages and kits even if their inputs are unchanged since they last built
+    /// successfully.
+    #[clap(long = "no-checkpoints")]
+    no_checkpoints: bool,
+
+    /// Don't record the SLSA provenance of the builds' artifacts in `build/provenance`.
+    #[clap(long = "no-provenance")]
+    no_provenance: bool,
+
+    /// Export the cache of package builds to the `[build-cache]` registry, even if Twoliter.toml
+    /// doesn't ask for it, e.g. only in CI.
+    #[clap(long = "export-build-cache", env = "TWOLITER_EXPORT_BUILD_CACHE")]
+    export_build_cache: bool,
+
+    /// Report how long each package, kit and variant took to build, as JSON and HTML in
+    /// `build/timings`.
+    #[clap(long = "timings")]
+    timings: bool,
+
+    /// Keep building the packages which do not depend on a package that failed to build, and list
+    /// every failed build at the end rather than stopping at the first.
+    #[clap(long = "keep-going")]
+    keep_going: bool,
+
+    /// Start the build without checking that there is enough free disk space for it.
+    #[clap(long = "skip-disk-check")]
+    skip_disk_check: bool,
+
+    /// The least free disk space, in GiB, the build may start with in the build directory and in
+    /// the container runtime's data root, however little earlier builds used.
+    #[clap(long = "min-free-space", value_name = "GIB", default_value_t = DEFAULT_MIN_FREE_GIB)]
+    min_free_space: u64,
+
+    /// How many package builds may run at once, rather than as many as Cargo decides. The
+    /// packages on the longest chains of dependent builds, weighed by how long they last took to
+    /// build, start first.
+    #[clap(long = "jobs", short = 'j')]
+    jobs: Option<NonZeroU16>,
+
+    /// Neither use nor remember the settings of the last build in `.twoliter/state.toml`, e.g. in
+    /// CI.
+    #[clap(long = "no-state")]
+    no_state: bool,
+
+    /// Also write a software bill of materials for the variant next to its images, in SPDX
+    /// unless another format is given.
+    #[clap(long = "sbom", value_enum, num_args = 0..=1, default_missing_value = "spdx")]
+    sbom: Option<SbomFormat>,
+
+    /// Also convert the variant's disk images to this format, next to those in the format the
+    /// variant declares. May be given multiple times.
+    #[clap(long = "output-format", value_enum)]
+    output_format: Vec<OutputFormat>,
 }
 
 impl BuildVariant {
-    pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
-        let lock = Lock::load(&project).await?;
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let command = self.with_state(&project.project_dir()).await?;
+        command.build_all(&project).await
+    }
+
+    /// Fills in the settings of the last build when no variant is given, and remembers the
+    /// settings of this build for next time.
+    async fn with_state(&self, project_dir: &Path) -> Result<Self> {
+        let mut command = self.clone();
+        if self.no_state || self.all {
+            return Ok(command);
+        }
+        if command.variant.is_none() {
+            let state = BuildState::load(project_dir).await;
+            command.variant = state.variant;
+            if command.arch.is_empty() {
+                command.arch = state.arch;
+            }
+            command.profile = command.profile.take().or(state.profile);
+            if let Some(variant) = &command.variant {
+                info!("Repeating the last build of variant '{variant}'");
+            }
+        }
+        if command.variant.is_some() {
+            BuildState {
+                arch: command.arch.clone(),
+                variant: command.variant.clone(),
+                profile: command.profile.clone(),
+            }
+            .save(project_dir)
+            .await?;
+        }
+        Ok(command)
+    }
+
+    async fn build_all(&self, project: &Project) -> Result<()> {
+        let lock = Lock::load(project).await?;
         let toolsdir = project.project_dir().join("build/tools");
         install_tools(&toolsdir).await?;
+
+        let variants: Vec<(&str, Option<&VariantConfig>)> = if self.all {
+            ensure!(
+                !project.variants().is_empty(),
+                "no variants are declared in Twoliter.toml"
+            );
+            project
+                .variants()
+                .iter()
+                .map(|variant| (variant.name.as_str(), Some(variant)))
+                .collect()
+        } else {
+            let name = self
+                .variant
+                .as_deref()
+                .context("a variant name or --all is required")?;
+            vec![(name, project.variant(name))]
+        };
+
+        let requested_arches = expand_arches(&self.arch)?;
+        let (lock, toolsdir) = (&lock, &toolsdir);
+        let mut runs = ArchRuns::new(self.parallel);
+        for (name, definition) in variants {
+            if let Some(definition) = definition {
+                scaffold::sync_variant(&project.project_dir(), definition).await?;
+            }
+            let arches = if !requested_arches.is_empty() {
+                requested_arches.clone()
+            } else if let Some(definition) = definition {
+                definition.arch.clone()
+            } else {
+                vec![DEFAULT_ARCH.to_string()]
+            };
+            for arch in arches {
+                let run = {
+                    let arch = arch.clone();
+                    async move {
+                        info!("Building variant '{}' for {}", name, arch);
+                        self.build(project, lock, toolsdir, name, &arch, definition)
+                            .await
+                    }
+                };
+                runs.push(format!("variant '{name}'"), arch, run.boxed_local());
+            }
+        }
+        runs.run().await
+    }
+
+    /// Builds one variant for one architecture. Settings declared for the variant in Twoliter.toml
+    /// apply unless overridden on the command line.
+    async fn build(
+        &self,
+        project: &Project,
+        lock: &Lock,
+        toolsdir: &Path,
+        variant: &str,
+        arch: &str,
+        definition: Option<&VariantConfig>,
+    ) -> Result<()> {
+        if !self.skip_disk_check {
+            Estimate::new(&project.project_dir(), arch, variant, self.min_free_space)
+                .await?
+                .check(&project.project_dir(), project.tools())
+                .await?;
+        }
+
         let makefile_path = toolsdir.join("Makefile.toml");
         // A temporary directory in the `build` directory
         let build_temp_dir = TempDir::new_in(project.project_dir())
@@ -125,31 +596,239 @@ impl BuildVariant {
 
         let mut optional_envs = Vec::new();
 
-        if let Some(lookaside_cache) = &self.lookaside_cache {
+        let lookaside_cache = self
+            .lookaside_cache
+            .as_ref()
+            .or(definition.and_then(|definition| definition.lookaside_cache.as_ref()));
+        if let Some(lookaside_cache) = lookaside_cache {
             optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache.to_string()))
         }
 
-        if let Some(infra_toml) = &self.infra_toml {
+        let infra_toml = self
+            .infra_toml
+            .clone()
+            .or(definition.and_then(|definition| {
+                definition
+                    .infra_toml
+                    .as_ref()
+                    .map(|infra_toml| project.project_dir().join(infra_toml))
+            }));
+        if let Some(infra_toml) = &infra_toml {
             optional_envs.push((
                 "PUBLISH_INFRA_CONFIG_PATH",
                 infra_toml.display().to_string(),
             ))
         }
 
-        CargoMake::new(&lock.sdk.source)?
+        if let Some(profile) = &self.profile {
+            optional_envs.extend(project.profile(profile)?.env(profile));
+        }
+
+        let timings_dir = project.project_dir().join("build/timings");
+        let raw_timings_dir = timings_dir.join("raw").join(format!("{arch}-{variant}"));
+        if self.timings {
+            if raw_timings_dir.exists() {
+                fs::remove_dir_all(&raw_timings_dir).await?;
+            }
+            optional_envs.push((
+                "BUILDSYS_TIMINGS_DIR",
+                raw_timings_dir.display().to_string(),
+            ));
+        }
+
+        let failures_dir = project
+            .project_dir()
+            .join("build/failures")
+            .join(format!("{arch}-{variant}"));
+        if self.keep_going {
+            if failures_dir.exists() {
+                fs::remove_dir_all(&failures_dir).await?;
+            }
+            optional_envs.push(("BUILDSYS_KEEP_GOING", "true".to_string()));
+            optional_envs.push(("BUILDSYS_FAILURES_DIR", failures_dir.display().to_string()));
+        }
+
+        if !self.output_format.is_empty() {
+            let formats: Vec<_> = self.output_format.iter().map(|f| f.to_string()).collect();
+            optional_envs.push(("BUILDSYS_IMAGE_FORMATS", formats.join(",")));
+        }
+
+        if let Some(jobs) = self.jobs {
+            // Cargo starts more builds than may run, so that buildsys can choose which of the
+            // waiting packages to build first.
+            optional_envs.push(("BUILDSYS_PACKAGE_JOBS", jobs.to_string()));
+            optional_envs.push(("BUILDSYS_JOBS", (u32::from(jobs.get()) * 2).to_string()));
+        }
+
+        let events_path = project
+            .project_dir()
+            .join("build/events")
+            .join(format!("{arch}-{variant}.jsonl"));
+        let mut events = EventLog::create(&events_path).await?;
+        optional_envs.push(("BUILDSYS_EVENTS_PATH", events_path.display().to_string()));
+
+        let diagnostics = Diagnostics::create(
+            &project
+                .project_dir()
+                .join("build/diagnostics")
+                .join(format!("{arch}-{variant}.jsonl")),
+        )
+        .await?;
+        optional_envs.extend(diagnostics.env());
+
+        let upstream_source_fallback = self.upstream_source_fallback
+            || definition
+                .and_then(|definition| definition.upstream_source_fallback)
+                .unwrap_or(false);
+
+        let output_dir = project
+            .project_dir()
+            .join("build/images")
+            .join(format!("{arch}-{variant}"))
+            .join("latest");
+        let hook_context = [
+            ("TWOLITER_ARCH", arch.to_string()),
+            ("TWOLITER_VARIANT", variant.to_string()),
+            ("TWOLITER_OUTPUT_DIR", output_dir.display().to_string()),
+        ];
+        project
+            .run_hook(Hook::PreVariantBuild, &hook_context)
+            .await?;
+
+        let start = SystemTime::now();
+        let cargo_make = CargoMake::new(&local_sdk::sdk_for(project, lock, arch).await?)?
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
-            .env("BUILDSYS_ARCH", &self.arch)
-            .env("BUILDSYS_VARIANT", &self.variant)
+            .env("BUILDSYS_ARCH", arch)
+            .env("BUILDSYS_VARIANT", variant)
+            .envs(scaffold::variant_crate_env(project, variant).into_iter())
+            .env("BUILDSYS_KIT_FEATURES", lock.kit_features())
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
             .env("GO_MODULES", project.find_go_modules().await?.join(" "))
             .env(
                 "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
-                self.upstream_source_fallback.to_string(),
+                upstream_source_fallback.to_string(),
             )
+            .env("BUILDSYS_CHECKPOINTS", (!self.no_checkpoints).to_string())
+            .env("BUILDSYS_PROVENANCE", (!self.no_provenance).to_string())
             .envs(optional_envs.into_iter())
             .makefile(makefile_path)
             .project_dir(project.project_dir())
-            .exec("build")
+            .envs(project.cargo_make_env(self.export_build_cache)?.into_iter());
+        let result = events.follow(cargo_make.exec("build")).await;
+        info!("Variant '{variant}' ({arch}): {}", events.summary());
+        info!("Wrote the build's events to '{}'", events.path().display());
+        diagnostics.report()?;
+        if diagnostics.path().exists() {
+            info!(
+                "Wrote the build's errors and warnings to '{}'",
+                diagnostics.path().display()
+            );
+        }
+        if self.timings {
+            let report =
+                TimingReport::load(variant, arch, &raw_timings_dir, start, SystemTime::now())
+                    .await?;
+            let report_path = report.write(&timings_dir).await?;
+            info!("{}", report.summary());
+            info!("Wrote the timing report to '{}'", report_path.display());
+        }
+        let stats_dir = project
+            .project_dir()
+            .join("build/state")
+            .join(arch)
+            .join("compiler-cache");
+        for cache_stats in CacheStats::load_since(&stats_dir, start).await? {
+            info!(
+                "Compiler cache statistics for package '{}':\n{}",
+                cache_stats.package,
+                cache_stats.stats.trim_end()
+            );
+        }
+        if self.keep_going && result.is_err() {
+            let failures = BuildFailure::load_all(&failures_dir).await?;
+            if !failures.is_empty() {
+                for failure in &failures {
+                    error!(
+                        "{}\n  See '{}' for its log",
+                        failure.description,
+                        failure.log.display()
+                    );
+                }
+                bail!(
+                    "{} package or kit builds failed for variant '{variant}' ({arch})",
+                    failures.len()
+                );
+            }
+        }
+        result?;
+        if let Some(format) = self.sbom {
+            let sbom_path =
+                sbom::write_for_variant(project, variant, arch, format, &output_dir).await?;
+            info!("Wrote the SBOM to '{}'", sbom_path.display());
+        }
+        let build_output =
+            BuildOutput::scan(variant, arch, project.release_version(), &output_dir).await?;
+        build_output.write().await?;
+        let manifest = artifact_signing::write_manifest(&build_output).await?;
+        if let Some(signing) = project.artifact_signing() {
+            artifact_signing::sign(&manifest, &signing, project.tools().cosign()).await?;
+        }
+        for report in build_output.image_reports().await? {
+            info!(
+                "Image '{}' has dm-verity root hash {}",
+                report.image, report.verity.root_hash
+            );
+        }
+        project
+            .run_hook(Hook::PostVariantBuild, &hook_context)
             .await
     }
 }
+
+#[tokio::test]
+async fn test_build_variant_state() {
+    let temp_dir = TempDir::new().unwrap();
+    let project_dir = temp_dir.path();
+    let with_state = |args: &'static [&'static str]| async move {
+        BuildVariant::try_parse_from(std::iter::once("variant").chain(args.iter().copied()))
+            .unwrap()
+            .with_state(project_dir)
+            .await
+            .unwrap()
+    };
+
+    let first = with_state(&["--arch", "aarch64", "--profile", "release", "my-variant"]).await;
+    assert_eq!(first.variant.as_deref(), Some("my-variant"));
+
+    let repeated = with_state(&[]).await;
+    assert_eq!(repeated.variant.as_deref(), Some("my-variant"));
+    assert_eq!(repeated.arch, vec!["aarch64"]);
+    assert_eq!(repeated.profile.as_deref(), Some("release"));
+
+    let overridden = with_state(&["--arch", "x86_64"]).await;
+    assert_eq!(overridden.variant.as_deref(), Some("my-variant"));
+    assert_eq!(overridden.arch, vec!["x86_64"]);
+
+    let unset = with_state(&["--no-state"]).await;
+    assert_eq!(unset.variant, None);
+    assert_eq!(with_state(&[]).await.arch, vec!["x86_64"]);
+}
+
+#[test]
+fn test_build_package_args() {
+    let command = BuildPackage::try_parse_from([
+        "package",
+        "--arch",
+        "all",
+        "--profile",
+        "release",
+        "kernel-6.1",
+    ])
+    .unwrap();
+    assert_eq!(command.package, "kernel-6.1");
+    assert_eq!(command.arch, vec!["all"]);
+    assert_eq!(command.profile.as_deref(), Some("release"));
+    assert!(!command.no_checkpoints);
+
+    assert!(BuildPackage::try_parse_from(["package"]).is_err());
+}
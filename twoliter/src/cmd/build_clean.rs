@@ -1,4 +1,5 @@
 use crate::cargo_make::CargoMake;
+use crate::local_sdk;
 use crate::lock::Lock;
 use crate::project;
 use crate::tools;
@@ -14,17 +15,18 @@ pub(crate) struct BuildClean {
 }
 
 impl BuildClean {
-    pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
         let lock = Lock::load(&project).await?;
         let toolsdir = project.project_dir().join("build/tools");
         tools::install_tools(&toolsdir).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
 
-        CargoMake::new(&lock.sdk.source)?
+        CargoMake::new(&local_sdk::host_sdk(&project, &lock).await?)?
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
             .makefile(makefile_path)
             .project_dir(project.project_dir())
+            .envs(project.cargo_make_env(false)?.into_iter())
             .exec("clean")
             .await?;
 
@@ -0,0 +1,100 @@
+use crate::cargo_make::CargoMake;
+use crate::lint::read_manifests;
+use crate::local_sdk;
+use crate::lock::Lock;
+use crate::project;
+use crate::tools::install_tools;
+use anyhow::{ensure, Result};
+use buildsys::BuildType;
+use clap::Parser;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Manage the lookaside cache of packages' external files
+#[derive(Debug, Parser)]
+pub(crate) enum CacheCommand {
+    Populate(PopulateCache),
+}
+
+impl CacheCommand {
+    pub(crate) async fn run(self, strict: bool) -> Result<()> {
+        match self {
+            CacheCommand::Populate(command) => command.run(strict).await,
+        }
+    }
+}
+
+/// Download the external files of the project's packages from upstream, verify them, and upload
+/// them to a lookaside cache. Files which are already cached are skipped
+#[derive(Debug, Parser)]
+pub(crate) struct PopulateCache {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The lookaside cache to upload to, as a `file://` directory or an `s3://<bucket>/<prefix>`
+    /// URL. S3 requests use the AWS credentials and region from the environment
+    #[clap(long = "lookaside-cache")]
+    lookaside_cache: String,
+
+    /// Only upload the external files of the named package. May be given multiple times
+    #[clap(long = "package")]
+    package: Vec<String>,
+
+    /// The number of files to transfer at once
+    #[clap(long = "jobs")]
+    jobs: Option<usize>,
+}
+
+impl PopulateCache {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let project_dir = project.project_dir();
+
+        let mut manifests = Vec::new();
+        let mut found = HashSet::new();
+        for (manifest_path, info) in read_manifests(&project_dir).await? {
+            if !matches!(info.build_type(), Ok(BuildType::Package)) {
+                continue;
+            }
+            if !self.package.is_empty() && !self.package.iter().any(|p| p == info.package_name()) {
+                continue;
+            }
+            if info.external_files().is_some_and(|files| !files.is_empty()) {
+                found.insert(info.package_name().to_string());
+                let relative = manifest_path.strip_prefix(&project_dir)?;
+                manifests.push(relative.display().to_string());
+            }
+        }
+        for package in &self.package {
+            ensure!(
+                found.contains(package),
+                "Package '{package}' has no external files"
+            );
+        }
+        if manifests.is_empty() {
+            println!("No package has external files");
+            return Ok(());
+        }
+
+        let lock = Lock::load(&project).await?;
+        let toolsdir = project_dir.join("build/tools");
+        install_tools(&toolsdir).await?;
+
+        let mut optional_envs = Vec::new();
+        if let Some(jobs) = self.jobs {
+            optional_envs.push(("BUILDSYS_FETCH_JOBS", jobs.to_string()));
+        }
+
+        CargoMake::new(&local_sdk::host_sdk(&project, &lock).await?)?
+            .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
+            .env("BUILDSYS_LOOKASIDE_CACHE", &self.lookaside_cache)
+            .env("BUILDSYS_POPULATE_MANIFESTS", manifests.join(" "))
+            .makefile(toolsdir.join("Makefile.toml"))
+            .project_dir(project_dir)
+            .envs(project.cargo_make_env(false)?.into_iter())
+            .envs(optional_envs.into_iter())
+            .exec("populate-cache")
+            .await
+    }
+}
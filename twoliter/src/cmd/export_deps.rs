@@ -0,0 +1,28 @@
+use crate::deps;
+use crate::lock::Lock;
+use crate::progress::Progress;
+use crate::project;
+use anyhow::Result;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Bundle the sdk and all kits in Twoliter.lock, for all architectures, into a single tarball
+/// which can be imported on a machine without network access.
+#[derive(Debug, Parser)]
+pub(crate) struct ExportDeps {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// Path of the bundle to write
+    #[clap(long = "output", default_value = "twoliter-deps.tar.gz")]
+    pub(crate) output: PathBuf,
+}
+
+impl ExportDeps {
+    pub(super) async fn run(&self, strict: bool, progress: &Progress) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let lock = Lock::load(&project).await?;
+        deps::export(&project, &lock, &self.output, progress).await
+    }
+}
@@ -1,7 +1,13 @@
+use crate::arch_runs::{expand_arches, ArchRuns};
+use crate::cargo_make::CargoMake;
+use crate::local_sdk;
 use crate::lock::Lock;
-use crate::project;
+use crate::progress::Progress;
+use crate::project::{self, Hook, Project};
+use crate::tools::install_tools;
 use anyhow::Result;
 use clap::Parser;
+use futures::FutureExt;
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -10,16 +16,81 @@ pub(crate) struct Fetch {
     #[clap(long = "project-path")]
     pub(crate) project_path: Option<PathBuf>,
 
-    /// Architecture of images to fetch
+    /// Architecture of images to fetch. May be given multiple times, or as `all` for every
+    /// supported architecture
     #[clap(long = "arch", default_value = "x86_64")]
-    pub(crate) arch: String,
+    pub(crate) arch: Vec<String>,
+
+    /// Fetch for all of the architectures at once, rather than one after another
+    #[clap(long = "parallel")]
+    pub(crate) parallel: bool,
+
+    /// Only pull the SDK image, skipping all kits
+    #[clap(long = "sdk-only", conflicts_with = "kit")]
+    pub(crate) sdk_only: bool,
+
+    /// Only fetch the named kit, given as `<name>` or `<name>@<vendor>`. May be given multiple
+    /// times. Fetches all kits when absent
+    #[clap(long = "kit")]
+    pub(crate) kit: Vec<String>,
+
+    /// Do not contact any registry. Images must already be cached, e.g. by `twoliter import-deps`
+    #[clap(long = "offline")]
+    pub(crate) offline: bool,
 }
 
 impl Fetch {
-    pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
-        let lock_file = Lock::load(&project).await?;
-        lock_file.fetch(&project, self.arch.as_str()).await?;
-        Ok(())
+    pub(super) async fn run(&self, strict: bool, progress: &Progress) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let lock_file = if self.offline {
+            Lock::load_offline(&project).await?
+        } else {
+            Lock::load(&project).await?
+        };
+        let mut runs = ArchRuns::new(self.parallel);
+        for arch in expand_arches(&self.arch)? {
+            let run = self.fetch(&project, &lock_file, progress, arch.clone());
+            runs.push("fetch", arch, run.boxed_local());
+        }
+        runs.run().await
+    }
+
+    /// Fetches the images for one architecture.
+    async fn fetch(
+        &self,
+        project: &Project,
+        lock_file: &Lock,
+        progress: &Progress,
+        arch: String,
+    ) -> Result<()> {
+        let hook_context = [
+            ("TWOLITER_ARCH", arch.clone()),
+            (
+                "TWOLITER_OUTPUT_DIR",
+                project
+                    .project_dir()
+                    .join("build/external-kits")
+                    .display()
+                    .to_string(),
+            ),
+        ];
+        project.run_hook(Hook::PreFetch, &hook_context).await?;
+        if self.sdk_only {
+            let toolsdir = project.project_dir().join("build/tools");
+            install_tools(&toolsdir).await?;
+            CargoMake::new(&local_sdk::sdk_for(project, lock_file, &arch).await?)?
+                .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
+                .env("BUILDSYS_ARCH", &arch)
+                .makefile(toolsdir.join("Makefile.toml"))
+                .project_dir(project.project_dir())
+                .envs(project.cargo_make_env(false)?.into_iter())
+                .exec("fetch-sdk")
+                .await?;
+        } else {
+            lock_file
+                .fetch(project, arch.as_str(), &self.kit, progress)
+                .await?;
+        }
+        project.run_hook(Hook::PostFetch, &hook_context).await
     }
 }
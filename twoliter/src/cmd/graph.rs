@@ -0,0 +1,64 @@
+use crate::lint::find_manifests;
+use crate::project;
+use anyhow::{Context, Result};
+use buildsys::graph::PackageGraph;
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Format {
+    Dot,
+    Json,
+}
+
+/// Print the graph of dependencies between the project's packages, as declared by their manifests
+/// and spec files, to see which packages a change to one of them affects.
+#[derive(Debug, Parser)]
+pub(crate) struct Graph {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// Graph the dependencies between packages
+    #[clap(long = "packages", required = true)]
+    pub(crate) packages: bool,
+
+    /// Output format
+    #[clap(long = "format", value_enum, default_value = "dot")]
+    pub(crate) format: Format,
+}
+
+impl Graph {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let mut manifests = find_manifests(&project.project_dir()).await?;
+        manifests.sort();
+        let graph = PackageGraph::new(&manifests).context("Unable to graph the packages")?;
+        match self.format {
+            Format::Dot => print!("{}", graph.to_dot()),
+            Format::Json => println!(
+                "{}",
+                graph.to_json().context("Unable to serialize the graph")?
+            ),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn graphs_project_packages() {
+        let temp_dir = crate::test::copy_project_to_temp_dir("project1");
+        let project =
+            project::load_or_find_project(Some(temp_dir.path().join("Twoliter.toml")), false)
+                .await
+                .unwrap();
+        let manifests = find_manifests(&project.project_dir()).await.unwrap();
+        let graph = PackageGraph::new(&manifests).unwrap();
+        assert!(graph.packages.contains(&"hello-go".to_string()));
+        assert!(graph.to_dot().starts_with("digraph packages {\n"));
+    }
+}
@@ -0,0 +1,34 @@
+use crate::deps;
+use crate::progress::Progress;
+use crate::project;
+use anyhow::Result;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Import a bundle created by `twoliter export-deps` so that `twoliter fetch --offline` can run
+/// without network access.
+#[derive(Debug, Parser)]
+pub(crate) struct ImportDeps {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// Path of the bundle to import
+    pub(crate) bundle: PathBuf,
+
+    /// Architecture of the sdk image to load into docker
+    #[clap(long = "arch", default_value = "x86_64")]
+    pub(crate) arch: String,
+
+    /// Do not load the sdk image into docker
+    #[clap(long = "skip-sdk-load")]
+    pub(crate) skip_sdk_load: bool,
+}
+
+impl ImportDeps {
+    pub(super) async fn run(&self, strict: bool, progress: &Progress) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let sdk_arch = (!self.skip_sdk_load).then_some(self.arch.as_str());
+        deps::import(&project, &self.bundle, sdk_arch, progress).await
+    }
+}
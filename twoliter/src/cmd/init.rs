@@ -0,0 +1,34 @@
+use crate::scaffold;
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Create a new project with a Twoliter.toml, a Cargo workspace, and a sample kit containing a
+/// sample package.
+#[derive(Debug, Parser)]
+pub(crate) struct Init {
+    /// The directory to create the project in. Defaults to the current directory.
+    pub(crate) path: Option<PathBuf>,
+
+    /// The name of the sample kit
+    #[clap(long = "kit", default_value = "my-kit")]
+    pub(crate) kit: String,
+
+    /// The name of the sample package
+    #[clap(long = "package", default_value = "hello")]
+    pub(crate) package: String,
+
+    /// The version of the Bottlerocket SDK the project builds with
+    #[clap(long = "sdk-version", default_value = scaffold::DEFAULT_SDK_VERSION)]
+    pub(crate) sdk_version: String,
+}
+
+impl Init {
+    pub(super) async fn run(&self) -> Result<()> {
+        let dir = match &self.path {
+            Some(path) => path.clone(),
+            None => std::env::current_dir().context("unable to get the current directory")?,
+        };
+        scaffold::init(&dir, &self.kit, &self.package, &self.sdk_version).await
+    }
+}
@@ -0,0 +1,326 @@
+use super::table;
+use crate::common::fs;
+use crate::lock::{ImageInspection, Lock};
+use crate::project;
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use oci_cli_wrapper::{ImageTool, Referrer, KIT_METADATA_LABEL};
+use serde::Serialize;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Show what a registry knows about the images in Twoliter.lock
+#[derive(Debug, Parser)]
+pub(crate) enum InspectCommand {
+    Kit(InspectKit),
+    Image(InspectImage),
+}
+
+impl InspectCommand {
+    pub(crate) async fn run(self, strict: bool) -> Result<()> {
+        match self {
+            InspectCommand::Kit(command) => command.run(strict).await,
+            InspectCommand::Image(command) => command.run(strict).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Format {
+    Text,
+    Json,
+}
+
+/// Show a locked kit along with the SBOMs, provenance and test results its publisher attached to
+/// it, which can be downloaded with `--download`
+#[derive(Debug, Parser)]
+pub(crate) struct InspectKit {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The kit to inspect, as `<name>` or `<name>@<vendor>`
+    kit: String,
+
+    /// Download the attached artifacts to this directory
+    #[clap(long = "download")]
+    download: Option<PathBuf>,
+
+    /// Output format
+    #[clap(long = "format", value_enum, default_value = "text")]
+    format: Format,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct KitView<'a> {
+    name: &'a str,
+    version: String,
+    vendor: &'a str,
+    source: &'a str,
+    referrers: &'a [Referrer],
+}
+
+impl InspectKit {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let lock = Lock::load(&project).await?;
+        let kit = lock.select_kits(std::slice::from_ref(&self.kit))?[0];
+        let image_tool = project.image_tool()?;
+        let referrers = kit.referrers(&image_tool).await?;
+
+        if let Some(dir) = &self.download {
+            fs::create_dir_all(dir).await?;
+            for (i, referrer) in referrers.iter().enumerate() {
+                // The title is chosen by the publisher, so only its file name is used.
+                let name = referrer
+                    .title
+                    .as_deref()
+                    .and_then(|title| std::path::Path::new(title).file_name())
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| format!("artifact-{i}"));
+                let path = dir.join(name);
+                let content = image_tool
+                    .get_referrer_content(&kit.source, referrer)
+                    .await
+                    .context(format!("Unable to download '{}'", referrer.digest))?;
+                fs::write(&path, content).await?;
+                info!("Downloaded '{}'", path.display());
+            }
+        }
+
+        match self.format {
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&KitView {
+                    name: &kit.name,
+                    version: kit.version.to_string(),
+                    vendor: &kit.vendor,
+                    source: &kit.source,
+                    referrers: &referrers,
+                })
+                .context("failed to serialize the kit")?
+            ),
+            Format::Text => {
+                println!("kit: {}", kit);
+                if referrers.is_empty() {
+                    println!("No artifacts are attached to the kit");
+                } else {
+                    print!("{}", referrer_table(&referrers));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Show the kit metadata, per-architecture digests, layers and labels of a kit or SDK image, as
+/// the registry holds them
+#[derive(Debug, Parser)]
+pub(crate) struct InspectImage {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// An image reference such as `public.ecr.aws/bottlerocket/core-kit:v2.0.0`, or the name of
+    /// an image in Twoliter.lock: `sdk`, or a kit as `<name>` or `<name>@<vendor>`
+    image: String,
+
+    /// Output format
+    #[clap(long = "format", value_enum, default_value = "text")]
+    format: Format,
+}
+
+impl InspectImage {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let (uri, image_tool) = self.resolve(strict).await?;
+        let inspection = ImageInspection::fetch(&image_tool, &uri)
+            .await
+            .context(format!("Unable to inspect '{uri}'"))?;
+        match self.format {
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&inspection)
+                    .context("failed to serialize the image")?
+            ),
+            Format::Text => print!("{}", describe(&inspection)),
+        }
+        Ok(())
+    }
+
+    /// The uri of the image to inspect, and the image tool to fetch it with. Image references are
+    /// fetched with the project's image tool when there is a project, so that its registry
+    /// credentials are used.
+    async fn resolve(&self, strict: bool) -> Result<(String, ImageTool)> {
+        if self.image.contains('/') {
+            let image_tool =
+                match project::load_or_find_project(self.project_path.clone(), strict).await {
+                    Ok(project) => project.image_tool()?,
+                    Err(_) if self.project_path.is_none() => {
+                        ImageTool::from_environment().context("Unable to find an image tool")?
+                    }
+                    Err(e) => return Err(e),
+                };
+            return Ok((self.image.clone(), image_tool));
+        }
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let lock = Lock::read_lock_file(&project).await?;
+        let source = if self.image == "sdk" {
+            lock.sdk.source.clone()
+        } else {
+            lock.select_kits(std::slice::from_ref(&self.image))?[0]
+                .source
+                .clone()
+        };
+        Ok((source, project.image_tool()?))
+    }
+}
+
+/// Describes an inspected image for the terminal. The kit metadata label is shown decoded rather
+/// than among the labels.
+fn describe(inspection: &ImageInspection) -> String {
+    let mut out = format!("image: {}\n", inspection.source);
+    match &inspection.metadata {
+        Some(metadata) => {
+            out.push_str(&format!("kit: {} {}\n", metadata.name, metadata.version));
+            out.push_str(&format!("sdk: {}\n", metadata.sdk));
+            for kit in &metadata.kits {
+                out.push_str(&format!("depends on: {kit}\n"));
+            }
+            if let Some(reason) = &metadata.deprecated {
+                out.push_str(&format!("deprecated: {reason}\n"));
+            }
+            if let Some(date) = &metadata.end_of_support {
+                out.push_str(&format!("end of support: {date}\n"));
+            }
+        }
+        None => out.push_str("kit: none, the image has no kit metadata\n"),
+    }
+    out.push('\n');
+    out.push_str(&table(
+        ["ARCH", "DIGEST", "LAYERS", "SIZE"],
+        inspection
+            .platforms
+            .iter()
+            .map(|platform| {
+                [
+                    platform.architecture.clone(),
+                    platform.digest.clone(),
+                    platform.layers.len().to_string(),
+                    platform.size().to_string(),
+                ]
+            })
+            .collect(),
+    ));
+    for platform in &inspection.platforms {
+        out.push_str(&format!("\n{} layers:\n", platform.architecture));
+        out.push_str(&table(
+            ["DIGEST", "SIZE"],
+            platform
+                .layers
+                .iter()
+                .map(|layer| [layer.digest.to_string(), layer.size.to_string()])
+                .collect(),
+        ));
+        let labels: Vec<_> = platform
+            .labels
+            .iter()
+            .filter(|(key, _)| key.as_str() != KIT_METADATA_LABEL)
+            .collect();
+        if !labels.is_empty() {
+            out.push_str(&format!("{} labels:\n", platform.architecture));
+            for (key, value) in labels {
+                out.push_str(&format!("  {key}={value}\n"));
+            }
+        }
+    }
+    out
+}
+
+fn referrer_table(referrers: &[Referrer]) -> String {
+    table(
+        ["KIND", "NAME", "SIZE", "DIGEST"],
+        referrers
+            .iter()
+            .map(|referrer| {
+                [
+                    referrer
+                        .kind()
+                        .map(|kind| kind.to_string())
+                        .unwrap_or_else(|| referrer.artifact_type.clone()),
+                    referrer.title.clone().unwrap_or_else(|| "-".to_string()),
+                    referrer.size.to_string(),
+                    referrer.digest.clone(),
+                ]
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lock::PlatformInspection;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn lists_referrers_in_table() {
+        let referrers = vec![Referrer {
+            digest: "sha256:abc".to_string(),
+            artifact_type: oci_cli_wrapper::ReferrerKind::Sbom
+                .artifact_type()
+                .to_string(),
+            title: Some("core-kit.spdx.json".to_string()),
+            content_digest: "sha256:def".to_string(),
+            size: 1024,
+            annotations: BTreeMap::new(),
+        }];
+        assert_eq!(
+            referrer_table(&referrers),
+            "KIND  NAME                SIZE  DIGEST\n\
+             sbom  core-kit.spdx.json  1024  sha256:abc\n"
+        );
+    }
+
+    #[test]
+    fn describes_kit_image() {
+        let inspection = ImageInspection {
+            source: "example.com/core-kit:v1.0.0".to_string(),
+            metadata: Some(
+                serde_json::from_str(
+                    r#"{"name": "core-kit", "version": "1.0.0", "kit": [],
+                    "sdk": {"name": "bottlerocket-sdk", "version": "0.50.0", "vendor": "bottlerocket"}}"#,
+                )
+                .unwrap(),
+            ),
+            platforms: vec![PlatformInspection {
+                architecture: "amd64".to_string(),
+                digest: "sha256:abc".to_string(),
+                layers: serde_json::from_str(
+                    r#"[{"digest": "sha256:d1", "size": 100}, {"digest": "sha256:d2", "size": 20}]"#,
+                )
+                .unwrap(),
+                labels: BTreeMap::from([
+                    (KIT_METADATA_LABEL.to_string(), "e30=".to_string()),
+                    ("org.example".to_string(), "yes".to_string()),
+                ]),
+            }],
+        };
+        assert_eq!(
+            describe(&inspection),
+            "image: example.com/core-kit:v1.0.0\n\
+             kit: core-kit 1.0.0\n\
+             sdk: bottlerocket-sdk-0.50.0@bottlerocket\n\
+             \n\
+             ARCH   DIGEST      LAYERS  SIZE\n\
+             amd64  sha256:abc  2       120\n\
+             \n\
+             amd64 layers:\n\
+             DIGEST     SIZE\n\
+             sha256:d1  100\n\
+             sha256:d2  20\n\
+             amd64 labels:\n  \
+             org.example=yes\n"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use crate::licenses::{self, LicenseStatus};
+use crate::project;
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Format {
+    Csv,
+    Json,
+}
+
+/// List the license declared by each package, external file and kit in the project, and whether
+/// the `[licenses]` policy in Twoliter.toml allows it.
+#[derive(Debug, Parser)]
+pub(crate) struct Licenses {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// Output format
+    #[clap(long = "format", value_enum, default_value = "csv")]
+    pub(crate) format: Format,
+
+    /// Fail when a license is denied, or is unknown because it is undeclared or not allowed
+    #[clap(long = "check")]
+    pub(crate) check: bool,
+}
+
+impl Licenses {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let entries = licenses::collect(&project).await?;
+        match self.format {
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&entries).context("failed to serialize licenses")?
+            ),
+            Format::Csv => print!("{}", licenses::to_csv(&entries)),
+        }
+
+        let flagged: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.status != LicenseStatus::Allowed)
+            .collect();
+        for entry in &flagged {
+            let name = match &entry.package {
+                Some(package) => format!("'{}' of package '{package}'", entry.name),
+                None => format!("'{}'", entry.name),
+            };
+            match (&entry.license, entry.status) {
+                (None, _) => warn!("{name} does not declare a license"),
+                (Some(license), LicenseStatus::Denied) => {
+                    warn!("{name} uses denied license '{license}'")
+                }
+                (Some(license), _) => {
+                    warn!("{name} uses license '{license}', which is not allowed")
+                }
+            }
+        }
+        if self.check && !flagged.is_empty() {
+            bail!(
+                "{} licenses are denied or unknown under the project's license policy",
+                flagged.len()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+use crate::lint::lint;
+use crate::project;
+use anyhow::{ensure, Result};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Check the package and kit manifests in the project for deprecated metadata, missing spec files,
+/// sources not covered by `source-groups`, and external files without checksums.
+#[derive(Debug, Parser)]
+pub(crate) struct Lint {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+}
+
+impl Lint {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let findings = lint(&project).await?;
+        for finding in findings.iter() {
+            println!("{finding}");
+        }
+        ensure!(
+            findings.is_empty(),
+            "found {} problem(s) in the project's manifests",
+            findings.len()
+        );
+        Ok(())
+    }
+}
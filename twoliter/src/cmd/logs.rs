@@ -0,0 +1,117 @@
+use crate::project;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often a followed log is checked for new output.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The kinds of build which write a log, in the order they are searched for a name.
+const LOG_KINDS: [&str; 4] = ["package", "kit", "variant", "repack"];
+
+/// Show the output of the last Docker build of a package, kit, variant or repacked variant.
+#[derive(Debug, Parser)]
+pub(crate) struct Logs {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The architecture the package, kit or variant was built for.
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// The name of the package, kit or variant.
+    name: String,
+
+    /// Keep printing the log as the build writes to it, until interrupted.
+    #[clap(long = "follow", short = 'f')]
+    follow: bool,
+}
+
+impl Logs {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let logs_dir = project.project_dir().join("build/logs").join(&self.arch);
+        let log_path = find_log(&logs_dir, &self.name)?;
+        let mut log =
+            File::open(&log_path).context(format!("Unable to open '{}'", log_path.display()))?;
+        let mut stdout = std::io::stdout();
+        loop {
+            copy_new_output(&mut log, &mut stdout)
+                .context(format!("Unable to read '{}'", log_path.display()))?;
+            if !self.follow {
+                return Ok(());
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Finds the log of the package, kit or variant called `name`.
+fn find_log(logs_dir: &Path, name: &str) -> Result<PathBuf> {
+    for kind in LOG_KINDS {
+        let log_path = logs_dir.join(format!("{kind}-{name}.log"));
+        if log_path.is_file() {
+            return Ok(log_path);
+        }
+    }
+    bail!(
+        "no build log for '{name}' was found in '{}'",
+        logs_dir.display()
+    )
+}
+
+/// Copies whatever was written to `log` since it was last read. Starts again from the beginning
+/// when the log was truncated by a new build.
+fn copy_new_output(log: &mut File, out: &mut impl Write) -> std::io::Result<()> {
+    if log.metadata()?.len() < log.stream_position()? {
+        log.rewind()?;
+    }
+    std::io::copy(log, out)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shows_build_logs() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let logs_dir = temp_dir.path();
+        std::fs::write(logs_dir.join("kit-core.log"), "kit output\n").unwrap();
+        std::fs::write(logs_dir.join("package-core.log"), "package output\n").unwrap();
+        assert_eq!(
+            find_log(logs_dir, "core").unwrap(),
+            logs_dir.join("package-core.log")
+        );
+        assert!(find_log(logs_dir, "kernel").is_err());
+        std::fs::write(logs_dir.join("repack-aws-dev.log"), "repack output\n").unwrap();
+        assert_eq!(
+            find_log(logs_dir, "aws-dev").unwrap(),
+            logs_dir.join("repack-aws-dev.log")
+        );
+
+        let log_path = logs_dir.join("kit-core.log");
+        let mut log = File::open(&log_path).unwrap();
+        let mut out = Vec::new();
+        copy_new_output(&mut log, &mut out).unwrap();
+        assert_eq!(out, b"kit output\n");
+
+        // Only new output is copied, until a new build truncates the log.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log_path)
+            .unwrap()
+            .write_all(b"more output\n")
+            .unwrap();
+        copy_new_output(&mut log, &mut out).unwrap();
+        assert_eq!(out, b"kit output\nmore output\n");
+        std::fs::write(&log_path, "new\n").unwrap();
+        copy_new_output(&mut log, &mut out).unwrap();
+        assert_eq!(out, b"kit output\nmore output\nnew\n");
+    }
+}
@@ -1,8 +1,11 @@
 use crate::cargo_make::CargoMake;
+use crate::common::fs;
+use crate::local_sdk;
 use crate::lock::Lock;
+use crate::make_targets::make_targets;
 use crate::project::{self};
 use crate::tools::install_tools;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 use std::path::PathBuf;
 
@@ -18,16 +21,46 @@ pub(crate) struct Make {
     /// Twoliter does not read this from the CARGO_HOME environment variable to avoid any possible
     /// confusion between a CARGO_HOME set on the system, and the path intended for the Bottlerocket
     /// build.
-    #[clap(long)]
-    cargo_home: PathBuf,
+    #[clap(long, required_unless_present = "list")]
+    cargo_home: Option<PathBuf>,
 
     /// This can be passed by environment variable. We require it as part of the command arguments
     /// because we need it to pull the right SDK target architecture.
-    #[clap(long, env = "BUILDSYS_ARCH")]
-    arch: String,
+    #[clap(long, env = "BUILDSYS_ARCH", required_unless_present = "list")]
+    arch: Option<String>,
+
+    /// Export the cache of package builds to the `[build-cache]` registry, even if Twoliter.toml
+    /// doesn't ask for it, e.g. only in CI.
+    #[clap(long = "export-build-cache", env = "TWOLITER_EXPORT_BUILD_CACHE")]
+    export_build_cache: bool,
+
+    /// Locally built kits which `repack-variant` swaps into the variant's images in place of the
+    /// locked kits' packages, e.g. `--repack-kit my-kit`.
+    #[clap(long = "repack-kit", value_delimiter = ',')]
+    repack_kits: Vec<String>,
+
+    /// A user data TOML file which `repack-variant` writes to the images' private partition, so
+    /// that they boot with its settings.
+    #[clap(long = "repack-user-data")]
+    repack_user_data: Option<PathBuf>,
+
+    /// A bootconfig file which `repack-variant` renders into the images' private partition.
+    #[clap(long = "repack-bootconfig")]
+    repack_bootconfig: Option<PathBuf>,
+
+    /// A JSON Schema of the variant's settings, which the settings in `--repack-user-data` must
+    /// follow.
+    #[clap(long = "repack-settings-schema", requires = "repack_user_data")]
+    repack_settings_schema: Option<PathBuf>,
+
+    /// List the available cargo make tasks, with a description of each and the environment
+    /// variables it reads, instead of running one.
+    #[clap(long, conflicts_with = "makefile_task")]
+    list: bool,
 
     /// Cargo make task. E.g. the word "build" if we want to execute `cargo make build`.
-    makefile_task: String,
+    #[clap(required_unless_present = "list")]
+    makefile_task: Option<String>,
 
     /// Uninspected arguments to be passed to cargo make after the target name. For example, --foo
     /// in the following command : cargo make test --foo.
@@ -35,19 +68,52 @@ pub(crate) struct Make {
 }
 
 impl Make {
-    pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        if self.list {
+            for target in make_targets()? {
+                println!("{target}");
+            }
+            return Ok(());
+        }
+        // clap requires these unless --list is given.
+        let cargo_home = self
+            .cargo_home
+            .as_ref()
+            .context("--cargo-home is required")?;
+        let makefile_task = self.makefile_task.as_ref().context("a task is required")?;
+        let arch = self.arch.as_ref().context("--arch is required")?;
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
         let lock = Lock::load(&project).await?;
         let toolsdir = project.project_dir().join("build/tools");
         install_tools(&toolsdir).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
-        CargoMake::new(&lock.sdk.source)?
-            .env("CARGO_HOME", self.cargo_home.display().to_string())
+        let mut cargo_make = CargoMake::new(&local_sdk::sdk_for(&project, &lock, arch).await?)?;
+        if !self.repack_kits.is_empty() {
+            cargo_make = cargo_make.env("BUILDSYS_REPACK_KITS", self.repack_kits.join(","));
+        }
+        // Tasks run in the project directory, so the files are given to them as absolute paths.
+        for (var, path) in [
+            ("BUILDSYS_REPACK_USER_DATA", &self.repack_user_data),
+            ("BUILDSYS_REPACK_BOOTCONFIG", &self.repack_bootconfig),
+            (
+                "BUILDSYS_REPACK_SETTINGS_SCHEMA",
+                &self.repack_settings_schema,
+            ),
+        ] {
+            if let Some(path) = path {
+                let path = fs::canonicalize(path).await?;
+                cargo_make = cargo_make.env(var, path.display().to_string());
+            }
+        }
+        cargo_make
+            .env("CARGO_HOME", cargo_home.display().to_string())
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+            .env("BUILDSYS_KIT_FEATURES", lock.kit_features())
             .makefile(makefile_path)
             .project_dir(project.project_dir())
-            .exec_with_args(&self.makefile_task, self.additional_args.clone())
+            .envs(project.cargo_make_env(self.export_build_cache)?.into_iter())
+            .exec_with_args(makefile_task, self.additional_args.clone())
             .await
     }
 }
@@ -72,7 +138,7 @@ fn test_trailing_args_1() {
     ])
     .unwrap();
 
-    assert_eq!(args.makefile_task, "testsys");
+    assert_eq!(args.makefile_task.as_deref(), Some("testsys"));
     assert_eq!(args.additional_args[0], "add");
     assert_eq!(args.additional_args[1], "secret");
     assert_eq!(args.additional_args[2], "map");
@@ -101,7 +167,7 @@ fn test_trailing_args_2() {
     ])
     .unwrap();
 
-    assert_eq!(args.makefile_task, "testsys");
+    assert_eq!(args.makefile_task.as_deref(), Some("testsys"));
     assert_eq!(args.additional_args[0], "add");
     assert_eq!(args.additional_args[1], "secret");
     assert_eq!(args.additional_args[2], "map");
@@ -133,7 +199,7 @@ fn test_trailing_args_3() {
     ])
     .unwrap();
 
-    assert_eq!(args.makefile_task, "testsys");
+    assert_eq!(args.makefile_task.as_deref(), Some("testsys"));
     assert_eq!(args.additional_args[0], "add");
     assert_eq!(args.additional_args[1], "secret");
     assert_eq!(args.additional_args[2], "map");
@@ -146,3 +212,31 @@ fn test_trailing_args_3() {
     assert_eq!(args.additional_args[7], "something-else=baz");
     assert_eq!(args.additional_args[8], "--");
 }
+
+#[test]
+fn test_list() {
+    let args = Make::try_parse_from(["make", "--list"]).unwrap();
+    assert!(args.list);
+    assert_eq!(args.makefile_task, None);
+
+    assert!(
+        Make::try_parse_from(["make", "--cargo-home", "/tmp/foo", "--arch", "x86_64"]).is_err()
+    );
+    assert!(Make::try_parse_from(["make", "--list", "build"]).is_err());
+}
+
+#[test]
+fn test_export_build_cache() {
+    let args = Make::try_parse_from([
+        "make",
+        "--cargo-home",
+        "/tmp/foo",
+        "--arch",
+        "x86_64",
+        "--export-build-cache",
+        "build-package",
+    ])
+    .unwrap();
+    assert!(args.export_build_cache);
+    assert_eq!(args.makefile_task.as_deref(), Some("build-package"));
+}
@@ -1,17 +1,59 @@
+mod affected;
 mod build;
 mod build_clean;
+mod cache;
 mod debug;
+mod export_deps;
 mod fetch;
+mod graph;
+mod import_deps;
+mod init;
+mod inspect;
+mod licenses;
+mod lint;
+mod logs;
 mod make;
+mod new;
+mod outdated;
+mod promote_kit;
+mod prune;
 mod publish_kit;
+mod publish_repo;
+mod publish_variant;
+mod registry;
+mod sbom;
+mod schema;
 mod update;
+mod verify_artifacts;
+mod watch;
+mod why;
 
 use self::build::BuildCommand;
+use crate::cmd::affected::Affected;
+use crate::cmd::cache::CacheCommand;
 use crate::cmd::debug::DebugAction;
+use crate::cmd::export_deps::ExportDeps;
 use crate::cmd::fetch::Fetch;
+use crate::cmd::graph::Graph;
+use crate::cmd::import_deps::ImportDeps;
+use crate::cmd::init::Init;
+use crate::cmd::inspect::InspectCommand;
+use crate::cmd::licenses::Licenses;
+use crate::cmd::lint::Lint;
+use crate::cmd::logs::Logs;
 use crate::cmd::make::Make;
+use crate::cmd::new::NewCommand;
+use crate::cmd::outdated::Outdated;
+use crate::cmd::prune::Prune;
 use crate::cmd::publish_kit::PublishCommand;
+use crate::cmd::registry::RegistryCommand;
+use crate::cmd::sbom::Sbom;
+use crate::cmd::schema::Schema;
 use crate::cmd::update::Update;
+use crate::cmd::verify_artifacts::VerifyArtifacts;
+use crate::cmd::watch::Watch;
+use crate::cmd::why::Why;
+use crate::progress::Progress;
 use anyhow::Result;
 use clap::Parser;
 use env_logger::Builder;
@@ -22,12 +64,22 @@ const DEFAULT_LEVEL_FILTER: LevelFilter = LevelFilter::Info;
 /// A tool for building custom variants of Bottlerocket.
 #[derive(Debug, Parser)]
 #[clap(about, long_about = None, version)]
-pub(crate) struct Args {
+pub struct Args {
     /// Set the logging level. One of [off|error|warn|info|debug|trace]. Defaults to warn. You can
     /// also leave this unset and use the RUST_LOG env variable. See
     /// https://github.com/rust-cli/env_logger/
     #[clap(long = "log-level")]
-    pub(crate) log_level: Option<LevelFilter>,
+    pub log_level: Option<LevelFilter>,
+
+    /// Fail when Twoliter.toml contains keys that Twoliter does not recognize, or when kits with
+    /// the same name are published by more than one vendor, rather than warning about them.
+    #[clap(long = "strict", global = true, env = "TWOLITER_STRICT")]
+    pub strict: bool,
+
+    /// Do not display progress bars, such as while pulling and extracting images. They are also
+    /// hidden when stdout is not a terminal.
+    #[clap(long = "quiet", global = true)]
+    pub quiet: bool,
 
     #[clap(subcommand)]
     pub(crate) subcommand: Subcommand,
@@ -35,17 +87,61 @@ pub(crate) struct Args {
 
 #[derive(Debug, Parser)]
 pub(crate) enum Subcommand {
+    Affected(Affected),
+
     /// Build something, such as a Bottlerocket image or a kit of packages.
     #[clap(subcommand)]
     Build(BuildCommand),
 
+    /// Manage the lookaside cache of packages' external files.
+    #[clap(subcommand)]
+    Cache(CacheCommand),
+
     Fetch(Fetch),
 
+    ExportDeps(ExportDeps),
+
+    Graph(Graph),
+
+    ImportDeps(ImportDeps),
+
+    Init(Init),
+
+    Licenses(Licenses),
+
+    Lint(Lint),
+
+    Logs(Logs),
+
     Make(Make),
 
+    /// Add something new to the project, such as a package or a kit.
+    #[clap(subcommand)]
+    New(NewCommand),
+
+    Outdated(Outdated),
+
+    Prune(Prune),
+
+    #[clap(subcommand)]
+    Inspect(InspectCommand),
+
+    #[clap(subcommand)]
+    Registry(RegistryCommand),
+
+    Sbom(Sbom),
+
+    Schema(Schema),
+
     /// Update Twoliter.lock
     Update(Update),
 
+    VerifyArtifacts(VerifyArtifacts),
+
+    Watch(Watch),
+
+    Why(Why),
+
     /// Publish something, such as a Kit
     #[clap(subcommand)]
     Publish(PublishCommand),
@@ -56,19 +152,40 @@ pub(crate) enum Subcommand {
 }
 
 /// Entrypoint for the `twoliter` command line program.
-pub(super) async fn run(args: Args) -> Result<()> {
+pub async fn run(args: Args) -> Result<()> {
+    let strict = args.strict;
+    let progress = Progress::new(args.quiet);
     match args.subcommand {
-        Subcommand::Build(build_command) => build_command.run().await,
-        Subcommand::Fetch(fetch_args) => fetch_args.run().await,
-        Subcommand::Make(make_args) => make_args.run().await,
-        Subcommand::Update(update_args) => update_args.run().await,
-        Subcommand::Publish(publish_command) => publish_command.run().await,
-        Subcommand::Debug(debug_action) => debug_action.run().await,
+        Subcommand::Affected(affected_args) => affected_args.run(strict).await,
+        Subcommand::Build(build_command) => build_command.run(strict).await,
+        Subcommand::Cache(cache_command) => cache_command.run(strict).await,
+        Subcommand::Fetch(fetch_args) => fetch_args.run(strict, &progress).await,
+        Subcommand::ExportDeps(export_args) => export_args.run(strict, &progress).await,
+        Subcommand::Graph(graph_args) => graph_args.run(strict).await,
+        Subcommand::ImportDeps(import_args) => import_args.run(strict, &progress).await,
+        Subcommand::Init(init_args) => init_args.run().await,
+        Subcommand::Inspect(inspect_command) => inspect_command.run(strict).await,
+        Subcommand::Licenses(licenses_args) => licenses_args.run(strict).await,
+        Subcommand::Lint(lint_args) => lint_args.run(strict).await,
+        Subcommand::Logs(logs_args) => logs_args.run(strict).await,
+        Subcommand::Make(make_args) => make_args.run(strict).await,
+        Subcommand::New(new_command) => new_command.run(strict).await,
+        Subcommand::Outdated(outdated_args) => outdated_args.run(strict).await,
+        Subcommand::Prune(prune_args) => prune_args.run(strict).await,
+        Subcommand::Registry(registry_command) => registry_command.run(strict).await,
+        Subcommand::Sbom(sbom_args) => sbom_args.run(strict).await,
+        Subcommand::Schema(schema_args) => schema_args.run().await,
+        Subcommand::Update(update_args) => update_args.run(strict).await,
+        Subcommand::VerifyArtifacts(verify_args) => verify_args.run().await,
+        Subcommand::Watch(watch_args) => watch_args.run(strict).await,
+        Subcommand::Why(why_args) => why_args.run(strict).await,
+        Subcommand::Publish(publish_command) => publish_command.run(strict).await,
+        Subcommand::Debug(debug_action) => debug_action.run(strict).await,
     }
 }
 
 /// use `level` if present, or else use `RUST_LOG` if present, or else use a default.
-pub(super) fn init_logger(level: Option<LevelFilter>) {
+pub fn init_logger(level: Option<LevelFilter>) {
     match (std::env::var(env_logger::DEFAULT_FILTER_ENV).ok(), level) {
         (Some(_), None) => {
             // RUST_LOG exists and level does not; use the environment variable.
@@ -86,6 +203,40 @@ pub(super) fn init_logger(level: Option<LevelFilter>) {
     }
 }
 
+/// Lays out `rows` in columns under `header`.
+pub(crate) fn table<const N: usize>(header: [&str; N], mut rows: Vec<[String; N]>) -> String {
+    rows.insert(0, header.map(str::to_string));
+    let widths: Vec<usize> = (0..N)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    let mut out = String::new();
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod cli_test {
+    use super::*;
+
+    #[test]
+    fn quiet_is_accepted_after_the_subcommand() {
+        let args = Args::try_parse_from(["twoliter", "fetch", "--quiet"]).unwrap();
+        assert!(args.quiet);
+        assert!(matches!(args.subcommand, Subcommand::Fetch(_)));
+        let args = Args::try_parse_from(["twoliter", "--quiet", "import-deps", "deps.tar.gz"]);
+        assert!(args.unwrap().quiet);
+    }
+}
+
 #[cfg(feature = "integ-tests")]
 #[cfg(test)]
 mod test {
@@ -132,16 +283,22 @@ mod test {
     async fn twoliter_update(project_path: &Path) {
         let command = Update {
             project_path: Some(project_path.to_path_buf()),
+            report: None,
+            deny_deprecated: false,
         };
-        command.run().await.unwrap();
+        command.run(false).await.unwrap();
     }
 
     async fn twoliter_fetch(project_path: &Path, arch: &str) {
         let command = Fetch {
             project_path: Some(project_path.to_path_buf()),
-            arch: arch.into(),
+            arch: vec![arch.into()],
+            parallel: false,
+            sdk_only: false,
+            kit: Vec::new(),
+            offline: false,
         };
-        command.run().await.unwrap()
+        command.run(false, &Progress::new(true)).await.unwrap()
     }
 
     #[tokio::test]
@@ -156,13 +313,18 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: vec![arch.to_string()],
+            parallel: false,
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            profile: None,
+            no_checkpoints: false,
+            no_provenance: false,
+            export_build_cache: false,
         };
 
-        command.run().await.unwrap();
+        command.run(false).await.unwrap();
         expect_kit(&project_dir, "core-kit", arch, &["pkg-a"]).await;
     }
 
@@ -178,13 +340,18 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: vec![arch.to_string()],
+            parallel: false,
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            profile: None,
+            no_checkpoints: false,
+            no_provenance: false,
+            export_build_cache: false,
         };
 
-        command.run().await.unwrap();
+        command.run(false).await.unwrap();
         expect_kit(&project_dir, "core-kit", arch, &["pkg-a"]).await;
         expect_kit(&project_dir, "extra-1-kit", arch, &["pkg-b", "pkg-d"]).await;
     }
@@ -201,13 +368,18 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: vec![arch.to_string()],
+            parallel: false,
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            profile: None,
+            no_checkpoints: false,
+            no_provenance: false,
+            export_build_cache: false,
         };
 
-        command.run().await.unwrap();
+        command.run(false).await.unwrap();
         expect_kit(&project_dir, "core-kit", arch, &["pkg-a"]).await;
         expect_kit(&project_dir, "extra-2-kit", arch, &["pkg-c"]).await;
     }
@@ -224,13 +396,18 @@ mod test {
 
         let command = BuildKit {
             project_path: Some(project_path),
-            arch: arch.to_string(),
+            arch: vec![arch.to_string()],
+            parallel: false,
             kit: kit_name.to_string(),
             lookaside_cache: None,
             upstream_source_fallback: false,
+            profile: None,
+            no_checkpoints: false,
+            no_provenance: false,
+            export_build_cache: false,
         };
 
-        command.run().await.unwrap();
+        command.run(false).await.unwrap();
         expect_kit(&project_dir, "core-kit", arch, &["pkg-a"]).await;
         expect_kit(&project_dir, "extra-1-kit", arch, &["pkg-b", "pkg-d"]).await;
         expect_kit(&project_dir, "extra-2-kit", arch, &["pkg-c"]).await;
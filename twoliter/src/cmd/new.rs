@@ -0,0 +1,72 @@
+use crate::project;
+use crate::scaffold;
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+pub(crate) enum NewCommand {
+    Package(NewPackage),
+    Kit(NewKit),
+}
+
+impl NewCommand {
+    pub(crate) async fn run(self, strict: bool) -> Result<()> {
+        match self {
+            NewCommand::Package(command) => command.run(strict).await,
+            NewCommand::Kit(command) => command.run(strict).await,
+        }
+    }
+}
+
+/// Add a package with an empty spec file to the project and register it in the Cargo workspace.
+#[derive(Debug, Parser)]
+pub(crate) struct NewPackage {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The name of the package
+    pub(crate) name: String,
+}
+
+impl NewPackage {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        scaffold::new_package(&project.project_dir(), &self.name).await
+    }
+}
+
+/// Add a kit to the project and register it in the Cargo workspace.
+#[derive(Debug, Parser)]
+pub(crate) struct NewKit {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The name of the kit
+    pub(crate) name: String,
+
+    /// The vendor that publishes the kit. Defaults to the vendor of the project's sdk.
+    #[clap(long = "vendor")]
+    pub(crate) vendor: Option<String>,
+
+    /// A package in the project to include in the kit. May be given more than once.
+    #[clap(long = "package")]
+    pub(crate) packages: Vec<String>,
+}
+
+impl NewKit {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let vendor = match &self.vendor {
+            Some(vendor) => vendor.clone(),
+            None => project
+                .sdk_image()
+                .map(|sdk| sdk.vendor.to_string())
+                .context("no sdk is declared in Twoliter.toml, so --vendor is required")?,
+        };
+        let packages: Vec<&str> = self.packages.iter().map(String::as_str).collect();
+        scaffold::new_kit(&project.project_dir(), &self.name, &vendor, &packages).await
+    }
+}
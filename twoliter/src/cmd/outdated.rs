@@ -0,0 +1,71 @@
+use super::table;
+use crate::outdated::{self, DependencyKind, OutdatedEntry};
+use crate::project;
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum Format {
+    Text,
+    Json,
+}
+
+/// List the sdk and each kit in Twoliter.toml along with the locked version and the newest version
+/// available from the vendor.
+#[derive(Debug, Parser)]
+pub(crate) struct Outdated {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// Output format
+    #[clap(long = "format", value_enum, default_value = "text")]
+    pub(crate) format: Format,
+}
+
+impl Outdated {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let entries = outdated::check(&project).await?;
+        match self.format {
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&entries)
+                    .context("failed to serialize outdated dependencies")?
+            ),
+            Format::Text => print!("{}", outdated_table(&entries)),
+        }
+        Ok(())
+    }
+}
+
+/// Lays out `entries` in columns, with `-` for versions which aren't known.
+fn outdated_table(entries: &[OutdatedEntry]) -> String {
+    let display = |version: &Option<semver::Version>| {
+        version
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_else(|| "-".to_string())
+    };
+    table(
+        ["NAME", "VENDOR", "KIND", "REQUIRED", "LOCKED", "LATEST"],
+        entries
+            .iter()
+            .map(|entry| {
+                [
+                    entry.name.clone(),
+                    entry.vendor.clone(),
+                    match entry.kind {
+                        DependencyKind::Sdk => "sdk",
+                        DependencyKind::Kit => "kit",
+                    }
+                    .to_string(),
+                    entry.required.to_string(),
+                    display(&entry.locked),
+                    display(&entry.latest),
+                ]
+            })
+            .collect(),
+    )
+}
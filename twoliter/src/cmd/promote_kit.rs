@@ -0,0 +1,293 @@
+use crate::cmd::publish_kit::vendor_settings;
+use crate::common::exec;
+use crate::lock::ManifestListView;
+use crate::project::{self, Hook, Project, ValidIdentifier};
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::info;
+
+/// Copy a published kit, with every architecture it was built for, from one registry to another
+/// without rebuilding it, such as from a staging registry to a public one. The kit keeps its
+/// digests, so projects which locked it in one registry can consume it from the other. When the
+/// kit is promoted to a vendor which signs its images in Infra.toml, it is signed again there.
+#[derive(Debug, Parser)]
+pub(crate) struct PromoteKit {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// Kit name to promote
+    kit_name: String,
+
+    /// The version of the kit to promote. Defaults to the release version of the project
+    #[clap(long = "version")]
+    version: Option<String>,
+
+    /// The registry to copy the kit from, or the name of a vendor in Twoliter.toml whose registry
+    /// to copy it from
+    #[clap(long = "from")]
+    from: String,
+
+    /// The registry to copy the kit to, or the name of a vendor in Twoliter.toml whose registry to
+    /// copy it to
+    #[clap(long = "to")]
+    to: String,
+}
+
+impl PromoteKit {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let from = registry(&project, &self.from);
+        let to = registry(&project, &self.to);
+        ensure!(
+            from != to,
+            "the kit is already in '{from}', promote it to another registry"
+        );
+        let version = self.version.as_deref().unwrap_or(project.release_version());
+        let tag = format!("v{}", version.trim_start_matches('v'));
+        let source = format!("{from}/{}:{tag}", self.kit_name);
+        let target = format!("{to}/{}:{tag}", self.kit_name);
+        let to_vendor = is_vendor(&project, &self.to).then(|| self.to.clone());
+
+        // The publish hooks run for the promoted kit too. `TWOLITER_VENDOR` is only given when the
+        // kit is promoted to one of the project's vendors.
+        let mut hook_context = vec![
+            ("TWOLITER_KIT", self.kit_name.clone()),
+            ("TWOLITER_REGISTRY", to.clone()),
+        ];
+        hook_context.extend(to_vendor.clone().map(|vendor| ("TWOLITER_VENDOR", vendor)));
+        project.run_hook(Hook::PrePublishKit, &hook_context).await?;
+
+        // Digests are compared as the registries serve the manifests, and the kit is copied by
+        // digest, so that a tag moved in the meantime can't be promoted in its place.
+        let image_tool = project.image_tool()?;
+        let digest = image_tool
+            .digest(&source)
+            .await
+            .context(format!("Unable to find the kit '{source}'"))?;
+        let source_list = format!("{from}/{}@{digest}", self.kit_name);
+        let manifest = image_tool
+            .get_manifest(&source_list)
+            .await
+            .context(format!("Unable to find the kit '{source_list}'"))?;
+        let manifest_list: ManifestListView =
+            serde_json::from_slice(&manifest).context(format!(
+                "'{source}' is not a manifest list, so it does not appear to be a published kit"
+            ))?;
+
+        // A kit already at the target is only replaced if it is the same kit, since projects may
+        // have locked its digest.
+        match image_tool.digest(&target).await {
+            Ok(existing) if existing == digest => {
+                info!("'{target}' is already '{source}' ({digest})");
+            }
+            Ok(existing) => bail!(
+                "'{target}' is already a different kit ({existing}) than '{source}' ({digest}), \
+                refusing to replace it"
+            ),
+            Err(e) if e.is_not_found() => {
+                info!("Promoting kit '{source}' ({digest}) to '{target}'");
+                image_tool
+                    .copy_image(&source_list, &target)
+                    .await
+                    .context(format!("Unable to copy '{source}' to '{target}'"))?;
+            }
+            Err(e) => return Err(e).context(format!("Unable to check for an existing '{target}'")),
+        }
+
+        // Check that the copy is identical, down to the image of each architecture, since
+        // projects consuming the kit have locked its digests.
+        let promoted = image_tool
+            .digest(&target)
+            .await
+            .context(format!("Unable to find the promoted kit '{target}'"))?;
+        ensure!(
+            promoted == digest,
+            "the digest of '{target}' is {promoted} rather than {digest}, the digest of the kit was \
+            not preserved"
+        );
+        let mut promoted_uris = vec![format!("{to}/{}@{digest}", self.kit_name)];
+        for image in &manifest_list.manifests {
+            let target_image = format!("{to}/{}@{}", self.kit_name, image.digest);
+            let promoted = image_tool.digest(&target_image).await.context(format!(
+                "Unable to find the promoted image '{target_image}'"
+            ))?;
+            ensure!(
+                promoted == image.digest,
+                "the digest of '{target_image}' is {promoted}"
+            );
+            promoted_uris.push(target_image);
+        }
+
+        // Signatures are stored beside the images they sign, so they aren't copied with the kit.
+        if let Some(vendor) = &to_vendor {
+            let infra_toml = project.project_dir().join("Infra.toml");
+            if let Some(signing) = ImageSigning::of_vendor(&infra_toml, vendor).await? {
+                for uri in &promoted_uris {
+                    info!("Signing {uri}");
+                    let mut command = signing.command(project.tools().cosign(), uri);
+                    command.current_dir(project.project_dir());
+                    exec(&mut command, true)
+                        .await
+                        .context(format!("Unable to sign '{uri}'"))?;
+                }
+            }
+        }
+
+        info!(
+            "Promoted kit '{source}' to '{target}' with {} architecture(s)",
+            manifest_list.manifests.len()
+        );
+        project.run_hook(Hook::PostPublishKit, &hook_context).await
+    }
+}
+
+/// How pubsys signs the images published to a vendor's registry, from the vendor's `signing` in
+/// `Infra.toml`. Promoted kits are signed again with it.
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+struct ImageSigning {
+    key: SigningKey,
+    #[serde(default)]
+    tlog_upload: bool,
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SigningKey {
+    File { path: PathBuf },
+    Kms { key_arn: String },
+}
+
+impl ImageSigning {
+    /// The signing settings of `vendor`, if it signs its images.
+    async fn of_vendor(infra_toml: &Path, vendor: &str) -> Result<Option<Self>> {
+        let Some(signing) = vendor_settings(infra_toml, vendor)
+            .await?
+            .and_then(|mut settings| settings.remove("signing"))
+        else {
+            return Ok(None);
+        };
+        signing.try_into().map(Some).context(format!(
+            "Unable to parse the signing settings of vendor '{vendor}'"
+        ))
+    }
+
+    /// The cosign command which signs the image at `uri`, as pubsys signs published kits.
+    fn command(&self, cosign: &Path, uri: &str) -> Command {
+        let key = match &self.key {
+            SigningKey::File { path } => path.display().to_string(),
+            SigningKey::Kms { key_arn } => format!("awskms:///{key_arn}"),
+        };
+        let mut command = Command::new(cosign);
+        command
+            .args(["sign", "--yes", "--key", &key])
+            .arg(format!("--tlog-upload={}", self.tlog_upload))
+            .arg(uri);
+        command
+    }
+}
+
+/// Whether `value` is the name of one of the project's vendors rather than a registry.
+fn is_vendor(project: &Project, value: &str) -> bool {
+    project
+        .vendor()
+        .contains_key(&ValidIdentifier(value.to_string()))
+}
+
+/// The registry named by `value`, which is either the name of one of the project's vendors or a
+/// registry.
+fn registry(project: &Project, value: &str) -> String {
+    project
+        .vendor()
+        .get(&ValidIdentifier(value.to_string()))
+        .map(|vendor| vendor.registry.clone())
+        .unwrap_or_else(|| value.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn resolves_registry_of_vendor() {
+        let temp_dir = crate::test::copy_project_to_temp_dir("project1");
+        let project =
+            project::load_or_find_project(Some(temp_dir.path().join("Twoliter.toml")), false)
+                .await
+                .unwrap();
+        let (name, vendor) = project.vendor().iter().next().unwrap();
+        assert_eq!(registry(&project, &name.to_string()), vendor.registry);
+        assert_eq!(
+            registry(&project, "example.com/staging/"),
+            "example.com/staging"
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_signing_of_vendor() {
+        let temp_dir = TempDir::new().unwrap();
+        let infra_toml = temp_dir.path().join("Infra.toml");
+        assert_eq!(
+            ImageSigning::of_vendor(&infra_toml, "my-vendor")
+                .await
+                .unwrap(),
+            None
+        );
+
+        std::fs::write(
+            &infra_toml,
+            r#"
+            [vendor.my-vendor]
+            registry = "example.com/kits"
+            signing = { key = { kms = { key_arn = "arn:aws:kms:key" } }, tlog_upload = true }
+
+            [vendor.unsigned]
+            registry = "example.com/unsigned"
+            "#,
+        )
+        .unwrap();
+        let signing = ImageSigning::of_vendor(&infra_toml, "my-vendor")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            signing,
+            ImageSigning {
+                key: SigningKey::Kms {
+                    key_arn: "arn:aws:kms:key".to_string()
+                },
+                tlog_upload: true,
+            }
+        );
+        assert_eq!(
+            ImageSigning::of_vendor(&infra_toml, "unsigned")
+                .await
+                .unwrap(),
+            None
+        );
+
+        let command = signing.command(
+            Path::new("/pinned/cosign"),
+            "example.com/kits/core@sha256:0",
+        );
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "/pinned/cosign");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "sign",
+                "--yes",
+                "--key",
+                "awskms:///arn:aws:kms:key",
+                "--tlog-upload=true",
+                "example.com/kits/core@sha256:0",
+            ]
+        );
+    }
+}
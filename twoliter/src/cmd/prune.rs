@@ -0,0 +1,382 @@
+use crate::common::{exec, fs};
+use crate::disk_space::{dir_size, gib};
+use crate::oci_store::OciStore;
+use crate::project;
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, FixedOffset};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// The images buildsys builds are tagged with this prefix. They are removed once a build finishes,
+/// so any which remain were left behind by builds that were interrupted or failed.
+const BUILDSYS_IMAGES: &str = "buildsys-*";
+
+/// Free disk space by removing old build outputs, without cleaning the whole build directory.
+///
+/// Variant images other than the ones `latest` points to are pruned, as are the RPMs of packages
+/// which were last built before `--older-than`, and images buildsys left behind in Docker. A
+/// package only keeps the RPMs of its last build, so `--keep-last` does not apply to RPMs. Blobs in
+/// the kit image cache which no cached kit image uses any longer are always pruned.
+#[derive(Debug, Parser)]
+pub(crate) struct Prune {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// Keep the N most recent images of each variant, counting the ones `latest` points to, and the
+    /// N most recent buildsys images.
+    #[clap(long = "keep-last", value_name = "N")]
+    keep_last: Option<usize>,
+
+    /// Only remove outputs older than this, such as "30m", "12h", "7d" or "2w".
+    #[clap(long = "older-than", value_name = "AGE", value_parser = parse_age)]
+    older_than: Option<Duration>,
+
+    /// Show what would be removed without removing anything.
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+}
+
+impl Prune {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        ensure!(
+            self.keep_last.is_some() || self.older_than.is_some(),
+            "at least one of --keep-last or --older-than is required"
+        );
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let build_dir = project.project_dir().join("build");
+        let policy = Policy {
+            keep_last: self.keep_last,
+            older_than: self.older_than,
+        };
+        let now = SystemTime::now();
+
+        let mut paths = Vec::new();
+        for images_dir in subdirs(&build_dir.join("images")).await? {
+            paths.extend(variant_images_to_prune(&images_dir, policy, now).await?);
+        }
+        let rpms = Policy {
+            keep_last: None,
+            older_than: self.older_than,
+        };
+        if rpms.older_than.is_some() {
+            let packages = subdirs(&build_dir.join("rpms")).await?;
+            paths.extend(rpms.select(now, newest_modified_times(packages).await?));
+        }
+        paths.extend(
+            OciStore::new(project.oci_cache_dir())
+                .unreferenced()
+                .await?,
+        );
+
+        let mut freed = 0;
+        for path in &paths {
+            let is_dir = path.is_dir();
+            freed += if is_dir {
+                dir_size(path.clone()).await?
+            } else {
+                fs::metadata(path).await?.len()
+            };
+            if self.dry_run {
+                info!("Would remove '{}'", path.display());
+            } else if is_dir {
+                info!("Removing '{}'", path.display());
+                fs::remove_dir_all(path).await?;
+            } else {
+                info!("Removing '{}'", path.display());
+                fs::remove_file(path).await?;
+            }
+        }
+
+        let cli = project.tools().container_runtime_cli();
+        let images = policy.select(now, buildsys_images(cli).await?);
+        for image in &images {
+            if self.dry_run {
+                info!("Would remove image '{image}'");
+                continue;
+            }
+            info!("Removing image '{image}'");
+            // An image that a running build still uses can't be removed, which is fine.
+            if let Err(e) = exec(Command::new(cli).args(["rmi", image]), true).await {
+                warn!("Unable to remove image '{image}': {e}");
+            }
+        }
+
+        info!(
+            "{} {} of build outputs and {} buildsys images",
+            if self.dry_run { "Would free" } else { "Freed" },
+            gib(freed),
+            images.len()
+        );
+        Ok(())
+    }
+}
+
+/// Which build outputs to remove. When both limits are given, only outputs outside the most recent
+/// `keep_last` that are also older than `older_than` are removed.
+#[derive(Debug, Copy, Clone)]
+struct Policy {
+    keep_last: Option<usize>,
+    older_than: Option<Duration>,
+}
+
+impl Policy {
+    /// Selects the outputs to remove from `outputs`, given with the time each was last modified.
+    fn select<T>(&self, now: SystemTime, mut outputs: Vec<(T, SystemTime)>) -> Vec<T> {
+        outputs.sort_by(|(_, a), (_, b)| b.cmp(a));
+        outputs
+            .into_iter()
+            .enumerate()
+            .filter(|(position, (_, modified))| {
+                self.is_beyond_last(*position) && self.is_old(now, *modified)
+            })
+            .map(|(_, (output, _))| output)
+            .collect()
+    }
+
+    fn is_beyond_last(&self, position: usize) -> bool {
+        match self.keep_last {
+            Some(keep_last) => position >= keep_last,
+            None => true,
+        }
+    }
+
+    fn is_old(&self, now: SystemTime, modified: SystemTime) -> bool {
+        let Some(older_than) = self.older_than else {
+            return true;
+        };
+        now.duration_since(modified)
+            .is_ok_and(|age| age >= older_than)
+    }
+}
+
+/// Selects the image directories of a variant under `images_dir` to remove. The images `latest`
+/// points to are always kept, and count toward `keep_last`.
+async fn variant_images_to_prune(
+    images_dir: &Path,
+    policy: Policy,
+    now: SystemTime,
+) -> Result<Vec<PathBuf>> {
+    // `latest` is a symlink, so it is not among the subdirectories.
+    let latest = tokio::fs::read_link(images_dir.join("latest")).await.ok();
+    let (kept, versions): (Vec<PathBuf>, Vec<PathBuf>) =
+        subdirs(images_dir).await?.into_iter().partition(|version| {
+            latest
+                .as_ref()
+                .is_some_and(|latest| latest.file_name() == version.file_name())
+        });
+    let policy = Policy {
+        keep_last: policy
+            .keep_last
+            .map(|keep_last| keep_last.saturating_sub(kept.len())),
+        ..policy
+    };
+    Ok(policy.select(now, modified_times(versions).await?))
+}
+
+/// Parses an age such as "30m", "12h", "7d" or "2w".
+fn parse_age(age: &str) -> Result<Duration> {
+    let unit = match age.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => bail!("'{age}' must end with a unit, one of s, m, h, d or w"),
+    };
+    let count: u64 = age[..age.len() - 1]
+        .parse()
+        .context(format!("'{age}' must be a whole number followed by a unit"))?;
+    Ok(Duration::from_secs(count.saturating_mul(unit)))
+}
+
+/// The directories directly under `dir`, which may not exist.
+async fn subdirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut subdirs = Vec::new();
+    if !dir.is_dir() {
+        return Ok(subdirs);
+    }
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .context(format!("Unable to read '{}'", dir.display()))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context(format!("Unable to read '{}'", dir.display()))?
+    {
+        if entry.file_type().await.is_ok_and(|kind| kind.is_dir()) {
+            subdirs.push(entry.path());
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Pairs each of `dirs` with the time it was last modified.
+async fn modified_times(
+    dirs: impl IntoIterator<Item = PathBuf>,
+) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut times = Vec::new();
+    for dir in dirs {
+        let modified = fs::metadata(&dir)
+            .await?
+            .modified()
+            .context(format!("Unable to read the age of '{}'", dir.display()))?;
+        times.push((dir, modified));
+    }
+    Ok(times)
+}
+
+/// Pairs each of `dirs` with the time the newest file in it was last modified, since copying a
+/// build's RPMs into a package's directory does not always modify the directory itself.
+async fn newest_modified_times(dirs: Vec<PathBuf>) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut times = Vec::new();
+    for (dir, dir_modified) in modified_times(dirs).await? {
+        let mut newest = dir_modified;
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .context(format!("Unable to read '{}'", dir.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("Unable to read '{}'", dir.display()))?
+        {
+            if let Ok(modified) = fs::metadata(entry.path()).await?.modified() {
+                newest = newest.max(modified);
+            }
+        }
+        times.push((dir, newest));
+    }
+    Ok(times)
+}
+
+/// The images buildsys left behind, with the time each was created.
+async fn buildsys_images(cli: &Path) -> Result<Vec<(String, SystemTime)>> {
+    let output = exec(
+        Command::new(cli).args([
+            "image",
+            "ls",
+            "--filter",
+            &format!("reference={BUILDSYS_IMAGES}"),
+            "--format",
+            "{{.Repository}}:{{.Tag}}\t{{.CreatedAt}}",
+        ]),
+        true,
+    )
+    .await
+    .context("Unable to list the images buildsys left behind")?
+    .unwrap_or_default();
+    Ok(output.lines().filter_map(parse_image).collect())
+}
+
+/// Parses an image and its creation time, such as "2024-05-01 10:00:00 +0000 UTC", from a line of
+/// `docker image ls` output.
+fn parse_image(line: &str) -> Option<(String, SystemTime)> {
+    let (image, created) = line.split_once('\t')?;
+    // The time zone's name follows its offset, and can't be parsed.
+    let created = created
+        .split_whitespace()
+        .take(3)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let created = DateTime::<FixedOffset>::parse_from_str(&created, "%Y-%m-%d %H:%M:%S %z").ok()?;
+    Some((image.to_string(), created.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn selects_outputs_to_prune() {
+        let now = SystemTime::now();
+        let outputs = || {
+            vec![
+                ("1.0.0", now - 10 * DAY),
+                ("1.2.0", now - DAY),
+                ("1.1.0", now - 5 * DAY),
+            ]
+        };
+        let keep_last = Policy {
+            keep_last: Some(1),
+            older_than: None,
+        };
+        assert_eq!(keep_last.select(now, outputs()), vec!["1.1.0", "1.0.0"]);
+        let older_than = Policy {
+            keep_last: None,
+            older_than: Some(2 * DAY),
+        };
+        assert_eq!(older_than.select(now, outputs()), vec!["1.1.0", "1.0.0"]);
+        let both = Policy {
+            keep_last: Some(2),
+            older_than: Some(2 * DAY),
+        };
+        assert_eq!(both.select(now, outputs()), vec!["1.0.0"]);
+    }
+
+    #[tokio::test]
+    async fn keep_last_counts_latest() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let images_dir = temp_dir.path();
+        for version in ["1.0.0", "1.1.0", "1.2.0"] {
+            std::fs::create_dir(images_dir.join(version)).unwrap();
+        }
+        std::os::unix::fs::symlink("1.2.0", images_dir.join("latest")).unwrap();
+        let keep_last = |keep_last| Policy {
+            keep_last: Some(keep_last),
+            older_than: None,
+        };
+        let now = SystemTime::now();
+
+        let mut pruned = variant_images_to_prune(images_dir, keep_last(1), now)
+            .await
+            .unwrap();
+        pruned.sort();
+        assert_eq!(
+            pruned,
+            vec![images_dir.join("1.0.0"), images_dir.join("1.1.0")]
+        );
+        assert_eq!(
+            variant_images_to_prune(images_dir, keep_last(3), now)
+                .await
+                .unwrap(),
+            Vec::<PathBuf>::new()
+        );
+        // The latest images are kept even when no others are.
+        assert_eq!(
+            variant_images_to_prune(images_dir, keep_last(0), now)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_age("7d").unwrap(), 7 * DAY);
+        assert_eq!(parse_age("2w").unwrap(), 14 * DAY);
+        assert!(parse_age("7").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("").is_err());
+    }
+
+    #[test]
+    fn test_parse_image() {
+        let (image, created) = parse_image(
+            "buildsys-pkg-kernel-x86_64-0123456789ab:latest\t2024-05-01 10:00:00 +0000 UTC",
+        )
+        .unwrap();
+        assert_eq!(image, "buildsys-pkg-kernel-x86_64-0123456789ab:latest");
+        assert_eq!(
+            created,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1714557600)
+        );
+        assert_eq!(parse_image("buildsys-pkg-kernel:latest\tyesterday"), None);
+    }
+}
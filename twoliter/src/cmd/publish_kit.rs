@@ -1,21 +1,38 @@
 use crate::cargo_make::CargoMake;
+use crate::cmd::promote_kit::PromoteKit;
+use crate::cmd::publish_repo::PublishRepo;
+use crate::cmd::publish_variant::PublishVariant;
+use crate::common::fs;
+use crate::local_registry;
+use crate::local_sdk;
 use crate::lock::Lock;
-use crate::project;
+use crate::project::{self, Hook, Project};
+use crate::sbom::{Document, SbomFormat, Scope};
 use crate::tools::install_tools;
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
+use chrono::Utc;
 use clap::Parser;
-use std::path::PathBuf;
+use oci_cli_wrapper::ReferrerKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tempfile::TempDir;
 
 /// Group all publish commands
 #[derive(Debug, Parser)]
 pub(crate) enum PublishCommand {
     Kit(PublishKit),
+    Promote(PromoteKit),
+    Variant(PublishVariant),
+    Repo(PublishRepo),
 }
 
 impl PublishCommand {
-    pub(crate) async fn run(self) -> Result<()> {
+    pub(crate) async fn run(self, strict: bool) -> Result<()> {
         match self {
-            PublishCommand::Kit(command) => command.run().await,
+            PublishCommand::Kit(command) => command.run(strict).await,
+            PublishCommand::Promote(command) => command.run(strict).await,
+            PublishCommand::Variant(command) => command.run(strict).await,
+            PublishCommand::Repo(command) => command.run(strict).await,
         }
     }
 }
@@ -32,24 +49,262 @@ pub(crate) struct PublishKit {
 
     /// Vendor to publish to
     vendor: String,
+
+    /// Also attach the provenance recorded when the kit was built to the kit's manifest list, as
+    /// an OCI referrer for each platform
+    #[clap(long = "attach-provenance")]
+    attach_provenance: bool,
+
+    /// Also attach an SPDX SBOM of the kit to its manifest list as a signed attestation. The
+    /// vendor must sign its images with cosign in Infra.toml
+    #[clap(long = "attach-sbom")]
+    attach_sbom: bool,
+
+    /// Also attach a file to the kit's manifest list as an OCI referrer, given as KIND=PATH where
+    /// KIND is `sbom`, `provenance` or `test-results`. May be given multiple times. Attached files
+    /// are listed by `twoliter inspect kit`.
+    #[clap(long = "referrer", value_name = "KIND=PATH")]
+    referrers: Vec<String>,
+
+    /// Publish to the local registry started by `twoliter registry start`, on the given port or
+    /// the default one, rather than to the vendor's registry in Infra.toml. The kit is published
+    /// under `localhost:<port>/<vendor>`.
+    #[clap(
+        long = "local-registry",
+        value_name = "PORT",
+        num_args = 0..=1,
+        default_missing_value = "5000",
+        conflicts_with = "registry"
+    )]
+    local_registry: Option<u16>,
+
+    /// Publish to the given registry, such as `example.com/my-namespace`, rather than to the
+    /// vendor's registry in Infra.toml. Each platform's kit image is pushed there, followed by
+    /// the manifest list which ties them together.
+    #[clap(long = "registry", value_name = "URI")]
+    registry: Option<String>,
 }
 
 impl PublishKit {
-    pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
         let lock = Lock::load(&project).await?;
         let toolsdir = project.project_dir().join("build/tools");
         install_tools(&toolsdir).await?;
         let makefile_path = toolsdir.join("Makefile.toml");
+        let hook_context = [
+            ("TWOLITER_KIT", self.kit_name.clone()),
+            ("TWOLITER_VENDOR", self.vendor.clone()),
+        ];
+        project.run_hook(Hook::PrePublishKit, &hook_context).await?;
+
+        let mut optional_envs = Vec::new();
+        if self.attach_provenance {
+            optional_envs.push(("PUBLISH_KIT_PROVENANCE", "true".to_string()));
+        }
+
+        // The directory holding the SBOM is kept until the kit is published.
+        let sbom = if self.attach_sbom {
+            Some(self.write_sbom(&project).await?)
+        } else {
+            None
+        };
+        if let Some((_, sbom)) = &sbom {
+            optional_envs.push(("PUBLISH_KIT_SBOM", sbom.display().to_string()));
+        }
+
+        if !self.referrers.is_empty() {
+            optional_envs.push(("PUBLISH_KIT_REFERRERS", self.referrers_env()?));
+        }
+
+        // The directory holding the generated Infra.toml is kept until the kit is published.
+        let target_infra = match (self.local_registry, &self.registry) {
+            (Some(port), _) => Some(self.local_infra_config(&project, port).await?),
+            (None, Some(registry)) => Some(self.infra_config(&project, registry).await?),
+            (None, None) => None,
+        };
+        if let Some((_, infra_toml)) = &target_infra {
+            optional_envs.push((
+                "PUBLISH_INFRA_CONFIG_PATH",
+                infra_toml.display().to_string(),
+            ));
+        }
 
-        CargoMake::new(&lock.sdk.source)?
+        CargoMake::new(&local_sdk::host_sdk(&project, &lock).await?)?
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
             .env("BUILDSYS_KIT", &self.kit_name)
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
             .env("PUBLISH_VENDOR", &self.vendor)
             .makefile(makefile_path)
             .project_dir(project.project_dir())
+            .envs(project.cargo_make_env(false)?.into_iter())
+            .envs(optional_envs.into_iter())
             .exec("publish-kit")
-            .await
+            .await?;
+        project.run_hook(Hook::PostPublishKit, &hook_context).await
+    }
+
+    /// The artifacts to attach as `KIND=PATH` lines, with absolute paths since pubsys runs from
+    /// another directory.
+    fn referrers_env(&self) -> Result<String> {
+        let mut lines = Vec::new();
+        for referrer in &self.referrers {
+            let (kind, path) = referrer.split_once('=').context(format!(
+                "expected an artifact to attach as KIND=PATH, found '{referrer}'"
+            ))?;
+            let kind = ReferrerKind::from_str(kind)?;
+            let path = std::path::absolute(path)
+                .context(format!("Unable to find the absolute path of '{path}'"))?;
+            ensure!(path.is_file(), "'{}' does not exist", path.display());
+            lines.push(format!("{kind}={}", path.display()));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Writes an SPDX SBOM of the kit for pubsys to attach to the published kit.
+    async fn write_sbom(&self, project: &Project) -> Result<(TempDir, PathBuf)> {
+        let sbom = Document::collect(
+            project,
+            Scope::Kit {
+                name: self.kit_name.clone(),
+            },
+        )
+        .await?
+        .render(SbomFormat::Spdx, Utc::now())?;
+        let dir = TempDir::new().context("Unable to create a tempdir for the SBOM")?;
+        let path = dir.path().join(SbomFormat::Spdx.file_name());
+        fs::write(&path, sbom).await?;
+        Ok((dir, path))
+    }
+
+    /// Writes an Infra.toml which publishes the vendor's kits to the local registry on `port`.
+    async fn local_infra_config(&self, project: &Project, port: u16) -> Result<(TempDir, PathBuf)> {
+        let cli = project.tools().container_runtime_cli();
+        ensure!(
+            local_registry::is_running(cli, port).await?,
+            "the local registry is not running at 'localhost:{port}', start it with \
+            `twoliter registry start --port {port}`"
+        );
+        self.infra_config(
+            project,
+            &local_registry::vendor_registry(port, &self.vendor),
+        )
+        .await
+    }
+
+    /// Writes an Infra.toml which publishes the vendor's kits to `registry`.
+    async fn infra_config(&self, project: &Project, registry: &str) -> Result<(TempDir, PathBuf)> {
+        let dir = TempDir::new().context("Unable to create a tempdir for Infra.toml")?;
+        let infra_toml = write_infra_config(
+            dir.path(),
+            &project.project_dir().join("Infra.toml"),
+            &self.vendor,
+            registry,
+        )
+        .await?;
+        Ok((dir, infra_toml))
+    }
+}
+
+/// Writes an `Infra.toml` to `dir` which has `vendor` publish to `registry`, for publishing a kit
+/// somewhere other than the registry in the project's own `Infra.toml`. The vendor's other
+/// settings, such as how its images are signed, are carried over from the project's.
+async fn write_infra_config(
+    dir: &Path,
+    infra_toml: &Path,
+    vendor: &str,
+    registry: &str,
+) -> Result<PathBuf> {
+    let mut settings = vendor_settings(infra_toml, vendor)
+        .await?
+        .unwrap_or_default();
+    settings.insert(
+        "registry".to_string(),
+        toml::Value::String(registry.trim_end_matches('/').to_string()),
+    );
+    let vendors = toml::Table::from_iter([(vendor.to_string(), toml::Value::Table(settings))]);
+    let config = toml::Table::from_iter([("vendor".to_string(), toml::Value::Table(vendors))]);
+    let path = dir.join("Infra.toml");
+    fs::write(&path, config.to_string()).await?;
+    Ok(path)
+}
+
+/// The settings of `vendor` in the project's `Infra.lock`, or `infra_toml` when there is no lock,
+/// which is the file pubsys reads them from.
+pub(super) async fn vendor_settings(
+    infra_toml: &Path,
+    vendor: &str,
+) -> Result<Option<toml::Table>> {
+    let Some(source) = [infra_toml.with_file_name("Infra.lock"), infra_toml.into()]
+        .into_iter()
+        .find(|path| path.is_file())
+    else {
+        return Ok(None);
+    };
+    let config: toml::Table = toml::from_str(&fs::read_to_string(&source).await?)
+        .context(format!("Unable to parse '{}'", source.display()))?;
+    Ok(config
+        .get("vendor")
+        .and_then(|vendors| vendors.get(vendor))
+        .and_then(toml::Value::as_table)
+        .cloned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_infra_config_for_vendor() {
+        let temp_dir = TempDir::new().unwrap();
+        let infra_toml = temp_dir.path().join("project/Infra.toml");
+        let path = write_infra_config(
+            temp_dir.path(),
+            &infra_toml,
+            "my-vendor",
+            "example.com/kits/",
+        )
+        .await
+        .unwrap();
+        let config: toml::Table = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            config["vendor"]["my-vendor"]["registry"].as_str(),
+            Some("example.com/kits")
+        );
+
+        // The vendor's signing settings are kept, so that a kit published elsewhere is still
+        // signed.
+        std::fs::create_dir(infra_toml.parent().unwrap()).unwrap();
+        std::fs::write(
+            &infra_toml,
+            r#"
+            [vendor.my-vendor]
+            registry = "registry.example.com/kits"
+            signing = { key = { kms = { key_arn = "arn:aws:kms:key" } } }
+
+            [vendor.other-vendor]
+            registry = "other.example.com"
+            "#,
+        )
+        .unwrap();
+        let path = write_infra_config(
+            temp_dir.path(),
+            &infra_toml,
+            "my-vendor",
+            "localhost:5000/my-vendor",
+        )
+        .await
+        .unwrap();
+        let config: toml::Table = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let vendors = config["vendor"].as_table().unwrap();
+        assert_eq!(vendors.len(), 1);
+        assert_eq!(
+            vendors["my-vendor"]["registry"].as_str(),
+            Some("localhost:5000/my-vendor")
+        );
+        assert_eq!(
+            vendors["my-vendor"]["signing"]["key"]["kms"]["key_arn"].as_str(),
+            Some("arn:aws:kms:key")
+        );
     }
 }
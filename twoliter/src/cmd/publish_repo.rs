@@ -0,0 +1,68 @@
+use crate::cargo_make::CargoMake;
+use crate::local_sdk;
+use crate::lock::Lock;
+use crate::project;
+use crate::tools::install_tools;
+use anyhow::Result;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Add a built variant to a TUF update repository, as configured by `[publish.repo]` in
+/// Twoliter.toml. The repository's targets, snapshot and timestamp metadata are created or
+/// updated and signed with the repository's keys from Infra.toml
+#[derive(Debug, Parser)]
+pub(crate) struct PublishRepo {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The variant to add to the repository, which needs to have been built
+    variant: String,
+
+    /// The architecture the variant was built for
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// The name of the repository in Infra.toml. Overrides `[publish.repo]` in Twoliter.toml
+    #[clap(long = "repo")]
+    repo: Option<String>,
+
+    /// When the first wave of the update starts, in RFC 3339 format. Defaults to now
+    #[clap(long = "release-start-time")]
+    release_start_time: Option<String>,
+}
+
+impl PublishRepo {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let mut envs = project
+            .publish()
+            .repo
+            .clone()
+            .unwrap_or_default()
+            .env(&project.project_dir());
+        if let Some(repo) = &self.repo {
+            envs.retain(|(key, _)| *key != "PUBLISH_REPO");
+            envs.push(("PUBLISH_REPO", repo.clone()));
+        }
+        if let Some(start) = &self.release_start_time {
+            envs.push(("RELEASE_START_TIME", start.clone()));
+        }
+
+        let lock = Lock::load(&project).await?;
+        let toolsdir = project.project_dir().join("build/tools");
+        install_tools(&toolsdir).await?;
+        let makefile_path = toolsdir.join("Makefile.toml");
+        CargoMake::new(&local_sdk::sdk_for(&project, &lock, &self.arch).await?)?
+            .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
+            .env("BUILDSYS_ARCH", &self.arch)
+            .env("BUILDSYS_VARIANT", &self.variant)
+            .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+            .makefile(makefile_path)
+            .project_dir(project.project_dir())
+            .envs(project.cargo_make_env(false)?.into_iter())
+            .envs(envs.into_iter())
+            .exec("repo")
+            .await
+    }
+}
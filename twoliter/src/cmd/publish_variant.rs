@@ -0,0 +1,71 @@
+use crate::cargo_make::CargoMake;
+use crate::local_sdk;
+use crate::lock::Lock;
+use crate::project;
+use crate::scaffold;
+use crate::tools::install_tools;
+use anyhow::{ensure, Result};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Publish a built variant as configured by `[publish]` in Twoliter.toml: upload its images to S3
+/// with their checksums, and register it as an EC2 AMI in each region
+#[derive(Debug, Parser)]
+pub(crate) struct PublishVariant {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The variant to publish, which needs to have been built
+    variant: String,
+
+    /// The architecture the variant was built for
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// Don't upload the variant's images to S3, even if `[publish.s3]` is configured
+    #[clap(long = "skip-s3")]
+    skip_s3: bool,
+
+    /// Don't register the variant as an AMI, even if `[publish.ami]` is configured
+    #[clap(long = "skip-ami")]
+    skip_ami: bool,
+}
+
+impl PublishVariant {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let publish = project.publish();
+        let mut tasks = Vec::new();
+        if let Some(s3) = publish.s3.as_ref().filter(|_| !self.skip_s3) {
+            tasks.push(("upload-images", s3.env()));
+        }
+        if let Some(ami) = publish.ami.as_ref().filter(|_| !self.skip_ami) {
+            tasks.push(("ami", ami.env()));
+        }
+        ensure!(
+            !tasks.is_empty(),
+            "there is nothing to publish, configure [publish.s3] or [publish.ami] in Twoliter.toml"
+        );
+
+        let lock = Lock::load(&project).await?;
+        let toolsdir = project.project_dir().join("build/tools");
+        install_tools(&toolsdir).await?;
+        let makefile_path = toolsdir.join("Makefile.toml");
+        for (task, envs) in tasks {
+            CargoMake::new(&local_sdk::sdk_for(&project, &lock, &self.arch).await?)?
+                .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
+                .env("BUILDSYS_ARCH", &self.arch)
+                .env("BUILDSYS_VARIANT", &self.variant)
+                .envs(scaffold::variant_crate_env(&project, &self.variant).into_iter())
+                .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+                .makefile(&makefile_path)
+                .project_dir(project.project_dir())
+                .envs(project.cargo_make_env(false)?.into_iter())
+                .envs(envs.into_iter())
+                .exec(task)
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::local_registry::{self, DEFAULT_IMAGE, DEFAULT_PORT};
+use crate::project::{self, Tools};
+use anyhow::Result;
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+/// Run a container registry on this host, so that kits built in one local project can be consumed
+/// by another before they're published.
+#[derive(Debug, Parser)]
+pub(crate) enum RegistryCommand {
+    Start(StartRegistry),
+    Stop(StopRegistry),
+}
+
+impl RegistryCommand {
+    pub(crate) async fn run(self, strict: bool) -> Result<()> {
+        match self {
+            RegistryCommand::Start(command) => command.run(strict).await,
+            RegistryCommand::Stop(command) => command.run(strict).await,
+        }
+    }
+}
+
+/// The tools of the project at `project_path`, or the defaults when no project is given, since the
+/// registry doesn't otherwise need one.
+async fn tools(project_path: Option<&Path>, strict: bool) -> Result<Tools> {
+    Ok(match project_path {
+        Some(path) => project::load_or_find_project(Some(path.to_path_buf()), strict)
+            .await?
+            .tools()
+            .clone(),
+        None => Tools::default(),
+    })
+}
+
+/// Start the local registry. Publish kits to it with `twoliter publish kit --local-registry`, and
+/// consume them from a project with a vendor whose registry is `localhost:<port>/<vendor>`.
+#[derive(Debug, Parser)]
+pub(crate) struct StartRegistry {
+    /// The port on localhost to serve the registry on.
+    #[clap(long = "port", default_value_t = DEFAULT_PORT)]
+    port: u16,
+
+    /// The registry image to run.
+    #[clap(long = "image", default_value = DEFAULT_IMAGE)]
+    image: String,
+
+    /// The project whose container runtime runs the registry. Docker is used when none is given.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+}
+
+impl StartRegistry {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let tools = tools(self.project_path.as_deref(), strict).await?;
+        local_registry::start(tools.container_runtime_cli(), self.port, &self.image).await
+    }
+}
+
+/// Stop the local registry, discarding the kits published to it.
+#[derive(Debug, Parser)]
+pub(crate) struct StopRegistry {
+    /// The port on localhost the registry is served on.
+    #[clap(long = "port", default_value_t = DEFAULT_PORT)]
+    port: u16,
+
+    /// The project whose container runtime runs the registry. Docker is used when none is given.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+}
+
+impl StopRegistry {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let tools = tools(self.project_path.as_deref(), strict).await?;
+        local_registry::stop(tools.container_runtime_cli(), self.port).await
+    }
+}
@@ -0,0 +1,59 @@
+use crate::common::fs;
+use crate::project;
+use crate::sbom::{Document, SbomFormat, Scope};
+use anyhow::Result;
+use chrono::Utc;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Write a software bill of materials for a variant, a kit or the whole project, listing the
+/// packages, kits, source archives, Go modules and crates it is made of.
+#[derive(Debug, Parser)]
+pub(crate) struct Sbom {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// Describe this variant, which needs to have been built to find its dependencies
+    #[clap(long = "variant", conflicts_with = "kit")]
+    pub(crate) variant: Option<String>,
+
+    /// Describe this kit, which needs to have been built to find its dependencies
+    #[clap(long = "kit")]
+    pub(crate) kit: Option<String>,
+
+    /// The architecture the variant was built for
+    #[clap(long = "arch", default_value = "x86_64")]
+    pub(crate) arch: String,
+
+    #[clap(long = "format", value_enum, default_value = "spdx")]
+    pub(crate) format: SbomFormat,
+
+    /// Write the SBOM to this file rather than to stdout
+    #[clap(long = "output")]
+    pub(crate) output: Option<PathBuf>,
+}
+
+impl Sbom {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let scope = match (&self.variant, &self.kit) {
+            (Some(variant), _) => Scope::Variant {
+                name: variant.clone(),
+                arch: self.arch.clone(),
+            },
+            (None, Some(kit)) => Scope::Kit { name: kit.clone() },
+            (None, None) => Scope::Project,
+        };
+        let sbom = Document::collect(&project, scope)
+            .await?
+            .render(self.format, Utc::now())?;
+        match &self.output {
+            Some(path) => fs::write(path, sbom).await,
+            None => {
+                println!("{sbom}");
+                Ok(())
+            }
+        }
+    }
+}
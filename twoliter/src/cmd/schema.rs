@@ -0,0 +1,27 @@
+use crate::common::fs;
+use crate::project::json_schema;
+use anyhow::Result;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Print the JSON schema of Twoliter.toml, so that editors and CI can check the project file
+/// before running a build.
+#[derive(Debug, Parser)]
+pub(crate) struct Schema {
+    /// Write the schema to this file instead of stdout, e.g. `twoliter.schema.json`.
+    #[clap(long)]
+    output: Option<PathBuf>,
+}
+
+impl Schema {
+    pub(super) async fn run(&self) -> Result<()> {
+        let schema = json_schema()?;
+        match &self.output {
+            Some(path) => fs::write(path, format!("{schema}\n")).await,
+            None => {
+                println!("{schema}");
+                Ok(())
+            }
+        }
+    }
+}
@@ -9,12 +9,22 @@ pub(crate) struct Update {
     /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
     #[clap(long = "project-path")]
     pub(crate) project_path: Option<PathBuf>,
+
+    /// Write a JSON report of every registry request made and decision taken while resolving the
+    /// lock file to this path
+    #[clap(long = "report")]
+    pub(crate) report: Option<PathBuf>,
+
+    /// Fail, without updating Twoliter.lock, if any locked kit has been deprecated by its
+    /// publisher or is past its end of support
+    #[clap(long = "deny-deprecated")]
+    pub(crate) deny_deprecated: bool,
 }
 
 impl Update {
-    pub(super) async fn run(&self) -> Result<()> {
-        let project = project::load_or_find_project(self.project_path.clone()).await?;
-        Lock::create(&project).await?;
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        Lock::create_with_report(&project, self.report.as_deref(), self.deny_deprecated).await?;
         Ok(())
     }
 }
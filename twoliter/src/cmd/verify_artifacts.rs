@@ -0,0 +1,49 @@
+use crate::artifact_signing;
+use crate::project::SigningMethod;
+use anyhow::Result;
+use buildsys::checksums::{CHECKSUMS_FILE, SIGNATURE_FILE};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Verify downloaded variant artifacts against the signed `SHA256SUMS` written by the build: the
+/// signature of the manifest is checked with the public key, then every file it lists is checked
+/// against its checksum
+#[derive(Debug, Parser)]
+pub(crate) struct VerifyArtifacts {
+    /// The directory holding the artifacts, `SHA256SUMS` and `SHA256SUMS.sig`
+    dir: PathBuf,
+
+    /// The public key matching the key which signed the manifest
+    #[clap(long = "public-key")]
+    public_key: PathBuf,
+
+    /// The tool which signed the manifest
+    #[clap(long = "method", value_enum)]
+    method: SigningMethod,
+
+    /// Require a cosign signature to be recorded in the Rekor transparency log, for manifests
+    /// signed with `tlog-upload = true`
+    #[clap(long = "tlog")]
+    tlog: bool,
+
+    /// The cosign binary to check cosign signatures with
+    #[clap(long = "cosign", default_value = "cosign")]
+    cosign: PathBuf,
+}
+
+impl VerifyArtifacts {
+    pub(super) async fn run(&self) -> Result<()> {
+        let checked = artifact_signing::verify(
+            &self.dir,
+            self.method,
+            &self.public_key,
+            self.tlog,
+            &self.cosign,
+        )
+        .await?;
+        println!(
+            "Verified {SIGNATURE_FILE} and the checksums of {checked} artifacts in {CHECKSUMS_FILE}"
+        );
+        Ok(())
+    }
+}
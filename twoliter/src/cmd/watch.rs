@@ -0,0 +1,265 @@
+use super::build::BuildPackage;
+use crate::lint::find_manifests;
+use crate::lock::Lock;
+use crate::project;
+use crate::tools::install_tools;
+use anyhow::{bail, Context, Result};
+use buildsys::manifest::ManifestInfo;
+use buildsys::project::ProjectInfo;
+use buildsys::spec::SpecInfo;
+use buildsys::BuildType;
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info, warn};
+
+/// How often the package's files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Rebuild a package whenever its manifest, spec file, sources or patches change.
+#[derive(Debug, Parser)]
+pub(crate) struct Watch {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The architecture to build for.
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// The name of the package to watch, as given in its `Cargo.toml`.
+    #[clap(long = "package")]
+    package: String,
+
+    /// The URL to the lookaside cache where sources are stored to avoid pulling them from upstream.
+    /// Defaults to https://cache.bottlerocket.aws
+    #[clap(long = "lookaside-cache")]
+    lookaside_cache: Option<String>,
+
+    /// If sources are not found in the lookaside cache, this flag will cause buildsys to pull them
+    /// from the upstream URL found in a package's `Cargo.toml`.
+    #[clap(long = "upstream-source-fallback")]
+    upstream_source_fallback: bool,
+
+    /// The build profile to use, as declared by `[profile.<name>]` in Twoliter.toml.
+    #[clap(long = "profile")]
+    profile: Option<String>,
+}
+
+impl Watch {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let lock = Lock::load(&project).await?;
+        let toolsdir = project.project_dir().join("build/tools");
+        install_tools(&toolsdir).await?;
+        let manifest_path = find_package(&project.project_dir(), &self.package).await?;
+        let sources_dir = project.project_dir().join("sources");
+
+        let build = BuildPackage {
+            project_path: self.project_path.clone(),
+            arch: vec![self.arch.clone()],
+            parallel: false,
+            package: self.package.clone(),
+            lookaside_cache: self.lookaside_cache.clone(),
+            upstream_source_fallback: self.upstream_source_fallback,
+            profile: self.profile.clone(),
+            no_checkpoints: false,
+            no_provenance: false,
+            export_build_cache: false,
+        };
+
+        let mut files = vec![manifest_path.clone()];
+        let mut last_error = None;
+        loop {
+            let start = Instant::now();
+            match build
+                .build(&project, &lock, &toolsdir, self.arch.clone())
+                .await
+            {
+                Ok(()) => info!(
+                    "Built package '{}' in {:.1}s",
+                    self.package,
+                    start.elapsed().as_secs_f64()
+                ),
+                Err(e) => error!(
+                    "Failed to build package '{}' after {:.1}s: {e:?}",
+                    self.package,
+                    start.elapsed().as_secs_f64()
+                ),
+            }
+
+            // The snapshot is taken after the build, since the build fetches external files into
+            // the package directory. The files are found again for every build, since the spec
+            // file may have gained sources or patches.
+            refresh_watched_files(&mut files, &mut last_error, &manifest_path, &sources_dir);
+            let snapshot = Snapshot::take(&files);
+            info!("Watching package '{}' for changes", self.package);
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                refresh_watched_files(&mut files, &mut last_error, &manifest_path, &sources_dir);
+                if let Some(changed) = snapshot.changed(&Snapshot::take(&files)) {
+                    info!("'{}' changed, rebuilding", changed.display());
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Finds the `Cargo.toml` of the package whose crate name, or `package-name` override, is
+/// `package`.
+async fn find_package(project_dir: &Path, package: &str) -> Result<PathBuf> {
+    for manifest_path in find_manifests(project_dir).await? {
+        let Ok(manifest) = ManifestInfo::new(&manifest_path) else {
+            continue;
+        };
+        let is_named = manifest.manifest_name() == package || manifest.package_name() == package;
+        if is_named && matches!(manifest.build_type(), Ok(BuildType::Package)) {
+            return Ok(manifest_path);
+        }
+    }
+    bail!("no package named '{package}' was found in the project")
+}
+
+/// Returns the files which buildsys watches for changes when building the package: its manifest,
+/// its spec file along with the sources and patches the spec names, and the files in its
+/// `source-groups`.
+fn watched_files(manifest_path: &Path, sources_dir: &Path) -> Result<Vec<PathBuf>> {
+    let package_dir = manifest_path
+        .parent()
+        .context("package manifest has no parent directory")?;
+    let manifest = ManifestInfo::new(manifest_path)
+        .context(format!("Unable to parse '{}'", manifest_path.display()))?;
+    let mut files = vec![manifest_path.to_path_buf()];
+
+    let spec = package_dir.join(format!("{}.spec", manifest.package_name()));
+    if spec.is_file() {
+        let info = SpecInfo::new(&spec).context(format!("Unable to parse '{}'", spec.display()))?;
+        files.extend(
+            info.sources
+                .iter()
+                .chain(info.patches.iter())
+                .map(|file| package_dir.join(file)),
+        );
+    }
+    files.push(spec);
+
+    if let Some(groups) = manifest.source_groups() {
+        let dirs: Vec<PathBuf> = groups.iter().map(|group| sources_dir.join(group)).collect();
+        let info = ProjectInfo::crawl(&dirs).context("Unable to find the package's sources")?;
+        files.extend(info.files);
+    }
+    Ok(files)
+}
+
+/// Replaces `files` with the files `watched_files` finds now. When they can't be found, e.g. while
+/// the manifest or spec file is half edited, the files found before are kept and watching goes on.
+/// The error is logged once, rather than on every check, until it changes.
+fn refresh_watched_files(
+    files: &mut Vec<PathBuf>,
+    last_error: &mut Option<String>,
+    manifest_path: &Path,
+    sources_dir: &Path,
+) {
+    match watched_files(manifest_path, sources_dir) {
+        Ok(found) => {
+            *files = found;
+            *last_error = None;
+        }
+        Err(e) => {
+            let message = format!("{e:#}");
+            if last_error.as_ref() != Some(&message) {
+                warn!("Unable to find the files to watch, watching those found before: {message}");
+                *last_error = Some(message);
+            }
+        }
+    }
+}
+
+/// The modification times of the watched files, `None` for files which do not exist.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Snapshot(BTreeMap<PathBuf, Option<SystemTime>>);
+
+impl Snapshot {
+    fn take(files: &[PathBuf]) -> Self {
+        Self(
+            files
+                .iter()
+                .map(|file| {
+                    let modified = std::fs::metadata(file).and_then(|m| m.modified()).ok();
+                    (file.clone(), modified)
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns a file which was added, removed or modified between `self` and `other`.
+    fn changed<'a>(&'a self, other: &'a Self) -> Option<&'a Path> {
+        self.0
+            .iter()
+            .find(|(file, modified)| other.0.get(*file) != Some(*modified))
+            .or_else(|| other.0.iter().find(|(file, _)| !self.0.contains_key(*file)))
+            .map(|(file, _)| file.as_path())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn watches_package_files() {
+        let temp_dir = crate::test::copy_project_to_temp_dir("local-kit");
+        let project_dir = temp_dir.path();
+        let manifest_path = find_package(project_dir, "pkg-a-1.27").await.unwrap();
+        assert_eq!(
+            manifest_path,
+            project_dir.join("packages/pkg-a-1.27/Cargo.toml")
+        );
+        assert!(find_package(project_dir, "pkg-z").await.is_err());
+
+        let package_dir = project_dir.join("packages/pkg-b");
+        let files = watched_files(
+            &package_dir.join("Cargo.toml"),
+            &project_dir.join("sources"),
+        )
+        .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                package_dir.join("Cargo.toml"),
+                package_dir.join("pkg-b.txt"),
+                package_dir.join("pkg-b.spec"),
+            ]
+        );
+
+        // Files which can't be found are logged, and those found before are kept.
+        let mut watched = vec![package_dir.join("Cargo.toml")];
+        let mut last_error = None;
+        refresh_watched_files(
+            &mut watched,
+            &mut last_error,
+            &package_dir.join("Cargo.toml"),
+            &project_dir.join("sources"),
+        );
+        assert_eq!(watched, files);
+        assert_eq!(last_error, None);
+        refresh_watched_files(
+            &mut watched,
+            &mut last_error,
+            &package_dir.join("missing.toml"),
+            &project_dir.join("sources"),
+        );
+        assert_eq!(watched, files);
+        assert!(last_error.is_some());
+
+        let before = Snapshot::take(&files);
+        assert_eq!(before.changed(&Snapshot::take(&files)), None);
+        std::fs::remove_file(package_dir.join("pkg-b.txt")).unwrap();
+        assert_eq!(
+            before.changed(&Snapshot::take(&files)),
+            Some(package_dir.join("pkg-b.txt").as_path())
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use crate::lock::Lock;
+use crate::project;
+use anyhow::Result;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Explain why a kit is in Twoliter.lock by showing the chain of kits that require it, back to
+/// Twoliter.toml, along with the sdk each kit was built against.
+#[derive(Debug, Parser)]
+pub(crate) struct Why {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent
+    #[clap(long = "project-path")]
+    pub(crate) project_path: Option<PathBuf>,
+
+    /// The name of the kit to explain
+    pub(crate) kit: String,
+}
+
+impl Why {
+    pub(super) async fn run(&self, strict: bool) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone(), strict).await?;
+        let (lock, graph) = Lock::load_with_graph(&project).await?;
+        print!("{}", graph.why(&self.kit)?);
+        println!("\nsdk: {}", lock.sdk);
+        for (arch, sdk) in lock.sdk_arch.iter() {
+            println!("sdk ({arch}): {sdk}");
+        }
+        Ok(())
+    }
+}
@@ -184,6 +184,32 @@ pub(crate) mod fs {
         ))
     }
 
+    /// Renames the complete directory `from` to `to`, so that `to` is never seen part way through
+    /// being written. A directory already at `to` is first renamed out of the way rather than
+    /// removed in place, so that a process reading it never finds it half removed.
+    #[instrument(
+        level = "trace",
+        skip_all,
+        fields(from = %from.as_ref().display(), to = %to.as_ref().display())
+    )]
+    pub(crate) async fn replace_dir(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let file_name = to.file_name().unwrap_or_default().to_string_lossy();
+        let replaced = to.with_file_name(format!(".replaced-{file_name}-{}", uuid::Uuid::new_v4()));
+        let had_dir = match fs::rename(to, &replaced).await {
+            Ok(()) => true,
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => {
+                return Err(e).context(format!("Unable to move '{}' out of the way", to.display()))
+            }
+        };
+        rename(from, to).await?;
+        if had_dir {
+            remove_dir_all(&replaced).await?;
+        }
+        Ok(())
+    }
+
     #[instrument(level = "trace", skip(path), fields(path = %path.as_ref().display()))]
     pub(crate) async fn remove_file(path: impl AsRef<Path>) -> Result<()> {
         fs::remove_file(path.as_ref()).await.context(format!(
@@ -192,6 +218,25 @@ pub(crate) mod fs {
         ))
     }
 
+    /// Writes `contents` to a temporary file next to `path` and renames it into place, so that
+    /// `path` never holds part of the contents.
+    #[instrument(level = "trace", skip_all, fields(path = %path.as_ref().display()))]
+    pub(crate) async fn write_atomic<P, C>(path: P, contents: C) -> Result<()>
+    where
+        P: AsRef<Path>,
+        C: AsRef<[u8]>,
+    {
+        let path = path.as_ref();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{file_name}.{}", uuid::Uuid::new_v4()));
+        write(&temp, contents).await?;
+        if let Err(e) = rename(&temp, path).await {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
     #[instrument(level = "trace", skip_all, fields(path = %path.as_ref().display()))]
     pub(crate) async fn write<P, C>(path: P, contents: C) -> Result<()>
     where
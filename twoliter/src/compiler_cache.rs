@@ -0,0 +1,82 @@
+//! Reports the compiler cache statistics buildsys records for each package it builds with the
+//! compiler cache selected by `compiler-cache` in the build profile.
+use crate::common::fs;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The compiler cache statistics of a package build.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct CacheStats {
+    pub(crate) package: String,
+    /// The statistics, as the compiler cache printed them
+    pub(crate) stats: String,
+}
+
+impl CacheStats {
+    /// Loads the statistics buildsys recorded in `stats_dir` for packages built since `since`,
+    /// ordered by package name.
+    pub(crate) async fn load_since(stats_dir: &Path, since: SystemTime) -> Result<Vec<Self>> {
+        let mut all_stats = Vec::new();
+        if !stats_dir.is_dir() {
+            return Ok(all_stats);
+        }
+        let mut entries = tokio::fs::read_dir(stats_dir)
+            .await
+            .context(format!("Unable to read '{}'", stats_dir.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("Unable to read '{}'", stats_dir.display()))?
+        {
+            let path = entry.path();
+            let Ok(modified) = fs::metadata(&path).await?.modified() else {
+                continue;
+            };
+            if modified < since {
+                continue;
+            }
+            let Some(package) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            all_stats.push(Self {
+                package: package.to_string(),
+                stats: fs::read_to_string(&path).await?,
+            });
+        }
+        all_stats.sort_by(|a, b| a.package.cmp(&b.package));
+        Ok(all_stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn loads_stats_since_build_start() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let stats_dir = temp_dir.path().join("compiler-cache");
+        let start = SystemTime::now() - Duration::from_secs(60);
+        assert!(CacheStats::load_since(&stats_dir, start)
+            .await
+            .unwrap()
+            .is_empty());
+
+        std::fs::create_dir(&stats_dir).unwrap();
+        std::fs::write(stats_dir.join("kernel.txt"), "Hits: 450 / 900\n").unwrap();
+        assert_eq!(
+            CacheStats::load_since(&stats_dir, start).await.unwrap(),
+            vec![CacheStats {
+                package: "kernel".to_string(),
+                stats: "Hits: 450 / 900\n".to_string(),
+            }]
+        );
+        let later = SystemTime::now() + Duration::from_secs(60);
+        assert!(CacheStats::load_since(&stats_dir, later)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+//! Records which kits and sdk each resolved kit requires, so that we can explain why a kit ends up
+//! in `Twoliter.lock`.
+use crate::project::Image;
+use anyhow::{ensure, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The name shown for requirements which come directly from the project.
+const PROJECT_ROOT: &str = "Twoliter.toml";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Requirements {
+    sdk: Image,
+    kits: Vec<Image>,
+}
+
+/// The requirement edges discovered while resolving a project's kits.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct DependencyGraph {
+    /// Kits required directly by `Twoliter.toml`
+    roots: Vec<Image>,
+    /// The kits and sdk required by each resolved kit
+    kits: BTreeMap<Image, Requirements>,
+}
+
+impl DependencyGraph {
+    pub(crate) fn new(roots: Vec<Image>) -> Self {
+        Self {
+            roots,
+            kits: BTreeMap::new(),
+        }
+    }
+
+    /// Records the sdk and kits required by the resolved kit `image`.
+    pub(crate) fn insert(&mut self, image: Image, sdk: Image, kits: Vec<Image>) {
+        self.kits.insert(image, Requirements { sdk, kits });
+    }
+
+    /// Renders an inverted dependency tree for every resolved kit named `name`, in the style of
+    /// `cargo tree -i`: the kit itself, then each kit that requires it and, recursively, what
+    /// requires those, ending at `Twoliter.toml`.
+    pub(crate) fn why(&self, name: &str) -> Result<String> {
+        let matches: Vec<&Image> = self
+            .kits
+            .keys()
+            .filter(|image| image.name.to_string() == name)
+            .collect();
+        ensure!(
+            !matches.is_empty(),
+            "kit '{name}' is not a dependency of this project (resolved kits: {})",
+            self.kits
+                .keys()
+                .map(|image| image.name.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let mut out = String::new();
+        for (i, image) in matches.into_iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            writeln!(out, "{}{}", image, self.pinned_sdk(image))?;
+            self.write_requirers(&mut out, image, "", &mut vec![image])?;
+        }
+        Ok(out)
+    }
+
+    /// Describes each kit name which is provided by more than one vendor, listing every provider
+    /// along with what requires it. Returns `None` when each kit name has a single vendor.
+    pub(crate) fn name_collisions(&self) -> Option<String> {
+        let mut providers: BTreeMap<String, Vec<&Image>> = BTreeMap::new();
+        for image in self.kits.keys() {
+            let vendors = providers.entry(image.name.to_string()).or_default();
+            if !vendors.iter().any(|other| other.vendor == image.vendor) {
+                vendors.push(image);
+            }
+        }
+        let mut out = String::new();
+        for (name, images) in providers.iter().filter(|(_, images)| images.len() > 1) {
+            let _ = writeln!(out, "kit '{name}' is provided by more than one vendor:");
+            for image in images {
+                let (kits, from_project) = self.requirers(image);
+                let requirers = kits
+                    .iter()
+                    .map(ToString::to_string)
+                    .chain(from_project.then(|| PROJECT_ROOT.to_string()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let _ = writeln!(out, "  {image} (required by {requirers})");
+            }
+        }
+        (!out.is_empty()).then_some(out)
+    }
+
+    /// Returns a suffix naming the sdk that a kit was built against.
+    fn pinned_sdk(&self, image: &Image) -> String {
+        self.kits
+            .get(image)
+            .map(|requirements| format!(" (sdk: {})", requirements.sdk))
+            .unwrap_or_default()
+    }
+
+    /// Returns the kits which require `image`, along with a flag noting whether the project itself
+    /// requires it directly.
+    fn requirers(&self, image: &Image) -> (Vec<&Image>, bool) {
+        let kits = self
+            .kits
+            .iter()
+            .filter(|(_, requirements)| requirements.kits.contains(image))
+            .map(|(kit, _)| kit)
+            .collect();
+        (kits, self.roots.contains(image))
+    }
+
+    fn write_requirers<'a>(
+        &'a self,
+        out: &mut String,
+        image: &Image,
+        indent: &str,
+        path: &mut Vec<&'a Image>,
+    ) -> Result<()> {
+        let (kits, from_project) = self.requirers(image);
+        let constraint = format!("requires {} = {}", image.name, image.version);
+        let count = kits.len() + usize::from(from_project);
+        for (i, kit) in kits.into_iter().enumerate() {
+            let last = i + 1 == count;
+            let (branch, child_indent) = branch(indent, last);
+            if path.contains(&kit) {
+                writeln!(out, "{branch}{kit} ({constraint}) (cycle)")?;
+                continue;
+            }
+            writeln!(out, "{branch}{kit} ({constraint}){}", self.pinned_sdk(kit))?;
+            path.push(kit);
+            self.write_requirers(out, kit, &child_indent, path)?;
+            path.pop();
+        }
+        if from_project {
+            let (branch, _) = branch(indent, true);
+            writeln!(out, "{branch}{PROJECT_ROOT} ({constraint})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the tree branch for an entry and the indentation for its children.
+fn branch(indent: &str, last: bool) -> (String, String) {
+    if last {
+        (format!("{indent}└── "), format!("{indent}    "))
+    } else {
+        (format!("{indent}├── "), format!("{indent}│   "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::ValidIdentifier;
+    use semver::Version;
+
+    fn image(name: &str) -> Image {
+        Image {
+            name: ValidIdentifier(name.to_string()),
+            version: Version::new(1, 0, 0),
+            vendor: ValidIdentifier("my-vendor".to_string()),
+        }
+    }
+
+    fn graph() -> DependencyGraph {
+        let sdk = image("sdk");
+        let mut graph = DependencyGraph::new(vec![image("extra-1-kit"), image("extra-3-kit")]);
+        graph.insert(image("core-kit"), sdk.clone(), Vec::new());
+        graph.insert(image("extra-1-kit"), sdk.clone(), vec![image("core-kit")]);
+        graph.insert(
+            image("extra-3-kit"),
+            sdk,
+            vec![image("extra-1-kit"), image("core-kit")],
+        );
+        graph
+    }
+
+    #[test]
+    fn why_renders_inverted_tree() {
+        let expected = "\
+core-kit-1.0.0@my-vendor (sdk: sdk-1.0.0@my-vendor)
+├── extra-1-kit-1.0.0@my-vendor (requires core-kit = 1.0.0) (sdk: sdk-1.0.0@my-vendor)
+│   ├── extra-3-kit-1.0.0@my-vendor (requires extra-1-kit = 1.0.0) (sdk: sdk-1.0.0@my-vendor)
+│   │   └── Twoliter.toml (requires extra-3-kit = 1.0.0)
+│   └── Twoliter.toml (requires extra-1-kit = 1.0.0)
+└── extra-3-kit-1.0.0@my-vendor (requires core-kit = 1.0.0) (sdk: sdk-1.0.0@my-vendor)
+    └── Twoliter.toml (requires extra-3-kit = 1.0.0)
+";
+        assert_eq!(graph().why("core-kit").unwrap(), expected);
+    }
+
+    #[test]
+    fn why_unknown_kit_fails() {
+        assert!(graph().why("missing-kit").is_err());
+    }
+
+    #[test]
+    fn name_collisions_lists_providers() {
+        let mut graph = graph();
+        assert_eq!(graph.name_collisions(), None);
+
+        let other_core = Image {
+            vendor: ValidIdentifier("other-vendor".to_string()),
+            ..image("core-kit")
+        };
+        graph.insert(other_core.clone(), image("sdk"), Vec::new());
+        graph.insert(image("another-kit"), image("sdk"), vec![other_core]);
+        let expected = "\
+kit 'core-kit' is provided by more than one vendor:
+  core-kit-1.0.0@my-vendor (required by extra-1-kit-1.0.0@my-vendor, extra-3-kit-1.0.0@my-vendor)
+  core-kit-1.0.0@other-vendor (required by another-kit-1.0.0@my-vendor)
+";
+        assert_eq!(graph.name_collisions().unwrap(), expected);
+    }
+}
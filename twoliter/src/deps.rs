@@ -0,0 +1,392 @@
+//! Bundles every image referenced by `Twoliter.lock` into a single portable tarball, and imports
+//! such a bundle into the local image cache, so that a project can be fetched on a machine without
+//! network access.
+//!
+//! The bundle holds each image as a self-contained OCI image layout, whose blobs are moved into the
+//! cache's shared blob store when it is imported:
+//!
+//! ```text
+//! Twoliter.lock              the lock file the bundle was exported for
+//! manifests/<digest>.json    the manifest list of each locked image
+//! oci/<image-digest>/        the OCI image layout of each image, for every architecture
+//! ```
+use crate::common::exec;
+use crate::common::fs::{create_dir_all, read_to_string, remove_dir_all, rename};
+use crate::local_sdk;
+use crate::lock::{Lock, LockedImage, OCIArchive};
+use crate::oci_store::OciStore;
+use crate::progress::Progress;
+use crate::project::Project;
+use anyhow::{ensure, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use oci_cli_wrapper::{DockerArchitecture, ImageTool};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tar::{Archive as TarArchive, Builder as TarBuilder};
+use tempfile::TempDir;
+use tokio::process::Command;
+use tracing::{debug, info, instrument};
+
+const LOCK_ENTRY: &str = "Twoliter.lock";
+const MANIFESTS_DIR: &str = "manifests";
+const OCI_DIR: &str = "oci";
+
+/// The variable which selects the tool that runs image builds.
+const BUILD_BACKEND_VAR: &str = "BUILDSYS_BUILD_BACKEND";
+
+/// Writes a bundle containing the sdk and every kit in `lock`, for all architectures, to `output`.
+/// Images which are not yet in the local cache are pulled first.
+#[instrument(level = "trace", skip(project, lock, progress))]
+pub(crate) async fn export(
+    project: &Project,
+    lock: &Lock,
+    output: &Path,
+    progress: &Progress,
+) -> Result<()> {
+    let image_tool = project.image_tool()?;
+    let cache_dir = project.oci_cache_dir();
+    create_dir_all(&cache_dir).await?;
+
+    // Pairs of (path on disk, path in bundle)
+    let mut entries: Vec<(PathBuf, PathBuf)> = vec![(
+        project.project_dir().join(LOCK_ENTRY),
+        PathBuf::from(LOCK_ENTRY),
+    )];
+    for image in locked_images(lock) {
+        info!("Collecting '{}' for export", image);
+        let manifest_list = Lock::manifest_list(&image_tool, image, &cache_dir).await?;
+        let manifest_path = image.manifest_cache_path(&cache_dir)?;
+        entries.push((
+            manifest_path.clone(),
+            Path::new(MANIFESTS_DIR).join(file_name(&manifest_path)?),
+        ));
+        for manifest in manifest_list.manifests {
+            let archive = OCIArchive::new(image, manifest.digest.as_str(), &cache_dir)?;
+            archive.pull_image(&image_tool, progress).await?;
+            let layout_dir = Path::new(OCI_DIR).join(file_name(&archive.layout_path())?);
+            for (source, dest) in archive.layout_entries().await? {
+                entries.push((source, layout_dir.join(dest)));
+            }
+        }
+    }
+
+    info!("Writing dependency bundle to '{}'", output.display());
+    let spinner = progress.spinner("bundle", "writing dependency bundle");
+    let output = output.to_path_buf();
+    let written = tokio::task::spawn_blocking(move || write_bundle(&output, &entries))
+        .await
+        .context("dependency bundle task panicked")
+        .and_then(|written| written);
+    match written {
+        Ok(()) => spinner.finish_with_message("wrote dependency bundle"),
+        Err(_) => spinner.abandon_with_message("failed to write dependency bundle"),
+    }
+    written
+}
+
+fn write_bundle(output: &Path, entries: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let file = File::create(output).context(format!(
+        "failed to create dependency bundle '{}'",
+        output.display()
+    ))?;
+    let mut builder = TarBuilder::new(GzEncoder::new(file, Compression::default()));
+    for (source, dest) in entries {
+        debug!(
+            "Adding '{}' to bundle as '{}'",
+            source.display(),
+            dest.display()
+        );
+        if source.is_dir() {
+            builder.append_dir_all(dest, source)
+        } else {
+            builder.append_path_with_name(source, dest)
+        }
+        .context(format!("failed to add '{}' to bundle", source.display()))?;
+    }
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .context(format!(
+            "failed to finish dependency bundle '{}'",
+            output.display()
+        ))?;
+    Ok(())
+}
+
+/// Unpacks a bundle created by [`export`] into the local image cache. The bundle must have been
+/// exported for the same `Twoliter.lock` as the project. When `sdk_arch` is given, the sdk image
+/// for that architecture is also loaded into the local container runtime.
+#[instrument(level = "trace", skip(project, progress))]
+pub(crate) async fn import(
+    project: &Project,
+    bundle: &Path,
+    sdk_arch: Option<&str>,
+    progress: &Progress,
+) -> Result<()> {
+    let lock = Lock::read_lock_file(project).await?;
+    let cache_dir = project.oci_cache_dir();
+    create_dir_all(&cache_dir).await?;
+    let staging = TempDir::new_in(&cache_dir).context(format!(
+        "failed to create staging directory in '{}'",
+        cache_dir.display()
+    ))?;
+
+    info!("Unpacking dependency bundle '{}'", bundle.display());
+    let spinner = progress.spinner("bundle", "unpacking dependency bundle");
+    let (bundle_path, staging_path) = (bundle.to_path_buf(), staging.path().to_path_buf());
+    let unpacked = tokio::task::spawn_blocking(move || -> Result<()> {
+        let file = File::open(&bundle_path).context(format!(
+            "failed to open dependency bundle '{}'",
+            bundle_path.display()
+        ))?;
+        TarArchive::new(GzDecoder::new(file))
+            .unpack(&staging_path)
+            .context(format!(
+                "failed to unpack dependency bundle '{}'",
+                bundle_path.display()
+            ))
+    })
+    .await
+    .context("dependency bundle task panicked")
+    .and_then(|unpacked| unpacked);
+    match unpacked {
+        Ok(()) => spinner.finish_with_message("unpacked dependency bundle"),
+        Err(_) => spinner.abandon_with_message("failed to unpack dependency bundle"),
+    }
+    unpacked?;
+
+    let bundled_lock: Lock = toml::from_str(
+        read_to_string(staging.path().join(LOCK_ENTRY))
+            .await?
+            .as_str(),
+    )
+    .context("failed to deserialize the lock file in the dependency bundle")?;
+    ensure!(
+        bundled_lock == lock,
+        "the dependency bundle was exported for a different Twoliter.lock than this project's"
+    );
+
+    move_entries(
+        &staging.path().join(MANIFESTS_DIR),
+        &cache_dir.join(MANIFESTS_DIR),
+    )
+    .await?;
+    import_images(&staging.path().join(OCI_DIR), &OciStore::new(&cache_dir)).await?;
+
+    if let Some(arch) = sdk_arch {
+        let spinner = progress.spinner("sdk", "loading sdk image");
+        let sdk = lock.sdk_for(arch);
+        // buildkitd can't see images loaded into the container runtime, so builds with buildkit
+        // take the SDK from a layout instead.
+        let layout = (std::env::var(BUILD_BACKEND_VAR).as_deref() == Ok("buildkit"))
+            .then(|| local_sdk::locked_layout_path(project, sdk, arch));
+        let loaded = load_sdk(
+            &project.image_tool()?,
+            project.tools().container_runtime_cli(),
+            sdk,
+            &cache_dir,
+            arch,
+            layout.as_deref(),
+        )
+        .await;
+        match loaded {
+            Ok(()) => spinner.finish_with_message("loaded sdk image"),
+            Err(_) => spinner.abandon_with_message("failed to load sdk image"),
+        }
+        loaded?;
+    }
+    Ok(())
+}
+
+/// Moves each entry of `from` into `to`, keeping any entry that already exists in `to`.
+async fn move_entries(from: &Path, to: &Path) -> Result<()> {
+    create_dir_all(to).await?;
+    let mut entries = tokio::fs::read_dir(from)
+        .await
+        .context(format!("failed to read '{}'", from.display()))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context(format!("failed to read entry in '{}'", from.display()))?
+    {
+        let dest = to.join(entry.file_name());
+        if dest.exists() {
+            debug!("'{}' is already present in the cache", dest.display());
+            continue;
+        }
+        rename(entry.path(), &dest).await?;
+    }
+    Ok(())
+}
+
+/// Adds each image layout in `from`, which is named by its manifest digest, to `store`.
+async fn import_images(from: &Path, store: &OciStore) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(from)
+        .await
+        .context(format!("failed to read '{}'", from.display()))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context(format!("failed to read entry in '{}'", from.display()))?
+    {
+        let digest = entry.file_name().to_string_lossy().replacen('-', ":", 1);
+        if store.has_image(&digest) {
+            debug!("Image '{}' is already present in the cache", digest);
+            continue;
+        }
+        store.commit(&entry.path(), &digest).await?;
+    }
+    Ok(())
+}
+
+/// Loads the sdk image for `arch` from the cache into the local container runtime and tags it with
+/// the locked source, so that builds do not need to pull it. When `layout` is given, the image is
+/// written there as an OCI image layout instead, and the container runtime isn't used.
+async fn load_sdk(
+    image_tool: &ImageTool,
+    cli: &Path,
+    sdk: &LockedImage,
+    cache_dir: &Path,
+    arch: &str,
+    layout: Option<&Path>,
+) -> Result<()> {
+    let docker_arch = DockerArchitecture::try_from(arch)?;
+    let manifest = Lock::manifest_list(image_tool, sdk, cache_dir)
+        .await?
+        .manifests
+        .into_iter()
+        .find(|manifest| manifest.architecture() == Some(&docker_arch))
+        .context(format!(
+            "the dependency bundle has no sdk image for architecture '{arch}'"
+        ))?;
+    let archive = OCIArchive::new(sdk, manifest.digest.as_str(), cache_dir)?;
+
+    if let Some(layout) = layout {
+        info!("Writing sdk '{}' to '{}'", sdk, layout.display());
+        return write_layout(archive.layout_entries().await?, layout).await;
+    }
+
+    info!("Loading sdk '{}' into {}", sdk, cli.display());
+    let tarball = TempDir::new_in(cache_dir).context("failed to create temporary directory")?;
+    let tarball_path = tarball.path().join("sdk.tar");
+    let (entries, tar_path) = (archive.layout_entries().await?, tarball_path.clone());
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut builder =
+            TarBuilder::new(File::create(&tar_path).context("failed to create sdk image tarball")?);
+        entries
+            .iter()
+            .try_for_each(|(source, dest)| {
+                if source.is_dir() {
+                    builder.append_dir_all(dest, source)
+                } else {
+                    builder.append_path_with_name(source, dest)
+                }
+            })
+            .and_then(|_| builder.finish())
+            .context("failed to write sdk image tarball")
+    })
+    .await
+    .context("sdk tarball task panicked")??;
+
+    let output = exec(
+        Command::new(cli)
+            .arg("load")
+            .arg(format!("--input={}", tarball_path.display())),
+        true,
+    )
+    .await?
+    .unwrap_or_default();
+    let loaded = loaded_image(&output).context(format!(
+        "unable to determine the image loaded by '{}' from its output: {output}",
+        cli.display()
+    ))?;
+    exec(
+        Command::new(cli)
+            .arg("tag")
+            .arg(loaded)
+            .arg(sdk.source.as_str()),
+        true,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Copies the `entries` of an image layout to a fresh layout directory at `layout`.
+async fn write_layout(entries: Vec<(PathBuf, PathBuf)>, layout: &Path) -> Result<()> {
+    if layout.exists() {
+        remove_dir_all(layout).await?;
+    }
+    create_dir_all(layout).await?;
+    let layout = layout.to_path_buf();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        for (source, dest) in entries {
+            copy_entry(&source, &layout.join(dest))?;
+        }
+        Ok(())
+    })
+    .await
+    .context("sdk layout task panicked")?
+}
+
+fn copy_entry(source: &Path, dest: &Path) -> Result<()> {
+    if source.is_dir() {
+        std::fs::create_dir_all(dest).context(format!("failed to create '{}'", dest.display()))?;
+        for entry in
+            std::fs::read_dir(source).context(format!("failed to read '{}'", source.display()))?
+        {
+            let entry = entry.context(format!("failed to read entry in '{}'", source.display()))?;
+            copy_entry(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .context(format!("failed to create '{}'", parent.display()))?;
+    }
+    std::fs::copy(source, dest).context(format!(
+        "failed to copy '{}' to '{}'",
+        source.display(),
+        dest.display()
+    ))?;
+    Ok(())
+}
+
+/// Parses the output of `docker load` for the id or name of the image that was loaded.
+fn loaded_image(output: &str) -> Option<&str> {
+    output.lines().find_map(|line| {
+        line.strip_prefix("Loaded image ID: ")
+            .or_else(|| line.strip_prefix("Loaded image: "))
+            .map(str::trim)
+    })
+}
+
+fn locked_images(lock: &Lock) -> impl Iterator<Item = &LockedImage> {
+    std::iter::once(&lock.sdk)
+        .chain(lock.sdk_arch.values())
+        .chain(lock.kit.iter())
+}
+
+fn file_name(path: &Path) -> Result<&std::ffi::OsStr> {
+    path.file_name()
+        .context(format!("'{}' has no file name", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loaded_image() {
+        assert_eq!(
+            loaded_image("Loaded image ID: sha256:abc\n"),
+            Some("sha256:abc")
+        );
+        assert_eq!(
+            loaded_image("some progress\nLoaded image: a.com/b/sdk:v1.0.0\n"),
+            Some("a.com/b/sdk:v1.0.0")
+        );
+        assert_eq!(loaded_image("nothing useful"), None);
+    }
+}
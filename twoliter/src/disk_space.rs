@@ -0,0 +1,256 @@
+//! Checks that there is enough free disk space for a variant build before starting it, so that a
+//! build fails with a clear message rather than running out of space part way through.
+//!
+//! The space a build needs is estimated from what earlier builds in the project left behind: the
+//! RPMs and kits that will be copied into image builds, and the size of the variant's last images.
+//! Space which can't be measured is not checked, with a warning, so that the check never stops a
+//! build that might succeed.
+use crate::common::exec;
+use crate::project::Tools;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// The least free space, in GiB, a build is allowed to start with unless another is given, which
+/// is also what is required when nothing has been built before.
+pub(crate) const DEFAULT_MIN_FREE_GIB: u64 = 20;
+
+/// How much larger a variant's images are while the container runtime builds them than once they
+/// are compressed in the build directory.
+const IMAGE_EXPANSION: u64 = 4;
+
+/// The free space a variant build needs in the build directory and in the container runtime's data
+/// root.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Estimate {
+    pub(crate) build_dir: u64,
+    pub(crate) runtime: u64,
+}
+
+impl Estimate {
+    /// Estimates the space needed to build `variant` for `arch` in the project, which is at least
+    /// `min_free_gib` GiB.
+    pub(crate) async fn new(
+        project_dir: &Path,
+        arch: &str,
+        variant: &str,
+        min_free_gib: u64,
+    ) -> Result<Self> {
+        let build_dir = project_dir.join("build");
+        let inputs = dir_size(build_dir.join("rpms")).await?
+            + dir_size(build_dir.join("kits")).await?
+            + dir_size(build_dir.join("external-kits")).await?;
+        let images = dir_size(
+            build_dir
+                .join("images")
+                .join(format!("{arch}-{variant}"))
+                .join("latest"),
+        )
+        .await?;
+        Ok(Self::from_history(inputs, images, min_free_gib * GIB))
+    }
+
+    /// Estimates the space needed from the size of the RPMs and kits used by image builds, and the
+    /// size of the last images built for the variant, but no less than `min_free` bytes.
+    fn from_history(inputs: u64, images: u64, min_free: u64) -> Self {
+        Self {
+            // The new images are written next to the previous ones.
+            build_dir: (2 * images).max(min_free),
+            runtime: (inputs + IMAGE_EXPANSION * images).max(min_free),
+        }
+    }
+
+    /// Fails unless the build directory and the data root of the container runtime configured in
+    /// `tools` have enough free space.
+    pub(crate) async fn check(&self, project_dir: &Path, tools: &Tools) -> Result<()> {
+        let build_dir = project_dir.join("build");
+        let runtime = tools.container_runtime();
+        let mut checks = vec![(build_dir, self.build_dir)];
+        match runtime_root_dir(tools).await {
+            Some(runtime_dir) => checks.push((runtime_dir, self.runtime)),
+            None => {
+                warn!("Unable to find the data root of {runtime}, its free space was not checked")
+            }
+        }
+        for (dir, required) in checks {
+            let available = match available_space(&dir).await {
+                Ok(available) => available,
+                Err(e) => {
+                    warn!(
+                        "Unable to check the free space of '{}', continuing anyway: {e:#}",
+                        dir.display()
+                    );
+                    continue;
+                }
+            };
+            debug!(
+                "'{}' has {} free, and about {} is needed",
+                dir.display(),
+                gib(available),
+                gib(required)
+            );
+            if available < required {
+                bail!(
+                    "'{}' has {} free, but the build needs about {}. Free some space, for \
+                    example with 'twoliter build clean' or '{runtime} system prune', or pass \
+                    --skip-disk-check to build anyway",
+                    dir.display(),
+                    gib(available),
+                    gib(required)
+                );
+            }
+        }
+        info!("There is enough free disk space for the build");
+        Ok(())
+    }
+}
+
+/// Finds the data root of the container runtime configured in `tools`, if it is on this host.
+async fn runtime_root_dir(tools: &Tools) -> Option<PathBuf> {
+    let format = tools.container_runtime().root_dir_format();
+    let output = match exec(
+        Command::new(tools.container_runtime_cli()).args(["info", "--format", format]),
+        true,
+    )
+    .await
+    {
+        Ok(output) => output?,
+        Err(e) => {
+            debug!("Unable to query the container runtime's data root: {e:#}");
+            return None;
+        }
+    };
+    let dir = PathBuf::from(output.trim());
+    dir.is_absolute().then_some(dir)
+}
+
+/// The space available to unprivileged users on the filesystem holding `dir`, or its closest
+/// existing parent.
+async fn available_space(dir: &Path) -> Result<u64> {
+    let dir = dir
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or(Path::new("/"));
+    let output = exec(Command::new("df").arg("-Pk").arg(dir), true)
+        .await?
+        .unwrap_or_default();
+    parse_df(&output).context(format!(
+        "Unable to find the free space of '{}' in the output of df: {output}",
+        dir.display()
+    ))
+}
+
+/// Parses the available space, in bytes, from the output of `df -Pk`.
+fn parse_df(output: &str) -> Option<u64> {
+    let kib: u64 = output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// The total size of the files under `path`, without following symlinks below it. Zero if the path
+/// does not exist.
+pub(crate) async fn dir_size(path: PathBuf) -> Result<u64> {
+    tokio::task::spawn_blocking(move || {
+        let Ok(path) = std::fs::canonicalize(&path) else {
+            return 0;
+        };
+        let mut size = 0;
+        let mut dirs = vec![path];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.path().symlink_metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                } else if metadata.is_file() {
+                    size += metadata.len();
+                }
+            }
+        }
+        size
+    })
+    .await
+    .context("Unable to measure the size of the build directory")
+}
+
+pub(crate) fn gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / GIB as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_df() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+            /dev/nvme0n1p1   104845292  52422646  52422646      50% /\n";
+        assert_eq!(parse_df(output), Some(52422646 * 1024));
+        assert_eq!(parse_df("Filesystem\n"), None);
+    }
+
+    #[tokio::test]
+    async fn estimates_from_earlier_builds() {
+        let min_free = DEFAULT_MIN_FREE_GIB * GIB;
+        assert_eq!(
+            Estimate::from_history(0, 0, min_free),
+            Estimate {
+                build_dir: min_free,
+                runtime: min_free,
+            }
+        );
+        assert_eq!(
+            Estimate::from_history(30 * GIB, 5 * GIB, min_free),
+            Estimate {
+                build_dir: min_free,
+                runtime: 50 * GIB,
+            }
+        );
+        assert_eq!(
+            Estimate::from_history(30 * GIB, 5 * GIB, 0),
+            Estimate {
+                build_dir: 10 * GIB,
+                runtime: 50 * GIB,
+            }
+        );
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let images_dir = temp_dir.path().join("build/images/x86_64-aws-dev");
+        std::fs::create_dir_all(images_dir.join("1.0.0")).unwrap();
+        std::fs::write(images_dir.join("1.0.0/image.img.lz4"), [0; 1000]).unwrap();
+        std::os::unix::fs::symlink("1.0.0", images_dir.join("latest")).unwrap();
+        assert_eq!(dir_size(images_dir.join("latest")).await.unwrap(), 1000);
+        assert_eq!(dir_size(temp_dir.path().join("missing")).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn runtime_which_cannot_be_queried_is_not_checked() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let tools = Tools {
+            docker: Some(PathBuf::from("/nonexistent/docker")),
+            ..Default::default()
+        };
+        assert_eq!(
+            tools.container_runtime_cli(),
+            Path::new("/nonexistent/docker")
+        );
+        assert_eq!(runtime_root_dir(&tools).await, None);
+        let estimate = Estimate {
+            build_dir: 0,
+            runtime: u64::MAX,
+        };
+        estimate.check(temp_dir.path(), &tools).await.unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt::{Debug, Display, Formatter};
+
+/// The error type returned by twoliter's public library API. Internally twoliter uses `anyhow` to
+/// build up context as errors propagate; this wraps the resulting error so that library consumers
+/// receive a type implementing `std::error::Error`, with the context chain preserved as its
+/// `source`.
+pub struct Error(anyhow::Error);
+
+/// Alias for a `Result` with twoliter's library [`Error`] type.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Debug for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Self(e)
+    }
+}
+
+#[test]
+fn test_error_preserves_context_chain() {
+    use anyhow::Context;
+
+    let inner: anyhow::Result<()> = Err(anyhow::anyhow!("root cause"));
+    let err = Error::from(inner.context("outer context").unwrap_err());
+    assert_eq!(err.to_string(), "outer context");
+    let source = std::error::Error::source(&err).unwrap();
+    assert_eq!(source.to_string(), "root cause");
+}
@@ -0,0 +1,241 @@
+//! A user-level cache of pulled and unpacked kit images which is shared between projects. When
+//! enabled, kits are unpacked once into the cache and then hard-linked into each project's
+//! `build/external-kits` directory, so that multiple checkouts do not each hold their own copy.
+use crate::common::fs::{create_dir_all, remove_dir_all, replace_dir, write_atomic};
+use crate::kit_contents::{is_intact, make_read_only, DIGEST_FILE, STAMPS_FILE};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::{debug, trace};
+
+/// Environment variable which, when set, enables the shared kit cache at the given directory. This
+/// takes precedence over `kit-cache-dir` in `Twoliter.toml`.
+pub(crate) const TWOLITER_KIT_CACHE_ENV: &str = "TWOLITER_KIT_CACHE";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct KitCache {
+    root: PathBuf,
+}
+
+impl KitCache {
+    pub(crate) fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Determines the shared cache location from the environment, falling back to the value
+    /// configured in `Twoliter.toml`. Returns `None` when no shared cache is configured.
+    pub(crate) fn from_config(configured: Option<&Path>, project_dir: &Path) -> Option<Self> {
+        let env_value = std::env::var(TWOLITER_KIT_CACHE_ENV)
+            .ok()
+            .filter(|value| !value.is_empty());
+        let home = std::env::var("HOME").ok();
+        let dir = match env_value {
+            Some(value) => PathBuf::from(value),
+            None => configured?.to_path_buf(),
+        };
+        Some(Self::new(resolve_dir(&dir, project_dir, home.as_deref())))
+    }
+
+    /// Directory holding pulled OCI images, whose blobs are shared between them.
+    pub(crate) fn oci_dir(&self) -> PathBuf {
+        self.root.join("oci")
+    }
+
+    /// Directory holding the unpacked contents of the image with the given manifest digest.
+    pub(crate) fn unpacked_dir(&self, digest: &str) -> PathBuf {
+        self.root.join("kits").join(digest.replace(':', "-"))
+    }
+
+    /// A scratch directory next to the unpacked kits which can be atomically renamed into place.
+    pub(crate) fn staging_dir(&self) -> PathBuf {
+        self.root
+            .join("kits")
+            .join(format!(".staging-{}", uuid::Uuid::new_v4()))
+    }
+
+    /// Moves a fully unpacked staging directory into its final location. If another process won the
+    /// race and already populated the destination, the staging directory is discarded. The files
+    /// are made read-only first, since every project links to them.
+    pub(crate) async fn commit(&self, staging: &Path, digest: &str) -> Result<PathBuf> {
+        let dest = self.unpacked_dir(digest);
+        if is_intact(&dest, digest).await? {
+            trace!(
+                "Kit '{}' was unpacked into the shared cache concurrently",
+                digest
+            );
+            remove_dir_all(staging).await?;
+            return Ok(dest);
+        }
+        make_read_only(staging).await?;
+        replace_dir(staging, &dest).await?;
+        Ok(dest)
+    }
+}
+
+/// Populates `dest` with the unpacked image at `src`, hard-linking each file where possible and
+/// copying when the cache lives on a different filesystem. The files are linked into a directory
+/// next to `dest` which is renamed into place once its digest file is written, so that an
+/// interrupted link never leaves `dest` looking complete.
+pub(crate) async fn link_unpacked(src: &Path, dest: &Path, digest: &str) -> Result<()> {
+    if is_intact(dest, digest).await? {
+        trace!("'{}' is already linked from the kit cache", dest.display());
+        return Ok(());
+    }
+    debug!(
+        "Linking kit from cache '{}' into '{}'",
+        src.display(),
+        dest.display()
+    );
+    let file_name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let staging = dest.with_file_name(format!(".staging-{file_name}-{}", uuid::Uuid::new_v4()));
+    create_dir_all(&staging).await?;
+    let (src, staging_owned) = (src.to_path_buf(), staging.clone());
+    let linked = tokio::task::spawn_blocking(move || link_tree(&src, &staging_owned, true))
+        .await
+        .context("kit cache link task panicked")
+        .and_then(|linked| linked);
+    if let Err(e) = linked {
+        remove_dir_all(&staging).await?;
+        return Err(e);
+    }
+    write_atomic(staging.join(DIGEST_FILE), digest).await?;
+    replace_dir(&staging, dest).await
+}
+
+fn link_tree(src: &Path, dest: &Path, top_level: bool) -> Result<()> {
+    for entry in
+        std::fs::read_dir(src).context(format!("failed to read directory '{}'", src.display()))?
+    {
+        let entry = entry.context(format!(
+            "failed to read directory entry in '{}'",
+            src.display()
+        ))?;
+        if top_level && (entry.file_name() == DIGEST_FILE || entry.file_name() == STAMPS_FILE) {
+            continue;
+        }
+        let from = entry.path();
+        let to = dest.join(entry.file_name());
+        let file_type = entry
+            .file_type()
+            .context(format!("failed to stat '{}'", from.display()))?;
+        if file_type.is_dir() {
+            std::fs::create_dir_all(&to)
+                .context(format!("failed to create directory '{}'", to.display()))?;
+            link_tree(&from, &to, false)?;
+        } else if file_type.is_symlink() {
+            let target = std::fs::read_link(&from)
+                .context(format!("failed to read symlink '{}'", from.display()))?;
+            std::os::unix::fs::symlink(&target, &to)
+                .context(format!("failed to create symlink '{}'", to.display()))?;
+        } else {
+            link_file(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
+fn link_file(from: &Path, to: &Path) -> Result<()> {
+    if let Err(e) = std::fs::hard_link(from, to) {
+        // Hard links cannot cross filesystems, and some filesystems do not support them at all.
+        trace!(
+            "Unable to hard link '{}', falling back to copy: {}",
+            from.display(),
+            e
+        );
+        std::fs::copy(from, to).context(format!(
+            "failed to copy '{}' to '{}'",
+            from.display(),
+            to.display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// Expands a leading `~` to the user's home directory and anchors relative paths at the project
+/// directory.
+fn resolve_dir(dir: &Path, project_dir: &Path, home: Option<&str>) -> PathBuf {
+    if let (Ok(rest), Some(home)) = (dir.strip_prefix("~"), home) {
+        return PathBuf::from(home).join(rest);
+    }
+    if dir.is_relative() {
+        return project_dir.join(dir);
+    }
+    dir.to_path_buf()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn resolve_dir_expands_home() {
+        let resolved = resolve_dir(
+            Path::new("~/.cache/twoliter"),
+            Path::new("/project"),
+            Some("/home/me"),
+        );
+        assert_eq!(resolved, PathBuf::from("/home/me/.cache/twoliter"));
+    }
+
+    #[test]
+    fn resolve_dir_anchors_relative_paths() {
+        let resolved = resolve_dir(Path::new("cache"), Path::new("/project"), None);
+        assert_eq!(resolved, PathBuf::from("/project/cache"));
+        let resolved = resolve_dir(Path::new("/abs"), Path::new("/project"), None);
+        assert_eq!(resolved, PathBuf::from("/abs"));
+    }
+
+    #[tokio::test]
+    async fn committed_kits_are_read_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tempdir = TempDir::new().unwrap();
+        let kit_cache = KitCache::new(tempdir.path());
+        let staging = kit_cache.staging_dir();
+        std::fs::create_dir_all(staging.join("rpms")).unwrap();
+        std::fs::write(staging.join("rpms/a.rpm"), "a").unwrap();
+        crate::kit_contents::record(&staging).await.unwrap();
+        std::fs::write(staging.join(DIGEST_FILE), "sha256:abc").unwrap();
+
+        let dest = kit_cache.commit(&staging, "sha256:abc").await.unwrap();
+        let mode = std::fs::metadata(dest.join("rpms/a.rpm"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o222, 0);
+        assert!(is_intact(&dest, "sha256:abc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn link_unpacked_shares_inodes() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tempdir = TempDir::new().unwrap();
+        let src = tempdir.path().join("src");
+        let dest = tempdir.path().join("dest");
+        std::fs::create_dir_all(src.join("rpms")).unwrap();
+        std::fs::write(src.join("rpms/a.rpm"), "a").unwrap();
+        crate::kit_contents::record(&src).await.unwrap();
+        std::fs::write(src.join(DIGEST_FILE), "sha256:abc").unwrap();
+
+        link_unpacked(&src, &dest, "sha256:abc").await.unwrap();
+
+        let src_ino = std::fs::metadata(src.join("rpms/a.rpm")).unwrap().ino();
+        let dest_ino = std::fs::metadata(dest.join("rpms/a.rpm")).unwrap().ino();
+        assert_eq!(src_ino, dest_ino);
+        assert!(is_intact(&dest, "sha256:abc").await.unwrap());
+        assert!(!is_intact(&dest, "sha256:def").await.unwrap());
+
+        // Linking again for another digest replaces the directory, leaving nothing behind.
+        std::fs::write(src.join(DIGEST_FILE), "sha256:def").unwrap();
+        link_unpacked(&src, &dest, "sha256:def").await.unwrap();
+        assert!(is_intact(&dest, "sha256:def").await.unwrap());
+        let entries: Vec<_> = std::fs::read_dir(tempdir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries.len(), 2);
+    }
+}
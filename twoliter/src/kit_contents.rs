@@ -0,0 +1,359 @@
+//! Records the contents of an extracted kit image so that later runs can detect, and repair, files
+//! under `build/external-kits` which were modified after extraction.
+//!
+//! Checking an extraction only reads the files whose size, modification time or inode changed
+//! since it was last found intact, as recorded in a stamps file kept next to the contents. The
+//! stamps are only a shortcut: when they are missing or don't match, the files are checksummed.
+use crate::common::fs::{read, read_to_string, write, write_atomic};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tracing::{debug, trace, warn};
+
+/// The name of the file, written last, which records the digest of an extracted image.
+pub(crate) const DIGEST_FILE: &str = "digest";
+
+/// The name of the file which lists every extracted file along with its checksum and mode.
+pub(crate) const CONTENTS_FILE: &str = "contents.json";
+
+/// The name of the file which records the size, modification time and inode of each file when the
+/// extraction was last found intact. It belongs to the directory it is in, so it is never shared
+/// through the kit cache.
+pub(crate) const STAMPS_FILE: &str = "stamps.json";
+
+/// The maximum number of modified paths to list when warning about a modified kit.
+const MAX_REPORTED_CHANGES: usize = 10;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Entry {
+    File { sha256: String, mode: u32 },
+    Symlink { target: PathBuf },
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+struct ContentsManifest {
+    files: BTreeMap<PathBuf, Entry>,
+}
+
+/// What a file's metadata looked like when its checksum was last known to match.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+struct Stamp {
+    len: u64,
+    modified: (i64, i64),
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: (metadata.mtime(), metadata.mtime_nsec()),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+type Stamps = BTreeMap<PathBuf, Stamp>;
+
+impl ContentsManifest {
+    /// Walks `dir` and checksums every file in it, excluding our own bookkeeping files.
+    fn scan(dir: &Path) -> Result<Self> {
+        Ok(Self::scan_with(dir, &Self::default(), &Stamps::new())?.0)
+    }
+
+    /// Walks `dir` like [`ContentsManifest::scan`], but takes the checksums of files whose stamps
+    /// match those in `stamps` from `known` rather than reading them. Returns the stamps of the
+    /// files found along with the manifest.
+    fn scan_with(dir: &Path, known: &Self, stamps: &Stamps) -> Result<(Self, Stamps)> {
+        let mut manifest = Self::default();
+        let mut found = Stamps::new();
+        manifest.scan_dir(dir, Path::new(""), known, stamps, &mut found)?;
+        Ok((manifest, found))
+    }
+
+    fn scan_dir(
+        &mut self,
+        root: &Path,
+        relative: &Path,
+        known: &Self,
+        stamps: &Stamps,
+        found: &mut Stamps,
+    ) -> Result<()> {
+        let dir = root.join(relative);
+        for entry in
+            std::fs::read_dir(&dir).context(format!("failed to read '{}'", dir.display()))?
+        {
+            let entry = entry.context(format!("failed to read entry in '{}'", dir.display()))?;
+            let relative = relative.join(entry.file_name());
+            if [DIGEST_FILE, CONTENTS_FILE, STAMPS_FILE]
+                .iter()
+                .any(|file| relative == Path::new(file))
+            {
+                continue;
+            }
+            let path = entry.path();
+            let file_type = entry
+                .file_type()
+                .context(format!("failed to stat '{}'", path.display()))?;
+            if file_type.is_dir() {
+                self.scan_dir(root, &relative, known, stamps, found)?;
+            } else if file_type.is_symlink() {
+                let target = std::fs::read_link(&path)
+                    .context(format!("failed to read symlink '{}'", path.display()))?;
+                self.files.insert(relative, Entry::Symlink { target });
+            } else {
+                let metadata = entry
+                    .metadata()
+                    .context(format!("failed to stat '{}'", path.display()))?;
+                let stamp = Stamp::of(&metadata);
+                let mode = metadata.permissions().mode() & 0o7777;
+                let sha256 = match known.files.get(&relative) {
+                    Some(Entry::File { sha256, .. }) if stamps.get(&relative) == Some(&stamp) => {
+                        sha256.clone()
+                    }
+                    _ => sha256_file(&path)?,
+                };
+                found.insert(relative.clone(), stamp);
+                self.files.insert(relative, Entry::File { sha256, mode });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the paths which differ between `self` (the recorded state) and `actual`.
+    fn changes(&self, actual: &Self) -> Vec<PathBuf> {
+        let mut changes: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(path, entry)| actual.files.get(*path) != Some(*entry))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changes.extend(
+            actual
+                .files
+                .keys()
+                .filter(|path| !self.files.contains_key(*path))
+                .cloned(),
+        );
+        changes.sort();
+        changes
+    }
+}
+
+/// Returns the hex-encoded sha256 of the file at `path`.
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context(format!("failed to open '{}'", path.display()))?;
+    sha256_reader(&mut file, path)
+}
+
+fn sha256_reader(file: &mut File, path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(file, &mut hasher).context(format!("failed to read '{}'", path.display()))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn scan(dir: &Path) -> Result<ContentsManifest> {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || ContentsManifest::scan(&dir))
+        .await
+        .context("kit contents scan task panicked")?
+}
+
+async fn scan_with(
+    dir: &Path,
+    known: &ContentsManifest,
+    stamps: Stamps,
+) -> Result<(ContentsManifest, Stamps)> {
+    let (dir, known) = (dir.to_path_buf(), known.clone());
+    tokio::task::spawn_blocking(move || ContentsManifest::scan_with(&dir, &known, &stamps))
+        .await
+        .context("kit contents scan task panicked")?
+}
+
+/// The stamps recorded in `dir`, or none if they can't be read.
+async fn read_stamps(dir: &Path) -> Stamps {
+    let stamps_file = dir.join(STAMPS_FILE);
+    match tokio::fs::read(&stamps_file).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+        Err(_) => Stamps::new(),
+    }
+}
+
+/// Removes write permission from every file in the freshly extracted image in `dir`, and records
+/// the new modes in its contents manifest. Files in the kit cache are hard-linked into each
+/// project, so a build which tried to change one would otherwise change every project's copy.
+pub(crate) async fn make_read_only(dir: &Path) -> Result<()> {
+    let contents_file = dir.join(CONTENTS_FILE);
+    let mut manifest: ContentsManifest = serde_json::from_slice(&read(&contents_file).await?)
+        .context(format!("failed to parse '{}'", contents_file.display()))?;
+    for (relative, entry) in manifest.files.iter_mut() {
+        if let Entry::File { mode, .. } = entry {
+            *mode &= !0o222;
+            let path = dir.join(relative);
+            tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(*mode))
+                .await
+                .context(format!("failed to make '{}' read-only", path.display()))?;
+        }
+    }
+    let bytes = serde_json::to_vec_pretty(&manifest).context("failed to serialize kit contents")?;
+    write(contents_file, bytes).await
+}
+
+/// Writes the contents manifest for the freshly extracted image in `dir`. This must be called
+/// before the digest file is written.
+pub(crate) async fn record(dir: &Path) -> Result<()> {
+    let manifest = scan(dir).await?;
+    let bytes = serde_json::to_vec_pretty(&manifest).context("failed to serialize kit contents")?;
+    write(dir.join(CONTENTS_FILE), bytes).await
+}
+
+/// Returns true if `dir` holds a completely extracted image with the given digest whose files
+/// have not been modified since extraction.
+pub(crate) async fn is_intact(dir: &Path, digest: &str) -> Result<bool> {
+    let digest_file = dir.join(DIGEST_FILE);
+    if !digest_file.exists() || read_to_string(&digest_file).await? != digest {
+        return Ok(false);
+    }
+    let contents_file = dir.join(CONTENTS_FILE);
+    if !contents_file.exists() {
+        warn!(
+            "No contents manifest found in '{}', it will be extracted again",
+            dir.display()
+        );
+        return Ok(false);
+    }
+    let recorded: ContentsManifest = match serde_json::from_slice(&read(&contents_file).await?) {
+        Ok(recorded) => recorded,
+        Err(e) => {
+            warn!(
+                "Unable to parse '{}', it will be extracted again: {e}",
+                contents_file.display()
+            );
+            return Ok(false);
+        }
+    };
+
+    trace!("Verifying the contents of '{}'", dir.display());
+    let stamps = read_stamps(dir).await;
+    let (actual, found) = scan_with(dir, &recorded, stamps.clone()).await?;
+    let changes = recorded.changes(&actual);
+    if changes.is_empty() {
+        if found != stamps {
+            // The stamps only save work, so the check stands even if they can't be written.
+            let bytes = serde_json::to_vec(&found).context("failed to serialize kit stamps")?;
+            if let Err(e) = write_atomic(dir.join(STAMPS_FILE), bytes).await {
+                debug!("Unable to record the stamps of '{}': {e:#}", dir.display());
+            }
+        }
+        return Ok(true);
+    }
+    let listed = changes
+        .iter()
+        .take(MAX_REPORTED_CHANGES)
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let more = changes.len().saturating_sub(MAX_REPORTED_CHANGES);
+    warn!(
+        "{} file(s) in '{}' were modified after extraction and will be repaired: {listed}{}",
+        changes.len(),
+        dir.display(),
+        if more > 0 {
+            format!(" (and {more} more)")
+        } else {
+            String::new()
+        }
+    );
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn extracted_dir() -> TempDir {
+        let tempdir = TempDir::new().unwrap();
+        let dir = tempdir.path();
+        std::fs::create_dir_all(dir.join("Packages")).unwrap();
+        std::fs::write(dir.join("Packages/a.rpm"), "a").unwrap();
+        std::os::unix::fs::symlink("Packages/a.rpm", dir.join("latest.rpm")).unwrap();
+        record(dir).await.unwrap();
+        std::fs::write(dir.join(DIGEST_FILE), "sha256:abc").unwrap();
+        tempdir
+    }
+
+    #[tokio::test]
+    async fn unmodified_contents_are_intact() {
+        let tempdir = extracted_dir().await;
+        assert!(is_intact(tempdir.path(), "sha256:abc").await.unwrap());
+        assert!(!is_intact(tempdir.path(), "sha256:def").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn modified_contents_are_detected() {
+        let tempdir = extracted_dir().await;
+        std::fs::write(tempdir.path().join("Packages/a.rpm"), "b").unwrap();
+        assert!(!is_intact(tempdir.path(), "sha256:abc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn added_and_removed_files_are_detected() {
+        let tempdir = extracted_dir().await;
+        std::fs::write(tempdir.path().join("Packages/extra.rpm"), "x").unwrap();
+        assert!(!is_intact(tempdir.path(), "sha256:abc").await.unwrap());
+
+        let tempdir = extracted_dir().await;
+        std::fs::remove_file(tempdir.path().join("latest.rpm")).unwrap();
+        assert!(!is_intact(tempdir.path(), "sha256:abc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn intact_files_are_only_read_again_once_changed() {
+        let tempdir = extracted_dir().await;
+        let dir = tempdir.path();
+        assert!(is_intact(dir, "sha256:abc").await.unwrap());
+        assert!(dir.join(STAMPS_FILE).is_file());
+
+        // A file whose stamp matches is trusted without being read, so a recorded checksum which
+        // no longer matches goes unnoticed, while one whose stamp changed is read again.
+        let contents_file = dir.join(CONTENTS_FILE);
+        let mut manifest: ContentsManifest =
+            serde_json::from_slice(&std::fs::read(&contents_file).unwrap()).unwrap();
+        if let Some(Entry::File { sha256, .. }) =
+            manifest.files.get_mut(Path::new("Packages/a.rpm"))
+        {
+            *sha256 = "0".repeat(64);
+        }
+        std::fs::write(&contents_file, serde_json::to_vec(&manifest).unwrap()).unwrap();
+        assert!(is_intact(dir, "sha256:abc").await.unwrap());
+        std::fs::remove_file(dir.join(STAMPS_FILE)).unwrap();
+        assert!(!is_intact(dir, "sha256:abc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_only_contents_are_intact() {
+        let tempdir = extracted_dir().await;
+        let dir = tempdir.path();
+        make_read_only(dir).await.unwrap();
+        let mode = std::fs::metadata(dir.join("Packages/a.rpm"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o222, 0);
+        assert!(is_intact(dir, "sha256:abc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn mode_changes_are_detected() {
+        let tempdir = extracted_dir().await;
+        let path = tempdir.path().join("Packages/a.rpm");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(!is_intact(tempdir.path(), "sha256:abc").await.unwrap());
+    }
+}
@@ -0,0 +1,118 @@
+//! Deprecation and end-of-support notices which kit publishers embed in the kit metadata of their
+//! images, so that projects find out before a kit they depend on stops receiving fixes.
+use chrono::NaiveDate;
+use std::fmt::Write;
+use tracing::warn;
+
+/// The support status published for a locked kit.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct KitSupport {
+    /// The kit, displayed as `<name>-<version>@<vendor>`
+    pub(crate) kit: String,
+    /// Why the kit is deprecated, usually naming its replacement
+    pub(crate) deprecated: Option<String>,
+    /// The last date on which the kit is supported
+    pub(crate) end_of_support: Option<NaiveDate>,
+}
+
+impl KitSupport {
+    /// Returns the support status of `kit`, or `None` if its publisher has not deprecated it or
+    /// given it an end of support. An end of support which is not a `YYYY-MM-DD` date is ignored
+    /// with a warning, since it is the publisher's mistake rather than the project's.
+    pub(crate) fn new(
+        kit: String,
+        deprecated: Option<String>,
+        end_of_support: Option<&str>,
+    ) -> Option<Self> {
+        let end_of_support = end_of_support.and_then(|date| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| warn!("Ignoring invalid end-of-support date '{date}' of kit '{kit}'"))
+                .ok()
+        });
+        (deprecated.is_some() || end_of_support.is_some()).then_some(Self {
+            kit,
+            deprecated,
+            end_of_support,
+        })
+    }
+
+    /// Whether the kit should no longer be used as of `today`, because it is deprecated or past its
+    /// end of support.
+    pub(crate) fn is_unsupported(&self, today: NaiveDate) -> bool {
+        self.deprecated.is_some() || self.is_past_end_of_support(today)
+    }
+
+    fn is_past_end_of_support(&self, today: NaiveDate) -> bool {
+        self.end_of_support.is_some_and(|date| date < today)
+    }
+
+    /// Describes the kit's support status as of `today`.
+    pub(crate) fn notice(&self, today: NaiveDate) -> String {
+        let mut notice = format!("kit '{}'", self.kit);
+        match (&self.deprecated, self.end_of_support) {
+            (_, Some(date)) if self.is_past_end_of_support(today) => {
+                let _ = write!(notice, " reached end of support on {date}");
+            }
+            (Some(_), Some(date)) => {
+                let _ = write!(
+                    notice,
+                    " is deprecated and reaches end of support on {date}"
+                );
+            }
+            (Some(_), None) => notice.push_str(" is deprecated"),
+            (None, Some(date)) => {
+                let _ = write!(notice, " reaches end of support on {date}");
+            }
+            (None, None) => notice.push_str(" is supported"),
+        }
+        if let Some(deprecated) = &self.deprecated {
+            let _ = write!(notice, ": {deprecated}");
+        }
+        notice
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn notices_describe_support_status() {
+        let today = date("2025-01-01");
+        assert_eq!(KitSupport::new("core-kit".into(), None, None), None);
+        assert_eq!(
+            KitSupport::new("core-kit".into(), None, Some("next week")),
+            None
+        );
+
+        let upcoming = KitSupport::new("core-kit".into(), None, Some("2025-06-30")).unwrap();
+        assert!(!upcoming.is_unsupported(today));
+        assert_eq!(
+            upcoming.notice(today),
+            "kit 'core-kit' reaches end of support on 2025-06-30"
+        );
+
+        let past = KitSupport::new(
+            "core-kit".into(),
+            Some("use core-kit-2".into()),
+            Some("2024-06-30"),
+        )
+        .unwrap();
+        assert!(past.is_unsupported(today));
+        assert_eq!(
+            past.notice(today),
+            "kit 'core-kit' reached end of support on 2024-06-30: use core-kit-2"
+        );
+
+        let deprecated = KitSupport::new("core-kit".into(), Some("use core-kit-2".into()), None);
+        assert!(deprecated.as_ref().unwrap().is_unsupported(today));
+        assert_eq!(
+            deprecated.unwrap().notice(today),
+            "kit 'core-kit' is deprecated: use core-kit-2"
+        );
+    }
+}
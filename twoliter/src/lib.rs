@@ -0,0 +1,64 @@
+/*!
+
+Twoliter is a tool for building custom variants of Bottlerocket. In addition to the `twoliter`
+command line interface, this crate exposes the lock resolution machinery so that other tools can
+resolve and fetch a project's kit dependencies programmatically:
+
+```no_run
+# async fn example() -> twoliter::Result<()> {
+use twoliter::lock::Lock;
+use twoliter::progress::Progress;
+use twoliter::project::Project;
+
+let project = Project::find_and_load(".").await?;
+let lock = Lock::resolve(&project).await?;
+lock.fetch(&project, "x86_64", &[], &Progress::new(true)).await?;
+# Ok(())
+# }
+```
+
+!*/
+
+mod affected;
+mod arch_runs;
+mod artifact_signing;
+mod build_diagnostics;
+mod build_events;
+mod build_failures;
+mod build_output;
+mod build_state;
+mod cargo_make;
+#[doc(hidden)]
+pub mod cmd;
+mod common;
+mod compiler_cache;
+mod dependency_graph;
+mod deps;
+mod disk_space;
+mod docker;
+mod error;
+mod kit_cache;
+mod kit_contents;
+mod kit_support;
+mod licenses;
+mod lint;
+mod local_registry;
+mod local_sdk;
+pub mod lock;
+mod make_targets;
+mod oci_store;
+mod outdated;
+pub mod progress;
+pub mod project;
+mod project_keys;
+mod resolution_report;
+mod sbom;
+mod scaffold;
+pub mod schema_version;
+/// Test code that should only be compiled when running tests.
+#[cfg(test)]
+mod test;
+mod timings;
+mod tools;
+
+pub use error::{Error, Result};
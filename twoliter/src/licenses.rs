@@ -0,0 +1,285 @@
+//! Collects the licenses declared by the packages, external files and kits of a project and checks
+//! them against the `[licenses]` policy in `Twoliter.toml`. A package declares its license with the
+//! `License` tag of its spec, an external file with the `license` key in its package's manifest,
+//! falling back to the package's license, and a kit with `package.license` in its manifest. The
+//! license of an external kit in `Twoliter.lock` is read from the kit's metadata in the local image
+//! cache, where its publisher recorded it.
+use crate::lint::read_manifests;
+use crate::lock::{Lock, TWOLITER_LOCK};
+use crate::project::{LicensePolicy, Project};
+use anyhow::{Context, Result};
+use buildsys::spec::SpecInfo;
+use buildsys::BuildType;
+use serde::Serialize;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ItemKind {
+    Package,
+    ExternalFile,
+    Kit,
+    ExternalKit,
+}
+
+/// Whether a license is acceptable under the project's policy.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LicenseStatus {
+    Allowed,
+    /// The license is not declared, or is neither allowed nor denied
+    Unknown,
+    Denied,
+}
+
+/// The license declared by a package, external file or kit.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LicenseEntry {
+    pub(crate) kind: ItemKind,
+    pub(crate) name: String,
+    /// The package an external file belongs to
+    pub(crate) package: Option<String>,
+    /// The declared license, as an SPDX expression
+    pub(crate) license: Option<String>,
+    pub(crate) status: LicenseStatus,
+}
+
+/// Lists the declared license of every package, external file and kit in the project, and of the
+/// external kits it uses, sorted by package and name.
+pub(crate) async fn collect(project: &Project) -> Result<Vec<LicenseEntry>> {
+    let policy = project.licenses();
+    let entry = |kind, name: &str, package: Option<&str>, license: Option<String>| LicenseEntry {
+        kind,
+        name: name.to_string(),
+        package: package.map(str::to_string),
+        status: check(license.as_deref(), policy),
+        license,
+    };
+    let mut entries = Vec::new();
+    for (manifest_path, info) in read_manifests(&project.project_dir()).await? {
+        match info.build_type() {
+            Ok(BuildType::Package) => {
+                let name = info.package_name();
+                let spec_path = manifest_path
+                    .parent()
+                    .context("package manifest has no parent directory")?
+                    .join(format!("{name}.spec"));
+                let license = if spec_path.is_file() {
+                    SpecInfo::new(&spec_path)
+                        .context(format!("Unable to parse '{}'", spec_path.display()))?
+                        .license
+                } else {
+                    None
+                };
+                for file in info.external_files().into_iter().flatten() {
+                    let file_name = match file.file_name() {
+                        Ok(name) => name.display().to_string(),
+                        Err(_) => file.source_url().to_string(),
+                    };
+                    let file_license = file.license.clone().or_else(|| license.clone());
+                    entries.push(entry(
+                        ItemKind::ExternalFile,
+                        &file_name,
+                        Some(name),
+                        file_license,
+                    ));
+                }
+                entries.push(entry(ItemKind::Package, name, None, license));
+            }
+            Ok(BuildType::Kit) => {
+                let license = info.license().map(str::to_string);
+                entries.push(entry(ItemKind::Kit, info.kit_name(), None, license));
+            }
+            Ok(BuildType::Variant) | Ok(BuildType::Repack) | Err(_) => (),
+        }
+    }
+    if project.project_dir().join(TWOLITER_LOCK).exists() {
+        let lock = Lock::read_lock_file(project).await?;
+        let cache_dir = project.oci_cache_dir();
+        for kit in &lock.kit {
+            let license = match Lock::cached_kit_metadata(kit, &cache_dir).await {
+                Ok(metadata) => metadata.license,
+                Err(e) => {
+                    warn!("The license of kit '{kit}' is unknown: {e}");
+                    None
+                }
+            };
+            entries.push(entry(ItemKind::ExternalKit, &kit.name, None, license));
+        }
+    }
+    entries.sort_by(|a, b| {
+        let package =
+            |entry: &LicenseEntry| entry.package.clone().unwrap_or_else(|| entry.name.clone());
+        (package(a), a.package.is_some(), &a.name).cmp(&(package(b), b.package.is_some(), &b.name))
+    });
+    Ok(entries)
+}
+
+/// Checks a license expression against the policy. An expression with `OR` is as acceptable as its
+/// best alternative and one with `AND` as its worst part. The exception named by `WITH` is ignored.
+pub(crate) fn check(license: Option<&str>, policy: &LicensePolicy) -> LicenseStatus {
+    let Some(license) = license else {
+        return LicenseStatus::Unknown;
+    };
+    let spaced = license.replace('(', " ( ").replace(')', " ) ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        policy,
+    };
+    match parser.or() {
+        Some(status) if parser.position == tokens.len() => status,
+        _ => LicenseStatus::Unknown,
+    }
+}
+
+/// A recursive descent parser over the tokens of a license expression, which evaluates it as it
+/// goes.
+struct Parser<'a> {
+    tokens: &'a [&'a str],
+    position: usize,
+    policy: &'a LicensePolicy,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Option<LicenseStatus> {
+        let mut status = self.and()?;
+        while self.accept("OR") {
+            status = status.min(self.and()?);
+        }
+        Some(status)
+    }
+
+    fn and(&mut self) -> Option<LicenseStatus> {
+        let mut status = self.term()?;
+        while self.accept("AND") {
+            status = status.max(self.term()?);
+        }
+        Some(status)
+    }
+
+    fn term(&mut self) -> Option<LicenseStatus> {
+        if self.accept("(") {
+            let status = self.or()?;
+            return self.accept(")").then_some(status);
+        }
+        let license = *self.tokens.get(self.position)?;
+        if ["AND", "OR", "WITH", ")"].contains(&license) {
+            return None;
+        }
+        self.position += 1;
+        if self.accept("WITH") {
+            self.tokens.get(self.position)?;
+            self.position += 1;
+        }
+        let license = license.trim_end_matches('+');
+        let listed = |licenses: &[String]| licenses.iter().any(|listed| listed == license);
+        Some(if listed(&self.policy.denied) {
+            LicenseStatus::Denied
+        } else if self.policy.allowed.is_empty() || listed(&self.policy.allowed) {
+            LicenseStatus::Allowed
+        } else {
+            LicenseStatus::Unknown
+        })
+    }
+
+    fn accept(&mut self, token: &str) -> bool {
+        let accepted = self
+            .tokens
+            .get(self.position)
+            .is_some_and(|next| next.eq_ignore_ascii_case(token));
+        if accepted {
+            self.position += 1;
+        }
+        accepted
+    }
+}
+
+/// Renders the entries as CSV, with a header row.
+pub(crate) fn to_csv(entries: &[LicenseEntry]) -> String {
+    let mut csv = String::from("kind,name,package,license,status\n");
+    for entry in entries {
+        let row = [
+            kebab_case(&entry.kind),
+            entry.name.clone(),
+            entry.package.clone().unwrap_or_default(),
+            entry.license.clone().unwrap_or_default(),
+            kebab_case(&entry.status),
+        ];
+        let row: Vec<String> = row.iter().map(|cell| quote(cell)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn kebab_case(value: &impl Serialize) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn quote(cell: &str) -> String {
+    if cell.contains([',', '"', '\n']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project;
+
+    #[test]
+    fn checks_expressions() {
+        let policy = LicensePolicy {
+            allowed: vec!["MIT".to_string(), "Apache-2.0".to_string()],
+            denied: vec!["GPL-3.0-only".to_string()],
+        };
+        let status = |license| check(Some(license), &policy);
+        assert_eq!(status("MIT"), LicenseStatus::Allowed);
+        assert_eq!(status("Apache-2.0 OR MIT"), LicenseStatus::Allowed);
+        assert_eq!(status("MIT OR GPL-3.0-only"), LicenseStatus::Allowed);
+        assert_eq!(status("MIT AND GPL-3.0-only"), LicenseStatus::Denied);
+        assert_eq!(
+            status("MIT AND (BSD-3-Clause OR ISC)"),
+            LicenseStatus::Unknown
+        );
+        assert_eq!(
+            status("Apache-2.0 WITH LLVM-exception"),
+            LicenseStatus::Allowed
+        );
+        assert_eq!(status("MIT AND"), LicenseStatus::Unknown);
+        assert_eq!(check(None, &policy), LicenseStatus::Unknown);
+        assert_eq!(
+            check(Some("BSD-3-Clause"), &LicensePolicy::default()),
+            LicenseStatus::Allowed
+        );
+    }
+
+    #[tokio::test]
+    async fn collects_licenses() {
+        let temp_dir = crate::test::copy_project_to_temp_dir("project1");
+        let project =
+            project::load_or_find_project(Some(temp_dir.path().join("Twoliter.toml")), false)
+                .await
+                .unwrap();
+        let entries = collect(&project).await.unwrap();
+        let hello_go = entries
+            .iter()
+            .find(|entry| entry.name == "hello-go")
+            .unwrap();
+        assert_eq!(hello_go.kind, ItemKind::Package);
+        assert_eq!(hello_go.license.as_deref(), Some("Apache-2.0 OR MIT"));
+        assert_eq!(hello_go.status, LicenseStatus::Allowed);
+
+        let csv = to_csv(&entries);
+        assert!(csv.starts_with("kind,name,package,license,status\n"));
+        assert!(csv.contains("package,hello-go,,Apache-2.0 OR MIT,allowed\n"));
+    }
+}
@@ -0,0 +1,296 @@
+//! Checks the package and kit `Cargo.toml` manifests of a project for problems which buildsys
+//! would otherwise only report one at a time, partway through a build.
+use crate::common::fs::read_to_string;
+use crate::project::Project;
+use anyhow::{Context, Result};
+use async_walkdir::{Filtering, WalkDir};
+use buildsys::manifest::ManifestInfo;
+use futures::stream::StreamExt;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use toml::Value;
+use tracing::warn;
+
+/// Directories which never contain package or kit manifests.
+const SKIPPED_DIRS: &[&str] = &["build", "target", "sources"];
+
+/// Package metadata keys which buildsys rejects.
+const DEPRECATED_PACKAGE_KEYS: &[&str] = &["package-features", "variant-sensitive"];
+
+/// What a spec file calls the directory into which the project's `sources` are mounted.
+const SPEC_SOURCES_DIR: &str = "%{_builddir}/sources/";
+
+/// A problem found in a manifest.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) struct Finding {
+    /// The `Cargo.toml` the problem was found in
+    pub(crate) manifest: PathBuf,
+    pub(crate) message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.manifest.display(), self.message)
+    }
+}
+
+/// Checks every package and kit manifest in the project, returning all of the problems found
+/// sorted by manifest.
+pub(crate) async fn lint(project: &Project) -> Result<Vec<Finding>> {
+    let project_dir = project.project_dir();
+    let mut findings = Vec::new();
+    for manifest_path in find_manifests(&project_dir).await? {
+        let manifest: Value = toml::from_str(&read_to_string(&manifest_path).await?)
+            .context(format!("Unable to parse '{}'", manifest_path.display()))?;
+        let messages = match (package_metadata(&manifest), kit_metadata(&manifest)) {
+            (Some(metadata), _) => {
+                lint_package(&project_dir, &manifest_path, &manifest, metadata).await?
+            }
+            (None, Some(metadata)) => lint_kit(metadata),
+            (None, None) => continue,
+        };
+        let manifest = manifest_path
+            .strip_prefix(&project_dir)
+            .unwrap_or(&manifest_path)
+            .to_path_buf();
+        findings.extend(messages.into_iter().map(|message| Finding {
+            manifest: manifest.clone(),
+            message,
+        }));
+    }
+    findings.sort();
+    Ok(findings)
+}
+
+/// Finds every `Cargo.toml` in the project outside of build output and first-party sources.
+pub(crate) async fn find_manifests(project_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = WalkDir::new(project_dir).filter(|entry| async move {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref()) {
+            return Filtering::IgnoreDir;
+        }
+        Filtering::Continue
+    });
+    let mut manifests = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let entry = entry.context(format!(
+            "Unable to search '{}' for manifests",
+            project_dir.display()
+        ))?;
+        if entry.file_name() == "Cargo.toml" {
+            manifests.push(entry.path());
+        }
+    }
+    Ok(manifests)
+}
+
+/// Reads every package, kit and variant manifest in the project, sorted by path. Workspace
+/// manifests, which have no `[package]`, are skipped; so are manifests buildsys can't read, with a
+/// warning, since a build would fail on them.
+pub(crate) async fn read_manifests(project_dir: &Path) -> Result<Vec<(PathBuf, ManifestInfo)>> {
+    let mut manifests = find_manifests(project_dir).await?;
+    manifests.sort();
+    let mut read = Vec::new();
+    for manifest_path in manifests {
+        match ManifestInfo::new(&manifest_path) {
+            Ok(info) => read.push((manifest_path, info)),
+            Err(e) => {
+                let contents = read_to_string(&manifest_path).await?;
+                let is_workspace = toml::from_str::<Value>(&contents)
+                    .is_ok_and(|manifest| manifest.get("package").is_none());
+                if !is_workspace {
+                    warn!("Skipping '{}': {e}", manifest_path.display());
+                }
+            }
+        }
+    }
+    Ok(read)
+}
+
+fn package_metadata(manifest: &Value) -> Option<&Value> {
+    manifest
+        .get("package")?
+        .get("metadata")?
+        .get("build-package")
+}
+
+fn kit_metadata(manifest: &Value) -> Option<&Value> {
+    manifest.get("package")?.get("metadata")?.get("build-kit")
+}
+
+async fn lint_package(
+    project_dir: &Path,
+    manifest_path: &Path,
+    manifest: &Value,
+    metadata: &Value,
+) -> Result<Vec<String>> {
+    let mut messages = Vec::new();
+    for key in DEPRECATED_PACKAGE_KEYS {
+        if metadata.get(key).is_some() {
+            messages.push(format!(
+                "'{key}' is no longer supported by buildsys and must be removed"
+            ));
+        }
+    }
+
+    for (i, file) in array(metadata.get("external-files")).iter().enumerate() {
+        let has_checksum = file
+            .get("sha512")
+            .and_then(Value::as_str)
+            .is_some_and(|sha512| !sha512.is_empty());
+        if !has_checksum {
+            let url = file.get("url").and_then(Value::as_str).unwrap_or("?");
+            messages.push(format!(
+                "external file {i} ('{url}') has no sha512 checksum"
+            ));
+        }
+    }
+
+    let source_groups: BTreeSet<&str> = array(metadata.get("source-groups"))
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    let sources_dir = project_dir.join("sources");
+    for group in source_groups.iter() {
+        if !sources_dir.join(group).is_dir() {
+            messages.push(format!(
+                "source group '{group}' does not exist in '{}'",
+                sources_dir.display()
+            ));
+        }
+    }
+
+    let name = metadata
+        .get("package-name")
+        .or_else(|| {
+            manifest
+                .get("package")
+                .and_then(|package| package.get("name"))
+        })
+        .and_then(Value::as_str)
+        .context(format!("'{}' has no package name", manifest_path.display()))?;
+    let spec_path = manifest_path.with_file_name(format!("{name}.spec"));
+    if !spec_path.is_file() {
+        messages.push(format!("spec file '{name}.spec' is missing"));
+        return Ok(messages);
+    }
+
+    let spec = read_to_string(&spec_path).await?;
+    for group in spec_source_dirs(&spec) {
+        if sources_dir.join(group).is_dir() && !source_groups.contains(group) {
+            messages.push(format!(
+                "'{name}.spec' uses sources from '{group}', which is not listed in 'source-groups'"
+            ));
+        }
+    }
+    Ok(messages)
+}
+
+fn lint_kit(metadata: &Value) -> Vec<String> {
+    let has_vendor = metadata
+        .get("vendor")
+        .and_then(Value::as_str)
+        .is_some_and(|vendor| !vendor.is_empty());
+    if has_vendor {
+        Vec::new()
+    } else {
+        vec!["kit has no 'vendor' in 'package.metadata.build-kit'".to_string()]
+    }
+}
+
+/// Returns the first path component of every use of the project's `sources` in a spec file.
+fn spec_source_dirs(spec: &str) -> BTreeSet<&str> {
+    spec.match_indices(SPEC_SOURCES_DIR)
+        .filter_map(|(i, _)| {
+            let rest = &spec[i + SPEC_SOURCES_DIR.len()..];
+            let end = rest
+                .find(|c: char| c == '/' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            Some(&rest[..end]).filter(|dir| !dir.is_empty())
+        })
+        .collect()
+}
+
+fn array(value: Option<&Value>) -> &[Value] {
+    value
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::copy_project_to_temp_dir;
+
+    #[test]
+    fn test_spec_source_dirs() {
+        let spec = "cp -r %{_builddir}/sources/hello-go/* .\n\
+            %cargo_build --manifest-path %{_builddir}/sources/Cargo.toml\n";
+        assert_eq!(
+            spec_source_dirs(spec),
+            BTreeSet::from(["Cargo.toml", "hello-go"])
+        );
+    }
+
+    #[tokio::test]
+    async fn lint_reports_all_findings() {
+        let temp_dir = copy_project_to_temp_dir("project1");
+        let project_dir = temp_dir.path();
+        let project = Project::load(project_dir.join("Twoliter.toml"))
+            .await
+            .unwrap();
+        assert_eq!(lint(&project).await.unwrap(), Vec::new());
+
+        let manifest_path = project_dir.join("packages/hello-go/Cargo.toml");
+        let manifest = std::fs::read_to_string(&manifest_path).unwrap().replace(
+            "source-groups = [\"hello-go\"]",
+            "source-groups = []\n\
+                package-features = []\n\
+                [[package.metadata.build-package.external-files]]\n\
+                url = \"https://example.com/a.tar.gz\"\n\
+                sha512 = \"\"",
+        );
+        std::fs::write(&manifest_path, manifest).unwrap();
+        std::fs::remove_file(project_dir.join("packages/hello-agent/hello-agent.spec")).unwrap();
+
+        let messages: Vec<String> = lint(&project)
+            .await
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "packages/hello-agent/Cargo.toml: spec file 'hello-agent.spec' is missing",
+                "packages/hello-go/Cargo.toml: 'hello-go.spec' uses sources from 'hello-go', which \
+                is not listed in 'source-groups'",
+                "packages/hello-go/Cargo.toml: 'package-features' is no longer supported by \
+                buildsys and must be removed",
+                "packages/hello-go/Cargo.toml: external file 0 ('https://example.com/a.tar.gz') has \
+                no sha512 checksum",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_buildsys_manifests() {
+        let temp_dir = copy_project_to_temp_dir("project1");
+        let project_dir = temp_dir.path();
+        let broken_dir = project_dir.join("packages/broken");
+        std::fs::create_dir_all(&broken_dir).unwrap();
+        std::fs::write(broken_dir.join("Cargo.toml"), "[package]\nname = 1\n").unwrap();
+
+        let manifests = read_manifests(project_dir).await.unwrap();
+        let paths: Vec<&Path> = manifests.iter().map(|(path, _)| path.as_path()).collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(paths, sorted);
+        assert!(paths.contains(&project_dir.join("packages/hello-go/Cargo.toml").as_path()));
+        assert!(!paths.contains(&broken_dir.join("Cargo.toml").as_path()));
+        assert!(!paths.contains(&project_dir.join("Cargo.toml").as_path()));
+    }
+}
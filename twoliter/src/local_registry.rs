@@ -0,0 +1,93 @@
+//! Runs a container registry on this host for locally built kits, so that other local projects can
+//! consume them through an ordinary vendor before they're published. The registry listens on
+//! `localhost`, which docker and crane reach over plain HTTP, and keeps its images only until it is
+//! stopped. The registry runs in the container runtime whose CLI is given, which is the project's
+//! when there is one.
+use crate::common::exec;
+use anyhow::Result;
+use std::path::Path;
+use tokio::process::Command;
+use tracing::info;
+
+/// The port the registry listens on unless another is given.
+pub(crate) const DEFAULT_PORT: u16 = 5000;
+
+/// The image the registry runs unless another is given.
+pub(crate) const DEFAULT_IMAGE: &str = "public.ecr.aws/docker/library/registry:2";
+
+/// The port the registry listens on within its container.
+const CONTAINER_PORT: u16 = 5000;
+
+/// The name of the container running the registry on `port`.
+fn container_name(port: u16) -> String {
+    format!("twoliter-registry-{port}")
+}
+
+/// The registry which a vendor publishing to, or consuming from, the local registry uses. Each
+/// vendor gets a namespace, as it would in a hosted registry.
+pub(crate) fn vendor_registry(port: u16, vendor: &str) -> String {
+    format!("localhost:{port}/{vendor}")
+}
+
+/// Starts the registry on `port` with the container runtime `cli`, unless it's already running.
+pub(crate) async fn start(cli: &Path, port: u16, image: &str) -> Result<()> {
+    if is_running(cli, port).await? {
+        info!("The local registry is already running at 'localhost:{port}'");
+        return Ok(());
+    }
+    exec(
+        Command::new(cli)
+            .args(["run", "--detach", "--rm", "--name"])
+            .arg(container_name(port))
+            .arg("--publish")
+            .arg(format!("127.0.0.1:{port}:{CONTAINER_PORT}"))
+            .arg(image),
+        true,
+    )
+    .await?;
+    info!("Started the local registry at 'localhost:{port}'");
+    Ok(())
+}
+
+/// Stops the registry on `port`, which discards the images pushed to it.
+pub(crate) async fn stop(cli: &Path, port: u16) -> Result<()> {
+    if !is_running(cli, port).await? {
+        info!("The local registry is not running at 'localhost:{port}'");
+        return Ok(());
+    }
+    exec(
+        Command::new(cli).args(["stop"]).arg(container_name(port)),
+        true,
+    )
+    .await?;
+    info!("Stopped the local registry at 'localhost:{port}'");
+    Ok(())
+}
+
+/// Whether the registry on `port` is running.
+pub(crate) async fn is_running(cli: &Path, port: u16) -> Result<bool> {
+    let name = container_name(port);
+    let output = exec(
+        Command::new(cli)
+            .args(["ps", "--format", "{{.Names}}", "--filter"])
+            .arg(format!("name=^{name}$")),
+        true,
+    )
+    .await?
+    .unwrap_or_default();
+    Ok(output.lines().any(|line| line.trim() == name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_registry_by_port() {
+        assert_eq!(container_name(5001), "twoliter-registry-5001");
+        assert_eq!(
+            vendor_registry(5001, "my-vendor"),
+            "localhost:5001/my-vendor"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Records the SDK images which `twoliter build sdk` builds from a local checkout of the SDK.
+//! Builds use them in place of the SDK in `Twoliter.lock` until `twoliter build sdk --clear`
+//! removes them, so that changes to the SDK can be tried out without publishing it. The image built
+//! for each architecture is recorded in `build/local-sdk/<arch>`, and its ID in
+//! `build/local-sdk/<arch>.id`. Every build of the SDK gets the same tag, so builds are keyed on
+//! the ID instead.
+//!
+//! buildkitd can't see the images in the container runtime, so when builds run with `buildctl` the
+//! SDK is kept as an OCI image layout instead: `build/local-sdk/<arch>.oci` for the local SDK, and
+//! a directory under `build/sdk-layout` for the locked SDK loaded from a dependency bundle.
+use crate::common::fs;
+use crate::lock::{Lock, LockedImage};
+use crate::project::Project;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tracing::info;
+
+/// The directory, within the project, which records the local SDK images.
+const LOCAL_SDK_DIR: &str = "build/local-sdk";
+
+/// The directory, within the project, which holds the layouts of locked SDK images.
+const SDK_LAYOUT_DIR: &str = "build/sdk-layout";
+
+/// The tag for the local SDK image of `arch`, which is unique to the project so that projects
+/// sharing a host don't replace each other's SDK.
+pub(crate) fn tag(project: &Project, arch: &str) -> String {
+    let digest = Sha256::digest(project.project_dir().to_string_lossy().as_bytes());
+    let id = hex::encode(&digest[..6]);
+    format!("twoliter-local-sdk-{id}:{arch}")
+}
+
+/// The SDK image a build runs with.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Sdk {
+    /// The image's URI or tag, which the build pulls or runs
+    pub(crate) image: String,
+    /// The digest of the image, which identifies its contents where the tag may move
+    pub(crate) digest: String,
+    /// An OCI image layout holding the image, for builds which can't pull or load it
+    pub(crate) layout: Option<PathBuf>,
+}
+
+/// Records `image` as the SDK to build with for `arch`.
+pub(crate) async fn record(project: &Project, arch: &str, image: &str) -> Result<()> {
+    fs::create_dir_all(local_sdk_dir(project)).await?;
+    fs::write(local_sdk_dir(project).join(arch), image).await
+}
+
+/// The file to which the ID of the local SDK image for `arch` is written when it's built. Its
+/// directory is created if it doesn't exist.
+pub(crate) async fn id_path(project: &Project, arch: &str) -> Result<PathBuf> {
+    fs::create_dir_all(local_sdk_dir(project)).await?;
+    Ok(local_sdk_dir(project).join(format!("{arch}.id")))
+}
+
+/// The OCI image layout to which the local SDK image for `arch` is written when it's built with
+/// buildkit. Its directory is created if it doesn't exist.
+pub(crate) async fn layout_path(project: &Project, arch: &str) -> Result<PathBuf> {
+    fs::create_dir_all(local_sdk_dir(project)).await?;
+    Ok(local_sdk_dir(project).join(format!("{arch}.oci")))
+}
+
+/// The OCI image layout which holds the locked `sdk` image for `arch`, named for the image's digest
+/// so that a layout of an SDK the lock no longer uses is never picked up.
+pub(crate) fn locked_layout_path(project: &Project, sdk: &LockedImage, arch: &str) -> PathBuf {
+    let digest = Sha256::digest(sdk.digest.as_bytes());
+    let id = hex::encode(&digest[..6]);
+    project
+        .project_dir()
+        .join(SDK_LAYOUT_DIR)
+        .join(format!("{id}-{arch}"))
+}
+
+/// Removes the record of every local SDK image, so that builds go back to the locked SDK.
+pub(crate) async fn clear(project: &Project) -> Result<()> {
+    let dir = local_sdk_dir(project);
+    if dir.exists() {
+        fs::remove_dir_all(dir).await?;
+    }
+    Ok(())
+}
+
+/// The SDK image to build with for `arch`: the local SDK image if one is recorded, or else the one
+/// in `Twoliter.lock`.
+pub(crate) async fn sdk_for(project: &Project, lock: &Lock, arch: &str) -> Result<Sdk> {
+    let path = local_sdk_dir(project).join(arch);
+    if !path.is_file() {
+        let locked = lock.sdk_for(arch);
+        let layout = locked_layout_path(project, locked, arch);
+        return Ok(Sdk {
+            image: locked.source.clone(),
+            digest: locked.digest.clone(),
+            layout: layout.is_dir().then_some(layout),
+        });
+    }
+    let image = fs::read_to_string(&path).await?.trim().to_string();
+    let id_path = local_sdk_dir(project).join(format!("{arch}.id"));
+    let digest = if id_path.is_file() {
+        fs::read_to_string(&id_path).await?.trim().to_string()
+    } else {
+        image.clone()
+    };
+    let layout = local_sdk_dir(project).join(format!("{arch}.oci"));
+    info!("Building {arch} with the local SDK '{image}' in place of the locked SDK");
+    Ok(Sdk {
+        image,
+        digest,
+        layout: layout.is_dir().then_some(layout),
+    })
+}
+
+/// The SDK image to run with for commands which don't build for a particular architecture, which
+/// is the one for the host's architecture.
+pub(crate) async fn host_sdk(project: &Project, lock: &Lock) -> Result<Sdk> {
+    sdk_for(project, lock, std::env::consts::ARCH).await
+}
+
+fn local_sdk_dir(project: &Project) -> PathBuf {
+    project.project_dir().join(LOCAL_SDK_DIR)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project;
+
+    #[tokio::test]
+    async fn local_sdk_replaces_locked_sdk() {
+        let temp_dir = crate::test::copy_project_to_temp_dir("project1");
+        let project =
+            project::load_or_find_project(Some(temp_dir.path().join("Twoliter.toml")), false)
+                .await
+                .unwrap();
+        let lock: Lock = toml::from_str(
+            "schema-version = 1\nkit = []\n\n[sdk]\nname = \"sdk\"\nversion = \"1.0.0\"\n\
+            vendor = \"my-vendor\"\nsource = \"a.com/b/sdk:v1.0.0\"\ndigest = \"abc=\"\n",
+        )
+        .unwrap();
+
+        let tag = tag(&project, "aarch64");
+        assert!(tag.starts_with("twoliter-local-sdk-"));
+        assert!(tag.ends_with(":aarch64"));
+        record(&project, "aarch64", &tag).await.unwrap();
+        assert_eq!(
+            sdk_for(&project, &lock, "aarch64").await.unwrap().image,
+            tag
+        );
+        assert_eq!(
+            sdk_for(&project, &lock, "x86_64").await.unwrap(),
+            Sdk {
+                image: "a.com/b/sdk:v1.0.0".to_string(),
+                digest: "abc=".to_string(),
+                layout: None,
+            }
+        );
+
+        // Builds with buildkit use the layouts of the SDKs.
+        let local_layout = layout_path(&project, "aarch64").await.unwrap();
+        std::fs::create_dir(&local_layout).unwrap();
+        assert_eq!(
+            sdk_for(&project, &lock, "aarch64").await.unwrap().layout,
+            Some(local_layout)
+        );
+        let locked_layout = locked_layout_path(&project, lock.sdk_for("x86_64"), "x86_64");
+        std::fs::create_dir_all(&locked_layout).unwrap();
+        assert_eq!(
+            sdk_for(&project, &lock, "x86_64").await.unwrap().layout,
+            Some(locked_layout)
+        );
+
+        clear(&project).await.unwrap();
+        assert_eq!(
+            sdk_for(&project, &lock, "aarch64").await.unwrap().image,
+            "a.com/b/sdk:v1.0.0"
+        );
+    }
+}
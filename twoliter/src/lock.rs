@@ -1,32 +1,48 @@
-use crate::common::fs::{create_dir_all, read, remove_dir_all, write};
-use crate::project::{Image, Project, ValidIdentifier, Vendor};
-use crate::schema_version::SchemaVersion;
+use crate::common::fs::{create_dir_all, read, remove_dir_all, write, write_atomic};
+use crate::dependency_graph::DependencyGraph;
+use crate::kit_cache::{self, KitCache};
+use crate::kit_contents::{self, DIGEST_FILE};
+use crate::kit_support::KitSupport;
+use crate::oci_store::{OciStore, INDEX_FILE};
+use crate::progress::Progress;
+use crate::project::{Image, MetadataMismatchPolicy, Project, ValidIdentifier, Vendor};
+use crate::resolution_report::{image_id, MetadataRecord, ResolutionReport};
+use crate::schema_version::LockSchemaVersion;
 use anyhow::{bail, ensure, Context, Result};
 use base64::Engine;
+use flate2::read::MultiGzDecoder;
 use futures::pin_mut;
 use futures::stream::{self, StreamExt, TryStreamExt};
-use oci_cli_wrapper::{DockerArchitecture, ImageTool};
+use oci_cli_wrapper::{DockerArchitecture, ImageTool, Referrer, KIT_METADATA_LABEL};
 use olpc_cjson::CanonicalFormatter as CanonicalJsonFormatter;
 use semver::Version;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize};
 use sha2::Digest;
 use std::cmp::PartialEq;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read};
 use std::mem::take;
 use std::path::{Path, PathBuf};
 use tar::Archive as TarArchive;
 use tokio::fs::read_to_string;
-use tracing::{debug, error, info, instrument, trace};
+use tracing::{debug, error, info, instrument, trace, warn};
 
-const TWOLITER_LOCK: &str = "Twoliter.lock";
+pub(crate) const TWOLITER_LOCK: &str = "Twoliter.lock";
+
+/// The maximum number of registry requests to have in flight at once while resolving kits.
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+/// The maximum number of kits to pull and unpack at once, which is lower than the number of
+/// fetches since each one also writes a whole kit to disk.
+const MAX_CONCURRENT_EXTRACTIONS: usize = 4;
 
 /// Represents a locked dependency on an image
 #[derive(Debug, Clone, Eq, Ord, PartialOrd, Serialize, Deserialize)]
-pub(crate) struct LockedImage {
+pub struct LockedImage {
     /// The name of the dependency
     pub name: String,
     /// The version of the dependency
@@ -37,25 +53,42 @@ pub(crate) struct LockedImage {
     pub source: String,
     /// The digest of the image
     pub digest: String,
+    /// The digest of each per-architecture image, keyed by docker architecture. Only recorded in
+    /// lock schema version 2.
+    #[serde(
+        default,
+        rename = "arch-digests",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub arch_digests: BTreeMap<String, String>,
+    /// The features enabled for the kit in Twoliter.toml
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
     #[serde(skip)]
     pub(crate) manifest: Vec<u8>,
 }
 
 impl PartialEq for LockedImage {
     fn eq(&self, other: &Self) -> bool {
-        self.source == other.source && self.digest == other.digest
+        self.source == other.source
+            && self.digest == other.digest
+            && self.arch_digests == other.arch_digests
+            && self.features == other.features
     }
 }
 
 impl LockedImage {
-    pub async fn new(image_tool: &ImageTool, vendor: &Vendor, image: &Image) -> Result<Self> {
-        let source = format!("{}/{}:v{}", vendor.registry, image.name, image.version);
+    pub(crate) async fn new(
+        image_tool: &ImageTool,
+        vendor: &Vendor,
+        image: &Image,
+    ) -> Result<Self> {
+        let source = format!("{}/{}:v{}", vendor.repository(), image.name, image.version);
         debug!("Pulling image manifest for locked image '{}'", source);
         let manifest_bytes = image_tool.get_manifest(source.as_str()).await?;
 
         // We calculate a 'digest' of the manifest to use as our unique id
-        let digest = sha2::Sha256::digest(manifest_bytes.as_slice());
-        let digest = base64::engine::general_purpose::STANDARD.encode(digest.as_slice());
+        let digest = manifest_digest(manifest_bytes.as_slice());
         trace!(
             "Calculated digest for locked image '{}': '{}'",
             source,
@@ -68,16 +101,78 @@ impl LockedImage {
             vendor: image.vendor.to_string(),
             source,
             digest,
+            arch_digests: BTreeMap::new(),
+            features: Vec::new(),
             manifest: manifest_bytes,
         })
     }
 
+    /// Records the digest of each per-architecture image from the manifest list fetched at lock
+    /// time.
+    fn record_arch_digests(&mut self) -> Result<()> {
+        let manifest_list: ManifestListView = serde_json::from_slice(self.manifest.as_slice())
+            .context(format!(
+                "failed to deserialize manifest list for '{}'",
+                self
+            ))?;
+        self.arch_digests = manifest_list
+            .manifests
+            .iter()
+            .filter_map(|manifest| {
+                manifest
+                    .architecture()
+                    .map(|arch| (arch.to_string(), manifest.digest.clone()))
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Lists the artifacts, such as SBOMs, provenance and test results, which the publisher
+    /// attached to the image as OCI referrers. The image is first checked against its locked
+    /// digest, so that the artifacts describe the image the project builds with. Both are looked
+    /// up by the image's digest rather than its tag, so that the tag can't move in between.
+    pub(crate) async fn referrers(&self, image_tool: &ImageTool) -> Result<Vec<Referrer>> {
+        let registry_digest = image_tool.digest(&self.source).await?;
+        let digest_uri = self.digest_uri(&registry_digest);
+        let manifest_bytes = image_tool.get_manifest(&digest_uri).await?;
+        ensure!(
+            manifest_digest(&manifest_bytes) == self.digest,
+            "'{}' no longer matches its digest in Twoliter.lock, run `twoliter update`",
+            self.source
+        );
+        image_tool
+            .list_referrers(&digest_uri)
+            .await
+            .context(format!("failed to list the artifacts attached to '{self}'"))
+    }
+
     pub fn digest_uri(&self, digest: &str) -> String {
         self.source.replace(
             format!(":v{}", self.version).as_str(),
             format!("@{}", digest).as_str(),
         )
     }
+
+    /// The SHA-256 of the manifest list for this image in hex, as registries name it in its
+    /// `sha256:` digest. The lock records it in base64.
+    pub(crate) fn manifest_sha256(&self) -> Result<String> {
+        let digest = base64::engine::general_purpose::STANDARD
+            .decode(self.digest.as_str())
+            .context(format!("invalid digest in lock for '{}'", self))?;
+        Ok(hex::encode(digest))
+    }
+
+    /// The path at which the manifest list for this image is cached, named for its locked digest.
+    pub(crate) fn manifest_cache_path(&self, cache_dir: &Path) -> Result<PathBuf> {
+        let name = self.manifest_sha256()?;
+        Ok(cache_dir.join("manifests").join(format!("{name}.json")))
+    }
+}
+
+/// Calculates the digest used to identify an image in `Twoliter.lock` from its manifest list.
+pub(crate) fn manifest_digest(manifest_bytes: &[u8]) -> String {
+    let digest = sha2::Sha256::digest(manifest_bytes);
+    base64::engine::general_purpose::STANDARD.encode(digest.as_slice())
 }
 
 impl Display for LockedImage {
@@ -98,19 +193,26 @@ impl Hash for LockedImage {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct ImageMetadata {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ImageMetadata {
     /// The name of the kit
-    #[allow(dead_code)]
     pub name: String,
     /// The version of the kit
-    #[allow(dead_code)]
     pub version: Version,
     /// The required sdk of the kit,
     pub sdk: Image,
     /// Any dependent kits
     #[serde(rename = "kit")]
     pub kits: Vec<Image>,
+    /// Why the kit's publisher has deprecated it, if they have
+    #[serde(default)]
+    pub deprecated: Option<String>,
+    /// The last date, as `YYYY-MM-DD`, on which the kit's publisher supports it
+    #[serde(default, rename = "end-of-support")]
+    pub end_of_support: Option<String>,
+    /// The license of the kit, as an SPDX expression, if its publisher declared one
+    #[serde(default)]
+    pub license: Option<String>,
 }
 
 impl TryFrom<EncodedKitMetadata> for ImageMetadata {
@@ -136,7 +238,7 @@ impl EncodedKitMetadata {
         let kit_metadata = EncodedKitMetadata(
             config
                 .labels
-                .get("dev.bottlerocket.kit.v1")
+                .get(KIT_METADATA_LABEL)
                 .context("no metadata stored on image, this image appears to not be a kit")?
                 .to_owned(),
         );
@@ -177,16 +279,31 @@ impl Debug for EncodedKitMetadata {
 }
 
 #[derive(Deserialize, Debug)]
-struct ManifestListView {
-    manifests: Vec<ManifestView>,
+pub(crate) struct ManifestListView {
+    pub(crate) manifests: Vec<ManifestView>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
-struct ManifestView {
-    digest: String,
+pub(crate) struct ManifestView {
+    pub(crate) digest: String,
     platform: Option<Platform>,
 }
 
+impl ManifestView {
+    pub(crate) fn architecture(&self) -> Option<&DockerArchitecture> {
+        self.platform
+            .as_ref()
+            .map(|platform| &platform.architecture)
+    }
+}
+
+fn display_arch(manifest: &ManifestView) -> String {
+    manifest
+        .architecture()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "unknown architecture".to_string())
+}
+
 #[derive(Deserialize, Debug, Clone)]
 struct Platform {
     architecture: DockerArchitecture,
@@ -199,16 +316,34 @@ struct IndexView {
 
 #[derive(Deserialize, Debug)]
 struct ManifestLayoutView {
+    #[serde(default)]
+    config: Option<Layer>,
     layers: Vec<Layer>,
 }
 
-#[derive(Deserialize, Debug)]
-struct Layer {
-    digest: ContainerDigest,
+impl ManifestLayoutView {
+    /// The digests of the blobs the manifest references.
+    fn blobs(&self) -> Vec<String> {
+        self.config
+            .iter()
+            .chain(self.layers.iter())
+            .map(|blob| blob.digest.to_string())
+            .collect()
+    }
 }
 
-#[derive(Debug)]
-struct ContainerDigest(String);
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Layer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) media_type: Option<String>,
+    pub(crate) digest: ContainerDigest,
+    pub(crate) size: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(transparent)]
+pub(crate) struct ContainerDigest(String);
 
 impl<'de> Deserialize<'de> for ContainerDigest {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
@@ -232,6 +367,88 @@ impl Display for ContainerDigest {
     }
 }
 
+/// What a registry holds for a kit or SDK image, as shown by `twoliter inspect image`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ImageInspection {
+    pub(crate) source: String,
+    /// The decoded kit metadata, which SDK images do not have
+    pub(crate) metadata: Option<ImageMetadata>,
+    pub(crate) platforms: Vec<PlatformInspection>,
+}
+
+/// One per-architecture image of an inspected image.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PlatformInspection {
+    pub(crate) architecture: String,
+    pub(crate) digest: String,
+    pub(crate) layers: Vec<Layer>,
+    pub(crate) labels: BTreeMap<String, String>,
+}
+
+impl PlatformInspection {
+    /// The total size of the image's layers.
+    pub(crate) fn size(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.size).sum()
+    }
+}
+
+impl ImageInspection {
+    /// Fetches the manifest list of the image at `uri`, and the layers and labels of each of its
+    /// per-architecture images. An image with a single architecture is inspected as a list of one.
+    /// The kit metadata is decoded from the first image which has it.
+    pub(crate) async fn fetch(image_tool: &ImageTool, uri: &str) -> Result<Self> {
+        let manifest_bytes = image_tool.get_manifest(uri).await?;
+        let manifests = match serde_json::from_slice::<ManifestListView>(&manifest_bytes) {
+            Ok(manifest_list) => manifest_list.manifests,
+            Err(_) => vec![ManifestView {
+                digest: image_tool.digest(uri).await?,
+                platform: None,
+            }],
+        };
+        let repository = oci_cli_wrapper::repository(uri);
+        let platforms: Vec<PlatformInspection> = stream::iter(manifests)
+            .map(|manifest| async move {
+                let image_uri = format!("{repository}@{}", manifest.digest);
+                let manifest_bytes = image_tool.get_manifest(&image_uri).await?;
+                let manifest_layout: ManifestLayoutView =
+                    serde_json::from_slice(manifest_bytes.as_slice())
+                        .context(format!("failed to deserialize manifest of '{image_uri}'"))?;
+                let config = image_tool.get_config(&image_uri).await?;
+                // A single-architecture image names its architecture in its config rather than
+                // in a manifest list.
+                let architecture = match manifest.architecture() {
+                    Some(_) => display_arch(&manifest),
+                    None => config
+                        .architecture
+                        .unwrap_or_else(|| display_arch(&manifest)),
+                };
+                Ok::<_, anyhow::Error>(PlatformInspection {
+                    architecture,
+                    digest: manifest.digest,
+                    layers: manifest_layout.layers,
+                    labels: config.labels.into_iter().collect(),
+                })
+            })
+            .buffered(MAX_CONCURRENT_FETCHES)
+            .try_collect()
+            .await?;
+
+        let metadata = platforms
+            .iter()
+            .find_map(|platform| platform.labels.get(KIT_METADATA_LABEL))
+            .map(|encoded| ImageMetadata::try_from(EncodedKitMetadata(encoded.clone())))
+            .transpose()
+            .context(format!("failed to decode the kit metadata of '{uri}'"))?;
+        Ok(Self {
+            source: uri.to_string(),
+            metadata,
+            platforms,
+        })
+    }
+}
+
 #[derive(Serialize, Debug)]
 struct ExternalKitMetadata {
     sdk: LockedImage,
@@ -240,103 +457,180 @@ struct ExternalKitMetadata {
 }
 
 #[derive(Debug)]
-struct OCIArchive {
+pub(crate) struct OCIArchive {
     image: LockedImage,
     digest: String,
-    cache_dir: PathBuf,
+    store: OciStore,
 }
 
 impl OCIArchive {
-    fn new<P>(image: &LockedImage, digest: &str, cache_dir: P) -> Result<Self>
+    pub(crate) fn new<P>(image: &LockedImage, digest: &str, cache_dir: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
         Ok(Self {
             image: image.clone(),
             digest: digest.into(),
-            cache_dir: cache_dir.as_ref().to_path_buf(),
+            store: OciStore::new(cache_dir),
         })
     }
 
-    fn archive_path(&self) -> PathBuf {
-        self.cache_dir.join(self.digest.replace(':', "-"))
+    /// The directory holding the image's `index.json` and `oci-layout`. Its blobs are kept in the
+    /// cache's shared blob store, see [`OCIArchive::layout_entries`].
+    pub(crate) fn layout_path(&self) -> PathBuf {
+        self.store.image_dir(self.digest.as_str())
     }
 
     #[instrument(level = "trace", skip_all, fields(image = %self.image))]
-    async fn pull_image(&self, image_tool: &ImageTool) -> Result<()> {
+    pub(crate) async fn pull_image(
+        &self,
+        image_tool: &ImageTool,
+        progress: &Progress,
+    ) -> Result<()> {
+        if self.store.has_image(self.digest.as_str())
+            || self.store.migrate_legacy(self.digest.as_str()).await?
+        {
+            debug!("Image '{}' already present -- no need to pull.", self.image);
+            return Ok(());
+        }
         debug!("Pulling image '{}'", self.image);
         let digest_uri = self.image.digest_uri(self.digest.as_str());
-        let oci_archive_path = self.archive_path();
-        if !oci_archive_path.exists() {
-            create_dir_all(&oci_archive_path).await?;
-            image_tool
-                .pull_oci_image(oci_archive_path.as_path(), digest_uri.as_str())
-                .await?;
-        } else {
-            debug!("Image '{}' already present -- no need to pull.", self.image);
+
+        // Layers shared with images which were pulled before are linked from the store rather
+        // than pulled again.
+        let blobs = match image_tool.get_manifest(digest_uri.as_str()).await {
+            Ok(bytes) => serde_json::from_slice::<ManifestLayoutView>(bytes.as_slice())
+                .map(|manifest| manifest.blobs())
+                .unwrap_or_default(),
+            Err(e) => {
+                debug!("Unable to fetch the manifest of '{}': {}", digest_uri, e);
+                Vec::new()
+            }
+        };
+        let staging = self.store.stage(blobs.as_slice()).await?;
+
+        let spinner = progress.spinner(self.image.name.as_str(), "pulling image");
+        let pulled = image_tool
+            .pull_oci_image(staging.as_path(), digest_uri.as_str())
+            .await;
+        if let Err(e) = pulled {
+            spinner.abandon_with_message("failed to pull image");
+            remove_dir_all(&staging).await?;
+            return Err(e.into());
         }
+        spinner.finish_with_message("pulled image");
+        self.store.commit(&staging, self.digest.as_str()).await?;
         Ok(())
     }
 
+    /// Reads the image's manifest, returning its digest along with it.
+    async fn manifest(&self) -> Result<(String, ManifestLayoutView)> {
+        let index_bytes = read(self.layout_path().join(INDEX_FILE)).await?;
+        let index: IndexView = serde_json::from_slice(index_bytes.as_slice())
+            .context("failed to deserialize oci image index")?;
+        let digest = index
+            .manifests
+            .first()
+            .context("empty oci image")?
+            .digest
+            .clone();
+        let manifest_bytes = read(self.store.blob_path(digest.as_str()))
+            .await
+            .context("failed to read manifest blob")?;
+        let manifest_layout: ManifestLayoutView = serde_json::from_slice(manifest_bytes.as_slice())
+            .context("failed to deserialize oci manifest")?;
+        Ok((digest, manifest_layout))
+    }
+
+    /// Reads the kit metadata label from the image's config.
+    async fn kit_metadata(&self) -> Result<EncodedKitMetadata> {
+        let (_, manifest) = self.manifest().await?;
+        let config = manifest
+            .config
+            .context(format!("the manifest of '{}' has no config", self.image))?;
+        let config_bytes = read(self.store.blob_path(config.digest.to_string().as_str()))
+            .await
+            .context("failed to read image config blob")?;
+        let config: serde_json::Value = serde_json::from_slice(config_bytes.as_slice())
+            .context("failed to deserialize image config")?;
+        let encoded = config
+            .pointer(&format!("/config/Labels/{KIT_METADATA_LABEL}"))
+            .and_then(serde_json::Value::as_str)
+            .context("no metadata stored on image, this image appears to not be a kit")?;
+        Ok(EncodedKitMetadata(encoded.to_string()))
+    }
+
+    /// The files which make up the image as a self-contained OCI image layout, as pairs of their
+    /// path on disk and their path within the layout.
+    pub(crate) async fn layout_entries(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let layout_path = self.layout_path();
+        let mut entries = Vec::new();
+        let mut files = tokio::fs::read_dir(&layout_path)
+            .await
+            .context(format!("failed to read '{}'", layout_path.display()))?;
+        while let Some(file) = files.next_entry().await.context(format!(
+            "failed to read entry in '{}'",
+            layout_path.display()
+        ))? {
+            entries.push((file.path(), PathBuf::from(file.file_name())));
+        }
+        let (digest, manifest) = self.manifest().await?;
+        for blob in std::iter::once(digest).chain(manifest.blobs()) {
+            entries.push((
+                self.store.blob_path(blob.as_str()),
+                Path::new("blobs").join(blob.replace(':', "/")),
+            ));
+        }
+        Ok(entries)
+    }
+
     #[instrument(
         level = "trace",
         skip_all,
         fields(image = %self.image, out_dir = %out_dir.as_ref().display()),
     )]
-    async fn unpack_layers<P>(&self, out_dir: P) -> Result<()>
+    async fn unpack_layers<P>(&self, out_dir: P, progress: &Progress) -> Result<()>
     where
         P: AsRef<Path>,
     {
         let path = out_dir.as_ref();
-        let digest_file = path.join("digest");
-        if digest_file.exists() {
-            let digest = read_to_string(&digest_file).await.context(format!(
-                "failed to read digest file at {}",
-                digest_file.display()
-            ))?;
-            if digest == self.digest {
-                trace!(
-                    "Found existing digest file for image '{}' at '{}'",
-                    self.image,
-                    digest_file.display()
-                );
-                return Ok(());
-            }
+        let digest_file = path.join(DIGEST_FILE);
+        if kit_contents::is_intact(path, self.digest.as_str()).await? {
+            trace!(
+                "Found existing, unmodified extraction of image '{}' at '{}'",
+                self.image,
+                path.display()
+            );
+            return Ok(());
         }
 
         debug!("Unpacking layers for image '{}'", self.image);
         remove_dir_all(path).await?;
         create_dir_all(path).await?;
-        let index_bytes = read(self.archive_path().join("index.json")).await?;
-        let index: IndexView = serde_json::from_slice(index_bytes.as_slice())
-            .context("failed to deserialize oci image index")?;
 
         // Read the manifest so we can get the layer digests
         trace!(image = %self.image, "Extracting layer digests from image manifest");
-        let digest = index
-            .manifests
-            .first()
-            .context("empty oci image")?
-            .digest
-            .replace(':', "/");
-        let manifest_bytes = read(self.archive_path().join(format!("blobs/{digest}")))
-            .await
-            .context("failed to read manifest blob")?;
-        let manifest_layout: ManifestLayoutView = serde_json::from_slice(manifest_bytes.as_slice())
-            .context("failed to deserialize oci manifest")?;
+        let (_, manifest_layout) = self.manifest().await?;
 
         // Extract each layer into the target directory
         trace!(image = %self.image, "Extracting image layers");
-        for layer in manifest_layout.layers {
-            let digest = layer.digest.to_string().replace(':', "/");
-            let layer_blob = File::open(self.archive_path().join(format!("blobs/{digest}")))
+        let layer_count = manifest_layout.layers.len();
+        for (index, layer) in manifest_layout.layers.into_iter().enumerate() {
+            let layer_blob = File::open(self.store.blob_path(layer.digest.to_string().as_str()))
                 .context("failed to read layer of oci image")?;
-            let mut layer_archive = TarArchive::new(layer_blob);
-            layer_archive
-                .unpack(path)
-                .context("failed to unpack layer to disk")?;
+            let bar = progress.bytes(self.image.name.as_str(), layer.size);
+            bar.set_message(format!("extracting layer {}/{}", index + 1, layer_count));
+            let (blob, out_dir) = (bar.wrap_read(layer_blob), path.to_path_buf());
+            let unpacked = tokio::task::spawn_blocking(move || {
+                unpack_layer(blob, layer.media_type.as_deref(), &out_dir)
+            })
+            .await
+            .context("layer extraction task panicked")?;
+            bar.finish_and_clear();
+            unpacked.context("failed to unpack layer to disk")?;
         }
-        write(&digest_file, self.digest.as_str())
+        kit_contents::record(path).await?;
+        write_atomic(&digest_file, self.digest.as_str())
             .await
             .context(format!(
                 "failed to record digest to {}",
@@ -347,37 +641,322 @@ impl OCIArchive {
     }
 }
 
+/// How the blob of an image layer is compressed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum LayerCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl LayerCompression {
+    const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &'static [u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    /// Determines the compression from the layer's media type, such as
+    /// `application/vnd.oci.image.layer.v1.tar+zstd`, or from the first bytes of the blob when the
+    /// media type does not say.
+    fn detect(media_type: Option<&str>, header: &[u8]) -> Self {
+        match media_type {
+            Some(media_type) if media_type.ends_with("+gzip") || media_type.ends_with(".gzip") => {
+                Self::Gzip
+            }
+            Some(media_type) if media_type.ends_with("+zstd") || media_type.ends_with(".zstd") => {
+                Self::Zstd
+            }
+            Some(media_type) if media_type.ends_with(".tar") => Self::None,
+            _ if header.starts_with(Self::GZIP_MAGIC) => Self::Gzip,
+            _ if header.starts_with(Self::ZSTD_MAGIC) => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+}
+
+/// Unpacks the layer read from `blob` into `out_dir`, decompressing it as it is read.
+fn unpack_layer<R>(blob: R, media_type: Option<&str>, out_dir: &Path) -> Result<()>
+where
+    R: Read,
+{
+    let mut blob = BufReader::new(blob);
+    let header = blob
+        .fill_buf()
+        .context("failed to read layer of oci image")?;
+    let compression = LayerCompression::detect(media_type, header);
+    trace!(?compression, ?media_type, "Unpacking layer");
+    let reader: Box<dyn Read + '_> = match compression {
+        LayerCompression::None => Box::new(blob),
+        LayerCompression::Gzip => Box::new(MultiGzDecoder::new(blob)),
+        LayerCompression::Zstd => Box::new(
+            zstd::stream::read::Decoder::with_buffer(blob)
+                .context("failed to start decompressing zstd layer")?,
+        ),
+    };
+    TarArchive::new(reader)
+        .unpack(out_dir)
+        .context(format!("failed to unpack {compression:?} compressed layer"))
+}
+
+/// The outcome of resolving a project: the lock itself, the requirement edges between kits, and a
+/// record of how each decision was made.
+pub(crate) struct Resolution {
+    pub(crate) lock: Lock,
+    pub(crate) graph: DependencyGraph,
+    pub(crate) report: ResolutionReport,
+    /// The support status of each locked kit whose publisher has deprecated it or given it an end
+    /// of support
+    pub(crate) support: Vec<KitSupport>,
+}
+
 /// Represents the structure of a `Twoliter.lock` lock file.
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
-pub(crate) struct Lock {
-    /// The version of the Twoliter.toml this was generated from
-    pub schema_version: SchemaVersion<1>,
+pub struct Lock {
+    /// The schema version of this lock file
+    pub schema_version: LockSchemaVersion,
     /// The resolved bottlerocket sdk
     pub sdk: LockedImage,
+    /// The resolved sdks which replace `sdk` when building for particular architectures
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sdk_arch: BTreeMap<String, LockedImage>,
     /// Resolved kit dependencies
     pub kit: Vec<LockedImage>,
 }
 
 #[allow(dead_code)]
 impl Lock {
+    /// Resolves the project's kit and sdk references and writes the result to `Twoliter.lock`.
+    pub async fn create(project: &Project) -> crate::Result<Self> {
+        Ok(Self::create_lock(project).await?)
+    }
+
+    /// Returns the sdk used to build for `arch`.
+    pub(crate) fn sdk_for(&self, arch: &str) -> &LockedImage {
+        self.sdk_arch.get(arch).unwrap_or(&self.sdk)
+    }
+
+    /// The features enabled for the locked kits, as the space-separated `<kit>:<feature>` pairs
+    /// which buildsys reads from `BUILDSYS_KIT_FEATURES`.
+    pub(crate) fn kit_features(&self) -> String {
+        self.kit
+            .iter()
+            .flat_map(|kit| {
+                kit.features
+                    .iter()
+                    .map(move |feature| format!("{}:{feature}", kit.name))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Loads `Twoliter.lock`, ensuring that it matches a fresh resolution of the project.
+    pub async fn load(project: &Project) -> crate::Result<Self> {
+        Ok(Self::load_lock(project).await?)
+    }
+
+    /// Loads `Twoliter.lock` without contacting any registry. The kit metadata which decides the
+    /// transitive kit and sdk dependencies is read from the images in the local image cache, e.g.
+    /// as imported by `twoliter import-deps`.
+    pub async fn load_offline(project: &Project) -> crate::Result<Self> {
+        Ok(Self::load_lock_offline(project).await?)
+    }
+
+    /// Resolves the project's kit and sdk references without reading or writing `Twoliter.lock`.
+    pub async fn resolve(project: &Project) -> crate::Result<Self> {
+        Ok(Self::resolve_lock(project).await?)
+    }
+
+    /// Fetches the external kits named in `kits` to the build directory, or all external kits
+    /// defined in Twoliter.lock if `kits` is empty.
+    pub async fn fetch(
+        &self,
+        project: &Project,
+        arch: &str,
+        kits: &[String],
+        progress: &Progress,
+    ) -> crate::Result<()> {
+        Ok(self.fetch_kits(project, arch, kits, progress).await?)
+    }
+
+    #[instrument(level = "trace", skip(project))]
+    async fn create_lock(project: &Project) -> Result<Self> {
+        Self::create_with_report(project, None, false).await
+    }
+
+    /// Creates `Twoliter.lock` like [`Lock::create`], also writing a report of the registry
+    /// requests made and decisions taken during resolution to `report_path`, if given. Warns about
+    /// each locked kit which is deprecated or near or past its end of support, and fails without
+    /// writing the lock file if `deny_deprecated` is set and any kit is deprecated or past its end
+    /// of support.
     #[instrument(level = "trace", skip(project))]
-    pub(crate) async fn create(project: &Project) -> Result<Self> {
+    pub(crate) async fn create_with_report(
+        project: &Project,
+        report_path: Option<&Path>,
+        deny_deprecated: bool,
+    ) -> Result<Self> {
         let lock_file_path = project.project_dir().join(TWOLITER_LOCK);
 
         info!("Resolving project references to create lock file");
-        let lock_state = Self::resolve(project).await?;
-        let lock_str = toml::to_string(&lock_state).context("failed to serialize lock file")?;
+        let resolution = Self::resolution(project).await?;
+        if let Some(report_path) = report_path {
+            info!("Writing resolution report to '{}'", report_path.display());
+            resolution.report.write(report_path).await?;
+        }
+
+        let today = chrono::Utc::now().date_naive();
+        for support in resolution.support.iter() {
+            warn!("{}", support.notice(today));
+        }
+        let unsupported: Vec<String> = resolution
+            .support
+            .iter()
+            .filter(|support| support.is_unsupported(today))
+            .map(|support| support.notice(today))
+            .collect();
+        ensure!(
+            !deny_deprecated || unsupported.is_empty(),
+            "refusing to lock deprecated or unsupported kits because --deny-deprecated is set:\n  {}",
+            unsupported.join("\n  ")
+        );
+
+        let lock_str =
+            toml::to_string(&resolution.lock).context("failed to serialize lock file")?;
 
         debug!("Writing new lock file to '{}'", lock_file_path.display());
         write(&lock_file_path, lock_str)
             .await
             .context("failed to write lock file")?;
-        Ok(lock_state)
+        Ok(resolution.lock)
     }
 
     #[instrument(level = "trace", skip(project))]
-    pub(crate) async fn load(project: &Project) -> Result<Self> {
+    async fn load_lock(project: &Project) -> Result<Self> {
+        Ok(Self::load_with_graph(project).await?.0)
+    }
+
+    /// Loads `Twoliter.lock` like [`Lock::load`], also returning the requirement edges discovered
+    /// while resolving the project.
+    #[instrument(level = "trace", skip(project))]
+    pub(crate) async fn load_with_graph(project: &Project) -> Result<(Self, DependencyGraph)> {
+        let lock = Self::read_lock_file(project).await?;
+
+        info!("Resolving project references to check against lock file");
+        let Resolution {
+            lock: lock_state,
+            graph,
+            ..
+        } = Self::resolution(project).await?;
+
+        ensure!(lock_state == lock, "changes have occured to Twoliter.toml or the remote kit images that require an update to Twoliter.lock");
+        Ok((lock, graph))
+    }
+
+    #[instrument(level = "trace", skip(project))]
+    async fn load_lock_offline(project: &Project) -> Result<Self> {
+        let lock = Self::read_lock_file(project).await?;
+        info!("Checking project references against lock file without network access");
+        let locks = |locked: &LockedImage, image: &Image| {
+            locked.name == image.name.to_string()
+                && locked.vendor == image.vendor.to_string()
+                && locked.version == image.version
+        };
+        let cache_dir = project.oci_cache_dir();
+        let mut remaining = project.kits();
+        let mut checked = HashSet::new();
+        while let Some(kit) = remaining.pop() {
+            let locked = lock
+                .kit
+                .iter()
+                .find(|locked| locks(locked, &kit) && locked.features == project.kit_features(&kit))
+                .context(format!(
+                    "kit '{kit}' is not in Twoliter.lock, please run `twoliter update`"
+                ))?;
+            if !checked.insert(locked.digest.clone()) {
+                continue;
+            }
+            let metadata = Self::cached_kit_metadata(locked, &cache_dir).await?;
+            ensure!(
+                locks(&lock.sdk, &metadata.sdk),
+                "kit '{kit}' requires sdk '{}', which does not match Twoliter.lock, please run \
+                `twoliter update`",
+                metadata.sdk
+            );
+            remaining.extend(metadata.kits);
+        }
+        let stale: Vec<String> = lock
+            .kit
+            .iter()
+            .filter(|locked| !checked.contains(&locked.digest))
+            .map(ToString::to_string)
+            .collect();
+        ensure!(
+            stale.is_empty(),
+            "Twoliter.lock has kits which the project no longer uses ({}), please run \
+            `twoliter update`",
+            stale.join(", ")
+        );
+        if let Some(sdk) = project.sdk_image() {
+            ensure!(
+                locks(&lock.sdk, &sdk),
+                "sdk '{sdk}' does not match Twoliter.lock, please run `twoliter update`"
+            );
+        }
+        for (arch, sdk) in project.sdk_arch_images() {
+            ensure!(
+                lock.sdk_arch
+                    .get(arch)
+                    .is_some_and(|locked| locks(locked, sdk)),
+                "{arch} sdk '{sdk}' does not match Twoliter.lock, please run `twoliter update`"
+            );
+        }
+        Ok(lock)
+    }
+
+    /// Reads the metadata of a locked kit from whichever of its images is in the local image cache
+    /// at `cache_dir`.
+    pub(crate) async fn cached_kit_metadata(
+        kit: &LockedImage,
+        cache_dir: &Path,
+    ) -> Result<ImageMetadata> {
+        let digests: Vec<String> = if kit.arch_digests.is_empty() {
+            let cache_path = kit.manifest_cache_path(cache_dir)?;
+            let manifest_bytes = if cache_path.exists() {
+                read(&cache_path).await?
+            } else {
+                Vec::new()
+            };
+            ensure!(
+                manifest_digest(manifest_bytes.as_slice()) == kit.digest,
+                "the manifest list of kit '{kit}' is not in the local image cache, please run \
+                `twoliter import-deps` or fetch without --offline"
+            );
+            let manifest_list: ManifestListView = serde_json::from_slice(&manifest_bytes)
+                .context("failed to deserialize manifest list")?;
+            manifest_list
+                .manifests
+                .into_iter()
+                .map(|manifest| manifest.digest)
+                .collect()
+        } else {
+            kit.arch_digests.values().cloned().collect()
+        };
+        for digest in digests {
+            let archive = OCIArchive::new(kit, digest.as_str(), cache_dir)?;
+            if archive.store.has_image(digest.as_str()) {
+                return archive
+                    .kit_metadata()
+                    .await?
+                    .try_into()
+                    .context(format!("failed to decode the kit metadata of '{kit}'"));
+            }
+        }
+        bail!(
+            "no image of kit '{kit}' is in the local image cache, please run \
+            `twoliter import-deps` or fetch without --offline"
+        )
+    }
+
+    /// Reads `Twoliter.lock` from the project directory.
+    pub(crate) async fn read_lock_file(project: &Project) -> Result<Self> {
         let lock_file_path = project.project_dir().join(TWOLITER_LOCK);
         ensure!(
             lock_file_path.exists(),
@@ -387,14 +966,45 @@ impl Lock {
         let lock_str = read_to_string(&lock_file_path)
             .await
             .context("failed to read lockfile")?;
-        let lock: Self =
-            toml::from_str(lock_str.as_str()).context("failed to deserialize lockfile")?;
-
-        info!("Resolving project references to check against lock file");
-        let lock_state = Self::resolve(project).await?;
+        toml::from_str(lock_str.as_str()).context("failed to deserialize lockfile")
+    }
 
-        ensure!(lock_state == lock, "changes have occured to Twoliter.toml or the remote kit images that require an update to Twoliter.lock");
-        Ok(lock)
+    /// Returns the manifest list of a locked image, preferring the copy cached in `cache_dir` and
+    /// otherwise fetching it from the registry and caching it. Either way, the manifest list is
+    /// checked against the digest recorded in the lock.
+    pub(crate) async fn manifest_list(
+        image_tool: &ImageTool,
+        image: &LockedImage,
+        cache_dir: &Path,
+    ) -> Result<ManifestListView> {
+        let cache_path = image.manifest_cache_path(cache_dir)?;
+        let cached = if cache_path.exists() {
+            Some(read(&cache_path).await?)
+        } else {
+            None
+        };
+        let manifest_bytes = if let Some(cached) =
+            cached.filter(|bytes| manifest_digest(bytes.as_slice()) == image.digest)
+        {
+            trace!(
+                "Using cached manifest list for '{}' from '{}'",
+                image,
+                cache_path.display()
+            );
+            cached
+        } else {
+            let manifest_bytes = image_tool.get_manifest(image.source.as_str()).await?;
+            ensure!(
+                manifest_digest(manifest_bytes.as_slice()) == image.digest,
+                "the manifest list for '{}' no longer matches Twoliter.lock",
+                image
+            );
+            create_dir_all(cache_dir.join("manifests")).await?;
+            write(&cache_path, manifest_bytes.as_slice()).await?;
+            manifest_bytes
+        };
+        serde_json::from_slice(manifest_bytes.as_slice())
+            .context("failed to deserialize manifest list")
     }
 
     fn external_kit_metadata(&self) -> ExternalKitMetadata {
@@ -404,10 +1014,63 @@ impl Lock {
         }
     }
 
-    /// Fetches all external kits defined in a Twoliter.lock to the build directory
+    /// Returns the locked kits with the given names, or every locked kit if `names` is empty. A
+    /// name may be given as `<name>@<vendor>` to choose between kits of the same name published by
+    /// different vendors.
+    pub fn select_kits(&self, names: &[String]) -> crate::Result<Vec<&LockedImage>> {
+        if names.is_empty() {
+            return Ok(self.kit.iter().collect());
+        }
+        names
+            .iter()
+            .map(|name| self.select_kit(name))
+            .collect::<Result<_>>()
+            .map_err(Into::into)
+    }
+
+    fn select_kit(&self, name: &str) -> Result<&LockedImage> {
+        let (kit, vendor) = match name.split_once('@') {
+            Some((kit, vendor)) => (kit, Some(vendor)),
+            None => (name, None),
+        };
+        let matches: Vec<&LockedImage> = self
+            .kit
+            .iter()
+            .filter(|image| {
+                image.name == kit && vendor.iter().all(|vendor| image.vendor == *vendor)
+            })
+            .collect();
+        match matches.as_slice() {
+            [image] => Ok(image),
+            [] => bail!(
+                "kit '{name}' is not in Twoliter.lock (available kits: {})",
+                self.kit
+                    .iter()
+                    .map(|image| format!("{}@{}", image.name, image.vendor))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            _ => bail!(
+                "kit '{name}' is published by more than one vendor, choose one of: {}",
+                matches
+                    .iter()
+                    .map(|image| format!("{}@{}", image.name, image.vendor))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
     #[instrument(level = "trace", skip_all)]
-    pub(crate) async fn fetch(&self, project: &Project, arch: &str) -> Result<()> {
-        let image_tool = ImageTool::from_environment()?;
+    async fn fetch_kits(
+        &self,
+        project: &Project,
+        arch: &str,
+        kits: &[String],
+        progress: &Progress,
+    ) -> Result<()> {
+        let selected = self.select_kits(kits)?;
+        let image_tool = project.image_tool()?;
         let target_dir = project.external_kits_dir();
         create_dir_all(&target_dir).await.context(format!(
             "failed to create external-kits directory at {}",
@@ -415,13 +1078,30 @@ impl Lock {
         ))?;
 
         info!(
-            dependencies = ?self.kit.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            dependencies = ?selected.iter().map(ToString::to_string).collect::<Vec<_>>(),
             "Extracting kit dependencies."
         );
-        for image in self.kit.iter() {
-            self.extract_kit(&image_tool, &project.external_kits_dir(), image, arch)
-                .await?;
+        let kit_cache = project.kit_cache();
+        if let Some(kit_cache) = kit_cache.as_ref() {
+            debug!(?kit_cache, "Using shared kit cache");
         }
+        let image_tool = &image_tool;
+        let kit_cache = kit_cache.as_ref();
+        stream::iter(selected)
+            .map(|image| async move {
+                self.extract_kit(
+                    image_tool,
+                    &project.external_kits_dir(),
+                    image,
+                    arch,
+                    kit_cache,
+                    progress,
+                )
+                .await
+            })
+            .buffer_unordered(MAX_CONCURRENT_EXTRACTIONS)
+            .try_collect::<()>()
+            .await?;
         let mut kit_list = Vec::new();
         let mut ser =
             serde_json::Serializer::with_formatter(&mut kit_list, CanonicalJsonFormatter::new());
@@ -456,11 +1136,19 @@ impl Lock {
         image_tool: &ImageTool,
         image: &LockedImage,
         arch: &str,
+        cache_dir: &Path,
     ) -> Result<ManifestView> {
-        let manifest_bytes = image_tool.get_manifest(image.source.as_str()).await?;
-        let manifest_list: ManifestListView = serde_json::from_slice(manifest_bytes.as_slice())
-            .context("failed to deserialize manifest list")?;
         let docker_arch = DockerArchitecture::try_from(arch)?;
+        if let Some(digest) = image.arch_digests.get(&docker_arch.to_string()) {
+            trace!("Using the locked '{}' digest of '{}'", docker_arch, image);
+            return Ok(ManifestView {
+                digest: digest.clone(),
+                platform: Some(Platform {
+                    architecture: docker_arch,
+                }),
+            });
+        }
+        let manifest_list = Self::manifest_list(image_tool, image, cache_dir).await?;
         manifest_list
             .manifests
             .iter()
@@ -474,7 +1162,7 @@ impl Lock {
 
     #[instrument(
         level = "trace",
-        skip(image),
+        skip(image, kit_cache, progress),
         fields(image = %image, path = %path.as_ref().display())
     )]
     async fn extract_kit<P>(
@@ -483,6 +1171,8 @@ impl Lock {
         path: P,
         image: &LockedImage,
         arch: &str,
+        kit_cache: Option<&KitCache>,
+        progress: &Progress,
     ) -> Result<()>
     where
         P: AsRef<Path>,
@@ -495,32 +1185,57 @@ impl Lock {
         let vendor = image.vendor.clone();
         let name = image.name.clone();
         let target_path = path.as_ref().join(format!("{vendor}/{name}/{arch}"));
-        let cache_path = path.as_ref().join("cache");
+        let cache_path = match kit_cache {
+            Some(kit_cache) => kit_cache.oci_dir(),
+            None => path.as_ref().join("cache"),
+        };
         create_dir_all(&target_path).await?;
         create_dir_all(&cache_path).await?;
 
         // First get the manifest for the specific requested architecture
-        let manifest = self.get_manifest(image_tool, image, arch).await?;
+        let manifest = self
+            .get_manifest(image_tool, image, arch, &cache_path)
+            .await?;
         let oci_archive = OCIArchive::new(image, manifest.digest.as_str(), &cache_path)?;
 
         // Checks for the saved image locally, or else pulls and saves it
-        oci_archive.pull_image(image_tool).await?;
+        oci_archive.pull_image(image_tool, progress).await?;
 
         // Checks if this archive has already been extracted by checking a digest file
         // otherwise cleans up the path and unpacks the archive
-        oci_archive.unpack_layers(&target_path).await?;
+        match kit_cache {
+            Some(kit_cache) => {
+                let unpacked = kit_cache.unpacked_dir(manifest.digest.as_str());
+                if !kit_contents::is_intact(&unpacked, manifest.digest.as_str()).await? {
+                    let staging = kit_cache.staging_dir();
+                    oci_archive.unpack_layers(&staging, progress).await?;
+                    kit_cache.commit(&staging, manifest.digest.as_str()).await?;
+                }
+                kit_cache::link_unpacked(&unpacked, &target_path, manifest.digest.as_str()).await?;
+            }
+            None => oci_archive.unpack_layers(&target_path, progress).await?,
+        }
 
         Ok(())
     }
 
     #[instrument(level = "trace", skip(project))]
-    async fn resolve(project: &Project) -> Result<Self> {
+    async fn resolve_lock(project: &Project) -> Result<Self> {
+        Ok(Self::resolution(project).await?.lock)
+    }
+
+    #[instrument(level = "trace", skip(project))]
+    async fn resolution(project: &Project) -> Result<Resolution> {
         let vendor_table = project.vendor();
+        let policy = project.kit_metadata_mismatch();
         let mut known: HashMap<(ValidIdentifier, ValidIdentifier), Version> = HashMap::new();
         let mut locked: Vec<LockedImage> = Vec::new();
-        let image_tool = ImageTool::from_environment()?;
+        let image_tool = project.image_tool()?;
 
         let mut remaining: Vec<Image> = project.kits();
+        let mut graph = DependencyGraph::new(remaining.clone());
+        let mut report = ResolutionReport::default();
+        let mut support = Vec::new();
         let mut sdk_set: HashSet<Image> = HashSet::new();
         if let Some(sdk) = project.sdk_image() {
             // We don't scan over the sdk images as they are not kit images and there is no kit metadata to fetch
@@ -528,6 +1243,9 @@ impl Lock {
         }
         while !remaining.is_empty() {
             let working_set: Vec<_> = take(&mut remaining);
+            // Decide which kits in this working set need resolving in order, so that the lock
+            // output is deterministic, then fetch them concurrently.
+            let mut to_resolve = Vec::new();
             for image in working_set.iter() {
                 debug!(%image, "Resolving kit '{}'", image.name);
                 if let Some(version) = known.get(&(image.name.clone(), image.vendor.clone())) {
@@ -542,6 +1260,7 @@ impl Lock {
                         ?image,
                         "Skipping kit '{}' as it has already been resolved", image.name
                     );
+                    report.already_locked(image);
                     continue;
                 }
                 let vendor = vendor_table.get(&image.vendor).context(format!(
@@ -552,8 +1271,32 @@ impl Lock {
                     (image.name.clone(), image.vendor.clone()),
                     image.version.clone(),
                 );
-                let locked_image = LockedImage::new(&image_tool, vendor, image).await?;
-                let kit = Self::find_kit(&image_tool, vendor, &locked_image).await?;
+                to_resolve.push((image, vendor));
+            }
+
+            let image_tool = &image_tool;
+            let resolved: Vec<(&Image, LockedImage, ImageMetadata, Vec<MetadataRecord>)> =
+                stream::iter(to_resolve)
+                    .map(|(image, vendor)| async move {
+                        let mut locked_image = LockedImage::new(image_tool, vendor, image).await?;
+                        locked_image.features = project.kit_features(image).to_vec();
+                        let (kit, records) =
+                            Self::find_kit(image_tool, vendor, &locked_image, policy).await?;
+                        Ok::<_, anyhow::Error>((image, locked_image, kit, records))
+                    })
+                    .buffered(MAX_CONCURRENT_FETCHES)
+                    .try_collect()
+                    .await?;
+            for (image, locked_image, kit, records) in resolved {
+                report.record_manifest(&locked_image);
+                report.record_metadata(records);
+                report.lock_kit(&locked_image);
+                support.extend(KitSupport::new(
+                    image.to_string(),
+                    kit.deprecated.clone(),
+                    kit.end_of_support.as_deref(),
+                ));
+                graph.insert(image.clone(), kit.sdk.clone(), kit.kits.clone());
                 locked.push(locked_image);
                 sdk_set.insert(kit.sdk);
                 for dep in kit.kits {
@@ -562,6 +1305,17 @@ impl Lock {
             }
         }
 
+        if let Some(collisions) = graph.name_collisions() {
+            let message = format!(
+                "{collisions}Kits from different vendors are extracted separately, but their \
+                packages are offered to builds together. Check that the 'vendor' of each [[kit]] \
+                in Twoliter.toml names the intended publisher, and refer to a single provider as \
+                '<name>@<vendor>', e.g. with 'twoliter fetch --kit'."
+            );
+            ensure!(!project.is_strict(), message);
+            warn!("{message}");
+        }
+
         debug!(?sdk_set, "Resolving workspace SDK");
         ensure!(
             sdk_set.len() <= 1,
@@ -580,10 +1334,58 @@ impl Lock {
             "vendor '{}' is not specified in Twoliter.toml",
             sdk.vendor
         ))?;
-        Ok(Self {
-            schema_version: project.schema_version(),
-            sdk: LockedImage::new(&image_tool, vendor, sdk).await?,
-            kit: locked,
+
+        // Per-architecture sdks are the project's explicit choice, so they are not checked against
+        // the sdk that kits were built with. They are fetched alongside the sdk.
+        let image_tool = &image_tool;
+        let sdk_arch_images = project
+            .sdk_arch_images()
+            .iter()
+            .map(|(arch, image)| {
+                let vendor = vendor_table.get(&image.vendor).context(format!(
+                    "vendor '{}' is not specified in Twoliter.toml",
+                    image.vendor
+                ))?;
+                Ok((arch, image, vendor))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (mut sdk, mut sdk_arch) = futures::try_join!(
+            LockedImage::new(image_tool, vendor, sdk),
+            stream::iter(sdk_arch_images)
+                .map(|(arch, image, vendor)| async move {
+                    let locked_sdk = LockedImage::new(image_tool, vendor, image).await?;
+                    Ok::<_, anyhow::Error>((arch.clone(), locked_sdk))
+                })
+                .buffered(MAX_CONCURRENT_FETCHES)
+                .try_collect::<BTreeMap<_, _>>(),
+        )?;
+        report.record_manifest(&sdk);
+        report.choose_sdk(&sdk, &sdk_set);
+        for locked_sdk in sdk_arch.values() {
+            report.record_manifest(locked_sdk);
+        }
+
+        let schema_version = project.lock_schema_version();
+        if schema_version == LockSchemaVersion::V2 {
+            sdk.record_arch_digests()?;
+            for sdk in sdk_arch.values_mut() {
+                sdk.record_arch_digests()?;
+            }
+            for kit in locked.iter_mut() {
+                kit.record_arch_digests()?;
+            }
+        }
+        report.record_retries(image_tool.retries());
+        Ok(Resolution {
+            lock: Self {
+                schema_version,
+                sdk,
+                sdk_arch,
+                kit: locked,
+            },
+            graph,
+            report,
+            support,
         })
     }
 
@@ -592,39 +1394,90 @@ impl Lock {
         image_tool: &ImageTool,
         vendor: &Vendor,
         image: &LockedImage,
-    ) -> Result<ImageMetadata> {
+        policy: MetadataMismatchPolicy,
+    ) -> Result<(ImageMetadata, Vec<MetadataRecord>)> {
         debug!(kit_image = %image, "Searching for kit");
         let manifest_list: ManifestListView = serde_json::from_slice(image.manifest.as_slice())
             .context("failed to deserialize manifest list")?;
         trace!(manifest_list = ?manifest_list, "Deserialized manifest list");
         debug!("Extracting kit metadata from OCI image");
-        let embedded_kit_metadata =
-            stream::iter(manifest_list.manifests).then(|manifest| async move {
-                let image_uri = format!("{}/{}@{}", vendor.registry, image.name, manifest.digest);
-                EncodedKitMetadata::try_from_image(&image_uri, image_tool).await
-            });
+        let embedded_kit_metadata = stream::iter(manifest_list.manifests)
+            .map(|manifest| async move {
+                let image_uri =
+                    format!("{}/{}@{}", vendor.repository(), image.name, manifest.digest);
+                let metadata = EncodedKitMetadata::try_from_image(&image_uri, image_tool).await?;
+                Ok::<_, anyhow::Error>((manifest, image_uri, metadata))
+            })
+            .buffered(MAX_CONCURRENT_FETCHES);
         pin_mut!(embedded_kit_metadata);
 
-        let canonical_metadata = embedded_kit_metadata
+        let (canonical_manifest, canonical_uri, canonical_metadata) = embedded_kit_metadata
             .try_next()
             .await?
             .context(format!("could not find metadata for kit {}", image))?;
+        let mut records = vec![metadata_record(
+            image,
+            canonical_uri,
+            &canonical_manifest,
+            &canonical_metadata,
+        )];
 
         trace!("Checking that all manifests refer to the same kit.");
-        while let Some(kit_metadata) = embedded_kit_metadata.try_next().await? {
+        while let Some((manifest, image_uri, kit_metadata)) =
+            embedded_kit_metadata.try_next().await?
+        {
+            records.push(metadata_record(image, image_uri, &manifest, &kit_metadata));
             if kit_metadata != canonical_metadata {
-                error!(
-                    ?canonical_metadata,
-                    ?kit_metadata,
-                    "Mismatched kit metadata in manifest list"
-                );
-                bail!("Metadata does not match between images in manifest list");
+                match policy {
+                    MetadataMismatchPolicy::Strict => {
+                        error!(
+                            ?canonical_metadata,
+                            ?kit_metadata,
+                            "Mismatched kit metadata in manifest list"
+                        );
+                        bail!("Metadata does not match between images in manifest list");
+                    }
+                    MetadataMismatchPolicy::Warn => {
+                        warn!(
+                            ?canonical_metadata,
+                            ?kit_metadata,
+                            "Kit metadata for '{}' differs between architectures: the {} image \
+                            ({}) does not match the {} image ({}). Continuing with the metadata \
+                            of the {} image because kit-metadata-mismatch is set to 'warn'.",
+                            image,
+                            display_arch(&manifest),
+                            manifest.digest,
+                            display_arch(&canonical_manifest),
+                            canonical_manifest.digest,
+                            display_arch(&canonical_manifest),
+                        );
+                    }
+                }
             }
         }
 
-        canonical_metadata
+        let metadata = canonical_metadata
             .try_into()
-            .context("Failed to decode and parse kit metadata")
+            .context("Failed to decode and parse kit metadata")?;
+        Ok((metadata, records))
+    }
+}
+
+/// Describes the kit metadata found in one per-architecture image for the resolution report.
+fn metadata_record(
+    image: &LockedImage,
+    uri: String,
+    manifest: &ManifestView,
+    metadata: &EncodedKitMetadata,
+) -> MetadataRecord {
+    let decoded = ImageMetadata::try_from(metadata.clone()).ok();
+    MetadataRecord {
+        image: image_id(image),
+        uri,
+        architecture: display_arch(manifest),
+        label: metadata.0.clone(),
+        sdk: decoded.as_ref().map(|decoded| decoded.sdk.to_string()),
+        kits: decoded.map(|decoded| decoded.kits.iter().map(ToString::to_string).collect()),
     }
 }
 
@@ -654,4 +1507,278 @@ mod test {
         let junk_data = EncodedKitMetadata("abcdefghijklmnophello".to_string());
         assert!(junk_data.debug_image_metadata().is_none());
     }
+
+    fn locked_image(name: &str) -> LockedImage {
+        LockedImage {
+            name: name.to_string(),
+            version: Version::new(1, 0, 0),
+            vendor: "my-vendor".to_string(),
+            source: format!("a.com/b/{name}:v1.0.0"),
+            digest: String::new(),
+            arch_digests: BTreeMap::new(),
+            features: Vec::new(),
+            manifest: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_kit_metadata_reads_the_image_config() {
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path());
+        let put_blob = |bytes: &[u8]| {
+            let digest = format!("sha256:{:x}", sha2::Sha256::digest(bytes));
+            let path = store.blob_path(&digest);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, bytes).unwrap();
+            digest
+        };
+        let encoded =
+            "eyJraXQiOltdLCJuYW1lIjoiYm90dGxlcm9ja2V0LWNvcmUta2l0Iiwic2RrIjp7ImRpZ2VzdCI6I\
+            mlyY09EUld3ZmxjTTdzaisrMmszSk5RWkovb3ZDUVRpUlkrRFpvaGdrNlk9IiwibmFtZSI6InRoYXItYmUtYm\
+            V0YS1zZGsiLCJzb3VyY2UiOiJwdWJsaWMuZWNyLmF3cy91MWczYzh6NC90aGFyLWJlLWJldGEtc2RrOnYwLjQz\
+            LjAiLCJ2ZW5kb3IiOiJib3R0bGVyb2NrZXQtbmV3IiwidmVyc2lvbiI6IjAuNDMuMCJ9LCJ2ZXJzaW9uIjoiM\
+            i4wLjAifQo=";
+        let config = serde_json::json!({"config": {"Labels": {KIT_METADATA_LABEL: encoded}}});
+        let config_digest = put_blob(config.to_string().as_bytes());
+        let manifest = serde_json::json!({
+            "config": {"digest": config_digest, "size": 0},
+            "layers": [],
+        });
+        let manifest_digest = put_blob(manifest.to_string().as_bytes());
+        let image_dir = store.image_dir(&manifest_digest);
+        std::fs::create_dir_all(&image_dir).unwrap();
+        let index = serde_json::json!({"manifests": [{"digest": manifest_digest}]});
+        std::fs::write(image_dir.join(INDEX_FILE), index.to_string()).unwrap();
+
+        let mut kit = locked_image("bottlerocket-core-kit");
+        kit.arch_digests
+            .insert("amd64".to_string(), manifest_digest.clone());
+        let metadata = Lock::cached_kit_metadata(&kit, tempdir.path())
+            .await
+            .unwrap();
+        assert_eq!(metadata.name, "bottlerocket-core-kit");
+        assert_eq!(metadata.sdk.name.to_string(), "thar-be-beta-sdk");
+        assert!(metadata.kits.is_empty());
+
+        // Without the image in the cache, the metadata can't be known offline.
+        kit.arch_digests
+            .insert("amd64".to_string(), format!("sha256:{}", "0".repeat(64)));
+        assert!(Lock::cached_kit_metadata(&kit, tempdir.path())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn inspects_single_architecture_image() {
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let layout = tempdir.path().join("kit");
+        let put_blob = |bytes: &[u8]| {
+            let digest = format!("sha256:{:x}", sha2::Sha256::digest(bytes));
+            let path = layout.join("blobs").join(digest.replace(':', "/"));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, bytes).unwrap();
+            (digest, bytes.len())
+        };
+        let config = serde_json::json!({
+            "architecture": "arm64",
+            "config": {"Labels": {"org.example": "yes"}},
+        });
+        let (config_digest, config_size) = put_blob(config.to_string().as_bytes());
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "config": {"digest": config_digest, "size": config_size},
+            "layers": [],
+        });
+        let (manifest_digest, manifest_size) = put_blob(manifest.to_string().as_bytes());
+        let index = serde_json::json!({"manifests": [{
+            "digest": manifest_digest,
+            "size": manifest_size,
+            "annotations": {"org.opencontainers.image.ref.name": "v1"},
+        }]});
+        std::fs::write(layout.join("index.json"), index.to_string()).unwrap();
+        std::fs::write(
+            layout.join("oci-layout"),
+            r#"{"imageLayoutVersion":"1.0.0"}"#,
+        )
+        .unwrap();
+
+        let image_tool = ImageTool::from_tool_path("crane", Path::new("/bin/true")).unwrap();
+        let uri = format!("oci-layout:{}:v1", layout.display());
+        let inspection = ImageInspection::fetch(&image_tool, &uri).await.unwrap();
+        assert!(inspection.metadata.is_none());
+        assert_eq!(inspection.platforms.len(), 1);
+        let platform = &inspection.platforms[0];
+        assert_eq!(platform.architecture, "arm64");
+        assert_eq!(platform.digest, manifest_digest);
+        assert_eq!(platform.labels["org.example"], "yes");
+    }
+
+    #[test]
+    fn test_lock_schema_v2_round_trip() {
+        let lock_str = r#"
+schema-version = 2
+kit = []
+
+[sdk]
+name = "sdk"
+version = "1.0.0"
+vendor = "my-vendor"
+source = "a.com/b/sdk:v1.0.0"
+digest = "abc="
+
+[sdk.arch-digests]
+amd64 = "sha256:1111"
+arm64 = "sha256:2222"
+"#;
+        let lock: Lock = toml::from_str(lock_str).unwrap();
+        assert_eq!(lock.schema_version, LockSchemaVersion::V2);
+        assert_eq!(lock.sdk.arch_digests.get("arm64").unwrap(), "sha256:2222");
+        let reserialized: Lock = toml::from_str(&toml::to_string(&lock).unwrap()).unwrap();
+        assert_eq!(reserialized, lock);
+
+        // Version 1 lock files do not record per-arch digests.
+        let v1: Lock =
+            toml::from_str(&lock_str.replace("schema-version = 2", "schema-version = 1")).unwrap();
+        assert_eq!(v1.schema_version, LockSchemaVersion::V1);
+        assert!(toml::from_str::<Lock>(&lock_str.replace("= 2", "= 3")).is_err());
+    }
+
+    #[test]
+    fn test_sdk_for_arch() {
+        let lock = Lock {
+            schema_version: LockSchemaVersion::V1,
+            sdk: locked_image("sdk"),
+            sdk_arch: BTreeMap::from([("aarch64".to_string(), locked_image("experimental-sdk"))]),
+            kit: Vec::new(),
+        };
+        assert_eq!(lock.sdk_for("x86_64").name, "sdk");
+        assert_eq!(lock.sdk_for("aarch64").name, "experimental-sdk");
+
+        let lock_str = toml::to_string(&lock).unwrap();
+        assert!(lock_str.contains("[sdk-arch.aarch64]"));
+        assert_eq!(toml::from_str::<Lock>(&lock_str).unwrap(), lock);
+        let without_overrides = Lock {
+            sdk_arch: BTreeMap::new(),
+            ..lock
+        };
+        assert!(!toml::to_string(&without_overrides)
+            .unwrap()
+            .contains("sdk-arch"));
+    }
+
+    #[test]
+    fn test_kit_features() {
+        let mut core_kit = locked_image("core-kit");
+        core_kit.features = vec!["fips".to_string(), "nvidia".to_string()];
+        let lock = Lock {
+            schema_version: LockSchemaVersion::V1,
+            sdk: locked_image("sdk"),
+            sdk_arch: BTreeMap::new(),
+            kit: vec![core_kit, locked_image("extra-kit")],
+        };
+        assert_eq!(lock.kit_features(), "core-kit:fips core-kit:nvidia");
+
+        let serialized = toml::to_string(&lock).unwrap();
+        assert_eq!(serialized.matches("features = ").count(), 1);
+        let deserialized: Lock = toml::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, lock);
+    }
+
+    #[test]
+    fn test_select_kits() {
+        let lock = Lock {
+            schema_version: LockSchemaVersion::V1,
+            sdk: locked_image("sdk"),
+            sdk_arch: BTreeMap::new(),
+            kit: vec![locked_image("core-kit"), locked_image("extra-kit")],
+        };
+        assert_eq!(lock.select_kits(&[]).unwrap().len(), 2);
+        let selected = lock.select_kits(&["extra-kit".to_string()]).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name, "extra-kit");
+        assert!(lock.select_kits(&["missing-kit".to_string()]).is_err());
+
+        let mut other_vendor = locked_image("extra-kit");
+        other_vendor.vendor = "other-vendor".to_string();
+        let lock = Lock {
+            kit: vec![locked_image("extra-kit"), other_vendor],
+            ..lock
+        };
+        assert!(lock.select_kits(&["extra-kit".to_string()]).is_err());
+        let selected = lock
+            .select_kits(&["extra-kit@other-vendor".to_string()])
+            .unwrap();
+        assert_eq!(selected[0].vendor, "other-vendor");
+    }
+
+    #[test]
+    fn test_detect_layer_compression() {
+        let detect = LayerCompression::detect;
+        assert_eq!(
+            detect(Some("application/vnd.oci.image.layer.v1.tar+gzip"), b""),
+            LayerCompression::Gzip
+        );
+        assert_eq!(
+            detect(
+                Some("application/vnd.docker.image.rootfs.diff.tar.gzip"),
+                b""
+            ),
+            LayerCompression::Gzip
+        );
+        assert_eq!(
+            detect(Some("application/vnd.oci.image.layer.v1.tar+zstd"), b""),
+            LayerCompression::Zstd
+        );
+        assert_eq!(
+            detect(
+                Some("application/vnd.oci.image.layer.v1.tar"),
+                &[0x1f, 0x8b]
+            ),
+            LayerCompression::None
+        );
+        assert_eq!(detect(None, &[0x1f, 0x8b, 0x08]), LayerCompression::Gzip);
+        assert_eq!(
+            detect(None, &[0x28, 0xb5, 0x2f, 0xfd]),
+            LayerCompression::Zstd
+        );
+        assert_eq!(detect(None, b"rpms/"), LayerCompression::None);
+    }
+
+    #[test]
+    fn test_unpack_compressed_layers() {
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "rpms/a.rpm", &b"abc"[..])
+            .unwrap();
+        let tar = tar.into_inner().unwrap();
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut gzip, &tar).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(tar.as_slice(), 0).unwrap();
+
+        for (blob, media_type) in [
+            (tar.clone(), Some("application/vnd.oci.image.layer.v1.tar")),
+            (
+                gzip.clone(),
+                Some("application/vnd.oci.image.layer.v1.tar+gzip"),
+            ),
+            (
+                zstd.clone(),
+                Some("application/vnd.oci.image.layer.v1.tar+zstd"),
+            ),
+            (gzip, None),
+            (zstd, None),
+        ] {
+            let out_dir = tempfile::TempDir::new().unwrap();
+            unpack_layer(blob.as_slice(), media_type, out_dir.path()).unwrap();
+            assert_eq!(
+                std::fs::read(out_dir.path().join("rpms/a.rpm")).unwrap(),
+                b"abc"
+            );
+        }
+    }
 }
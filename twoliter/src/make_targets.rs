@@ -0,0 +1,146 @@
+//! Lists the tasks of the embedded `Makefile.toml` which can be run with `twoliter make`, so that
+//! users do not need to read the makefile to find out what is available.
+use crate::cargo_make::is_build_system_env;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use toml::{Table, Value};
+
+const MAKEFILE: &str = include_str!("../embedded/Makefile.toml");
+
+/// A task in the embedded `Makefile.toml`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct MakeTarget {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    /// The environment variables, passed through by `twoliter make`, which the task reads
+    pub(crate) env_vars: BTreeSet<String>,
+}
+
+impl Display for MakeTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(description) = &self.description {
+            write!(f, "\n    {description}")?;
+        }
+        if !self.env_vars.is_empty() {
+            let env_vars: Vec<&str> = self.env_vars.iter().map(String::as_str).collect();
+            write!(f, "\n    env: {}", env_vars.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the public tasks of the embedded `Makefile.toml`, sorted by name. Tasks whose names
+/// begin with `_` are only meant to be extended by other tasks and are left out.
+pub(crate) fn make_targets() -> Result<Vec<MakeTarget>> {
+    let makefile: Table =
+        toml::from_str(MAKEFILE).context("Unable to parse the embedded Makefile.toml")?;
+    let tasks = makefile
+        .get("tasks")
+        .and_then(Value::as_table)
+        .context("The embedded Makefile.toml has no tasks")?;
+    Ok(tasks
+        .iter()
+        .filter(|(name, _)| !name.starts_with('_'))
+        .map(|(name, task)| MakeTarget {
+            name: name.clone(),
+            description: task
+                .get("description")
+                .and_then(Value::as_str)
+                .map(ToString::to_string),
+            env_vars: task_env_vars(tasks, name),
+        })
+        .collect())
+}
+
+/// Returns the environment variables read by the task, including those read by the task it extends
+/// or runs in its place. Variables read by its dependencies are not included, since those are
+/// listed with the dependencies themselves.
+fn task_env_vars(tasks: &Table, name: &str) -> BTreeSet<String> {
+    let mut env_vars = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    let mut next = Some(name);
+    while let Some(name) = next.filter(|name| visited.insert(*name)) {
+        let Some(task) = tasks.get(name) else {
+            break;
+        };
+        collect_env_vars(task, &mut env_vars);
+        next = task
+            .get("extend")
+            .or_else(|| task.get("run_task"))
+            .and_then(Value::as_str);
+    }
+    env_vars
+}
+
+fn collect_env_vars(value: &Value, env_vars: &mut BTreeSet<String>) {
+    match value {
+        Value::String(s) => env_vars.extend(
+            variable_references(s)
+                .filter(|name| is_build_system_env(name))
+                .map(ToString::to_string),
+        ),
+        Value::Array(items) => items
+            .iter()
+            .for_each(|item| collect_env_vars(item, env_vars)),
+        Value::Table(table) => {
+            for (key, value) in table {
+                if is_build_system_env(key) {
+                    env_vars.insert(key.clone());
+                }
+                collect_env_vars(value, env_vars);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the names of the variables referenced as `$NAME` or `${NAME...}` in a script.
+fn variable_references(script: &str) -> impl Iterator<Item = &str> {
+    script.match_indices('$').filter_map(|(i, _)| {
+        let rest = &script[i + 1..];
+        let rest = rest.strip_prefix('{').unwrap_or(rest);
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        Some(&rest[..end]).filter(|name| !name.is_empty())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lists_targets_with_descriptions_and_env_vars() {
+        let targets = make_targets().unwrap();
+        assert!(targets.iter().all(|target| !target.name.starts_with('_')));
+        assert!(targets.iter().all(|target| target.description.is_some()));
+
+        let publish_kit = targets
+            .iter()
+            .find(|target| target.name == "publish-kit")
+            .unwrap();
+        assert!(publish_kit.env_vars.contains("BUILDSYS_KIT"));
+        assert!(publish_kit.env_vars.contains("PUBLISH_VENDOR"));
+        assert!(!publish_kit.env_vars.contains("TWOLITER_TOOLS_DIR"));
+
+        // The variables of the task being extended are included.
+        let vmware_template = targets
+            .iter()
+            .find(|target| target.name == "vmware-template")
+            .unwrap();
+        assert!(vmware_template.env_vars.contains("MARK_OVA_AS_TEMPLATE"));
+        assert!(vmware_template.env_vars.contains("VMWARE_IMPORT_SPEC_PATH"));
+    }
+
+    #[test]
+    fn test_variable_references() {
+        let script = "echo ${BUILDSYS_ARCH:-$(uname -m)} $PUBLISH_REGIONS ${cmd} $$";
+        assert_eq!(
+            variable_references(script).collect::<Vec<_>>(),
+            vec!["BUILDSYS_ARCH", "PUBLISH_REGIONS", "cmd"]
+        );
+    }
+}
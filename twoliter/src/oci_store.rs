@@ -0,0 +1,421 @@
+//! The OCI cache directory in which pulled images are kept. Kits are often built on the same base
+//! layers, so the blobs of every image are kept in a single store, and each image only keeps its
+//! own layout files, which reference blobs in the store:
+//!
+//! ```text
+//! blobs/sha256/<hex>          the blobs of every cached image
+//! images/<image-digest>/      the `index.json` and `oci-layout` of each image
+//! manifests/<digest>.json     the manifest list of each locked image
+//! ```
+//!
+//! An image is pulled into a staging directory which is seeded with hard links to the blobs the
+//! store already has, so that the image tools skip pulling them again. Its new blobs are checked
+//! against their digests and moved into the store, since every later image which shares them reuses
+//! them, and its layout is moved into `images/`, which marks the image as complete.
+//!
+//! Blobs stay in the store after the images which use them are gone, until `twoliter prune`
+//! removes those which no image references.
+use crate::common::fs::{create_dir_all, read, remove_dir_all, remove_file, rename, replace_dir};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, trace};
+
+const BLOBS_DIR: &str = "blobs";
+const IMAGES_DIR: &str = "images";
+
+/// The file of an image layout which marks it as complete.
+pub(crate) const INDEX_FILE: &str = "index.json";
+
+/// How long a blob or staging directory is left alone before it may be pruned, so that a pull which
+/// has moved its blobs into the store but not yet its layout doesn't lose them.
+const PRUNE_GRACE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize, Debug)]
+struct IndexView {
+    manifests: Vec<DescriptorView>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ManifestView {
+    config: Option<DescriptorView>,
+    #[serde(default)]
+    layers: Vec<DescriptorView>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DescriptorView {
+    digest: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct OciStore {
+    root: PathBuf,
+}
+
+impl OciStore {
+    pub(crate) fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The path of the blob with the given digest, which may not be present.
+    pub(crate) fn blob_path(&self, digest: &str) -> PathBuf {
+        self.root.join(BLOBS_DIR).join(digest.replace(':', "/"))
+    }
+
+    /// The directory holding the layout files of the image with the given manifest digest.
+    pub(crate) fn image_dir(&self, digest: &str) -> PathBuf {
+        self.root.join(IMAGES_DIR).join(digest.replace(':', "-"))
+    }
+
+    /// Where the image with the given manifest digest was kept, with its own copy of every blob,
+    /// before images shared a store.
+    fn legacy_dir(&self, digest: &str) -> PathBuf {
+        self.root.join(digest.replace(':', "-"))
+    }
+
+    /// Whether the image with the given manifest digest has been pulled completely.
+    pub(crate) fn has_image(&self, digest: &str) -> bool {
+        self.image_dir(digest).join(INDEX_FILE).exists()
+    }
+
+    /// Moves an image kept in the layout used before images shared a store into the store, and
+    /// returns whether there was one.
+    pub(crate) async fn migrate_legacy(&self, digest: &str) -> Result<bool> {
+        let legacy = self.legacy_dir(digest);
+        if !legacy.join(INDEX_FILE).exists() {
+            return Ok(false);
+        }
+        debug!(
+            "Moving the blobs of '{}' into the shared blob store",
+            legacy.display()
+        );
+        self.commit(&legacy, digest).await?;
+        Ok(true)
+    }
+
+    /// Creates a directory to pull an image into, holding hard links to those of `blobs` which the
+    /// store already has.
+    pub(crate) async fn stage(&self, blobs: &[String]) -> Result<PathBuf> {
+        let staging = self.root.join(format!(".staging-{}", uuid::Uuid::new_v4()));
+        create_dir_all(&staging).await?;
+        for digest in blobs {
+            let blob = self.blob_path(digest);
+            if !blob.exists() {
+                continue;
+            }
+            let link = staging.join(BLOBS_DIR).join(digest.replace(':', "/"));
+            if let Some(parent) = link.parent() {
+                create_dir_all(parent).await?;
+            }
+            // A blob which can't be linked is pulled again, as it would be without the store.
+            match tokio::fs::hard_link(&blob, &link).await {
+                Ok(()) => trace!("Reusing blob '{}' from the store", digest),
+                Err(e) => debug!("Unable to link blob '{}' from the store: {}", digest, e),
+            }
+        }
+        Ok(staging)
+    }
+
+    /// Moves the blobs of the image layout at `staging` into the store, and the rest of the layout
+    /// to the image's directory. If the image was completed concurrently, `staging` is discarded.
+    pub(crate) async fn commit(&self, staging: &Path, digest: &str) -> Result<PathBuf> {
+        let staged_blobs = staging.join(BLOBS_DIR);
+        if staged_blobs.exists() {
+            for algorithm in read_dir(&staged_blobs).await? {
+                let store = self.root.join(BLOBS_DIR).join(algorithm.file_name());
+                create_dir_all(&store).await?;
+                for blob in read_dir(&algorithm.path()).await? {
+                    let dest = store.join(blob.file_name());
+                    if dest.exists() {
+                        remove_file(blob.path()).await?;
+                        continue;
+                    }
+                    // The image is pulled again next time rather than keeping a blob which
+                    // doesn't match its digest, and sharing it with every image which uses it.
+                    if let Err(e) = check_blob(blob.path()).await {
+                        remove_dir_all(staging).await?;
+                        return Err(e);
+                    }
+                    rename(blob.path(), &dest).await?;
+                }
+            }
+            remove_dir_all(&staged_blobs).await?;
+        }
+
+        let dest = self.image_dir(digest);
+        if self.has_image(digest) {
+            trace!("Image '{}' was added to the store concurrently", digest);
+            remove_dir_all(staging).await?;
+            return Ok(dest);
+        }
+        create_dir_all(self.root.join(IMAGES_DIR)).await?;
+        replace_dir(staging, &dest).await?;
+        Ok(dest)
+    }
+
+    /// The blobs which no image in the store references, and the staging directories left behind
+    /// by pulls which were interrupted. Anything changed within [`PRUNE_GRACE`] is kept, since a
+    /// pull may be using it.
+    pub(crate) async fn unreferenced(&self) -> Result<Vec<PathBuf>> {
+        if !self.root.exists() {
+            return Ok(Vec::new());
+        }
+        let mut referenced = HashSet::new();
+        let images = self.root.join(IMAGES_DIR);
+        if images.exists() {
+            for image in read_dir(&images).await? {
+                referenced.extend(self.image_blobs(&image.path()).await?);
+            }
+        }
+
+        let now = SystemTime::now();
+        let mut unreferenced = Vec::new();
+        let blobs = self.root.join(BLOBS_DIR);
+        if blobs.exists() {
+            for algorithm in read_dir(&blobs).await? {
+                let name = algorithm.file_name().to_string_lossy().to_string();
+                for blob in read_dir(&algorithm.path()).await? {
+                    let digest = format!("{name}:{}", blob.file_name().to_string_lossy());
+                    if !referenced.contains(&digest) && is_stale(&blob, now).await? {
+                        unreferenced.push(blob.path());
+                    }
+                }
+            }
+        }
+        for entry in read_dir(&self.root).await? {
+            let staging = entry.file_name().to_string_lossy().starts_with(".staging-");
+            if staging && is_stale(&entry, now).await? {
+                unreferenced.push(entry.path());
+            }
+        }
+        Ok(unreferenced)
+    }
+
+    /// The digests of the blobs the image with the layout files in `dir` uses.
+    async fn image_blobs(&self, dir: &Path) -> Result<Vec<String>> {
+        let index_path = dir.join(INDEX_FILE);
+        if !index_path.exists() {
+            return Ok(Vec::new());
+        }
+        let index: IndexView = serde_json::from_slice(&read(&index_path).await?)
+            .context(format!("failed to parse '{}'", index_path.display()))?;
+        let mut blobs = Vec::new();
+        for manifest in index.manifests {
+            let path = self.blob_path(&manifest.digest);
+            let view: ManifestView = serde_json::from_slice(&read(&path).await?)
+                .context(format!("failed to parse the manifest '{}'", path.display()))?;
+            blobs.extend(view.config.into_iter().chain(view.layers).map(|d| d.digest));
+            blobs.push(manifest.digest);
+        }
+        Ok(blobs)
+    }
+}
+
+/// Whether `entry` was last changed longer than [`PRUNE_GRACE`] before `now`.
+async fn is_stale(entry: &tokio::fs::DirEntry, now: SystemTime) -> Result<bool> {
+    let modified = entry
+        .metadata()
+        .await
+        .and_then(|metadata| metadata.modified())
+        .context(format!("failed to read '{}'", entry.path().display()))?;
+    Ok(now
+        .duration_since(modified)
+        .is_ok_and(|age| age >= PRUNE_GRACE))
+}
+
+/// Checks that the blob at `path`, which is named `<algorithm>/<hex>` like a digest, has that
+/// digest.
+async fn check_blob(path: PathBuf) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let algorithm = path
+            .parent()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let expected = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if algorithm != "sha256" {
+            bail!(
+                "blob '{}' has a digest algorithm other than sha256",
+                path.display()
+            );
+        }
+        let mut file =
+            std::fs::File::open(&path).context(format!("failed to open '{}'", path.display()))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 1 << 20];
+        loop {
+            let read = file
+                .read(&mut buf)
+                .context(format!("failed to read '{}'", path.display()))?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+        let actual = format!("{:x}", hasher.finalize());
+        if actual != expected {
+            bail!(
+                "blob 'sha256:{expected}' was pulled with the digest 'sha256:{actual}', it may \
+                have been truncated or corrupted; it will be pulled again next time"
+            );
+        }
+        Ok(())
+    })
+    .await
+    .context("failed to check a pulled blob")?
+}
+
+async fn read_dir(dir: &Path) -> Result<Vec<tokio::fs::DirEntry>> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .context(format!("failed to read '{}'", dir.display()))?;
+    let mut result = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context(format!("failed to read entry in '{}'", dir.display()))?
+    {
+        result.push(entry);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::TempDir;
+
+    const DIGEST: &str = "sha256:abc";
+
+    fn digest(content: &str) -> String {
+        format!("sha256:{:x}", Sha256::digest(content))
+    }
+
+    /// Writes an image layout to `dir` holding blobs with the given contents.
+    fn write_layout(dir: &Path, blobs: &[&str]) {
+        std::fs::create_dir_all(dir.join("blobs/sha256")).unwrap();
+        std::fs::write(dir.join(INDEX_FILE), r#"{"manifests": []}"#).unwrap();
+        for blob in blobs {
+            std::fs::write(dir.join("blobs").join(digest(blob).replace(':', "/")), blob).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn commit_shares_blobs_between_images() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path());
+
+        let staging = store.stage(&[]).await.unwrap();
+        write_layout(&staging, &["base", "kit-a"]);
+        store.commit(&staging, DIGEST).await.unwrap();
+        assert!(store.has_image(DIGEST));
+        assert!(!staging.exists());
+        assert!(!store.image_dir(DIGEST).join("blobs").exists());
+
+        // The second image reuses the base layer through a hard link to the store.
+        let staging = store
+            .stage(&[digest("base"), digest("kit-b")])
+            .await
+            .unwrap();
+        let base = store.blob_path(&digest("base"));
+        let linked = staging.join("blobs").join(digest("base").replace(':', "/"));
+        assert_eq!(
+            std::fs::metadata(&base).unwrap().ino(),
+            std::fs::metadata(&linked).unwrap().ino()
+        );
+        assert!(!store.blob_path(&digest("kit-b")).exists());
+        write_layout(&staging, &["kit-b"]);
+        store.commit(&staging, "sha256:def").await.unwrap();
+
+        assert!(store.has_image("sha256:def"));
+        assert_eq!(std::fs::metadata(&base).unwrap().nlink(), 1);
+        assert!(store.blob_path(&digest("kit-a")).exists());
+        assert!(store.blob_path(&digest("kit-b")).exists());
+    }
+
+    #[tokio::test]
+    async fn commit_rejects_corrupt_blobs() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path());
+
+        let staging = store.stage(&[]).await.unwrap();
+        write_layout(&staging, &["base"]);
+        let truncated = staging.join("blobs").join(digest("kit").replace(':', "/"));
+        std::fs::write(&truncated, "ki").unwrap();
+        assert!(store.commit(&staging, DIGEST).await.is_err());
+        assert!(!staging.exists());
+        assert!(!store.has_image(DIGEST));
+        assert!(!store.blob_path(&digest("kit")).exists());
+    }
+
+    #[tokio::test]
+    async fn migrates_legacy_archives() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path());
+        write_layout(&tempdir.path().join("sha256-abc"), &["base"]);
+
+        assert!(store.migrate_legacy(DIGEST).await.unwrap());
+        assert!(store.has_image(DIGEST));
+        assert!(store.blob_path(&digest("base")).exists());
+        assert!(!tempdir.path().join("sha256-abc").exists());
+        assert!(!store.migrate_legacy(DIGEST).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn finds_unreferenced_blobs() {
+        let tempdir = TempDir::new().unwrap();
+        let store = OciStore::new(tempdir.path());
+        let manifest = format!(
+            r#"{{"config": {{"digest": "{}"}}, "layers": [{{"digest": "{}"}}]}}"#,
+            digest("config"),
+            digest("layer")
+        );
+        let staging = store.stage(&[]).await.unwrap();
+        write_layout(
+            &staging,
+            &["config", "layer", &manifest, "orphan", "new-orphan"],
+        );
+        std::fs::write(
+            staging.join(INDEX_FILE),
+            format!(
+                r#"{{"manifests": [{{"digest": "{}"}}]}}"#,
+                digest(&manifest)
+            ),
+        )
+        .unwrap();
+        store.commit(&staging, DIGEST).await.unwrap();
+        let interrupted = store.stage(&[]).await.unwrap();
+        // Nothing which was just pulled is pruned.
+        assert_eq!(store.unreferenced().await.unwrap(), Vec::<PathBuf>::new());
+
+        let old = SystemTime::now() - PRUNE_GRACE * 2;
+        for blob in ["config", "layer", manifest.as_str(), "orphan"] {
+            let file = std::fs::File::options()
+                .write(true)
+                .open(store.blob_path(&digest(blob)))
+                .unwrap();
+            file.set_modified(old).unwrap();
+        }
+        std::fs::File::open(&interrupted)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let mut unreferenced = store.unreferenced().await.unwrap();
+        unreferenced.sort();
+        let mut expected = vec![store.blob_path(&digest("orphan")), interrupted];
+        expected.sort();
+        assert_eq!(unreferenced, expected);
+    }
+}
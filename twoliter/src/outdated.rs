@@ -0,0 +1,122 @@
+//! Compares the sdk and kits required by `Twoliter.toml` with the newest versions published by
+//! their vendors, so that updates can be proposed automatically.
+use crate::lock::{Lock, LockedImage, TWOLITER_LOCK};
+use crate::project::{Image, Project};
+use anyhow::{Context, Result};
+use semver::Version;
+use serde::Serialize;
+use tracing::{debug, instrument};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DependencyKind {
+    Sdk,
+    Kit,
+}
+
+/// The versions of a single dependency declared in `Twoliter.toml`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct OutdatedEntry {
+    pub(crate) name: String,
+    pub(crate) vendor: String,
+    pub(crate) kind: DependencyKind,
+    /// The version required by `Twoliter.toml`
+    pub(crate) required: Version,
+    /// The version recorded in `Twoliter.lock`, if the project has been locked
+    pub(crate) locked: Option<Version>,
+    /// The newest release version tagged in the vendor's repository
+    pub(crate) latest: Option<Version>,
+    pub(crate) outdated: bool,
+}
+
+/// Looks up the newest available version of the sdk and each kit in `Twoliter.toml`.
+#[instrument(level = "trace", skip(project))]
+pub(crate) async fn check(project: &Project) -> Result<Vec<OutdatedEntry>> {
+    let image_tool = project.image_tool()?;
+    let lock = if project.project_dir().join(TWOLITER_LOCK).exists() {
+        Some(Lock::read_lock_file(project).await?)
+    } else {
+        None
+    };
+    let locked_images: Vec<&LockedImage> = lock
+        .iter()
+        .flat_map(|lock| std::iter::once(&lock.sdk).chain(lock.kit.iter()))
+        .collect();
+
+    let dependencies = project
+        .sdk_image()
+        .map(|sdk| (DependencyKind::Sdk, sdk))
+        .into_iter()
+        .chain(
+            project
+                .kits()
+                .into_iter()
+                .map(|kit| (DependencyKind::Kit, kit)),
+        );
+    let mut entries = Vec::new();
+    for (kind, image) in dependencies {
+        let vendor = project.vendor().get(&image.vendor).context(format!(
+            "vendor '{}' is not specified in Twoliter.toml",
+            image.vendor
+        ))?;
+        let repository = format!("{}/{}", vendor.repository(), image.name);
+        debug!("Listing tags of '{}'", repository);
+        let tags = image_tool.list_tags(&repository).await?;
+        let latest = latest_version(&tags);
+        let locked = locked_version(&locked_images, &image);
+        entries.push(OutdatedEntry {
+            name: image.name.to_string(),
+            vendor: image.vendor.to_string(),
+            kind,
+            outdated: latest
+                .as_ref()
+                .is_some_and(|latest| *latest > image.version),
+            required: image.version,
+            locked,
+            latest,
+        });
+    }
+    Ok(entries)
+}
+
+fn locked_version(locked_images: &[&LockedImage], image: &Image) -> Option<Version> {
+    locked_images
+        .iter()
+        .find(|locked| {
+            locked.name == image.name.to_string() && locked.vendor == image.vendor.to_string()
+        })
+        .map(|locked| locked.version.clone())
+}
+
+/// Returns the newest release version among tags of the form `v<semver>`, ignoring pre-releases
+/// and tags which are not versions.
+fn latest_version(tags: &[String]) -> Option<Version> {
+    tags.iter()
+        .filter_map(|tag| tag.strip_prefix('v'))
+        .filter_map(|version| Version::parse(version).ok())
+        .filter(|version| version.pre.is_empty())
+        .max()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn latest_version_ignores_prereleases_and_other_tags() {
+        let tags: Vec<String> = [
+            "latest",
+            "v1.2.0",
+            "v1.10.0",
+            "v2.0.0-rc1",
+            "1.11.0",
+            "v1.9.9",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        assert_eq!(latest_version(&tags), Some(Version::new(1, 10, 0)));
+        assert_eq!(latest_version(&[]), None);
+    }
+}
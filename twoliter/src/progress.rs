@@ -0,0 +1,56 @@
+//! Terminal progress reporting for long-running operations such as pulling kit images and
+//! extracting their layers.
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+use std::time::Duration;
+
+const SPINNER_TEMPLATE: &str = "{spinner:.green} {prefix:.bold} {msg} [{elapsed}]";
+const BYTES_TEMPLATE: &str =
+    "{spinner:.green} {prefix:.bold} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}";
+const TICK_INTERVAL: Duration = Duration::from_millis(120);
+
+/// Owns the set of progress bars drawn to the terminal. When progress is hidden, every bar handed
+/// out is a no-op so callers never need to check whether progress is enabled.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    multi: MultiProgress,
+}
+
+impl Progress {
+    /// Creates a progress reporter that draws to stdout, unless `quiet` is set or stdout is not a
+    /// terminal.
+    pub fn new(quiet: bool) -> Self {
+        let target = if !quiet && std::io::stdout().is_terminal() {
+            ProgressDrawTarget::stdout()
+        } else {
+            ProgressDrawTarget::hidden()
+        };
+        Self {
+            multi: MultiProgress::with_draw_target(target),
+        }
+    }
+
+    /// Adds a spinner for an operation with no measurable size, such as an image pull.
+    pub(crate) fn spinner(&self, prefix: impl Into<String>, msg: impl Into<String>) -> ProgressBar {
+        let bar = self.multi.add(ProgressBar::new_spinner());
+        bar.set_style(
+            ProgressStyle::with_template(SPINNER_TEMPLATE).expect("valid progress template"),
+        );
+        bar.set_prefix(prefix.into());
+        bar.set_message(msg.into());
+        bar.enable_steady_tick(TICK_INTERVAL);
+        bar
+    }
+
+    /// Adds a bar that tracks `total` bytes, such as the extraction of an image layer.
+    pub(crate) fn bytes(&self, prefix: impl Into<String>, total: u64) -> ProgressBar {
+        let bar = self.multi.add(ProgressBar::new(total));
+        bar.set_style(
+            ProgressStyle::with_template(BYTES_TEMPLATE)
+                .expect("valid progress template")
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(prefix.into());
+        bar
+    }
+}
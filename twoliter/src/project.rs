@@ -1,42 +1,189 @@
-use crate::common::fs;
+use crate::common::{exec, fs};
 use crate::docker::ImageUri;
-use crate::schema_version::SchemaVersion;
+use crate::kit_cache::KitCache;
+use crate::project_keys::unknown_keys;
+use crate::schema_version::{LockSchemaVersion, SchemaVersion};
 use anyhow::{ensure, Context, Result};
 use async_recursion::async_recursion;
 use async_walkdir::WalkDir;
+use buildsys::manifest::{BuildMode, PackageNetwork};
+use buildsys::proxy::redact_credentials;
+use buildsys::runtime::ContainerRuntime;
 use buildsys_config::{EXTERNAL_KIT_DIRECTORY, EXTERNAL_KIT_METADATA};
 use futures::stream::StreamExt;
-use semver::Version;
+use oci_cli_wrapper::{CredentialHelper, ImageTool, Mirror, RegistryLimits, OCI_LAYOUT_SCHEME};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{
+    InstanceType, Metadata, RootSchema, Schema, SchemaObject, StringValidation,
+};
+use schemars::JsonSchema;
+use semver::{Version, VersionReq};
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
 use std::fmt::{Display, Formatter};
 use std::hash::Hash;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use tokio::process::Command;
 use toml::Table;
 use tracing::{debug, info, instrument, trace, warn};
 
 /// Common functionality in commands, if the user gave a path to the `Twoliter.toml` file,
 /// we use it, otherwise we search for the file. Returns the `Project` and the path at which it was
-/// found (this is the same as `user_path` if provided).
+/// found (this is the same as `user_path` if provided). When `strict` is set, unknown keys in the
+/// project file, and kit names published by more than one vendor, are an error rather than a
+/// warning.
 #[instrument(level = "trace")]
-pub(crate) async fn load_or_find_project(user_path: Option<PathBuf>) -> Result<Project> {
+pub(crate) async fn load_or_find_project(
+    user_path: Option<PathBuf>,
+    strict: bool,
+) -> Result<Project> {
     let project = match user_path {
-        None => Project::find_and_load(".").await?,
-        Some(p) => Project::load(&p).await?,
+        None => Project::find_and_load_file(Path::new("."), strict).await?,
+        Some(p) => Project::load_file(&p, strict).await?,
     };
     debug!(
         "Project file loaded from '{}'",
         project.filepath().display()
     );
+    project.proxy.log();
     Ok(project)
 }
 
+/// Errors if `table`, read from the project file at `path`, has keys which Twoliter does not
+/// recognize and `strict` is set, otherwise warns about them.
+fn check_unknown_keys(table: &Table, path: &Path, strict: bool) -> Result<()> {
+    let unknown = unknown_keys(table)?;
+    if strict {
+        ensure!(
+            unknown.is_empty(),
+            "'{}' has unknown keys:\n  {}",
+            path.display(),
+            unknown
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        );
+    }
+    for key in unknown {
+        warn!("Ignoring {key} in '{}'", path.display());
+    }
+    Ok(())
+}
+
+/// The key giving the versions of Twoliter which can load a project, e.g. `">=0.5"`.
+const REQUIRED_VERSION_KEY: &str = "required-twoliter-version";
+
+/// Errors if the project file at `path` requires a different version of Twoliter than this one.
+/// This is checked before the rest of the file is interpreted, so that a project using newer
+/// features fails with an upgrade message rather than a parse error.
+fn check_required_version(table: &Table, path: &Path) -> Result<()> {
+    let Some(value) = table.get(REQUIRED_VERSION_KEY) else {
+        return Ok(());
+    };
+    let requirement: VersionReq = value
+        .as_str()
+        .context(format!(
+            "'{REQUIRED_VERSION_KEY}' in '{}' must be a string, e.g. \">=0.5\"",
+            path.display()
+        ))?
+        .parse()
+        .context(format!(
+            "Unable to parse '{REQUIRED_VERSION_KEY}' in '{}'",
+            path.display()
+        ))?;
+    let version = Version::parse(env!("CARGO_PKG_VERSION"))
+        .context("Unable to parse the version of Twoliter")?;
+    ensure!(
+        requirement.matches(&version),
+        "'{}' requires twoliter {requirement}, but this is twoliter {version}. Please upgrade \
+        twoliter to build this project.",
+        path.display()
+    );
+    Ok(())
+}
+
+/// The key listing other TOML files to merge into `Twoliter.toml`. Included files may themselves
+/// include others.
+const INCLUDE_KEY: &str = "include";
+
+/// Reads the project file at `path` and every file it includes, merged into a single table. Paths
+/// in `include` are relative to the file that lists them. Included files are merged in the order
+/// listed, each overriding those before it, and the including file overrides them all. Tables are
+/// merged key by key; any other value, including an array, replaces the value it overrides.
+/// `chain` holds the files currently being included, for cycle detection.
+#[async_recursion]
+async fn read_with_includes(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Table> {
+    if let Some(start) = chain.iter().position(|included| included == path) {
+        anyhow::bail!(
+            "Twoliter.toml includes form a cycle: {}",
+            chain[start..]
+                .iter()
+                .chain(std::iter::once(&path.to_path_buf()))
+                .map(|path| format!("'{}'", path.display()))
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        );
+    }
+    let data = fs::read_to_string(path)
+        .await
+        .context(format!("Unable to read project file '{}'", path.display()))?;
+    let mut table: Table = toml::from_str(&data).context(format!(
+        "Unable to deserialize project file '{}'",
+        path.display()
+    ))?;
+    let includes: Vec<PathBuf> = match table.remove(INCLUDE_KEY) {
+        Some(includes) => includes.try_into().context(format!(
+            "'{INCLUDE_KEY}' in '{}' must be a list of paths",
+            path.display()
+        ))?,
+        None => Vec::new(),
+    };
+    let dir = path
+        .parent()
+        .context(format!(
+            "Unable to find the parent directory of '{}'",
+            path.display()
+        ))?
+        .to_path_buf();
+
+    chain.push(path.to_path_buf());
+    let mut merged = Table::new();
+    for include in includes {
+        let include = fs::canonicalize(dir.join(&include)).await.context(format!(
+            "Unable to include '{}' from '{}'",
+            include.display(),
+            path.display()
+        ))?;
+        debug!("Including '{}' in '{}'", include.display(), path.display());
+        merge_tables(&mut merged, read_with_includes(&include, chain).await?);
+    }
+    chain.pop();
+
+    merge_tables(&mut merged, table);
+    Ok(merged)
+}
+
+/// Merges `overrides` into `base`, recursing into tables that are present in both.
+fn merge_tables(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                merge_tables(base, overrides)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 /// Represents the structure of a `Twoliter.toml` project file.
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
 #[serde(rename_all = "kebab-case")]
-pub(crate) struct Project {
+pub struct Project {
     #[serde(skip)]
     filepath: PathBuf,
     #[serde(skip)]
@@ -51,35 +198,98 @@ pub(crate) struct Project {
     /// The Bottlerocket SDK container image.
     sdk: Option<Image>,
 
+    /// SDK container images used instead of `sdk` when building for particular architectures
+    sdk_arch: BTreeMap<String, Image>,
+
     /// Set of vendors
     vendor: BTreeMap<ValidIdentifier, Vendor>,
 
     /// Set of kit dependencies
-    kit: Vec<Image>,
+    kit: Vec<KitDependency>,
+
+    /// Optional user-level directory in which kits are cached and shared between projects.
+    kit_cache_dir: Option<PathBuf>,
+
+    /// What to do when the per-architecture images of a kit carry different kit metadata.
+    kit_metadata_mismatch: MetadataMismatchPolicy,
+
+    /// The schema version of `Twoliter.lock` to write.
+    lock_schema_version: LockSchemaVersion,
+
+    /// Variants which can be built from this project
+    variant: Vec<VariantConfig>,
+
+    /// Named sets of build settings, selected with `--profile`
+    profile: BTreeMap<String, Profile>,
+
+    /// Pinned copies of the external tools used by builds and registry operations
+    tools: Tools,
+
+    /// The registry through which package builds share their cache
+    build_cache: BuildCache,
+
+    /// The CPUs, memory and network access each package build may use by default, and the proxy
+    /// through which builds with `sdk-proxy` network access download
+    package_limits: PackageLimits,
+
+    /// Changes to the embedded Dockerfile
+    dockerfile: DockerfileChanges,
+
+    /// Secrets which package builds may read, by name
+    secrets: BTreeMap<String, Secret>,
+
+    /// Commands run before and after Twoliter's commands
+    hooks: Hooks,
+
+    /// The licenses which `twoliter licenses` allows and denies
+    licenses: LicensePolicy,
+
+    /// The proxy through which Twoliter and the tools it runs reach the network
+    proxy: Proxy,
+
+    /// Where `twoliter publish variant` publishes built variants
+    publish: Publish,
+
+    /// How the checksums of a variant build's artifacts are signed
+    artifact_signing: Option<ArtifactSigning>,
+
+    /// Whether unknown keys in `Twoliter.toml` and kit names published by more than one vendor are
+    /// errors rather than warnings
+    #[serde(skip)]
+    strict: bool,
 }
 
 impl Project {
     /// Load a `Twoliter.toml` file from the given file path (it can have any filename).
-    pub(crate) async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    pub async fn load<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        Ok(Self::load_file(path.as_ref(), false).await?)
+    }
+
+    async fn load_file(path: &Path, strict: bool) -> Result<Self> {
         let path = fs::canonicalize(path).await?;
-        let data = fs::read_to_string(&path)
-            .await
-            .context(format!("Unable to read project file '{}'", path.display()))?;
-        let unvalidated: UnvalidatedProject = toml::from_str(&data).context(format!(
-            "Unable to deserialize project file '{}'",
-            path.display()
-        ))?;
-        unvalidated.validate(path).await
+        let table = read_with_includes(&path, &mut Vec::new()).await?;
+        check_required_version(&table, &path)?;
+        check_unknown_keys(&table, &path, strict)?;
+        let unvalidated: UnvalidatedProject = toml::Value::Table(table).try_into().context(
+            format!("Unable to deserialize project file '{}'", path.display()),
+        )?;
+        Ok(Project {
+            strict,
+            ..unvalidated.validate(path).await?
+        })
     }
 
     /// Recursively search for a file named `Twoliter.toml` starting in `dir`. If it is not found,
     /// move up (i.e. `cd ..`) until it is found. Return an error if there is no parent directory.
-    #[async_recursion]
-    pub(crate) async fn find_and_load<P>(dir: P) -> Result<Self>
+    pub async fn find_and_load<P>(dir: P) -> crate::Result<Self>
     where
         P: Send + AsRef<Path>,
     {
-        let dir = dir.as_ref();
+        Ok(Self::find_and_load_file(dir.as_ref(), false).await?)
+    }
+
+    #[async_recursion]
+    async fn find_and_load_file(dir: &Path, strict: bool) -> Result<Self> {
         trace!("Looking for Twoliter.toml in '{}'", dir.display());
         ensure!(
             dir.is_dir(),
@@ -91,21 +301,29 @@ impl Project {
             .context(format!("Unable to canonicalize '{}'", dir.display()))?;
         let filepath = dir.join("Twoliter.toml");
         if filepath.is_file() {
-            return Self::load(&filepath).await;
+            return Self::load_file(&filepath, strict).await;
         }
         // Move up a level and recurse.
         let parent = dir
             .parent()
             .context("Unable to find Twoliter.toml file")?
             .to_owned();
-        Self::find_and_load(parent).await
+        Self::find_and_load_file(&parent, strict).await
+    }
+
+    /// Whether the project was loaded in strict mode, which makes kit names published by more than
+    /// one vendor an error rather than a warning.
+    pub(crate) fn is_strict(&self) -> bool {
+        self.strict
     }
 
-    pub(crate) fn filepath(&self) -> PathBuf {
+    /// The path to the `Twoliter.toml` file this project was loaded from.
+    pub fn filepath(&self) -> PathBuf {
         self.filepath.clone()
     }
 
-    pub(crate) fn project_dir(&self) -> PathBuf {
+    /// The directory containing `Twoliter.toml`.
+    pub fn project_dir(&self) -> PathBuf {
         self.project_dir.clone()
     }
 
@@ -117,10 +335,6 @@ impl Project {
         self.project_dir.join(EXTERNAL_KIT_METADATA)
     }
 
-    pub(crate) fn schema_version(&self) -> SchemaVersion<1> {
-        self.schema_version
-    }
-
     pub(crate) fn release_version(&self) -> &str {
         self.release_version.as_str()
     }
@@ -130,16 +344,187 @@ impl Project {
     }
 
     pub(crate) fn kits(&self) -> Vec<Image> {
-        self.kit.clone()
+        self.kit.iter().map(|kit| kit.image.clone()).collect()
+    }
+
+    /// The features enabled for a kit which the project depends on directly.
+    pub(crate) fn kit_features(&self, image: &Image) -> &[String] {
+        self.kit
+            .iter()
+            .find(|kit| kit.image == *image)
+            .map(|kit| kit.features.as_slice())
+            .unwrap_or_default()
     }
 
     pub(crate) fn sdk_image(&self) -> Option<Image> {
         self.sdk.clone()
     }
 
+    /// The SDK images declared as `[sdk-arch.<arch>]`, which replace the sdk when building for
+    /// that architecture.
+    pub(crate) fn sdk_arch_images(&self) -> &BTreeMap<String, Image> {
+        &self.sdk_arch
+    }
+
+    /// The shared kit cache, if one is configured via `TWOLITER_KIT_CACHE` or `kit-cache-dir`.
+    pub(crate) fn kit_cache(&self) -> Option<KitCache> {
+        KitCache::from_config(self.kit_cache_dir.as_deref(), &self.project_dir)
+    }
+
+    pub(crate) fn lock_schema_version(&self) -> LockSchemaVersion {
+        self.lock_schema_version
+    }
+
+    pub(crate) fn variants(&self) -> &[VariantConfig] {
+        self.variant.as_slice()
+    }
+
+    pub(crate) fn variant(&self, name: &str) -> Option<&VariantConfig> {
+        self.variant.iter().find(|variant| variant.name == name)
+    }
+
+    /// Returns the build profile declared as `[profile.<name>]`.
+    pub(crate) fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profile.get(name).context(format!(
+            "profile '{name}' is not declared in Twoliter.toml (declared profiles: {})",
+            self.profile
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+
+    pub(crate) fn tools(&self) -> &Tools {
+        &self.tools
+    }
+
+    /// The environment variables which give the tools Twoliter runs the project's proxy settings.
+    pub(crate) fn proxy_env(&self) -> Vec<(&'static str, String)> {
+        self.proxy.env()
+    }
+
+    /// The environment variables through which cargo make tasks, and the tools they run, receive
+    /// the project's tools, proxy, build cache, package limits, Dockerfile changes, and where its
+    /// secrets are read from. Builds export their cache to the `[build-cache]` registry when
+    /// `export_build_cache` is set, even if Twoliter.toml doesn't ask for it.
+    pub(crate) fn cargo_make_env(
+        &self,
+        export_build_cache: bool,
+    ) -> Result<Vec<(&'static str, String)>> {
+        let mut env = self.tools.env()?;
+        env.extend(self.proxy_env());
+        env.extend(self.build_cache.env(export_build_cache));
+        env.extend(self.package_limits.env());
+        env.extend(self.dockerfile.env()?);
+        if !self.secrets.is_empty() {
+            env.push((
+                "BUILDSYS_SECRETS",
+                secrets_env(&self.project_dir(), &self.secrets),
+            ));
+        }
+        Ok(env)
+    }
+
+    pub(crate) fn licenses(&self) -> &LicensePolicy {
+        &self.licenses
+    }
+
+    pub(crate) fn publish(&self) -> &Publish {
+        &self.publish
+    }
+
+    /// How the checksums of a variant build's artifacts are signed, with the key's path resolved
+    /// against the project directory.
+    pub(crate) fn artifact_signing(&self) -> Option<ArtifactSigning> {
+        self.artifact_signing
+            .as_ref()
+            .map(|signing| ArtifactSigning {
+                method: signing.method,
+                key: signing_key_path(&self.project_dir, &signing.key),
+                tlog_upload: signing.tlog_upload,
+            })
+    }
+
+    /// Runs the commands declared for `hook` in Twoliter.toml, with `context` added to their
+    /// environment.
+    pub(crate) async fn run_hook(&self, hook: Hook, context: &[(&str, String)]) -> Result<()> {
+        self.hooks.run(hook, &self.project_dir, context).await
+    }
+
+    pub(crate) fn kit_metadata_mismatch(&self) -> MetadataMismatchPolicy {
+        self.kit_metadata_mismatch
+    }
+
+    /// Returns the image tool for registry operations, authenticating with the credential helpers
+    /// configured for the project's vendors and falling back to anonymous pulls from public ones.
+    pub(crate) fn image_tool(&self) -> Result<ImageTool> {
+        let mut limits: HashMap<String, RegistryLimits> = HashMap::new();
+        for vendor in self.vendor.values() {
+            limits
+                .entry(vendor.registry_host())
+                .and_modify(|limit| *limit = limit.min(vendor.registry_limits()))
+                .or_insert(vendor.registry_limits());
+        }
+        let mut image_tool = self
+            .tools
+            .image_tool()?
+            .with_env(self.proxy.env())
+            .with_layout_root(&self.project_dir)
+            .with_registry_limits(limits)
+            .with_mirrors(
+                self.vendor
+                    .values()
+                    .filter(|vendor| vendor.oci_layout.is_none())
+                    .filter_map(|vendor| {
+                        vendor
+                            .mirror
+                            .as_ref()
+                            .map(|mirror| Mirror::new(&vendor.registry, mirror))
+                    })
+                    .collect(),
+            );
+        let public: Vec<_> = self
+            .vendor
+            .values()
+            .filter(|vendor| vendor.public)
+            .map(|vendor| vendor.registry_host())
+            .collect();
+        if !public.is_empty() {
+            image_tool = image_tool.with_anonymous_fallback(public)?;
+        }
+        let helpers: Vec<_> = self
+            .vendor
+            .values()
+            .filter_map(|vendor| {
+                vendor
+                    .credential_helper
+                    .clone()
+                    .map(|helper| (vendor.registry.clone(), helper))
+            })
+            .collect();
+        if helpers.is_empty() {
+            return Ok(image_tool);
+        }
+        Ok(image_tool.with_repository_auth(helpers)?)
+    }
+
+    /// The directory in which pulled OCI archives and image manifests are cached.
+    pub(crate) fn oci_cache_dir(&self) -> PathBuf {
+        match self.kit_cache() {
+            Some(kit_cache) => kit_cache.oci_dir(),
+            None => self.external_kits_dir().join("cache"),
+        }
+    }
+
     #[allow(unused)]
     pub(crate) fn kit(&self, name: &str) -> Result<Option<ImageUri>> {
-        if let Some(kit) = self.kit.iter().find(|y| y.name.to_string() == name) {
+        if let Some(kit) = self
+            .kit
+            .iter()
+            .map(|kit| &kit.image)
+            .find(|y| y.name.to_string() == name)
+        {
             let vendor = self.vendor.get(&kit.vendor).context(format!(
                 "vendor '{}' was not specified in Twoliter.toml",
                 kit.vendor
@@ -201,12 +586,957 @@ impl Project {
     }
 }
 
+/// Controls how resolution treats a kit whose per-architecture images carry different kit metadata,
+/// for example because a vendor staggers its per-architecture releases.
+#[derive(
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    Serialize,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum MetadataMismatchPolicy {
+    /// Fail resolution.
+    #[default]
+    Strict,
+    /// Log a detailed warning and continue with the metadata of the first architecture.
+    Warn,
+}
+
+/// The architectures which variants can be built for.
+pub(crate) const SUPPORTED_ARCHES: &[&str] = &["x86_64", "aarch64"];
+
+/// A variant declared in `Twoliter.toml`, along with the settings used when building it. Settings
+/// given on the command line take precedence.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct VariantConfig {
+    /// The name of the variant, e.g. `aws-dev`
+    pub name: String,
+    /// The architectures the variant is built for
+    #[serde(default = "default_variant_arch")]
+    pub arch: Vec<String>,
+    /// The lookaside cache to fetch sources from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lookaside_cache: Option<String>,
+    /// Whether to fall back to upstream sources when they are missing from the lookaside cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_source_fallback: Option<bool>,
+    /// Path to the Infra.toml file, relative to the project directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub infra_toml: Option<PathBuf>,
+    /// The project's kits which the variant is built from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kits: Vec<String>,
+    /// The packages included in the variant image
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// The image features enabled or disabled for the variant, e.g. `uefi-secure-boot = true`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub image_features: BTreeMap<String, bool>,
+}
+
+impl VariantConfig {
+    /// Whether the variant's contents are declared here, in which case Twoliter generates the
+    /// variant's Cargo manifest rather than the project providing one.
+    pub(crate) fn defines_contents(&self) -> bool {
+        !self.kits.is_empty() || !self.packages.is_empty()
+    }
+}
+
+fn default_variant_arch() -> Vec<String> {
+    vec!["x86_64".to_string()]
+}
+
+/// The range of lz4 compression levels which can be used for disk images.
+const IMAGE_COMPRESSION_LEVELS: std::ops::RangeInclusive<u8> = 1..=12;
+
+/// A named set of build settings, declared as `[profile.<name>]` in `Twoliter.toml`, e.g. `dev`
+/// or `release`. Settings which are not given keep the build system's defaults.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Profile {
+    /// Whether RPM builds generate debuginfo packages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpm_debuginfo: Option<bool>,
+    /// Whether package builds are reproducible: RPMs built twice from the same commit are
+    /// identical, and the digest of each RPM is recorded under `build/state/<arch>/digests`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reproducible: Option<bool>,
+    /// The compiler cache package builds keep, which is shared by every package build. Packages
+    /// are built without one, with a warning, if the SDK does not provide it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compiler_cache: Option<CompilerCache>,
+    /// The largest the compiler cache may grow, e.g. `20G`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compiler_cache_size: Option<String>,
+    /// Flags given to the Go toolchain when building packages, after any `GOFLAGS` the SDK sets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub go_build_flags: Option<String>,
+    /// The lz4 compression level used for disk images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_compression_level: Option<u8>,
+    /// How packages are built, unless their `Cargo.toml` sets a `build-mode` of its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_mode: Option<BuildMode>,
+}
+
+impl Profile {
+    /// The environment variables through which buildsys receives this profile's settings.
+    pub(crate) fn env(&self, name: &str) -> Vec<(&'static str, String)> {
+        let mut env = vec![("BUILDSYS_PROFILE", name.to_string())];
+        if let Some(rpm_debuginfo) = self.rpm_debuginfo {
+            env.push(("BUILDSYS_RPM_DEBUGINFO", rpm_debuginfo.to_string()));
+        }
+        if let Some(reproducible) = self.reproducible {
+            env.push(("BUILDSYS_REPRODUCIBLE", reproducible.to_string()));
+        }
+        if let Some(compiler_cache) = self.compiler_cache {
+            env.push(("BUILDSYS_COMPILER_CACHE", compiler_cache.to_string()));
+        }
+        if let Some(size) = &self.compiler_cache_size {
+            env.push(("BUILDSYS_COMPILER_CACHE_SIZE", size.clone()));
+        }
+        if let Some(go_build_flags) = &self.go_build_flags {
+            env.push(("BUILDSYS_GO_BUILD_FLAGS", go_build_flags.clone()));
+        }
+        if let Some(level) = self.image_compression_level {
+            env.push(("BUILDSYS_IMAGE_COMPRESSION_LEVEL", level.to_string()));
+        }
+        if let Some(build_mode) = self.build_mode {
+            env.push(("BUILDSYS_PACKAGE_BUILD_MODE", build_mode.to_string()));
+        }
+        env
+    }
+}
+
+/// A compiler cache which package builds can keep.
+#[derive(
+    Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CompilerCache {
+    /// Caches C and C++ compilation
+    Ccache,
+    /// Caches Rust compilation
+    Sccache,
+}
+
+impl Display for CompilerCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompilerCache::Ccache => write!(f, "ccache"),
+            CompilerCache::Sccache => write!(f, "sccache"),
+        }
+    }
+}
+
+/// Pinned copies of external tools, declared as `[tools]` in `Twoliter.toml`, so that builds do not
+/// depend on whatever is first in each developer's `PATH`. Bare tool names are found through
+/// `PATH` when the project is loaded, and other relative paths are resolved from the project
+/// directory.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Tools {
+    /// The docker CLI used for builds and for loading the sdk. BuildKit runs within the docker
+    /// daemon that this CLI talks to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docker: Option<PathBuf>,
+    /// The `crane`, `gcrane` or `krane` binary used to query registries and pull kits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crane: Option<PathBuf>,
+    /// The cosign binary used to sign build artifacts and published kits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cosign: Option<PathBuf>,
+    /// The container runtime buildsys uses to build packages, kits and variants. Defaults to
+    /// docker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_runtime: Option<ContainerRuntime>,
+}
+
+impl Tools {
+    /// Resolves the pinned tools: bare names are found through `PATH`, and other relative paths
+    /// are resolved from `project_dir`.
+    fn resolve(self, project_dir: &Path) -> Result<Self> {
+        let resolve = |tool: Option<PathBuf>| -> Result<Option<PathBuf>> {
+            let Some(tool) = tool else {
+                return Ok(None);
+            };
+            if tool.components().count() == 1 && tool.is_relative() {
+                return which::which(&tool).map(Some).context(format!(
+                    "Unable to find the tool '{}' pinned in Twoliter.toml in PATH",
+                    tool.display()
+                ));
+            }
+            Ok(Some(project_dir.join(tool)))
+        };
+        Ok(Self {
+            docker: resolve(self.docker)?,
+            crane: resolve(self.crane)?,
+            cosign: resolve(self.cosign)?,
+            container_runtime: self.container_runtime,
+        })
+    }
+
+    /// The docker CLI to run.
+    pub(crate) fn docker(&self) -> &Path {
+        self.docker.as_deref().unwrap_or(Path::new("docker"))
+    }
+
+    /// The cosign binary to run.
+    pub(crate) fn cosign(&self) -> &Path {
+        self.cosign.as_deref().unwrap_or(Path::new("cosign"))
+    }
+
+    /// The container runtime buildsys builds with.
+    pub(crate) fn container_runtime(&self) -> ContainerRuntime {
+        self.container_runtime.unwrap_or_default()
+    }
+
+    /// The CLI of the container runtime buildsys builds with, which is the pinned docker CLI when
+    /// the runtime is docker.
+    pub(crate) fn container_runtime_cli(&self) -> &Path {
+        match self.container_runtime() {
+            ContainerRuntime::Docker => self.docker(),
+            runtime => Path::new(runtime.program()),
+        }
+    }
+
+    /// The name and path of the pinned image tool for registry operations. A pinned `crane` is
+    /// preferred, then a pinned `docker`.
+    fn pinned_image_tool(&self) -> Option<(&'static str, &Path)> {
+        match (&self.crane, &self.docker) {
+            (Some(crane), _) => Some(("crane", crane)),
+            (None, Some(docker)) => Some(("docker", docker)),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the image tool for registry operations, which is the pinned one if there is one;
+    /// otherwise the tool is chosen from the environment.
+    fn image_tool(&self) -> Result<ImageTool> {
+        Ok(match self.pinned_image_tool() {
+            Some((name, path)) => ImageTool::from_tool_path(name, path)?,
+            None => ImageTool::from_environment()?,
+        })
+    }
+
+    /// The environment variables which make `cargo make` tasks, and the buildsys and pubsys
+    /// processes they start, run the pinned tools and the chosen container runtime. The directory
+    /// containing the docker CLI is put first in `PATH`.
+    pub(crate) fn env(&self) -> Result<Vec<(&'static str, String)>> {
+        let mut env = Vec::new();
+        if let Some(runtime) = self.container_runtime {
+            env.push(("BUILDSYS_CONTAINER_RUNTIME", runtime.to_string()));
+        }
+        if self.container_runtime.is_some() || self.docker.is_some() {
+            env.push((
+                "BUILDSYS_CONTAINER_CLI",
+                self.container_runtime_cli().display().to_string(),
+            ));
+        }
+        if let Some((name, path)) = self.pinned_image_tool() {
+            env.push(("TWOLITER_KIT_IMAGE_TOOL", name.to_string()));
+            env.push(("TWOLITER_KIT_IMAGE_TOOL_PATH", path.display().to_string()));
+        }
+        if let Some(cosign) = &self.cosign {
+            env.push(("TWOLITER_COSIGN", cosign.display().to_string()));
+        }
+        let Some(docker) = &self.docker else {
+            return Ok(env);
+        };
+        ensure!(
+            docker.file_name() == Some(OsStr::new("docker")),
+            "the docker CLI pinned in Twoliter.toml must be named 'docker', found '{}'",
+            docker.display()
+        );
+        let dir = docker.parent().context(format!(
+            "Unable to find the directory containing '{}'",
+            docker.display()
+        ))?;
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let path = std::env::join_paths(
+            std::iter::once(dir.to_path_buf()).chain(std::env::split_paths(&path)),
+        )
+        .context("Unable to add the pinned docker CLI to PATH")?;
+        env.push(("PATH", path.to_string_lossy().into_owned()));
+        Ok(env)
+    }
+}
+
+/// A registry through which package builds share the cache of their build stages, declared as
+/// `[build-cache]` in `Twoliter.toml`, so that CI runners and developers do not rebuild stages
+/// which were already built elsewhere. Each package's cache is stored in its own repository under
+/// the registry.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BuildCache {
+    /// The registry repository under which the cache is stored, e.g.
+    /// `registry.example.com/my-project/build-cache`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// Whether builds export their cache to the registry, rather than only importing it. This can
+    /// also be enabled for a single build with `--export-build-cache`, e.g. only in CI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub export: Option<bool>,
+}
+
+impl BuildCache {
+    /// The environment variables through which buildsys receives the cache settings. The cache is
+    /// exported when `export` is set, or when the project asks for it.
+    fn env(&self, export: bool) -> Vec<(&'static str, String)> {
+        let Some(registry) = &self.registry else {
+            return Vec::new();
+        };
+        let mut env = vec![("BUILDSYS_CACHE_FROM", registry.clone())];
+        if export || self.export.unwrap_or(false) {
+            env.push(("BUILDSYS_CACHE_TO", registry.clone()));
+        }
+        env
+    }
+}
+
+/// The CPUs, memory and network access each package build may use, declared as `[package-limits]`
+/// in `Twoliter.toml`, so that heavyweight packages don't starve the builds running alongside them
+/// on shared hosts, and builds can't download dependencies they don't declare. A package can set
+/// its own limits with the same keys in the `[package.metadata.build-package]` of its `Cargo.toml`.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PackageLimits {
+    /// The number of CPUs a package build may use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<u32>,
+    /// The memory a package build may use, as container runtimes take it, e.g. `8g`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    /// The network access of a package build once its sources are fetched. Defaults to `none`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<PackageNetwork>,
+    /// The proxy through which builds with `sdk-proxy` network access download, e.g.
+    /// `http://proxy.example.com:3128`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sdk_proxy: Option<String>,
+}
+
+impl PackageLimits {
+    /// The environment variables through which buildsys receives the default limits.
+    pub(crate) fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = Vec::new();
+        if let Some(cpus) = self.cpus {
+            env.push(("BUILDSYS_PACKAGE_CPUS", cpus.to_string()));
+        }
+        if let Some(memory) = &self.memory {
+            env.push(("BUILDSYS_PACKAGE_MEMORY", memory.clone()));
+        }
+        if let Some(network) = self.network {
+            env.push(("BUILDSYS_PACKAGE_NETWORK", network.to_string()));
+        }
+        if let Some(sdk_proxy) = &self.sdk_proxy {
+            env.push(("BUILDSYS_SDK_PROXY", sdk_proxy.clone()));
+        }
+        env
+    }
+}
+
+/// Changes to the embedded Dockerfile declared as `[dockerfile]` in `Twoliter.toml`, so that a
+/// project can add build stages or label the images it builds without forking the Dockerfile.
+/// buildsys appends the fragments to the Dockerfile, and checks that they only add stages.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DockerfileChanges {
+    /// Files of extra build stages to append to the Dockerfile, relative to the project directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fragments: Vec<PathBuf>,
+    /// Stages from the fragments to build in place of the usual ones, keyed by the kind of build:
+    /// `package`, `kit`, `variant` or `repack`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub targets: BTreeMap<String, String>,
+    /// Labels to give the images built
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl DockerfileChanges {
+    /// The kinds of build whose target stage may be replaced.
+    const KINDS: [&'static str; 4] = ["package", "kit", "variant", "repack"];
+
+    /// The environment variables through which buildsys receives the changes, as lists separated
+    /// by commas.
+    pub(crate) fn env(&self) -> Result<Vec<(&'static str, String)>> {
+        let mut env = Vec::new();
+        for fragment in &self.fragments {
+            ensure!(
+                fragment.is_relative()
+                    && fragment
+                        .components()
+                        .all(|component| component != Component::ParentDir),
+                "Dockerfile fragment '{}' must be a path within the project directory",
+                fragment.display()
+            );
+        }
+        for (kind, stage) in &self.targets {
+            ensure!(
+                Self::KINDS.contains(&kind.as_str()),
+                "unknown kind of build '{kind}' in dockerfile.targets, expected one of {}",
+                Self::KINDS.join(", ")
+            );
+            ensure!(
+                !stage.is_empty(),
+                "the Dockerfile target for {kind} builds is empty"
+            );
+        }
+        let fragments: Vec<String> = self
+            .fragments
+            .iter()
+            .map(|fragment| fragment.display().to_string())
+            .collect();
+        let pairs = |map: &BTreeMap<String, String>| -> Vec<String> {
+            map.iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect()
+        };
+        let lists = [
+            ("BUILDSYS_DOCKERFILE_FRAGMENTS", fragments),
+            ("BUILDSYS_DOCKERFILE_TARGETS", pairs(&self.targets)),
+            ("BUILDSYS_DOCKERFILE_LABELS", pairs(&self.labels)),
+        ];
+        for (var, list) in lists {
+            if list.is_empty() {
+                continue;
+            }
+            ensure!(
+                list.iter().all(|item| !item.contains(',')),
+                "the Dockerfile changes in Twoliter.toml may not contain commas, found '{}'",
+                list.join(",")
+            );
+            env.push((var, list.join(",")));
+        }
+        Ok(env)
+    }
+}
+
+/// A secret declared as `[secrets.<name>]` in `Twoliter.toml`, such as a token for a private git
+/// mirror, which the package builds that list it under `secrets` can read from
+/// `%{_package_secrets_dir}/<name>`. Secrets reach builds through BuildKit secret mounts, so they
+/// are kept out of build arguments and images.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Secret {
+    /// The environment variable holding the secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    /// The file holding the secret, relative to the project directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// Where each secret is read from, as `<name>=env:<variable>` or `<name>=file:<path>` lines, with
+/// files resolved against the project directory. buildsys only reads the secrets a package
+/// declares, when the package is built.
+fn secrets_env(project_dir: &Path, secrets: &BTreeMap<String, Secret>) -> String {
+    secrets
+        .iter()
+        .filter_map(|(name, secret)| match (&secret.env, &secret.file) {
+            (Some(var), _) => Some(format!("{name}=env:{var}")),
+            (None, Some(file)) => Some(format!("{name}=file:{}", project_dir.join(file).display())),
+            (None, None) => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The licenses declared as `[licenses]` in `Twoliter.toml`, which `twoliter licenses` checks the
+/// packages, external files and kits of the project against. Licenses are SPDX identifiers such as
+/// `Apache-2.0`. When `allowed` is empty, every license which is not denied is allowed.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LicensePolicy {
+    /// The licenses which may be used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<String>,
+    /// The licenses which may not be used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied: Vec<String>,
+}
+
+/// Proxy settings declared as `[proxy]` in `Twoliter.toml`, for networks where the lookaside cache,
+/// the Go module proxy and registries can only be reached through a proxy. Each setting overrides
+/// the `HTTP_PROXY`, `HTTPS_PROXY` or `NO_PROXY` environment variable, in either case, and the
+/// effective settings are given to every process Twoliter starts for a build or a registry request.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Proxy {
+    /// The proxy for HTTP requests, e.g. `http://proxy.example.com:3128`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    /// The proxy for HTTPS requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<String>,
+    /// The hosts which are reached without the proxy, separated by commas, e.g.
+    /// `localhost,.corp.example.com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+}
+
+/// Where `twoliter publish variant` publishes built variants, declared as `[publish]` in
+/// `Twoliter.toml`. AWS credentials, roles and the default regions come from `Infra.toml`, as they
+/// do for the other publishing tasks.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Publish {
+    /// Upload the images of built variants to S3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3: Option<S3Publish>,
+    /// Register built variants as EC2 AMIs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ami: Option<AmiPublish>,
+    /// Add built variants to a TUF update repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<RepoPublish>,
+}
+
+/// The S3 bucket which the artifacts of built variants are uploaded to, along with the
+/// `SHA256SUMS` file listing their checksums and, when `[artifact-signing]` is set, its signature.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct S3Publish {
+    /// The bucket to upload to
+    pub bucket: String,
+    /// The prefix of the uploaded images' keys, which is followed by
+    /// `<variant>/<arch>/<version>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// The region of the bucket. Defaults to the first region in `Infra.toml`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Tags applied to each uploaded object, which the bucket's lifecycle rules can match
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl S3Publish {
+    /// The environment variables which give the `upload-images` task its settings.
+    pub(crate) fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("PUBLISH_S3_BUCKET", self.bucket.clone()),
+            ("PUBLISH_S3_TAGS", tags_env(&self.tags)),
+        ];
+        env.extend(self.prefix.clone().map(|p| ("PUBLISH_S3_PREFIX", p)));
+        env.extend(self.region.clone().map(|r| ("PUBLISH_S3_REGION", r)));
+        env
+    }
+}
+
+/// How built variants are registered as EC2 AMIs. The AMI is registered in the first region and
+/// copied to the rest.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AmiPublish {
+    /// The regions to register the AMI in. Defaults to the regions in `Infra.toml`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<String>,
+    /// The name of the AMI. Defaults to one made of the project, variant, architecture and version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The description of the AMI. Defaults to its name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Tags applied to the AMI in every region
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl AmiPublish {
+    /// The environment variables which give the `ami` task its settings.
+    pub(crate) fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![("PUBLISH_AMI_TAGS", tags_env(&self.tags))];
+        if !self.regions.is_empty() {
+            env.push(("PUBLISH_REGIONS", self.regions.join(",")));
+        }
+        env.extend(self.name.clone().map(|n| ("PUBLISH_AMI_NAME", n)));
+        env.extend(
+            self.description
+                .clone()
+                .map(|d| ("PUBLISH_AMI_DESCRIPTION", d)),
+        );
+        env
+    }
+}
+
+/// The TUF repository which `twoliter publish repo` adds built variants to, with their migrations
+/// and images as targets. The repository is signed with the keys given for it in `Infra.toml`,
+/// which may be local files, KMS keys or SSM parameters. Without one, a local key is generated at
+/// `key`.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct RepoPublish {
+    /// The name of the repository in `Infra.toml`. Defaults to `default`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Path to the repository's root role, relative to the project directory. Defaults to
+    /// `roles/<name>.root.json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_role: Option<PathBuf>,
+    /// Path to the local signing key used when `Infra.toml` gives none, relative to the project
+    /// directory. Defaults to `keys/<name>.pem`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+    /// Path to the policy setting when the repository's metadata expires, relative to the
+    /// project directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration_policy: Option<PathBuf>,
+    /// Path to the policy setting the waves in which updates roll out, relative to the project
+    /// directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wave_policy: Option<PathBuf>,
+}
+
+impl RepoPublish {
+    /// The environment variables which give the `repo` task its settings, with paths resolved
+    /// against `project_dir`.
+    pub(crate) fn env(&self, project_dir: &Path) -> Vec<(&'static str, String)> {
+        let path = |p: &PathBuf| project_dir.join(p).display().to_string();
+        let mut env = Vec::new();
+        env.extend(self.name.clone().map(|n| ("PUBLISH_REPO", n)));
+        env.extend(
+            self.root_role
+                .as_ref()
+                .map(|p| ("PUBLISH_REPO_ROOT_JSON", path(p))),
+        );
+        env.extend(self.key.as_ref().map(|p| ("PUBLISH_REPO_KEY", path(p))));
+        env.extend(
+            self.expiration_policy
+                .as_ref()
+                .map(|p| ("PUBLISH_EXPIRATION_POLICY_PATH", path(p))),
+        );
+        env.extend(
+            self.wave_policy
+                .as_ref()
+                .map(|p| ("PUBLISH_WAVE_POLICY_PATH", path(p))),
+        );
+        env
+    }
+}
+
+/// How the `SHA256SUMS` manifest of a variant build's artifacts is signed, declared as
+/// `[artifact-signing]` in `Twoliter.toml`. `twoliter verify-artifacts` checks downloaded artifacts
+/// against the manifest with the matching public key.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ArtifactSigning {
+    /// The tool which signs the manifest
+    pub method: SigningMethod,
+    /// Path to the private key, relative to the project directory. Cosign also accepts a KMS URI
+    /// such as `awskms:///alias/signing`
+    pub key: PathBuf,
+    /// Also record cosign signatures in the public Rekor transparency log
+    #[serde(default)]
+    pub tlog_upload: bool,
+}
+
+/// Resolves a signing key against the project directory, unless it is a URI such as
+/// `awskms:///alias/signing`, which the signing tool is given as it is.
+fn signing_key_path(project_dir: &Path, key: &Path) -> PathBuf {
+    let is_uri = key
+        .to_str()
+        .and_then(|key| key.split_once("://"))
+        .is_some_and(|(scheme, _)| {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        });
+    if is_uri {
+        key.to_path_buf()
+    } else {
+        project_dir.join(key)
+    }
+}
+
+/// A tool which signs and verifies the checksum manifest of a variant build.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    Serialize,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    JsonSchema,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SigningMethod {
+    /// `ssh-keygen -Y sign` with an SSH key
+    Ssh,
+    /// `cosign sign-blob` with a cosign key
+    Cosign,
+    /// `minisign` with a minisign key
+    Minisign,
+}
+
+impl SigningMethod {
+    pub(crate) fn tool(&self) -> &'static str {
+        match self {
+            SigningMethod::Ssh => "ssh-keygen",
+            SigningMethod::Cosign => "cosign",
+            SigningMethod::Minisign => "minisign",
+        }
+    }
+}
+
+/// Tags as `KEY=VALUE` lines, which the publishing tasks pass on as `--tag` arguments.
+fn tags_env(tags: &BTreeMap<String, String>) -> String {
+    tags.iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Proxy {
+    /// The environment variables which give the effective proxy settings, in both cases since tools
+    /// disagree on which they read.
+    pub(crate) fn env(&self) -> Vec<(&'static str, String)> {
+        self.env_from(|name| std::env::var(name).ok())
+    }
+
+    /// Logs the effective proxy settings, so that connectivity problems can be traced to them.
+    fn log(&self) {
+        let env = self.env();
+        if env.is_empty() {
+            debug!("No proxy is configured");
+            return;
+        }
+        let setting = |name: &str| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| redact_credentials(value))
+                .unwrap_or_else(|| "none".to_string())
+        };
+        info!(
+            "Using proxy settings HTTP_PROXY={} HTTPS_PROXY={} NO_PROXY={}",
+            setting("HTTP_PROXY"),
+            setting("HTTPS_PROXY"),
+            setting("NO_PROXY")
+        );
+    }
+
+    fn env_from(&self, var: impl Fn(&str) -> Option<String>) -> Vec<(&'static str, String)> {
+        let settings = [
+            (&self.http_proxy, ["HTTP_PROXY", "http_proxy"]),
+            (&self.https_proxy, ["HTTPS_PROXY", "https_proxy"]),
+            (&self.no_proxy, ["NO_PROXY", "no_proxy"]),
+        ];
+        let mut env = Vec::new();
+        for (setting, names) in settings {
+            let value = setting.clone().or_else(|| {
+                names
+                    .iter()
+                    .find_map(|name| var(name).filter(|value| !value.is_empty()))
+            });
+            if let Some(value) = value {
+                env.extend(names.map(|name| (name, value.clone())));
+            }
+        }
+        env
+    }
+}
+
+/// Commands declared as `[hooks]` in `Twoliter.toml`, so that steps such as uploading artifacts
+/// or sending notifications can be added to a build without wrapping Twoliter in a script. Each
+/// command is run with `sh -c` from the project directory, and a failing command fails the
+/// Twoliter command it is attached to.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Hooks {
+    /// Run before `twoliter fetch`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_fetch: Vec<String>,
+    /// Run after `twoliter fetch` succeeds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_fetch: Vec<String>,
+    /// Run before `twoliter build kit`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_kit_build: Vec<String>,
+    /// Run after `twoliter build kit` succeeds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_kit_build: Vec<String>,
+    /// Run before each architecture of a variant is built
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_variant_build: Vec<String>,
+    /// Run after each architecture of a variant is built
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_variant_build: Vec<String>,
+    /// Run before `twoliter publish kit` and `twoliter publish promote`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_publish_kit: Vec<String>,
+    /// Run after `twoliter publish kit` or `twoliter publish promote` succeeds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_publish_kit: Vec<String>,
+}
+
+/// The points at which hook commands are run. `post-*` hooks only run when the command succeeds.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Hook {
+    PreFetch,
+    PostFetch,
+    PreKitBuild,
+    PostKitBuild,
+    PreVariantBuild,
+    PostVariantBuild,
+    PrePublishKit,
+    PostPublishKit,
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Hook::PreFetch => "pre-fetch",
+            Hook::PostFetch => "post-fetch",
+            Hook::PreKitBuild => "pre-kit-build",
+            Hook::PostKitBuild => "post-kit-build",
+            Hook::PreVariantBuild => "pre-variant-build",
+            Hook::PostVariantBuild => "post-variant-build",
+            Hook::PrePublishKit => "pre-publish-kit",
+            Hook::PostPublishKit => "post-publish-kit",
+        })
+    }
+}
+
+impl Hooks {
+    fn commands(&self, hook: Hook) -> &[String] {
+        match hook {
+            Hook::PreFetch => &self.pre_fetch,
+            Hook::PostFetch => &self.post_fetch,
+            Hook::PreKitBuild => &self.pre_kit_build,
+            Hook::PostKitBuild => &self.post_kit_build,
+            Hook::PreVariantBuild => &self.pre_variant_build,
+            Hook::PostVariantBuild => &self.post_variant_build,
+            Hook::PrePublishKit => &self.pre_publish_kit,
+            Hook::PostPublishKit => &self.post_publish_kit,
+        }
+    }
+
+    /// Runs the commands of `hook` in order. Besides `context`, each command is given
+    /// `TWOLITER_HOOK` and `TWOLITER_PROJECT_DIR`.
+    async fn run(&self, hook: Hook, project_dir: &Path, context: &[(&str, String)]) -> Result<()> {
+        for command in self.commands(hook) {
+            info!("Running {hook} hook '{command}'");
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c")
+                .arg(command)
+                .current_dir(project_dir)
+                .env("TWOLITER_HOOK", hook.to_string())
+                .env("TWOLITER_PROJECT_DIR", project_dir)
+                .envs(context.iter().map(|(key, value)| (key, value)));
+            exec(&mut cmd, false)
+                .await
+                .context(format!("The {hook} hook '{command}' failed"))?;
+        }
+        Ok(())
+    }
+}
+
 /// This represents a container registry vendor that is used in resolving the kits and also
 /// now the bottlerocket sdk
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Vendor {
+    /// The registry to pull the vendor's images from. Empty when `oci-layout` is used instead.
+    #[serde(default)]
     pub registry: String,
+    /// A local directory to read the vendor's images from instead of a registry, holding an OCI
+    /// image layout for each image, named after it, with its versions as tags. Relative paths are
+    /// resolved from the project directory, and recorded in `Twoliter.lock` as written so that the
+    /// lock does not depend on where the project is checked out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oci_layout: Option<PathBuf>,
+    /// The docker credential helper used to authenticate to the vendor's repository, either the
+    /// name of a `docker-credential-*` program or a `{ command = [...] }` implementing the same
+    /// protocol. Vendors whose repositories share a registry host may use different helpers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<CredentialHelperSchema>")]
+    pub credential_helper: Option<CredentialHelper>,
+    /// Whether the registry allows anonymous pulls, in which case pulls rejected as unauthorized
+    /// are retried without credentials.
+    #[serde(default)]
+    pub public: bool,
+    /// The most registry operations, such as resolving a tag or pulling or pushing an image, to
+    /// have in flight to the registry at once. When vendors share a registry, the lowest limit of
+    /// any of them applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<usize>,
+    /// The most registry operations to start against the registry each minute. Each one may make
+    /// several HTTP requests, such as one per layer of a pulled image, so this should be set well
+    /// below the registry's quota of requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    /// A pull-through cache or proxy of the registry to pull the vendor's images from instead,
+    /// e.g. `<account>.dkr.ecr.<region>.amazonaws.com/ecr-public/bottlerocket`. Images are still
+    /// recorded in `Twoliter.lock` by their `registry` uris, and their tags resolved by it; only
+    /// their digests are pulled through the mirror, and checked against what it returns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+}
+
+impl Vendor {
+    /// The prefix of the vendor's image uris, to which `/<name>:<tag>` or `/<name>@<digest>` is
+    /// appended.
+    pub(crate) fn repository(&self) -> String {
+        match &self.oci_layout {
+            Some(dir) => format!("{OCI_LAYOUT_SCHEME}{}", dir.display()),
+            None => self.registry.clone(),
+        }
+    }
+
+    /// The limits on requests to the vendor's registry.
+    pub(crate) fn registry_limits(&self) -> RegistryLimits {
+        RegistryLimits {
+            max_concurrent: self.max_concurrent_requests,
+            requests_per_minute: self.requests_per_minute,
+        }
+    }
+
+    /// The registry host, by which requests are limited and retried anonymously.
+    pub(crate) fn registry_host(&self) -> String {
+        // The registry may be a bare host, which only becomes an image uri once an image name is
+        // appended to it.
+        oci_cli_wrapper::registry_host(&format!("{}/", self.registry)).to_string()
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -253,7 +1583,45 @@ impl Display for ValidIdentifier {
     }
 }
 
-fn is_valid_id_char(c: char) -> bool {
+impl JsonSchema for ValidIdentifier {
+    fn schema_name() -> String {
+        "ValidIdentifier".to_string()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some("^[A-Za-z0-9_-]+$".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// Describes [`CredentialHelper`], which is defined outside of this crate, in the JSON schema.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[schemars(
+    rename = "CredentialHelper",
+    description = "How to obtain credentials for a registry"
+)]
+enum CredentialHelperSchema {
+    /// The name of a docker credential helper, such as `ecr-login` for the
+    /// `docker-credential-ecr-login` program.
+    Name(String),
+    /// A command which implements the docker credential helper protocol.
+    Command { command: Vec<String> },
+}
+
+pub(crate) fn is_valid_id_char(c: char) -> bool {
     match c {
         // Allow alphanumeric characters, underscores, and hyphens
         'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => true,
@@ -263,10 +1631,13 @@ fn is_valid_id_char(c: char) -> bool {
 }
 
 /// This represents a dependency on a container, primarily used for kits
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(
+    Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, JsonSchema,
+)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Image {
     pub name: ValidIdentifier,
+    #[schemars(with = "String")]
     pub version: Version,
     pub vendor: ValidIdentifier,
 }
@@ -277,18 +1648,110 @@ impl Display for Image {
     }
 }
 
+/// The JSON schema of `Twoliter.toml`. It also gives the keys Twoliter recognizes in the file.
+pub(crate) fn project_schema() -> RootSchema {
+    // TOML has no null, so optional keys may only be left out.
+    let settings = SchemaSettings::draft07().with(|settings| settings.option_add_null_type = false);
+    let mut generator = settings.into_generator();
+    // Includes are merged, and the required Twoliter version checked, before the project is
+    // deserialized, so neither is a field of the project.
+    let mut include = generator.subschema_for::<Vec<PathBuf>>().into_object();
+    include.metadata().description = Some(
+        "Other TOML files to merge into this one, relative to it. Each included file overrides \
+        those listed before it, and this file overrides them all"
+            .to_string(),
+    );
+    let mut schema = generator.into_root_schema_for::<UnvalidatedProject>();
+    let metadata = schema.schema.metadata();
+    metadata.title = Some("Twoliter.toml".to_string());
+    metadata.description = Some("The project file of a Twoliter project".to_string());
+    let required_version = SchemaObject {
+        metadata: Some(Box::new(Metadata {
+            description: Some(
+                "The versions of Twoliter which can build the project, e.g. `>=0.5`".to_string(),
+            ),
+            ..Default::default()
+        })),
+        instance_type: Some(InstanceType::String.into()),
+        ..Default::default()
+    };
+    let properties = &mut schema.schema.object().properties;
+    properties.insert(INCLUDE_KEY.to_string(), include.into());
+    properties.insert(REQUIRED_VERSION_KEY.to_string(), required_version.into());
+    schema
+}
+
+/// Returns the JSON schema of `Twoliter.toml`.
+pub(crate) fn json_schema() -> Result<String> {
+    serde_json::to_string_pretty(&project_schema())
+        .context("Unable to serialize the Twoliter.toml schema")
+}
+
+/// A kit which the project depends on, declared as `[[kit]]` in `Twoliter.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct KitDependency {
+    #[serde(flatten)]
+    pub image: Image,
+    /// Features of the kit enabled for the project's builds, e.g. `fips`. Spec files can check for
+    /// them with `%{with kit_<kit>_<feature>}`, where dashes in the kit name become underscores.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+}
+
 /// This is used to `Deserialize` a project, then run validation code before returning a valid
 /// [`Project`]. This is necessary both because there is no post-deserialization serde hook for
 /// validation and, even if there was, we need to know the project directory path in order to check
 /// some things.
-#[derive(Debug, Clone, Deserialize)]
+///
+/// Its JSON schema, exported by `twoliter schema`, describes the format of `Twoliter.toml`, so its
+/// fields are documented for users.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 struct UnvalidatedProject {
+    /// The version of the `Twoliter.toml` format
     schema_version: SchemaVersion<1>,
+    /// The version of the images built by the project
     release_version: String,
+    /// The Bottlerocket SDK container image
     sdk: Option<Image>,
+    /// SDK container images used instead of `sdk` when building for particular architectures
+    sdk_arch: Option<BTreeMap<String, Image>>,
+    /// The registries which the SDK and kits are pulled from, by vendor name
     vendor: Option<BTreeMap<ValidIdentifier, Vendor>>,
-    kit: Option<Vec<Image>>,
+    /// The kits which the project depends on
+    kit: Option<Vec<KitDependency>>,
+    /// A user-level directory in which kits are cached and shared between projects
+    kit_cache_dir: Option<PathBuf>,
+    /// What to do when the per-architecture images of a kit carry different kit metadata
+    kit_metadata_mismatch: Option<MetadataMismatchPolicy>,
+    /// The schema version of `Twoliter.lock` to write
+    lock_schema_version: Option<LockSchemaVersion>,
+    /// Variants which can be built from this project
+    variant: Option<Vec<VariantConfig>>,
+    /// Named sets of build settings, selected with `--profile`
+    profile: Option<BTreeMap<String, Profile>>,
+    /// Pinned copies of the external tools used by builds and registry operations
+    tools: Option<Tools>,
+    /// The registry through which package builds share their cache
+    build_cache: Option<BuildCache>,
+    /// The CPUs, memory and network access each package build may use by default, and the proxy
+    /// through which builds with `sdk-proxy` network access download
+    package_limits: Option<PackageLimits>,
+    /// Changes to the embedded Dockerfile
+    dockerfile: Option<DockerfileChanges>,
+    /// Secrets which package builds may read, by name
+    secrets: Option<BTreeMap<String, Secret>>,
+    /// Commands run before and after Twoliter's commands
+    hooks: Option<Hooks>,
+    /// The licenses which `twoliter licenses` allows and denies
+    licenses: Option<LicensePolicy>,
+    /// The proxy through which Twoliter and the tools it runs reach the network
+    proxy: Option<Proxy>,
+    /// Where `twoliter publish variant` publishes built variants
+    publish: Option<Publish>,
+    /// How the checksums of a variant build's artifacts are signed
+    artifact_signing: Option<ArtifactSigning>,
 }
 
 impl UnvalidatedProject {
@@ -304,26 +1767,61 @@ impl UnvalidatedProject {
             .to_path_buf();
 
         self.check_vendor_availability().await?;
+        self.check_vendor_sources()?;
+        self.check_kit_features()?;
+        self.check_variants()?;
+        self.check_profiles()?;
+        self.check_secrets()?;
         self.check_release_toml(&project_dir).await?;
 
+        // OCI layout directories are kept as written, so that the image uris recorded in
+        // Twoliter.lock do not depend on where the project is checked out. The image tool resolves
+        // them from the project directory.
+        let vendor = self.vendor.unwrap_or_default();
+
+        let tools = self.tools.unwrap_or_default().resolve(&project_dir)?;
+
         Ok(Project {
             filepath,
             project_dir,
             schema_version: self.schema_version,
             release_version: self.release_version,
             sdk: self.sdk,
-            vendor: self.vendor.unwrap_or_default(),
+            sdk_arch: self.sdk_arch.unwrap_or_default(),
+            vendor,
             kit: self.kit.unwrap_or_default(),
+            kit_cache_dir: self.kit_cache_dir,
+            kit_metadata_mismatch: self.kit_metadata_mismatch.unwrap_or_default(),
+            lock_schema_version: self.lock_schema_version.unwrap_or_default(),
+            variant: self.variant.unwrap_or_default(),
+            profile: self.profile.unwrap_or_default(),
+            tools,
+            build_cache: self.build_cache.unwrap_or_default(),
+            package_limits: self.package_limits.unwrap_or_default(),
+            dockerfile: self.dockerfile.unwrap_or_default(),
+            secrets: self.secrets.unwrap_or_default(),
+            hooks: self.hooks.unwrap_or_default(),
+            licenses: self.licenses.unwrap_or_default(),
+            proxy: self.proxy.unwrap_or_default(),
+            publish: self.publish.unwrap_or_default(),
+            artifact_signing: self.artifact_signing,
+            strict: false,
         })
     }
 
     /// Errors if the user has defined a sdk and/or kit dependency without specifying the associated
     /// vendor
     async fn check_vendor_availability(&self) -> Result<()> {
-        let mut dependency_list = self.kit.clone().unwrap_or_default();
+        let mut dependency_list: Vec<Image> = self
+            .kit
+            .iter()
+            .flatten()
+            .map(|kit| kit.image.clone())
+            .collect();
         if let Some(sdk) = self.sdk.as_ref() {
             dependency_list.push(sdk.clone());
         }
+        dependency_list.extend(self.sdk_arch.iter().flatten().map(|(_, sdk)| sdk.clone()));
         for dependency in dependency_list.iter() {
             ensure!(
                 self.vendor.is_some()
@@ -338,6 +1836,121 @@ impl UnvalidatedProject {
         Ok(())
     }
 
+    /// Errors unless each vendor specifies exactly one of `registry` and `oci-layout`, and the sdks
+    /// come from a registry, since docker must be able to pull them. Per-architecture sdks must be
+    /// for architectures that can be built.
+    fn check_vendor_sources(&self) -> Result<()> {
+        let vendors = self.vendor.clone().unwrap_or_default();
+        for (name, vendor) in vendors.iter() {
+            ensure!(
+                vendor.registry.is_empty() != vendor.oci_layout.is_none(),
+                "vendor '{name}' must specify exactly one of 'registry' and 'oci-layout'"
+            );
+        }
+        let sdks = self
+            .sdk
+            .iter()
+            .chain(self.sdk_arch.iter().flat_map(|sdk_arch| sdk_arch.values()));
+        for sdk in sdks {
+            if let Some(vendor) = vendors.get(&sdk.vendor) {
+                ensure!(
+                    vendor.oci_layout.is_none(),
+                    "the sdk cannot be read from an 'oci-layout', because docker must be able to \
+                    pull it from a registry"
+                );
+            }
+        }
+        for arch in self.sdk_arch.iter().flat_map(|sdk_arch| sdk_arch.keys()) {
+            ensure!(
+                SUPPORTED_ARCHES.contains(&arch.as_str()),
+                "'sdk-arch' has unsupported arch '{arch}', expected one of: {}",
+                SUPPORTED_ARCHES.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Errors if a kit feature is not a valid identifier, or is enabled more than once, since
+    /// features are passed to buildsys as space-separated `<kit>:<feature>` pairs.
+    fn check_kit_features(&self) -> Result<()> {
+        for kit in self.kit.iter().flatten() {
+            for (i, feature) in kit.features.iter().enumerate() {
+                ensure!(
+                    !feature.is_empty() && feature.chars().all(is_valid_id_char),
+                    "kit '{}' has invalid feature '{feature}', features may only contain letters, \
+                    digits, '_' and '-'",
+                    kit.image
+                );
+                ensure!(
+                    !kit.features[..i].contains(feature),
+                    "kit '{}' enables feature '{feature}' more than once",
+                    kit.image
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors if variants are declared more than once, or for architectures that cannot be built.
+    fn check_variants(&self) -> Result<()> {
+        let variants = self.variant.as_deref().unwrap_or_default();
+        for (i, variant) in variants.iter().enumerate() {
+            ensure!(
+                !variants[..i].iter().any(|other| other.name == variant.name),
+                "variant '{}' is declared more than once in Twoliter.toml",
+                variant.name
+            );
+            ensure!(
+                !variant.arch.is_empty(),
+                "variant '{}' must list at least one arch",
+                variant.name
+            );
+            for arch in variant.arch.iter() {
+                ensure!(
+                    SUPPORTED_ARCHES.contains(&arch.as_str()),
+                    "variant '{}' has unsupported arch '{arch}', expected one of: {}",
+                    variant.name,
+                    SUPPORTED_ARCHES.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors if a profile sets a value which the build system cannot use.
+    fn check_profiles(&self) -> Result<()> {
+        for (name, profile) in self.profile.iter().flatten() {
+            if let Some(level) = profile.image_compression_level {
+                ensure!(
+                    IMAGE_COMPRESSION_LEVELS.contains(&level),
+                    "profile '{name}' has image-compression-level {level}, expected {} to {}",
+                    IMAGE_COMPRESSION_LEVELS.start(),
+                    IMAGE_COMPRESSION_LEVELS.end()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors unless each secret has a name that can be a file name, and exactly one source.
+    fn check_secrets(&self) -> Result<()> {
+        for (name, secret) in self.secrets.iter().flatten() {
+            ensure!(
+                !name.starts_with('.')
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')),
+                "secret '{name}' must be named with letters, digits, '-', '_' and '.', and may not \
+                start with '.'"
+            );
+            ensure!(
+                secret.env.is_some() != secret.file.is_some(),
+                "secret '{name}' must set exactly one of 'env' or 'file'"
+            );
+        }
+        Ok(())
+    }
+
     /// Issues a warning if `Release.toml` is found and, if so, ensures that it contains the same
     /// version (i.e. `release-version`) as the `Twoliter.toml` project file.
     async fn check_release_toml(&self, project_dir: &Path) -> Result<()> {
@@ -389,6 +2002,7 @@ mod test {
     use super::*;
     use crate::common::fs;
     use crate::test::{data_dir, projects_dir};
+    use std::error::Error as _;
     use tempfile::TempDir;
 
     /// Ensure that `Twoliter.toml` can be deserialized.
@@ -418,9 +2032,9 @@ mod test {
         assert_eq!("my-vendor", sdk.vendor.to_string());
 
         assert_eq!(1, deserialized.kit.len());
-        assert_eq!("my-core-kit", deserialized.kit[0].name.to_string());
-        assert_eq!(Version::new(1, 2, 3), deserialized.kit[0].version);
-        assert_eq!("my-vendor", deserialized.kit[0].vendor.to_string());
+        assert_eq!("my-core-kit", deserialized.kit[0].image.name.to_string());
+        assert_eq!(Version::new(1, 2, 3), deserialized.kit[0].image.version);
+        assert_eq!("my-vendor", deserialized.kit[0].image.vendor.to_string());
     }
 
     /// Ensure that a `Twoliter.toml` cannot be serialized if the `schema_version` is incorrect.
@@ -485,17 +2099,42 @@ mod test {
                 version: Version::new(1, 41, 1),
                 vendor: ValidIdentifier("bottlerocket".into()),
             }),
+            sdk_arch: None,
             vendor: Some(BTreeMap::from([(
                 ValidIdentifier("not-bottlerocket".into()),
                 Vendor {
                     registry: "public.ecr.aws/not-bottlerocket".into(),
+                    oci_layout: None,
+                    credential_helper: None,
+                    public: false,
+                    max_concurrent_requests: None,
+                    requests_per_minute: None,
+                    mirror: None,
                 },
             )])),
-            kit: Some(vec![Image {
-                name: ValidIdentifier("bottlerocket-core-kit".into()),
-                version: Version::new(1, 20, 0),
-                vendor: ValidIdentifier("not-bottlerocket".into()),
+            kit: Some(vec![KitDependency {
+                image: Image {
+                    name: ValidIdentifier("bottlerocket-core-kit".into()),
+                    version: Version::new(1, 20, 0),
+                    vendor: ValidIdentifier("not-bottlerocket".into()),
+                },
+                features: Vec::new(),
             }]),
+            kit_cache_dir: None,
+            kit_metadata_mismatch: None,
+            lock_schema_version: None,
+            variant: None,
+            profile: None,
+            tools: None,
+            build_cache: None,
+            package_limits: None,
+            dockerfile: None,
+            secrets: None,
+            hooks: None,
+            licenses: None,
+            proxy: None,
+            publish: None,
+            artifact_signing: None,
         };
         assert!(project.check_vendor_availability().await.is_err());
     }
@@ -520,6 +2159,737 @@ mod test {
         Project::find_and_load(p).await.unwrap();
     }
 
+    #[test]
+    fn check_kit_features() {
+        let toml = r#"
+            schema-version = 1
+            release-version = "1.0.0"
+
+            [[kit]]
+            name = "core-kit"
+            version = "1.0.0"
+            vendor = "my-vendor"
+            features = ["fips", "nvidia"]
+        "#;
+        let project: UnvalidatedProject = toml::from_str(toml).unwrap();
+        project.check_kit_features().unwrap();
+        assert_eq!(
+            project.kit.unwrap()[0].features,
+            vec!["fips".to_string(), "nvidia".to_string()]
+        );
+
+        for features in [r#"["fips", "fips"]"#, r#"["fips nvidia"]"#, r#"[""]"#] {
+            let toml = toml.replace(r#"["fips", "nvidia"]"#, features);
+            let project: UnvalidatedProject = toml::from_str(&toml).unwrap();
+            assert!(project.check_kit_features().is_err(), "{features}");
+        }
+    }
+
+    #[test]
+    fn deserialize_kit_metadata_mismatch_policy() {
+        let toml = r#"
+            schema-version = 1
+            release-version = "1.0.0"
+            kit-metadata-mismatch = "warn"
+        "#;
+        let project: UnvalidatedProject = toml::from_str(toml).unwrap();
+        assert_eq!(
+            project.kit_metadata_mismatch,
+            Some(MetadataMismatchPolicy::Warn)
+        );
+        assert_eq!(
+            MetadataMismatchPolicy::default(),
+            MetadataMismatchPolicy::Strict
+        );
+    }
+
+    #[test]
+    fn deserialize_vendor_auth_options() {
+        let toml = r#"
+            [ecr]
+            registry = "111111111111.dkr.ecr.us-west-2.amazonaws.com/kits"
+            credential-helper = "ecr-login"
+
+            [custom]
+            registry = "registry.example.com"
+            credential-helper = { command = ["/usr/bin/get-creds", "--quiet"] }
+
+            [bottlerocket]
+            registry = "public.ecr.aws/bottlerocket"
+            public = true
+
+            [hub]
+            registry = "bottlerocket"
+        "#;
+        let vendors: BTreeMap<ValidIdentifier, Vendor> = toml::from_str(toml).unwrap();
+        let ecr = vendors.get(&ValidIdentifier("ecr".into())).unwrap();
+        assert_eq!(
+            ecr.credential_helper,
+            Some(CredentialHelper::Name("ecr-login".into()))
+        );
+        assert_eq!(
+            ecr.registry_host(),
+            "111111111111.dkr.ecr.us-west-2.amazonaws.com"
+        );
+        let custom = vendors.get(&ValidIdentifier("custom".into())).unwrap();
+        assert_eq!(
+            custom.credential_helper,
+            Some(CredentialHelper::Command {
+                command: vec!["/usr/bin/get-creds".into(), "--quiet".into()]
+            })
+        );
+        assert_eq!(custom.registry_host(), "registry.example.com");
+        assert!(!custom.public);
+        let bottlerocket = vendors
+            .get(&ValidIdentifier("bottlerocket".into()))
+            .unwrap();
+        assert!(bottlerocket.public);
+        assert_eq!(bottlerocket.credential_helper, None);
+        let hub = vendors.get(&ValidIdentifier("hub".into())).unwrap();
+        assert_eq!(hub.registry_host(), "docker.io");
+    }
+
+    #[test]
+    fn check_vendor_sources() {
+        let toml = r#"
+            schema-version = 1
+            release-version = "1.0.0"
+
+            [vendor.local]
+            oci-layout = "build/oci"
+
+            [[kit]]
+            name = "core-kit"
+            version = "1.0.0"
+            vendor = "local"
+        "#;
+        let project: UnvalidatedProject = toml::from_str(toml).unwrap();
+        project.check_vendor_sources().unwrap();
+        let local = &project.vendor.as_ref().unwrap()[&ValidIdentifier("local".into())];
+        assert_eq!(local.repository(), "oci-layout:build/oci");
+
+        let both = toml.replace(
+            r#"oci-layout = "build/oci""#,
+            "oci-layout = \"build/oci\"\nregistry = \"a.com/b\"",
+        );
+        let project: UnvalidatedProject = toml::from_str(&both).unwrap();
+        assert!(project.check_vendor_sources().is_err());
+
+        let sdk =
+            format!("{toml}\n[sdk]\nname = \"sdk\"\nversion = \"1.0.0\"\nvendor = \"local\"\n");
+        let project: UnvalidatedProject = toml::from_str(&sdk).unwrap();
+        assert!(project.check_vendor_sources().is_err());
+
+        let sdk_arch = format!(
+            "{toml}\n[vendor.remote]\nregistry = \"a.com/b\"\n\
+            [sdk-arch.aarch64]\nname = \"sdk\"\nversion = \"1.1.0\"\nvendor = \"remote\"\n"
+        );
+        let project: UnvalidatedProject = toml::from_str(&sdk_arch).unwrap();
+        project.check_vendor_sources().unwrap();
+        let sdk_arch = project.sdk_arch.unwrap();
+        assert_eq!(sdk_arch["aarch64"].version, Version::new(1, 1, 0));
+
+        let bad_arch = format!(
+            "{toml}\n[vendor.remote]\nregistry = \"a.com/b\"\n\
+            [sdk-arch.riscv64]\nname = \"sdk\"\nversion = \"1.1.0\"\nvendor = \"remote\"\n"
+        );
+        let project: UnvalidatedProject = toml::from_str(&bad_arch).unwrap();
+        assert!(project.check_vendor_sources().is_err());
+    }
+
+    #[test]
+    fn check_variants() {
+        let toml = r#"
+            schema-version = 1
+            release-version = "1.0.0"
+
+            [[variant]]
+            name = "aws-dev"
+            arch = ["x86_64", "aarch64"]
+            upstream-source-fallback = true
+
+            [[variant]]
+            name = "metal-dev"
+            kits = ["core-kit"]
+            packages = ["release"]
+
+            [variant.image-features]
+            uefi-secure-boot = true
+        "#;
+        let project: UnvalidatedProject = toml::from_str(toml).unwrap();
+        project.check_variants().unwrap();
+        let variants = project.variant.unwrap();
+        assert_eq!(variants[0].arch, vec!["x86_64", "aarch64"]);
+        assert_eq!(variants[0].upstream_source_fallback, Some(true));
+        assert_eq!(variants[1].arch, vec!["x86_64"]);
+        assert!(!variants[0].defines_contents());
+        assert!(variants[1].defines_contents());
+        assert_eq!(
+            variants[1].image_features.get("uefi-secure-boot"),
+            Some(&true)
+        );
+
+        let duplicate = toml.replace("metal-dev", "aws-dev");
+        let project: UnvalidatedProject = toml::from_str(&duplicate).unwrap();
+        assert!(project.check_variants().is_err());
+
+        let bad_arch = toml.replace("\"aarch64\"", "\"riscv64\"");
+        let project: UnvalidatedProject = toml::from_str(&bad_arch).unwrap();
+        assert!(project.check_variants().is_err());
+    }
+
+    #[test]
+    fn tools_env_puts_docker_first_in_path() {
+        let tools = Tools {
+            docker: Some(PathBuf::from("/opt/docker/bin/docker")),
+            ..Default::default()
+        };
+        assert_eq!(tools.docker(), Path::new("/opt/docker/bin/docker"));
+        let env = tools.env().unwrap();
+        assert_eq!(
+            env[..3],
+            [
+                (
+                    "BUILDSYS_CONTAINER_CLI",
+                    "/opt/docker/bin/docker".to_string()
+                ),
+                ("TWOLITER_KIT_IMAGE_TOOL", "docker".to_string()),
+                (
+                    "TWOLITER_KIT_IMAGE_TOOL_PATH",
+                    "/opt/docker/bin/docker".to_string()
+                ),
+            ]
+        );
+        assert_eq!(env[3].0, "PATH");
+        assert!(env[3].1.starts_with("/opt/docker/bin:"));
+
+        assert_eq!(Tools::default().docker(), Path::new("docker"));
+        assert!(Tools::default().env().unwrap().is_empty());
+
+        let misnamed = Tools {
+            docker: Some(PathBuf::from("/opt/docker/bin/docker-24")),
+            ..Default::default()
+        };
+        assert!(misnamed.env().is_err());
+
+        let tools: Tools = toml::from_str("container-runtime = \"podman\"").unwrap();
+        assert_eq!(
+            tools.env().unwrap(),
+            vec![
+                ("BUILDSYS_CONTAINER_RUNTIME", "podman".to_string()),
+                ("BUILDSYS_CONTAINER_CLI", "podman".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tools_resolve_bare_names_through_path() {
+        let project_dir = Path::new("/project");
+        let tools = Tools {
+            docker: Some(PathBuf::from("sh")),
+            crane: Some(PathBuf::from("bin/crane")),
+            cosign: Some(PathBuf::from("/opt/cosign")),
+            container_runtime: None,
+        }
+        .resolve(project_dir)
+        .unwrap();
+        assert_eq!(tools.docker, Some(which::which("sh").unwrap()));
+        assert_eq!(tools.crane, Some(PathBuf::from("/project/bin/crane")));
+        assert_eq!(tools.cosign(), Path::new("/opt/cosign"));
+
+        let missing = Tools {
+            cosign: Some(PathBuf::from("twoliter-no-such-tool")),
+            ..Default::default()
+        };
+        assert!(missing.resolve(project_dir).is_err());
+    }
+
+    #[test]
+    fn build_cache_env() {
+        assert!(BuildCache::default().env(true).is_empty());
+
+        let cache: BuildCache =
+            toml::from_str("registry = \"registry.example.com/cache\"").unwrap();
+        assert_eq!(
+            cache.env(false),
+            vec![(
+                "BUILDSYS_CACHE_FROM",
+                "registry.example.com/cache".to_string()
+            )]
+        );
+        assert_eq!(cache.env(true).len(), 2);
+
+        let cache: BuildCache =
+            toml::from_str("registry = \"registry.example.com/cache\"\nexport = true").unwrap();
+        assert_eq!(
+            cache.env(false)[1],
+            (
+                "BUILDSYS_CACHE_TO",
+                "registry.example.com/cache".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn package_limits_env() {
+        assert!(PackageLimits::default().env().is_empty());
+        let limits: PackageLimits = toml::from_str("cpus = 4\nmemory = \"8g\"").unwrap();
+        assert_eq!(
+            limits.env(),
+            vec![
+                ("BUILDSYS_PACKAGE_CPUS", "4".to_string()),
+                ("BUILDSYS_PACKAGE_MEMORY", "8g".to_string()),
+            ]
+        );
+        let limits: PackageLimits =
+            toml::from_str("network = \"sdk-proxy\"\nsdk-proxy = \"http://proxy:3128\"").unwrap();
+        assert_eq!(
+            limits.env(),
+            vec![
+                ("BUILDSYS_PACKAGE_NETWORK", "sdk-proxy".to_string()),
+                ("BUILDSYS_SDK_PROXY", "http://proxy:3128".to_string()),
+            ]
+        );
+        assert!(toml::from_str::<PackageLimits>("network = \"some\"").is_err());
+    }
+
+    #[test]
+    fn proxy_env() {
+        let environment = |name: &str| match name {
+            "http_proxy" => Some("http://env-proxy:3128".to_string()),
+            "NO_PROXY" => Some(String::new()),
+            _ => None,
+        };
+        assert_eq!(
+            Proxy::default().env_from(environment),
+            vec![
+                ("HTTP_PROXY", "http://env-proxy:3128".to_string()),
+                ("http_proxy", "http://env-proxy:3128".to_string()),
+            ]
+        );
+
+        let proxy: Proxy =
+            toml::from_str("https-proxy = \"http://proxy:3128\"\nno-proxy = \"localhost\"")
+                .unwrap();
+        let env = proxy.env_from(environment);
+        assert_eq!(env.len(), 6);
+        assert!(env.contains(&("HTTPS_PROXY", "http://proxy:3128".to_string())));
+        assert!(env.contains(&("no_proxy", "localhost".to_string())));
+    }
+
+    #[test]
+    fn publish_env() {
+        let publish: Publish = toml::from_str(
+            r#"
+            [s3]
+            bucket = "images"
+            tags = { lifecycle = "30-days", team = "os" }
+
+            [ami]
+            regions = ["us-west-2", "us-east-1"]
+            name = "my-ami"
+            "#,
+        )
+        .unwrap();
+        let s3 = publish.s3.unwrap().env();
+        assert_eq!(
+            s3,
+            vec![
+                ("PUBLISH_S3_BUCKET", "images".to_string()),
+                ("PUBLISH_S3_TAGS", "lifecycle=30-days\nteam=os".to_string()),
+            ]
+        );
+        let ami = publish.ami.unwrap().env();
+        assert!(ami.contains(&("PUBLISH_REGIONS", "us-west-2,us-east-1".to_string())));
+        assert!(ami.contains(&("PUBLISH_AMI_NAME", "my-ami".to_string())));
+        assert!(ami.contains(&("PUBLISH_AMI_TAGS", String::new())));
+    }
+
+    #[test]
+    fn publish_repo_env() {
+        let repo: RepoPublish = toml::from_str(
+            r#"
+            name = "stable"
+            root-role = "roles/stable.root.json"
+            wave-policy = "/etc/waves.toml"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            repo.env(Path::new("/project")),
+            vec![
+                ("PUBLISH_REPO", "stable".to_string()),
+                (
+                    "PUBLISH_REPO_ROOT_JSON",
+                    "/project/roles/stable.root.json".to_string()
+                ),
+                ("PUBLISH_WAVE_POLICY_PATH", "/etc/waves.toml".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn signing_key_path_keeps_uris() {
+        let project_dir = Path::new("/project");
+        assert_eq!(
+            signing_key_path(project_dir, Path::new("keys/signing.key")),
+            PathBuf::from("/project/keys/signing.key")
+        );
+        assert_eq!(
+            signing_key_path(project_dir, Path::new("/etc/signing.key")),
+            PathBuf::from("/etc/signing.key")
+        );
+        assert_eq!(
+            signing_key_path(project_dir, Path::new("awskms:///alias/signing"))
+                .to_str()
+                .unwrap(),
+            "awskms:///alias/signing"
+        );
+        assert_eq!(
+            signing_key_path(project_dir, Path::new("keys/a://b")),
+            PathBuf::from("/project/keys/a://b")
+        );
+    }
+
+    #[test]
+    fn dockerfile_changes_env() {
+        assert!(DockerfileChanges::default().env().unwrap().is_empty());
+        let changes: DockerfileChanges = toml::from_str(
+            r#"
+            fragments = ["docker/scan.Dockerfile", "docker/sign.Dockerfile"]
+            targets = { package = "scanned-package" }
+            labels = { "org.example.team" = "os", "org.example.tier" = "1" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            changes.env().unwrap(),
+            vec![
+                (
+                    "BUILDSYS_DOCKERFILE_FRAGMENTS",
+                    "docker/scan.Dockerfile,docker/sign.Dockerfile".to_string()
+                ),
+                (
+                    "BUILDSYS_DOCKERFILE_TARGETS",
+                    "package=scanned-package".to_string()
+                ),
+                (
+                    "BUILDSYS_DOCKERFILE_LABELS",
+                    "org.example.team=os,org.example.tier=1".to_string()
+                ),
+            ]
+        );
+
+        let unknown_kind: DockerfileChanges =
+            toml::from_str(r#"targets = { image = "my-image" }"#).unwrap();
+        assert!(unknown_kind.env().is_err());
+        let comma: DockerfileChanges =
+            toml::from_str(r#"labels = { "team" = "os,kernel" }"#).unwrap();
+        assert!(comma.env().is_err());
+        let absolute: DockerfileChanges =
+            toml::from_str(r#"fragments = ["/etc/extra.Dockerfile"]"#).unwrap();
+        assert!(absolute.env().is_err());
+        let outside: DockerfileChanges =
+            toml::from_str(r#"fragments = ["docker/../../extra.Dockerfile"]"#).unwrap();
+        assert!(outside.env().is_err());
+    }
+
+    #[tokio::test]
+    async fn hooks_run_with_context() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_dir = temp_dir.path();
+        let hooks = Hooks {
+            post_variant_build: vec![
+                "echo \"$TWOLITER_HOOK $TWOLITER_VARIANT\" > hook.out".into(),
+                "echo \"$TWOLITER_PROJECT_DIR\" >> hook.out".into(),
+            ],
+            pre_fetch: vec!["exit 3".into()],
+            ..Default::default()
+        };
+        let context = [("TWOLITER_VARIANT", "my-variant".to_string())];
+        hooks
+            .run(Hook::PostVariantBuild, project_dir, &context)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(project_dir.join("hook.out")).unwrap(),
+            format!("post-variant-build my-variant\n{}\n", project_dir.display())
+        );
+
+        hooks
+            .run(Hook::PreKitBuild, project_dir, &[])
+            .await
+            .unwrap();
+        assert!(hooks.run(Hook::PreFetch, project_dir, &[]).await.is_err());
+    }
+
+    #[test]
+    fn json_schema_describes_project_file() {
+        let schema: serde_json::Value = serde_json::from_str(&json_schema().unwrap()).unwrap();
+        assert_eq!(schema["title"], "Twoliter.toml");
+        let properties = schema["properties"].as_object().unwrap();
+        for key in [
+            "schema-version",
+            "release-version",
+            "required-twoliter-version",
+            "sdk",
+            "vendor",
+            "kit",
+            "variant",
+            "profile",
+            "tools",
+            "hooks",
+        ] {
+            assert!(properties.contains_key(key), "missing '{key}'");
+        }
+        assert_eq!(
+            schema["properties"]["schema-version"]["enum"],
+            serde_json::json!([1])
+        );
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["release-version", "schema-version"])
+        );
+    }
+
+    #[test]
+    fn json_schema_accepts_every_top_level_key() {
+        let toml = r#"
+            schema-version = 1
+            release-version = "1.0.0"
+            required-twoliter-version = ">=0.5"
+            include = ["shared.toml"]
+            kit-cache-dir = "/var/cache/twoliter/kits"
+            kit-metadata-mismatch = "warn"
+            lock-schema-version = 2
+
+            [sdk]
+            name = "bottlerocket-sdk"
+            version = "0.41.0"
+            vendor = "bottlerocket"
+
+            [sdk-arch.aarch64]
+            name = "bottlerocket-sdk-arm"
+            version = "0.41.0"
+            vendor = "bottlerocket"
+
+            [vendor.bottlerocket]
+            registry = "public.ecr.aws/bottlerocket"
+            credential-helper = { command = ["my-helper"] }
+
+            [[kit]]
+            name = "core-kit"
+            version = "1.0.0"
+            vendor = "bottlerocket"
+            features = ["fips"]
+
+            [[variant]]
+            name = "aws-dev"
+            arch = ["x86_64", "aarch64"]
+            kits = ["core-kit"]
+            image-features = { uefi-secure-boot = true }
+
+            [profile.dev]
+            build-mode = "debug"
+
+            [tools]
+            container-runtime = "podman"
+
+            [build-cache]
+            registry = "registry.example.com/build-cache"
+
+            [package-limits]
+            cpus = 4
+
+            [dockerfile]
+            labels = { team = "os" }
+
+            [secrets.git-token]
+            env = "PRIVATE_GIT_TOKEN"
+
+            [hooks]
+            pre-fetch = ["./hooks/pre-fetch"]
+
+            [licenses]
+            denied = ["GPL-3.0-only"]
+
+            [proxy]
+            https-proxy = "http://proxy.example.com:3128"
+
+            [publish.s3]
+            bucket = "my-bucket"
+
+            [artifact-signing]
+            method = "cosign"
+            key = "cosign.key"
+        "#;
+        let mut table: Table = toml::from_str(toml).unwrap();
+        let schema: serde_json::Value = serde_json::from_str(&json_schema().unwrap()).unwrap();
+        let mut properties: Vec<&String> =
+            schema["properties"].as_object().unwrap().keys().collect();
+        let mut keys: Vec<&String> = table.keys().collect();
+        properties.sort();
+        keys.sort();
+        assert_eq!(properties, keys);
+        assert_eq!(unknown_keys(&table).unwrap(), Vec::new());
+
+        table.remove(INCLUDE_KEY);
+        let project: UnvalidatedProject = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(project.kit.unwrap()[0].features, vec!["fips"]);
+    }
+
+    #[test]
+    fn check_required_version_compares_with_this_version() {
+        let path = Path::new("Twoliter.toml");
+        let table = |requirement: &str| -> Table {
+            toml::from_str(&format!("{REQUIRED_VERSION_KEY} = \"{requirement}\"")).unwrap()
+        };
+        check_required_version(&Table::new(), path).unwrap();
+        check_required_version(&table(">=0.1"), path).unwrap();
+        check_required_version(&table(env!("CARGO_PKG_VERSION")), path).unwrap();
+
+        let err = check_required_version(&table(">=999.0"), path).unwrap_err();
+        assert!(err.to_string().contains("Please upgrade twoliter"));
+        assert!(check_required_version(&table("not a version"), path).is_err());
+    }
+
+    #[test]
+    fn check_unknown_keys_errors_only_when_strict() {
+        let path = Path::new("Twoliter.toml");
+        let table: Table = toml::from_str("schema-version = 1\nrelase-version = \"1.0\"").unwrap();
+        check_unknown_keys(&table, path, false).unwrap();
+        let err = check_unknown_keys(&table, path, true).unwrap_err();
+        assert!(err.to_string().contains("relase-version"));
+    }
+
+    #[test]
+    fn check_profiles() {
+        let toml = r#"
+            schema-version = 1
+            release-version = "1.0.0"
+
+            [profile.dev]
+            rpm-debuginfo = false
+            reproducible = true
+            compiler-cache = "ccache"
+            go-build-flags = "-race"
+            build-mode = "asan"
+
+            [profile.release]
+            image-compression-level = 12
+        "#;
+        assert!(unknown_keys(&toml::from_str(toml).unwrap())
+            .unwrap()
+            .is_empty());
+        let project: UnvalidatedProject = toml::from_str(toml).unwrap();
+        project.check_profiles().unwrap();
+        let profiles = project.profile.unwrap();
+        assert_eq!(
+            profiles["dev"].env("dev"),
+            vec![
+                ("BUILDSYS_PROFILE", "dev".to_string()),
+                ("BUILDSYS_RPM_DEBUGINFO", "false".to_string()),
+                ("BUILDSYS_REPRODUCIBLE", "true".to_string()),
+                ("BUILDSYS_COMPILER_CACHE", "ccache".to_string()),
+                ("BUILDSYS_GO_BUILD_FLAGS", "-race".to_string()),
+                ("BUILDSYS_PACKAGE_BUILD_MODE", "asan".to_string()),
+            ]
+        );
+
+        let bad_level = toml.replace("= 12", "= 13");
+        let project: UnvalidatedProject = toml::from_str(&bad_level).unwrap();
+        assert!(project.check_profiles().is_err());
+    }
+
+    #[test]
+    fn check_secrets() {
+        let toml = r#"
+            schema-version = 1
+            release-version = "1.0.0"
+
+            [secrets.git-token]
+            env = "PRIVATE_GIT_TOKEN"
+
+            [secrets."license.pem"]
+            file = "secrets/license.pem"
+        "#;
+        let project: UnvalidatedProject = toml::from_str(toml).unwrap();
+        project.check_secrets().unwrap();
+        let secrets = project.secrets.unwrap();
+        assert_eq!(
+            secrets["git-token"].env.as_deref(),
+            Some("PRIVATE_GIT_TOKEN")
+        );
+        assert_eq!(
+            secrets["license.pem"].file.as_deref(),
+            Some(Path::new("secrets/license.pem"))
+        );
+        assert_eq!(
+            secrets_env(Path::new("/project"), &secrets),
+            "git-token=env:PRIVATE_GIT_TOKEN\nlicense.pem=file:/project/secrets/license.pem"
+        );
+
+        let both = toml.replace(
+            "env = \"PRIVATE_GIT_TOKEN\"",
+            "env = \"PRIVATE_GIT_TOKEN\"\nfile = \"token\"",
+        );
+        let project: UnvalidatedProject = toml::from_str(&both).unwrap();
+        assert!(project.check_secrets().is_err());
+        let bad_name = toml.replace("[secrets.git-token]", "[secrets.\"../git-token\"]");
+        let project: UnvalidatedProject = toml::from_str(&bad_name).unwrap();
+        assert!(project.check_secrets().is_err());
+    }
+
+    #[tokio::test]
+    async fn load_with_includes() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        std::fs::create_dir(dir.join("shared")).unwrap();
+        std::fs::write(
+            dir.join("shared/common.toml"),
+            r#"
+                include = ["sdk.toml"]
+                release-version = "0.0.1"
+
+                [vendor.bottlerocket]
+                registry = "public.ecr.aws/bottlerocket"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("shared/sdk.toml"),
+            r#"
+                [sdk]
+                name = "bottlerocket-sdk"
+                vendor = "bottlerocket"
+                version = "0.41.0"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("Twoliter.toml"),
+            r#"
+                include = ["shared/common.toml"]
+                schema-version = 1
+                release-version = "1.0.0"
+
+                [vendor.my-vendor]
+                registry = "example.com/my-vendor"
+            "#,
+        )
+        .unwrap();
+
+        let project = Project::load(dir.join("Twoliter.toml")).await.unwrap();
+        assert_eq!(project.release_version(), "1.0.0");
+        assert_eq!(project.sdk_image().unwrap().version, Version::new(0, 41, 0));
+        assert_eq!(project.vendor().len(), 2);
+
+        // An include cycle is an error rather than endless recursion.
+        std::fs::write(dir.join("shared/sdk.toml"), r#"include = ["common.toml"]"#).unwrap();
+        let err = Project::load(dir.join("Twoliter.toml")).await.unwrap_err();
+        assert!(format!("{err:#}").contains("cycle"));
+    }
+
     #[tokio::test]
     async fn find_go_modules() {
         let twoliter_toml_path = projects_dir().join("project1").join("Twoliter.toml");
@@ -0,0 +1,245 @@
+//! Finds keys in `Twoliter.toml` which Twoliter does not recognize. Serde silently ignores unknown
+//! keys, so without this check a typo such as `verndor` goes unnoticed.
+//!
+//! The keys Twoliter recognizes are read from the JSON schema of the project file, which is
+//! generated from the types the project is deserialized into, so a new field is recognized as soon
+//! as it is added.
+use crate::project::project_schema;
+use anyhow::{Context, Result};
+use serde_json::{Map, Value as Schema};
+use std::fmt::{Display, Formatter};
+use toml::{Table, Value};
+
+/// A key which Twoliter does not recognize, along with the known key it most resembles.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct UnknownKey {
+    /// The dotted path to the key, e.g. `vendor.bottlerocket.regsitry`
+    pub(crate) path: String,
+    pub(crate) suggestion: Option<String>,
+}
+
+impl Display for UnknownKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown key '{}'", self.path)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean '{suggestion}'?)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns every key in the project table which Twoliter does not recognize.
+pub(crate) fn unknown_keys(project: &Table) -> Result<Vec<UnknownKey>> {
+    let schema = serde_json::to_value(project_schema())
+        .context("Unable to serialize the Twoliter.toml schema")?;
+    let no_definitions = Map::new();
+    let keys = Keys {
+        definitions: schema
+            .get("definitions")
+            .and_then(Schema::as_object)
+            .unwrap_or(&no_definitions),
+    };
+    let mut unknown = Vec::new();
+    keys.check_table(project, &schema, "", &mut unknown);
+    Ok(unknown)
+}
+
+/// Reads the keys which may appear in a TOML value from the JSON schema of the value.
+struct Keys<'a> {
+    /// The schemas which `$ref`s in the project schema point to, by name
+    definitions: &'a Map<String, Schema>,
+}
+
+impl<'a> Keys<'a> {
+    fn check_table(
+        &self,
+        table: &Table,
+        schema: &'a Schema,
+        path: &str,
+        unknown: &mut Vec<UnknownKey>,
+    ) {
+        let alternatives = self.alternatives(schema);
+        // The keys of a struct are its properties, while a map's keys are names chosen by the user,
+        // each of whose values has the schema given by `additionalProperties`.
+        let properties: Vec<(&String, &Schema)> = alternatives
+            .iter()
+            .filter_map(|schema| schema.get("properties").and_then(Schema::as_object))
+            .flatten()
+            .collect();
+        let values = alternatives
+            .iter()
+            .find_map(|schema| schema.get("additionalProperties").filter(|v| v.is_object()));
+        if properties.is_empty() && values.is_none() {
+            return;
+        }
+        for (key, value) in table {
+            let key_path = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{path}.{key}")
+            };
+            let known = properties
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, schema)| *schema);
+            match known.or(values) {
+                Some(schema) => self.check_value(value, schema, &key_path, unknown),
+                None => unknown.push(UnknownKey {
+                    path: key_path,
+                    suggestion: suggest(key, properties.iter().map(|(name, _)| name.as_str()))
+                        .map(str::to_string),
+                }),
+            }
+        }
+    }
+
+    fn check_value(
+        &self,
+        value: &Value,
+        schema: &'a Schema,
+        path: &str,
+        unknown: &mut Vec<UnknownKey>,
+    ) {
+        match value {
+            Value::Table(table) => self.check_table(table, schema, path, unknown),
+            Value::Array(items) => {
+                let Some(item_schema) = self
+                    .alternatives(schema)
+                    .into_iter()
+                    .find_map(|schema| schema.get("items").filter(|v| v.is_object()))
+                else {
+                    return;
+                };
+                for (i, item) in items.iter().enumerate() {
+                    self.check_value(item, item_schema, &format!("{path}[{i}]"), unknown);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The schemas a value described by `schema` may match: `schema` itself, the definition it
+    /// refers to, and those it combines with `allOf`, `anyOf` or `oneOf`, such as the fields of a
+    /// flattened struct or the variants of an enum.
+    fn alternatives(&self, schema: &'a Schema) -> Vec<&'a Schema> {
+        let mut alternatives = Vec::new();
+        let mut pending = vec![schema];
+        while let Some(schema) = pending.pop() {
+            if let Some(name) = schema
+                .get("$ref")
+                .and_then(Schema::as_str)
+                .and_then(|reference| reference.strip_prefix("#/definitions/"))
+            {
+                pending.extend(self.definitions.get(name));
+            }
+            for combinator in ["allOf", "anyOf", "oneOf"] {
+                if let Some(schemas) = schema.get(combinator).and_then(Schema::as_array) {
+                    pending.extend(schemas);
+                }
+            }
+            alternatives.push(schema);
+        }
+        alternatives
+    }
+}
+
+/// Returns the known key closest to `key`, if it is near enough to be a likely typo.
+fn suggest<'a>(key: &str, known: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (key.chars().count() / 3).max(1);
+    known
+        .map(|name| (edit_distance(key, name), name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_unknown_keys_with_suggestions() {
+        let project: Table = toml::from_str(
+            r#"
+                schema-version = 1
+                release-version = "1.0.0"
+                frobnicate = true
+
+                [verndor.bottlerocket]
+                registry = "public.ecr.aws/bottlerocket"
+
+                [vendor.my-vendor]
+                regsitry = "example.com/my-vendor"
+                credential-helper = "ecr-login"
+
+                [[kit]]
+                name = "core-kit"
+                version = "1.0.0"
+                vendor = "my-vendor"
+                vendr = "my-vendor"
+            "#,
+        )
+        .unwrap();
+        let unknown: Vec<String> = unknown_keys(&project)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            unknown,
+            vec![
+                "unknown key 'frobnicate'",
+                "unknown key 'kit[0].vendr' (did you mean 'vendor'?)",
+                "unknown key 'vendor.my-vendor.regsitry' (did you mean 'registry'?)",
+                "unknown key 'verndor' (did you mean 'vendor'?)",
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_every_key_of_the_project_types() {
+        let project: Table = toml::from_str(
+            r#"
+                schema-version = 1
+                release-version = "1.0.0"
+                required-twoliter-version = ">=0.5"
+
+                [vendor.my-vendor]
+                registry = "example.com/my-vendor"
+                credential-helper = { command = ["my-helper", "--profile", "ci"] }
+                public = true
+                max-concurrent-requests = 4
+
+                [[kit]]
+                name = "core-kit"
+                version = "1.0.0"
+                vendor = "my-vendor"
+                features = ["fips"]
+
+                [profile.dev]
+                build-mode = "debug"
+
+                [publish.s3]
+                bucket = "my-bucket"
+                tags = { team = "os" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(unknown_keys(&project).unwrap(), Vec::new());
+    }
+}
@@ -0,0 +1,219 @@
+//! A machine-readable record of how `Twoliter.lock` was resolved, for supply-chain audits. It lists
+//! every registry request made and any which had to be retried, the manifest lists seen, the kit
+//! metadata decoded from each image, and the decisions taken along the way.
+use crate::common::fs::write;
+use crate::lock::{LockedImage, ManifestListView};
+use crate::project::Image;
+use anyhow::{Context, Result};
+use oci_cli_wrapper::Retry;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Operation {
+    GetManifest,
+    GetConfig,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct RegistryRequest {
+    pub(crate) operation: Operation,
+    pub(crate) uri: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ManifestRecord {
+    pub(crate) image: String,
+    pub(crate) source: String,
+    /// The digest of the manifest list as recorded in `Twoliter.lock`
+    pub(crate) digest: String,
+    /// The digest of each per-architecture image in the manifest list
+    pub(crate) architectures: BTreeMap<String, String>,
+}
+
+/// The kit metadata label found in a single per-architecture image.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct MetadataRecord {
+    pub(crate) image: String,
+    pub(crate) uri: String,
+    pub(crate) architecture: String,
+    /// The label exactly as it appears in the image config
+    pub(crate) label: String,
+    /// The decoded label, if it could be decoded
+    pub(crate) sdk: Option<String>,
+    pub(crate) kits: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "decision", rename_all = "kebab-case")]
+pub(crate) enum Decision {
+    /// A kit was locked at the given version.
+    LockKit {
+        kit: String,
+        version: String,
+        source: String,
+        digest: String,
+    },
+    /// A kit was required again after it had already been locked.
+    AlreadyLocked { kit: String, version: String },
+    /// The sdk was chosen from those required by the project and its kits.
+    ChooseSdk {
+        sdk: String,
+        required: Vec<String>,
+        source: String,
+        digest: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ResolutionReport {
+    pub(crate) requests: Vec<RegistryRequest>,
+    pub(crate) retries: Vec<Retry>,
+    pub(crate) manifests: Vec<ManifestRecord>,
+    pub(crate) metadata: Vec<MetadataRecord>,
+    pub(crate) decisions: Vec<Decision>,
+}
+
+impl ResolutionReport {
+    /// Records the manifest list request made to lock `image` and the manifest list it returned.
+    pub(crate) fn record_manifest(&mut self, image: &LockedImage) {
+        self.requests.push(RegistryRequest {
+            operation: Operation::GetManifest,
+            uri: image.source.clone(),
+        });
+        let architectures = serde_json::from_slice::<ManifestListView>(image.manifest.as_slice())
+            .map(|manifest_list| {
+                manifest_list
+                    .manifests
+                    .iter()
+                    .map(|manifest| {
+                        let arch = manifest
+                            .architecture()
+                            .map(ToString::to_string)
+                            .unwrap_or_else(|| "unknown".to_string());
+                        (arch, manifest.digest.clone())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.manifests.push(ManifestRecord {
+            image: image_id(image),
+            source: image.source.clone(),
+            digest: image.digest.clone(),
+            architectures,
+        });
+    }
+
+    /// Records the image config requests made to read kit metadata, and what they returned.
+    pub(crate) fn record_metadata(&mut self, records: Vec<MetadataRecord>) {
+        for record in records {
+            self.requests.push(RegistryRequest {
+                operation: Operation::GetConfig,
+                uri: record.uri.clone(),
+            });
+            self.metadata.push(record);
+        }
+    }
+
+    /// Records the registry requests which failed and were made again, e.g. after rate limiting.
+    pub(crate) fn record_retries(&mut self, retries: Vec<Retry>) {
+        self.retries.extend(retries);
+    }
+
+    pub(crate) fn lock_kit(&mut self, image: &LockedImage) {
+        self.decisions.push(Decision::LockKit {
+            kit: format!("{}@{}", image.name, image.vendor),
+            version: image.version.to_string(),
+            source: image.source.clone(),
+            digest: image.digest.clone(),
+        });
+    }
+
+    pub(crate) fn already_locked(&mut self, image: &Image) {
+        self.decisions.push(Decision::AlreadyLocked {
+            kit: format!("{}@{}", image.name, image.vendor),
+            version: image.version.to_string(),
+        });
+    }
+
+    pub(crate) fn choose_sdk<'a, I>(&mut self, sdk: &LockedImage, required: I)
+    where
+        I: IntoIterator<Item = &'a Image>,
+    {
+        let mut required: Vec<String> = required.into_iter().map(ToString::to_string).collect();
+        required.sort();
+        self.decisions.push(Decision::ChooseSdk {
+            sdk: image_id(sdk),
+            required,
+            source: sdk.source.clone(),
+            digest: sdk.digest.clone(),
+        });
+    }
+
+    /// Writes the report to `path` as JSON.
+    pub(crate) async fn write(&self, path: &Path) -> Result<()> {
+        let json =
+            serde_json::to_string_pretty(self).context("failed to serialize resolution report")?;
+        write(path, json).await.context(format!(
+            "failed to write resolution report to '{}'",
+            path.display()
+        ))
+    }
+}
+
+/// Identifies a locked image the same way [`Image`] is displayed.
+pub(crate) fn image_id(image: &LockedImage) -> String {
+    format!("{}-{}@{}", image.name, image.version, image.vendor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn report_serializes_decisions() {
+        let mut report = ResolutionReport::default();
+        report.already_locked(&Image {
+            name: crate::project::ValidIdentifier("core-kit".into()),
+            version: Version::new(1, 0, 0),
+            vendor: crate::project::ValidIdentifier("my-vendor".into()),
+        });
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json["decisions"][0],
+            serde_json::json!({
+                "decision": "already-locked",
+                "kit": "core-kit@my-vendor",
+                "version": "1.0.0",
+            })
+        );
+        assert_eq!(json["requests"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn report_serializes_retries() {
+        let mut report = ResolutionReport::default();
+        report.record_retries(vec![Retry {
+            uri: "a.com/b/core-kit:v1.0.0".to_string(),
+            reason: oci_cli_wrapper::RetryReason::RateLimited { wait_secs: 2 },
+            error: "too many requests".to_string(),
+        }]);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json["retries"][0],
+            serde_json::json!({
+                "uri": "a.com/b/core-kit:v1.0.0",
+                "reason": "rate-limited",
+                "wait-secs": 2,
+                "error": "too many requests",
+            })
+        );
+    }
+}
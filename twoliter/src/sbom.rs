@@ -0,0 +1,1114 @@
+//! Describes what a variant, a kit or the whole project is made of, as a software bill of materials
+//! in SPDX or CycloneDX format. The packages come from their manifests and spec files, along with
+//! the external files fetched from the lookaside cache, the Go modules vendored into them and the
+//! crates locked for their source groups. Kits come from the project and from `Twoliter.lock`. For
+//! a variant which has been built, the RPMs listed in its application inventory are added too.
+use crate::common::fs;
+use crate::lint::read_manifests;
+use crate::lock::{Lock, LockedImage, TWOLITER_LOCK};
+use crate::project::Project;
+use anyhow::{bail, Context, Result};
+use buildsys::manifest::{BundleModule, Manifest, ManifestInfo};
+use buildsys::spec::SpecInfo;
+use buildsys::BuildType;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tar::Archive as TarArchive;
+use tracing::debug;
+use uuid::Uuid;
+
+/// Where buildsys finds the Cargo metadata of the project, which is written by every build.
+const CARGO_METADATA: &str = "build/metadata/cargo_metadata.json";
+
+/// The name of the inventory of installed RPMs written alongside a variant's images.
+const INVENTORY: &str = "application-inventory.json";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub(crate) enum SbomFormat {
+    Spdx,
+    Cyclonedx,
+}
+
+impl SbomFormat {
+    /// The name of the file an SBOM in this format is written to next to a variant's images.
+    pub(crate) fn file_name(&self) -> &'static str {
+        match self {
+            SbomFormat::Spdx => "sbom.spdx.json",
+            SbomFormat::Cyclonedx => "sbom.cdx.json",
+        }
+    }
+}
+
+/// What an SBOM describes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum Scope {
+    Project,
+    Variant { name: String, arch: String },
+    Kit { name: String },
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub(crate) enum ComponentKind {
+    Package,
+    Kit,
+    SourceArchive,
+    GoModule,
+    Crate,
+}
+
+impl ComponentKind {
+    fn id_prefix(&self) -> &'static str {
+        match self {
+            ComponentKind::Package => "Package",
+            ComponentKind::Kit => "Kit",
+            ComponentKind::SourceArchive => "Source",
+            ComponentKind::GoModule => "GoModule",
+            ComponentKind::Crate => "Crate",
+        }
+    }
+}
+
+/// Something the described variant, kit or project is made of.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Component {
+    pub(crate) kind: ComponentKind,
+    pub(crate) name: String,
+    pub(crate) version: Option<String>,
+    /// The license declared for the component, as an SPDX expression
+    pub(crate) license: Option<String>,
+    /// Where the component comes from
+    pub(crate) url: Option<String>,
+    pub(crate) purl: Option<String>,
+    pub(crate) sha256: Option<String>,
+    pub(crate) sha512: Option<String>,
+    /// The package which is built from or with this component, if any
+    pub(crate) used_by: Option<String>,
+}
+
+impl Component {
+    fn new(kind: ComponentKind, name: impl Into<String>) -> Self {
+        Self {
+            kind,
+            name: name.into(),
+            version: None,
+            license: None,
+            url: None,
+            purl: None,
+            sha256: None,
+            sha512: None,
+            used_by: None,
+        }
+    }
+}
+
+/// A software bill of materials, before it is rendered in a format.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Document {
+    pub(crate) scope: Scope,
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) components: Vec<Component>,
+}
+
+/// A local package and what was read from its manifest.
+struct LocalPackage {
+    manifest_path: PathBuf,
+    info: ManifestInfo,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Inventory {
+    content: Vec<InventoryEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InventoryEntry {
+    name: String,
+    version: String,
+    release: String,
+    architecture: String,
+    url: String,
+}
+
+impl Document {
+    /// Collects the components of `scope`.
+    pub(crate) async fn collect(project: &Project, scope: Scope) -> Result<Self> {
+        let project_dir = project.project_dir();
+        let mut packages = HashMap::new();
+        let mut kits = HashMap::new();
+        let mut variants = HashMap::new();
+        for (manifest_path, info) in read_manifests(&project_dir).await? {
+            match info.build_type() {
+                Ok(BuildType::Package) => {
+                    packages.insert(
+                        info.package_name().to_string(),
+                        LocalPackage {
+                            manifest_path,
+                            info,
+                        },
+                    );
+                }
+                Ok(BuildType::Kit) => {
+                    kits.insert(info.kit_name().to_string(), manifest_path);
+                }
+                Ok(BuildType::Variant) => {
+                    variants.insert(info.manifest_name().to_string(), manifest_path);
+                }
+                Ok(BuildType::Repack) | Err(_) => (),
+            }
+        }
+
+        let (name, package_names, kit_names) = match &scope {
+            Scope::Project => (
+                project_name(&project_dir),
+                packages.keys().cloned().collect(),
+                kits.keys().cloned().collect(),
+            ),
+            Scope::Variant { name, .. } => {
+                let manifest_path = variants.get(name).context(format!(
+                    "no variant named '{name}' was found in the project"
+                ))?;
+                let (package_names, kit_names) = dependencies(&project_dir, manifest_path, &kits)?;
+                (name.clone(), package_names, kit_names)
+            }
+            Scope::Kit { name } => {
+                let manifest_path = kits
+                    .get(name)
+                    .context(format!("no kit named '{name}' was found in the project"))?;
+                let (package_names, kit_names) = dependencies(&project_dir, manifest_path, &kits)?;
+                (name.clone(), package_names, kit_names)
+            }
+        };
+
+        let mut components = Vec::new();
+        for package_name in &package_names {
+            let Some(package) = packages.get(package_name) else {
+                continue;
+            };
+            components.extend(package_components(&project_dir, package)?);
+        }
+        for kit in &kit_names {
+            if *kit == name && matches!(scope, Scope::Kit { .. }) {
+                continue;
+            }
+            let mut component = Component::new(ComponentKind::Kit, kit);
+            component.version = Some(project.release_version().to_string());
+            components.push(component);
+        }
+        let inventory = match &scope {
+            Scope::Variant { name, arch } => read_inventory(&project_dir, name, arch).await?,
+            Scope::Kit { .. } | Scope::Project => None,
+        };
+        if project_dir.join(TWOLITER_LOCK).exists() {
+            let lock = Lock::read_lock_file(project).await?;
+            // A variant only uses the external kits its installed RPMs come from.
+            let used = inventory
+                .as_ref()
+                .map(|inventory| used_kits(&project.external_kits_dir(), &lock.kit, inventory))
+                .transpose()?;
+            let kits = lock
+                .kit
+                .iter()
+                .filter(|kit| used.as_ref().map_or(true, |used| used.contains(&kit.name)));
+            for kit in kits {
+                components.push(external_kit_component(kit)?);
+            }
+        }
+        if let Some(inventory) = inventory {
+            add_inventory(&mut components, inventory);
+        }
+
+        let mut seen = HashSet::new();
+        components.retain(|component| {
+            seen.insert((
+                component.kind,
+                component.name.clone(),
+                component.version.clone(),
+                component.used_by.clone(),
+            ))
+        });
+        Ok(Self {
+            scope,
+            name,
+            version: project.release_version().to_string(),
+            components,
+        })
+    }
+
+    /// Renders the document as JSON in `format`.
+    pub(crate) fn render(&self, format: SbomFormat, created: DateTime<Utc>) -> Result<String> {
+        let document = match format {
+            SbomFormat::Spdx => self.spdx(created),
+            SbomFormat::Cyclonedx => self.cyclonedx(created),
+        };
+        serde_json::to_string_pretty(&document).context("Unable to serialize the SBOM")
+    }
+
+    /// A unique identifier for each component, made only of letters, digits, `.` and `-` as SPDX
+    /// requires.
+    fn ids(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.components
+            .iter()
+            .map(|component| {
+                let base = sanitize(&format!(
+                    "{}-{}-{}",
+                    component.kind.id_prefix(),
+                    component.name,
+                    component.version.as_deref().unwrap_or_default()
+                ));
+                let mut id = base.clone();
+                let mut n = 1;
+                while !seen.insert(id.clone()) {
+                    n += 1;
+                    id = format!("{base}-{n}");
+                }
+                id
+            })
+            .collect()
+    }
+
+    /// The ID of the component each component is used by, or `None` for those used directly.
+    fn parents<'a>(&self, ids: &'a [String]) -> Vec<Option<&'a str>> {
+        let packages: HashMap<&str, &str> = self
+            .components
+            .iter()
+            .zip(ids)
+            .filter(|(component, _)| component.kind == ComponentKind::Package)
+            .map(|(component, id)| (component.name.as_str(), id.as_str()))
+            .collect();
+        self.components
+            .iter()
+            .map(|component| {
+                component
+                    .used_by
+                    .as_deref()
+                    .and_then(|package| packages.get(package).copied())
+            })
+            .collect()
+    }
+
+    fn spdx(&self, created: DateTime<Utc>) -> Value {
+        let ids = self.ids();
+        let parents = self.parents(&ids);
+        let root = "SPDXRef-Root";
+        let mut packages = vec![json!({
+            "SPDXID": root,
+            "name": self.name,
+            "versionInfo": self.version,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "primaryPackagePurpose": match self.scope {
+                Scope::Variant { .. } => "OPERATING-SYSTEM",
+                Scope::Kit { .. } => "CONTAINER",
+                Scope::Project => "APPLICATION",
+            },
+        })];
+        let mut relationships = vec![json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": root,
+        })];
+        for ((component, id), parent) in self.components.iter().zip(&ids).zip(&parents) {
+            let id = format!("SPDXRef-{id}");
+            let mut package = json!({
+                "SPDXID": id,
+                "name": component.name,
+                "downloadLocation": component.url.as_deref().unwrap_or("NOASSERTION"),
+                "filesAnalyzed": false,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": component
+                    .license
+                    .as_deref()
+                    .filter(|license| is_spdx_expression(license))
+                    .unwrap_or("NOASSERTION"),
+            });
+            if let Some(version) = &component.version {
+                package["versionInfo"] = json!(version);
+            }
+            let checksums: Vec<Value> =
+                [("SHA256", &component.sha256), ("SHA512", &component.sha512)]
+                    .into_iter()
+                    .filter_map(|(algorithm, value)| {
+                        value.as_ref().map(|value| {
+                            json!({
+                                "algorithm": algorithm,
+                                "checksumValue": value,
+                            })
+                        })
+                    })
+                    .collect();
+            if !checksums.is_empty() {
+                package["checksums"] = json!(checksums);
+            }
+            if let Some(purl) = &component.purl {
+                package["externalRefs"] = json!([{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": purl,
+                }]);
+            }
+            packages.push(package);
+
+            let relationship = match (parent, component.kind) {
+                (Some(parent), ComponentKind::SourceArchive) => json!({
+                    "spdxElementId": format!("SPDXRef-{parent}"),
+                    "relationshipType": "GENERATED_FROM",
+                    "relatedSpdxElement": id,
+                }),
+                (Some(parent), _) => json!({
+                    "spdxElementId": format!("SPDXRef-{parent}"),
+                    "relationshipType": "CONTAINS",
+                    "relatedSpdxElement": id,
+                }),
+                (None, _) => json!({
+                    "spdxElementId": root,
+                    "relationshipType": "CONTAINS",
+                    "relatedSpdxElement": id,
+                }),
+            };
+            relationships.push(relationship);
+        }
+
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": format!("{}-{}", self.name, self.version),
+            "documentNamespace": format!(
+                "https://github.com/bottlerocket-os/twoliter/spdx/{}-{}-{}",
+                sanitize(&self.name),
+                sanitize(&self.version),
+                Uuid::new_v4()
+            ),
+            "creationInfo": {
+                "created": created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "creators": [format!("Tool: twoliter-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+
+    fn cyclonedx(&self, created: DateTime<Utc>) -> Value {
+        let ids = self.ids();
+        let parents = self.parents(&ids);
+        let root = "root";
+        let mut components = Vec::new();
+        let mut depends_on: Vec<(&str, Vec<&str>)> = vec![(root, Vec::new())];
+        let mut used = HashMap::new();
+        for ((component, id), parent) in self.components.iter().zip(&ids).zip(&parents) {
+            let mut entry = json!({
+                "type": match component.kind {
+                    ComponentKind::Kit => "container",
+                    ComponentKind::SourceArchive => "file",
+                    ComponentKind::Package | ComponentKind::GoModule | ComponentKind::Crate => {
+                        "library"
+                    }
+                },
+                "bom-ref": id,
+                "name": component.name,
+            });
+            if let Some(version) = &component.version {
+                entry["version"] = json!(version);
+            }
+            match &component.license {
+                Some(license) if is_spdx_expression(license) => {
+                    entry["licenses"] = json!([{ "expression": license }]);
+                }
+                Some(license) => entry["licenses"] = json!([{ "license": { "name": license } }]),
+                None => (),
+            }
+            if let Some(purl) = &component.purl {
+                entry["purl"] = json!(purl);
+            }
+            let hashes: Vec<Value> = [
+                ("SHA-256", &component.sha256),
+                ("SHA-512", &component.sha512),
+            ]
+            .into_iter()
+            .filter_map(|(alg, value)| {
+                value
+                    .as_ref()
+                    .map(|value| json!({ "alg": alg, "content": value }))
+            })
+            .collect();
+            if !hashes.is_empty() {
+                entry["hashes"] = json!(hashes);
+            }
+            if let Some(url) = &component.url {
+                entry["externalReferences"] = json!([{ "type": "distribution", "url": url }]);
+            }
+            components.push(entry);
+
+            match parent {
+                Some(parent) => used
+                    .entry(*parent)
+                    .or_insert_with(Vec::new)
+                    .push(id.as_str()),
+                None => depends_on[0].1.push(id.as_str()),
+            }
+        }
+        depends_on.extend(ids.iter().filter_map(|id| {
+            used.remove(id.as_str())
+                .map(|dependencies| (id.as_str(), dependencies))
+        }));
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", Uuid::new_v4()),
+            "version": 1,
+            "metadata": {
+                "timestamp": created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": "twoliter",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {
+                    "type": match self.scope {
+                        Scope::Variant { .. } => "operating-system",
+                        Scope::Kit { .. } => "container",
+                        Scope::Project => "application",
+                    },
+                    "bom-ref": root,
+                    "name": self.name,
+                    "version": self.version,
+                },
+            },
+            "components": components,
+            "dependencies": depends_on
+                .into_iter()
+                .map(|(id, dependencies)| json!({ "ref": id, "dependsOn": dependencies }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Writes the SBOM of a variant built for `arch` into `output_dir`, next to its images.
+pub(crate) async fn write_for_variant(
+    project: &Project,
+    variant: &str,
+    arch: &str,
+    format: SbomFormat,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let document = Document::collect(
+        project,
+        Scope::Variant {
+            name: variant.to_string(),
+            arch: arch.to_string(),
+        },
+    )
+    .await?;
+    let path = output_dir.join(format.file_name());
+    fs::write(&path, document.render(format, Utc::now())?).await?;
+    Ok(path)
+}
+
+/// The packages and kits that the variant or kit at `manifest_path` is built from, including the
+/// packages of the project's kits that it uses.
+fn dependencies(
+    project_dir: &Path,
+    manifest_path: &Path,
+    kits: &HashMap<String, PathBuf>,
+) -> Result<(BTreeSet<String>, BTreeSet<String>)> {
+    let cargo_metadata = project_dir.join(CARGO_METADATA);
+    if !cargo_metadata.exists() {
+        bail!(
+            "'{}' does not exist, build the project first to find what it depends on",
+            cargo_metadata.display()
+        );
+    }
+    let load = |path: &Path| {
+        Manifest::new(path, &cargo_metadata).context(format!("Unable to load '{}'", path.display()))
+    };
+    let manifest = load(manifest_path)?;
+    let mut packages: BTreeSet<String> = manifest.package_dependencies()?.into_iter().collect();
+    let kit_names: BTreeSet<String> = manifest.kit_dependencies()?.into_iter().collect();
+    for kit in &kit_names {
+        if let Some(kit_manifest_path) = kits.get(kit).filter(|path| *path != manifest_path) {
+            packages.extend(load(kit_manifest_path)?.package_dependencies()?);
+        }
+    }
+    Ok((packages, kit_names))
+}
+
+/// The package itself, the external files it is built from, and the Go modules and crates built
+/// into it.
+fn package_components(project_dir: &Path, package: &LocalPackage) -> Result<Vec<Component>> {
+    let name = package.info.package_name();
+    let package_dir = package
+        .manifest_path
+        .parent()
+        .context("package manifest has no parent directory")?;
+    let mut rpm = Component::new(ComponentKind::Package, name);
+    let spec_path = package_dir.join(format!("{name}.spec"));
+    if spec_path.is_file() {
+        let spec = SpecInfo::new(&spec_path)
+            .context(format!("Unable to parse '{}'", spec_path.display()))?;
+        rpm.purl = spec
+            .version
+            .as_ref()
+            .map(|version| format!("pkg:rpm/bottlerocket/{name}@{version}"));
+        rpm.version = spec.version;
+        rpm.license = spec.license;
+        rpm.url = spec.url;
+    }
+    let mut components = vec![rpm];
+
+    for file in package.info.external_files().into_iter().flatten() {
+        let file_name = match file.file_name() {
+            Ok(name) => name.display().to_string(),
+            Err(_) => file.source_url().to_string(),
+        };
+        let mut source = Component::new(ComponentKind::SourceArchive, &file_name);
+        source.url = Some(file.source_url().to_string());
+        source.sha512 = Some(file.sha512.clone());
+        source.used_by = Some(name.to_string());
+        components.push(source);
+
+        let vendors_go = file
+            .bundle_modules
+            .iter()
+            .flatten()
+            .any(|module| matches!(module, BundleModule::Go));
+        if vendors_go {
+            let bundle = package_dir.join(
+                file.bundle_output_path
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from(format!("bundled-{file_name}"))),
+            );
+            if !bundle.is_file() {
+                debug!(
+                    "'{}' has not been created, so its Go modules are not listed",
+                    bundle.display()
+                );
+                continue;
+            }
+            components.extend(go_modules(&bundle)?.into_iter().map(|(path, version)| {
+                let mut module = Component::new(ComponentKind::GoModule, &path);
+                module.purl = Some(format!("pkg:golang/{path}@{version}"));
+                module.version = Some(version);
+                module.used_by = Some(name.to_string());
+                module
+            }));
+        }
+    }
+
+    let sources_dir = project_dir.join("sources");
+    let mut locks = BTreeSet::new();
+    for group in package.info.source_groups().into_iter().flatten() {
+        // A source group is usually a member of a workspace in the sources directory.
+        let lock = [sources_dir.join(group), sources_dir.clone()]
+            .into_iter()
+            .map(|dir| dir.join("Cargo.lock"))
+            .find(|lock| lock.is_file());
+        locks.extend(lock);
+    }
+    for lock in locks {
+        let contents = std::fs::read_to_string(&lock)
+            .context(format!("Unable to read '{}'", lock.display()))?;
+        let crates = crates(&contents).context(format!("Unable to parse '{}'", lock.display()))?;
+        components.extend(crates.into_iter().map(|(name, version, checksum)| {
+            let mut component = Component::new(ComponentKind::Crate, &name);
+            component.purl = Some(format!("pkg:cargo/{name}@{version}"));
+            component.version = Some(version);
+            component.sha256 = checksum;
+            component.used_by = Some(package.info.package_name().to_string());
+            component
+        }));
+    }
+    Ok(components)
+}
+
+/// Reads the inventory of the RPMs installed in the images of a variant built for `arch`, if it has
+/// been built.
+async fn read_inventory(
+    project_dir: &Path,
+    variant: &str,
+    arch: &str,
+) -> Result<Option<Inventory>> {
+    let inventory_path = project_dir
+        .join("build/images")
+        .join(format!("{arch}-{variant}"))
+        .join("latest")
+        .join(INVENTORY);
+    if !inventory_path.exists() {
+        debug!(
+            "'{}' does not exist, so neither the variant's installed RPMs nor the external kits \
+            they come from are known",
+            inventory_path.display()
+        );
+        return Ok(None);
+    }
+    let inventory = serde_json::from_str(&fs::read_to_string(&inventory_path).await?)
+        .context(format!("Unable to parse '{}'", inventory_path.display()))?;
+    Ok(Some(inventory))
+}
+
+/// The names of the external kits, as fetched into `external_kits_dir`, which provide any of the
+/// RPMs in `inventory`.
+fn used_kits(
+    external_kits_dir: &Path,
+    kits: &[LockedImage],
+    inventory: &Inventory,
+) -> Result<HashSet<String>> {
+    let installed: HashSet<String> = inventory
+        .content
+        .iter()
+        .map(|entry| {
+            format!(
+                "{}-{}-{}.{}.rpm",
+                entry.name, entry.version, entry.release, entry.architecture
+            )
+        })
+        .collect();
+    let mut used = HashSet::new();
+    for kit in kits {
+        let kit_dir = external_kits_dir.join(&kit.vendor).join(&kit.name);
+        if rpm_file_names(&kit_dir)?
+            .iter()
+            .any(|name| installed.contains(name))
+        {
+            used.insert(kit.name.clone());
+        }
+    }
+    Ok(used)
+}
+
+/// The file names of the RPMs anywhere under `dir`, which need not exist.
+fn rpm_file_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if !dir.is_dir() {
+        return Ok(names);
+    }
+    let entries = std::fs::read_dir(dir).context(format!("Unable to read '{}'", dir.display()))?;
+    for entry in entries {
+        let entry = entry.context(format!("Unable to read '{}'", dir.display()))?;
+        let path = entry.path();
+        if path.is_dir() {
+            names.extend(rpm_file_names(&path)?);
+        } else if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if name.ends_with(".rpm") {
+                names.push(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Adds the RPMs installed in a variant's image which aren't built by the project, such as those
+/// from external kits.
+fn add_inventory(components: &mut Vec<Component>, inventory: Inventory) {
+    let local: HashSet<String> = components
+        .iter()
+        .filter(|component| component.kind == ComponentKind::Package)
+        .map(|component| component.name.clone())
+        .collect();
+    for entry in inventory.content {
+        let unprefixed = entry.name.trim_start_matches("bottlerocket-");
+        if local.contains(&entry.name) || local.contains(unprefixed) {
+            continue;
+        }
+        let version = format!("{}-{}", entry.version, entry.release);
+        let mut component = Component::new(ComponentKind::Package, &entry.name);
+        component.purl = Some(format!(
+            "pkg:rpm/bottlerocket/{}@{}?arch={}",
+            entry.name, version, entry.architecture
+        ));
+        component.version = Some(version);
+        component.url = Some(entry.url).filter(|url| !url.is_empty() && url != "(none)");
+        components.push(component);
+    }
+}
+
+/// The Go modules listed in the `vendor/modules.txt` of a bundle of vendored modules, as pairs of
+/// module path and version.
+fn go_modules(bundle: &Path) -> Result<Vec<(String, String)>> {
+    let file =
+        std::fs::File::open(bundle).context(format!("Unable to open '{}'", bundle.display()))?;
+    let mut archive = TarArchive::new(GzDecoder::new(file));
+    let entries = archive
+        .entries()
+        .context(format!("Unable to read '{}'", bundle.display()))?;
+    for entry in entries {
+        let mut entry = entry.context(format!("Unable to read '{}'", bundle.display()))?;
+        if entry.path()?.ends_with("vendor/modules.txt") {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).context(format!(
+                "Unable to read modules.txt in '{}'",
+                bundle.display()
+            ))?;
+            return Ok(parse_modules_txt(&contents));
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Parses the module lines of `vendor/modules.txt`, such as `# golang.org/x/net v0.17.0`, using
+/// the replacement of modules which are replaced by other modules.
+fn parse_modules_txt(contents: &str) -> Vec<(String, String)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let words: Vec<&str> = line.strip_prefix("# ")?.split_whitespace().collect();
+            let (path, version) = match words.as_slice() {
+                [path, version] => (path, version),
+                [_, _, "=>", path, version] => (path, version),
+                [_, "=>", path, version] => (path, version),
+                // Replaced by a local directory
+                [path, version, "=>", _] => (path, version),
+                _ => return None,
+            };
+            Some((path.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+/// The crates from registries and git repositories in a `Cargo.lock`, with their checksums.
+fn crates(lock: &str) -> Result<Vec<(String, String, Option<String>)>> {
+    #[derive(Deserialize)]
+    struct CargoLock {
+        #[serde(default)]
+        package: Vec<LockedCrate>,
+    }
+    #[derive(Deserialize)]
+    struct LockedCrate {
+        name: String,
+        version: String,
+        source: Option<String>,
+        checksum: Option<String>,
+    }
+    let lock: CargoLock = toml::from_str(lock)?;
+    Ok(lock
+        .package
+        .into_iter()
+        .filter(|package| package.source.is_some())
+        .map(|package| (package.name, package.version, package.checksum))
+        .collect())
+}
+
+/// The repository of an image reference, without its tag.
+/// Describes an external kit by the digest of its manifest list, which the lock records in base64.
+fn external_kit_component(kit: &LockedImage) -> Result<Component> {
+    let sha256 = kit.manifest_sha256()?;
+    let mut component = Component::new(ComponentKind::Kit, &kit.name);
+    component.version = Some(kit.version.to_string());
+    component.url = Some(kit.source.clone());
+    component.purl = Some(format!(
+        "pkg:oci/{}@sha256%3A{}?repository_url={}",
+        kit.name,
+        sha256,
+        repository(&kit.source)
+    ));
+    component.sha256 = Some(sha256);
+    Ok(component)
+}
+
+fn repository(source: &str) -> &str {
+    match source.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => source,
+    }
+}
+
+fn project_name(project_dir: &Path) -> String {
+    project_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "project".to_string())
+}
+
+/// Whether `license` is written as an SPDX license expression: identifiers joined by `AND`, `OR`
+/// and `WITH`, with parentheses. Spec files often give names such as `GPLv2 or later` instead,
+/// which SPDX documents must not declare.
+fn is_spdx_expression(license: &str) -> bool {
+    let spaced = license.replace('(', " ( ").replace(')', " ) ");
+    let mut depth = 0usize;
+    // Whether the next token must be an identifier or `(`, rather than an operator or `)`.
+    let mut expect_operand = true;
+    let mut after_with = false;
+    for token in spaced.split_whitespace() {
+        match token {
+            "(" if expect_operand && !after_with => depth += 1,
+            ")" if !expect_operand && depth > 0 => depth -= 1,
+            "AND" | "OR" if !expect_operand => expect_operand = true,
+            "WITH" if !expect_operand && !after_with => {
+                expect_operand = true;
+                after_with = true;
+                continue;
+            }
+            _ if expect_operand && is_spdx_id(token) => expect_operand = false,
+            _ => return false,
+        }
+        after_with = false;
+    }
+    !expect_operand && depth == 0
+}
+
+fn is_spdx_id(token: &str) -> bool {
+    let id = token.strip_suffix('+').unwrap_or(token);
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lock::manifest_digest;
+    use crate::project;
+
+    #[test]
+    fn parses_vendored_go_modules() {
+        let modules = parse_modules_txt(
+            "# github.com/a/b v1.2.3\n## explicit; go 1.21\ngithub.com/a/b/c\n\
+            # golang.org/x/net v0.1.0 => golang.org/x/net v0.17.0\n\
+            # example.com/local v1.0.0 => ./local\n",
+        );
+        assert_eq!(
+            modules,
+            vec![
+                ("github.com/a/b".to_string(), "v1.2.3".to_string()),
+                ("golang.org/x/net".to_string(), "v0.17.0".to_string()),
+                ("example.com/local".to_string(), "v1.0.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_locked_crates() {
+        let crates = crates(
+            r#"
+version = 3
+
+[[package]]
+name = "hello-agent"
+version = "0.1.0"
+
+[[package]]
+name = "libc"
+version = "0.2.150"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "89d92a4743f9a61002fae18374ed11e7973f530cb3a3255fb354818118b2203c"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            crates,
+            vec![(
+                "libc".to_string(),
+                "0.2.150".to_string(),
+                Some(
+                    "89d92a4743f9a61002fae18374ed11e7973f530cb3a3255fb354818118b2203c".to_string()
+                )
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn describes_project() {
+        let temp_dir = crate::test::copy_project_to_temp_dir("project1");
+        let project =
+            project::load_or_find_project(Some(temp_dir.path().join("Twoliter.toml")), false)
+                .await
+                .unwrap();
+        let document = Document::collect(&project, Scope::Project).await.unwrap();
+        let hello_go = document
+            .components
+            .iter()
+            .find(|component| component.name == "hello-go")
+            .unwrap();
+        assert_eq!(hello_go.kind, ComponentKind::Package);
+        assert_eq!(hello_go.version.as_deref(), Some("0.0"));
+        assert_eq!(hello_go.license.as_deref(), Some("Apache-2.0 OR MIT"));
+        assert!(document
+            .components
+            .iter()
+            .any(|component| component.name == "hello-agent"));
+
+        let spdx: Value =
+            serde_json::from_str(&document.render(SbomFormat::Spdx, Utc::now()).unwrap()).unwrap();
+        assert_eq!(spdx["spdxVersion"], "SPDX-2.3");
+        assert_eq!(
+            spdx["packages"].as_array().unwrap().len(),
+            document.components.len() + 1
+        );
+        let cyclonedx: Value =
+            serde_json::from_str(&document.render(SbomFormat::Cyclonedx, Utc::now()).unwrap())
+                .unwrap();
+        assert_eq!(cyclonedx["bomFormat"], "CycloneDX");
+        assert_eq!(
+            cyclonedx["components"].as_array().unwrap().len(),
+            document.components.len()
+        );
+
+        assert!(Document::collect(
+            &project,
+            Scope::Kit {
+                name: "missing-kit".to_string()
+            }
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn checks_spdx_expressions() {
+        for license in [
+            "MIT",
+            "Apache-2.0 OR MIT",
+            "GPL-2.0-or-later AND (BSD-3-Clause OR ISC)",
+            "Apache-2.0 WITH LLVM-exception",
+            "LGPL-2.1+",
+            "LicenseRef-Bottlerocket",
+        ] {
+            assert!(is_spdx_expression(license), "{license}");
+        }
+        for license in [
+            "",
+            "GPLv2 or later",
+            "MIT AND",
+            "(MIT",
+            "MIT)",
+            "MIT WITH (Classpath-exception-2.0)",
+            "Public Domain",
+        ] {
+            assert!(!is_spdx_expression(license), "{license}");
+        }
+    }
+
+    #[test]
+    fn renders_valid_spdx() {
+        let mut package = Component::new(ComponentKind::Package, "glibc");
+        package.version = Some("2.40".to_string());
+        package.license = Some("LGPLv2+ and GPLv2+".to_string());
+        let mut archive = Component::new(ComponentKind::SourceArchive, "glibc-2.40.tar.xz");
+        archive.used_by = Some("glibc".to_string());
+        archive.sha512 = Some("ab".repeat(64));
+        let mut module = Component::new(ComponentKind::GoModule, "golang.org/x/sys");
+        module.license = Some("BSD-3-Clause".to_string());
+        let document = Document {
+            scope: Scope::Project,
+            name: "my project".to_string(),
+            version: "1.0.0".to_string(),
+            components: vec![package, archive, module.clone(), module],
+        };
+        let spdx: Value =
+            serde_json::from_str(&document.render(SbomFormat::Spdx, Utc::now()).unwrap()).unwrap();
+
+        let packages = spdx["packages"].as_array().unwrap();
+        let mut ids = HashSet::from(["SPDXRef-DOCUMENT".to_string()]);
+        for package in packages {
+            let id = package["SPDXID"].as_str().unwrap();
+            let suffix = id.strip_prefix("SPDXRef-").unwrap();
+            assert!(!suffix.is_empty() && suffix == sanitize(suffix), "{id}");
+            assert!(ids.insert(id.to_string()), "{id} is not unique");
+            assert!(package["name"].is_string());
+            assert!(package["downloadLocation"].is_string());
+            if let Some(license) = package["licenseDeclared"].as_str() {
+                assert!(license == "NOASSERTION" || is_spdx_expression(license));
+            }
+        }
+        assert_eq!(packages[1]["licenseDeclared"], "NOASSERTION");
+        assert_eq!(packages[3]["licenseDeclared"], "BSD-3-Clause");
+        for relationship in spdx["relationships"].as_array().unwrap() {
+            for key in ["spdxElementId", "relatedSpdxElement"] {
+                assert!(ids.contains(relationship[key].as_str().unwrap()));
+            }
+        }
+        assert!(spdx["relationships"].as_array().unwrap().contains(&json!({
+            "spdxElementId": packages[1]["SPDXID"],
+            "relationshipType": "GENERATED_FROM",
+            "relatedSpdxElement": packages[2]["SPDXID"],
+        })));
+        let namespace = spdx["documentNamespace"].as_str().unwrap();
+        assert!(namespace
+            .starts_with("https://github.com/bottlerocket-os/twoliter/spdx/my-project-1.0.0-"));
+    }
+
+    #[test]
+    fn finds_the_kits_a_variant_uses() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let kits_dir = temp_dir.path();
+        let kit = |name: &str| LockedImage {
+            name: name.to_string(),
+            version: semver::Version::new(1, 0, 0),
+            vendor: "bottlerocket".to_string(),
+            source: format!("public.ecr.aws/bottlerocket/{name}:v1.0.0"),
+            digest: manifest_digest(name.as_bytes()),
+            arch_digests: Default::default(),
+            features: Vec::new(),
+            manifest: Vec::new(),
+        };
+        let packages = kits_dir.join("bottlerocket/core-kit/x86_64/Packages");
+        std::fs::create_dir_all(&packages).unwrap();
+        std::fs::write(
+            packages.join("bottlerocket-kernel-6.1-6.1.100-1.x86_64.rpm"),
+            "",
+        )
+        .unwrap();
+        let inventory: Inventory = serde_json::from_value(json!({"Content": [{
+            "Name": "bottlerocket-kernel-6.1",
+            "Version": "6.1.100",
+            "Release": "1",
+            "Architecture": "x86_64",
+            "Url": "",
+        }]}))
+        .unwrap();
+        let kits = [kit("core-kit"), kit("extra-kit")];
+        assert_eq!(
+            used_kits(kits_dir, &kits, &inventory).unwrap(),
+            HashSet::from(["core-kit".to_string()])
+        );
+    }
+
+    #[test]
+    fn describes_external_kits_by_manifest_digest() {
+        let kit = LockedImage {
+            name: "core-kit".to_string(),
+            version: semver::Version::new(1, 0, 0),
+            vendor: "bottlerocket".to_string(),
+            source: "public.ecr.aws/bottlerocket/core-kit:v1.0.0".to_string(),
+            digest: manifest_digest(b"{}"),
+            arch_digests: Default::default(),
+            features: Vec::new(),
+            manifest: Vec::new(),
+        };
+        let sha256 = "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a";
+        let component = external_kit_component(&kit).unwrap();
+        assert_eq!(component.sha256.as_deref(), Some(sha256));
+        assert_eq!(
+            component.purl.unwrap(),
+            format!(
+                "pkg:oci/core-kit@sha256%3A{sha256}\
+                ?repository_url=public.ecr.aws/bottlerocket/core-kit"
+            )
+        );
+    }
+}
@@ -0,0 +1,669 @@
+//! Generates the files for a new out-of-tree project, so that a project can be started without
+//! copying the layout of an existing Bottlerocket repository.
+//!
+//! Templates use `@NAME@` style placeholders rather than `format!` so that the braces of RPM macros
+//! and TOML tables can be written as they are.
+use crate::common::fs::{create_dir_all, read_to_string, write};
+use crate::project::{is_valid_id_char, Project, VariantConfig};
+use anyhow::{ensure, Context, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Value};
+use tracing::info;
+
+/// The vendor of the Bottlerocket SDK, written to new projects.
+const DEFAULT_VENDOR: &str = "bottlerocket";
+const DEFAULT_REGISTRY: &str = "public.ecr.aws/bottlerocket";
+const DEFAULT_SDK_NAME: &str = "bottlerocket-sdk";
+/// The version of the SDK written to new projects, unless another is given.
+pub(crate) const DEFAULT_SDK_VERSION: &str = "0.41.0";
+
+const PACKAGES_DIR: &str = "packages";
+const KITS_DIR: &str = "kits";
+const VARIANTS_DIR: &str = "variants";
+/// The directory, within the project, in which the crates of variants declared in `Twoliter.toml`
+/// are generated.
+const GENERATED_VARIANTS_DIR: &str = "build/variants";
+
+/// The first line of a variant manifest generated from `Twoliter.toml`.
+const GENERATED_MARKER: &str = "# Generated by Twoliter from Twoliter.toml.";
+
+const TWOLITER_TOML: &str = r#"schema-version = 1
+release-version = "0.1.0"
+
+[vendor.@VENDOR@]
+registry = "@REGISTRY@"
+
+[sdk]
+name = "@SDK_NAME@"
+vendor = "@VENDOR@"
+version = "@SDK_VERSION@"
+"#;
+
+const WORKSPACE_CARGO_TOML: &str = r#"[workspace]
+resolver = "2"
+members = [
+    "kits/@KIT@",
+    "packages/@PACKAGE@",
+]
+
+[profile.dev]
+debug = false
+opt-level = 'z'
+
+[profile.dev.build-override]
+opt-level = 'z'
+"#;
+
+const GITIGNORE: &str = r#"/build/
+/.twoliter/
+**/target/
+/.cargo/
+/.gomodcache/
+/keys/
+/roles/
+/sbkeys/
+Test.toml
+testsys.kubeconfig
+Infra.toml
+"#;
+
+const DOCKERIGNORE: &str = r#"/.git
+/.gomodcache
+/build/*
+!/build/rpms/
+/build/rpms/*
+!/build/rpms/*.rpm
+/build/rpms/*-debuginfo-*.rpm
+/build/rpms/*-debugsource-*.rpm
+**/target/*
+/sbkeys
+/.twoliter
+"#;
+
+const SOURCES_README: &str = r#"Sources for first-party packages, such as Go modules, go here.
+"#;
+
+const BUILD_RS: &str = r#"use std::process::{exit, Command};
+
+fn main() -> Result<(), std::io::Error> {
+    let ret = Command::new("buildsys").arg("@BUILDSYS_COMMAND@").status()?;
+    if !ret.success() {
+        exit(1);
+    }
+    Ok(())
+}
+"#;
+
+const EMPTY_LIB_RS: &str = r#"/*!
+
+This is an intentionally empty file that all of the @KIND@ `Cargo.toml` files can point to as their
+`lib.rs`. The build system uses `build.rs` to invoke `buildsys` but Cargo needs something to compile
+so we give it an empty `lib.rs` file.
+
+!*/
+"#;
+
+const PACKAGE_CARGO_TOML: &str = r#"[package]
+name = "@PACKAGE@"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+
+[package.metadata.build-package]
+source-groups = []
+
+[lib]
+path = "../packages.rs"
+
+# RPM BuildRequires
+[build-dependencies]
+# None
+
+# RPM Requires
+[dependencies]
+# None
+"#;
+
+const PACKAGE_SPEC: &str = r#"%global _cross_first_party 1
+%undefine _debugsource_packages
+
+Name: %{_cross_os}@PACKAGE@
+Version: 0.0
+Release: 0%{?dist}
+Summary: @PACKAGE@
+License: Apache-2.0 OR MIT
+
+%description
+%{summary}.
+
+%prep
+%setup -T -c
+
+%build
+
+%install
+
+%files
+"#;
+
+const KIT_CARGO_TOML: &str = r#"[package]
+name = "@KIT@"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+
+[package.metadata.build-kit]
+vendor = "@VENDOR@"
+
+[lib]
+path = "../kit.rs"
+
+[build-dependencies]
+@DEPENDENCIES@"#;
+
+const VARIANT_CARGO_TOML: &str = r#"@MARKER@ Do not edit, changes will be lost.
+[package]
+name = "@VARIANT@"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+
+[package.metadata.build-variant]
+included-packages = @PACKAGES@
+supported-arches = @ARCHES@
+
+[package.metadata.build-variant.image-features]
+@IMAGE_FEATURES@
+[lib]
+path = "../variants.rs"
+
+# The variant is a workspace of its own, rather than a member of the project's workspace.
+[workspace]
+
+[build-dependencies]
+@DEPENDENCIES@"#;
+
+/// The contents of a file to be created, relative to the project directory.
+type File = (PathBuf, String);
+
+/// Replaces each `@KEY@` placeholder in `template` with its value.
+fn render(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |rendered, (key, value)| {
+            rendered.replace(&format!("@{key}@"), value)
+        })
+}
+
+/// Generates a working project skeleton in `dir`, with a kit named `kit` that contains a single
+/// package named `package`, built with version `sdk_version` of the SDK. Fails without writing
+/// anything if any of the files already exist.
+pub(crate) async fn init(dir: &Path, kit: &str, package: &str, sdk_version: &str) -> Result<()> {
+    check_name("kit", kit)?;
+    check_name("package", package)?;
+    semver::Version::parse(sdk_version)
+        .context(format!("'{sdk_version}' is not a valid SDK version"))?;
+    let values = [
+        ("VENDOR", DEFAULT_VENDOR),
+        ("REGISTRY", DEFAULT_REGISTRY),
+        ("SDK_NAME", DEFAULT_SDK_NAME),
+        ("SDK_VERSION", sdk_version),
+        ("KIT", kit),
+        ("PACKAGE", package),
+    ];
+    let mut files = vec![
+        ("Twoliter.toml".into(), render(TWOLITER_TOML, &values)),
+        ("Cargo.toml".into(), render(WORKSPACE_CARGO_TOML, &values)),
+        (".gitignore".into(), GITIGNORE.to_string()),
+        (".dockerignore".into(), DOCKERIGNORE.to_string()),
+        ("sources/README.md".into(), SOURCES_README.to_string()),
+    ];
+    files.extend(shared_files(PACKAGES_DIR));
+    files.extend(package_files(package));
+    files.extend(shared_files(KITS_DIR));
+    files.extend(kit_files(kit, DEFAULT_VENDOR, &[package]));
+    write_files(dir, files).await?;
+    info!("Created a new project in '{}'", dir.display());
+    Ok(())
+}
+
+/// Adds a package named `name` with an empty spec file to the project in `project_dir`, and
+/// registers it as a member of the project's Cargo workspace.
+pub(crate) async fn new_package(project_dir: &Path, name: &str) -> Result<()> {
+    check_name("package", name)?;
+    add_crate(
+        project_dir,
+        Path::new(PACKAGES_DIR).join(name),
+        package_files(name),
+        shared_files(PACKAGES_DIR),
+    )
+    .await?;
+    info!("Created package '{name}'");
+    Ok(())
+}
+
+/// Adds a kit named `name`, published by `vendor` and containing `packages`, to the project in
+/// `project_dir`, and registers it as a member of the project's Cargo workspace.
+pub(crate) async fn new_kit(
+    project_dir: &Path,
+    name: &str,
+    vendor: &str,
+    packages: &[&str],
+) -> Result<()> {
+    check_name("kit", name)?;
+    for package in packages {
+        ensure!(
+            project_dir
+                .join(PACKAGES_DIR)
+                .join(package)
+                .join("Cargo.toml")
+                .is_file(),
+            "package '{package}' does not exist in '{}'",
+            project_dir.join(PACKAGES_DIR).display()
+        );
+    }
+    add_crate(
+        project_dir,
+        Path::new(KITS_DIR).join(name),
+        kit_files(name, vendor, packages),
+        shared_files(KITS_DIR),
+    )
+    .await?;
+    info!("Created kit '{name}'");
+    Ok(())
+}
+
+/// The directory of the crate Twoliter generates for `variant`, if its contents are declared in
+/// `Twoliter.toml`. Other variants have a crate of their own in the project's `variants` directory.
+pub(crate) fn generated_variant_dir(
+    project_dir: &Path,
+    variant: &VariantConfig,
+) -> Option<PathBuf> {
+    variant
+        .defines_contents()
+        .then(|| project_dir.join(GENERATED_VARIANTS_DIR).join(&variant.name))
+}
+
+/// The variable which points the build at the crate of `variant`, when Twoliter generates it.
+pub(crate) fn variant_crate_env(
+    project: &Project,
+    variant: &str,
+) -> Option<(&'static str, String)> {
+    let dir = generated_variant_dir(&project.project_dir(), project.variant(variant)?)?;
+    Some(("BUILDSYS_VARIANT_CRATE_DIR", dir.display().to_string()))
+}
+
+/// Generates the crate of a variant whose contents are declared in `Twoliter.toml`, so that
+/// buildsys can build it. The crate is generated under `build/variants` as a workspace of its own,
+/// so that building a variant never edits the project's own manifests. Files are only written when
+/// they change, so that Cargo doesn't rebuild the variant needlessly.
+pub(crate) async fn sync_variant(project_dir: &Path, variant: &VariantConfig) -> Result<()> {
+    let Some(crate_dir) = generated_variant_dir(project_dir, variant) else {
+        return Ok(());
+    };
+    check_name("variant", &variant.name)?;
+    for kit in variant.kits.iter() {
+        ensure!(
+            project_dir
+                .join(KITS_DIR)
+                .join(kit)
+                .join("Cargo.toml")
+                .is_file(),
+            "kit '{kit}' of variant '{}' does not exist in '{}'",
+            variant.name,
+            project_dir.join(KITS_DIR).display()
+        );
+    }
+    let own_manifest = project_dir
+        .join(VARIANTS_DIR)
+        .join(&variant.name)
+        .join("Cargo.toml");
+    ensure!(
+        !own_manifest.exists(),
+        "variant '{}' declares its kits and packages in Twoliter.toml but also has a manifest at \
+        '{}'; remove one of them",
+        variant.name,
+        own_manifest.display()
+    );
+
+    let generated_dir = project_dir.join(GENERATED_VARIANTS_DIR);
+    let shared = shared_files(VARIANTS_DIR)
+        .into_iter()
+        .filter_map(|(path, contents)| Some((generated_dir.join(path.file_name()?), contents)));
+    let manifest_path = crate_dir.join("Cargo.toml");
+    for (path, contents) in
+        std::iter::once((manifest_path.clone(), variant_manifest(variant))).chain(shared)
+    {
+        if path.is_file() && read_to_string(&path).await? == contents {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+        write(&path, contents).await?;
+    }
+    info!("Generated '{}' from Twoliter.toml", manifest_path.display());
+    Ok(())
+}
+
+/// Renders the Cargo manifest of `variant` from its declaration in `Twoliter.toml`.
+fn variant_manifest(variant: &VariantConfig) -> String {
+    let string_array = |items: &[String]| {
+        toml::Value::Array(items.iter().cloned().map(toml::Value::String).collect()).to_string()
+    };
+    let image_features =
+        variant
+            .image_features
+            .iter()
+            .fold(String::new(), |mut out, (feature, enabled)| {
+                let _ = writeln!(out, "{feature} = {enabled}");
+                out
+            });
+    let dependencies = variant.kits.iter().fold(String::new(), |mut out, kit| {
+        let _ = writeln!(out, "{kit} = {{ path = \"../../../{KITS_DIR}/{kit}\" }}");
+        out
+    });
+    let packages = string_array(&variant.packages);
+    let arches = string_array(&variant.arch);
+    let values = [
+        ("MARKER", GENERATED_MARKER),
+        ("VARIANT", variant.name.as_str()),
+        ("PACKAGES", packages.as_str()),
+        ("ARCHES", arches.as_str()),
+        ("IMAGE_FEATURES", image_features.as_str()),
+        ("DEPENDENCIES", dependencies.as_str()),
+    ];
+    render(VARIANT_CARGO_TOML, &values)
+}
+
+/// Writes the files of a new crate at `member`, along with any of the shared files that are
+/// missing, and adds the crate to the workspace members in the project's `Cargo.toml`.
+async fn add_crate(
+    project_dir: &Path,
+    member: PathBuf,
+    files: Vec<File>,
+    shared: Vec<File>,
+) -> Result<()> {
+    let cargo_toml = project_dir.join("Cargo.toml");
+    let member = member.display().to_string();
+    let workspace = add_workspace_member(&read_to_string(&cargo_toml).await?, &member).context(
+        format!("unable to add '{member}' to '{}'", cargo_toml.display()),
+    )?;
+
+    let missing_shared = shared
+        .into_iter()
+        .filter(|(path, _)| !project_dir.join(path).exists());
+    write_files(
+        project_dir,
+        files.into_iter().chain(missing_shared).collect(),
+    )
+    .await?;
+    write(&cargo_toml, workspace).await
+}
+
+/// Returns `cargo_toml` with `member` added to its workspace members. The members are written
+/// sorted, one per line, and the rest of the file is kept as it is.
+fn add_workspace_member(cargo_toml: &str, member: &str) -> Result<String> {
+    let mut manifest: DocumentMut = cargo_toml.parse().context("unable to parse Cargo.toml")?;
+    let members = manifest
+        .get_mut("workspace")
+        .and_then(|workspace| workspace.get_mut("members"))
+        .and_then(|members| members.as_array_mut())
+        .context("Cargo.toml has no workspace members list")?;
+    let mut sorted = members
+        .iter()
+        .map(|existing| {
+            existing
+                .as_str()
+                .map(ToString::to_string)
+                .context("workspace members must be strings")
+        })
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(
+        !sorted.iter().any(|existing| existing == member),
+        "'{member}' is already a workspace member"
+    );
+    sorted.push(member.to_string());
+    sorted.sort();
+
+    members.clear();
+    for member in sorted {
+        let mut value = Value::from(member);
+        value.decor_mut().set_prefix("\n    ");
+        members.push_formatted(value);
+    }
+    members.set_trailing_comma(true);
+    members.set_trailing("\n");
+    Ok(manifest.to_string())
+}
+
+/// Errors unless `name` can be used as the name of a kit or package.
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty() && name.chars().all(is_valid_id_char),
+        "invalid {kind} name '{name}', expected letters, digits, '-' and '_'"
+    );
+    Ok(())
+}
+
+/// The `build.rs` and empty `lib.rs` shared by every crate in `dir`, which is the packages, kits
+/// or variants directory.
+fn shared_files(dir: &str) -> Vec<File> {
+    let (lib, command, kind) = match dir {
+        KITS_DIR => ("kit.rs", "build-kit", "kit"),
+        VARIANTS_DIR => ("variants.rs", "build-variant", "variant"),
+        _ => ("packages.rs", "build-package", "package"),
+    };
+    let values = [("BUILDSYS_COMMAND", command), ("KIND", kind)];
+    let dir = Path::new(dir);
+    vec![
+        (dir.join("build.rs"), render(BUILD_RS, &values)),
+        (dir.join(lib), render(EMPTY_LIB_RS, &values)),
+    ]
+}
+
+/// The files for a package named `name`.
+fn package_files(name: &str) -> Vec<File> {
+    let dir = Path::new(PACKAGES_DIR).join(name);
+    let values = [("PACKAGE", name)];
+    vec![
+        (dir.join("Cargo.toml"), render(PACKAGE_CARGO_TOML, &values)),
+        (
+            dir.join(format!("{name}.spec")),
+            render(PACKAGE_SPEC, &values),
+        ),
+    ]
+}
+
+/// The files for a kit named `name` containing `packages`.
+fn kit_files(name: &str, vendor: &str, packages: &[&str]) -> Vec<File> {
+    let dir = Path::new(KITS_DIR).join(name);
+    let dependencies = packages.iter().fold(String::new(), |mut out, package| {
+        let _ = writeln!(out, "{package} = {{ path = \"../../packages/{package}\" }}");
+        out
+    });
+    let values = [
+        ("KIT", name),
+        ("VENDOR", vendor),
+        ("DEPENDENCIES", dependencies.as_str()),
+    ];
+    vec![(dir.join("Cargo.toml"), render(KIT_CARGO_TOML, &values))]
+}
+
+/// Writes `files` into `dir`, failing before anything is written if any of them already exist.
+async fn write_files(dir: &Path, files: Vec<File>) -> Result<()> {
+    for (path, _) in files.iter() {
+        let path = dir.join(path);
+        ensure!(
+            !path.exists(),
+            "refusing to overwrite existing file '{}'",
+            path.display()
+        );
+    }
+    for (path, contents) in files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+        write(&path, contents).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn init_creates_loadable_project() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        init(dir, "my-kit", "hello", DEFAULT_SDK_VERSION)
+            .await
+            .unwrap();
+
+        Project::load(dir.join("Twoliter.toml")).await.unwrap();
+
+        let workspace: toml::Value =
+            toml::from_str(&std::fs::read_to_string(dir.join("Cargo.toml")).unwrap()).unwrap();
+        for member in workspace["workspace"]["members"].as_array().unwrap() {
+            let member_dir = dir.join(member.as_str().unwrap());
+            let manifest: toml::Value =
+                toml::from_str(&std::fs::read_to_string(member_dir.join("Cargo.toml")).unwrap())
+                    .unwrap();
+            assert!(manifest["package"]["metadata"].is_table());
+        }
+        assert!(dir.join("packages/hello/hello.spec").is_file());
+
+        // A second init must not clobber the project.
+        assert!(init(dir, "my-kit", "hello", DEFAULT_SDK_VERSION)
+            .await
+            .is_err());
+
+        let other_dir = TempDir::new().unwrap();
+        assert!(init(other_dir.path(), "my-kit", "hello", "latest")
+            .await
+            .is_err());
+        init(other_dir.path(), "my-kit", "hello", "0.50.0")
+            .await
+            .unwrap();
+        let twoliter_toml =
+            std::fs::read_to_string(other_dir.path().join("Twoliter.toml")).unwrap();
+        assert!(twoliter_toml.contains("version = \"0.50.0\""));
+    }
+
+    #[tokio::test]
+    async fn new_kit_registers_workspace_member() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        init(dir, "my-kit", "hello", DEFAULT_SDK_VERSION)
+            .await
+            .unwrap();
+
+        new_package(dir, "goodbye").await.unwrap();
+        new_kit(dir, "another-kit", "bottlerocket", &["goodbye"])
+            .await
+            .unwrap();
+        assert!(dir.join("packages/goodbye/goodbye.spec").is_file());
+        assert!(new_package(dir, "goodbye").await.is_err());
+        assert!(new_kit(dir, "bad-kit", "bottlerocket", &["missing"])
+            .await
+            .is_err());
+
+        let workspace = std::fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(workspace.starts_with(
+            "[workspace]\nresolver = \"2\"\nmembers = [\n    \"kits/another-kit\",\n    \
+            \"kits/my-kit\",\n    \"packages/goodbye\",\n    \"packages/hello\",\n]\n"
+        ));
+        let kit = std::fs::read_to_string(dir.join("kits/another-kit/Cargo.toml")).unwrap();
+        assert!(kit.contains("goodbye = { path = \"../../packages/goodbye\" }"));
+    }
+
+    #[tokio::test]
+    async fn sync_variant_generates_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        init(dir, "my-kit", "hello", DEFAULT_SDK_VERSION)
+            .await
+            .unwrap();
+        let variant = VariantConfig {
+            name: "my-variant".to_string(),
+            arch: vec!["x86_64".to_string(), "aarch64".to_string()],
+            lookaside_cache: None,
+            upstream_source_fallback: None,
+            infra_toml: None,
+            kits: vec!["my-kit".to_string()],
+            packages: vec!["hello".to_string()],
+            image_features: [("uefi-secure-boot".to_string(), true)].into(),
+        };
+        let workspace = std::fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        sync_variant(dir, &variant).await.unwrap();
+        let crate_dir = generated_variant_dir(dir, &variant).unwrap();
+        assert_eq!(crate_dir, dir.join("build/variants/my-variant"));
+        let manifest_path = crate_dir.join("Cargo.toml");
+        let modified = std::fs::metadata(&manifest_path)
+            .unwrap()
+            .modified()
+            .unwrap();
+        // Syncing again must leave the manifest as it is.
+        sync_variant(dir, &variant).await.unwrap();
+        assert_eq!(
+            std::fs::metadata(&manifest_path)
+                .unwrap()
+                .modified()
+                .unwrap(),
+            modified
+        );
+
+        let manifest: toml::Value =
+            toml::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        let metadata = &manifest["package"]["metadata"]["build-variant"];
+        assert_eq!(
+            metadata["included-packages"],
+            toml::Value::from(vec!["hello"])
+        );
+        assert_eq!(
+            metadata["supported-arches"],
+            toml::Value::from(vec!["x86_64", "aarch64"])
+        );
+        assert_eq!(
+            metadata["image-features"]["uefi-secure-boot"],
+            toml::Value::Boolean(true)
+        );
+        assert_eq!(
+            manifest["build-dependencies"]["my-kit"]["path"].as_str(),
+            Some("../../../kits/my-kit")
+        );
+        assert!(manifest["workspace"].is_table());
+        assert!(dir.join("build/variants/build.rs").is_file());
+        assert!(dir.join("build/variants/variants.rs").is_file());
+        // The project's own manifests are left alone.
+        assert_eq!(
+            std::fs::read_to_string(dir.join("Cargo.toml")).unwrap(),
+            workspace
+        );
+        assert!(!dir.join("variants").exists());
+
+        // A variant can't have a crate of its own as well.
+        std::fs::create_dir_all(dir.join("variants/my-variant")).unwrap();
+        std::fs::write(dir.join("variants/my-variant/Cargo.toml"), "[package]\n").unwrap();
+        assert!(sync_variant(dir, &variant).await.is_err());
+    }
+
+    #[test]
+    fn add_workspace_member_keeps_rest_of_file() {
+        let cargo_toml =
+            "# comment\n[workspace]\nmembers = [\"b\", \"a\"] # sorted\n\n[profile.dev]\n";
+        assert_eq!(
+            add_workspace_member(cargo_toml, "c").unwrap(),
+            "# comment\n[workspace]\nmembers = [\n    \"a\",\n    \"b\",\n    \"c\",\n] # sorted\n\n\
+            [profile.dev]\n"
+        );
+        assert!(add_workspace_member(cargo_toml, "a").is_err());
+    }
+}
@@ -1,3 +1,6 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
@@ -6,10 +9,10 @@ use std::fmt;
 /// have an after-deserialization validation hook, so we have this struct to limit the version to a
 /// single acceptable value.
 #[derive(Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub(crate) struct SchemaVersion<const N: u32>;
+pub struct SchemaVersion<const N: u32>;
 
 impl<const N: u32> SchemaVersion<N> {
-    pub(crate) fn get(&self) -> u32 {
+    pub fn get(&self) -> u32 {
         N
     }
 
@@ -62,3 +65,92 @@ impl<'de, const N: u32> Deserialize<'de> for SchemaVersion<N> {
         }
     }
 }
+
+impl<const N: u32> JsonSchema for SchemaVersion<N> {
+    fn schema_name() -> String {
+        format!("SchemaVersion{N}")
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        integer_schema(&[N])
+    }
+}
+
+/// The schema version of `Twoliter.lock`. Version 2 additionally records the digest of each
+/// per-architecture image, so that kits can be fetched without querying the registry for the
+/// manifest list.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum LockSchemaVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+impl LockSchemaVersion {
+    pub fn get(&self) -> u32 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+        }
+    }
+}
+
+impl fmt::Display for LockSchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt::Display::fmt(&self.get(), f)
+    }
+}
+
+impl Serialize for LockSchemaVersion {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u32(self.get())
+    }
+}
+
+impl<'de> Deserialize<'de> for LockSchemaVersion {
+    fn deserialize<D>(deserializer: D) -> Result<LockSchemaVersion, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value: u32 = Deserialize::deserialize(deserializer)?;
+        match value {
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            _ => Err(Error::custom(format!(
+                "Incorrect lock schema-version: got '{}', expected '1' or '2'",
+                value
+            ))),
+        }
+    }
+}
+
+impl JsonSchema for LockSchemaVersion {
+    fn schema_name() -> String {
+        "LockSchemaVersion".to_string()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        integer_schema(&[Self::V1.get(), Self::V2.get()])
+    }
+}
+
+/// The JSON schema of an integer which must be one of `values`.
+fn integer_schema(values: &[u32]) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Integer.into()),
+        enum_values: Some(values.iter().map(|value| (*value).into()).collect()),
+        ..Default::default()
+    }
+    .into()
+}
@@ -1,8 +1,10 @@
 use semver::Version;
 use serde::Deserialize;
 
+use crate::local_sdk::Sdk;
 use crate::lock::{Lock, LockedImage};
 use crate::project::ValidIdentifier;
+use crate::schema_version::LockSchemaVersion;
 use crate::{cargo_make::CargoMake, project::Project, test::data_dir};
 
 #[tokio::test]
@@ -13,7 +15,7 @@ async fn test_cargo_make() {
     let vendor_id = ValidIdentifier("my-vendor".into());
     let vendor = project.vendor().get(&vendor_id).unwrap();
     let lock = Lock {
-        schema_version: project.schema_version(),
+        schema_version: LockSchemaVersion::V1,
         kit: Vec::new(),
         sdk: LockedImage {
             name: "my-bottlerocket-sdk".to_string(),
@@ -22,9 +24,17 @@ async fn test_cargo_make() {
             source: format!("{}/{}:v{}", vendor.registry, "my-bottlerocket-sdk", "1.2.3"),
             digest: "abc".to_string(),
             manifest: Vec::new(),
+            arch_digests: Default::default(),
+            features: Vec::new(),
         },
+        sdk_arch: Default::default(),
     };
-    let cargo_make = CargoMake::new(&lock.sdk.source)
+    let sdk = Sdk {
+        image: lock.sdk.source.clone(),
+        digest: lock.sdk.digest.clone(),
+        layout: None,
+    };
+    let cargo_make = CargoMake::new(&sdk)
         .unwrap()
         .makefile(data_dir().join("Makefile.toml"));
     cargo_make.exec("verify-twoliter-env").await.unwrap();
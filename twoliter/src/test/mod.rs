@@ -1,7 +1,7 @@
 /*!
 
 This directory and module are for tests, test data, and re-usable test code. This module should only
-be compiled for `cfg(test)`, which is accomplished at its declaration in `main.rs`.
+be compiled for `cfg(test)`, which is accomplished at its declaration in `lib.rs`.
 
 !*/
 
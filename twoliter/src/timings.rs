@@ -0,0 +1,236 @@
+//! Reports how long each package, kit and variant took during `twoliter build variant --timings`,
+//! from the timings buildsys records for every build it runs.
+use crate::common::fs;
+use anyhow::{Context, Result};
+use buildsys::timing::{unix_ms, Cache, Timing};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The number of slowest stages summarized in the log.
+const SLOWEST_STAGES: usize = 5;
+
+/// One package, kit or variant build.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Stage {
+    pub(crate) name: String,
+    pub(crate) kind: String,
+    pub(crate) arch: String,
+    /// How long after the start of the variant build the stage started, having waited for its
+    /// dependencies and for its sources to be fetched
+    pub(crate) offset_ms: u128,
+    /// How long the stage then waited for a slot to run in, when the number of builds is limited
+    pub(crate) queue_ms: u128,
+    /// How long the stage took
+    pub(crate) wall_ms: u128,
+    pub(crate) cache: Option<Cache>,
+    pub(crate) succeeded: bool,
+}
+
+/// The timings of a variant build.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct TimingReport {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) wall_ms: u128,
+    /// The stages, slowest first. Stages which Cargo did not need to rerun are not included.
+    pub(crate) stages: Vec<Stage>,
+}
+
+impl TimingReport {
+    /// Builds the report from the timings buildsys recorded in `timings_dir` during a variant
+    /// build which ran from `start` to `end`.
+    pub(crate) async fn load(
+        variant: &str,
+        arch: &str,
+        timings_dir: &Path,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Self> {
+        let start_ms = unix_ms(start);
+        let mut stages = Vec::new();
+        if timings_dir.is_dir() {
+            let mut entries = tokio::fs::read_dir(timings_dir)
+                .await
+                .context(format!("Unable to read '{}'", timings_dir.display()))?;
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .context(format!("Unable to read '{}'", timings_dir.display()))?
+            {
+                let path = entry.path();
+                let timing: Timing = serde_json::from_str(&fs::read_to_string(&path).await?)
+                    .context(format!("Unable to parse '{}'", path.display()))?;
+                stages.push(Stage {
+                    offset_ms: timing.start_ms.saturating_sub(start_ms),
+                    queue_ms: timing.queue_ms,
+                    wall_ms: timing.end_ms.saturating_sub(timing.start_ms),
+                    name: timing.name,
+                    kind: timing.kind,
+                    arch: timing.arch,
+                    cache: timing.cache,
+                    succeeded: timing.succeeded,
+                });
+            }
+        }
+        stages.sort_by(|a, b| b.wall_ms.cmp(&a.wall_ms).then(a.name.cmp(&b.name)));
+        Ok(Self {
+            variant: variant.to_string(),
+            arch: arch.to_string(),
+            wall_ms: unix_ms(end).saturating_sub(start_ms),
+            stages,
+        })
+    }
+
+    /// Writes the report as `<arch>-<variant>.json` and `<arch>-<variant>.html` in `report_dir`,
+    /// returning the path of the HTML report.
+    pub(crate) async fn write(&self, report_dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(report_dir).await?;
+        let name = format!("{}-{}", self.arch, self.variant);
+        let json =
+            serde_json::to_string_pretty(self).context("Unable to serialize the timing report")?;
+        fs::write(report_dir.join(format!("{name}.json")), json).await?;
+        let html_path = report_dir.join(format!("{name}.html"));
+        fs::write(&html_path, self.to_html()).await?;
+        Ok(html_path)
+    }
+
+    /// Describes the slowest stages, for the log.
+    pub(crate) fn summary(&self) -> String {
+        let mut summary = format!(
+            "Built variant '{}' for {} in {}",
+            self.variant,
+            self.arch,
+            seconds(self.wall_ms)
+        );
+        for stage in self.stages.iter().take(SLOWEST_STAGES) {
+            let _ = write!(
+                summary,
+                "\n  {} {}: {}",
+                stage.kind,
+                stage.name,
+                seconds(stage.wall_ms)
+            );
+        }
+        summary
+    }
+
+    /// Renders the report as a standalone HTML page, with a bar showing when each stage ran.
+    pub(crate) fn to_html(&self) -> String {
+        let total = self.wall_ms.max(1);
+        let mut rows = String::new();
+        for stage in &self.stages {
+            let cache = match stage.cache {
+                Some(Cache::Hit) => "hit",
+                Some(Cache::Miss) => "miss",
+                None => "",
+            };
+            let _ = writeln!(
+                rows,
+                "<tr class=\"{status}\"><td>{kind}</td><td>{name}</td><td>{queue}</td>\
+                <td>{wall}</td><td>{cache}</td><td class=\"timeline\">\
+                <div style=\"margin-left:{left:.2}%;width:{width:.2}%\"></div></td></tr>",
+                status = if stage.succeeded { "ok" } else { "failed" },
+                kind = escape(&stage.kind),
+                name = escape(&stage.name),
+                queue = seconds(stage.queue_ms),
+                wall = seconds(stage.wall_ms),
+                left = percent(stage.offset_ms, total),
+                width = percent(stage.wall_ms, total).max(0.1),
+            );
+        }
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+            <title>Build timings: {title}</title>\n<style>\n\
+            body {{ font-family: sans-serif; }}\n\
+            table {{ border-collapse: collapse; width: 100%; }}\n\
+            td, th {{ padding: 2px 8px; text-align: left; white-space: nowrap; }}\n\
+            tr.failed {{ color: #b00; }}\n\
+            td.timeline {{ width: 50%; }}\n\
+            td.timeline div {{ background: #4a90d9; height: 1em; }}\n\
+            tr.failed td.timeline div {{ background: #b00; }}\n\
+            </style>\n</head>\n<body>\n<h1>Build timings: {title}</h1>\n\
+            <p>Total time: {total}</p>\n<table>\n\
+            <tr><th>Kind</th><th>Name</th><th>Queued</th><th>Wall time</th><th>Cache</th>\
+            <th>Timeline</th></tr>\n{rows}</table>\n</body>\n</html>\n",
+            title = escape(&format!("{} ({})", self.variant, self.arch)),
+            total = seconds(self.wall_ms),
+        )
+    }
+}
+
+fn seconds(ms: u128) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+fn percent(ms: u128, total: u128) -> f64 {
+    ms as f64 * 100.0 / total as f64
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn reports_stage_timings() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let timings_dir = temp_dir.path().join("raw");
+        std::fs::create_dir(&timings_dir).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        let end = start + Duration::from_secs(60);
+        std::fs::write(
+            timings_dir.join("x86_64-package-kernel.json"),
+            r#"{"name":"kernel","kind":"package","arch":"x86_64","start-ms":1002000,
+                "queue-ms":1500,"end-ms":1042000,"cache":"miss","succeeded":true}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            timings_dir.join("x86_64-package-glibc.json"),
+            r#"{"name":"glibc","kind":"package","arch":"x86_64","start-ms":1001000,
+                "end-ms":1001500,"cache":"hit","succeeded":true}"#,
+        )
+        .unwrap();
+
+        let report = TimingReport::load("aws-dev", "x86_64", &timings_dir, start, end)
+            .await
+            .unwrap();
+        assert_eq!(report.wall_ms, 60000);
+        let stages: Vec<(&str, u128, u128, u128, Option<Cache>)> = report
+            .stages
+            .iter()
+            .map(|stage| {
+                (
+                    stage.name.as_str(),
+                    stage.offset_ms,
+                    stage.queue_ms,
+                    stage.wall_ms,
+                    stage.cache,
+                )
+            })
+            .collect();
+        assert_eq!(
+            stages,
+            vec![
+                ("kernel", 2000, 1500, 40000, Some(Cache::Miss)),
+                ("glibc", 1000, 0, 500, Some(Cache::Hit)),
+            ]
+        );
+        assert!(report.summary().contains("package kernel: 40.0s"));
+
+        let html_path = report.write(temp_dir.path()).await.unwrap();
+        let html = std::fs::read_to_string(html_path).unwrap();
+        assert!(html.contains("<td>kernel</td>"));
+        assert!(temp_dir.path().join("x86_64-aws-dev.json").is_file());
+    }
+}
@@ -111,6 +111,7 @@ async fn test_install_tools() {
     assert!(toolsdir.join("Makefile.toml").is_file());
     assert!(toolsdir.join("build.Dockerfile").is_file());
     assert!(toolsdir.join("build.Dockerfile.dockerignore").is_file());
+    assert!(toolsdir.join("docker-bundle").is_file());
     assert!(toolsdir.join("docker-go").is_file());
     assert!(toolsdir.join("img2img").is_file());
     assert!(toolsdir.join("imghelper").is_file());